@@ -0,0 +1,48 @@
+//! Per-classification latency. The local model benchmark runs only when built with
+//! `--features local` and `PARSEC_LOCAL_MODEL` points at a model directory.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use parsec_classifier::HeuristicClassifier;
+use parsec_core::CommandClassifier;
+
+const INPUTS: &[&str] = &[
+    "ls -la",
+    "git commit -m \"fix the parser\"",
+    "make me a sandwich",
+    "how do I undo the last commit?",
+    "list every file that was changed in the last week and sort them by size",
+];
+
+fn bench_classifier(c: &mut Criterion, name: &str, classifier: &dyn CommandClassifier) {
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            for input in INPUTS {
+                let _ = black_box(classifier.classify(black_box(input), None));
+            }
+        })
+    });
+}
+
+fn heuristic(c: &mut Criterion) {
+    let classifier = HeuristicClassifier::default()
+        .with_path_executables(parsec_core::tools::path_executables());
+    bench_classifier(c, "heuristic", &classifier);
+}
+
+#[cfg(feature = "local")]
+fn local(c: &mut Criterion) {
+    let Some(dir) = std::env::var_os("PARSEC_LOCAL_MODEL") else {
+        eprintln!("PARSEC_LOCAL_MODEL not set; skipping local model benchmark");
+        return;
+    };
+    let classifier = parsec_classifier::LocalClassifier::new(dir);
+    // Load the model outside the measured loop
+    let _ = classifier.classify("warm up", None);
+    bench_classifier(c, "local", &classifier);
+}
+
+#[cfg(not(feature = "local"))]
+fn local(_c: &mut Criterion) {}
+
+criterion_group!(benches, heuristic, local);
+criterion_main!(benches);
@@ -0,0 +1,61 @@
+"""
+Minimal, dependency-free classifier implementing the PythonClassifier protocol.
+
+Rust calls `classify_input(request_json)` with
+    {"input": "...", "context": {"session_id": "...", "history": ["..."]} | null}
+and expects back
+    {"classification": "shell" | "prompt", "confidence": 0.0-1.0,
+     "reasoning": "...", "metadata": {"detected_patterns": [...], "language_indicators": [...]}}
+
+Copy this file as a starting point for your own classifier and point parsec at it with
+`--classifier python --classifier-script path/to/your_classifier.py`.
+"""
+
+import json
+
+SHELL_COMMANDS = {
+    'ls', 'cd', 'pwd', 'mkdir', 'rm', 'cp', 'mv', 'cat', 'grep', 'find',
+    'git', 'cargo', 'npm', 'python', 'node', 'curl', 'wget', 'ssh', 'docker',
+    'kubectl', 'make', 'sudo', 'chmod', 'ps', 'kill', 'tar', 'echo', 'touch',
+}
+
+QUESTION_WORDS = ('what', 'how', 'why', 'when', 'where', 'can you', 'please')
+
+
+def classify(text, history):
+    lower = text.strip().lower()
+    first_word = lower.split()[0] if lower.split() else ""
+    patterns = []
+    indicators = []
+
+    if first_word in SHELL_COMMANDS:
+        patterns.append("command_verb")
+    if " -" in text:
+        patterns.append("flag_pattern")
+    if lower.endswith("?") or lower.startswith(QUESTION_WORDS):
+        indicators.append("question_pattern")
+
+    # Repeating a command the session already ran is a strong shell signal
+    if text.strip() in history:
+        patterns.append("history_match")
+
+    if patterns and not indicators:
+        return "shell", 0.9, "Shell patterns: %s" % ", ".join(patterns), patterns, indicators
+    if indicators and not patterns:
+        return "prompt", 0.9, "Language indicators: %s" % ", ".join(indicators), patterns, indicators
+    return "prompt", 0.5, "No clear signal, defaulting to prompt", patterns, indicators
+
+
+def classify_input(request_json):
+    request = json.loads(request_json)
+    history = (request.get("context") or {}).get("history") or []
+    kind, confidence, reasoning, patterns, indicators = classify(request["input"], history)
+    return json.dumps({
+        "classification": kind,
+        "confidence": confidence,
+        "reasoning": reasoning,
+        "metadata": {
+            "detected_patterns": patterns,
+            "language_indicators": indicators,
+        },
+    })
@@ -0,0 +1,52 @@
+use parsec_core::{CommandClassifier, InputKind, Session};
+
+/// Labeled inputs, one `<shell|prompt>\t<input>` per line; `#` starts a comment.
+pub const LABELED_INPUTS: &str = include_str!("../data/corpus.tsv");
+
+/// Accuracy below this is a regression in the heuristic classifier.
+pub const MIN_HEURISTIC_ACCURACY: f32 = 0.9;
+
+#[derive(Debug, Default)]
+pub struct CorpusReport {
+    pub total: usize,
+    pub correct: usize,
+    /// Inputs classified wrongly, with the expected kind.
+    pub misclassified: Vec<(String, InputKind)>,
+}
+
+impl CorpusReport {
+    pub fn accuracy(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.correct as f32 / self.total as f32
+    }
+}
+
+pub fn labeled_inputs() -> impl Iterator<Item = (InputKind, &'static str)> {
+    LABELED_INPUTS
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (label, input) = line.split_once('\t')?;
+            let kind = match label {
+                "shell" => InputKind::Shell,
+                "prompt" => InputKind::Prompt,
+                _ => return None,
+            };
+            Some((kind, input))
+        })
+}
+
+/// Run `classifier` over the labeled corpus. Classifier errors count as misses.
+pub fn evaluate(classifier: &dyn CommandClassifier, context: Option<&Session>) -> CorpusReport {
+    let mut report = CorpusReport::default();
+    for (expected, input) in labeled_inputs() {
+        report.total += 1;
+        match classifier.classify(input, context) {
+            Ok(classification) if classification.kind == expected => report.correct += 1,
+            _ => report.misclassified.push((input.to_string(), expected)),
+        }
+    }
+    report
+}
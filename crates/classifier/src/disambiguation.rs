@@ -0,0 +1,90 @@
+//! What to do with a classification the classifier is unsure about. Below the threshold
+//! parsec does not guess: it asks the user, reuses their answer for a similar input earlier
+//! in the session, or with nobody to ask takes the configured default kind.
+
+use crate::heuristic::command_word;
+use parsec_core::{input_hash, Classification, InputKind, Session};
+
+/// Asked below the echoed input when the classifier is unsure.
+pub const QUESTION: &str = "Run as shell command, or ask the AI? [s/a] ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disambiguation {
+    /// Confident enough to act on as classified.
+    Confident,
+    /// The user answered for this input, or one with its command word, earlier in the session.
+    Remembered(InputKind),
+    /// Nobody to ask, so the configured default kind.
+    Default(InputKind),
+    /// Ask the user with `QUESTION`.
+    Ask,
+}
+
+#[derive(Debug, Clone)]
+pub struct Disambiguator {
+    threshold: f32,
+    default_kind: InputKind,
+    interactive: bool,
+}
+
+impl Disambiguator {
+    /// Settle classifications less confident than `threshold`; `default_kind` is used when
+    /// nobody can be asked.
+    pub fn new(threshold: f32, default_kind: InputKind) -> Self {
+        Self {
+            threshold,
+            default_kind,
+            interactive: true,
+        }
+    }
+
+    /// Whether a user is there to answer, as opposed to `--execute` or auto-approve runs.
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    pub fn default_kind(&self) -> &InputKind {
+        &self.default_kind
+    }
+
+    pub fn disambiguate(
+        &self,
+        input: &str,
+        classification: &Classification,
+        session: &Session,
+    ) -> Disambiguation {
+        if classification.confidence >= self.threshold {
+            Disambiguation::Confident
+        } else if let Some(kind) = remembered(input, session) {
+            Disambiguation::Remembered(kind)
+        } else if self.interactive {
+            Disambiguation::Ask
+        } else {
+            Disambiguation::Default(self.default_kind.clone())
+        }
+    }
+}
+
+/// The latest answer the user gave this session for `input` or an input with the same
+/// command word; corrections carried over from earlier sessions do not count.
+pub fn remembered(input: &str, session: &Session) -> Option<InputKind> {
+    let hash = input_hash(input);
+    let word = command_word(input).unwrap_or_default();
+    session
+        .classification_corrections
+        .iter()
+        .rev()
+        .filter(|answer| answer.disambiguated && answer.corrected_at >= session.created_at)
+        .find(|answer| answer.input_hash == hash || (!word.is_empty() && answer.first_word == word))
+        .map(|answer| answer.corrected_to.clone())
+}
+
+/// The kind an answer to `QUESTION` picks, if it picks one.
+pub fn parse_answer(answer: &str) -> Option<InputKind> {
+    match answer.trim().to_lowercase().as_str() {
+        "s" | "shell" => Some(InputKind::Shell),
+        "a" | "ai" | "ask" => Some(InputKind::Prompt),
+        _ => None,
+    }
+}
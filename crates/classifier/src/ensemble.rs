@@ -0,0 +1,135 @@
+use crate::HeuristicClassifier;
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Runs the heuristic first and only consults the (slow) remote classifier when the
+/// heuristic is unsure. Remote timeouts and errors fall back to the heuristic answer.
+pub struct EnsembleClassifier {
+    heuristic: HeuristicClassifier,
+    remote: Arc<dyn CommandClassifier>,
+    shell_cutoff: f32,
+    prompt_cutoff: f32,
+    timeout: Duration,
+}
+
+impl EnsembleClassifier {
+    pub fn new(heuristic: HeuristicClassifier, remote: Arc<dyn CommandClassifier>) -> Self {
+        Self {
+            heuristic,
+            remote,
+            shell_cutoff: 0.85,
+            prompt_cutoff: 0.85,
+            timeout: Duration::from_millis(1500),
+        }
+    }
+
+    /// Heuristic shell answers at or above this confidence skip the remote classifier.
+    pub fn with_shell_cutoff(mut self, cutoff: f32) -> Self {
+        self.shell_cutoff = cutoff;
+        self
+    }
+
+    /// Heuristic prompt answers at or above this confidence skip the remote classifier.
+    pub fn with_prompt_cutoff(mut self, cutoff: f32) -> Self {
+        self.prompt_cutoff = cutoff;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the remote classifier on its own thread so a slow response can be abandoned.
+    fn consult_remote(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, String> {
+        let (sender, receiver) = mpsc::channel();
+        let remote = self.remote.clone();
+        let input = input.to_string();
+        let context = context.cloned();
+
+        thread::spawn(move || {
+            // The receiver is gone if we already timed out; nothing to report then
+            let _ = sender.send(remote.classify(&input, context.as_ref()));
+        });
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(Ok(classification)) => Ok(classification),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(format!("timed out after {}ms", self.timeout.as_millis()))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err("remote classifier panicked".to_string())
+            }
+        }
+    }
+}
+
+impl CommandClassifier for EnsembleClassifier {
+    fn classify(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        let heuristic = self.heuristic.classify(input, context)?;
+        let heuristic_summary = summarize("heuristic", &heuristic);
+
+        let cutoff = match heuristic.kind {
+            InputKind::Shell => self.shell_cutoff,
+            InputKind::Prompt => self.prompt_cutoff,
+        };
+        if heuristic.confidence >= cutoff {
+            return Ok(Classification {
+                reasoning: Some(format!(
+                    "{}; above cutoff, remote skipped",
+                    heuristic_summary
+                )),
+                ..heuristic
+            });
+        }
+
+        match self.consult_remote(input, context) {
+            Ok(remote) => {
+                let remote_summary = summarize("remote", &remote);
+                let (decision, source) = if remote.confidence >= heuristic.confidence {
+                    (remote, "remote")
+                } else {
+                    (heuristic, "heuristic")
+                };
+                Ok(Classification {
+                    reasoning: Some(format!(
+                        "{}; {}; chose {}",
+                        heuristic_summary, remote_summary, source
+                    )),
+                    ..decision
+                })
+            }
+            Err(e) => Ok(Classification {
+                reasoning: Some(format!(
+                    "{}; remote failed ({}), used heuristic",
+                    heuristic_summary, e
+                )),
+                ..heuristic
+            }),
+        }
+    }
+}
+
+fn summarize(source: &str, classification: &Classification) -> String {
+    match &classification.reasoning {
+        Some(reasoning) => format!(
+            "{}: {:?} {:.2} [{}]",
+            source, classification.kind, classification.confidence, reasoning
+        ),
+        None => format!(
+            "{}: {:?} {:.2}",
+            source, classification.kind, classification.confidence
+        ),
+    }
+}
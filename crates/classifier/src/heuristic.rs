@@ -0,0 +1,532 @@
+use parsec_core::tools::detect_tools;
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use std::collections::HashSet;
+
+/// One piece of evidence about an input. Positive weights lean shell, negative lean prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub weight: f32,
+}
+
+impl Signal {
+    fn new(name: impl Into<String>, weight: f32) -> Self {
+        Self {
+            name: name.into(),
+            weight,
+        }
+    }
+}
+
+const KNOWN_COMMAND: f32 = 2.0;
+const ACTIVE_TOOL: f32 = 1.5;
+const ON_PATH: f32 = 1.0;
+const RECENTLY_RUN: f32 = 1.0;
+/// Magnitude of a session correction; its sign follows the corrected kind.
+const CORRECTED: f32 = 3.0;
+const ENV_ASSIGNMENT: f32 = 2.0;
+const SHELL_OPERATOR: f32 = 1.5;
+const FLAGS: f32 = 1.0;
+const PATH_ARGUMENT: f32 = 0.8;
+const PROMPT_PHRASE: f32 = -2.0;
+const QUESTION: f32 = -1.5;
+const LONG_SENTENCE: f32 = -1.5;
+const FOLLOW_UP: f32 = -1.5;
+const ENGLISH_ARGUMENT: f32 = -2.0;
+const FUNCTION_WORD: f32 = -0.75;
+const FUNCTION_WORD_CAP: f32 = -2.25;
+const SHEBANG: f32 = 4.0;
+const SCRIPT_LINES: f32 = 3.0;
+const MULTI_LINE: f32 = -3.0;
+
+/// How many recent shell commands are compared against the input.
+const HISTORY_WINDOW: usize = 20;
+
+const SHELL_OPERATORS: &[&str] = &["|", "&&", "||", "$(", "`", ";", ">", "<"];
+
+/// Words that start lines of shell scripts besides command names.
+const SCRIPT_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "function", "{", "}", "[", "[[", "export", "set", "unset", "local", "source", ".", "echo",
+    "printf", "read", "return", "exit", "shift", "trap", "alias", "test",
+];
+
+/// Words that almost never follow a command name as its first argument.
+const ENGLISH_ARGUMENTS: &[&str] = &[
+    "me", "my", "an", "the", "to", "for", "some", "every", "us", "our", "this", "that", "what",
+    "how", "why", "please",
+];
+
+const FUNCTION_WORDS: &[&str] = &[
+    "the", "a", "an", "this", "that", "my", "me", "in", "of", "for", "to", "with", "all", "some",
+    "from", "into", "and", "it", "is", "are",
+];
+
+const FOLLOW_UP_PHRASES: &[&str] = &[
+    "also ",
+    "and ",
+    "then ",
+    "now ",
+    "next ",
+    "instead",
+    "but ",
+    "what about",
+    "same ",
+    "again",
+    "do that",
+    "undo that",
+];
+
+pub struct HeuristicClassifier {
+    shell_commands: Vec<String>,
+    prompt_indicators: Vec<String>,
+    path_executables: Option<HashSet<String>>,
+}
+
+impl Default for HeuristicClassifier {
+    fn default() -> Self {
+        Self {
+            shell_commands: vec![
+                "ls",
+                "cd",
+                "pwd",
+                "mkdir",
+                "rm",
+                "cp",
+                "mv",
+                "cat",
+                "grep",
+                "find",
+                "git",
+                "cargo",
+                "npm",
+                "python",
+                "node",
+                "curl",
+                "wget",
+                "ssh",
+                "scp",
+                "vim",
+                "nano",
+                "emacs",
+                "docker",
+                "kubectl",
+                "make",
+                "sudo",
+                "chmod",
+                "chown",
+                "ps",
+                "kill",
+                "top",
+                "htop",
+                "df",
+                "du",
+                "tar",
+                "unzip",
+                "pnpm",
+                "yarn",
+                "uv",
+                "just",
+                "terraform",
+                "helm",
+                "go",
+                "rustup",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            prompt_indicators: vec![
+                "please",
+                "how do i",
+                "help me",
+                "can you",
+                "i need",
+                "i want",
+                "what is",
+                "how to",
+                "show me",
+                "explain",
+                "create a",
+                "build a",
+                "set up",
+                "configure",
+                "install",
+                "initialize",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            path_executables: None,
+        }
+    }
+}
+
+impl HeuristicClassifier {
+    pub fn with_extra_shell_commands<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        extend_unique(&mut self.shell_commands, commands);
+        self
+    }
+
+    pub fn without_shell_commands<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        remove_all(&mut self.shell_commands, commands);
+        self
+    }
+
+    pub fn with_extra_prompt_indicators<I, S>(mut self, indicators: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        extend_unique(&mut self.prompt_indicators, indicators);
+        self
+    }
+
+    pub fn without_prompt_indicators<I, S>(mut self, indicators: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        remove_all(&mut self.prompt_indicators, indicators);
+        self
+    }
+
+    /// Use a snapshot of the executables on `$PATH` (see `tools::path_executables`)
+    /// instead of probing `$PATH` on every classification.
+    pub fn with_path_executables(mut self, executables: HashSet<String>) -> Self {
+        self.path_executables = Some(executables);
+        self
+    }
+
+    fn on_path(&self, command: &str) -> bool {
+        match &self.path_executables {
+            Some(executables) => executables.contains(command),
+            None => !command.contains('/') && !detect_tools(&[command.to_string()]).is_empty(),
+        }
+    }
+
+    /// Every signal that fires for `input`, in evaluation order.
+    pub fn signals(&self, input: &str, context: Option<&Session>) -> Vec<Signal> {
+        let input_lower = input.trim().to_lowercase();
+        // Quoted arguments (commit messages, echo strings) say nothing about the input's kind
+        let unquoted = strip_quoted(&input_lower);
+        let words: Vec<&str> = unquoted.split_whitespace().collect();
+        let mut signals = self.multi_line_signals(input, context);
+
+        // Skip leading `VAR=value` assignments to find the command word
+        let assignments = words.iter().take_while(|w| is_assignment(w)).count();
+        if assignments > 0 {
+            signals.push(Signal::new("leading VAR=value assignment", ENV_ASSIGNMENT));
+        }
+        let command_word = words.get(assignments).copied().unwrap_or("");
+        let arguments = words.get(assignments + 1..).unwrap_or(&[]);
+
+        let mut command_is_tool = true;
+        if !command_word.is_empty() {
+            if self.shell_commands.iter().any(|c| c == command_word) {
+                signals.push(Signal::new(
+                    format!("`{}` is a known shell command", command_word),
+                    KNOWN_COMMAND,
+                ));
+            } else if context.is_some_and(|s| {
+                s.global_context
+                    .active_tools
+                    .iter()
+                    .any(|tool| tool == command_word)
+            }) {
+                signals.push(Signal::new(
+                    format!("`{}` is an active tool", command_word),
+                    ACTIVE_TOOL,
+                ));
+            } else if self.on_path(command_word) {
+                signals.push(Signal::new(
+                    format!("`{}` is on PATH", command_word),
+                    ON_PATH,
+                ));
+            } else {
+                command_is_tool = false;
+            }
+
+            if let Some(session) = context {
+                let recently_run = session
+                    .command_history
+                    .iter()
+                    .rev()
+                    .take(HISTORY_WINDOW)
+                    .any(|cmd| cmd.command.split_whitespace().next() == Some(command_word));
+                if recently_run {
+                    signals.push(Signal::new(
+                        format!("`{}` was run recently", command_word),
+                        RECENTLY_RUN,
+                    ));
+                }
+            }
+
+            // The user's latest correction for this command word wins over older ones
+            let correction = context.and_then(|s| {
+                s.classification_corrections
+                    .iter()
+                    .rev()
+                    .find(|c| c.first_word == command_word)
+            });
+            if let Some(correction) = correction {
+                let weight = match correction.corrected_to {
+                    InputKind::Shell => CORRECTED,
+                    InputKind::Prompt => -CORRECTED,
+                };
+                signals.push(Signal::new(
+                    format!(
+                        "`{}` was corrected to {:?}",
+                        command_word, correction.corrected_to
+                    ),
+                    weight,
+                ));
+            }
+
+            if let Some(first_argument) = arguments.first() {
+                if ENGLISH_ARGUMENTS.contains(first_argument) {
+                    signals.push(Signal::new(
+                        format!("`{} {}` reads as English", command_word, first_argument),
+                        ENGLISH_ARGUMENT,
+                    ));
+                }
+            }
+        }
+
+        if let Some(operator) = SHELL_OPERATORS.iter().find(|op| unquoted.contains(*op)) {
+            signals.push(Signal::new(
+                format!("shell operator `{}`", operator),
+                SHELL_OPERATOR,
+            ));
+        }
+
+        let has_flags = words.iter().any(|w| is_flag(w));
+        if has_flags {
+            signals.push(Signal::new("command-line flags", FLAGS));
+        }
+
+        if words.iter().any(|w| is_path_like(w)) {
+            signals.push(Signal::new("path or file argument", PATH_ARGUMENT));
+        }
+
+        // Single words like "install" are ignored as arguments to a tool, so `npm install` stays shell
+        if let Some(indicator) = self.prompt_indicators.iter().find(|indicator| {
+            if indicator.contains(' ') {
+                unquoted.contains(indicator.as_str())
+            } else {
+                words.contains(&indicator.as_str())
+                    && (words.first() == Some(&indicator.as_str()) || !command_is_tool)
+            }
+        }) {
+            signals.push(Signal::new(
+                format!("contains the phrase \"{}\"", indicator),
+                PROMPT_PHRASE,
+            ));
+        }
+
+        if unquoted.contains('?')
+            || ["what", "how", "why", "when", "where"]
+                .iter()
+                .any(|w| unquoted.starts_with(w))
+        {
+            signals.push(Signal::new("phrased as a question", QUESTION));
+        }
+
+        if words.len() > 8 && !has_flags {
+            signals.push(Signal::new(
+                format!("{}-word sentence without flags", words.len()),
+                LONG_SENTENCE,
+            ));
+        }
+
+        let function_words = words.iter().filter(|w| FUNCTION_WORDS.contains(w)).count();
+        if function_words > 0 {
+            signals.push(Signal::new(
+                format!("{} English function word(s)", function_words),
+                (FUNCTION_WORD * function_words as f32).max(FUNCTION_WORD_CAP),
+            ));
+        }
+
+        let follows_prompt = context.is_some_and(|s| s.last_input_kind == Some(InputKind::Prompt));
+        if follows_prompt
+            && FOLLOW_UP_PHRASES
+                .iter()
+                .any(|phrase| unquoted.starts_with(phrase))
+        {
+            signals.push(Signal::new("follow-up to the previous request", FOLLOW_UP));
+        }
+
+        signals
+    }
+
+    /// Multi-line input is almost always a prompt written over several lines, unless it
+    /// is a pasted script: one with a shebang, or whose every line reads as shell.
+    fn multi_line_signals(&self, input: &str, context: Option<&Session>) -> Vec<Signal> {
+        let lines: Vec<&str> = input
+            .trim()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.len() < 2 {
+            return Vec::new();
+        }
+        if lines[0].starts_with("#!") {
+            return vec![Signal::new("script with a shebang line", SHEBANG)];
+        }
+
+        let mut continued = false;
+        let script_lines = lines
+            .iter()
+            .filter(|line| {
+                let is_shell = continued
+                    || line.starts_with('#')
+                    // Lines of only `VAR=value` assignments have no command word
+                    || command_word(line).is_none_or(|word| {
+                        SCRIPT_KEYWORDS.contains(&word.as_str())
+                            || self.shell_commands.contains(&word)
+                            || context.is_some_and(|s| s.global_context.active_tools.contains(&word))
+                            || self.on_path(&word)
+                    });
+                continued = line.ends_with('\\');
+                is_shell
+            })
+            .count();
+        if script_lines == lines.len() {
+            vec![Signal::new(
+                format!("all {} lines read as shell commands", lines.len()),
+                SCRIPT_LINES,
+            )]
+        } else {
+            vec![Signal::new(
+                format!("{}-line text", lines.len()),
+                MULTI_LINE,
+            )]
+        }
+    }
+}
+
+/// The lowercased command word of `input`, skipping leading `VAR=value` assignments.
+pub fn command_word(input: &str) -> Option<String> {
+    strip_quoted(&input.trim().to_lowercase())
+        .split_whitespace()
+        .find(|w| !is_assignment(w))
+        .map(String::from)
+}
+
+impl CommandClassifier for HeuristicClassifier {
+    fn classify(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        if input.trim().is_empty() {
+            return Ok(Classification::new(InputKind::Shell, 1.0, "empty input"));
+        }
+
+        let signals = self.signals(input, context);
+        let score: f32 = signals.iter().map(|s| s.weight).sum();
+
+        // Ties go to the prompt path, which always asks before running anything
+        let kind = if score > 0.0 {
+            InputKind::Shell
+        } else {
+            InputKind::Prompt
+        };
+        let confidence = 0.5 + 0.5 * (score.abs() / 2.0).tanh();
+
+        let reasoning = if signals.is_empty() {
+            "no signals fired; defaulting to prompt".to_string()
+        } else {
+            signals
+                .iter()
+                .map(|s| format!("{} ({:+.2})", s.name, s.weight))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        Ok(Classification::new(kind, confidence, reasoning))
+    }
+}
+
+/// Entries are matched against lowercased input, so they are stored lowercased.
+fn extend_unique<I, S>(list: &mut Vec<String>, entries: I)
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    for entry in entries {
+        let entry = entry.into().to_lowercase();
+        if !list.contains(&entry) {
+            list.push(entry);
+        }
+    }
+}
+
+fn remove_all<I, S>(list: &mut Vec<String>, entries: I)
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let entries: Vec<String> = entries
+        .into_iter()
+        .map(|e| e.into().to_lowercase())
+        .collect();
+    list.retain(|entry| !entries.contains(entry));
+}
+
+fn strip_quoted(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut quote = None;
+    for c in input.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+fn is_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_flag(word: &str) -> bool {
+    let Some(rest) = word.strip_prefix('-') else {
+        return false;
+    };
+    rest.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_path_like(word: &str) -> bool {
+    if word.contains('/') || word.starts_with('~') {
+        return true;
+    }
+    // `main.rs`, `config.yaml`, but not a sentence ending in a full stop
+    match word.rsplit_once('.') {
+        Some((stem, ext)) => {
+            !stem.is_empty()
+                && (1..=4).contains(&ext.len())
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
+}
@@ -1,12 +1,19 @@
-use parsec_core::{ClassificationError, CommandClassifier, InputKind, Session};
-use reqwest::Client;
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use tracing::warn;
 
 #[derive(Debug, Serialize)]
 struct HuggingFaceRequest {
     inputs: String,
     parameters: HuggingFaceParameters,
+    options: HuggingFaceOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct HuggingFaceOptions {
+    wait_for_model: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -14,18 +21,30 @@ struct HuggingFaceParameters {
     candidate_labels: Vec<String>,
 }
 
+/// Body of the 503 returned while a cold model is being loaded.
+#[derive(Debug, Deserialize)]
+struct ModelLoadingResponse {
+    error: String,
+    estimated_time: Option<f64>,
+}
+
 #[derive(Debug, Deserialize)]
 struct HuggingFaceResponse {
-    sequence: String,
     labels: Vec<String>,
     scores: Vec<f64>,
 }
 
+/// The hosted Inference API; model names are appended to it.
+pub const DEFAULT_ENDPOINT: &str = "https://api-inference.huggingface.co/models";
+
 pub struct HuggingFaceClassifier {
     client: Client,
+    endpoint: String,
     api_token: String,
     model_name: String,
     threshold: f64,
+    max_loading_wait: Duration,
+    max_loading_retries: u32,
 }
 
 impl HuggingFaceClassifier {
@@ -42,9 +61,12 @@ impl HuggingFaceClassifier {
 
         Ok(Self {
             client,
+            endpoint: DEFAULT_ENDPOINT.to_string(),
             api_token,
             model_name: "facebook/bart-large-mnli".to_string(), // Zero-shot classification model
             threshold: 0.7,
+            max_loading_wait: Duration::from_secs(10),
+            max_loading_retries: 2,
         })
     }
 
@@ -53,14 +75,34 @@ impl HuggingFaceClassifier {
         self
     }
 
+    /// Base URL to send requests to in place of `DEFAULT_ENDPOINT`, such as a
+    /// self-hosted inference server.
+    pub fn with_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
     pub fn with_threshold(mut self, threshold: f64) -> Self {
         self.threshold = threshold;
         self
     }
 
-    async fn classify_async(&self, input: &str) -> Result<InputKind, ClassificationError> {
+    /// Upper bound on a single wait for a loading model, whatever `estimated_time` says.
+    pub fn with_max_loading_wait(mut self, wait: Duration) -> Self {
+        self.max_loading_wait = wait;
+        self
+    }
+
+    /// Retries after "model is loading" responses before falling back to the heuristic.
+    pub fn with_max_loading_retries(mut self, retries: u32) -> Self {
+        self.max_loading_retries = retries;
+        self
+    }
+
+    async fn classify_async(&self, input: &str) -> Result<Classification, ClassificationError> {
         let url = format!(
-            "https://api-inference.huggingface.co/models/{}",
+            "{}/{}",
+            self.endpoint.trim_end_matches('/'),
             self.model_name
         );
 
@@ -74,19 +116,58 @@ impl HuggingFaceClassifier {
                     "conversational prompt".to_string(),
                 ],
             },
+            options: HuggingFaceOptions {
+                wait_for_model: true,
+            },
         };
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| {
-                ClassificationError::ClassificationFailed(format!("HTTP request failed: {}", e))
-            })?;
+        let mut retries = 0;
+        let response = loop {
+            let response = self
+                .client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", self.api_token))
+                .header("Content-Type", "application/json")
+                .header("x-wait-for-model", "true")
+                .json(&request)
+                .send()
+                .await
+                .map_err(|e| {
+                    ClassificationError::ClassificationFailed(format!("HTTP request failed: {}", e))
+                })?;
+
+            if response.status() != StatusCode::SERVICE_UNAVAILABLE {
+                break response;
+            }
+
+            // A cold model answers 503 with an estimate of how long loading takes
+            let body = response.text().await.unwrap_or_default();
+            let Ok(loading) = serde_json::from_str::<ModelLoadingResponse>(&body) else {
+                return Err(ClassificationError::ClassificationFailed(format!(
+                    "API request failed with status 503: {}",
+                    body
+                )));
+            };
+            if retries >= self.max_loading_retries {
+                warn!(
+                    "Hugging Face model still loading after {} retries: {}",
+                    retries, loading.error
+                );
+                return Ok(self.heuristic_fallback(
+                    input,
+                    0.0,
+                    &format!("model still loading after {} retries", retries),
+                ));
+            }
+
+            let wait = loading
+                .estimated_time
+                .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+                .unwrap_or(self.max_loading_wait)
+                .min(self.max_loading_wait);
+            tokio::time::sleep(wait).await;
+            retries += 1;
+        };
 
         if !response.status().is_success() {
             let status = response.status();
@@ -105,22 +186,41 @@ impl HuggingFaceClassifier {
         if let (Some(best_label), Some(&best_score)) =
             (hf_response.labels.first(), hf_response.scores.first())
         {
+            let reasoning = format!("model labelled it \"{}\" ({:.2})", best_label, best_score);
             if best_score < self.threshold {
                 // If confidence is low, fall back to heuristic classification
-                return Ok(self.heuristic_fallback(input));
+                let reasoning = format!("{}, below threshold {:.2}", reasoning, self.threshold);
+                return Ok(self.heuristic_fallback(input, best_score as f32, &reasoning));
             }
 
-            match best_label.as_str() {
-                "shell command" | "system command" => Ok(InputKind::Shell),
-                "natural language request" | "conversational prompt" => Ok(InputKind::Prompt),
-                _ => Ok(self.heuristic_fallback(input)),
-            }
+            let kind = match best_label.as_str() {
+                "shell command" | "system command" => InputKind::Shell,
+                "natural language request" | "conversational prompt" => InputKind::Prompt,
+                _ => return Ok(self.heuristic_fallback(input, best_score as f32, &reasoning)),
+            };
+            Ok(Classification::new(kind, best_score as f32, reasoning))
         } else {
-            Ok(self.heuristic_fallback(input))
+            Ok(self.heuristic_fallback(input, 0.0, "model returned no labels"))
         }
     }
 
-    fn heuristic_fallback(&self, input: &str) -> InputKind {
+    /// Heuristic answer used when the model is unsure; keeps the model's score as
+    /// the confidence since the heuristic adds no certainty of its own.
+    fn heuristic_fallback(
+        &self,
+        input: &str,
+        model_score: f32,
+        model_reasoning: &str,
+    ) -> Classification {
+        let kind = self.heuristic_kind(input);
+        Classification::new(
+            kind,
+            model_score,
+            format!("{}; used heuristic fallback", model_reasoning),
+        )
+    }
+
+    fn heuristic_kind(&self, input: &str) -> InputKind {
         let input_lower = input.trim().to_lowercase();
         let first_word = input_lower.split_whitespace().next().unwrap_or("");
 
@@ -158,7 +258,7 @@ impl CommandClassifier for HuggingFaceClassifier {
         &self,
         input: &str,
         _context: Option<&Session>,
-    ) -> Result<InputKind, ClassificationError> {
+    ) -> Result<Classification, ClassificationError> {
         // Since this is a sync trait, we need to use a blocking runtime
         // In a real implementation, you might want to use async traits or a different approach
         let rt = tokio::runtime::Runtime::new().map_err(|e| {
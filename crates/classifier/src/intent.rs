@@ -0,0 +1,53 @@
+//! Whether a prompt asks for work, which parsec plans as a workflow, or asks a question,
+//! which the model answers without one. Only the first line is read, so a question with
+//! an error message pasted below it is still a question.
+
+use parsec_core::PromptIntent;
+
+/// Words that open a question however it ends.
+const QUESTION_WORDS: &[&str] = &[
+    "what", "what's", "whats", "why", "which", "who", "whose", "when", "where", "explain",
+    "describe",
+];
+
+/// Words that open a question when it ends in `?`: "is this branch merged?".
+const AUXILIARIES: &[&str] = &[
+    "is", "are", "was", "were", "does", "do", "did", "has", "have", "should", "can", "could",
+    "would", "will",
+];
+
+/// After an auxiliary, these make a request rather than a question: "can you install it?".
+const ADDRESSEES: &[&str] = &["you", "u"];
+
+/// After "how", these ask how to do something, which is left to a workflow: "how do I
+/// deploy this", "how to free up disk space".
+const HOW_TO: &[&str] = &["do", "can", "should", "would"];
+
+pub fn intent(prompt: &str) -> PromptIntent {
+    let first_line = prompt
+        .trim()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let words: Vec<&str> = first_line
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let asked = first_line.trim_end().ends_with('?');
+    let question = match words.as_slice() {
+        [] => false,
+        ["how", "to", ..] => false,
+        ["how", verb, "i" | "we", ..] if HOW_TO.contains(verb) => false,
+        ["how", ..] => true,
+        [first, second, ..] if AUXILIARIES.contains(first) && ADDRESSEES.contains(second) => false,
+        [first, ..] if QUESTION_WORDS.contains(first) => true,
+        [first, ..] if AUXILIARIES.contains(first) => asked,
+        _ => false,
+    };
+    if question {
+        PromptIntent::Question
+    } else {
+        PromptIntent::Task
+    }
+}
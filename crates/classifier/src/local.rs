@@ -0,0 +1,214 @@
+use crate::HeuristicClassifier;
+use ort::session::{Session as OrtSession, SessionInputValue};
+use ort::value::Tensor;
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tokenizers::Tokenizer;
+use tracing::warn;
+
+const MODEL_FILE: &str = "model.onnx";
+const TOKENIZER_FILE: &str = "tokenizer.json";
+/// Hugging Face style config; only `id2label` is read.
+const CONFIG_FILE: &str = "config.json";
+
+/// Runs a text-classification model (e.g. a distilled intent model exported to ONNX)
+/// locally. The model directory must hold `model.onnx` and `tokenizer.json`, plus an
+/// optional `config.json` whose `id2label` names the outputs; labels containing
+/// "shell" or "command" map to `InputKind::Shell`, everything else to `Prompt`.
+///
+/// The model is loaded on first use. If loading fails (missing files, no
+/// `libonnxruntime` — set `ORT_DYLIB_PATH` to point at it), every call falls back
+/// to the heuristic.
+pub struct LocalClassifier {
+    model_dir: PathBuf,
+    heuristic: HeuristicClassifier,
+    model: OnceLock<Result<LocalModel, String>>,
+}
+
+struct LocalModel {
+    session: Mutex<OrtSession>,
+    tokenizer: Tokenizer,
+    input_names: Vec<String>,
+    labels: Vec<String>,
+}
+
+impl LocalClassifier {
+    pub fn new(model_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            model_dir: model_dir.into(),
+            heuristic: HeuristicClassifier::default(),
+            model: OnceLock::new(),
+        }
+    }
+
+    /// Heuristic used when the model can't be loaded or run.
+    pub fn with_heuristic(mut self, heuristic: HeuristicClassifier) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+
+    fn model(&self) -> Result<&LocalModel, &str> {
+        self.model
+            .get_or_init(|| {
+                let model = LocalModel::load(&self.model_dir);
+                if let Err(e) = &model {
+                    warn!("Local classifier unavailable, using heuristic: {}", e);
+                }
+                model
+            })
+            .as_ref()
+            .map_err(|e| e.as_str())
+    }
+
+    fn fallback(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+        reason: &str,
+    ) -> Result<Classification, ClassificationError> {
+        let heuristic = self.heuristic.classify(input, context)?;
+        let reasoning = match &heuristic.reasoning {
+            Some(reasoning) => format!("{}; heuristic: {}", reason, reasoning),
+            None => reason.to_string(),
+        };
+        Ok(Classification {
+            reasoning: Some(reasoning),
+            ..heuristic
+        })
+    }
+}
+
+impl CommandClassifier for LocalClassifier {
+    fn classify(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        let model = match self.model() {
+            Ok(model) => model,
+            Err(e) => {
+                return self.fallback(input, context, &format!("local model unavailable ({})", e))
+            }
+        };
+
+        match model.predict(input) {
+            Ok((label, score)) => Ok(Classification::new(
+                label_kind(label),
+                score,
+                format!("local model labelled it \"{}\" ({:.2})", label, score),
+            )),
+            Err(e) => self.fallback(input, context, &format!("local model failed ({})", e)),
+        }
+    }
+}
+
+impl LocalModel {
+    fn load(dir: &Path) -> Result<Self, String> {
+        let model_path = dir.join(MODEL_FILE);
+        if !model_path.exists() {
+            return Err(format!("{} not found", model_path.display()));
+        }
+
+        let session = OrtSession::builder()
+            .and_then(|builder| builder.commit_from_file(&model_path))
+            .map_err(|e| format!("failed to load {}: {}", model_path.display(), e))?;
+        let tokenizer = Tokenizer::from_file(dir.join(TOKENIZER_FILE))
+            .map_err(|e| format!("failed to load {}: {}", TOKENIZER_FILE, e))?;
+        let input_names = session.inputs.iter().map(|i| i.name.clone()).collect();
+
+        Ok(Self {
+            session: Mutex::new(session),
+            tokenizer,
+            input_names,
+            labels: read_labels(&dir.join(CONFIG_FILE)),
+        })
+    }
+
+    /// The most probable label and its softmax probability.
+    fn predict(&self, input: &str) -> Result<(&str, f32), String> {
+        let encoding = self
+            .tokenizer
+            .encode(input, true)
+            .map_err(|e| e.to_string())?;
+        let as_i64 = |values: &[u32]| values.iter().map(|&v| v as i64).collect::<Vec<_>>();
+        let shape = [1usize, encoding.get_ids().len()];
+
+        let mut inputs: Vec<(Cow<str>, SessionInputValue)> = Vec::new();
+        for name in &self.input_names {
+            let data = match name.as_str() {
+                "input_ids" => as_i64(encoding.get_ids()),
+                "attention_mask" => as_i64(encoding.get_attention_mask()),
+                "token_type_ids" => as_i64(encoding.get_type_ids()),
+                other => return Err(format!("unsupported model input `{}`", other)),
+            };
+            let tensor = Tensor::from_array((shape, data)).map_err(|e| e.to_string())?;
+            inputs.push((Cow::from(name.as_str()), tensor.into()));
+        }
+
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| "model session poisoned".to_string())?;
+        let outputs = session.run(inputs).map_err(|e| e.to_string())?;
+        let (_, logits) = outputs[0]
+            .try_extract_tensor::<f32>()
+            .map_err(|e| e.to_string())?;
+
+        let probabilities = softmax(logits);
+        let (best, score) = probabilities
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .ok_or_else(|| "model returned no logits".to_string())?;
+        let label = self
+            .labels
+            .get(best)
+            .map(String::as_str)
+            .ok_or_else(|| format!("no label for output {}", best))?;
+
+        Ok((label, *score))
+    }
+}
+
+/// Labels ordered by id from `config.json`, defaulting to `["shell", "prompt"]`.
+fn read_labels(path: &Path) -> Vec<String> {
+    let labels = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+        .and_then(|config| {
+            let id2label = config.get("id2label")?.as_object()?.clone();
+            let mut labels: Vec<(usize, String)> = id2label
+                .into_iter()
+                .filter_map(|(id, label)| Some((id.parse().ok()?, label.as_str()?.to_string())))
+                .collect();
+            labels.sort();
+            Some(
+                labels
+                    .into_iter()
+                    .map(|(_, label)| label)
+                    .collect::<Vec<_>>(),
+            )
+        });
+
+    labels
+        .filter(|labels| !labels.is_empty())
+        .unwrap_or_else(|| vec!["shell".to_string(), "prompt".to_string()])
+}
+
+fn label_kind(label: &str) -> InputKind {
+    let label = label.to_lowercase();
+    if label.contains("shell") || label.contains("command") {
+        InputKind::Shell
+    } else {
+        InputKind::Prompt
+    }
+}
+
+fn softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits.iter().map(|l| (l - max).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|e| e / sum).collect()
+}
@@ -0,0 +1,53 @@
+use parsec_core::metrics::{self, Metrics};
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Counts another classifier's decisions by kind and how long they took, under the
+/// `classifier` label `name`.
+pub struct MeteredClassifier {
+    inner: Box<dyn CommandClassifier>,
+    name: String,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl MeteredClassifier {
+    pub fn new(
+        inner: Box<dyn CommandClassifier>,
+        name: impl Into<String>,
+        metrics: Arc<dyn Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            name: name.into(),
+            metrics,
+        }
+    }
+}
+
+impl CommandClassifier for MeteredClassifier {
+    fn classify(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        let started = Instant::now();
+        let result = self.inner.classify(input, context);
+        let kind = match &result {
+            Ok(classification) if classification.kind == InputKind::Shell => "shell",
+            Ok(_) => "prompt",
+            Err(_) => "error",
+        };
+        self.metrics.increment(
+            metrics::CLASSIFICATIONS,
+            &[("classifier", &self.name), ("kind", kind)],
+            1,
+        );
+        self.metrics.observe(
+            metrics::CLASSIFICATION_LATENCY_MS,
+            &[("classifier", &self.name)],
+            started.elapsed().as_secs_f64() * 1000.0,
+        );
+        result
+    }
+}
@@ -1,71 +1,84 @@
-use parsec_core::{CommandClassifier, InputKind, ClassificationError, Session};
+use crate::{ClassificationContext, ClassificationRequest, ClassificationResponse};
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyString};
-use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClassificationRequest {
-    pub input: String,
-    pub context: Option<ClassificationContext>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClassificationContext {
-    pub session_id: Option<String>,
-    pub history: Vec<String>,
-}
+/// Environment variable naming the classifier script (or the directory holding it).
+pub const SCRIPT_ENV: &str = "PARSEC_CLASSIFIER_SCRIPT";
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClassificationResponse {
-    pub classification: String,
-    pub confidence: f64,
-    pub reasoning: String,
-    pub metadata: ClassificationMetadata,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClassificationMetadata {
-    pub detected_patterns: Vec<String>,
-    pub language_indicators: Vec<String>,
-}
+const DEFAULT_SCRIPT_DIR: &str = "./py";
+const DEFAULT_MODULE: &str = "classifier";
 
+/// Calls `classify_input(request_json) -> response_json` in an embedded Python module.
+/// See `py/reference_classifier.py` for a dependency-free implementation of the protocol.
 pub struct PythonClassifier {
     py_module: Arc<PyObject>,
 }
 
 impl PythonClassifier {
+    /// Load the script named by `PARSEC_CLASSIFIER_SCRIPT`, or `./py/classifier.py`.
     pub fn new() -> Result<Self, ClassificationError> {
-        Python::with_gil(|py| {
-            // Add the Python script directory to sys.path
-            let sys = py.import("sys")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to import sys: {}", e)))?;
-            let path = sys.getattr("path")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to get sys.path: {}", e)))?;
-            path.call_method1("append", ("./py",))
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to append to sys.path: {}", e)))?;
-
-            // Import the classification module
-            let py_module = py.import("classifier")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to import classifier module: {}", e)))?;
-
-            Ok(PythonClassifier {
-                py_module: Arc::new(py_module.to_object(py)),
-            })
-        })
+        let script_path = std::env::var_os(SCRIPT_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SCRIPT_DIR));
+        Self::with_script_path(script_path)
     }
 
-    pub fn with_script_path(script_path: &str) -> Result<Self, ClassificationError> {
-        Python::with_gil(|py| {
-            let sys = py.import("sys")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to import sys: {}", e)))?;
-            let path = sys.getattr("path")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to get sys.path: {}", e)))?;
-            path.call_method1("append", (script_path,))
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to append to sys.path: {}", e)))?;
+    /// `script_path` is either a `.py` file or a directory containing `classifier.py`.
+    pub fn with_script_path(script_path: impl AsRef<Path>) -> Result<Self, ClassificationError> {
+        let script_path = script_path.as_ref();
+        let (dir, module) = if script_path.is_file() {
+            let module = script_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| {
+                    ClassificationError::PythonError(format!(
+                        "Invalid script name: {}",
+                        script_path.display()
+                    ))
+                })?;
+            let dir = script_path.parent().unwrap_or_else(|| Path::new("."));
+            (dir, module)
+        } else if script_path.is_dir() {
+            (script_path, DEFAULT_MODULE)
+        } else {
+            return Err(ClassificationError::PythonError(format!(
+                "Classifier script not found: {}",
+                script_path.display()
+            )));
+        };
 
-            let py_module = py.import("classifier")
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to import classifier module: {}", e)))?;
+        Python::with_gil(|py| {
+            // Put the script directory first so it wins over any installed `classifier`
+            let sys = py.import("sys").map_err(|e| {
+                ClassificationError::PythonError(format!("Failed to import sys: {}", e))
+            })?;
+            let path = sys.getattr("path").map_err(|e| {
+                ClassificationError::PythonError(format!("Failed to get sys.path: {}", e))
+            })?;
+            path.call_method1("insert", (0, dir.to_string_lossy().into_owned()))
+                .map_err(|e| {
+                    ClassificationError::PythonError(format!(
+                        "Failed to add {} to sys.path: {}",
+                        dir.display(),
+                        e
+                    ))
+                })?;
+
+            let py_module = py.import(module).map_err(|e| {
+                ClassificationError::PythonError(format!(
+                    "Failed to import classifier module `{}`: {}",
+                    module, e
+                ))
+            })?;
+            if !py_module.hasattr("classify_input").unwrap_or(false) {
+                return Err(ClassificationError::PythonError(format!(
+                    "Module `{}` has no classify_input function",
+                    module
+                )));
+            }
 
             Ok(PythonClassifier {
                 py_module: Arc::new(py_module.to_object(py)),
@@ -75,50 +88,53 @@ impl PythonClassifier {
 }
 
 impl CommandClassifier for PythonClassifier {
-    fn classify(&self, input: &str, context: Option<&Session>) -> Result<InputKind, ClassificationError> {
-        Python::with_gil(|py| {
-            // Prepare the classification request
-            let mut request = ClassificationRequest {
-                input: input.to_string(),
-                context: None,
-            };
-
-            // Add context if available
-            if let Some(session) = context {
-                request.context = Some(ClassificationContext {
-                    session_id: Some(session.id.clone()),
-                    history: session.command_history.iter()
-                        .map(|cmd| cmd.command.clone())
-                        .take(5)  // Last 5 commands for context
-                        .collect(),
-                });
-            }
-
-            // Serialize request to JSON
-            let request_json = serde_json::to_string(&request)
-                .map_err(|e| ClassificationError::ClassificationFailed(format!("Failed to serialize request: {}", e)))?;
-
-            // Call the Python classification function
-            let result = self.py_module
+    fn classify(
+        &self,
+        input: &str,
+        context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        let request = ClassificationRequest {
+            input: input.to_string(),
+            context: context.map(|session| ClassificationContext {
+                session_id: Some(session.id.clone()),
+                // Last 5 commands for context
+                history: session
+                    .command_history
+                    .iter()
+                    .rev()
+                    .take(5)
+                    .rev()
+                    .map(|cmd| cmd.command.clone())
+                    .collect(),
+            }),
+        };
+        let request_json = serde_json::to_string(&request)?;
+
+        let response_json: String = Python::with_gil(|py| {
+            self.py_module
                 .call_method1(py, "classify_input", (request_json,))
-                .map_err(|e| ClassificationError::PythonError(format!("Python function call failed: {}", e)))?;
-
-            // Extract the JSON response
-            let response_json: String = result.extract(py)
-                .map_err(|e| ClassificationError::PythonError(format!("Failed to extract response: {}", e)))?;
-
-            // Deserialize the response
-            let response: ClassificationResponse = serde_json::from_str(&response_json)
-                .map_err(|e| ClassificationError::InvalidJson(e))?;
-
-            // Convert to InputKind
-            match response.classification.as_str() {
-                "shell" => Ok(InputKind::Shell),
-                "prompt" => Ok(InputKind::Prompt),
-                _ => Err(ClassificationError::ClassificationFailed(
-                    format!("Unknown classification: {}", response.classification)
-                )),
+                .and_then(|result| result.extract(py))
+                .map_err(|e| {
+                    ClassificationError::PythonError(format!("classify_input failed: {}", e))
+                })
+        })?;
+        let response: ClassificationResponse = serde_json::from_str(&response_json)?;
+
+        let kind = match response.classification.as_str() {
+            "shell" => InputKind::Shell,
+            "prompt" => InputKind::Prompt,
+            other => {
+                return Err(ClassificationError::ClassificationFailed(format!(
+                    "Unknown classification: {}",
+                    other
+                )))
             }
-        })
+        };
+
+        Ok(Classification::new(
+            kind,
+            response.confidence as f32,
+            response.reasoning,
+        ))
     }
 }
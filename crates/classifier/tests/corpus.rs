@@ -0,0 +1,34 @@
+//! The heuristic classifier over the labeled corpus in `data/corpus.tsv`.
+
+use parsec_classifier::corpus::{self, MIN_HEURISTIC_ACCURACY};
+use parsec_classifier::HeuristicClassifier;
+use parsec_core::InputKind;
+
+#[test]
+fn the_corpus_parses_into_both_kinds() {
+    let kinds: Vec<_> = corpus::labeled_inputs().map(|(kind, _)| kind).collect();
+    assert_eq!(
+        kinds.len(),
+        corpus::LABELED_INPUTS
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .count(),
+        "every labeled line is <shell|prompt><TAB><input>"
+    );
+    assert!(kinds.contains(&InputKind::Shell));
+    assert!(kinds.contains(&InputKind::Prompt));
+}
+
+#[test]
+fn heuristic_accuracy_stays_above_the_floor() {
+    let report = corpus::evaluate(&HeuristicClassifier::default(), None);
+
+    assert!(
+        report.accuracy() >= MIN_HEURISTIC_ACCURACY,
+        "accuracy {:.3} over {} inputs is below {}; misclassified: {:#?}",
+        report.accuracy(),
+        report.total,
+        MIN_HEURISTIC_ACCURACY,
+        report.misclassified
+    );
+}
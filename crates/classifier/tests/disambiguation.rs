@@ -0,0 +1,118 @@
+//! Borderline inputs settled by asking, by an earlier answer, or by the default kind.
+
+use chrono::{Duration, Utc};
+use parsec_classifier::disambiguation::{self, Disambiguation, Disambiguator};
+use parsec_classifier::HeuristicClassifier;
+use parsec_core::*;
+use std::collections::HashMap;
+
+fn session() -> Session {
+    Session {
+        schema_version: SCHEMA_VERSION,
+        id: "session".to_string(),
+        created_at: Utc::now(),
+        last_active: Utc::now(),
+        conversations: Vec::new(),
+        command_history: Vec::new(),
+        global_context: GlobalContext {
+            working_directory: ".".into(),
+            environment_snapshot: HashMap::new(),
+            detected_project_type: None,
+            active_tools: Vec::new(),
+            tools_cache_key: None,
+        },
+        settings: SessionSettings::default(),
+        last_input_kind: None,
+        classification_log: Vec::new(),
+        classification_corrections: Vec::new(),
+        context_items: Vec::new(),
+        provider_health: None,
+        step_timings: Vec::new(),
+    }
+}
+
+fn answer(input: &str, kind: InputKind, disambiguated: bool) -> ClassificationCorrection {
+    ClassificationCorrection {
+        input_hash: input_hash(input),
+        first_word: parsec_classifier::heuristic::command_word(input).unwrap_or_default(),
+        corrected_to: kind,
+        classifier: "heuristic".to_string(),
+        corrected_at: Utc::now(),
+        disambiguated,
+    }
+}
+
+fn unsure() -> Classification {
+    Classification::new(InputKind::Prompt, 0.5, "no signals fired")
+}
+
+#[test]
+fn borderline_inputs_are_asked_about() {
+    let classifier = HeuristicClassifier::default();
+    let disambiguator = Disambiguator::new(0.6, InputKind::Prompt);
+    let session = session();
+
+    let borderline = classifier
+        .classify("deploy staging", Some(&session))
+        .unwrap();
+    assert_eq!(
+        disambiguator.disambiguate("deploy staging", &borderline, &session),
+        Disambiguation::Ask
+    );
+    let confident = classifier.classify("git status", Some(&session)).unwrap();
+    assert_eq!(
+        disambiguator.disambiguate("git status", &confident, &session),
+        Disambiguation::Confident
+    );
+}
+
+#[test]
+fn unattended_runs_take_the_default_kind() {
+    let session = session();
+    let shell = Disambiguator::new(0.6, InputKind::Shell).with_interactive(false);
+
+    assert_eq!(
+        shell.disambiguate("deploy staging", &unsure(), &session),
+        Disambiguation::Default(InputKind::Shell)
+    );
+}
+
+#[test]
+fn answers_are_remembered_for_similar_inputs_of_the_session() {
+    let disambiguator = Disambiguator::new(0.6, InputKind::Prompt);
+    let mut session = session();
+    session
+        .classification_corrections
+        .push(answer("deploy staging", InputKind::Shell, true));
+
+    for input in ["deploy staging", "deploy production"] {
+        assert_eq!(
+            disambiguator.disambiguate(input, &unsure(), &session),
+            Disambiguation::Remembered(InputKind::Shell)
+        );
+    }
+    assert_eq!(
+        disambiguator.disambiguate("rollout staging", &unsure(), &session),
+        Disambiguation::Ask
+    );
+
+    // Corrections typed with `wrong` or carried over from an earlier session are not answers
+    let mut carried = answer("release it", InputKind::Shell, true);
+    carried.corrected_at = session.created_at - Duration::days(1);
+    session.classification_corrections =
+        vec![carried, answer("deploy staging", InputKind::Shell, false)];
+    for input in ["release it", "deploy staging"] {
+        assert_eq!(
+            disambiguator.disambiguate(input, &unsure(), &session),
+            Disambiguation::Ask
+        );
+    }
+}
+
+#[test]
+fn answers_name_a_kind() {
+    assert_eq!(disambiguation::parse_answer("s\n"), Some(InputKind::Shell));
+    assert_eq!(disambiguation::parse_answer(" A "), Some(InputKind::Prompt));
+    assert_eq!(disambiguation::parse_answer("yes"), None);
+    assert_eq!(disambiguation::parse_answer("\n"), None);
+}
@@ -0,0 +1,141 @@
+//! `EnsembleClassifier` with a stubbed remote classifier in place of Hugging Face.
+
+use parsec_classifier::{EnsembleClassifier, HeuristicClassifier};
+use parsec_core::{Classification, ClassificationError, CommandClassifier, InputKind, Session};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Answers every input with the same canned result, after `delay`, recording the inputs
+/// it was asked about.
+struct StubRemote {
+    answer: Result<Classification, String>,
+    delay: Duration,
+    inputs: Mutex<Vec<String>>,
+}
+
+impl StubRemote {
+    fn answering(kind: InputKind, confidence: f32) -> Self {
+        Self {
+            answer: Ok(Classification::new(kind, confidence, "stub")),
+            delay: Duration::ZERO,
+            inputs: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn failing(message: &str) -> Self {
+        Self {
+            answer: Err(message.to_string()),
+            ..Self::answering(InputKind::Prompt, 0.0)
+        }
+    }
+
+    fn slow(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn inputs(&self) -> Vec<String> {
+        self.inputs.lock().unwrap().clone()
+    }
+}
+
+impl CommandClassifier for StubRemote {
+    fn classify(
+        &self,
+        input: &str,
+        _context: Option<&Session>,
+    ) -> Result<Classification, ClassificationError> {
+        self.inputs.lock().unwrap().push(input.to_string());
+        thread::sleep(self.delay);
+        self.answer
+            .clone()
+            .map_err(ClassificationError::ClassificationFailed)
+    }
+}
+
+/// An ensemble that consults `remote` for every input, the heuristic never being sure
+/// enough to skip it.
+fn always_consulting(remote: &Arc<StubRemote>) -> EnsembleClassifier {
+    EnsembleClassifier::new(HeuristicClassifier::default(), remote.clone())
+        .with_shell_cutoff(1.1)
+        .with_prompt_cutoff(1.1)
+}
+
+#[test]
+fn confident_heuristic_answers_skip_the_remote() {
+    let remote = Arc::new(StubRemote::answering(InputKind::Prompt, 0.99));
+    let ensemble = EnsembleClassifier::new(HeuristicClassifier::default(), remote.clone())
+        .with_shell_cutoff(0.0)
+        .with_prompt_cutoff(0.0);
+
+    let classification = ensemble.classify("ls -la", None).unwrap();
+
+    assert_eq!(classification.kind, InputKind::Shell);
+    assert!(classification
+        .reasoning
+        .unwrap()
+        .contains("above cutoff, remote skipped"));
+    assert!(remote.inputs().is_empty());
+}
+
+#[test]
+fn a_more_confident_remote_answer_wins() {
+    let remote = Arc::new(StubRemote::answering(InputKind::Prompt, 1.0));
+
+    let classification = always_consulting(&remote).classify("ls -la", None).unwrap();
+
+    assert_eq!(classification.kind, InputKind::Prompt);
+    assert_eq!(classification.confidence, 1.0);
+    assert!(classification.reasoning.unwrap().ends_with("chose remote"));
+    assert_eq!(remote.inputs(), vec!["ls -la".to_string()]);
+}
+
+#[test]
+fn a_less_confident_remote_answer_keeps_the_heuristic() {
+    let remote = Arc::new(StubRemote::answering(InputKind::Prompt, 0.0));
+    let heuristic = HeuristicClassifier::default()
+        .classify("ls -la", None)
+        .unwrap();
+
+    let classification = always_consulting(&remote).classify("ls -la", None).unwrap();
+
+    assert_eq!(classification.kind, heuristic.kind);
+    assert_eq!(classification.confidence, heuristic.confidence);
+    assert!(classification
+        .reasoning
+        .unwrap()
+        .ends_with("chose heuristic"));
+}
+
+#[test]
+fn remote_errors_fall_back_to_the_heuristic() {
+    let remote = Arc::new(StubRemote::failing("503 from the inference API"));
+
+    let classification = always_consulting(&remote).classify("ls -la", None).unwrap();
+
+    assert_eq!(classification.kind, InputKind::Shell);
+    let reasoning = classification.reasoning.unwrap();
+    assert!(reasoning.contains("remote failed"), "{}", reasoning);
+    assert!(
+        reasoning.contains("503 from the inference API"),
+        "{}",
+        reasoning
+    );
+    assert!(reasoning.ends_with("used heuristic"), "{}", reasoning);
+}
+
+#[test]
+fn slow_remotes_are_abandoned_for_the_heuristic() {
+    let remote =
+        Arc::new(StubRemote::answering(InputKind::Prompt, 1.0).slow(Duration::from_secs(2)));
+    let ensemble = always_consulting(&remote).with_timeout(Duration::from_millis(50));
+
+    let classification = ensemble.classify("ls -la", None).unwrap();
+
+    assert_eq!(classification.kind, InputKind::Shell);
+    assert!(classification
+        .reasoning
+        .unwrap()
+        .contains("remote failed (timed out after 50ms)"));
+}
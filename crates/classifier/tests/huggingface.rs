@@ -0,0 +1,130 @@
+//! `HuggingFaceClassifier` against a local server replaying canned HTTP responses.
+
+use parsec_classifier::HuggingFaceClassifier;
+use parsec_core::{CommandClassifier, InputKind};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const LOADING: (u16, &str) = (
+    503,
+    r#"{"error": "Model facebook/bart-large-mnli is currently loading", "estimated_time": 0.01}"#,
+);
+const SHELL: (u16, &str) = (
+    200,
+    r#"{"sequence": "ls -la", "labels": ["shell command", "conversational prompt"], "scores": [0.93, 0.07]}"#,
+);
+
+/// Serves `responses` in order, one per connection, and records the path of every
+/// request. Returns the base URL and the recorded paths.
+fn serve(responses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let recorded = paths.clone();
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut request_body = vec![0; content_length];
+            reader.read_exact(&mut request_body).unwrap();
+            recorded.lock().unwrap().push(
+                request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+
+            let reason = if status == 200 {
+                "OK"
+            } else {
+                "Service Unavailable"
+            };
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reason,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    (url, paths)
+}
+
+fn classifier(url: String) -> HuggingFaceClassifier {
+    HuggingFaceClassifier::new("token".to_string())
+        .unwrap()
+        .with_endpoint(url)
+        .with_max_loading_wait(Duration::from_millis(50))
+}
+
+#[test]
+fn a_loading_model_is_retried_and_its_answer_used() {
+    let (url, paths) = serve(vec![LOADING, SHELL]);
+
+    let classification = classifier(url).classify("ls -la", None).unwrap();
+
+    assert_eq!(*paths.lock().unwrap(), vec!["/facebook/bart-large-mnli"; 2]);
+    assert_eq!(classification.kind, InputKind::Shell);
+    assert!((classification.confidence - 0.93).abs() < 1e-6);
+    assert!(!classification
+        .reasoning
+        .unwrap()
+        .contains("heuristic fallback"));
+}
+
+#[test]
+fn a_model_still_loading_after_the_retries_falls_back_to_the_heuristic() {
+    let (url, paths) = serve(vec![LOADING, LOADING, SHELL]);
+
+    let classification = classifier(url)
+        .with_max_loading_retries(1)
+        .classify("ls -la", None)
+        .unwrap();
+
+    assert_eq!(paths.lock().unwrap().len(), 2);
+    assert_eq!(classification.kind, InputKind::Shell);
+    assert_eq!(classification.confidence, 0.0);
+    assert_eq!(
+        classification.reasoning.as_deref(),
+        Some("model still loading after 1 retries; used heuristic fallback")
+    );
+}
+
+#[test]
+fn other_unavailable_responses_fail_without_retrying() {
+    let (url, paths) = serve(vec![(503, "upstream overloaded"), SHELL]);
+
+    let error = classifier(url).classify("ls -la", None).unwrap_err();
+
+    assert_eq!(paths.lock().unwrap().len(), 1);
+    assert!(
+        error.to_string().contains("upstream overloaded"),
+        "{}",
+        error
+    );
+}
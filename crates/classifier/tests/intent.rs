@@ -0,0 +1,30 @@
+//! Prompts that ask questions, answered without a workflow, told from prompts asking for work.
+
+use parsec_classifier::intent::intent;
+use parsec_core::PromptIntent;
+
+#[test]
+fn questions_are_told_from_tasks() {
+    for question in [
+        "what does this error mean?",
+        "Why is the build so slow",
+        "which of these branches is safe to delete?",
+        "explain the difference between rebase and merge",
+        "is it safe to remove node_modules?",
+        "how does cargo resolve features?",
+        "What does this mean?\nerror[E0382]: borrow of moved value: `x`",
+    ] {
+        assert_eq!(intent(question), PromptIntent::Question, "{}", question);
+    }
+    for task in [
+        "set up a postgres database",
+        "delete the merged branches",
+        "how do I deploy this to staging",
+        "how to free up disk space",
+        "can you install the dependencies?",
+        "is the server running",
+        "",
+    ] {
+        assert_eq!(intent(task), PromptIntent::Task, "{}", task);
+    }
+}
@@ -0,0 +1,37 @@
+//! Runs `PythonClassifier` against `py/reference_classifier.py`.
+//!
+//! Needs a Python 3 interpreter with a shared libpython, so it is ignored by default:
+//! `cargo test -p parsec-classifier --features python -- --ignored`
+#![cfg(feature = "python")]
+
+use parsec_classifier::PythonClassifier;
+use parsec_core::{CommandClassifier, InputKind};
+use std::path::PathBuf;
+
+fn reference_script() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("py")
+        .join("reference_classifier.py")
+}
+
+#[test]
+#[ignore = "needs a Python 3 interpreter"]
+fn reference_script_classifies_shell_and_prompt() {
+    let classifier = PythonClassifier::with_script_path(reference_script()).unwrap();
+
+    let shell = classifier.classify("git status", None).unwrap();
+    assert_eq!(shell.kind, InputKind::Shell);
+    assert!(shell.confidence > 0.5);
+    assert!(shell.reasoning.unwrap().contains("command_verb"));
+
+    let prompt = classifier
+        .classify("how do I undo the last commit?", None)
+        .unwrap();
+    assert_eq!(prompt.kind, InputKind::Prompt);
+}
+
+#[test]
+#[ignore = "needs a Python 3 interpreter"]
+fn missing_script_is_an_error() {
+    assert!(PythonClassifier::with_script_path("/nonexistent/classifier.py").is_err());
+}
@@ -0,0 +1,305 @@
+//! Output and files a prompt refers to, attached to what the model is sent. `@last` is
+//! the output of the most recent command, whether the user ran it or a workflow step
+//! did; `@cmd:N` is the `N`th command of the session's history, as `!N` re-runs it; and
+//! `@file:path` is a small text file. Without a token, a prompt that speaks of "that",
+//! "this error" or "the output" soon after a command ran gets that command's output.
+//! Files given with `--context-file` and the project's manifests are attached too; see
+//! `context_files`.
+
+use crate::context_files::{ContextFiles, SkippedFile};
+use crate::{injection, secrets, CommandAttempt, DirectCommandExecution, Session, TruncatedText};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Attached command output keeps its first and last characters up to these.
+const OUTPUT_HEAD_CHARS: usize = 1500;
+const OUTPUT_TAIL_CHARS: usize = 4500;
+/// How recent the last command must be for "that" to mean its output.
+const IMPLICIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Tokens, each after the start of the prompt, whitespace or `(`.
+const TOKEN: &str = r"(?:^|[\s(])@(?:(?P<last>last)\b|cmd:(?P<cmd>\d+)|file:(?P<file>\S+))";
+/// Phrases that speak of output the user just saw.
+const IMPLICIT: &str = r"(?i)\b(?:(?:this|that|the) (?:error|output|failure|warning|message)s?|(?:the )?(?:last|previous) (?:command|output|error)|why did (?:that|it) fail|(?:did|does|fix|explain) that|that (?:failed|fails|happened|means?))\b";
+
+fn token() -> &'static Regex {
+    static TOKEN_RE: OnceLock<Regex> = OnceLock::new();
+    TOKEN_RE.get_or_init(|| Regex::new(TOKEN).expect("binding patterns compile"))
+}
+
+fn implicit() -> &'static Regex {
+    static IMPLICIT_RE: OnceLock<Regex> = OnceLock::new();
+    IMPLICIT_RE.get_or_init(|| Regex::new(IMPLICIT).expect("binding patterns compile"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    CommandOutput,
+    File,
+}
+
+impl AttachmentKind {
+    /// The tag the content is wrapped in, which the prompts say holds data only.
+    fn tag(self) -> &'static str {
+        match self {
+            AttachmentKind::CommandOutput => "command_output",
+            AttachmentKind::File => "file_content",
+        }
+    }
+}
+
+/// Output or a file attached to a prompt, redacted and capped as sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    /// What referred to it: a token such as `@cmd:3`, a phrase such as "this error",
+    /// `--context-file` or `project manifest`.
+    pub reference: String,
+    pub kind: AttachmentKind,
+    /// The command line with its exit status, or the file's path.
+    pub source: String,
+    pub content: TruncatedText,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum BindingError {
+    #[error("@last: no command has run yet")]
+    NoLastCommand,
+    #[error("@cmd:{0}: the history has no command {0}")]
+    NoCommand(usize),
+    #[error("{reference}: {reason}")]
+    File { reference: String, reason: String },
+}
+
+/// What a prompt refers to, and the manifests left out of it.
+#[derive(Debug, Default)]
+pub struct Bound {
+    pub attachments: Vec<Attachment>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// The output of a command, as a prompt may refer to it.
+#[derive(Debug, Clone, Copy)]
+pub struct RecentOutput<'a> {
+    pub command: &'a str,
+    pub exit_status: Option<i32>,
+    pub stdout: &'a str,
+    pub stderr: &'a str,
+    pub finished_at: DateTime<Utc>,
+}
+
+impl<'a> RecentOutput<'a> {
+    pub fn of_direct(execution: &'a DirectCommandExecution) -> Self {
+        Self {
+            command: &execution.command,
+            exit_status: Some(execution.exit_status),
+            stdout: &execution.stdout.content,
+            stderr: &execution.stderr.content,
+            finished_at: execution.completed_at.unwrap_or(execution.executed_at),
+        }
+    }
+
+    pub fn of_attempt(attempt: &'a CommandAttempt) -> Self {
+        Self {
+            command: &attempt.candidate.command,
+            exit_status: attempt.exit_status,
+            stdout: &attempt.stdout.content,
+            stderr: &attempt.stderr.content,
+            finished_at: attempt.completed_at.unwrap_or(attempt.timestamp),
+        }
+    }
+
+    fn attachment(&self, reference: String) -> Attachment {
+        let mut content = String::new();
+        for (label, text) in [("stdout", self.stdout), ("stderr", self.stderr)] {
+            if !text.trim().is_empty() {
+                content.push_str(&format!("{}:\n{}\n", label, text.trim_end()));
+            }
+        }
+        if content.is_empty() {
+            content.push_str("(no output)");
+        }
+        let exit = self
+            .exit_status
+            .map(|status| status.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Attachment {
+            reference,
+            kind: AttachmentKind::CommandOutput,
+            source: format!("`{}` (exit {})", self.command, exit),
+            content: TruncatedText::new_head_tail(content, OUTPUT_HEAD_CHARS, OUTPUT_TAIL_CHARS),
+        }
+    }
+}
+
+/// What `prompt` refers to in `session`, with `last` the most recent command output, at
+/// `now`, followed by the files `files` attaches to every prompt. Secrets are redacted
+/// with `redactor` if given, and in privacy mode the values of the session's environment
+/// too. A token or requested file naming something missing, unreadable or over the budget
+/// is an error; a phrase with no recent output is not, and manifests that cannot be
+/// attached are skipped.
+pub fn bind(
+    prompt: &str,
+    session: &Session,
+    last: Option<RecentOutput<'_>>,
+    files: &ContextFiles,
+    redactor: Option<&secrets::SecretRedactor>,
+    now: DateTime<Utc>,
+) -> Result<Bound, BindingError> {
+    let mut bound = Bound::default();
+    let mut bound_last = false;
+    for caps in token().captures_iter(prompt) {
+        let attachment = if caps.name("last").is_some() {
+            bound_last = true;
+            last.ok_or(BindingError::NoLastCommand)?
+                .attachment("@last".to_string())
+        } else if let Some(number) = caps.name("cmd") {
+            let number: usize = number.as_str().parse().unwrap_or(0);
+            let execution = number
+                .checked_sub(1)
+                .and_then(|index| session.command_history.get(index))
+                .ok_or(BindingError::NoCommand(number))?;
+            RecentOutput::of_direct(execution).attachment(format!("@cmd:{}", number))
+        } else if let Some(path) = caps.name("file") {
+            // Punctuation ending a sentence is not part of the path
+            let path = path
+                .as_str()
+                .trim_end_matches([',', ';', ':', '?', '!', '.', ')']);
+            let reference = format!("@file:{}", path);
+            file_attachment(&mut bound, files, session, path, reference, true)?;
+            continue;
+        } else {
+            continue;
+        };
+        push_new(&mut bound.attachments, attachment);
+    }
+
+    if !bound_last && bound.attachments.is_empty() {
+        let recent = last.filter(|last| {
+            (now - last.finished_at)
+                .to_std()
+                .is_ok_and(|age| age <= IMPLICIT_WINDOW)
+        });
+        if let (Some(recent), Some(phrase)) = (recent, implicit().find(prompt)) {
+            bound
+                .attachments
+                .push(recent.attachment(phrase.as_str().to_string()));
+        }
+    }
+
+    for path in files.requested() {
+        let path = path.display().to_string();
+        let reference = format!("--context-file {}", path);
+        file_attachment(&mut bound, files, session, &path, reference, true)?;
+    }
+    if session.global_context.detected_project_type.is_some() {
+        for name in files.manifests_in(&session.global_context.working_directory) {
+            let reference = "project manifest".to_string();
+            file_attachment(&mut bound, files, session, &name, reference, false)?;
+        }
+    }
+
+    for attachment in &mut bound.attachments {
+        let mut content = match redactor {
+            Some(redactor) => redactor.redact(&attachment.content.content),
+            None => attachment.content.content.clone(),
+        };
+        if session.settings.privacy_mode {
+            content = secrets::redact_environment_values(
+                &content,
+                &session.global_context.environment_snapshot,
+            );
+        }
+        attachment.content.content = content;
+    }
+    Ok(bound)
+}
+
+/// Attach the file at `path` unless it already is. One that cannot be read, or would go
+/// over the budget, is an error if `required` and otherwise skipped.
+fn file_attachment(
+    bound: &mut Bound,
+    files: &ContextFiles,
+    session: &Session,
+    path: &str,
+    reference: String,
+    required: bool,
+) -> Result<(), BindingError> {
+    if bound
+        .attachments
+        .iter()
+        .any(|attached| attached.kind == AttachmentKind::File && attached.source == path)
+    {
+        return Ok(());
+    }
+    let attached: usize = bound
+        .attachments
+        .iter()
+        .filter(|attached| attached.kind == AttachmentKind::File)
+        .map(|attached| attached.content.content.len())
+        .sum();
+    let read = files
+        .read(path, &session.global_context.working_directory)
+        .and_then(|content| {
+            if (attached + content.len()) as u64 > files.budget_bytes() {
+                Err(format!(
+                    "would take the files of this prompt over {} bytes",
+                    files.budget_bytes()
+                ))
+            } else {
+                Ok(content)
+            }
+        });
+    match read {
+        Ok(content) => {
+            let length = content.len();
+            bound.attachments.push(Attachment {
+                reference,
+                kind: AttachmentKind::File,
+                source: path.to_string(),
+                content: TruncatedText::new(content, length),
+            });
+            Ok(())
+        }
+        Err(reason) if required => Err(BindingError::File { reference, reason }),
+        Err(reason) => {
+            bound.skipped.push(SkippedFile {
+                path: path.to_string(),
+                reason,
+            });
+            Ok(())
+        }
+    }
+}
+
+fn push_new(attachments: &mut Vec<Attachment>, attachment: Attachment) {
+    if !attachments
+        .iter()
+        .any(|attached| attached.source == attachment.source)
+    {
+        attachments.push(attachment);
+    }
+}
+
+/// `prompt` followed by `attachments`, each in the tag of its kind with lines that read
+/// like instructions to the model withheld.
+pub fn render(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut rendered = prompt.to_string();
+    for attachment in attachments {
+        let tag = attachment.kind.tag();
+        let (content, _) = injection::withhold_instructions(&attachment.content.content);
+        rendered.push_str(&format!(
+            "\n\n{} ({}):\n<{}>\n{}\n</{}>",
+            attachment.reference,
+            attachment.source,
+            tag,
+            content.replace(&format!("</{}", tag), &format!("<\\/{}", tag)),
+            tag
+        ));
+    }
+    rendered
+}
@@ -0,0 +1,79 @@
+//! What validating a command before it runs found. Each finding names the rule that
+//! matched and where; `Block` rules stop the command outright, while `Warn` rules stop it
+//! until the rule is allowed in the configuration or the user types an override.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warn,
+    Block,
+}
+
+/// One rule matching part of a command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+    /// Short name of the rule, such as `rm-root`, used to allow it in the configuration.
+    pub rule: String,
+    /// Byte range of the match in the command.
+    pub span: Range<usize>,
+    pub matched: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (rule `{}`", self.message, self.rule)?;
+        if !self.matched.is_empty() {
+            write!(f, ", matched `{}`", self.matched)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Verdict {
+    Allow,
+    Warn(Vec<Finding>),
+    Block(Vec<Finding>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub verdict: Verdict,
+}
+
+impl ValidationReport {
+    /// The verdict the most severe of `findings` calls for.
+    pub fn from_findings(findings: Vec<Finding>) -> Self {
+        let verdict = match findings.iter().map(|finding| finding.severity).max() {
+            None => Verdict::Allow,
+            Some(Severity::Warn) => Verdict::Warn(findings),
+            Some(Severity::Block) => Verdict::Block(findings),
+        };
+        Self { verdict }
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        match &self.verdict {
+            Verdict::Allow => &[],
+            Verdict::Warn(findings) | Verdict::Block(findings) => findings,
+        }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        self.verdict == Verdict::Allow
+    }
+}
+
+/// `findings` joined for one line of output.
+pub fn describe(findings: &[Finding]) -> String {
+    findings
+        .iter()
+        .map(Finding::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -0,0 +1,437 @@
+//! Context items: what the model should remember about a session, derived from its
+//! direct commands and conversations. Failures become `Error` items, commands and steps
+//! that got something done become `Achievement`s and changed variables or directories
+//! become `Environment` items. Prompts include the items that score highest for the task
+//! at hand, weighing relevance to it, recency and importance.
+
+use crate::environment::is_secret_name;
+use crate::tools::command_programs;
+use crate::{
+    ContextItem, ContextType, ConversationContext, ConversationStatus, DirectCommandExecution,
+    ImportanceLevel, Session, StepStatus,
+};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Items a session keeps; the oldest go first.
+pub const MAX_SESSION_ITEMS: usize = 200;
+
+/// Programs that only look at things; running them successfully is not an achievement.
+const INSPECTION_PROGRAMS: &[&str] = &[
+    "cat", "clear", "df", "diff", "du", "echo", "env", "file", "find", "grep", "head", "history",
+    "less", "ls", "man", "more", "printenv", "ps", "pwd", "rg", "stat", "tail", "top", "tree",
+    "type", "wc", "which", "whoami",
+];
+
+/// Programs that change the shell's own environment.
+const ENVIRONMENT_PROGRAMS: &[&str] = &["cd", "export", "unset", "source", "."];
+
+/// Characters of stderr kept in an error item.
+const ERROR_DETAIL_CHARS: usize = 200;
+
+/// Relevance of an item that shares no words with the task; recent or important items
+/// still make it into prompts.
+const BASE_RELEVANCE: f32 = 0.3;
+
+impl ImportanceLevel {
+    pub fn weight(&self) -> f32 {
+        match self {
+            ImportanceLevel::Critical => 1.0,
+            ImportanceLevel::High => 0.75,
+            ImportanceLevel::Medium => 0.5,
+            ImportanceLevel::Low => 0.25,
+        }
+    }
+
+    /// One level up; `Critical` stays.
+    pub fn raised(self) -> Self {
+        match self {
+            ImportanceLevel::Low => ImportanceLevel::Medium,
+            ImportanceLevel::Medium => ImportanceLevel::High,
+            ImportanceLevel::High | ImportanceLevel::Critical => ImportanceLevel::Critical,
+        }
+    }
+}
+
+impl ContextType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContextType::Environment => "environment",
+            ContextType::Command => "command",
+            ContextType::Achievement => "achievement",
+            ContextType::Error => "error",
+            ContextType::Note => "note",
+        }
+    }
+}
+
+impl ContextItem {
+    pub fn new(
+        context_type: ContextType,
+        importance_level: ImportanceLevel,
+        content: String,
+        source: String,
+        created_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            content,
+            relevance_score: 1.0,
+            recency_weight: 1.0,
+            importance_level,
+            context_type,
+            source,
+            created_at,
+        }
+    }
+
+    /// Relevance times recency times importance, as last set by `select`.
+    pub fn score(&self) -> f32 {
+        self.relevance_score * self.recency_weight * self.importance_level.weight()
+    }
+
+    /// One line for a prompt: `[error] ...`.
+    pub fn render(&self) -> String {
+        format!("[{}] {}", self.context_type.as_str(), self.content)
+    }
+}
+
+/// How `select` picks items for a prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextSelection {
+    pub max_items: usize,
+    /// Characters the rendered items may take in total.
+    pub max_chars: usize,
+    /// Age at which an item's recency weight has halved.
+    pub half_life: Duration,
+}
+
+impl Default for ContextSelection {
+    fn default() -> Self {
+        Self {
+            max_items: 8,
+            max_chars: 2_000,
+            half_life: Duration::from_secs(12 * 60 * 60),
+        }
+    }
+}
+
+/// `0.5` per `half_life` of age at `now`; 1 for items from the future.
+pub fn recency_weight(created_at: DateTime<Utc>, now: DateTime<Utc>, half_life: Duration) -> f32 {
+    let age = (now - created_at).to_std().unwrap_or_default();
+    0.5f32.powf(age.as_secs_f32() / half_life.as_secs_f32().max(1.0))
+}
+
+/// Share of the words of `query` (three letters or more) that appear in `content`,
+/// scaled to between `BASE_RELEVANCE` and 1. An empty query finds everything relevant.
+pub fn relevance(content: &str, query: &str) -> f32 {
+    let words: HashSet<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return 1.0;
+    }
+    let content = content.to_lowercase();
+    let found = words
+        .iter()
+        .filter(|word| content.contains(word.as_str()))
+        .count();
+    BASE_RELEVANCE + (1.0 - BASE_RELEVANCE) * found as f32 / words.len() as f32
+}
+
+/// Relevance of an item whose embedding has cosine `similarity` to the task's, on the
+/// same scale as `relevance`.
+pub fn relevance_from_similarity(similarity: f32) -> f32 {
+    BASE_RELEVANCE + (1.0 - BASE_RELEVANCE) * similarity.clamp(0.0, 1.0)
+}
+
+/// The best `items` for a prompt about `query` at `now`, best first, with their
+/// `relevance_score` and `recency_weight` set. Items that would take the rendered list
+/// past `max_chars` are passed over for smaller ones.
+pub fn select<'a>(
+    items: impl IntoIterator<Item = &'a ContextItem>,
+    query: &str,
+    selection: &ContextSelection,
+    now: DateTime<Utc>,
+) -> Vec<ContextItem> {
+    select_scored(
+        items
+            .into_iter()
+            .map(|item| (item, relevance(&item.content, query))),
+        selection,
+        now,
+    )
+}
+
+/// As `select`, with each item's relevance given rather than found from a query.
+pub fn select_scored<'a>(
+    items: impl IntoIterator<Item = (&'a ContextItem, f32)>,
+    selection: &ContextSelection,
+    now: DateTime<Utc>,
+) -> Vec<ContextItem> {
+    let mut scored: Vec<ContextItem> = items
+        .into_iter()
+        .map(|(item, relevance_score)| ContextItem {
+            relevance_score,
+            recency_weight: recency_weight(item.created_at, now, selection.half_life),
+            ..item.clone()
+        })
+        .collect();
+    scored.sort_by(|a, b| {
+        b.score()
+            .total_cmp(&a.score())
+            .then_with(|| b.created_at.cmp(&a.created_at))
+    });
+
+    let mut selected = Vec::new();
+    let mut chars = 0;
+    for item in scored {
+        if selected.len() == selection.max_items {
+            break;
+        }
+        // One more for the line break
+        let len = item.render().chars().count() + 1;
+        if chars + len <= selection.max_chars {
+            chars += len;
+            selected.push(item);
+        }
+    }
+    selected
+}
+
+/// `items` one per line, as `select` returned them.
+pub fn render(items: &[ContextItem]) -> String {
+    items
+        .iter()
+        .map(|item| format!("- {}", item.render()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The item `execution` leaves behind, if any: an error if it failed, an environment
+/// change for `cd` and `export`, an achievement for anything else but inspection.
+pub fn from_direct_command(execution: &DirectCommandExecution) -> Option<ContextItem> {
+    let source = format!("command:{}", execution.executed_at.to_rfc3339());
+    let programs = command_programs(&execution.command);
+    let (context_type, importance, content) = if execution.exit_status != 0 {
+        let ended = match execution.termination {
+            Some(termination) => termination.to_string(),
+            None => format!("exited with status {}", execution.exit_status),
+        };
+        (
+            ContextType::Error,
+            ImportanceLevel::High,
+            with_detail(
+                format!("`{}` {}", execution.command, ended),
+                &execution.stderr.content,
+            ),
+        )
+    } else if programs
+        .iter()
+        .all(|program| ENVIRONMENT_PROGRAMS.contains(&program.as_str()))
+        && !programs.is_empty()
+    {
+        (
+            ContextType::Environment,
+            ImportanceLevel::Medium,
+            format!(
+                "Ran `{}` in {}",
+                execution.command,
+                execution.working_directory.display()
+            ),
+        )
+    } else if programs
+        .iter()
+        .all(|program| INSPECTION_PROGRAMS.contains(&program.as_str()))
+    {
+        return None;
+    } else {
+        (
+            ContextType::Achievement,
+            ImportanceLevel::Medium,
+            format!("Ran `{}` successfully", execution.command),
+        )
+    };
+    Some(ContextItem::new(
+        context_type,
+        importance,
+        content,
+        source,
+        execution.executed_at,
+    ))
+}
+
+/// The items of `conversation`: failed attempts, completed steps, variables it changed
+/// (by name only for secrets, or for all with `private`) and how it ended, led by the
+/// conversation's tags so that a task sharing a tag finds them.
+pub fn from_conversation(conversation: &ConversationContext, private: bool) -> Vec<ContextItem> {
+    let mut items = Vec::new();
+    let id = &conversation.id;
+    for step in &conversation.steps {
+        let description = &step.step.description;
+        for (i, attempt) in step.command_attempts.iter().enumerate() {
+            if !attempt.executed || attempt.exit_status == Some(0) {
+                continue;
+            }
+            items.push(ContextItem::new(
+                ContextType::Error,
+                ImportanceLevel::High,
+                with_detail(
+                    format!(
+                        "Step \"{}\": `{}` failed (exit {})",
+                        description,
+                        attempt.candidate.command,
+                        attempt.exit_description()
+                    ),
+                    &attempt.stderr.content,
+                ),
+                format!("{}:{}:{}", id, step.step.id, i),
+                attempt.timestamp,
+            ));
+        }
+        let done_by = step
+            .command_attempts
+            .iter()
+            .rev()
+            .find(|attempt| attempt.executed && attempt.exit_status == Some(0));
+        if let (StepStatus::Complete, Some(attempt)) = (&step.status, done_by) {
+            items.push(ContextItem::new(
+                ContextType::Achievement,
+                ImportanceLevel::Medium,
+                format!(
+                    "Step \"{}\" done with `{}`",
+                    description, attempt.candidate.command
+                ),
+                format!("{}:{}:done", id, step.step.id),
+                attempt.completed_at.unwrap_or(attempt.timestamp),
+            ));
+        }
+    }
+
+    for change in &conversation.context_summary.environment_changes {
+        let content = if private || is_secret_name(&change.variable_name) {
+            format!("{} was changed", change.variable_name)
+        } else {
+            format!("{} set to {}", change.variable_name, change.new_value)
+        };
+        items.push(ContextItem::new(
+            ContextType::Environment,
+            ImportanceLevel::Medium,
+            content,
+            format!(
+                "{}:env:{}:{}",
+                id,
+                change.variable_name,
+                change.changed_at.to_rfc3339()
+            ),
+            change.changed_at,
+        ));
+    }
+
+    let ended = match conversation.status {
+        ConversationStatus::Finished => Some((
+            ContextType::Achievement,
+            ImportanceLevel::High,
+            match conversation.context_summary.key_achievements.as_slice() {
+                [] => format!("Finished \"{}\"", conversation.name),
+                achievements => format!(
+                    "Finished \"{}\": {}",
+                    conversation.name,
+                    achievements.join("; ")
+                ),
+            },
+        )),
+        ConversationStatus::Aborted => Some((
+            ContextType::Error,
+            ImportanceLevel::Medium,
+            format!("Aborted \"{}\"", conversation.name),
+        )),
+        ConversationStatus::Error => Some((
+            ContextType::Error,
+            ImportanceLevel::High,
+            format!("\"{}\" stopped on a failed step", conversation.name),
+        )),
+        _ => None,
+    };
+    if let Some((context_type, importance, content)) = ended {
+        items.push(ContextItem::new(
+            context_type,
+            importance,
+            content,
+            format!("{}:{:?}", id, conversation.status),
+            conversation.last_activity(),
+        ));
+    }
+    for (i, note) in conversation.notes.iter().enumerate() {
+        items.push(ContextItem::new(
+            ContextType::Note,
+            ImportanceLevel::High,
+            format!("On \"{}\": {}", conversation.name, note.text),
+            format!("{}:note:{}", id, i),
+            note.added_at,
+        ));
+    }
+    // The user marked these as worth keeping
+    if conversation.pinned {
+        for item in &mut items {
+            item.importance_level = item.importance_level.raised();
+        }
+    }
+    if !conversation.tags.is_empty() {
+        let label = format!("[{}] ", conversation.tags.join(", "));
+        for item in &mut items {
+            item.content.insert_str(0, &label);
+        }
+    }
+    items
+}
+
+fn with_detail(summary: String, stderr: &str) -> String {
+    let detail = stderr.trim().lines().last().unwrap_or_default();
+    if detail.is_empty() {
+        return summary;
+    }
+    match detail.char_indices().nth(ERROR_DETAIL_CHARS) {
+        Some((end, _)) => format!("{}: {}…", summary, &detail[..end]),
+        None => format!("{}: {}", summary, detail),
+    }
+}
+
+impl Session {
+    /// Record the context item `execution` leaves behind, if any.
+    pub fn remember_command(&mut self, execution: &DirectCommandExecution) {
+        self.remember(from_direct_command(execution).into_iter());
+    }
+
+    /// Record the context items of `conversation` not recorded yet, and bring those
+    /// recorded before up to date with its name, tags, notes and pin.
+    pub fn remember_conversation(&mut self, conversation: &ConversationContext) {
+        // Notes the user removed go with their items
+        let notes = format!("{}:note:", conversation.id);
+        self.context_items
+            .retain(|item| !item.source.starts_with(&notes));
+        let items = from_conversation(conversation, self.settings.privacy_mode);
+        self.remember(items.into_iter());
+    }
+
+    fn remember(&mut self, items: impl Iterator<Item = ContextItem>) {
+        for item in items {
+            match self
+                .context_items
+                .iter_mut()
+                .find(|known| known.source == item.source)
+            {
+                Some(known) => {
+                    known.content = item.content;
+                    known.importance_level = item.importance_level;
+                }
+                None => self.context_items.push(item),
+            }
+        }
+        self.context_items.sort_by_key(|item| item.created_at);
+        let excess = self.context_items.len().saturating_sub(MAX_SESSION_ITEMS);
+        self.context_items.drain(..excess);
+    }
+}
@@ -0,0 +1,183 @@
+//! Files shown to the model with a prompt, so plans fit the project rather than being
+//! generic: the manifests of the working directory, such as `Cargo.toml` and
+//! `package.json`, files given with `--context-file`, and files a prompt names with
+//! `@file:`. Only text files are read, each up to a size cap and all of a prompt's within
+//! a budget, and files whose names usually hold secrets, such as `.env`, never are.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Files attached when they are in the working directory of a detected project.
+pub const DEFAULT_MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "requirements.txt",
+    "Pipfile",
+    "setup.py",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "Makefile",
+    "Dockerfile",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+    "compose.yaml",
+];
+
+/// File names, or patterns with one `*`, that are never attached.
+pub const DEFAULT_REFUSED: &[&str] = &[
+    ".env",
+    ".env.*",
+    "*.env",
+    ".envrc",
+    ".netrc",
+    ".npmrc",
+    ".pypirc",
+    ".pgpass",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "id_rsa*",
+    "id_ecdsa*",
+    "id_ed25519*",
+    "credentials*",
+    "secrets.*",
+];
+
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 16 * 1024;
+pub const DEFAULT_BUDGET_BYTES: u64 = 48 * 1024;
+
+/// Which files may be attached to prompts, and how much of them.
+#[derive(Debug, Clone)]
+pub struct ContextFiles {
+    manifests: Vec<String>,
+    refused: Vec<String>,
+    requested: Vec<PathBuf>,
+    max_file_bytes: u64,
+    budget_bytes: u64,
+}
+
+impl Default for ContextFiles {
+    fn default() -> Self {
+        Self {
+            manifests: DEFAULT_MANIFESTS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            refused: DEFAULT_REFUSED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            requested: Vec::new(),
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            budget_bytes: DEFAULT_BUDGET_BYTES,
+        }
+    }
+}
+
+/// A file left out of a prompt, and why.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+impl ContextFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// File names attached from the working directory of a detected project in place of
+    /// `DEFAULT_MANIFESTS`; empty attaches none.
+    pub fn with_manifests(mut self, manifests: Vec<String>) -> Self {
+        self.manifests = manifests;
+        self
+    }
+
+    /// File names or `*` patterns never attached, in place of `DEFAULT_REFUSED`.
+    pub fn with_refused(mut self, refused: Vec<String>) -> Self {
+        self.refused = refused;
+        self
+    }
+
+    /// Files attached to every prompt, relative to the working directory.
+    pub fn with_requested(mut self, requested: Vec<PathBuf>) -> Self {
+        self.requested = requested;
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    /// What the files attached to one prompt may hold together.
+    pub fn with_budget_bytes(mut self, budget_bytes: u64) -> Self {
+        self.budget_bytes = budget_bytes;
+        self
+    }
+
+    pub fn requested(&self) -> &[PathBuf] {
+        &self.requested
+    }
+
+    pub fn budget_bytes(&self) -> u64 {
+        self.budget_bytes
+    }
+
+    /// The manifests in `working_dir`, in the order they are configured.
+    pub fn manifests_in(&self, working_dir: &Path) -> Vec<String> {
+        self.manifests
+            .iter()
+            .filter(|name| working_dir.join(name).is_file())
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the name of `path` matches a refused pattern.
+    pub fn refuses(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        self.refused.iter().any(|pattern| matches(pattern, name))
+    }
+
+    /// The text of `path`, resolved against `working_dir`, or why it cannot be attached.
+    pub fn read(&self, path: &str, working_dir: &Path) -> Result<String, String> {
+        let resolved = working_dir.join(path);
+        if self.refuses(&resolved) {
+            return Err("files like this usually hold secrets and are not attached".to_string());
+        }
+        let metadata = std::fs::metadata(&resolved).map_err(|e| e.to_string())?;
+        if !metadata.is_file() {
+            return Err("not a file".to_string());
+        }
+        if metadata.len() > self.max_file_bytes {
+            return Err(format!(
+                "{} bytes; files of at most {} bytes can be attached",
+                metadata.len(),
+                self.max_file_bytes
+            ));
+        }
+        let bytes = std::fs::read(&resolved).map_err(|e| e.to_string())?;
+        match String::from_utf8(bytes) {
+            Ok(content) if !content.contains('\0') => Ok(content),
+            _ => Err("binary files cannot be attached".to_string()),
+        }
+    }
+}
+
+/// Whether `name` is `pattern`, where one `*` stands for any text.
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        }
+        None => name == pattern,
+    }
+}
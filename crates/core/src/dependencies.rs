@@ -0,0 +1,114 @@
+//! Step dependencies. A step lists the steps it needs in `depends_on` and is ready once
+//! they are all Complete; it can never run once one of them failed or was skipped. A step
+//! without the list runs once no step before it is pending, whatever became of them, so
+//! plans written without dependencies run in order as they always did.
+
+use crate::{ConversationContext, StepStatus, WorkflowStep, WorkflowStepState};
+
+impl AsRef<WorkflowStep> for WorkflowStep {
+    fn as_ref(&self) -> &WorkflowStep {
+        self
+    }
+}
+
+impl AsRef<WorkflowStep> for WorkflowStepState {
+    fn as_ref(&self) -> &WorkflowStep {
+        &self.step
+    }
+}
+
+/// Positions of the steps `steps[index]` waits for: those it depends on, or every step
+/// before it. Ids not among `steps` are ignored.
+pub fn dependencies(steps: &[impl AsRef<WorkflowStep>], index: usize) -> Vec<usize> {
+    match &steps[index].as_ref().depends_on {
+        None => (0..index).collect(),
+        Some(ids) => steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| ids.contains(&step.as_ref().id))
+            .map(|(i, _)| i)
+            .collect(),
+    }
+}
+
+/// Reject steps that depend on themselves, on steps not in `steps` or on each other in
+/// a cycle.
+pub fn check(steps: &[impl AsRef<WorkflowStep>]) -> Result<(), String> {
+    for step in steps {
+        let step = step.as_ref();
+        for id in step.depends_on.iter().flatten() {
+            if *id == step.id {
+                return Err(format!("Step \"{}\" depends on itself", step.description));
+            }
+            if !steps.iter().any(|other| other.as_ref().id == *id) {
+                return Err(format!(
+                    "Step \"{}\" depends on unknown step {}",
+                    step.description, id
+                ));
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        Visiting,
+        Done,
+    }
+    fn visit(
+        steps: &[impl AsRef<WorkflowStep>],
+        index: usize,
+        marks: &mut [Mark],
+    ) -> Result<(), String> {
+        match marks[index] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                return Err(format!(
+                    "Steps depend on each other in a cycle through \"{}\"",
+                    steps[index].as_ref().description
+                ))
+            }
+            Mark::Unvisited => {}
+        }
+        marks[index] = Mark::Visiting;
+        for dependency in dependencies(steps, index) {
+            visit(steps, dependency, marks)?;
+        }
+        marks[index] = Mark::Done;
+        Ok(())
+    }
+
+    let mut marks = vec![Mark::Unvisited; steps.len()];
+    (0..steps.len()).try_for_each(|index| visit(steps, index, &mut marks))
+}
+
+impl ConversationContext {
+    /// Pending steps that may run now, in plan order.
+    pub fn ready_steps(&self) -> Vec<usize> {
+        (0..self.steps.len())
+            .filter(|&index| {
+                let waits_for = |status: &StepStatus| match self.steps[index].step.depends_on {
+                    Some(_) => *status != StepStatus::Complete,
+                    None => matches!(status, StepStatus::Pending | StepStatus::NeedsAttention),
+                };
+                self.steps[index].status == StepStatus::Pending
+                    && !dependencies(&self.steps, index)
+                        .into_iter()
+                        .any(|dependency| waits_for(&self.steps[dependency].status))
+            })
+            .collect()
+    }
+
+    /// The first step `steps[index]` depends on that failed or was skipped, if any.
+    pub fn unmet_dependency(&self, index: usize) -> Option<usize> {
+        self.steps[index].step.depends_on.as_ref()?;
+        dependencies(&self.steps, index)
+            .into_iter()
+            .find(|&dependency| {
+                matches!(
+                    self.steps[dependency].status,
+                    StepStatus::Failed | StepStatus::Skipped
+                )
+            })
+    }
+}
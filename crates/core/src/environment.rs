@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Variables kept in a session's environment snapshot: the shell basics plus those that
+/// tell which toolchains and environments are active. A trailing `*` matches a prefix.
+pub const DEFAULT_ALLOWED: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "SHELL",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TERM",
+    "TZ",
+    "TMPDIR",
+    "EDITOR",
+    "VISUAL",
+    "PAGER",
+    "XDG_*",
+    "CARGO_HOME",
+    "CARGO_TARGET_DIR",
+    "RUSTUP_HOME",
+    "RUSTUP_TOOLCHAIN",
+    "GOPATH",
+    "GOROOT",
+    "GOBIN",
+    "JAVA_HOME",
+    "ANDROID_HOME",
+    "NODE_ENV",
+    "NVM_DIR",
+    "NPM_CONFIG_PREFIX",
+    "PNPM_HOME",
+    "VIRTUAL_ENV",
+    "CONDA_DEFAULT_ENV",
+    "CONDA_PREFIX",
+    "PYENV_VERSION",
+    "PYTHONPATH",
+    "GEM_HOME",
+    "RBENV_VERSION",
+    "KUBECONFIG",
+    "DOCKER_HOST",
+    "AWS_PROFILE",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "CI",
+];
+
+/// Words in a variable name that mark it as a credential; such variables are never kept.
+const SECRET_NAME_WORDS: &[&str] = &[
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "APIKEY",
+    "API_KEY",
+    "ACCESS_KEY",
+    "PRIVATE_KEY",
+    "CREDENTIAL",
+];
+
+/// PATH entries every system has; the summary lists only the others.
+const SYSTEM_PATH_DIRS: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr/bin",
+    "/usr/sbin",
+    "/usr/local/bin",
+    "/usr/local/sbin",
+    "/usr/games",
+    "/usr/local/games",
+    "/snap/bin",
+];
+
+/// Longest value the summary shows before cutting it short.
+const SUMMARY_VALUE_CHARS: usize = 60;
+
+/// Chooses which variables of the process environment a session records, so secrets and
+/// unrelated noise are neither stored nor sent to the model.
+#[derive(Debug, Clone)]
+pub struct EnvFilter {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl Default for EnvFilter {
+    fn default() -> Self {
+        Self {
+            allowed: DEFAULT_ALLOWED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            denied: Vec::new(),
+        }
+    }
+}
+
+impl EnvFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names or `PREFIX*` patterns to keep on top of `DEFAULT_ALLOWED`.
+    pub fn with_allowed(mut self, allowed: Vec<String>) -> Self {
+        self.allowed.extend(allowed);
+        self
+    }
+
+    /// Names or `PREFIX*` patterns to drop even when allowed.
+    pub fn with_denied(mut self, denied: Vec<String>) -> Self {
+        self.denied = denied;
+        self
+    }
+
+    pub fn keeps(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        !is_secret_name(name)
+            && self.allowed.iter().any(matches)
+            && !self.denied.iter().any(matches)
+    }
+
+    pub fn apply(
+        &self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> HashMap<String, String> {
+        vars.into_iter()
+            .filter(|(name, _)| self.keeps(name))
+            .collect()
+    }
+}
+
+/// Whether `name` looks like it holds a credential, such as `GITHUB_TOKEN` or `DB_PASSWORD`.
+pub fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_NAME_WORDS.iter().any(|word| name.contains(word))
+}
+
+/// A one-line description of `vars` for model prompts, such as
+/// `PATH contains cargo, nvm; EDITOR=nvim; LANG=en_US.UTF-8`. Empty when `vars` is.
+pub fn summarize(vars: &HashMap<String, String>) -> String {
+    let mut parts = Vec::new();
+    if let Some(path) = vars.get("PATH") {
+        let mut tools: Vec<String> = Vec::new();
+        for dir in std::env::split_paths(path) {
+            if let Some(tool) = path_tool(&dir) {
+                if !tools.contains(&tool) {
+                    tools.push(tool);
+                }
+            }
+        }
+        if !tools.is_empty() {
+            parts.push(format!("PATH contains {}", tools.join(", ")));
+        }
+    }
+    let mut names: Vec<&String> = vars.keys().filter(|name| *name != "PATH").collect();
+    names.sort();
+    for name in names {
+        let value = &vars[name];
+        let shown = match value.char_indices().nth(SUMMARY_VALUE_CHARS) {
+            Some((end, _)) => format!("{}...", &value[..end]),
+            None => value.clone(),
+        };
+        parts.push(format!("{}={}", name, shown));
+    }
+    parts.join("; ")
+}
+
+/// What a non-system PATH entry provides, named after its hidden directory
+/// (`~/.cargo/bin` is `cargo`) or else the directory holding `bin`.
+fn path_tool(dir: &Path) -> Option<String> {
+    if dir.as_os_str().is_empty()
+        || SYSTEM_PATH_DIRS
+            .iter()
+            .any(|system| dir == Path::new(system))
+    {
+        return None;
+    }
+    let names: Vec<&str> = dir
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect();
+    names
+        .iter()
+        .find_map(|name| name.strip_prefix('.').filter(|name| !name.is_empty()))
+        .or_else(|| {
+            names
+                .iter()
+                .rev()
+                .find(|name| !matches!(**name, "bin" | "sbin" | "/"))
+                .copied()
+        })
+        .map(str::to_string)
+}
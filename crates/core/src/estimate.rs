@@ -0,0 +1,149 @@
+//! Rough step run times for plans and a running ETA. A step is estimated by the median run
+//! time of earlier steps with the same normalized description when the session has one,
+//! and otherwise by the planner's guess; a finished step counts with what it actually
+//! took. Estimates are advisory: they bound command timeouts only when the user opts in.
+
+use crate::{
+    ConversationContext, EstimateSource, Session, StepStatus, StepTiming, WorkflowPlan,
+    WorkflowStep, WorkflowStepState,
+};
+use chrono::Utc;
+use std::time::Duration;
+
+/// Timings a session keeps; the oldest go first.
+pub const MAX_STEP_TIMINGS: usize = 200;
+/// With estimate timeouts on, a command may take this many times its step's estimate.
+const TIMEOUT_FACTOR: u32 = 3;
+/// Estimate timeouts are never shorter than this.
+const MIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `description` lowercased without punctuation or words holding digits, such as versions
+/// and ports, so "Bump the version to 1.2" matches "bump the version to 1.3".
+pub fn normalized(description: &str) -> String {
+    description
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !word.chars().any(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The timeout for the commands of a step estimated to take `estimate`, when the user
+/// opted in to estimate timeouts.
+pub fn timeout(estimate: Duration) -> Duration {
+    (estimate * TIMEOUT_FACTOR).max(MIN_TIMEOUT)
+}
+
+impl WorkflowStep {
+    pub fn estimate(&self) -> Option<Duration> {
+        self.estimated_minutes
+            .map(|minutes| Duration::from_secs_f32(minutes * 60.0))
+    }
+}
+
+impl WorkflowStepState {
+    /// How long the step's commands ran, over all its attempts.
+    pub fn run_time(&self) -> Duration {
+        self.command_attempts
+            .iter()
+            .map(|attempt| attempt.duration)
+            .sum()
+    }
+}
+
+impl Session {
+    /// Record the run time of each completed step of `conversation` not recorded yet.
+    pub fn remember_step_timings(&mut self, conversation: &ConversationContext) {
+        for state in &conversation.steps {
+            if state.status != StepStatus::Complete
+                || !state
+                    .command_attempts
+                    .iter()
+                    .any(|attempt| attempt.executed)
+                || self
+                    .step_timings
+                    .iter()
+                    .any(|timing| timing.step_id == state.step.id)
+            {
+                continue;
+            }
+            self.step_timings.push(StepTiming {
+                step_id: state.step.id.clone(),
+                description: normalized(&state.step.description),
+                duration: state.run_time(),
+                completed_at: Utc::now(),
+            });
+        }
+        let excess = self.step_timings.len().saturating_sub(MAX_STEP_TIMINGS);
+        self.step_timings.drain(..excess);
+    }
+
+    /// The median run time of the recorded steps like `description`, and how many there are.
+    pub fn median_step_time(&self, description: &str) -> Option<(Duration, usize)> {
+        let description = normalized(description);
+        let mut times: Vec<Duration> = self
+            .step_timings
+            .iter()
+            .filter(|timing| timing.description == description)
+            .map(|timing| timing.duration)
+            .collect();
+        times.sort();
+        let middle = times.len() / 2;
+        let median = match times.len() {
+            0 => return None,
+            n if n % 2 == 1 => times[middle],
+            _ => (times[middle - 1] + times[middle]) / 2,
+        };
+        Some((median, times.len()))
+    }
+}
+
+/// Estimate the steps of `plan` that the session has run before by the median of those
+/// runs rather than the planner's guess.
+pub fn from_history(plan: &mut WorkflowPlan, session: &Session) {
+    for step in &mut plan.steps {
+        if let Some((median, _)) = session.median_step_time(&step.description) {
+            step.estimated_minutes = Some(median.as_secs_f32() / 60.0);
+            step.estimate_source = EstimateSource::History;
+        }
+    }
+}
+
+/// Where a workflow stands in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Eta {
+    /// What the finished steps took.
+    pub spent: Duration,
+    /// What the steps still to run are estimated to take, less what they ran so far.
+    pub remaining: Duration,
+    /// Steps still to run without an estimate, which `remaining` leaves out.
+    pub unestimated: usize,
+}
+
+/// The ETA of `conversation`, or `None` when no step still to run has an estimate.
+pub fn eta(conversation: &ConversationContext) -> Option<Eta> {
+    let mut eta = Eta {
+        spent: Duration::ZERO,
+        remaining: Duration::ZERO,
+        unestimated: 0,
+    };
+    let mut estimated = false;
+    for state in &conversation.steps {
+        match state.status {
+            StepStatus::Complete | StepStatus::Failed | StepStatus::Skipped => {
+                eta.spent += state.run_time();
+            }
+            StepStatus::Pending
+            | StepStatus::CommandSuggested
+            | StepStatus::Running
+            | StepStatus::NeedsAttention => match state.step.estimate() {
+                Some(estimate) => {
+                    estimated = true;
+                    eta.remaining += estimate.saturating_sub(state.run_time());
+                }
+                None => eta.unestimated += 1,
+            },
+        }
+    }
+    (estimated || eta.unestimated == 0).then_some(eta)
+}
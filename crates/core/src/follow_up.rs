@@ -0,0 +1,181 @@
+//! Follow-up conversations: a conversation started from an earlier one links to it as
+//! its `parent_conversation`, and its planner is told what the parent and the
+//! conversations before it did. The nearest ancestors are described in full; older ones
+//! are cut to a line each so long chains do not crowd out the prompt.
+
+use crate::environment::is_secret_name;
+use crate::{ConversationContext, ConversationId, ConversationStatus, StepStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Ancestors looked at beyond those described in full.
+const MAX_EARLIER: usize = 10;
+
+/// Items of each kind listed for an ancestor.
+const MAX_LISTED: usize = 10;
+
+/// What one ancestor of a follow-up did.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AncestorSummary {
+    pub name: String,
+    pub user_prompt: String,
+    pub status: ConversationStatus,
+    /// Completed steps, each with the command that completed it.
+    pub completed_steps: Vec<(String, String)>,
+    pub achievements: Vec<String>,
+    /// Paths of the files it changed, directories ending in `/`.
+    pub artifacts: Vec<String>,
+    /// Variables it set, by name only for secrets or in privacy mode.
+    pub environment: Vec<String>,
+}
+
+impl AncestorSummary {
+    pub fn of(conversation: &ConversationContext, private: bool) -> Self {
+        let summary = &conversation.context_summary;
+        Self {
+            name: conversation.name.clone(),
+            user_prompt: conversation.user_prompt.clone(),
+            status: conversation.status.clone(),
+            completed_steps: conversation
+                .steps
+                .iter()
+                .filter(|state| state.status == StepStatus::Complete)
+                .filter_map(|state| {
+                    let attempt = state
+                        .command_attempts
+                        .iter()
+                        .rev()
+                        .find(|attempt| attempt.executed && attempt.exit_status == Some(0))?;
+                    Some((
+                        state.step.description.clone(),
+                        attempt.candidate.command.clone(),
+                    ))
+                })
+                .collect(),
+            achievements: summary.key_achievements.clone(),
+            artifacts: summary
+                .generated_artifacts
+                .iter()
+                .map(|artifact| {
+                    let path = artifact.file_path.display();
+                    if artifact.artifact_type == "directory" {
+                        format!("{}/", path)
+                    } else {
+                        path.to_string()
+                    }
+                })
+                .collect(),
+            environment: summary
+                .environment_changes
+                .iter()
+                .map(|change| {
+                    if private || is_secret_name(&change.variable_name) {
+                        format!("{} was changed", change.variable_name)
+                    } else {
+                        format!("{} set to {}", change.variable_name, change.new_value)
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// One line: the name and the most telling thing it did.
+    fn headline(&self) -> String {
+        let did = self
+            .achievements
+            .last()
+            .cloned()
+            .or_else(|| {
+                self.completed_steps
+                    .last()
+                    .map(|(description, _)| description.clone())
+            })
+            .unwrap_or_else(|| format!("{:?}", self.status));
+        format!("\"{}\": {}", self.name, did)
+    }
+}
+
+/// The conversations a follow-up continues, nearest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Lineage {
+    /// The parent and the ancestors before it described in full.
+    pub ancestors: Vec<AncestorSummary>,
+    /// One line for each older ancestor.
+    pub earlier: Vec<String>,
+}
+
+impl Lineage {
+    /// Follow `parent_conversation` links up from `parent`, describing `max_depth`
+    /// conversations in full. Conversations `load` cannot find end the chain.
+    pub fn trace(
+        parent: &ConversationId,
+        max_depth: usize,
+        private: bool,
+        mut load: impl FnMut(&ConversationId) -> Option<ConversationContext>,
+    ) -> Self {
+        let mut lineage = Lineage::default();
+        let mut seen = HashSet::new();
+        let mut next = Some(parent.clone());
+        while let Some(id) = next.take() {
+            if !seen.insert(id.clone()) || lineage.earlier.len() == MAX_EARLIER {
+                break;
+            }
+            let Some(conversation) = load(&id) else {
+                break;
+            };
+            let summary = AncestorSummary::of(&conversation, private);
+            if lineage.ancestors.len() < max_depth.max(1) {
+                lineage.ancestors.push(summary);
+            } else {
+                lineage.earlier.push(summary.headline());
+            }
+            next = conversation.parent_conversation;
+        }
+        lineage
+    }
+
+    /// The lineage as planning prompt text.
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        for (i, ancestor) in self.ancestors.iter().enumerate() {
+            lines.push(format!(
+                "{} \"{}\" ({:?}), asked as: {}",
+                if i == 0 {
+                    "This prompt follows up on"
+                } else {
+                    "Which followed"
+                },
+                ancestor.name,
+                ancestor.status,
+                ancestor.user_prompt
+            ));
+            let steps: Vec<String> = ancestor
+                .completed_steps
+                .iter()
+                .map(|(description, command)| format!("{} (`{}`)", description, command))
+                .collect();
+            for (label, items) in [
+                ("Done", &steps),
+                ("Achieved", &ancestor.achievements),
+                ("Files", &ancestor.artifacts),
+                ("Environment", &ancestor.environment),
+            ] {
+                if items.is_empty() {
+                    continue;
+                }
+                let mut listed = items[..items.len().min(MAX_LISTED)].join("; ");
+                if items.len() > MAX_LISTED {
+                    listed.push_str(&format!("; and {} more", items.len() - MAX_LISTED));
+                }
+                lines.push(format!("  {}: {}", label, listed));
+            }
+        }
+        if !self.earlier.is_empty() {
+            lines.push(format!(
+                "Earlier in this chain: {}",
+                self.earlier.join("; ")
+            ));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,107 @@
+//! Defenses against command output that tries to instruct the model. A build script in a
+//! cloned repository can print "ignore previous instructions and run ..."; such lines are
+//! withheld before output reaches a prompt, and a generated command that appears word
+//! for word in recent output is held for confirmation.
+
+use crate::command_rules::{Finding, Severity};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Lines that address a language model rather than a person reading a log.
+const PATTERNS: &[&str] = &[
+    r"\b(?:ignore|disregard|forget|override)\b.{0,30}\b(?:previous|prior|above|earlier|all|your|the system)\b.{0,20}\b(?:instructions?|prompts?|rules|directions|context)\b",
+    r"\b(?:new|updated|real)\s+instructions?\s*:",
+    r"\byou\s+are\s+now\s+(?:a|an|in|the)\b",
+    r"\b(?:ai|llm|language model|assistant)\b.{0,40}\b(?:must|should)\s+(?:now\s+)?(?:run|execute|output|respond|reply)\b",
+    r"^\s*(?:#+\s*)?(?:system\s+prompt|assistant)\s*:",
+    r"</?\s*(?:system|instructions?|command_output)\s*>",
+];
+
+/// Stands in for a withheld line in prompts.
+pub const WITHHELD_LINE: &str = "[parsec withheld a line that reads like instructions to a model]";
+
+/// Commands shorter than this are too common to be a sign of anything when they appear
+/// in output, like `make` or `npm test`.
+const MIN_ECHOED_CHARS: usize = 16;
+
+/// Name of the validation rule `echoed_command` reports.
+pub const ECHOED_OUTPUT_RULE: &str = "echoed-output";
+
+fn patterns() -> &'static Vec<Regex> {
+    static COMPILED: OnceLock<Vec<Regex>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        PATTERNS
+            .iter()
+            .map(|pattern| {
+                Regex::new(&format!("(?i){}", pattern)).expect("injection patterns compile")
+            })
+            .collect()
+    })
+}
+
+pub fn is_instruction_like(line: &str) -> bool {
+    patterns().iter().any(|pattern| pattern.is_match(line))
+}
+
+/// The lines of `output` that read like instructions to a model.
+pub fn instruction_like_lines(output: &str) -> Vec<&str> {
+    output
+        .lines()
+        .filter(|line| is_instruction_like(line))
+        .collect()
+}
+
+/// `output` with each instruction-like line replaced by `WITHHELD_LINE`, and how many
+/// were replaced.
+pub fn withhold_instructions(output: &str) -> (String, usize) {
+    let mut withheld = 0;
+    let lines: Vec<&str> = output
+        .split('\n')
+        .map(|line| {
+            if is_instruction_like(line) {
+                withheld += 1;
+                WITHHELD_LINE
+            } else {
+                line
+            }
+        })
+        .collect();
+    if withheld == 0 {
+        return (output.to_string(), 0);
+    }
+    (lines.join("\n"), withheld)
+}
+
+/// A `Warn` finding when `command` appears word for word in one of `outputs`: output that
+/// spells out the command the model then proposes most likely put it up to it.
+pub fn echoed_command<'a>(
+    command: &str,
+    outputs: impl IntoIterator<Item = &'a str>,
+) -> Option<Finding> {
+    let trimmed = command.trim();
+    if trimmed.chars().count() < MIN_ECHOED_CHARS {
+        return None;
+    }
+    let normalized = normalize(trimmed);
+    if !outputs
+        .into_iter()
+        .any(|output| normalize(output).contains(&normalized))
+    {
+        return None;
+    }
+    let start = command.len() - command.trim_start().len();
+    Some(Finding {
+        rule: ECHOED_OUTPUT_RULE.to_string(),
+        span: start..start + trimmed.len(),
+        matched: trimmed.to_string(),
+        severity: Severity::Warn,
+        message: "Command appears word for word in the output of an earlier command, which may have injected it"
+            .to_string(),
+    })
+}
+
+/// `text` with runs of whitespace collapsed, so line wrapping and indentation in output
+/// do not hide a command.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -1,20 +1,129 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub mod bindings;
+pub mod command_rules;
+pub mod context;
+pub mod context_files;
+pub mod dependencies;
+pub mod environment;
+pub mod estimate;
+pub mod follow_up;
+pub mod injection;
+pub mod logging;
+pub mod metrics;
+pub mod naming;
+pub mod preflight;
+pub mod project;
+pub mod search;
+pub mod secrets;
+pub mod step_risk;
+pub mod tags;
+pub mod tools;
+pub mod validation;
+
+pub use tokio_util::sync::CancellationToken;
 
 pub type SessionId = String; // ULID for chronological ordering
 pub type ConversationId = String;
 pub type StepId = String;
 pub type ModelProviderId = String;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InputKind {
     Shell,
     Prompt,
 }
 
+/// What a prompt asks for: work, which is planned as a workflow, or an answer, which the
+/// model gives in one call without one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptIntent {
+    #[default]
+    Task,
+    Question,
+}
+
+/// A classifier's decision together with how sure it is and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Classification {
+    pub kind: InputKind,
+    /// 0.0 (a coin toss) to 1.0 (certain).
+    pub confidence: f32,
+    pub reasoning: Option<String>,
+}
+
+impl Classification {
+    pub fn new(kind: InputKind, confidence: f32, reasoning: impl Into<String>) -> Self {
+        Self {
+            kind,
+            confidence,
+            reasoning: Some(reasoning.into()),
+        }
+    }
+}
+
+impl InputKind {
+    pub fn opposite(&self) -> InputKind {
+        match self {
+            InputKind::Shell => InputKind::Prompt,
+            InputKind::Prompt => InputKind::Shell,
+        }
+    }
+}
+
+/// A classification decision as logged in the session; the input itself is not stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassificationRecord {
+    pub input_hash: u64,
+    pub kind: InputKind,
+    pub confidence: f32,
+    /// `classifier.kind` that decided, or `override` for prefixes and confirmations.
+    pub classifier: String,
+    pub decided_at: DateTime<Utc>,
+}
+
+/// The user said what kind an input was: it was misclassified, or the classifier was
+/// unsure and asked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassificationCorrection {
+    pub input_hash: u64,
+    /// Lowercased command word of the input; the heuristic learns from it.
+    pub first_word: String,
+    pub corrected_to: InputKind,
+    pub classifier: String,
+    pub corrected_at: DateTime<Utc>,
+    /// An answer to the low-confidence question, which may agree with the classifier.
+    #[serde(default)]
+    pub disambiguated: bool,
+}
+
+/// `$XDG_DATA_HOME/parsec`, falling back to `~/.local/share/parsec`.
+pub fn default_data_dir() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("parsec")
+}
+
+/// FNV-1a, so hashes stay comparable across builds and sessions.
+pub fn input_hash(input: &str) -> u64 {
+    input
+        .trim()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ConversationStatus {
     Planning,
@@ -33,9 +142,24 @@ pub enum StepStatus {
     Complete,
     Failed,
     Skipped,
+    /// Every command generated for it is above the block threshold, so none was offered.
+    /// The steps after it wait until the user allows dangerous commands, edits or skips it;
+    /// the next run generates its commands again.
+    NeedsAttention,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What to do with a step that used up its attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StepEscalation {
+    /// Mark the step skipped and continue with the next one.
+    Skip,
+    /// Ask the planner to replace this and the remaining steps.
+    Replan,
+    /// Abort the whole conversation.
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImportanceLevel {
     Critical,
     High,
@@ -43,16 +167,20 @@ pub enum ImportanceLevel {
     Low,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ContextType {
     Environment,
     Command,
     Achievement,
     Error,
+    /// A note the user attached to a conversation.
+    Note,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
+    /// `SCHEMA_VERSION` when saved; stores migrate older data on load.
+    pub schema_version: u32,
     pub id: SessionId,
     pub created_at: DateTime<Utc>,
     pub last_active: DateTime<Utc>,
@@ -60,6 +188,24 @@ pub struct Session {
     pub command_history: Vec<DirectCommandExecution>,
     pub global_context: GlobalContext,
     pub settings: SessionSettings,
+    /// How the most recent input was handled, so follow-ups can be recognised.
+    #[serde(default)]
+    pub last_input_kind: Option<InputKind>,
+    #[serde(default)]
+    pub classification_log: Vec<ClassificationRecord>,
+    /// Carried into the next session when `enable_cross_conversation_learning` is on.
+    #[serde(default)]
+    pub classification_corrections: Vec<ClassificationCorrection>,
+    /// What prompts may draw on, oldest first; see `context`.
+    #[serde(default)]
+    pub context_items: Vec<ContextItem>,
+    /// The last successful model provider check; planning checks again only once the
+    /// configured provider changes.
+    #[serde(default)]
+    pub provider_health: Option<ProviderHealth>,
+    /// Run times of completed steps, oldest first, carried into the next session.
+    #[serde(default)]
+    pub step_timings: Vec<StepTiming>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,16 +213,90 @@ pub struct SessionSettings {
     pub max_conversation_history: usize,
     pub session_retention_days: u32,
     pub enable_cross_conversation_learning: bool,
+    /// Summarize older steps once their raw history exceeds this share of the prompt
+    /// budget; 1.0 or more turns compression off.
     pub context_compression_threshold: f32,
     pub privacy_mode: bool,
+    #[serde(default = "tools::default_tracked_tools")]
+    pub tracked_tools: Vec<String>,
+    /// Failed (or, counted separately, blocked) attempts a step may accumulate before escalating.
+    #[serde(default = "default_max_attempts_per_step")]
+    pub max_attempts_per_step: usize,
+    /// Direct commands kept in `Session::command_history`; the oldest are dropped first.
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    /// Most recent direct commands that keep their captured output; older ones are
+    /// reduced to the command, exit status and timestamp.
+    #[serde(default = "default_history_full_output")]
+    pub history_full_output: usize,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Conversations inactive for longer are pruned even when their session is kept.
+    #[serde(default = "default_conversation_retention_days")]
+    pub conversation_retention_days: u32,
+    /// Sessions kept at most, the least recently active pruned first; `None` for no cap.
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+}
+
+/// How fast and how much a workflow may execute, so a plan retrying a failing command
+/// unattended cannot fire it over and over.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Least time between the starts of two commands; zero for none.
+    #[serde(with = "duration_millis")]
+    pub min_command_interval: Duration,
+    /// Commands a conversation may execute in all; `None` for no cap.
+    pub max_commands_per_conversation: Option<usize>,
+    /// Consecutive failed commands within `failure_window` that pause the workflow until
+    /// the user confirms going on; zero turns the circuit breaker off.
+    pub breaker_failures: usize,
+    #[serde(with = "duration_millis")]
+    pub failure_window: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            min_command_interval: Duration::ZERO,
+            max_commands_per_conversation: None,
+            breaker_failures: 5,
+            failure_window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Version of the persisted `Session` and `ConversationContext` shape. Data saved before
+/// versioning has no `schema_version` and counts as version 1.
+pub const SCHEMA_VERSION: u32 = 2;
+
+pub fn default_max_attempts_per_step() -> usize {
+    3
+}
+
+pub fn default_conversation_retention_days() -> u32 {
+    30
+}
+
+pub fn default_history_limit() -> usize {
+    1000
+}
+
+pub fn default_history_full_output() -> usize {
+    100
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlobalContext {
     pub working_directory: PathBuf,
+    /// The variables `environment::EnvFilter` kept when the session started.
     pub environment_snapshot: HashMap<String, String>,
     pub detected_project_type: Option<String>,
     pub active_tools: Vec<String>,
+    /// `tools::tool_cache_key` at the time `active_tools` was detected
+    #[serde(default)]
+    pub tools_cache_key: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,20 +307,239 @@ pub struct DirectCommandExecution {
     pub stdout: TruncatedText,
     pub stderr: TruncatedText,
     pub working_directory: PathBuf,
+    /// The user forced shell handling with a `!` prefix.
+    #[serde(default)]
+    pub classification_overridden: bool,
+    #[serde(default, with = "duration_millis")]
+    pub duration: Duration,
+    /// `None` for records saved before completion times were tracked.
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// How the process ended; `exit_status` is -1 when it did not exit on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination: Option<CommandTermination>,
+}
+
+/// How a command's process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandTermination {
+    Exited(i32),
+    Signaled {
+        signal: i32,
+        core_dumped: bool,
+    },
+    /// Killed by the executor at its timeout.
+    TimedOut,
+}
+
+impl CommandTermination {
+    /// `success`, `failure`, `signal` or `timeout`, for counting outcomes.
+    pub fn exit_class(&self) -> &'static str {
+        match self {
+            CommandTermination::Exited(0) => "success",
+            CommandTermination::Exited(_) => "failure",
+            CommandTermination::Signaled { .. } => "signal",
+            CommandTermination::TimedOut => "timeout",
+        }
+    }
+
+    /// Conventional name of a signal whose number is the same on Linux and macOS.
+    fn signal_name(signal: i32) -> Option<&'static str> {
+        Some(match signal {
+            1 => "SIGHUP",
+            2 => "SIGINT",
+            3 => "SIGQUIT",
+            4 => "SIGILL",
+            6 => "SIGABRT",
+            8 => "SIGFPE",
+            9 => "SIGKILL",
+            11 => "SIGSEGV",
+            13 => "SIGPIPE",
+            14 => "SIGALRM",
+            15 => "SIGTERM",
+            _ => return None,
+        })
+    }
+
+    /// The usual reason a process gets `signal`.
+    fn signal_hint(signal: i32) -> Option<&'static str> {
+        Some(match signal {
+            2 => "interrupted",
+            6 => "aborted",
+            9 => "likely out of memory",
+            11 => "crashed",
+            15 => "terminated",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for CommandTermination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CommandTermination::Exited(code) => write!(f, "exited with status {}", code),
+            CommandTermination::Signaled {
+                signal,
+                core_dumped,
+            } => {
+                match Self::signal_name(signal) {
+                    Some(name) => write!(f, "killed by {}", name)?,
+                    None => write!(f, "killed by signal {}", signal)?,
+                }
+                if let Some(hint) = Self::signal_hint(signal) {
+                    write!(f, " ({})", hint)?;
+                }
+                if core_dumped {
+                    write!(f, ", core dumped")?;
+                }
+                Ok(())
+            }
+            CommandTermination::TimedOut => write!(f, "timed out"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationContext {
+    /// `SCHEMA_VERSION` when saved; stores migrate older data on load.
+    pub schema_version: u32,
     pub id: ConversationId,
     pub session_id: SessionId,
     pub name: String,
+    /// The name parsec gave the conversation; `name` differs once the user renamed it.
+    #[serde(default)]
+    pub auto_name: String,
     pub user_prompt: String,
+    pub created_at: DateTime<Utc>,
     pub workflow: Option<WorkflowPlan>,
     pub steps: Vec<WorkflowStepState>,
     pub status: ConversationStatus,
     pub history: Vec<ConversationEvent>,
     pub model_provider: ModelProviderId,
     pub context_summary: ContextSummary,
+    /// The user forced prompt handling with a `?` or `ai:` prefix.
+    #[serde(default)]
+    pub classification_overridden: bool,
+    /// The user edited, reordered or regenerated the plan before running it.
+    #[serde(default)]
+    pub plan_edited: bool,
+    /// The conversation this one was forked from.
+    #[serde(default)]
+    pub forked_from: Option<ConversationId>,
+    /// The conversation this one follows up on; see `follow_up`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_conversation: Option<ConversationId>,
+    /// Name of the saved task this conversation runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task: Option<String>,
+    /// Normalized labels, in the order they were added; see `tags::normalize`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// What the last check before planning or running found; see `preflight`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preflight: Vec<preflight::PreflightFinding>,
+    /// Bumped by every save; a save expecting an older revision is rejected as a conflict.
+    #[serde(default)]
+    pub revision: u64,
+    /// How the most recent run of the workflow ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<WorkflowOutcome>,
+    /// What the user wrote about the conversation, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<ConversationNote>,
+    /// Kept by retention pruning, listed first and weighed higher as context for later
+    /// conversations.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Planned without the session's context, so its commands are generated without it too.
+    #[serde(default)]
+    pub context_free: bool,
+    /// The model's markdown answer to a question handled without a workflow; such a
+    /// conversation has no steps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    /// Output and files the prompt referred to; see `bindings`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<bindings::Attachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationNote {
+    pub text: String,
+    pub added_at: DateTime<Utc>,
+}
+
+/// How a run of a conversation's workflow ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowStatus {
+    /// No steps are left pending.
+    Completed,
+    Aborted,
+    /// Steps are still pending, because the run was told to stop or they wait on a step
+    /// running in the background; run it again to go on.
+    Stopped,
+}
+
+/// Where one step of the plan stood when a run ended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepOutcome {
+    pub step_index: usize,
+    pub description: String,
+    pub status: StepStatus,
+    /// The run changed the step's status, rather than an earlier one.
+    pub this_run: bool,
+}
+
+/// Digest of one run of a conversation's workflow. Counts, commands, artifacts,
+/// environment changes and tokens cover only the run; `steps` covers the whole plan, so
+/// an aborted run still shows what was completed before the abort.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowOutcome {
+    pub status: WorkflowStatus,
+    pub steps_completed: usize,
+    pub steps_failed: usize,
+    pub steps_skipped: usize,
+    pub steps: Vec<StepOutcome>,
+    pub commands_executed: usize,
+    #[serde(with = "duration_millis")]
+    pub duration: Duration,
+    pub artifacts: Vec<ArtifactInfo>,
+    /// As recorded in the conversation summary, with secret values redacted.
+    pub environment_changes: Vec<EnvironmentChange>,
+    /// Estimated from prompt and response lengths; 0 when the provider does not count.
+    pub tokens_used: u64,
+    /// What needs the user's attention, such as failed steps and steps left pending.
+    pub follow_up: Vec<String>,
+    pub finished_at: DateTime<Utc>,
+}
+
+impl WorkflowOutcome {
+    pub fn steps_finished(&self) -> usize {
+        self.steps_completed + self.steps_failed + self.steps_skipped
+    }
+
+    /// One line for listings, such as `completed: 3 done, 1 failed in 4m12s`.
+    pub fn headline(&self) -> String {
+        let mut counts = vec![format!("{} done", self.steps_completed)];
+        if self.steps_failed > 0 {
+            counts.push(format!("{} failed", self.steps_failed));
+        }
+        if self.steps_skipped > 0 {
+            counts.push(format!("{} skipped", self.steps_skipped));
+        }
+        let status = match self.status {
+            WorkflowStatus::Completed => "completed",
+            WorkflowStatus::Aborted => "aborted",
+            WorkflowStatus::Stopped => "stopped",
+        };
+        format!(
+            "{}: {} in {}",
+            status,
+            counts.join(", "),
+            format_duration(self.duration)
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,6 +548,9 @@ pub struct ContextSummary {
     pub generated_artifacts: Vec<ArtifactInfo>,
     pub environment_changes: Vec<EnvironmentChange>,
     pub learned_preferences: HashMap<String, String>,
+    /// Steps whose raw history `key_achievements` stands in for in prompts.
+    #[serde(default)]
+    pub summarized_steps: Vec<StepId>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,6 +559,16 @@ pub struct ArtifactInfo {
     pub artifact_type: String,
     pub created_at: DateTime<Utc>,
     pub size_bytes: u64,
+    #[serde(default)]
+    pub change: ArtifactChange,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactChange {
+    #[default]
+    Created,
+    Modified,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -136,6 +588,44 @@ pub struct WorkflowPlan {
 pub struct WorkflowStep {
     pub id: StepId,
     pub description: String,
+    /// Steps that must be Complete before this one runs; without it, the step runs once
+    /// no step before it is pending.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<StepId>>,
+    /// Rough run time, for the plan and the ETA; advisory, see `estimate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_minutes: Option<f32>,
+    #[serde(default, skip_serializing_if = "EstimateSource::is_planner")]
+    pub estimate_source: EstimateSource,
+}
+
+/// Where a step's `estimated_minutes` came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EstimateSource {
+    /// The planner's guess.
+    #[default]
+    Planner,
+    /// The median run time of earlier steps like it.
+    History,
+}
+
+impl EstimateSource {
+    pub fn is_planner(&self) -> bool {
+        *self == EstimateSource::Planner
+    }
+}
+
+/// How long a completed step's commands ran, kept by the session so later steps like it
+/// can be estimated from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepTiming {
+    pub step_id: StepId,
+    /// The step's description, `estimate::normalized`.
+    pub description: String,
+    #[serde(with = "duration_millis")]
+    pub duration: Duration,
+    pub completed_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -145,11 +635,17 @@ pub struct WorkflowStepState {
     pub command_attempts: Vec<CommandAttempt>,
     pub context_used: StepContext,
     pub artifacts_produced: Vec<ArtifactInfo>,
+    /// Guidance the user gave when rejecting commands, oldest first.
+    #[serde(default)]
+    pub user_hints: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StepContext {
     pub working_directory: PathBuf,
+    /// Variables that differ from the session's `environment_snapshot`; the snapshot
+    /// itself is not copied into each step.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub environment_vars: HashMap<String, String>,
     pub previous_outputs: Vec<String>,
     pub error_context: Option<String>,
@@ -165,6 +661,33 @@ pub struct CommandAttempt {
     pub stderr: TruncatedText,
     pub error: Option<ExecutionError>,
     pub timestamp: DateTime<Utc>,
+    /// Zero, with no `completed_at`, for attempts that never ran.
+    #[serde(default, with = "duration_millis")]
+    pub duration: Duration,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// How the process ended; `None` for attempts that never ran and older records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination: Option<CommandTermination>,
+    /// The timeout the command ran under; `None` for attempts that never ran.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_duration_millis"
+    )]
+    pub timeout: Option<Duration>,
+    /// Ran attached to the user's terminal, so `stdout` is a transcript of what it showed
+    /// and there was no timeout.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub attached: bool,
+    /// Variables the command exported, with their real values; the conversation keeps
+    /// its own, redacted copy.
+    #[serde(skip)]
+    pub environment_changes: Vec<EnvironmentChange>,
+    /// The sandbox backend that confined the command, such as `bubblewrap`; `None` when it
+    /// ran unconfined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -172,12 +695,177 @@ pub struct GeneratedCommand {
     pub command: String,
     pub explanation: String,
     pub risk_score: Option<f32>,
+    /// How long the model expects the command to run, which picks its timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_duration: Option<ExpectedDuration>,
+    /// Programs the command runs that are not installed, from `tools::missing_programs`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "CommandKind::is_single")]
+    pub kind: CommandKind,
+    /// Text the command reads on standard input; without it, reads see end of file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// The command prompts the user, so approving it offers to run it interactively.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interactive: bool,
+    /// The command that reverses this one, proposed with it; `None` for read-only
+    /// commands. Only ever run after its own approval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undo_command: Option<String>,
+}
+
+impl GeneratedCommand {
+    /// `undo_command` as a command of its own, to validate and offer for approval.
+    pub fn undo(&self) -> Option<GeneratedCommand> {
+        let command = self.undo_command.as_ref()?;
+        Some(GeneratedCommand {
+            command: command.clone(),
+            explanation: format!("Undoes `{}`", self.command),
+            risk_score: None,
+            expected_duration: None,
+            missing_tools: Vec::new(),
+            kind: CommandKind::of(command),
+            stdin: None,
+            interactive: false,
+            undo_command: None,
+        })
+    }
+}
+
+/// Whether a generated command is one command line or a script of several, which runs
+/// from a file under the executor's shell and stops at the first failing line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandKind {
+    #[default]
+    Single,
+    Script,
+}
+
+impl CommandKind {
+    /// `Script` for text of more than one line.
+    pub fn of(command: &str) -> Self {
+        if command.trim().contains('\n') {
+            CommandKind::Script
+        } else {
+            CommandKind::Single
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        *self == CommandKind::Single
+    }
+}
+
+/// Rough run time of a generated command, so `ls` and `cargo build --release` do not
+/// share one timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectedDuration {
+    Quick,
+    Moderate,
+    Long,
+}
+
+impl ExpectedDuration {
+    pub fn timeout(self) -> Duration {
+        match self {
+            ExpectedDuration::Quick => Duration::from_secs(60),
+            ExpectedDuration::Moderate => Duration::from_secs(15 * 60),
+            ExpectedDuration::Long => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Limits for one command run, taking precedence over the executor's defaults and the
+/// command's `expected_duration`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionOverrides {
+    pub timeout: Option<Duration>,
+    pub max_output_size: Option<usize>,
+    /// Run the command even though `Warn` validation rules matched it, as the user
+    /// confirmed.
+    pub override_warnings: bool,
+    /// Run a command that needs a terminal attached to the user's, as the user approved.
+    pub attach_terminal: bool,
+    /// Variables earlier steps exported, set on top of parsec's own environment.
+    pub env: BTreeMap<String, String>,
+    /// Run the command outside the executor's sandbox, as the user approved.
+    pub unsandboxed: bool,
+}
+
+/// Where a running command's standard input comes from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ExecutionInput {
+    /// Nothing: reads see end of file at once.
+    #[default]
+    Closed,
+    Text(String),
+    /// The user's terminal, through a pseudo-terminal of the command's own where there is
+    /// one: what the user types, Ctrl-C included, goes to the command, and its output is
+    /// shown and kept as a transcript rather than captured.
+    Terminal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneratedCommands {
     pub commands: Vec<GeneratedCommand>,
+    /// The step needs nothing more. Commands given along with it are an optional finishing
+    /// touch: they are offered, and the step completes whether or not they run.
     pub done: bool,
+    /// Set when the commands were merged from several candidate responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidates: Option<CandidateCount>,
+}
+
+/// Candidate responses asked of the model for one set of commands, and how many it gave.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CandidateCount {
+    pub requested: u32,
+    pub received: u32,
+}
+
+/// How to undo one step, of an aborted workflow or on request. Offered for approval, never
+/// run automatically.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollbackSuggestion {
+    pub step_index: usize,
+    pub step_description: String,
+    pub action: RollbackAction,
+    /// What the step produced, for the user to check or remove by hand.
+    pub artifacts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RollbackAction {
+    Command(GeneratedCommand),
+    /// No safe automatic undo; `reason` says why.
+    ManualCleanup {
+        reason: String,
+    },
+}
+
+impl RollbackSuggestion {
+    pub fn manual_cleanup(
+        step_index: usize,
+        state: &WorkflowStepState,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            step_index,
+            step_description: state.step.description.clone(),
+            action: RollbackAction::ManualCleanup {
+                reason: reason.into(),
+            },
+            artifacts: state
+                .artifacts_produced
+                .iter()
+                .map(|artifact| artifact.file_path.clone())
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -194,20 +882,113 @@ pub struct ConversationEvent {
     pub data: serde_json::Value,
 }
 
+/// Machine-readable records printed one per line in `--output json` mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputRecord {
+    Classification {
+        input: String,
+        kind: InputKind,
+        confidence: f32,
+        reasoning: Option<String>,
+        overridden: bool,
+    },
+    Plan {
+        conversation_id: ConversationId,
+        name: String,
+        steps: Vec<WorkflowStep>,
+    },
+    Commands {
+        conversation_id: ConversationId,
+        step_index: usize,
+        step_id: StepId,
+        commands: Vec<GeneratedCommand>,
+        done: bool,
+    },
+    Execution {
+        conversation_id: Option<ConversationId>,
+        step_index: Option<usize>,
+        command: String,
+        executed: bool,
+        exit_status: Option<i32>,
+        risk_score: Option<f32>,
+        stdout: TruncatedText,
+        stderr: TruncatedText,
+    },
+    Rollback {
+        conversation_id: ConversationId,
+        suggestions: Vec<RollbackSuggestion>,
+    },
+    Summary {
+        conversation_id: ConversationId,
+        name: String,
+        status: ConversationStatus,
+        completed_steps: usize,
+        total_steps: usize,
+        #[serde(default)]
+        artifacts: Vec<ArtifactInfo>,
+    },
+    /// A plan made by `parsec plan`, with the risk each step looks to carry.
+    PlanPreview {
+        prompt: String,
+        steps: Vec<WorkflowStep>,
+        risks: Vec<step_risk::StepRisk>,
+        tokens_used: u64,
+    },
+    /// The last record of a workflow run.
+    Outcome {
+        conversation_id: ConversationId,
+        outcome: WorkflowOutcome,
+    },
+    /// The answer to a question handled without a workflow.
+    Answer {
+        conversation_id: ConversationId,
+        question: String,
+        answer: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContextItem {
     pub content: String,
+    /// Relevance to the task a prompt is for, from `context::select`.
     pub relevance_score: f32,
+    /// Decays with age; from `context::select`.
     pub recency_weight: f32,
     pub importance_level: ImportanceLevel,
     pub context_type: ContextType,
+    /// What the item was derived from, so it is recorded once.
+    pub source: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlanningOptions {
     pub max_steps: usize,
+    /// Show the planner the session: its working directory, tools and environment, its
+    /// other conversations, its context items and recent commands the user ran directly.
+    /// Without it the planner sees the prompt alone.
     pub include_context: bool,
+    /// Why the previous plan for the same prompt was rejected, for the planner to avoid.
+    #[serde(default)]
+    pub rejected_because: Option<String>,
+    /// The conversations a follow-up prompt continues.
+    #[serde(default)]
+    pub follows: Option<follow_up::Lineage>,
+    /// How long the planner may wait for the model; `None` leaves it to the provider.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_duration_millis"
+    )]
+    pub timeout: Option<Duration>,
     pub provider_specific: HashMap<String, serde_json::Value>,
+    /// Cancelled to abandon the plan, along with any model call or retry in flight.
+    #[serde(skip)]
+    pub cancel: CancellationToken,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -215,7 +996,25 @@ pub struct CommandGenOptions {
     pub max_alternatives: usize,
     pub risk_threshold: f32,
     pub include_explanations: bool,
+    /// Show the model the session: its working directory, tools and environment, its
+    /// context items and recent commands the user ran directly.
+    pub include_context: bool,
+    /// Tools earlier suggestions needed that are not installed, to be avoided.
+    pub unavailable_tools: Vec<String>,
+    /// Earlier answers for the step that gave no commands without saying it is done;
+    /// the prompt then asks for one or the other.
+    pub empty_answers: usize,
+    /// How long the generator may wait for the model; `None` leaves it to the provider.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_duration_millis"
+    )]
+    pub timeout: Option<Duration>,
     pub provider_specific: HashMap<String, serde_json::Value>,
+    /// Cancelled to abandon generation, along with any model call or retry in flight.
+    #[serde(skip)]
+    pub cancel: CancellationToken,
 }
 
 // Error types
@@ -229,6 +1028,12 @@ pub enum PlanError {
     ModelError(String),
     #[error("Context error: {0}")]
     ContextError(String),
+    #[error("Invalid step dependencies: {0}")]
+    InvalidDependencies(String),
+    #[error("Invalid plan: {0}")]
+    InvalidPlan(String),
+    #[error("Planning cancelled")]
+    Cancelled,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -241,6 +1046,8 @@ pub enum CommandGenError {
     ModelError(String),
     #[error("Context error: {0}")]
     ContextError(String),
+    #[error("Command generation cancelled")]
+    Cancelled,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
@@ -253,6 +1060,23 @@ pub enum ExecutionError {
     CommandNotFound(String),
     #[error("Timeout: {0}")]
     Timeout(String),
+    #[error("Denied by hook: {0}")]
+    Denied(String),
+    /// Stopped by validation before it ran.
+    #[error("Blocked: {}", command_rules::describe(.0))]
+    Blocked(Vec<command_rules::Finding>),
+    /// Scored above the block threshold while dangerous commands are not allowed.
+    #[error("Blocked: risk score {risk_score:.2} is above the block threshold of {threshold:.2}; allow dangerous commands to run it")]
+    HighRisk { risk_score: f32, threshold: f32 },
+    /// Would wait on a terminal it does not have; runs only attached to the user's.
+    #[error("Needs a terminal: {0}")]
+    NeedsTerminal(String),
+    /// Would use the network the sandbox takes away; runs only outside the sandbox.
+    #[error("Needs the network: {0}")]
+    NeedsNetwork(String),
+    /// What the configured sandbox runs commands with is missing.
+    #[error("Sandbox unavailable: {0}")]
+    SandboxUnavailable(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -273,6 +1097,32 @@ pub enum StoreError {
     SerializationError(#[from] serde_json::Error),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("Corrupt data recovered from backup: {0}")]
+    RecoveredFromBackup(String),
+    #[error("Corrupt data could not be recovered: {0}")]
+    Corrupted(String),
+    #[error(
+        "Conversation {conversation_id} was changed elsewhere (expected revision {expected}, found {found})"
+    )]
+    Conflict {
+        conversation_id: ConversationId,
+        expected: u64,
+        found: u64,
+    },
+    #[error(
+        "{path} was saved by a newer parsec (schema version {found}, this one reads up to {supported}); upgrade parsec"
+    )]
+    NewerSchema {
+        path: String,
+        found: u32,
+        supported: u32,
+    },
+    #[error("{0} is encrypted; open the store with its passphrase or key")]
+    Encrypted(String),
+    #[error("{0} failed authentication: it was modified or is corrupt")]
+    Tampered(String),
+    #[error("Wrong passphrase or key for the encrypted store in {0}")]
+    WrongKey(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -293,13 +1143,30 @@ pub enum InitError {
     ConfigError(String),
 }
 
+/// Why a model provider failed its health check, worded so the user knows what to fix.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum HealthError {
+    #[error("The model provider rejected its credentials: {0}; check the configured API key or credentials")]
+    InvalidCredentials(String),
+    #[error(
+        "Could not reach the model provider: {0}; check the network connection and proxy settings"
+    )]
+    Network(String),
+    #[error("The model provider is out of quota or rate limited: {0}; try again later, raise the quota or configure model.fallbacks")]
+    Quota(String),
+    #[error("The model provider failed its health check: {0}")]
+    Failed(String),
+    #[error("The {0} provider has no health check")]
+    Unsupported(String),
+}
+
 // Core traits
 pub trait CommandClassifier: Send + Sync {
     fn classify(
         &self,
         input: &str,
         context: Option<&Session>,
-    ) -> Result<InputKind, ClassificationError>;
+    ) -> Result<Classification, ClassificationError>;
 }
 
 #[async_trait]
@@ -321,24 +1188,300 @@ pub trait StepCommandGenerator: Send + Sync {
         step_index: usize,
         opts: CommandGenOptions,
     ) -> Result<GeneratedCommands, CommandGenError>;
+
+    /// Undo suggestions for every step of `ctx` that changed something, most recent first.
+    /// Providers without rollback support list each such step for manual cleanup.
+    async fn suggest_rollback(
+        &self,
+        ctx: &ConversationContext,
+        _session: &Session,
+    ) -> Result<Vec<RollbackSuggestion>, CommandGenError> {
+        Ok(ctx
+            .steps
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, state)| state.needs_rollback())
+            .map(|(index, state)| {
+                RollbackSuggestion::manual_cleanup(
+                    index,
+                    state,
+                    "This model provider cannot suggest rollback commands",
+                )
+            })
+            .collect())
+    }
+}
+
+/// Condenses completed workflow steps into short statements that replace their raw
+/// history in later prompts.
+#[async_trait]
+pub trait Summarizer: Send + Sync {
+    /// Key outcomes of `ctx.steps[steps]`, one statement per line, oldest first.
+    async fn summarize(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+        steps: Range<usize>,
+    ) -> Result<Vec<String>, CommandGenError>;
+}
+
+/// Summarizes each step as its last command and exit status with the first and last
+/// line of its output; used when no model is available or the model call fails.
+pub struct HeuristicSummarizer;
+
+impl HeuristicSummarizer {
+    pub fn summarize_steps(ctx: &ConversationContext, steps: Range<usize>) -> Vec<String> {
+        ctx.steps
+            .get(steps)
+            .unwrap_or_default()
+            .iter()
+            .map(|state| {
+                let attempt = state
+                    .command_attempts
+                    .iter()
+                    .rev()
+                    .find(|attempt| attempt.executed);
+                let Some(attempt) = attempt else {
+                    return format!(
+                        "{} ({:?}, no command run)",
+                        state.step.description, state.status
+                    );
+                };
+                let mut lines = attempt
+                    .stdout
+                    .content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty());
+                let output = match (lines.next(), lines.next_back()) {
+                    (Some(first), Some(last)) => format!(": {} … {}", first, last),
+                    (Some(only), None) => format!(": {}", only),
+                    _ => String::new(),
+                };
+                format!(
+                    "{} (`{}`, exit {}){}",
+                    state.step.description,
+                    attempt.candidate.command,
+                    attempt.exit_status.unwrap_or(-1),
+                    output
+                )
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Summarizer for HeuristicSummarizer {
+    async fn summarize(
+        &self,
+        ctx: &ConversationContext,
+        _session: &Session,
+        steps: Range<usize>,
+    ) -> Result<Vec<String>, CommandGenError> {
+        Ok(Self::summarize_steps(ctx, steps))
+    }
+}
+
+/// Answers questions that need no workflow, such as what an error means, in one call.
+#[async_trait]
+pub trait Explainer: Send + Sync {
+    /// A markdown answer to `question`. Of `opts`, `include_context`, `timeout` and
+    /// `cancel` apply as when planning.
+    async fn explain(
+        &self,
+        question: &str,
+        session: &Session,
+        opts: PlanningOptions,
+    ) -> Result<String, PlanError>;
 }
 
+/// Titles conversations once they are planned.
+#[async_trait]
+pub trait ConversationNamer: Send + Sync {
+    /// A short imperative title for `ctx`, such as "Set up a Postgres database".
+    async fn name_conversation(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+    ) -> Result<String, CommandGenError>;
+}
+
+#[async_trait]
 pub trait ModelProvider: Send + Sync {
     fn planner(&self) -> &dyn WorkflowPlanner;
     fn step_generator(&self) -> &dyn StepCommandGenerator;
     fn name(&self) -> &'static str;
+
+    /// Providers without model-backed summaries keep first and last output lines.
+    fn summarizer(&self) -> &dyn Summarizer {
+        &HeuristicSummarizer
+    }
+
+    /// Providers without model-backed names use the prompt's first verb phrase.
+    fn namer(&self) -> &dyn ConversationNamer {
+        &naming::HeuristicNamer
+    }
+
+    /// Providers without one plan questions like any other prompt.
+    fn explainer(&self) -> Option<&dyn Explainer> {
+        None
+    }
+
+    /// Label of the fallback that served the most recent call, if not this provider itself.
+    fn fallback_used(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the most recent call was answered from a response cache.
+    fn last_call_cached(&self) -> bool {
+        false
+    }
+
+    /// Prompt and raw response of the most recent call, for debugging.
+    fn last_exchange(&self) -> Option<ModelExchange> {
+        None
+    }
+
+    /// Tokens estimated to have gone to and come from the model so far; 0 for providers
+    /// that do not count them.
+    fn tokens_used(&self) -> u64 {
+        0
+    }
+
+    /// Make the smallest request the provider serves, to show it is reachable, has the
+    /// model and accepts its credentials.
+    async fn health_check(&self) -> Result<ProviderHealth, HealthError> {
+        Err(HealthError::Unsupported(self.name().to_string()))
+    }
+}
+
+/// A health check the model provider passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderHealth {
+    /// `ModelProvider::name`, or the label of the fallback that answered.
+    pub provider: String,
+    pub model: String,
+    pub latency: Duration,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// One prompt sent to a model and its raw response; `None` if the call failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelExchange {
+    pub prompt: String,
+    pub response: Option<String>,
 }
 
 pub trait SessionStore: Send + Sync {
     fn save_session(&self, session: &Session) -> Result<(), StoreError>;
     fn load_session(&self, session_id: &SessionId) -> Result<Session, StoreError>;
-    fn save_conversation(&self, conversation: &ConversationContext) -> Result<(), StoreError>;
+    /// Store `conversation` and bump its `revision`. With `expected_revision`, the save
+    /// fails with `StoreError::Conflict` unless the stored copy (0 if none) is at that revision.
+    fn save_conversation(
+        &self,
+        conversation: &mut ConversationContext,
+        expected_revision: Option<u64>,
+    ) -> Result<(), StoreError>;
     fn load_conversation(
         &self,
         conversation_id: &ConversationId,
     ) -> Result<ConversationContext, StoreError>;
     fn list_active_sessions(&self) -> Result<Vec<SessionSummary>, StoreError>;
-    fn prune_old_context(&self, retention_policy: &RetentionPolicy) -> Result<(), StoreError>;
+    /// Remove sessions and conversations past `retention_policy`. Pinned conversations
+    /// are kept, and so are the sessions they belong to.
+    fn prune_old_context(
+        &self,
+        retention_policy: &RetentionPolicy,
+        active_session: Option<&SessionId>,
+    ) -> Result<PruneReport, StoreError>;
+    /// Direct commands, executed workflow commands, step descriptions and prompts
+    /// matching every term of `query`, best first.
+    fn search(
+        &self,
+        query: &str,
+        scope: &search::SearchScope,
+    ) -> Result<Vec<search::SearchHit>, StoreError>;
+    /// Conversations of active sessions that match `filter`, pinned ones first and
+    /// otherwise most recent first.
+    fn find_conversations(
+        &self,
+        filter: &ConversationFilter,
+    ) -> Result<Vec<ConversationContext>, StoreError>;
+    /// Active sessions whose working directory is `dir` or one of its ancestors, the
+    /// closest directory first and, within one directory, the most recently active first.
+    fn find_sessions_by_directory(&self, dir: &Path) -> Result<Vec<SessionSummary>, StoreError>;
+    /// Active sessions and conversations counted, with the bytes they take as stored.
+    fn stats(&self) -> Result<StoreStats, StoreError>;
+
+    /// `find_conversations` reduced to what listing them shows, for callers that load a
+    /// conversation in full only once one is picked.
+    fn list_conversations(
+        &self,
+        filter: &ConversationFilter,
+    ) -> Result<Vec<ConversationSummary>, StoreError> {
+        Ok(self
+            .find_conversations(filter)?
+            .iter()
+            .map(ConversationSummary::of)
+            .collect())
+    }
+}
+
+/// What `SessionStore::find_conversations` returns.
+#[derive(Debug, Clone, Default)]
+pub struct ConversationFilter {
+    /// Only the conversations of this session.
+    pub session_id: Option<SessionId>,
+    /// Only conversations with every one of these tags.
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: ConversationId,
+    pub session_id: SessionId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub status: ConversationStatus,
+    pub task: Option<String>,
+    pub tags: Vec<String>,
+    pub steps_completed: usize,
+    pub steps_total: usize,
+    /// `WorkflowOutcome::headline` of the most recent run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl ConversationSummary {
+    pub fn of(conversation: &ConversationContext) -> Self {
+        Self {
+            id: conversation.id.clone(),
+            session_id: conversation.session_id.clone(),
+            name: conversation.name.clone(),
+            created_at: conversation.created_at,
+            status: conversation.status.clone(),
+            task: conversation.task.clone(),
+            tags: conversation.tags.clone(),
+            steps_completed: conversation
+                .steps
+                .iter()
+                .filter(|step| step.status == StepStatus::Complete)
+                .count(),
+            steps_total: conversation.steps.len(),
+            outcome: conversation.outcome.as_ref().map(WorkflowOutcome::headline),
+            notes: conversation
+                .notes
+                .iter()
+                .map(|note| note.text.clone())
+                .collect(),
+            pinned: conversation.pinned,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -357,6 +1500,42 @@ pub struct RetentionPolicy {
     pub max_sessions: Option<usize>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneReport {
+    pub sessions_removed: usize,
+    pub conversations_removed: usize,
+    pub bytes_reclaimed: u64,
+}
+
+/// How much a store holds; see `SessionStore::stats`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoreStats {
+    pub sessions: usize,
+    pub conversations: usize,
+    pub total_bytes: u64,
+    /// The conversation taking the most bytes, and how many.
+    pub largest_conversation: Option<(ConversationId, u64)>,
+}
+
+/// How much history a session and its conversations keep. Stores apply it on save.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionPolicy {
+    /// Direct commands kept at all; the oldest are dropped first.
+    pub max_commands: usize,
+    /// Most recent direct commands that keep their captured output.
+    pub full_output_commands: usize,
+    /// Events kept in each conversation's history.
+    pub max_conversation_events: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompactionReport {
+    pub commands_removed: usize,
+    pub outputs_dropped: usize,
+    pub events_removed: usize,
+    pub bytes_saved: u64,
+}
+
 pub trait ContextStore: Send + Sync {
     fn save_session(&self, session: &Session) -> Result<(), ContextError>;
     fn load_session(&self, session_id: &SessionId) -> Result<Session, ContextError>;
@@ -365,7 +1544,11 @@ pub trait ContextStore: Send + Sync {
         &self,
         conversation_id: &ConversationId,
     ) -> Result<ConversationContext, ContextError>;
-    fn prune_old_context(&self, retention_policy: &RetentionPolicy) -> Result<(), ContextError>;
+    fn prune_old_context(
+        &self,
+        retention_policy: &RetentionPolicy,
+        active_session: Option<&SessionId>,
+    ) -> Result<PruneReport, ContextError>;
 }
 
 impl Default for SessionSettings {
@@ -376,7 +1559,227 @@ impl Default for SessionSettings {
             enable_cross_conversation_learning: true,
             context_compression_threshold: 0.8,
             privacy_mode: false,
+            tracked_tools: tools::default_tracked_tools(),
+            max_attempts_per_step: default_max_attempts_per_step(),
+            history_limit: default_history_limit(),
+            history_full_output: default_history_full_output(),
+            rate_limits: RateLimits::default(),
+            conversation_retention_days: default_conversation_retention_days(),
+            max_sessions: None,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    pub fn from_settings(settings: &SessionSettings) -> Self {
+        Self {
+            max_commands: settings.history_limit,
+            full_output_commands: settings.history_full_output,
+            max_conversation_events: settings.max_conversation_history,
+        }
+    }
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self::from_settings(&SessionSettings::default())
+    }
+}
+
+impl CompactionReport {
+    pub fn is_empty(&self) -> bool {
+        self.commands_removed == 0 && self.outputs_dropped == 0 && self.events_removed == 0
+    }
+
+    pub fn add(&mut self, other: CompactionReport) {
+        self.commands_removed += other.commands_removed;
+        self.outputs_dropped += other.outputs_dropped;
+        self.events_removed += other.events_removed;
+        self.bytes_saved += other.bytes_saved;
+    }
+}
+
+fn serialized_len<T: Serialize>(value: &T) -> u64 {
+    serde_json::to_vec(value)
+        .map(|bytes| bytes.len() as u64)
+        .unwrap_or(0)
+}
+
+impl Session {
+    /// Apply this session's own `settings` limits to `command_history`: drop the oldest
+    /// commands past `history_limit` and the captured output of all but the most recent
+    /// `history_full_output`.
+    pub fn compact(&mut self) -> CompactionReport {
+        let policy = CompactionPolicy::from_settings(&self.settings);
+        let mut report = CompactionReport::default();
+
+        let excess = self
+            .command_history
+            .len()
+            .saturating_sub(policy.max_commands);
+        for execution in self.command_history.drain(..excess) {
+            report.bytes_saved += serialized_len(&execution);
+            report.commands_removed += 1;
+        }
+
+        let keep_output = self
+            .command_history
+            .len()
+            .saturating_sub(policy.full_output_commands);
+        for execution in &mut self.command_history[..keep_output] {
+            if execution.has_output() {
+                let before = serialized_len(execution);
+                execution.drop_output();
+                report.bytes_saved += before.saturating_sub(serialized_len(execution));
+                report.outputs_dropped += 1;
+            }
+        }
+
+        report
+    }
+}
+
+impl CommandAttempt {
+    /// The exit status, followed by how the command ended when it did not exit on its
+    /// own: `-1, killed by SIGKILL (likely out of memory)`.
+    pub fn exit_description(&self) -> String {
+        let status = self.exit_status.unwrap_or(-1);
+        match self.termination {
+            Some(
+                termination @ (CommandTermination::Signaled { .. } | CommandTermination::TimedOut),
+            ) => {
+                format!("{}, {}", status, termination)
+            }
+            _ => status.to_string(),
+        }
+    }
+}
+
+impl DirectCommandExecution {
+    fn has_output(&self) -> bool {
+        !self.stdout.content.is_empty() || !self.stderr.content.is_empty()
+    }
+
+    /// Reduce to a compact record: the output is emptied but still reported as truncated
+    /// from its original length.
+    fn drop_output(&mut self) {
+        for text in [&mut self.stdout, &mut self.stderr] {
+            text.content.clear();
+            text.truncated = text.original_length > 0;
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn from_settings(settings: &SessionSettings) -> Self {
+        Self {
+            session_retention_days: settings.session_retention_days,
+            conversation_retention_days: settings.conversation_retention_days,
+            max_sessions: settings.max_sessions,
+        }
+    }
+}
+
+impl WorkflowStepState {
+    /// Time spent running this step's commands, over all attempts.
+    pub fn command_time(&self) -> Duration {
+        self.command_attempts
+            .iter()
+            .map(|attempt| attempt.duration)
+            .sum()
+    }
+
+    /// Whether the step ran a command or produced an artifact that an abort may need to undo.
+    pub fn needs_rollback(&self) -> bool {
+        !self.artifacts_produced.is_empty()
+            || self.command_attempts.iter().any(|attempt| attempt.executed)
+    }
+
+    /// The recorded undo commands of the attempts that ran and succeeded, most recent
+    /// first, as one command; a script when there are several. `None` when none of them
+    /// recorded one.
+    pub fn recorded_undo(&self) -> Option<GeneratedCommand> {
+        let undos: Vec<GeneratedCommand> = self
+            .command_attempts
+            .iter()
+            .rev()
+            .filter(|attempt| attempt.executed && attempt.error.is_none())
+            .filter_map(|attempt| attempt.candidate.undo())
+            .collect();
+        match undos.as_slice() {
+            [] => None,
+            [undo] => Some(undo.clone()),
+            [first, ..] => {
+                let command = undos
+                    .iter()
+                    .map(|undo| undo.command.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Some(GeneratedCommand {
+                    kind: CommandKind::of(&command),
+                    explanation: format!(
+                        "Undoes the step's {} commands, most recent first",
+                        undos.len()
+                    ),
+                    command,
+                    ..first.clone()
+                })
+            }
+        }
+    }
+
+    /// Attempts that ran and failed, and attempts blocked before running (high risk).
+    pub fn failure_counts(&self) -> (usize, usize) {
+        self.command_attempts
+            .iter()
+            .filter(|attempt| attempt.error.is_some())
+            .fold((0, 0), |(failed, blocked), attempt| {
+                if attempt.executed {
+                    (failed + 1, blocked)
+                } else {
+                    (failed, blocked + 1)
+                }
+            })
+    }
+}
+
+impl ConversationContext {
+    /// Time spent running commands across every step.
+    pub fn command_time(&self) -> Duration {
+        self.steps.iter().map(WorkflowStepState::command_time).sum()
+    }
+
+    /// Drop the oldest history events past `policy.max_conversation_events`.
+    pub fn compact(&mut self, policy: &CompactionPolicy) -> CompactionReport {
+        let mut report = CompactionReport::default();
+        let excess = self
+            .history
+            .len()
+            .saturating_sub(policy.max_conversation_events);
+        for event in self.history.drain(..excess) {
+            report.bytes_saved += serialized_len(&event);
+            report.events_removed += 1;
         }
+        report
+    }
+
+    /// The prompt as the model is sent it: with its attachments.
+    pub fn model_prompt(&self) -> String {
+        bindings::render(&self.user_prompt, &self.attachments)
+    }
+
+    /// Whether the user renamed the conversation, so parsec keeps its name.
+    pub fn is_renamed(&self) -> bool {
+        !self.auto_name.is_empty() && self.name != self.auto_name
+    }
+
+    /// Timestamp of the most recent activity: the last history event, or creation time.
+    pub fn last_activity(&self) -> DateTime<Utc> {
+        self.history
+            .iter()
+            .map(|event| event.timestamp)
+            .max()
+            .map_or(self.created_at, |latest| latest.max(self.created_at))
     }
 }
 
@@ -385,7 +1788,11 @@ impl Default for PlanningOptions {
         Self {
             max_steps: 12,
             include_context: true,
+            rejected_because: None,
+            follows: None,
+            timeout: None,
             provider_specific: HashMap::new(),
+            cancel: CancellationToken::new(),
         }
     }
 }
@@ -396,9 +1803,63 @@ impl Default for CommandGenOptions {
             max_alternatives: 3,
             risk_threshold: 0.7,
             include_explanations: true,
+            include_context: true,
+            unavailable_tools: Vec::new(),
+            empty_answers: 0,
+            timeout: None,
             provider_specific: HashMap::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+}
+
+/// Serialize a `Duration` as whole milliseconds.
+mod duration_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+mod optional_duration_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&(duration.as_millis() as u64)),
+            None => serializer.serialize_none(),
         }
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<u64>::deserialize(deserializer).map(|millis| millis.map(Duration::from_millis))
+    }
+}
+
+/// Compact human form: `850ms`, `3.2s`, `4m12s`, `1h05m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
 }
 
 impl TruncatedText {
@@ -419,4 +1880,348 @@ impl TruncatedText {
             }
         }
     }
+
+    /// Keep the first `head` and last `tail` bytes of oversized `content`, cut at
+    /// character boundaries, with a marker saying how much was left out in between.
+    /// Command output usually ends with what matters: the error, the summary line.
+    pub fn new_head_tail(content: String, head: usize, tail: usize) -> Self {
+        let original_length = content.len();
+        if original_length <= head.saturating_add(tail) {
+            return Self {
+                content,
+                truncated: false,
+                original_length,
+            };
+        }
+        let mut head_end = head;
+        while !content.is_char_boundary(head_end) {
+            head_end -= 1;
+        }
+        let mut tail_start = original_length - tail;
+        while !content.is_char_boundary(tail_start) {
+            tail_start += 1;
+        }
+        Self::from_head_tail(
+            &content[..head_end],
+            tail_start - head_end,
+            &content[tail_start..],
+        )
+    }
+
+    /// Text already cut down to its `head` and `tail`, with `omitted` bytes left out in
+    /// between, as `new_head_tail` marks it.
+    pub fn from_head_tail(head: &str, omitted: usize, tail: &str) -> Self {
+        if omitted == 0 {
+            return Self {
+                content: format!("{}{}", head, tail),
+                truncated: false,
+                original_length: head.len() + tail.len(),
+            };
+        }
+        Self {
+            content: format!(
+                "{}\n... [{} bytes omitted] ...\n{}",
+                head,
+                group_thousands(omitted),
+                tail
+            ),
+            truncated: true,
+            original_length: head.len() + omitted + tail.len(),
+        }
+    }
+
+    /// Whether `text` survived truncation.
+    pub fn contains(&self, text: &str) -> bool {
+        self.content.contains(text)
+    }
+}
+
+/// `12340` as `12,340`.
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn at() -> DateTime<Utc> {
+        "2024-05-01T12:00:00Z".parse().unwrap()
+    }
+
+    fn text(content: &str) -> TruncatedText {
+        TruncatedText::new(content.to_string(), 100)
+    }
+
+    fn step() -> WorkflowStep {
+        WorkflowStep {
+            id: "step-1".to_string(),
+            description: "Build the project".to_string(),
+            depends_on: None,
+            estimated_minutes: Some(2.0),
+            estimate_source: EstimateSource::Planner,
+        }
+    }
+
+    fn command() -> GeneratedCommand {
+        GeneratedCommand {
+            command: "cargo build".to_string(),
+            explanation: "Builds it".to_string(),
+            risk_score: Some(0.25),
+            expected_duration: None,
+            missing_tools: Vec::new(),
+            kind: CommandKind::Single,
+            stdin: None,
+            interactive: false,
+            undo_command: None,
+        }
+    }
+
+    /// One record of each kind, with the JSON line it is printed as.
+    fn records() -> Vec<(OutputRecord, Value)> {
+        let step_json = json!({
+            "id": "step-1",
+            "description": "Build the project",
+            "estimated_minutes": 2.0,
+        });
+        let command_json = json!({
+            "command": "cargo build",
+            "explanation": "Builds it",
+            "risk_score": 0.25,
+        });
+        let artifact = ArtifactInfo {
+            file_path: PathBuf::from("target/app"),
+            artifact_type: "binary".to_string(),
+            created_at: at(),
+            size_bytes: 42,
+            change: ArtifactChange::Created,
+        };
+        let artifact_json = json!({
+            "file_path": "target/app",
+            "artifact_type": "binary",
+            "created_at": "2024-05-01T12:00:00Z",
+            "size_bytes": 42,
+            "change": "created",
+        });
+        vec![
+            (
+                OutputRecord::Classification {
+                    input: "ls -la".to_string(),
+                    kind: InputKind::Shell,
+                    confidence: 0.75,
+                    reasoning: None,
+                    overridden: false,
+                },
+                json!({
+                    "type": "classification",
+                    "input": "ls -la",
+                    "kind": "Shell",
+                    "confidence": 0.75,
+                    "reasoning": null,
+                    "overridden": false,
+                }),
+            ),
+            (
+                OutputRecord::Plan {
+                    conversation_id: "conv-1".to_string(),
+                    name: "Build".to_string(),
+                    steps: vec![step()],
+                },
+                json!({
+                    "type": "plan",
+                    "conversation_id": "conv-1",
+                    "name": "Build",
+                    "steps": [step_json],
+                }),
+            ),
+            (
+                OutputRecord::Commands {
+                    conversation_id: "conv-1".to_string(),
+                    step_index: 0,
+                    step_id: "step-1".to_string(),
+                    commands: vec![command()],
+                    done: false,
+                },
+                json!({
+                    "type": "commands",
+                    "conversation_id": "conv-1",
+                    "step_index": 0,
+                    "step_id": "step-1",
+                    "commands": [command_json],
+                    "done": false,
+                }),
+            ),
+            (
+                OutputRecord::Execution {
+                    conversation_id: None,
+                    step_index: None,
+                    command: "echo hello".to_string(),
+                    executed: true,
+                    exit_status: Some(0),
+                    risk_score: None,
+                    stdout: text("hello\n"),
+                    stderr: text(""),
+                },
+                json!({
+                    "type": "execution",
+                    "conversation_id": null,
+                    "step_index": null,
+                    "command": "echo hello",
+                    "executed": true,
+                    "exit_status": 0,
+                    "risk_score": null,
+                    "stdout": { "content": "hello\n", "truncated": false, "original_length": 6 },
+                    "stderr": { "content": "", "truncated": false, "original_length": 0 },
+                }),
+            ),
+            (
+                OutputRecord::Rollback {
+                    conversation_id: "conv-1".to_string(),
+                    suggestions: vec![RollbackSuggestion {
+                        step_index: 0,
+                        step_description: "Build the project".to_string(),
+                        action: RollbackAction::ManualCleanup {
+                            reason: "Nothing to undo".to_string(),
+                        },
+                        artifacts: vec![PathBuf::from("target/app")],
+                    }],
+                },
+                json!({
+                    "type": "rollback",
+                    "conversation_id": "conv-1",
+                    "suggestions": [{
+                        "step_index": 0,
+                        "step_description": "Build the project",
+                        "action": { "kind": "manual_cleanup", "reason": "Nothing to undo" },
+                        "artifacts": ["target/app"],
+                    }],
+                }),
+            ),
+            (
+                OutputRecord::Summary {
+                    conversation_id: "conv-1".to_string(),
+                    name: "Build".to_string(),
+                    status: ConversationStatus::Finished,
+                    completed_steps: 1,
+                    total_steps: 1,
+                    artifacts: vec![artifact.clone()],
+                },
+                json!({
+                    "type": "summary",
+                    "conversation_id": "conv-1",
+                    "name": "Build",
+                    "status": "Finished",
+                    "completed_steps": 1,
+                    "total_steps": 1,
+                    "artifacts": [artifact_json.clone()],
+                }),
+            ),
+            (
+                OutputRecord::PlanPreview {
+                    prompt: "build it".to_string(),
+                    steps: vec![step()],
+                    risks: vec![step_risk::StepRisk::ChangesFiles],
+                    tokens_used: 120,
+                },
+                json!({
+                    "type": "plan_preview",
+                    "prompt": "build it",
+                    "steps": [step_json],
+                    "risks": ["changes_files"],
+                    "tokens_used": 120,
+                }),
+            ),
+            (
+                OutputRecord::Outcome {
+                    conversation_id: "conv-1".to_string(),
+                    outcome: WorkflowOutcome {
+                        status: WorkflowStatus::Completed,
+                        steps_completed: 1,
+                        steps_failed: 0,
+                        steps_skipped: 0,
+                        steps: vec![StepOutcome {
+                            step_index: 0,
+                            description: "Build the project".to_string(),
+                            status: StepStatus::Complete,
+                            this_run: true,
+                        }],
+                        commands_executed: 1,
+                        duration: Duration::from_millis(1500),
+                        artifacts: vec![artifact],
+                        environment_changes: Vec::new(),
+                        tokens_used: 300,
+                        follow_up: Vec::new(),
+                        finished_at: at(),
+                    },
+                },
+                json!({
+                    "type": "outcome",
+                    "conversation_id": "conv-1",
+                    "outcome": {
+                        "status": "completed",
+                        "steps_completed": 1,
+                        "steps_failed": 0,
+                        "steps_skipped": 0,
+                        "steps": [{
+                            "step_index": 0,
+                            "description": "Build the project",
+                            "status": "Complete",
+                            "this_run": true,
+                        }],
+                        "commands_executed": 1,
+                        "duration": 1500,
+                        "artifacts": [artifact_json],
+                        "environment_changes": [],
+                        "tokens_used": 300,
+                        "follow_up": [],
+                        "finished_at": "2024-05-01T12:00:00Z",
+                    },
+                }),
+            ),
+            (
+                OutputRecord::Answer {
+                    conversation_id: "conv-2".to_string(),
+                    question: "what is cargo?".to_string(),
+                    answer: "Rust's build tool".to_string(),
+                },
+                json!({
+                    "type": "answer",
+                    "conversation_id": "conv-2",
+                    "question": "what is cargo?",
+                    "answer": "Rust's build tool",
+                }),
+            ),
+            (
+                OutputRecord::Error {
+                    message: "No such conversation".to_string(),
+                },
+                json!({ "type": "error", "message": "No such conversation" }),
+            ),
+        ]
+    }
+
+    #[test]
+    fn output_records_have_a_stable_json_shape() {
+        for (record, expected) in records() {
+            assert_eq!(serde_json::to_value(&record).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn output_records_round_trip_through_serde() {
+        for (record, expected) in records() {
+            let line = serde_json::to_string(&record).unwrap();
+            let parsed: OutputRecord = serde_json::from_str(&line).unwrap();
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), expected);
+        }
+    }
 }
@@ -0,0 +1,29 @@
+//! What log lines and tracing spans may show of user content: inputs, prompts and
+//! commands. Privacy mode is a session setting, but one parsec process runs one session
+//! at a time, so the frontend sets it here once the session is known.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PRIVATE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_private(private: bool) {
+    PRIVATE.store(private, Ordering::Relaxed);
+}
+
+pub fn is_private() -> bool {
+    PRIVATE.load(Ordering::Relaxed)
+}
+
+/// `text` as logs may show it: in privacy mode only its length and hash.
+pub fn loggable(text: &str) -> String {
+    if is_private() {
+        format!("<{} chars, {}>", text.chars().count(), content_hash(text))
+    } else {
+        text.to_string()
+    }
+}
+
+/// A short stable id for `text`, to follow it through logs without showing it.
+pub fn content_hash(text: &str) -> String {
+    format!("{:016x}", crate::input_hash(text))
+}
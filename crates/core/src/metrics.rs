@@ -0,0 +1,262 @@
+//! Counts and latencies of model calls, command executions, classifications and
+//! planning. Components take an `Arc<dyn Metrics>` through `with_metrics` and default to
+//! `NoopMetrics`; `InMemoryMetrics` accumulates them for `parsec stats`, and library
+//! users can implement the trait to bridge to their own monitoring.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Model calls; labels `provider`, `model` and `outcome` (`ok`, `cached` or `error`).
+pub const MODEL_CALLS: &str = "model_calls";
+/// Estimated tokens sent and received by calls that reached the API; labels `provider`,
+/// `model` and `direction` (`prompt` or `response`).
+pub const MODEL_TOKENS: &str = "model_tokens";
+/// Latency of model calls in milliseconds; labels `provider` and `model`.
+pub const MODEL_LATENCY_MS: &str = "model_latency_ms";
+/// Commands run or refused; labels `kind` (`direct`, `step` or `background`) and
+/// `exit_class` (a `CommandTermination::exit_class`, or `error`, `denied`, `blocked` or
+/// `started`).
+pub const EXECUTIONS: &str = "executions";
+/// How long commands ran in milliseconds; label `kind`.
+pub const EXECUTION_DURATION_MS: &str = "execution_duration_ms";
+/// Classifier decisions; labels `classifier` and `kind` (`shell`, `prompt` or `error`).
+pub const CLASSIFICATIONS: &str = "classifications";
+/// Time classifiers took in milliseconds; label `classifier`.
+pub const CLASSIFICATION_LATENCY_MS: &str = "classification_latency_ms";
+/// Time to plan a workflow in milliseconds; labels `provider` and `outcome`.
+pub const PLANNING_LATENCY_MS: &str = "planning_latency_ms";
+
+/// Where metrics go. Both methods default to doing nothing, so implementations need only
+/// the kinds they keep.
+pub trait Metrics: Send + Sync {
+    /// Add `value` to the counter `name`.
+    fn increment(&self, _name: &str, _labels: &[(&str, &str)], _value: u64) {}
+
+    /// Record one observation of `value` in the histogram `name`.
+    fn observe(&self, _name: &str, _labels: &[(&str, &str)], _value: f64) {}
+}
+
+/// Drops everything; the default for every component.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+pub fn noop() -> Arc<dyn Metrics> {
+    Arc::new(NoopMetrics)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        self.merge(&Histogram {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        });
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+/// Metric values by name, then by label set as written by `label_key`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    #[serde(default)]
+    pub counters: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(default)]
+    pub histograms: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+impl MetricsSnapshot {
+    pub fn merge(&mut self, other: &MetricsSnapshot) {
+        for (name, series) in &other.counters {
+            let counters = self.counters.entry(name.clone()).or_default();
+            for (labels, value) in series {
+                *counters.entry(labels.clone()).or_default() += value;
+            }
+        }
+        for (name, series) in &other.histograms {
+            let histograms = self.histograms.entry(name.clone()).or_default();
+            for (labels, histogram) in series {
+                histograms
+                    .entry(labels.clone())
+                    .or_default()
+                    .merge(histogram);
+            }
+        }
+    }
+
+    /// The counter `name` summed over the label sets that include all of `labels`.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        self.counters
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| {
+                labels
+                    .iter()
+                    .all(|(label, value)| label_value(key, label) == *value)
+            })
+            .map(|(_, value)| value)
+            .sum()
+    }
+
+    /// The histogram `name` merged over the label sets that include all of `labels`.
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Histogram {
+        let mut total = Histogram::default();
+        for (key, histogram) in self.histograms.get(name).into_iter().flatten() {
+            if labels
+                .iter()
+                .all(|(label, value)| label_value(key, label) == *value)
+            {
+                total.merge(histogram);
+            }
+        }
+        total
+    }
+
+    /// The counter `name` summed by the value of `label`.
+    pub fn counter_by(&self, name: &str, label: &str) -> BTreeMap<String, u64> {
+        let mut totals = BTreeMap::new();
+        for (labels, value) in self.counters.get(name).into_iter().flatten() {
+            *totals.entry(label_value(labels, label)).or_default() += value;
+        }
+        totals
+    }
+
+    /// The histogram `name` merged by the value of `label`.
+    pub fn histogram_by(&self, name: &str, label: &str) -> BTreeMap<String, Histogram> {
+        let mut totals: BTreeMap<String, Histogram> = BTreeMap::new();
+        for (labels, histogram) in self.histograms.get(name).into_iter().flatten() {
+            totals
+                .entry(label_value(labels, label))
+                .or_default()
+                .merge(histogram);
+        }
+        totals
+    }
+
+    /// The snapshot saved at `path`; empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)
+    }
+}
+
+/// `labels` sorted by name as `name=value` pairs joined by commas.
+pub fn label_key(labels: &[(&str, &str)]) -> String {
+    let mut labels = labels.to_vec();
+    labels.sort();
+    labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The value of `label` in a `label_key`; empty if it is not there.
+pub fn label_value(key: &str, label: &str) -> String {
+    key.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == label)
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default()
+}
+
+/// Accumulates metrics in memory, to read with `snapshot` or add to a file with `flush`.
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    snapshot: Mutex<MetricsSnapshot>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsSnapshot> {
+        self.snapshot.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.lock().clone()
+    }
+
+    /// Add what was recorded since the last flush to the snapshot saved at `path`.
+    pub fn flush(&self, path: &Path) -> Result<(), std::io::Error> {
+        let recorded = std::mem::take(&mut *self.lock());
+        if recorded == MetricsSnapshot::default() {
+            return Ok(());
+        }
+        MetricsSnapshot::load(path)
+            .and_then(|mut saved| {
+                saved.merge(&recorded);
+                saved.save(path)
+            })
+            .inspect_err(|_| {
+                // Keep the values for the next flush
+                self.lock().merge(&recorded);
+            })
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn increment(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        *self
+            .lock()
+            .counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_key(labels))
+            .or_default() += value;
+    }
+
+    fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.lock()
+            .histograms
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_key(labels))
+            .or_default()
+            .observe(value);
+    }
+}
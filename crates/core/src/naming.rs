@@ -0,0 +1,128 @@
+//! Conversation names: short imperative titles such as "Set up a Postgres database",
+//! shown in conversation lists and given to the model. Providers may name conversations
+//! through a `ConversationNamer`; `HeuristicNamer` names them from their prompt alone.
+
+use crate::{CommandGenError, ConversationContext, ConversationNamer, Session};
+use async_trait::async_trait;
+
+/// Longest name kept, in characters.
+pub const MAX_NAME_CHARS: usize = 60;
+
+/// Words a model-suggested title may have.
+const MAX_TITLE_WORDS: usize = 6;
+
+/// Words a heuristic name keeps of the prompt's first verb phrase.
+const MAX_HEURISTIC_WORDS: usize = 5;
+
+/// Pleasantries and framing that open prompts without saying what to do.
+const FILLER_WORDS: &[&str] = &[
+    "can", "could", "would", "will", "you", "please", "pls", "kindly", "help", "me", "us", "i",
+    "i'd", "i'm", "im", "we", "want", "wanna", "need", "like", "to", "let's", "lets", "hey", "hi",
+    "hello", "ok", "okay", "so", "just", "now", "how", "do",
+];
+
+/// Words that start a new clause; the verb phrase ends before them.
+const CLAUSE_WORDS: &[&str] = &["and", "then", "but", "so", "because", "which", "that"];
+
+/// Words a name should not end on.
+const TRAILING_WORDS: &[&str] = &[
+    "a", "an", "the", "for", "to", "of", "in", "on", "at", "with", "from", "my", "our", "your",
+    "this", "that", "it", "and", "or",
+];
+
+/// Names conversations after the first verb phrase of their prompt, without the
+/// pleasantries before it; used when no model is available, the model call fails or
+/// extra model calls are turned off.
+pub struct HeuristicNamer;
+
+impl HeuristicNamer {
+    pub fn name(user_prompt: &str) -> String {
+        let first_sentence = user_prompt
+            .split(['.', '?', '!', '\n', ';', ':'])
+            .map(str::trim)
+            .find(|sentence| !sentence.is_empty())
+            .unwrap_or_default();
+        let words: Vec<&str> = first_sentence.split_whitespace().collect();
+        let is = |list: &[&str], word: &str| {
+            let word = word
+                .trim_matches(|c: char| !c.is_alphanumeric() && c != '\'')
+                .to_lowercase();
+            list.contains(&word.as_str())
+        };
+
+        let start = words
+            .iter()
+            .position(|word| !is(FILLER_WORDS, word))
+            .unwrap_or(words.len());
+        let mut phrase: Vec<&str> = Vec::new();
+        for word in &words[start..] {
+            if !phrase.is_empty() && is(CLAUSE_WORDS, word) {
+                break;
+            }
+            if is(&["please"], word) {
+                continue;
+            }
+            let ends_clause = word.ends_with(',');
+            phrase.push(word.trim_end_matches(','));
+            if ends_clause || phrase.len() == MAX_HEURISTIC_WORDS {
+                break;
+            }
+        }
+        while phrase.len() > 1 && phrase.last().is_some_and(|word| is(TRAILING_WORDS, word)) {
+            phrase.pop();
+        }
+
+        // A prompt made only of filler keeps its opening words
+        if phrase.is_empty() {
+            phrase = words.iter().take(MAX_HEURISTIC_WORDS).copied().collect();
+        }
+        match capitalized(&truncated(&phrase.join(" "))) {
+            name if name.is_empty() => "Untitled Task".to_string(),
+            name => name,
+        }
+    }
+}
+
+#[async_trait]
+impl ConversationNamer for HeuristicNamer {
+    async fn name_conversation(
+        &self,
+        ctx: &ConversationContext,
+        _session: &Session,
+    ) -> Result<String, CommandGenError> {
+        Ok(Self::name(&ctx.user_prompt))
+    }
+}
+
+/// A model's suggested `title` as a name: on one line, without quotes or a final period,
+/// at most `MAX_TITLE_WORDS` words. `None` if nothing is left.
+pub fn clean_title(title: &str) -> Option<String> {
+    let words: Vec<&str> = title
+        .split_whitespace()
+        .take(MAX_TITLE_WORDS)
+        .map(|word| word.trim_matches(|c| matches!(c, '"' | '\'' | '`' | '*')))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let title = words.join(" ");
+    let title = title.trim_end_matches(['.', ':', ';', ',']);
+    (!title.is_empty()).then(|| capitalized(&truncated(title)))
+}
+
+/// `name` trimmed and cut to `MAX_NAME_CHARS`, for names given by hand.
+pub fn truncated(name: &str) -> String {
+    let name = name.trim();
+    match name.char_indices().nth(MAX_NAME_CHARS - 3) {
+        Some((end, _)) if name.chars().count() > MAX_NAME_CHARS => {
+            format!("{}...", name[..end].trim_end())
+        }
+        _ => name.to_string(),
+    }
+}
+
+fn capitalized(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
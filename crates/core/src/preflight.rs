@@ -0,0 +1,55 @@
+//! What checking a workflow's surroundings before it runs found. `Error` findings, such as
+//! a working directory that cannot be written, would make its steps fail and stop the
+//! workflow unless it is forced; `Warning` findings, such as an unreachable network or
+//! uncommitted changes, are shown and handed to the command generator to adapt to.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightSeverity {
+    Warning,
+    Error,
+}
+
+/// One check that did not pass.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreflightFinding {
+    /// Short name of the check, such as `disk-space`.
+    pub check: String,
+    pub severity: PreflightSeverity,
+    pub message: String,
+}
+
+impl PreflightFinding {
+    pub fn warning(check: &str, message: String) -> Self {
+        Self {
+            check: check.to_string(),
+            severity: PreflightSeverity::Warning,
+            message,
+        }
+    }
+
+    pub fn error(check: &str, message: String) -> Self {
+        Self {
+            check: check.to_string(),
+            severity: PreflightSeverity::Error,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for PreflightFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (check `{}`)", self.message, self.check)
+    }
+}
+
+/// The findings among `findings` that stop a workflow.
+pub fn errors(findings: &[PreflightFinding]) -> Vec<PreflightFinding> {
+    findings
+        .iter()
+        .filter(|finding| finding.severity == PreflightSeverity::Error)
+        .cloned()
+        .collect()
+}
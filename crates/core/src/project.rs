@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a working directory looks like to the model: one entry per detected
+/// ecosystem plus auxiliary tooling such as docker or git.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProjectDetection {
+    pub ecosystems: Vec<Ecosystem>,
+    pub tooling: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ecosystem {
+    pub name: String,
+    pub details: Vec<String>,
+}
+
+impl fmt::Display for Ecosystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.details.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} ({})", self.name, self.details.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for ProjectDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ecosystems: Vec<String> = self.ecosystems.iter().map(|e| e.to_string()).collect();
+        match (ecosystems.is_empty(), self.tooling.is_empty()) {
+            (true, true) => write!(f, "unknown"),
+            (true, false) => write!(f, "{}", self.tooling.join(", ")),
+            (false, true) => write!(f, "{}", ecosystems.join(" + ")),
+            (false, false) => write!(
+                f,
+                "{} [{}]",
+                ecosystems.join(" + "),
+                self.tooling.join(", ")
+            ),
+        }
+    }
+}
+
+impl ProjectDetection {
+    pub fn is_empty(&self) -> bool {
+        self.ecosystems.is_empty() && self.tooling.is_empty()
+    }
+}
+
+/// Caches detection results per directory, invalidated when the directory's mtime changes.
+#[derive(Default)]
+pub struct ProjectDetector {
+    cache: HashMap<PathBuf, (Option<SystemTime>, Option<String>)>,
+}
+
+impl ProjectDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the formatted project type for `dir`, or `None` if nothing was recognised.
+    pub fn detect(&mut self, dir: &Path) -> Option<String> {
+        let mtime = fs::metadata(dir).and_then(|m| m.modified()).ok();
+        if let Some((cached_mtime, cached)) = self.cache.get(dir) {
+            if mtime.is_some() && *cached_mtime == mtime {
+                return cached.clone();
+            }
+        }
+
+        let detection = detect_project(dir);
+        let result = (!detection.is_empty()).then(|| detection.to_string());
+        self.cache
+            .insert(dir.to_path_buf(), (mtime, result.clone()));
+        result
+    }
+}
+
+pub fn detect_project(dir: &Path) -> ProjectDetection {
+    let mut detection = ProjectDetection::default();
+
+    if let Some(rust) = detect_rust(dir) {
+        detection.ecosystems.push(rust);
+    }
+    if let Some(node) = detect_node(dir) {
+        detection.ecosystems.push(node);
+    }
+    if let Some(python) = detect_python(dir) {
+        detection.ecosystems.push(python);
+    }
+    if let Some(go) = detect_go(dir) {
+        detection.ecosystems.push(go);
+    }
+    if let Some(java) = detect_java(dir) {
+        detection.ecosystems.push(java);
+    }
+
+    if dir.join("Dockerfile").exists()
+        || dir.join("docker-compose.yml").exists()
+        || dir.join("docker-compose.yaml").exists()
+        || dir.join("compose.yaml").exists()
+    {
+        detection.tooling.push("docker".to_string());
+    }
+    if dir.join("Makefile").exists() {
+        detection.tooling.push("make".to_string());
+    }
+    if dir.join(".git").exists() {
+        detection.tooling.push("git".to_string());
+    }
+
+    detection
+}
+
+fn detect_rust(dir: &Path) -> Option<Ecosystem> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let mut details = Vec::new();
+
+    if manifest.lines().any(|line| line.trim() == "[workspace]") {
+        details.push("cargo workspace".to_string());
+        let members = count_workspace_members(&manifest);
+        if members > 0 {
+            details.push(format!(
+                "{} member{}",
+                members,
+                if members == 1 { "" } else { "s" }
+            ));
+        }
+    } else {
+        details.push("cargo".to_string());
+    }
+
+    Some(Ecosystem {
+        name: "rust".to_string(),
+        details,
+    })
+}
+
+/// Count the quoted entries in `members = [...]`, which may span several lines.
+fn count_workspace_members(manifest: &str) -> usize {
+    let Some(start) = manifest.find("members") else {
+        return 0;
+    };
+    let rest = &manifest[start..];
+    let (Some(open), Some(close)) = (rest.find('['), rest.find(']')) else {
+        return 0;
+    };
+    if close < open {
+        return 0;
+    }
+    rest[open + 1..close]
+        .split(',')
+        .filter(|entry| entry.contains('"') || entry.contains('\''))
+        .count()
+}
+
+fn detect_node(dir: &Path) -> Option<Ecosystem> {
+    let package = fs::read_to_string(dir.join("package.json")).ok()?;
+    let package: serde_json::Value = serde_json::from_str(&package).unwrap_or_default();
+    let mut details = Vec::new();
+
+    let manager = if dir.join("pnpm-lock.yaml").exists() {
+        "pnpm"
+    } else if dir.join("yarn.lock").exists() {
+        "yarn"
+    } else if dir.join("bun.lockb").exists() || dir.join("bun.lock").exists() {
+        "bun"
+    } else {
+        "npm"
+    };
+    details.push(manager.to_string());
+
+    let has_dependency = |name: &str| {
+        ["dependencies", "devDependencies"].iter().any(|section| {
+            package
+                .get(section)
+                .and_then(|deps| deps.get(name))
+                .is_some()
+        })
+    };
+    if dir.join("tsconfig.json").exists() || has_dependency("typescript") {
+        details.push("typescript".to_string());
+    }
+    if package.get("workspaces").is_some() || dir.join("pnpm-workspace.yaml").exists() {
+        details.push("workspaces".to_string());
+    }
+
+    Some(Ecosystem {
+        name: "node".to_string(),
+        details,
+    })
+}
+
+fn detect_python(dir: &Path) -> Option<Ecosystem> {
+    let mut details = Vec::new();
+
+    if let Ok(pyproject) = fs::read_to_string(dir.join("pyproject.toml")) {
+        if pyproject.contains("[tool.poetry]") {
+            details.push("poetry".to_string());
+        } else if dir.join("uv.lock").exists() {
+            details.push("uv".to_string());
+        } else {
+            details.push("pyproject".to_string());
+        }
+    } else if dir.join("Pipfile").exists() {
+        details.push("pipenv".to_string());
+    } else if dir.join("requirements.txt").exists() {
+        details.push("pip".to_string());
+    } else if dir.join("setup.py").exists() {
+        details.push("setuptools".to_string());
+    } else {
+        return None;
+    }
+
+    if dir.join(".venv").is_dir() || dir.join("venv").is_dir() {
+        details.push("virtualenv".to_string());
+    }
+
+    Some(Ecosystem {
+        name: "python".to_string(),
+        details,
+    })
+}
+
+fn detect_go(dir: &Path) -> Option<Ecosystem> {
+    let go_mod = fs::read_to_string(dir.join("go.mod")).ok()?;
+    let details = go_mod
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("module "))
+        .map(|module| vec![format!("module {}", module.trim())])
+        .unwrap_or_default();
+
+    Some(Ecosystem {
+        name: "go".to_string(),
+        details,
+    })
+}
+
+fn detect_java(dir: &Path) -> Option<Ecosystem> {
+    let details = if dir.join("pom.xml").exists() {
+        vec!["maven".to_string()]
+    } else if dir.join("build.gradle.kts").exists() {
+        vec!["gradle".to_string(), "kotlin dsl".to_string()]
+    } else if dir.join("build.gradle").exists() {
+        vec!["gradle".to_string()]
+    } else {
+        return None;
+    };
+
+    Some(Ecosystem {
+        name: "java".to_string(),
+        details,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context_files::ContextFiles;
+
+    /// A fresh directory holding `files`, each given as (relative path, content); a path
+    /// ending in `/` is created as a directory.
+    fn fixture(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parsec-project-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        for (path, content) in files {
+            match path.strip_suffix('/') {
+                Some(path) => fs::create_dir_all(dir.join(path)).unwrap(),
+                None => fs::write(dir.join(path), content).unwrap(),
+            }
+        }
+        dir
+    }
+
+    /// The project type and attached manifests of a directory holding `files`.
+    fn detected(files: &[(&str, &str)]) -> (String, Vec<String>) {
+        let dir = fixture(files);
+        let detection = detect_project(&dir).to_string();
+        let manifests = ContextFiles::new().manifests_in(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        (detection, manifests)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn cargo_packages_and_workspaces() {
+        assert_eq!(
+            detected(&[("Cargo.toml", "[package]\nname = \"app\"\n")]),
+            ("rust (cargo)".to_string(), names(&["Cargo.toml"]))
+        );
+        assert_eq!(
+            detected(&[(
+                "Cargo.toml",
+                "[workspace]\nmembers = [\n    \"crates/core\",\n    \"crates/ui\",\n]\n"
+            )]),
+            (
+                "rust (cargo workspace, 2 members)".to_string(),
+                names(&["Cargo.toml"])
+            )
+        );
+    }
+
+    #[test]
+    fn node_package_managers_and_typescript() {
+        assert_eq!(
+            detected(&[("package.json", "{\"name\": \"app\"}")]),
+            ("node (npm)".to_string(), names(&["package.json"]))
+        );
+        assert_eq!(
+            detected(&[
+                (
+                    "package.json",
+                    "{\"devDependencies\": {\"typescript\": \"^5\"}, \"workspaces\": [\"web\"]}"
+                ),
+                ("pnpm-lock.yaml", ""),
+            ]),
+            (
+                "node (pnpm, typescript, workspaces)".to_string(),
+                names(&["package.json"])
+            )
+        );
+        assert_eq!(
+            detected(&[
+                ("package.json", "{}"),
+                ("yarn.lock", ""),
+                ("tsconfig.json", "{}")
+            ])
+            .0,
+            "node (yarn, typescript)"
+        );
+    }
+
+    #[test]
+    fn python_project_layouts() {
+        assert_eq!(
+            detected(&[("pyproject.toml", "[tool.poetry]\nname = \"app\"\n")]),
+            ("python (poetry)".to_string(), names(&["pyproject.toml"]))
+        );
+        assert_eq!(
+            detected(&[("pyproject.toml", "[project]\n"), ("uv.lock", "")]).0,
+            "python (uv)"
+        );
+        assert_eq!(
+            detected(&[("requirements.txt", "requests\n"), (".venv/", "")]),
+            (
+                "python (pip, virtualenv)".to_string(),
+                names(&["requirements.txt"])
+            )
+        );
+        assert_eq!(
+            detected(&[("Pipfile", "")]),
+            ("python (pipenv)".to_string(), names(&["Pipfile"]))
+        );
+        assert_eq!(
+            detected(&[("setup.py", "")]),
+            ("python (setuptools)".to_string(), names(&["setup.py"]))
+        );
+    }
+
+    #[test]
+    fn go_modules_and_java_builds() {
+        assert_eq!(
+            detected(&[("go.mod", "module example.com/app\n\ngo 1.22\n")]),
+            (
+                "go (module example.com/app)".to_string(),
+                names(&["go.mod"])
+            )
+        );
+        assert_eq!(
+            detected(&[("pom.xml", "<project/>")]),
+            ("java (maven)".to_string(), names(&["pom.xml"]))
+        );
+        assert_eq!(
+            detected(&[("build.gradle.kts", "")]),
+            (
+                "java (gradle, kotlin dsl)".to_string(),
+                names(&["build.gradle.kts"])
+            )
+        );
+    }
+
+    #[test]
+    fn mixed_projects_list_every_ecosystem_and_their_tooling() {
+        assert_eq!(
+            detected(&[
+                ("Cargo.toml", "[package]\n"),
+                ("package.json", "{}"),
+                ("Dockerfile", "FROM scratch\n"),
+                ("Makefile", "all:\n"),
+                (".git/", ""),
+            ]),
+            (
+                "rust (cargo) + node (npm) [docker, make, git]".to_string(),
+                names(&["Cargo.toml", "package.json", "Makefile", "Dockerfile"])
+            )
+        );
+    }
+
+    #[test]
+    fn directories_without_a_project_are_unknown() {
+        assert_eq!(
+            detected(&[("notes.txt", "")]),
+            ("unknown".to_string(), Vec::new())
+        );
+        let dir = fixture(&[("README.md", "")]);
+        assert_eq!(ProjectDetector::new().detect(&dir), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,323 @@
+//! Full-text search over what sessions and conversations recorded: direct commands,
+//! the commands workflow steps ran, step descriptions and conversation prompts. Stores
+//! feed each session and conversation through `session_hits` and `conversation_hits`
+//! and order the result with `rank`. Text is matched and shown with secrets redacted, and
+//! environment values too for sessions in privacy mode, so a search cannot reveal them.
+
+use crate::secrets::{redact_environment_values, SecretRedactor};
+use crate::{ConversationContext, ConversationId, Session, SessionId};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Characters of context kept on each side of the first match in a snippet.
+const SNIPPET_CONTEXT: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchHitKind {
+    DirectCommand,
+    WorkflowCommand,
+    StepDescription,
+    ConversationPrompt,
+}
+
+impl SearchHitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchHitKind::DirectCommand => "command",
+            SearchHitKind::WorkflowCommand => "step command",
+            SearchHitKind::StepDescription => "step",
+            SearchHitKind::ConversationPrompt => "prompt",
+        }
+    }
+
+    /// Whether the hit's text can be run again as typed.
+    pub fn is_command(&self) -> bool {
+        matches!(
+            self,
+            SearchHitKind::DirectCommand | SearchHitKind::WorkflowCommand
+        )
+    }
+}
+
+/// What `SessionStore::search` looks through.
+#[derive(Debug, Clone, Default)]
+pub struct SearchScope {
+    /// Only this session and its conversations.
+    pub session_id: Option<SessionId>,
+    /// Only these kinds of hit; empty means all of them.
+    pub kinds: Vec<SearchHitKind>,
+    /// Most hits returned, best first; 0 means no limit.
+    pub limit: usize,
+    /// Applied to the text of every hit before it is matched.
+    pub redactor: Option<SecretRedactor>,
+}
+
+impl SearchScope {
+    fn includes(&self, kind: SearchHitKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub kind: SearchHitKind,
+    pub session_id: SessionId,
+    pub conversation_id: Option<ConversationId>,
+    pub step_index: Option<usize>,
+    /// The whole command, description or prompt that matched.
+    pub text: String,
+    /// `text` around its first match, on one line.
+    pub snippet: String,
+    /// Exit status of a command hit, if it ran to completion.
+    pub exit_status: Option<i32>,
+    /// `text` differs from what was recorded, so a command cannot be re-run from it.
+    pub redacted: bool,
+    pub timestamp: DateTime<Utc>,
+    pub score: f32,
+}
+
+/// A query as case-insensitive terms that must all appear.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    phrase: String,
+    terms: Vec<String>,
+}
+
+impl SearchQuery {
+    pub fn new(query: &str) -> Self {
+        Self {
+            phrase: query.trim().to_lowercase(),
+            terms: query.split_whitespace().map(str::to_lowercase).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Score of `text`, or `None` unless every term appears in it: one point per
+    /// occurrence of a term (up to five), one more when a term starts a word, and three
+    /// when the whole query appears as typed.
+    pub fn score(&self, text: &str) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        let lower = text.to_lowercase();
+        let mut score = 0.0;
+        for term in &self.terms {
+            let starts: Vec<usize> = lower
+                .match_indices(term.as_str())
+                .map(|(at, _)| at)
+                .collect();
+            if starts.is_empty() {
+                return None;
+            }
+            score += starts.len().min(5) as f32;
+            if starts.iter().any(|&at| {
+                lower[..at]
+                    .chars()
+                    .last()
+                    .is_none_or(|c| !c.is_alphanumeric())
+            }) {
+                score += 1.0;
+            }
+        }
+        if self.terms.len() > 1 && lower.contains(&self.phrase) {
+            score += 3.0;
+        }
+        Some(score)
+    }
+
+    /// `text` on one line, cut to the first match and `SNIPPET_CONTEXT` characters
+    /// either side of it.
+    pub fn snippet(&self, text: &str) -> String {
+        let chars: Vec<char> = text
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .collect();
+        let lower: Vec<char> = chars
+            .iter()
+            .map(|c| c.to_lowercase().next().unwrap_or(*c))
+            .collect();
+        let first = self
+            .terms
+            .first()
+            .map(|term| term.chars().collect::<Vec<_>>());
+        let at = first
+            .as_ref()
+            .and_then(|term| (0..lower.len()).find(|&i| lower[i..].starts_with(term.as_slice())))
+            .unwrap_or(0);
+        let match_len = first.map_or(0, |term| term.len());
+
+        let start = at.saturating_sub(SNIPPET_CONTEXT);
+        let end = (at + match_len + SNIPPET_CONTEXT).min(chars.len());
+        let mut snippet: String = chars[start..end].iter().collect();
+        if start > 0 {
+            snippet.insert(0, '…');
+        }
+        if end < chars.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+
+    fn hit(
+        &self,
+        origin: &Origin,
+        kind: SearchHitKind,
+        text: &str,
+        step_index: Option<usize>,
+        exit_status: Option<i32>,
+        timestamp: DateTime<Utc>,
+    ) -> Option<SearchHit> {
+        let recorded = text;
+        let mut text = Cow::Borrowed(text);
+        if let Some(environment) = origin.private_environment {
+            text = Cow::Owned(redact_environment_values(&text, environment));
+        }
+        if let Some(redactor) = origin.redactor {
+            text = Cow::Owned(redactor.redact(&text));
+        }
+        let mut score = self.score(&text)?;
+        // Commands that worked are usually the ones being looked for
+        if exit_status == Some(0) {
+            score += 0.5;
+        }
+        Some(SearchHit {
+            kind,
+            session_id: origin.session_id.clone(),
+            conversation_id: origin.conversation_id.cloned(),
+            step_index,
+            snippet: self.snippet(&text),
+            text: text.to_string(),
+            exit_status,
+            redacted: text != recorded,
+            timestamp,
+            score,
+        })
+    }
+}
+
+/// What the hits of one session or conversation have in common.
+struct Origin<'a> {
+    session_id: &'a SessionId,
+    conversation_id: Option<&'a ConversationId>,
+    private_environment: Option<&'a HashMap<String, String>>,
+    redactor: Option<&'a SecretRedactor>,
+}
+
+/// The environment to redact from the text of `session` and its conversations, if it is
+/// in privacy mode.
+pub fn private_environment(session: &Session) -> Option<&HashMap<String, String>> {
+    session
+        .settings
+        .privacy_mode
+        .then_some(&session.global_context.environment_snapshot)
+}
+
+/// Hits among the direct commands of `session`.
+pub fn session_hits(session: &Session, query: &SearchQuery, scope: &SearchScope) -> Vec<SearchHit> {
+    if !scope.includes(SearchHitKind::DirectCommand) {
+        return Vec::new();
+    }
+    let origin = Origin {
+        session_id: &session.id,
+        conversation_id: None,
+        private_environment: private_environment(session),
+        redactor: scope.redactor.as_ref(),
+    };
+    session
+        .command_history
+        .iter()
+        .filter_map(|execution| {
+            query.hit(
+                &origin,
+                SearchHitKind::DirectCommand,
+                &execution.command,
+                None,
+                execution.completed_at.map(|_| execution.exit_status),
+                execution.executed_at,
+            )
+        })
+        .collect()
+}
+
+/// Hits in the prompt, step descriptions and executed commands of `conversation`, with
+/// the `private_environment` of its session redacted.
+pub fn conversation_hits(
+    conversation: &ConversationContext,
+    private_environment: Option<&HashMap<String, String>>,
+    query: &SearchQuery,
+    scope: &SearchScope,
+) -> Vec<SearchHit> {
+    let origin = Origin {
+        session_id: &conversation.session_id,
+        conversation_id: Some(&conversation.id),
+        private_environment,
+        redactor: scope.redactor.as_ref(),
+    };
+    let mut hits = Vec::new();
+    if scope.includes(SearchHitKind::ConversationPrompt) {
+        hits.extend(query.hit(
+            &origin,
+            SearchHitKind::ConversationPrompt,
+            &conversation.user_prompt,
+            None,
+            None,
+            conversation.created_at,
+        ));
+    }
+    for (index, step) in conversation.steps.iter().enumerate() {
+        if scope.includes(SearchHitKind::StepDescription) {
+            hits.extend(
+                query.hit(
+                    &origin,
+                    SearchHitKind::StepDescription,
+                    &step.step.description,
+                    Some(index),
+                    None,
+                    step.command_attempts
+                        .first()
+                        .map_or(conversation.created_at, |attempt| attempt.timestamp),
+                ),
+            );
+        }
+        if scope.includes(SearchHitKind::WorkflowCommand) {
+            hits.extend(
+                step.command_attempts
+                    .iter()
+                    .filter(|attempt| attempt.executed)
+                    .filter_map(|attempt| {
+                        query.hit(
+                            &origin,
+                            SearchHitKind::WorkflowCommand,
+                            &attempt.candidate.command,
+                            Some(index),
+                            attempt.exit_status,
+                            attempt.timestamp,
+                        )
+                    }),
+            );
+        }
+    }
+    hits
+}
+
+/// `hits` best first, most recent first among equals, cut to `scope.limit`. The same
+/// command run several times is listed once, at its best.
+pub fn rank(mut hits: Vec<SearchHit>, scope: &SearchScope) -> Vec<SearchHit> {
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.timestamp.cmp(&a.timestamp))
+    });
+    let mut seen = std::collections::HashSet::new();
+    hits.retain(|hit| !hit.kind.is_command() || seen.insert(hit.text.clone()));
+    if scope.limit > 0 {
+        hits.truncate(scope.limit);
+    }
+    hits
+}
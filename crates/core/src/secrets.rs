@@ -0,0 +1,125 @@
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+/// Credentials recognized in command output, each replaced by `[REDACTED:<kind>]`.
+/// Private key blocks come first so the tokens inside them are not matched one by one.
+const PATTERNS: &[(&str, &str)] = &[
+    (
+        "private_key",
+        r"-----BEGIN [A-Z ]*PRIVATE KEY-----(?s:.*?)(?:-----END [A-Z ]*PRIVATE KEY-----|\z)",
+    ),
+    ("aws_access_key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "github_token",
+        r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b",
+    ),
+    ("gitlab_token", r"\bglpat-[A-Za-z0-9_-]{20,}"),
+    (
+        "jwt",
+        r"\beyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}",
+    ),
+];
+
+/// `NAME=value` and `name: value` assignments whose name ends in a credential word, as
+/// printed by `env`, `.env` files and YAML or JSON configs. Only the value is replaced.
+const ASSIGNMENT: &str = r#"(?i)(?P<key>\b[A-Z0-9_.-]*(?:PASSWORD|PASSWD|SECRET|TOKEN|API_?KEY|ACCESS_?KEY|PRIVATE_?KEY|CREDENTIALS?)["']?[ \t]*[=:][ \t]*)(?P<value>"[^"\n]*"|'[^'\n]*'|[^\s"',]+)"#;
+
+const PLACEHOLDER_PREFIX: &str = "[REDACTED:";
+
+/// Stands in for environment values hidden in privacy mode.
+pub const REDACTED_VALUE: &str = "[redacted]";
+/// Shorter environment values (`1`, `en`) are too common in text to redact.
+const MIN_REDACTED_VALUE_LEN: usize = 4;
+
+struct Compiled {
+    patterns: Vec<(&'static str, Regex)>,
+    assignment: Regex,
+}
+
+fn compiled() -> &'static Compiled {
+    static COMPILED: OnceLock<Compiled> = OnceLock::new();
+    COMPILED.get_or_init(|| Compiled {
+        patterns: PATTERNS
+            .iter()
+            .map(|(kind, pattern)| (*kind, Regex::new(pattern).expect("secret patterns compile")))
+            .collect(),
+        assignment: Regex::new(ASSIGNMENT).expect("secret patterns compile"),
+    })
+}
+
+/// Replaces credentials in command output with typed placeholders before the output is
+/// stored or sent to the model. Values on the allowlist are left alone.
+#[derive(Debug, Clone, Default)]
+pub struct SecretRedactor {
+    allowlist: Vec<String>,
+}
+
+impl SecretRedactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exact values that look like secrets but are not, such as documentation example keys.
+    pub fn with_allowlist(mut self, allowlist: Vec<String>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let compiled = compiled();
+        let mut text = text.to_string();
+        for (kind, pattern) in &compiled.patterns {
+            if !pattern.is_match(&text) {
+                continue;
+            }
+            text = pattern
+                .replace_all(&text, |caps: &Captures| {
+                    self.placeholder(&caps[0], kind)
+                        .unwrap_or_else(|| caps[0].to_string())
+                })
+                .into_owned();
+        }
+        compiled
+            .assignment
+            .replace_all(&text, |caps: &Captures| {
+                let value = &caps["value"];
+                let unquoted = value.trim_matches(|c| c == '"' || c == '\'');
+                match self.placeholder(unquoted, "secret") {
+                    Some(placeholder) if !unquoted.starts_with(PLACEHOLDER_PREFIX) => {
+                        format!("{}{}", &caps["key"], placeholder)
+                    }
+                    _ => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+
+    /// Whether `text` holds anything `redact` would replace.
+    pub fn finds_secret(&self, text: &str) -> bool {
+        self.redact(text) != text
+    }
+
+    /// The placeholder for `secret`, or `None` if it is empty or allowlisted.
+    fn placeholder(&self, secret: &str, kind: &str) -> Option<String> {
+        if secret.is_empty() || self.allowlist.iter().any(|allowed| allowed == secret) {
+            return None;
+        }
+        Some(format!("{}{}]", PLACEHOLDER_PREFIX, kind))
+    }
+}
+
+/// Replace environment values (of at least `MIN_REDACTED_VALUE_LEN` characters) found in `text`.
+pub fn redact_environment_values(
+    text: &str,
+    environment: &std::collections::HashMap<String, String>,
+) -> String {
+    let mut values: Vec<&String> = environment
+        .values()
+        .filter(|value| value.chars().count() >= MIN_REDACTED_VALUE_LEN)
+        .collect();
+    // Longest first, so a value is not partially replaced through a shorter one it contains
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    values.into_iter().fold(text.to_string(), |text, value| {
+        text.replace(value.as_str(), REDACTED_VALUE)
+    })
+}
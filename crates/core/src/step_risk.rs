@@ -0,0 +1,137 @@
+//! Risk categories guessed from a step's description alone, for previewing a plan before
+//! any command is generated. They are a hint for the reader: the commands generated later
+//! are scored and validated as usual.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a step looks like it will do, from least to most risky.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepRisk {
+    /// Looks at files, the system or the network without changing anything.
+    ReadOnly,
+    /// Creates or edits files in the project, or builds and tests it.
+    ChangesFiles,
+    /// Installs, upgrades or downloads packages and tools.
+    Installs,
+    /// Changes the system or something remote: services, users, permissions, deployments.
+    ChangesSystem,
+    /// Deletes, overwrites or resets things that may not come back.
+    Destructive,
+}
+
+/// Words, matched against the start of the description's words, that make a step riskier
+/// than changing files. The riskiest category matched wins.
+const DESTRUCTIVE: &[&str] = &[
+    "delete",
+    "remove",
+    "rm",
+    "drop",
+    "wipe",
+    "erase",
+    "purge",
+    "destroy",
+    "truncate",
+    "overwrite",
+    "reset",
+    "uninstall",
+    "prune",
+    "clean",
+    "force",
+];
+const CHANGES_SYSTEM: &[&str] = &[
+    "deploy",
+    "publish",
+    "push",
+    "release",
+    "sudo",
+    "chmod",
+    "chown",
+    "systemctl",
+    "restart",
+    "reboot",
+    "shutdown",
+    "kill",
+    "mount",
+    "migrate",
+    "firewall",
+    "upload",
+    "crontab",
+];
+const INSTALLS: &[&str] = &[
+    "install", "upgrade", "update", "download", "fetch", "pull", "clone",
+];
+/// Opening verbs of steps that only look.
+const READ_ONLY: &[&str] = &[
+    "check",
+    "list",
+    "show",
+    "view",
+    "inspect",
+    "read",
+    "find",
+    "search",
+    "look",
+    "verify",
+    "print",
+    "display",
+    "count",
+    "locate",
+    "determine",
+    "identify",
+    "review",
+    "diff",
+    "status",
+    "examine",
+    "confirm",
+    "detect",
+];
+
+impl StepRisk {
+    /// The category `description` suggests. A step none of the riskier words appear in
+    /// is read-only if it opens with a verb that only looks, and otherwise taken to
+    /// change files, as most steps do.
+    pub fn of(description: &str) -> Self {
+        let words: Vec<String> = description
+            .split(|c: char| !c.is_alphanumeric() && c != '-')
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        let matches = |stems: &[&str]| {
+            words
+                .iter()
+                .any(|word| stems.iter().any(|stem| word.starts_with(stem)))
+        };
+        if matches(DESTRUCTIVE) {
+            StepRisk::Destructive
+        } else if matches(CHANGES_SYSTEM) {
+            StepRisk::ChangesSystem
+        } else if matches(INSTALLS) {
+            StepRisk::Installs
+        } else if words
+            .first()
+            .is_some_and(|word| READ_ONLY.iter().any(|stem| word.starts_with(stem)))
+        {
+            StepRisk::ReadOnly
+        } else {
+            StepRisk::ChangesFiles
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StepRisk::ReadOnly => "read-only",
+            StepRisk::ChangesFiles => "changes files",
+            StepRisk::Installs => "installs",
+            StepRisk::ChangesSystem => "changes system",
+            StepRisk::Destructive => "destructive",
+        }
+    }
+}
+
+impl fmt::Display for StepRisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
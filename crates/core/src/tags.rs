@@ -0,0 +1,52 @@
+//! Conversation tags: short lowercase labels such as `deploy` or `rust` that group
+//! conversations for listing and for the context of later ones. Conversations are tagged
+//! with their provider and the session's project type when planned, and by hand after.
+
+use crate::{ConversationContext, ConversationFilter};
+
+/// `tag` lowercased and trimmed, with inner whitespace turned into `-`, or `None` if
+/// nothing is left.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    (!tag.is_empty()).then_some(tag)
+}
+
+impl ConversationContext {
+    /// Add `tag`, normalized; false if the conversation already had it.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        match normalize(tag) {
+            Some(tag) if !self.tags.contains(&tag) => {
+                self.tags.push(tag);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove `tag`, normalized; false if the conversation did not have it.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let Some(tag) = normalize(tag) else {
+            return false;
+        };
+        let before = self.tags.len();
+        self.tags.retain(|known| *known != tag);
+        self.tags.len() != before
+    }
+}
+
+impl ConversationFilter {
+    pub fn matches(&self, conversation: &ConversationContext) -> bool {
+        self.session_id
+            .as_ref()
+            .is_none_or(|id| *id == conversation.session_id)
+            && self
+                .tags
+                .iter()
+                .filter_map(|tag| normalize(tag))
+                .all(|tag| conversation.tags.contains(&tag))
+    }
+}
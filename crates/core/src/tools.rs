@@ -0,0 +1,354 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+pub const DEFAULT_TRACKED_TOOLS: &[&str] = &[
+    "git",
+    "cargo",
+    "npm",
+    "python",
+    "node",
+    "docker",
+    "kubectl",
+    "make",
+    "cmake",
+    "gcc",
+    "clang",
+    "rustc",
+    "javac",
+    "mvn",
+    "terraform",
+    "helm",
+    "poetry",
+];
+
+pub fn default_tracked_tools() -> Vec<String> {
+    DEFAULT_TRACKED_TOOLS
+        .iter()
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Which of `candidates` are executable files somewhere on `$PATH`.
+pub fn detect_tools(candidates: &[String]) -> Vec<String> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<_> = env::split_paths(&path).collect();
+
+    candidates
+        .iter()
+        .filter(|tool| dirs.iter().any(|dir| is_executable(&dir.join(tool))))
+        .cloned()
+        .collect()
+}
+
+/// Names of every executable file in the `$PATH` directories.
+pub fn path_executables() -> HashSet<String> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// Shell builtins and reserved words: never looked up on `$PATH`.
+pub const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "[", "[[", "alias", "bg", "break", "builtin", "case", "cd", "command", "continue",
+    "declare", "dirs", "do", "done", "echo", "elif", "else", "esac", "eval", "exec", "exit",
+    "export", "false", "fg", "fi", "for", "function", "hash", "history", "if", "jobs", "kill",
+    "let", "local", "popd", "printf", "pushd", "pwd", "read", "readonly", "return", "set", "shift",
+    "source", "test", "then", "time", "trap", "true", "type", "ulimit", "umask", "unalias",
+    "unset", "until", "wait", "while", "{", "}", "(", ")", "!",
+];
+
+/// Prefixes that run the command that follows them, e.g. `sudo apt install`.
+const COMMAND_WRAPPERS: &[&str] = &["sudo", "env", "time", "nohup", "nice", "xargs"];
+
+/// Control operators that separate the commands of a command line.
+const OPERATORS: &[&str] = &["&&", "||", ";;", "|", ";", "&"];
+
+/// One piece of a shell command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandToken {
+    /// A word with its quotes and backslash escapes removed.
+    Word(String),
+    /// `&&`, `||`, `|`, `;` or `&` between two commands.
+    Operator(&'static str),
+}
+
+/// Split `command` into words and control operators the way a POSIX shell would, minus
+/// expansions. An `&` inside a redirection such as `2>&1` stays part of its word.
+pub fn tokenize_command(command: &str) -> Vec<CommandToken> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                            word.extend(chars.next())
+                        }
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.extend(chars.next());
+            }
+            '&' if word.ends_with(['>', '<']) || chars.peek() == Some(&'>') => {
+                in_word = true;
+                word.push(c);
+            }
+            c if c.is_whitespace() || matches!(c, '&' | '|' | ';') => {
+                if in_word {
+                    tokens.push(CommandToken::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+                if !c.is_whitespace() {
+                    let rest: String = std::iter::once(c).chain(chars.clone().take(1)).collect();
+                    let operator = OPERATORS
+                        .iter()
+                        .find(|operator| rest.starts_with(**operator))
+                        .expect("every operator character starts an operator");
+                    if operator.len() == 2 {
+                        chars.next();
+                    }
+                    tokens.push(CommandToken::Operator(operator));
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        tokens.push(CommandToken::Word(word));
+    }
+    tokens
+}
+
+/// `command` as a program and its arguments, for running it without a shell; control
+/// operators are passed on as plain arguments.
+pub fn command_words(command: &str) -> Vec<String> {
+    tokenize_command(command)
+        .into_iter()
+        .map(|token| match token {
+            CommandToken::Word(word) => word,
+            CommandToken::Operator(operator) => operator.to_string(),
+        })
+        .collect()
+}
+
+/// Whether `command` means something different without a shell: it has control
+/// operators, redirections, expansions, globs or subshells, or starts with a `NAME=value`
+/// assignment. Quoted text counts too; the shell removes the quotes again.
+pub fn needs_shell(command: &str) -> bool {
+    let tokens = tokenize_command(command);
+    let assigns = match tokens.first() {
+        Some(CommandToken::Word(word)) => word.split_once('=').is_some_and(|(name, _)| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }),
+        _ => false,
+    };
+    assigns
+        || tokens
+            .iter()
+            .any(|token| matches!(token, CommandToken::Operator(_)))
+        || command.contains(['<', '>', '$', '`', '*', '?', '(', ')', '~'])
+}
+
+/// A command line reading a here-document, such as `psql app <<'SQL'` followed by the
+/// document's lines and a closing `SQL`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heredoc {
+    /// The command line without its `<<` redirection.
+    pub command: String,
+    /// What the command reads on standard input: each line of the document, with the
+    /// leading tabs of a `<<-` document stripped.
+    pub body: String,
+}
+
+/// `command` as a command line and the here-document it reads, when it is exactly that
+/// and can run without a shell: no other redirections, operators or expansions in the
+/// command line, nothing after the closing delimiter, and no `$`, backticks or
+/// backslashes in a document whose delimiter is unquoted, since a shell would expand them.
+pub fn split_heredoc(command: &str) -> Option<Heredoc> {
+    let (first, rest) = command.trim().split_once('\n')?;
+    let (line, redirect) = first.split_once("<<")?;
+    if line.trim().is_empty() || line.contains(['|', '&', ';', '<', '>', '$', '`', '(', ')']) {
+        return None;
+    }
+    let (strip_tabs, redirect) = match redirect.strip_prefix('-') {
+        Some(redirect) => (true, redirect),
+        None => (false, redirect),
+    };
+    let redirect = redirect.trim();
+    let (delimiter, quoted) = match redirect.chars().next()? {
+        quote @ ('\'' | '"') => (redirect[1..].strip_suffix(quote)?, true),
+        '\\' => (&redirect[1..], true),
+        _ => (redirect, false),
+    };
+    if delimiter.is_empty()
+        || delimiter.contains(|c: char| c.is_whitespace() || "'\"\\<>|&;".contains(c))
+    {
+        return None;
+    }
+
+    let mut body = String::new();
+    let mut lines = rest.lines();
+    loop {
+        let text = lines.next()?;
+        let text = if strip_tabs {
+            text.trim_start_matches('\t')
+        } else {
+            text
+        };
+        if text == delimiter {
+            break;
+        }
+        body.push_str(text);
+        body.push('\n');
+    }
+    let expands = !quoted && body.contains(['$', '`', '\\']);
+    if expands || lines.any(|text| !text.trim().is_empty()) {
+        return None;
+    }
+    Some(Heredoc {
+        command: line.trim_end().to_string(),
+        body,
+    })
+}
+
+/// Variables `command` sets for the commands after it: those named by `export`, and
+/// those assigned by a statement of assignments alone. `NAME=value cmd` only sets `NAME`
+/// for `cmd`, so it does not count.
+pub fn exported_variables(command: &str) -> Vec<String> {
+    let is_name = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let assigned = |word: &str| {
+        word.split_once('=')
+            .map(|(name, _)| name)
+            .filter(|name| is_name(name))
+            .map(str::to_string)
+    };
+    let mut names = Vec::new();
+    for line in command.lines() {
+        let tokens = tokenize_command(line);
+        for statement in tokens.split(|token| matches!(token, CommandToken::Operator(_))) {
+            let words: Vec<&str> = statement
+                .iter()
+                .filter_map(|token| match token {
+                    CommandToken::Word(word) => Some(word.as_str()),
+                    CommandToken::Operator(_) => None,
+                })
+                .collect();
+            let found: Vec<String> = match words.split_first() {
+                Some((&"export", rest)) => rest
+                    .iter()
+                    .filter(|word| !word.starts_with('-'))
+                    .filter_map(|word| {
+                        assigned(word).or_else(|| is_name(word).then(|| word.to_string()))
+                    })
+                    .collect(),
+                Some(_) if words.iter().all(|word| assigned(word).is_some()) => {
+                    words.iter().filter_map(|word| assigned(word)).collect()
+                }
+                _ => Vec::new(),
+            };
+            for name in found {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+/// The program each command of `command` runs: the first word after the start and
+/// after every control operator, skipping `NAME=value` assignments and wrappers such as
+/// `sudo` along with their options.
+pub fn command_programs(command: &str) -> Vec<String> {
+    let mut programs = Vec::new();
+    let mut expecting = true;
+    let mut after_wrapper = false;
+    for token in tokenize_command(command) {
+        match token {
+            CommandToken::Operator(_) => {
+                expecting = true;
+                after_wrapper = false;
+            }
+            CommandToken::Word(word) if expecting => {
+                let is_assignment = word.split_once('=').is_some_and(|(name, _)| {
+                    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+                });
+                if is_assignment || (after_wrapper && word.starts_with('-')) {
+                    continue;
+                }
+                after_wrapper = COMMAND_WRAPPERS.contains(&word.as_str());
+                expecting = after_wrapper;
+                programs.push(word);
+            }
+            CommandToken::Word(_) => {}
+        }
+    }
+    programs
+}
+
+/// Programs `command` runs that are neither shell builtins, nor in `active_tools`, nor
+/// on `$PATH`. Paths such as `./gradlew` and words built from variables are not checked.
+pub fn missing_programs(command: &str, active_tools: &[String]) -> Vec<String> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<_> = env::split_paths(&path).collect();
+    let mut missing = Vec::new();
+    for program in command_programs(command) {
+        let unchecked = SHELL_BUILTINS.contains(&program.as_str())
+            || program.contains(['/', '$', '`', '*'])
+            || active_tools.contains(&program)
+            || missing.contains(&program);
+        if !unchecked && !dirs.iter().any(|dir| is_executable(&dir.join(&program))) {
+            missing.push(program);
+        }
+    }
+    missing
+}
+
+/// Cache key for a detection result: changes whenever `$PATH` or the candidate list does.
+pub fn tool_cache_key(candidates: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env::var_os("PATH").unwrap_or_default().hash(&mut hasher);
+    candidates.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,103 @@
+//! Checks on the plans planners return, before a conversation takes them on. Harmless
+//! flaws are fixed: whitespace is collapsed, a step repeated right after itself is merged
+//! into it and plans are cut to `PlanningOptions::max_steps`. Plans without steps, with
+//! steps without a description or with broken dependencies are rejected.
+
+use crate::{dependencies, PlanError, PlanningOptions, StepId, WorkflowPlan};
+use std::collections::HashSet;
+
+/// Fix what can be fixed in `plan` and describe each fix, or fail with every problem left.
+pub fn validate_plan(
+    plan: &mut WorkflowPlan,
+    opts: &PlanningOptions,
+) -> Result<Vec<String>, PlanError> {
+    let mut fixes = Vec::new();
+    for (i, step) in plan.steps.iter_mut().enumerate() {
+        let normalized = step
+            .description
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if normalized != step.description {
+            fixes.push(format!("Normalized the whitespace of step {}", i + 1));
+            step.description = normalized;
+        }
+        if step
+            .estimated_minutes
+            .is_some_and(|minutes| !minutes.is_finite() || minutes <= 0.0)
+        {
+            fixes.push(format!("Dropped the estimate of step {}", i + 1));
+            step.estimated_minutes = None;
+        }
+    }
+
+    let mut problems: Vec<String> = plan
+        .steps
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| step.description.is_empty())
+        .map(|(i, _)| format!("Step {} has no description", i + 1))
+        .collect();
+    if plan.steps.is_empty() {
+        problems.push("The plan has no steps".to_string());
+    }
+    if !problems.is_empty() {
+        return Err(PlanError::InvalidPlan(problems.join("; ")));
+    }
+
+    let mut i = 1;
+    while i < plan.steps.len() {
+        if plan.steps[i]
+            .description
+            .eq_ignore_ascii_case(&plan.steps[i - 1].description)
+        {
+            let merged = plan.steps.remove(i);
+            let kept = plan.steps[i - 1].id.clone();
+            if let Some(minutes) = merged.estimated_minutes {
+                *plan.steps[i - 1].estimated_minutes.get_or_insert(0.0) += minutes;
+            }
+            fixes.push(format!(
+                "Merged the repeated step \"{}\"",
+                merged.description
+            ));
+            redirect_dependencies(plan, &[merged.id], Some(&kept));
+        } else {
+            i += 1;
+        }
+    }
+
+    let max_steps = opts.max_steps.max(1);
+    if plan.steps.len() > max_steps {
+        fixes.push(format!(
+            "Cut the plan from {} to {} steps",
+            plan.steps.len(),
+            max_steps
+        ));
+        let dropped: Vec<StepId> = plan.steps.drain(max_steps..).map(|step| step.id).collect();
+        redirect_dependencies(plan, &dropped, None);
+    }
+
+    dependencies::check(&plan.steps).map_err(PlanError::InvalidDependencies)?;
+    Ok(fixes)
+}
+
+/// Make steps that depend on one of `from` depend on `to` instead, or on nothing in its
+/// place without `to`.
+fn redirect_dependencies(plan: &mut WorkflowPlan, from: &[StepId], to: Option<&StepId>) {
+    for step in &mut plan.steps {
+        let Some(ids) = &mut step.depends_on else {
+            continue;
+        };
+        let mut seen = HashSet::new();
+        *ids = ids
+            .iter()
+            .filter_map(|id| match to {
+                _ if !from.contains(id) => Some(id.clone()),
+                Some(to) => Some(to.clone()),
+                None => None,
+            })
+            // A step merged into its twin would depend on itself
+            .filter(|id| *id != step.id && seen.insert(id.clone()))
+            .collect();
+    }
+}
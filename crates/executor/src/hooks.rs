@@ -0,0 +1,250 @@
+//! Callbacks around command execution, for audit logging and environment setup.
+
+use parsec_core::logging::loggable;
+use parsec_core::{DirectCommandExecution, ExecutionInput};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// What started a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Direct,
+    Step,
+    Background,
+}
+
+impl CommandKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandKind::Direct => "direct",
+            CommandKind::Step => "step",
+            CommandKind::Background => "background",
+        }
+    }
+}
+
+/// A command about to run, as the pre-exec hooks see it.
+#[derive(Debug, Clone)]
+pub struct PreparedCommand {
+    pub command: String,
+    pub kind: CommandKind,
+    pub program: String,
+    pub args: Vec<String>,
+    pub working_dir: PathBuf,
+    /// Variables set on top of parsec's own environment by earlier hooks.
+    pub env: BTreeMap<String, String>,
+    pub input: ExecutionInput,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookDecision {
+    Allow,
+    Deny(String),
+    /// Run with these variables added and, if set, in another directory.
+    Modify {
+        env: BTreeMap<String, String>,
+        working_dir: Option<PathBuf>,
+    },
+}
+
+pub(crate) type PreExecHook =
+    Arc<dyn Fn(&PreparedCommand) -> anyhow::Result<HookDecision> + Send + Sync>;
+
+pub(crate) type PostExecHook = Arc<dyn Fn(&DirectCommandExecution) + Send + Sync>;
+
+/// Run `hooks` in order, each seeing the changes of the ones before it. The first denial
+/// wins; a hook that fails or panics denies the command too.
+pub(crate) fn run_pre_exec(
+    hooks: &[PreExecHook],
+    mut prepared: PreparedCommand,
+) -> Result<PreparedCommand, String> {
+    for hook in hooks {
+        match panic::catch_unwind(AssertUnwindSafe(|| hook(&prepared))) {
+            Ok(Ok(HookDecision::Allow)) => {}
+            Ok(Ok(HookDecision::Deny(reason))) => return Err(reason),
+            Ok(Ok(HookDecision::Modify { env, working_dir })) => {
+                prepared.env.extend(env);
+                if let Some(working_dir) = working_dir {
+                    prepared.working_dir = working_dir;
+                }
+            }
+            Ok(Err(e)) => return Err(format!("pre-exec hook failed: {}", e)),
+            Err(payload) => {
+                return Err(format!(
+                    "pre-exec hook panicked: {}",
+                    panic_message(payload.as_ref())
+                ))
+            }
+        }
+    }
+    Ok(prepared)
+}
+
+/// Run `hooks` on a finished execution; failures are logged and otherwise ignored.
+pub(crate) fn run_post_exec(hooks: &[PostExecHook], execution: &DirectCommandExecution) {
+    for hook in hooks {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| hook(execution))) {
+            warn!(
+                "Post-exec hook panicked on {}: {}",
+                loggable(&execution.command),
+                panic_message(payload.as_ref())
+            );
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// A post-exec hook appending one JSON line per execution to `path`: the command, where
+/// and when it ran and how it ended. Output is left out.
+pub fn audit_log(path: PathBuf) -> impl Fn(&DirectCommandExecution) + Send + Sync + 'static {
+    move |execution| {
+        if let Err(e) = append_audit_record(&path, execution) {
+            warn!("Failed to write audit log {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn append_audit_record(path: &Path, execution: &DirectCommandExecution) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(&serde_json::json!({
+        "command": execution.command,
+        "working_directory": execution.working_directory,
+        "executed_at": execution.executed_at,
+        "completed_at": execution.completed_at,
+        "duration_ms": execution.duration.as_millis() as u64,
+        "exit_status": execution.exit_status,
+        "termination": execution.termination.map(|termination| termination.to_string()),
+    }))?;
+    line.push('\n');
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // One write per record, so concurrent parsec processes do not interleave lines
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SafeExecutor;
+    use parsec_core::{ExecutionError, ExecutionOverrides, GeneratedCommand};
+    use std::sync::Mutex;
+
+    fn step(command: &str) -> GeneratedCommand {
+        GeneratedCommand {
+            command: command.to_string(),
+            explanation: String::new(),
+            risk_score: None,
+            expected_duration: None,
+            missing_tools: Vec::new(),
+            kind: Default::default(),
+            stdin: None,
+            interactive: false,
+            undo_command: None,
+        }
+    }
+
+    #[test]
+    fn denied_and_panicking_hooks_block_step_commands() {
+        let dir = std::env::temp_dir();
+        let denying = SafeExecutor::new().with_pre_exec_hook(|prepared| {
+            Ok(match prepared.program.as_str() {
+                "rm" => HookDecision::Deny("no deletions".to_string()),
+                _ => HookDecision::Allow,
+            })
+        });
+        let panicking = SafeExecutor::new().with_pre_exec_hook(|_| panic!("hook bug"));
+
+        let denied = denying
+            .execute_step_command(&step("rm -f x"), &dir, &ExecutionOverrides::default())
+            .unwrap();
+        let allowed = denying
+            .execute_step_command(&step("true"), &dir, &ExecutionOverrides::default())
+            .unwrap();
+        let panicked = panicking
+            .execute_step_command(&step("true"), &dir, &ExecutionOverrides::default())
+            .unwrap();
+
+        assert!(!denied.executed && !denied.approved);
+        assert!(
+            matches!(&denied.error, Some(ExecutionError::Denied(reason)) if reason == "no deletions")
+        );
+        assert!(allowed.executed && allowed.error.is_none());
+        assert!(!panicked.executed);
+        assert!(
+            matches!(&panicked.error, Some(ExecutionError::Denied(reason)) if reason == "pre-exec hook panicked: hook bug")
+        );
+    }
+
+    #[test]
+    fn hooks_set_the_environment_and_see_failed_runs() {
+        let dir = std::env::temp_dir().join(format!("parsec-hooks-{}", std::process::id()));
+        let audit = dir.join("audit.jsonl");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let executor = SafeExecutor::new()
+            .with_pre_exec_hook(|_| {
+                Ok(HookDecision::Modify {
+                    env: [("PARSEC_HOOK_TEST".to_string(), "from-hook".to_string())].into(),
+                    working_dir: None,
+                })
+            })
+            .with_post_exec_hook({
+                let seen = seen.clone();
+                move |execution| {
+                    seen.lock()
+                        .unwrap()
+                        .push((execution.command.clone(), execution.exit_status))
+                }
+            })
+            .with_post_exec_hook(audit_log(audit.clone()));
+
+        let printed = executor
+            .execute_direct_command(
+                "printenv PARSEC_HOOK_TEST",
+                &std::env::temp_dir(),
+                ExecutionInput::Closed,
+            )
+            .unwrap();
+        let missing = executor.execute_direct_command(
+            "parsec-no-such-program",
+            &std::env::temp_dir(),
+            ExecutionInput::Closed,
+        );
+
+        assert_eq!(printed.stdout.content, "from-hook\n");
+        assert!(matches!(missing, Err(ExecutionError::CommandNotFound(_))));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                ("printenv PARSEC_HOOK_TEST".to_string(), 0),
+                ("parsec-no-such-program".to_string(), -1)
+            ]
+        );
+        let log = std::fs::read_to_string(&audit).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["command"], "printenv PARSEC_HOOK_TEST");
+        assert_eq!(records[0]["termination"], "exited with status 0");
+        assert_eq!(records[1]["exit_status"], -1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
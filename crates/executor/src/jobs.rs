@@ -0,0 +1,388 @@
+use crate::ScriptFile;
+use chrono::{DateTime, Utc};
+use parsec_core::logging::loggable;
+use parsec_core::secrets::SecretRedactor;
+use parsec_core::{CommandTermination, ExecutionError, TruncatedText};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Child;
+use tracing::{debug, warn};
+
+/// Small number the user refers to a job by, as in `tail 2`.
+pub type JobId = u32;
+
+/// How often `wait` checks whether a job has exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long `kill` waits for a killed job to be reaped.
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+/// How a killed job ended when it could not be reaped in time: SIGKILL is what
+/// `start_kill` sends on Unix.
+const KILLED: CommandTermination = CommandTermination::Signaled {
+    signal: 9,
+    core_dumped: false,
+};
+
+/// Bytes read from the end of a spool file for `tail`.
+const TAIL_WINDOW: u64 = 64 * 1024;
+
+/// A command started by `SafeExecutor::spawn_background`, writing its output to spool files.
+pub struct JobHandle {
+    pub command: String,
+    pub working_dir: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub stdout_path: PathBuf,
+    pub stderr_path: PathBuf,
+    /// The sandbox backend confining the job, if any.
+    pub sandbox: Option<String>,
+    pub(crate) child: Child,
+    pub(crate) started: Instant,
+    /// The script the job runs, removed once it finishes.
+    pub(crate) script: Option<ScriptFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Exited(i32),
+    /// Killed by `kill`, on exit, or by a signal from outside parsec.
+    Killed,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "running"),
+            JobState::Exited(code) => write!(f, "exit {}", code),
+            JobState::Killed => write!(f, "killed"),
+        }
+    }
+}
+
+/// What a finished job produced, in the shape of a foreground run.
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    pub termination: CommandTermination,
+    pub stdout: TruncatedText,
+    pub stderr: TruncatedText,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub completed_at: DateTime<Utc>,
+    pub sandbox: Option<String>,
+}
+
+/// One row of the `jobs` listing.
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    pub state: JobState,
+    /// Run time so far, or in total once the job finished.
+    pub duration: Duration,
+}
+
+struct Job {
+    handle: JobHandle,
+    state: JobState,
+    outcome: Option<JobOutcome>,
+    /// Returned by `take_finished` already.
+    reported: bool,
+}
+
+impl Job {
+    fn info(&self, id: JobId) -> JobInfo {
+        JobInfo {
+            id,
+            command: self.handle.command.clone(),
+            started_at: self.handle.started_at,
+            state: self.state,
+            duration: self
+                .outcome
+                .as_ref()
+                .map_or_else(|| self.handle.started.elapsed(), |outcome| outcome.duration),
+        }
+    }
+
+    /// Check whether a running job has exited and collect its output if so.
+    fn poll(&mut self, output: &OutputOptions) {
+        if self.state != JobState::Running {
+            return;
+        }
+        match self.handle.child.try_wait() {
+            Ok(Some(status)) => self.finish(crate::termination_of(status), output),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to check job {}: {}",
+                loggable(&self.handle.command),
+                e
+            ),
+        }
+    }
+
+    /// Fold the spool files into the outcome and remove them.
+    fn finish(&mut self, termination: CommandTermination, output: &OutputOptions) {
+        self.state = match termination {
+            CommandTermination::Exited(code) => JobState::Exited(code),
+            _ => JobState::Killed,
+        };
+        self.outcome = Some(JobOutcome {
+            termination,
+            stdout: read_spool(&self.handle.stdout_path, output),
+            stderr: read_spool(&self.handle.stderr_path, output),
+            started_at: self.handle.started_at,
+            duration: self.handle.started.elapsed(),
+            completed_at: Utc::now(),
+            sandbox: self.handle.sandbox.clone(),
+        });
+        for path in [&self.handle.stdout_path, &self.handle.stderr_path] {
+            if let Err(e) = fs::remove_file(path) {
+                debug!("Could not remove spool file {}: {}", path.display(), e);
+            }
+        }
+        self.handle.script = None;
+    }
+}
+
+/// A spool file's output, capped at `output.max_size` bytes from its start and end.
+fn read_spool(path: &Path, output: &OutputOptions) -> TruncatedText {
+    let raw = match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(e) => {
+            warn!("Failed to read job output {}: {}", path.display(), e);
+            String::new()
+        }
+    };
+    let clean = if output.sanitize {
+        crate::sanitize::sanitize(&raw)
+    } else {
+        raw
+    };
+    let clean = match &output.secret_redactor {
+        Some(redactor) => redactor.redact(&clean),
+        None => clean,
+    };
+    crate::truncate_output(clean, output.max_size)
+}
+
+/// The last `lines` lines written to a spool file so far.
+fn tail_spool(path: &Path, lines: usize) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(TAIL_WINDOW)))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let all: Vec<&str> = text.lines().collect();
+    Ok(all[all.len().saturating_sub(lines)..].join("\n"))
+}
+
+struct Jobs {
+    next_id: JobId,
+    jobs: BTreeMap<JobId, Job>,
+}
+
+/// How a finished job's spooled output is folded into its `JobOutcome`.
+#[derive(Debug, Clone)]
+struct OutputOptions {
+    max_size: usize,
+    sanitize: bool,
+    secret_redactor: Option<SecretRedactor>,
+}
+
+/// Background jobs started by this parsec process, numbered from 1.
+pub struct JobRegistry {
+    output: OutputOptions,
+    inner: Mutex<Jobs>,
+}
+
+impl JobRegistry {
+    pub fn new(max_output_size: usize) -> Self {
+        Self {
+            output: OutputOptions {
+                max_size: max_output_size,
+                sanitize: true,
+                secret_redactor: Some(SecretRedactor::new()),
+            },
+            inner: Mutex::new(Jobs {
+                next_id: 1,
+                jobs: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Strip escape sequences and progress-bar rewrites from the collected output.
+    pub fn with_output_sanitizing(mut self, sanitize: bool) -> Self {
+        self.output.sanitize = sanitize;
+        self
+    }
+
+    /// Redact secrets from the collected output with `redactor`, or not at all with `None`.
+    pub fn with_secret_redaction(mut self, redactor: Option<SecretRedactor>) -> Self {
+        self.output.secret_redactor = redactor;
+        self
+    }
+
+    pub fn register(&self, handle: JobHandle) -> JobId {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.jobs.insert(
+            id,
+            Job {
+                handle,
+                state: JobState::Running,
+                outcome: None,
+                reported: false,
+            },
+        );
+        id
+    }
+
+    pub fn contains(&self, id: JobId) -> bool {
+        self.inner.lock().unwrap().jobs.contains_key(&id)
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .jobs
+            .iter_mut()
+            .map(|(id, job)| {
+                job.poll(&self.output);
+                job.info(*id)
+            })
+            .collect()
+    }
+
+    pub fn running(&self) -> usize {
+        self.list()
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .count()
+    }
+
+    /// Jobs that finished since the last call, each returned once.
+    pub fn take_finished(&self) -> Vec<(JobInfo, JobOutcome)> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut finished = Vec::new();
+        for (id, job) in inner.jobs.iter_mut() {
+            job.poll(&self.output);
+            if let (Some(outcome), false) = (&job.outcome, job.reported) {
+                finished.push((job.info(*id), outcome.clone()));
+                job.reported = true;
+            }
+        }
+        finished
+    }
+
+    /// The last `lines` lines of a running job's stdout and stderr, or of a finished
+    /// job's collected output.
+    pub fn tail(&self, id: JobId, lines: usize) -> Result<(String, String), ExecutionError> {
+        let mut inner = self.inner.lock().unwrap();
+        let job = inner.jobs.get_mut(&id).ok_or_else(|| no_such_job(id))?;
+        job.poll(&self.output);
+        if let Some(outcome) = &job.outcome {
+            let last = |text: &TruncatedText| {
+                let all: Vec<&str> = text.content.lines().collect();
+                all[all.len().saturating_sub(lines)..].join("\n")
+            };
+            return Ok((last(&outcome.stdout), last(&outcome.stderr)));
+        }
+        let read = |path: &Path| {
+            tail_spool(path, lines).map_err(|e| {
+                ExecutionError::ExecutionFailed(format!(
+                    "Failed to read job output {}: {}",
+                    path.display(),
+                    e
+                ))
+            })
+        };
+        Ok((
+            read(&job.handle.stdout_path)?,
+            read(&job.handle.stderr_path)?,
+        ))
+    }
+
+    /// Wait for a job to finish.
+    pub async fn wait(&self, id: JobId) -> Result<JobOutcome, ExecutionError> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                let job = inner.jobs.get_mut(&id).ok_or_else(|| no_such_job(id))?;
+                job.poll(&self.output);
+                if let Some(outcome) = &job.outcome {
+                    return Ok(outcome.clone());
+                }
+            }
+            tokio::time::sleep(WAIT_INTERVAL).await;
+        }
+    }
+
+    /// Kill a running job. Killing a finished job does nothing.
+    pub fn kill(&self, id: JobId) -> Result<(), ExecutionError> {
+        let mut inner = self.inner.lock().unwrap();
+        let job = inner.jobs.get_mut(&id).ok_or_else(|| no_such_job(id))?;
+        self.kill_job(job);
+        Ok(())
+    }
+
+    fn kill_job(&self, job: &mut Job) {
+        job.poll(&self.output);
+        if job.state != JobState::Running {
+            return;
+        }
+        if let Err(e) = job.handle.child.start_kill() {
+            warn!(
+                "Failed to kill job {}: {}",
+                loggable(&job.handle.command),
+                e
+            );
+            return;
+        }
+        let deadline = Instant::now() + KILL_GRACE;
+        let mut termination = KILLED;
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = job.handle.child.try_wait() {
+                termination = crate::termination_of(status);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        job.finish(termination, &self.output);
+    }
+
+    /// On parsec exit: kill the running jobs, or leave them running with their output
+    /// still going to the spool files. Returns the jobs that were still running.
+    pub fn shutdown(&self, kill: bool) -> Vec<JobInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut running = Vec::new();
+        for (id, job) in inner.jobs.iter_mut() {
+            job.poll(&self.output);
+            if job.state != JobState::Running {
+                continue;
+            }
+            running.push(job.info(*id));
+            if kill {
+                self.kill_job(job);
+            } else {
+                debug!(
+                    "Leaving job {} running; output in {}",
+                    id,
+                    job.handle.stdout_path.display()
+                );
+            }
+        }
+        running
+    }
+}
+
+fn no_such_job(id: JobId) -> ExecutionError {
+    ExecutionError::ExecutionFailed(format!("No job {}", id))
+}
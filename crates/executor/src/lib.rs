@@ -1,12 +1,471 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use parsec_core::command_rules::{Finding, Severity, ValidationReport};
+use parsec_core::logging::content_hash;
+use parsec_core::metrics::{self, Metrics};
+use parsec_core::preflight::PreflightFinding;
+use parsec_core::secrets::SecretRedactor;
 use parsec_core::*;
-use std::path::Path;
-use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, field, instrument, warn, Span};
+
+pub mod hooks;
+pub mod jobs;
+pub mod preflight;
+#[cfg(unix)]
+mod pty;
+pub mod sandbox;
+pub mod sanitize;
+pub mod snapshot;
+
+use hooks::{CommandKind, HookDecision, PostExecHook, PreExecHook, PreparedCommand};
+use jobs::JobHandle;
+use preflight::PreflightOptions;
+use sandbox::SandboxBackend;
+
+/// How often a running command is checked for exit or timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait for a command's output once it has exited or been killed.
+const PIPE_GRACE: Duration = Duration::from_secs(1);
+
+/// Cap captured output at `max_size` bytes, keeping a quarter from the start and the
+/// rest from the end, where errors and summaries are.
+pub(crate) fn truncate_output(output: String, max_size: usize) -> TruncatedText {
+    let head = max_size / 4;
+    TruncatedText::new_head_tail(output, head, max_size - head)
+}
+
+/// Commands with a higher risk score are never run unless dangerous commands are allowed,
+/// unless `with_block_threshold` says otherwise.
+pub const DEFAULT_BLOCK_THRESHOLD: f32 = 0.8;
+
+/// A pattern `validate_command` looks for, matched case-insensitively.
+struct Rule {
+    name: &'static str,
+    pattern: &'static str,
+    /// The pattern must be followed by the end of a word, so `rm -rf /` does not match
+    /// `rm -rf /tmp/build`.
+    whole_word: bool,
+    severity: Severity,
+    message: &'static str,
+}
+
+impl Rule {
+    /// The span of the first match in the lowercased command `lower`.
+    fn find(&self, lower: &str) -> Option<Range<usize>> {
+        let boundary = |c: Option<char>, also: &str| {
+            c.is_none_or(|c| c.is_whitespace() || ";&|()".contains(c) || also.contains(c))
+        };
+        lower
+            .match_indices(self.pattern)
+            .find_map(|(start, matched)| {
+                let end = start + matched.len();
+                // `/sbin/shutdown` runs shutdown; `rm -rf /*` removes everything under the root
+                let starts_word = !self.pattern.starts_with(char::is_alphanumeric)
+                    || boundary(lower[..start].chars().next_back(), "/");
+                let ends_word = !self.whole_word || boundary(lower[end..].chars().next(), "*");
+                (starts_word && ends_word).then_some(start..end)
+            })
+    }
+}
+
+/// What `validate_command` checks commands for.
+const RULES: &[Rule] = &[
+    Rule {
+        name: "rm-root",
+        pattern: "rm -rf /",
+        whole_word: true,
+        severity: Severity::Block,
+        message: "Removes the root directory",
+    },
+    Rule {
+        name: "fork-bomb",
+        pattern: ":(){ :|:& };:",
+        whole_word: false,
+        severity: Severity::Block,
+        message: "Fork bomb",
+    },
+    Rule {
+        name: "mkfs",
+        pattern: "mkfs",
+        whole_word: false,
+        severity: Severity::Block,
+        message: "Formats a filesystem",
+    },
+    Rule {
+        name: "dd-zero",
+        pattern: "dd if=/dev/zero",
+        whole_word: false,
+        severity: Severity::Block,
+        message: "Overwrites its output with zeros",
+    },
+    Rule {
+        name: "shutdown",
+        pattern: "shutdown",
+        whole_word: true,
+        severity: Severity::Warn,
+        message: "Shuts the machine down",
+    },
+    Rule {
+        name: "reboot",
+        pattern: "reboot",
+        whole_word: true,
+        severity: Severity::Warn,
+        message: "Reboots the machine",
+    },
+];
+
+/// Whether `rule` names a `Warn` rule, which `with_allowed_rules` can let the user override.
+pub fn is_overridable(rule: &str) -> bool {
+    RULES
+        .iter()
+        .any(|known| known.name == rule && known.severity == Severity::Warn)
+}
+
+/// Programs that take over the terminal whenever they run.
+const FULL_SCREEN_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "less", "more", "man", "top", "htop", "tmux", "screen",
+];
+
+/// A program that prompts on the terminal unless it is given input or told what to do.
+struct PromptingProgram {
+    /// The program and the subcommand words that make it prompt, such as `gh auth login`.
+    words: &'static [&'static str],
+    /// Arguments, or prefixes of them, that make it run without prompting.
+    batch_args: &'static [&'static str],
+    /// Whether any plain argument, such as a script to run, does too.
+    runs_files: bool,
+}
+
+/// What `needs_terminal` looks for besides `FULL_SCREEN_PROGRAMS`.
+const PROMPTING_PROGRAMS: &[PromptingProgram] = &[
+    PromptingProgram {
+        words: &["python"],
+        batch_args: &["-c", "-m", "--version"],
+        runs_files: true,
+    },
+    PromptingProgram {
+        words: &["python3"],
+        batch_args: &["-c", "-m", "--version"],
+        runs_files: true,
+    },
+    PromptingProgram {
+        words: &["node"],
+        batch_args: &["-e", "-p", "--eval", "--print", "--version"],
+        runs_files: true,
+    },
+    PromptingProgram {
+        words: &["irb"],
+        batch_args: &["--version"],
+        runs_files: true,
+    },
+    PromptingProgram {
+        words: &["psql"],
+        batch_args: &[
+            "-c",
+            "-f",
+            "-l",
+            "--command",
+            "--file",
+            "--list",
+            "--version",
+        ],
+        runs_files: false,
+    },
+    PromptingProgram {
+        words: &["mysql"],
+        batch_args: &["-e", "--execute", "--version"],
+        runs_files: false,
+    },
+    PromptingProgram {
+        words: &["gh", "auth", "login"],
+        batch_args: &["--with-token"],
+        runs_files: false,
+    },
+];
+
+/// Why running `words` with `input` would wait on a terminal, if it would.
+fn terminal_reason(words: &[String], input: &ExecutionInput) -> Option<String> {
+    // Wrappers run the command after them on the same terminal
+    let words: Vec<&str> = words
+        .iter()
+        .map(String::as_str)
+        .skip_while(|word| {
+            matches!(*word, "sudo" | "env" | "nice" | "nohup" | "time") || word.starts_with('-')
+        })
+        .collect();
+    let (program, args) = words.split_first()?;
+    let program = Path::new(program).file_name()?.to_str()?;
+    if FULL_SCREEN_PROGRAMS.contains(&program) {
+        return Some(format!("`{}` takes over the terminal", program));
+    }
+    if *input != ExecutionInput::Closed {
+        return None;
+    }
+    let prompting = PROMPTING_PROGRAMS.iter().find(|prompting| {
+        prompting.words[0] == program && args.starts_with(&prompting.words[1..])
+    })?;
+    let args = &args[prompting.words.len() - 1..];
+    let batch = args.iter().any(|arg| {
+        prompting
+            .batch_args
+            .iter()
+            .any(|batch_arg| arg.starts_with(batch_arg))
+            || (prompting.runs_files && !arg.starts_with('-'))
+    });
+    (!batch).then(|| {
+        format!(
+            "`{}` waits for input on a terminal when given none",
+            prompting.words.join(" ")
+        )
+    })
+}
+
+/// Give `text` to a command on `pipe` from its own thread, so a command that does not read
+/// it all cannot block parsec.
+fn write_input(mut pipe: impl Write + Send + 'static, text: String) {
+    std::thread::spawn(move || {
+        if let Err(e) = pipe.write_all(text.as_bytes()) {
+            debug!("Command stopped reading its input: {}", e);
+        }
+    });
+}
+
+/// What to run for `command` and what it reads. A command line reading a here-document
+/// runs without a shell with the document as its input, unless `input` already gives it
+/// some; other multi-line commands run as scripts.
+fn resolve_input(
+    command: &str,
+    script: bool,
+    input: ExecutionInput,
+) -> (String, bool, ExecutionInput) {
+    if script && input == ExecutionInput::Closed {
+        if let Some(heredoc) = tools::split_heredoc(command) {
+            return (heredoc.command, false, ExecutionInput::Text(heredoc.body));
+        }
+    }
+    (command.to_string(), script, input)
+}
+
+/// The variables a script wrote to `dump` that differ from what it started with: `env`,
+/// then parsec's own environment.
+fn exported_changes(
+    dump: &Path,
+    env: &BTreeMap<String, String>,
+    changed_at: DateTime<Utc>,
+) -> Vec<EnvironmentChange> {
+    let bytes = match std::fs::read(dump) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            debug!("Could not read exported variables: {}", e);
+            return Vec::new();
+        }
+    };
+    String::from_utf8_lossy(&bytes)
+        .split('\0')
+        .filter_map(|entry| entry.split_once('='))
+        .filter_map(|(name, value)| {
+            let old_value = env.get(name).cloned().or_else(|| std::env::var(name).ok());
+            (old_value.as_deref() != Some(value)).then(|| EnvironmentChange {
+                variable_name: name.to_string(),
+                old_value,
+                new_value: value.to_string(),
+                changed_at,
+            })
+        })
+        .collect()
+}
+
+/// A command to put through the pre-exec hooks.
+struct Invocation<'a> {
+    command: &'a str,
+    script: bool,
+    input: ExecutionInput,
+    /// Variables to set on top of parsec's own environment.
+    env: BTreeMap<String, String>,
+    /// Where the script writes the variables it exported, making it run as a script.
+    env_dump: Option<(&'a Path, Vec<String>)>,
+    working_dir: &'a Path,
+    kind: CommandKind,
+    /// Run it in the executor's sandbox, if there is one.
+    sandboxed: bool,
+}
+
+/// Numbers spool files so concurrent jobs never share one.
+static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+/// Create `dir` and any missing parents so that only their owner can list or enter them.
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+/// Create `path` readable by its owner only, refusing to reuse a file that is already there.
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// A script written out to run or edit, or a file a script writes to, readable by its
+/// owner only and removed when dropped.
+#[derive(Debug)]
+pub struct ScriptFile(PathBuf);
+
+impl ScriptFile {
+    pub fn write(dir: &Path, script: &str) -> Result<Self, ExecutionError> {
+        Self::create(dir, "script", format!("{}\n", script.trim()).as_bytes())
+    }
+
+    fn create(dir: &Path, extension: &str, contents: &[u8]) -> Result<Self, ExecutionError> {
+        let path = dir.join(format!(
+            "{}-{}.{}",
+            std::process::id(),
+            NEXT_SPOOL.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        let write = || -> std::io::Result<()> {
+            create_private_dir(dir)?;
+            create_private_file(&path)?.write_all(contents)
+        };
+        write().map_err(|e| {
+            ExecutionError::ExecutionFailed(format!(
+                "Failed to write script {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            debug!("Could not remove script {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// What one run of a command produced.
+struct RunOutput {
+    termination: CommandTermination,
+    stdout: TruncatedText,
+    stderr: TruncatedText,
+    duration: Duration,
+}
+
+impl RunOutput {
+    /// The exit code, -1 for a signal; `None` when the command was killed at its timeout.
+    fn exit_status(&self) -> Option<i32> {
+        match self.termination {
+            CommandTermination::Exited(code) => Some(code),
+            CommandTermination::Signaled { .. } => Some(-1),
+            CommandTermination::TimedOut => None,
+        }
+    }
+}
+
+/// How a process that exited on its own ended.
+pub fn termination_of(status: ExitStatus) -> CommandTermination {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return CommandTermination::Signaled {
+                signal,
+                core_dumped: status.core_dumped(),
+            };
+        }
+    }
+    CommandTermination::Exited(status.code().unwrap_or(-1))
+}
+
+/// Program and arguments of `command`, with shell quoting removed, for a command line
+/// that does not need a shell.
+fn split_command(command: &str) -> Result<(String, Vec<String>), ExecutionError> {
+    let mut words = tools::command_words(command).into_iter();
+    let program = words
+        .next()
+        .ok_or_else(|| ExecutionError::CommandNotFound("Empty command".to_string()))?;
+    Ok((program, words.collect()))
+}
+
+fn spawn_error(program: &str, e: std::io::Error) -> ExecutionError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ExecutionError::CommandNotFound(program.to_string()),
+        std::io::ErrorKind::PermissionDenied => {
+            ExecutionError::PermissionDenied(program.to_string())
+        }
+        _ => ExecutionError::ExecutionFailed(format!("Failed to execute {}: {}", program, e)),
+    }
+}
+
+/// Drain `pipe` on its own thread so a chatty command cannot block on a full pipe.
+fn read_pipe(mut pipe: impl Read + Send + 'static) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        let _ = sender.send(bytes);
+    });
+    receiver
+}
 
 pub struct SafeExecutor {
     max_output_size: usize,
     timeout: Duration,
+    /// Where background jobs spool their output and scripts are written to run; created
+    /// readable by its owner only, under parsec's data directory unless set.
+    job_dir: PathBuf,
+    /// Runs scripts without a shebang line, with `-e`, and command lines using shell syntax,
+    /// with `-c`.
+    shell: String,
+    /// Strip escape sequences and progress-bar rewrites from captured output.
+    sanitize_output: bool,
+    /// Keep the unsanitized output of commands whose output sanitizing changed here.
+    raw_output_dir: Option<PathBuf>,
+    /// Replace credentials in captured output before it leaves the executor.
+    secret_redactor: Option<SecretRedactor>,
+    pre_exec_hooks: Vec<PreExecHook>,
+    post_exec_hooks: Vec<PostExecHook>,
+    /// Commands scored above it are not run unless dangerous commands are allowed.
+    block_threshold: f32,
+    /// Run commands scored above `block_threshold` instead of blocking them.
+    allow_dangerous: bool,
+    /// `Warn` validation rules that never stop a command.
+    allowed_rules: Vec<String>,
+    /// Confines step commands and background jobs; direct commands, which the user typed,
+    /// are never confined.
+    sandbox: Option<Arc<dyn SandboxBackend>>,
+    /// Let confined commands reach the network.
+    sandbox_network: bool,
+    preflight: PreflightOptions,
+    metrics: Arc<dyn Metrics>,
 }
 
 impl Default for SafeExecutor {
@@ -14,6 +473,20 @@ impl Default for SafeExecutor {
         Self {
             max_output_size: 64 * 1024,        // 64KB
             timeout: Duration::from_secs(300), // 5 minutes
+            job_dir: parsec_core::default_data_dir().join("jobs"),
+            shell: "sh".to_string(),
+            sanitize_output: true,
+            raw_output_dir: None,
+            secret_redactor: Some(SecretRedactor::new()),
+            pre_exec_hooks: Vec::new(),
+            post_exec_hooks: Vec::new(),
+            block_threshold: DEFAULT_BLOCK_THRESHOLD,
+            allow_dangerous: false,
+            allowed_rules: Vec::new(),
+            sandbox: None,
+            sandbox_network: false,
+            preflight: PreflightOptions::default(),
+            metrics: metrics::noop(),
         }
     }
 }
@@ -33,161 +506,834 @@ impl SafeExecutor {
         self
     }
 
+    pub fn with_job_dir(mut self, job_dir: PathBuf) -> Self {
+        self.job_dir = job_dir;
+        self
+    }
+
+    pub fn with_shell(mut self, shell: impl Into<String>) -> Self {
+        self.shell = shell.into();
+        self
+    }
+
+    pub fn with_output_sanitizing(mut self, sanitize: bool) -> Self {
+        self.sanitize_output = sanitize;
+        self
+    }
+
+    pub fn with_raw_output_dir(mut self, dir: PathBuf) -> Self {
+        self.raw_output_dir = Some(dir);
+        self
+    }
+
+    /// Redact secrets from captured output with `redactor`, or not at all with `None`.
+    pub fn with_secret_redaction(mut self, redactor: Option<SecretRedactor>) -> Self {
+        self.secret_redactor = redactor;
+        self
+    }
+
+    /// Run commands scored above the block threshold; whoever approves them must have
+    /// had them confirmed.
+    pub fn with_allow_dangerous(mut self, allow: bool) -> Self {
+        self.allow_dangerous = allow;
+        self
+    }
+
+    /// Block step commands scored above `threshold` rather than `DEFAULT_BLOCK_THRESHOLD`.
+    pub fn with_block_threshold(mut self, threshold: f32) -> Self {
+        self.block_threshold = threshold;
+        self
+    }
+
+    /// Confine step commands and background jobs with `sandbox`, which should have passed
+    /// its `check`; `sandbox::named` checks the backends it returns.
+    pub fn with_sandbox(mut self, sandbox: Option<Arc<dyn SandboxBackend>>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Let confined commands reach the network; without it, commands known to need the
+    /// network run only outside the sandbox, with `ExecutionOverrides::unsandboxed`.
+    pub fn with_sandbox_network(mut self, network: bool) -> Self {
+        self.sandbox_network = network;
+        self
+    }
+
+    pub fn with_preflight(mut self, options: PreflightOptions) -> Self {
+        self.preflight = options;
+        self
+    }
+
+    /// Let commands matching the `Warn` validation rules named in `rules` run once the
+    /// user overrides them; commands matching any other `Warn` rule are blocked.
+    pub fn with_allowed_rules(mut self, rules: Vec<String>) -> Self {
+        self.allowed_rules = rules;
+        self
+    }
+
+    /// Ask `hook` before every command runs, background jobs included. Hooks run in the
+    /// order they were added; a denial, error or panic stops the command.
+    pub fn with_pre_exec_hook(
+        mut self,
+        hook: impl Fn(&PreparedCommand) -> anyhow::Result<HookDecision> + Send + Sync + 'static,
+    ) -> Self {
+        self.pre_exec_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` after every direct and step command that passed the pre-exec hooks,
+    /// including ones that failed to start or timed out. Background jobs are not reported.
+    pub fn with_post_exec_hook(
+        mut self,
+        hook: impl Fn(&DirectCommandExecution) + Send + Sync + 'static,
+    ) -> Self {
+        self.post_exec_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Count commands by exit class and how long they ran.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn count(&self, kind: CommandKind, exit_class: &str, duration: Option<Duration>) {
+        let kind = kind.as_str();
+        self.metrics.increment(
+            metrics::EXECUTIONS,
+            &[("kind", kind), ("exit_class", exit_class)],
+            1,
+        );
+        if let Some(duration) = duration {
+            self.metrics.observe(
+                metrics::EXECUTION_DURATION_MS,
+                &[("kind", kind)],
+                duration.as_secs_f64() * 1000.0,
+            );
+        }
+    }
+
+    /// Split `command`, hand it to the shell if it uses shell syntax, or write it out if it
+    /// is a script, put it through the pre-exec hooks and wrap it in the sandbox. The script file, if any, must be kept until the
+    /// command has run.
+    fn prepare(
+        &self,
+        invocation: Invocation<'_>,
+    ) -> Result<(PreparedCommand, Option<ScriptFile>), ExecutionError> {
+        let Invocation {
+            command,
+            script,
+            input,
+            env,
+            env_dump,
+            working_dir,
+            kind,
+            sandboxed,
+        } = invocation;
+        let (line, script, input) = match env_dump {
+            Some(_) => (command.to_string(), true, input),
+            None => resolve_input(command, script, input),
+        };
+        let (script, (program, args)) = if script {
+            let file = ScriptFile::write(&self.job_dir, &line)?;
+            let invocation = self.script_invocation(&line, file.path(), env_dump);
+            (Some(file), invocation)
+        } else if tools::needs_shell(&line) {
+            let args = vec!["-c".to_string(), line.trim().to_string()];
+            (None, (self.shell.clone(), args))
+        } else {
+            (None, split_command(&line)?)
+        };
+        let prepared = PreparedCommand {
+            command: command.to_string(),
+            kind,
+            program,
+            args,
+            working_dir: working_dir.to_path_buf(),
+            env,
+            input,
+        };
+        let mut prepared =
+            hooks::run_pre_exec(&self.pre_exec_hooks, prepared).map_err(|reason| {
+                self.count(kind, "denied", None);
+                ExecutionError::Denied(reason)
+            })?;
+        // Scripts and the variables they export are written under the job directory
+        if let (true, Some(sandbox)) = (sandboxed, &self.sandbox) {
+            let (program, args) = sandbox.wrap(&prepared, &[&self.job_dir], self.sandbox_network);
+            prepared.program = program;
+            prepared.args = args;
+        }
+        Ok((prepared, script))
+    }
+
+    /// Program and arguments running the script `command` written to `path`: the
+    /// interpreter its shebang line names, else the shell with `-e`. With `env_dump`, the
+    /// shell sources the script and then writes the named variables it set to the dump,
+    /// NUL-separated; scripts with a shebang run in a process of their own and export
+    /// nothing.
+    fn script_invocation(
+        &self,
+        command: &str,
+        path: &Path,
+        env_dump: Option<(&Path, Vec<String>)>,
+    ) -> (String, Vec<String>) {
+        let path = path.display().to_string();
+        match command
+            .trim_start()
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("#!"))
+        {
+            Some(shebang) if !shebang.trim().is_empty() => {
+                let mut words = shebang.split_whitespace().map(str::to_string);
+                let program = words.next().unwrap_or_default();
+                (program, words.chain(std::iter::once(path)).collect())
+            }
+            _ => match env_dump {
+                Some((dump, names)) => {
+                    let writes: String = names
+                        .iter()
+                        .map(|name| {
+                            format!(
+                                "if [ -n \"${{{name}+x}}\" ]; then printf \"%s\\0\" \"{name}=${name}\"; fi; "
+                            )
+                        })
+                        .collect();
+                    let runner = format!(
+                        "parsec_env_dump=$1; shift; trap '{{ {}}} > \"$parsec_env_dump\"' EXIT; . \"$0\"",
+                        writes
+                    );
+                    let args = ["-e", "-c", &runner, &path, &dump.display().to_string()];
+                    (
+                        self.shell.clone(),
+                        args.iter().map(|arg| arg.to_string()).collect(),
+                    )
+                }
+                None => (self.shell.clone(), vec!["-e".to_string(), path]),
+            },
+        }
+    }
+
+    /// Report a command that could not be run to the post-exec hooks.
+    fn report_failure(
+        &self,
+        prepared: &PreparedCommand,
+        started_at: DateTime<Utc>,
+        error: &ExecutionError,
+    ) {
+        self.count(prepared.kind, "error", None);
+        hooks::run_post_exec(
+            &self.post_exec_hooks,
+            &DirectCommandExecution {
+                command: prepared.command.clone(),
+                executed_at: started_at,
+                exit_status: -1,
+                stdout: TruncatedText::new(String::new(), 0),
+                stderr: TruncatedText::new(error.to_string(), usize::MAX),
+                working_directory: prepared.working_dir.clone(),
+                classification_overridden: false,
+                duration: Duration::ZERO,
+                completed_at: Some(Utc::now()),
+                termination: None,
+            },
+        );
+    }
+
+    /// The attempt recorded for `command` when a pre-exec hook denied it.
+    pub fn denied_attempt(command: &GeneratedCommand, reason: String) -> CommandAttempt {
+        CommandAttempt {
+            candidate: command.clone(),
+            approved: false,
+            executed: false,
+            exit_status: None,
+            stdout: TruncatedText::new(String::new(), 0),
+            stderr: TruncatedText::new(reason.clone(), usize::MAX),
+            error: Some(ExecutionError::Denied(reason)),
+            timestamp: Utc::now(),
+            duration: Duration::ZERO,
+            completed_at: None,
+            termination: None,
+            timeout: None,
+            attached: false,
+            environment_changes: Vec::new(),
+            sandbox: None,
+        }
+    }
+
+    /// The attempt recorded for `command` when validation stopped it.
+    pub fn blocked_attempt(command: &GeneratedCommand, findings: Vec<Finding>) -> CommandAttempt {
+        Self::unrun_attempt(command, ExecutionError::Blocked(findings))
+    }
+
+    /// The attempt recorded for `command` when it needs a terminal it was not given.
+    pub fn needs_terminal_attempt(command: &GeneratedCommand, reason: String) -> CommandAttempt {
+        Self::unrun_attempt(command, ExecutionError::NeedsTerminal(reason))
+    }
+
+    /// The attempt recorded for `command` when the sandbox would keep it off the network
+    /// it needs.
+    pub fn needs_network_attempt(command: &GeneratedCommand, reason: String) -> CommandAttempt {
+        Self::unrun_attempt(command, ExecutionError::NeedsNetwork(reason))
+    }
+
+    fn unrun_attempt(command: &GeneratedCommand, error: ExecutionError) -> CommandAttempt {
+        CommandAttempt {
+            candidate: command.clone(),
+            approved: false,
+            executed: false,
+            exit_status: None,
+            stdout: TruncatedText::new(String::new(), 0),
+            stderr: TruncatedText::new(error.to_string(), usize::MAX),
+            error: Some(error),
+            timestamp: Utc::now(),
+            duration: Duration::ZERO,
+            completed_at: None,
+            termination: None,
+            timeout: None,
+            attached: false,
+            environment_changes: Vec::new(),
+            sandbox: None,
+        }
+    }
+
+    /// Why `command` is too risky to run, if it is.
+    pub fn risk_block(&self, command: &GeneratedCommand) -> Option<ExecutionError> {
+        command
+            .risk_score
+            .filter(|risk_score| !self.allow_dangerous && *risk_score > self.block_threshold)
+            .map(|risk_score| ExecutionError::HighRisk {
+                risk_score,
+                threshold: self.block_threshold,
+            })
+    }
+
+    /// Why `command` would wait on a terminal when run with `stdin`, if it would: it starts
+    /// a full-screen program, or an interactive one given neither input nor arguments
+    /// telling it what to do. Only single command lines are checked, not scripts.
+    pub fn needs_terminal(&self, command: &str, stdin: Option<&str>) -> Option<String> {
+        let script = parsec_core::CommandKind::of(command) == parsec_core::CommandKind::Script;
+        let input = stdin.map_or(ExecutionInput::Closed, |text| {
+            ExecutionInput::Text(text.to_string())
+        });
+        match resolve_input(command, script, input) {
+            (_, true, _) => None,
+            (line, false, input) => terminal_reason(&tools::command_words(&line), &input),
+        }
+    }
+
+    /// The sandbox backend confining step commands and background jobs, if any.
+    pub fn sandbox(&self) -> Option<&dyn SandboxBackend> {
+        self.sandbox.as_deref()
+    }
+
+    /// Why `command` must run outside the sandbox, if it must: it is known to need the
+    /// network, which the sandbox takes away.
+    pub fn needs_unsandboxing(&self, command: &str) -> Option<String> {
+        if self.sandbox.is_none() || self.sandbox_network {
+            return None;
+        }
+        sandbox::network_reason(command)
+    }
+
+    /// Why the step command `command` needs a terminal, if it does: `needs_terminal` says
+    /// so, or the model marked it interactive.
+    pub fn step_needs_terminal(&self, command: &GeneratedCommand) -> Option<String> {
+        self.needs_terminal(&command.command, command.stdin.as_deref())
+            .or_else(|| {
+                command
+                    .interactive
+                    .then(|| "the command prompts for answers".to_string())
+            })
+    }
+
+    /// Run `command` reading `input`; a command that needs a terminal fails unless `input`
+    /// is the terminal.
     pub fn execute_direct_command(
         &self,
         command: &str,
         working_dir: &Path,
+        input: ExecutionInput,
     ) -> Result<DirectCommandExecution, ExecutionError> {
         let start_time = Utc::now();
-
-        // Parse command into program and args
-        let mut parts = command.split_whitespace();
-        let program = parts
-            .next()
-            .ok_or_else(|| ExecutionError::CommandNotFound("Empty command".to_string()))?;
-        let args: Vec<&str> = parts.collect();
-
-        // Execute the command
-        let mut cmd = Command::new(program);
-        cmd.args(args)
-            .current_dir(working_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let output = cmd.output().map_err(|e| match e.kind() {
-            std::io::ErrorKind::NotFound => ExecutionError::CommandNotFound(program.to_string()),
-            std::io::ErrorKind::PermissionDenied => {
-                ExecutionError::PermissionDenied(program.to_string())
+        if input != ExecutionInput::Terminal {
+            let stdin = match &input {
+                ExecutionInput::Text(text) => Some(text.as_str()),
+                _ => None,
+            };
+            if let Some(reason) = self.needs_terminal(command, stdin) {
+                return Err(ExecutionError::NeedsTerminal(reason));
             }
-            _ => ExecutionError::ExecutionFailed(format!("Failed to execute {}: {}", program, e)),
+        }
+        let is_script = parsec_core::CommandKind::of(command) == parsec_core::CommandKind::Script;
+        let (prepared, _script) = self.prepare(Invocation {
+            command,
+            script: is_script,
+            input,
+            env: BTreeMap::new(),
+            env_dump: None,
+            working_dir,
+            kind: CommandKind::Direct,
+            sandboxed: false,
         })?;
+        let output = self
+            .run(&prepared, self.timeout, self.max_output_size)
+            .inspect_err(|e| self.report_failure(&prepared, start_time, e))?;
 
-        let stdout = TruncatedText::new(
-            String::from_utf8_lossy(&output.stdout).to_string(),
-            self.max_output_size,
-        );
-
-        let stderr = TruncatedText::new(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-            self.max_output_size,
-        );
-
-        Ok(DirectCommandExecution {
+        let execution = DirectCommandExecution {
             command: command.to_string(),
             executed_at: start_time,
-            exit_status: output.status.code().unwrap_or(-1),
-            stdout,
-            stderr,
-            working_directory: working_dir.to_path_buf(),
-        })
+            exit_status: output.exit_status().unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+            working_directory: prepared.working_dir,
+            classification_overridden: false,
+            duration: output.duration,
+            completed_at: Some(Utc::now()),
+            termination: Some(output.termination),
+        };
+        hooks::run_post_exec(&self.post_exec_hooks, &execution);
+        if output.termination == CommandTermination::TimedOut {
+            return Err(ExecutionError::Timeout(format!(
+                "{} did not finish within {}",
+                command,
+                format_duration(self.timeout)
+            )));
+        }
+        Ok(execution)
     }
 
+    /// Run a step's command. `overrides` win over the command's `expected_duration`,
+    /// which wins over the executor defaults.
     pub fn execute_step_command(
         &self,
         command: &GeneratedCommand,
         working_dir: &Path,
+        overrides: &ExecutionOverrides,
     ) -> Result<CommandAttempt, ExecutionError> {
         let start_time = Utc::now();
+        let timeout = overrides
+            .timeout
+            .or_else(|| command.expected_duration.map(ExpectedDuration::timeout))
+            .unwrap_or(self.timeout);
+        let max_output_size = overrides.max_output_size.unwrap_or(self.max_output_size);
 
-        // Check for dangerous patterns
-        if let Some(risk_score) = command.risk_score {
-            if risk_score > 0.8 {
-                return Ok(CommandAttempt {
-                    candidate: command.clone(),
-                    approved: false,
-                    executed: false,
-                    exit_status: None,
-                    stdout: TruncatedText::new(
-                        "Command blocked due to high risk score".to_string(),
-                        self.max_output_size,
-                    ),
-                    stderr: TruncatedText::new(
-                        format!("Risk score: {:.2}", risk_score),
-                        self.max_output_size,
-                    ),
-                    error: Some(ExecutionError::ExecutionFailed(
-                        "High risk command blocked".to_string(),
-                    )),
-                    timestamp: start_time,
-                });
+        // Whoever approved it should have refused it already
+        if let Some(blocked) = self.risk_block(command) {
+            self.count(CommandKind::Step, "blocked", None);
+            return Err(blocked);
+        }
+
+        let input = if overrides.attach_terminal {
+            ExecutionInput::Terminal
+        } else if let Some(reason) = self.step_needs_terminal(command) {
+            self.count(CommandKind::Step, "needs_terminal", None);
+            return Ok(Self::needs_terminal_attempt(command, reason));
+        } else {
+            command
+                .stdin
+                .clone()
+                .map_or(ExecutionInput::Closed, ExecutionInput::Text)
+        };
+        if !overrides.unsandboxed {
+            if let Some(reason) = self.needs_unsandboxing(&command.command) {
+                self.count(CommandKind::Step, "needs_network", None);
+                return Ok(Self::needs_network_attempt(command, reason));
             }
         }
+        let sandbox = self
+            .sandbox
+            .as_ref()
+            .filter(|_| !overrides.unsandboxed)
+            .map(|sandbox| sandbox.name().to_string());
+        let is_script = command.kind == parsec_core::CommandKind::Script;
+        // A step exporting variables runs in the shell, which then reports them
+        let exported = tools::exported_variables(&command.command);
+        let env_dump = if exported.is_empty() {
+            None
+        } else {
+            Some(ScriptFile::create(&self.job_dir, "env", b"")?)
+        };
+        let (prepared, _script) = match self.prepare(Invocation {
+            command: &command.command,
+            script: is_script,
+            input,
+            env: overrides.env.clone(),
+            env_dump: env_dump.as_ref().map(|dump| (dump.path(), exported)),
+            working_dir,
+            kind: CommandKind::Step,
+            sandboxed: sandbox.is_some(),
+        }) {
+            Ok(prepared) => prepared,
+            Err(ExecutionError::Denied(reason)) => {
+                return Ok(Self::denied_attempt(command, reason))
+            }
+            Err(e) => return Err(e),
+        };
 
         // Execute the command
-        let execution_result = self.execute_direct_command(&command.command, working_dir)?;
+        let attached = prepared.input == ExecutionInput::Terminal;
+        let output = self
+            .run(&prepared, timeout, max_output_size)
+            .inspect_err(|e| self.report_failure(&prepared, start_time, e))?;
+        let completed_at = Utc::now();
+        hooks::run_post_exec(
+            &self.post_exec_hooks,
+            &DirectCommandExecution {
+                command: command.command.clone(),
+                executed_at: start_time,
+                exit_status: output.exit_status().unwrap_or(-1),
+                stdout: output.stdout.clone(),
+                stderr: output.stderr.clone(),
+                working_directory: prepared.working_dir.clone(),
+                classification_overridden: false,
+                duration: output.duration,
+                completed_at: Some(completed_at),
+                termination: Some(output.termination),
+            },
+        );
+
+        let environment_changes = match (&env_dump, output.termination) {
+            (Some(dump), CommandTermination::Exited(0)) => {
+                exported_changes(dump.path(), &prepared.env, completed_at)
+            }
+            _ => Vec::new(),
+        };
 
         Ok(CommandAttempt {
             candidate: command.clone(),
             approved: true,
             executed: true,
-            exit_status: Some(execution_result.exit_status),
-            stdout: execution_result.stdout,
-            stderr: execution_result.stderr,
-            error: if execution_result.exit_status == 0 {
-                None
-            } else {
-                Some(ExecutionError::ExecutionFailed(format!(
-                    "Command exited with status {}",
-                    execution_result.exit_status
-                )))
+            exit_status: output.exit_status(),
+            error: match output.termination {
+                CommandTermination::TimedOut => Some(ExecutionError::Timeout(format!(
+                    "Command did not finish within {}",
+                    format_duration(timeout)
+                ))),
+                CommandTermination::Exited(0) => None,
+                termination => Some(ExecutionError::ExecutionFailed(format!(
+                    "Command {}",
+                    termination
+                ))),
             },
+            stdout: output.stdout,
+            stderr: output.stderr,
             timestamp: start_time,
+            duration: output.duration,
+            completed_at: Some(completed_at),
+            termination: Some(output.termination),
+            timeout: (!attached).then_some(timeout),
+            attached,
+            environment_changes,
+            sandbox,
         })
     }
 
-    pub fn validate_command(&self, command: &str) -> Result<(), ExecutionError> {
-        // Basic validation checks
-        if command.trim().is_empty() {
-            return Err(ExecutionError::ExecutionFailed("Empty command".to_string()));
-        }
-
-        // Check for dangerous patterns
-        let dangerous_patterns = vec![
-            "rm -rf /",
-            ":(){ :|:& };:", // Fork bomb
-            "mkfs",
-            "dd if=/dev/zero",
-            "shutdown",
-            "reboot",
-        ];
-
-        let command_lower = command.to_lowercase();
-        for pattern in dangerous_patterns {
-            if command_lower.contains(pattern) {
-                return Err(ExecutionError::ExecutionFailed(format!(
-                    "Dangerous command pattern detected: {}",
-                    pattern
-                )));
+    /// Run the prepared command, killing it once `timeout` passes. Commands attached to
+    /// the terminal run until they exit, with the user there to stop them.
+    #[instrument(
+        name = "execute",
+        skip_all,
+        err(level = "debug"),
+        fields(
+            command_hash = %content_hash(&prepared.command),
+            kind = ?prepared.kind,
+            outcome = field::Empty,
+            duration_ms = field::Empty,
+        )
+    )]
+    fn run(
+        &self,
+        prepared: &PreparedCommand,
+        timeout: Duration,
+        max_output_size: usize,
+    ) -> Result<RunOutput, ExecutionError> {
+        let program = &prepared.program;
+        let spool = NEXT_SPOOL.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(unix)]
+        if prepared.input == ExecutionInput::Terminal {
+            let started = Instant::now();
+            let (status, transcript) = pty::run_attached(prepared, max_output_size)?;
+            let (head, omitted, tail) = transcript.into_parts();
+            let stdout = TruncatedText::from_head_tail(
+                &self.clean_output(head, spool, "transcript-head"),
+                omitted,
+                &self.clean_output(tail, spool, "transcript-tail"),
+            );
+            return Ok(self.finish_run(
+                prepared,
+                Some(status),
+                started.elapsed(),
+                stdout,
+                TruncatedText::new(String::new(), 0),
+            ));
+        }
+
+        let attached = prepared.input == ExecutionInput::Terminal;
+        let stdin = match &prepared.input {
+            ExecutionInput::Closed => Stdio::null(),
+            ExecutionInput::Text(_) => Stdio::piped(),
+            ExecutionInput::Terminal => Stdio::inherit(),
+        };
+        let output = || {
+            if attached {
+                Stdio::inherit()
+            } else {
+                Stdio::piped()
             }
+        };
+
+        let started = Instant::now();
+        let mut child = Command::new(program)
+            .args(&prepared.args)
+            .envs(&prepared.env)
+            .current_dir(&prepared.working_dir)
+            .stdin(stdin)
+            .stdout(output())
+            .stderr(output())
+            .spawn()
+            .map_err(|e| spawn_error(program, e))?;
+        if let (Some(pipe), ExecutionInput::Text(text)) = (child.stdin.take(), &prepared.input) {
+            write_input(pipe, text.clone());
         }
+        let stdout = child.stdout.take().map(read_pipe);
+        let stderr = child.stderr.take().map(read_pipe);
 
-        // Check for unescaped newlines (except in valid cases)
-        if command.contains('\n') && !command.contains("<<") {
-            return Err(ExecutionError::ExecutionFailed(
-                "Unescaped newlines in command".to_string(),
-            ));
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if !attached && started.elapsed() >= timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break None;
+                }
+                Ok(None) => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    let _ = child.kill();
+                    return Err(ExecutionError::ExecutionFailed(format!(
+                        "Failed to wait for {}: {}",
+                        program, e
+                    )));
+                }
+            }
+        };
+        let duration = started.elapsed();
+
+        // Processes the command started may still hold the pipes open after a kill
+        let collect = |pipe: Option<Receiver<Vec<u8>>>, stream: &str| {
+            let bytes = pipe
+                .and_then(|pipe| pipe.recv_timeout(PIPE_GRACE).ok())
+                .unwrap_or_default();
+            let raw = String::from_utf8_lossy(&bytes).to_string();
+            truncate_output(self.clean_output(raw, spool, stream), max_output_size)
+        };
+        let stdout = collect(stdout, "stdout");
+        let stderr = collect(stderr, "stderr");
+        Ok(self.finish_run(prepared, status, duration, stdout, stderr))
+    }
+
+    /// Record how a run ended on the current span and in the metrics. `status` is `None`
+    /// for a command killed at its timeout.
+    fn finish_run(
+        &self,
+        prepared: &PreparedCommand,
+        status: Option<ExitStatus>,
+        duration: Duration,
+        stdout: TruncatedText,
+        stderr: TruncatedText,
+    ) -> RunOutput {
+        let termination = status.map_or(CommandTermination::TimedOut, termination_of);
+        let span = Span::current();
+        span.record("outcome", field::display(&termination));
+        span.record("duration_ms", duration.as_millis() as u64);
+        self.count(prepared.kind, termination.exit_class(), Some(duration));
+        RunOutput {
+            termination,
+            stdout,
+            stderr,
+            duration,
         }
+    }
 
-        Ok(())
+    /// Sanitize `raw` if configured, keeping the original under `raw_output_dir` when
+    /// that changed it, and redact secrets from both.
+    fn clean_output(&self, raw: String, spool: u64, stream: &str) -> String {
+        let redact = |text: String| match &self.secret_redactor {
+            Some(redactor) => redactor.redact(&text),
+            None => text,
+        };
+        if !self.sanitize_output {
+            return redact(raw);
+        }
+        let clean = sanitize::sanitize(&raw);
+        if let (Some(dir), true) = (&self.raw_output_dir, clean != raw) {
+            let path = dir.join(format!("{}-{}.{}", std::process::id(), spool, stream));
+            match std::fs::create_dir_all(dir).and_then(|()| std::fs::write(&path, redact(raw))) {
+                Ok(()) => debug!("Raw {} kept in {}", stream, path.display()),
+                Err(e) => warn!("Failed to keep raw output in {}: {}", path.display(), e),
+            }
+        }
+        redact(clean)
     }
 
-    pub fn check_prerequisites(&self, working_dir: &Path) -> Vec<String> {
-        let mut warnings = Vec::new();
+    /// Start `command` reading `stdin`, with the variables of `overrides` set on top of
+    /// parsec's environment, without waiting for it, spooling its output under the job
+    /// directory. Background jobs have no timeout and no terminal, so commands that need
+    /// one fail, as do commands the sandbox would keep off the network unless `overrides`
+    /// runs them unsandboxed. Register the handle with a `JobRegistry` to track, tail, wait
+    /// for or kill them.
+    #[instrument(
+        name = "execute",
+        skip_all,
+        err(level = "debug"),
+        fields(command_hash = %content_hash(command), kind = "Background")
+    )]
+    pub fn spawn_background(
+        &self,
+        command: &str,
+        stdin: Option<&str>,
+        working_dir: &Path,
+        overrides: &ExecutionOverrides,
+    ) -> Result<JobHandle, ExecutionError> {
+        if let Some(reason) = self.needs_terminal(command, stdin) {
+            return Err(ExecutionError::NeedsTerminal(reason));
+        }
+        if !overrides.unsandboxed {
+            if let Some(reason) = self.needs_unsandboxing(command) {
+                return Err(ExecutionError::NeedsNetwork(reason));
+            }
+        }
+        let sandbox = self
+            .sandbox
+            .as_ref()
+            .filter(|_| !overrides.unsandboxed)
+            .map(|sandbox| sandbox.name().to_string());
+        let is_script = parsec_core::CommandKind::of(command) == parsec_core::CommandKind::Script;
+        let input = stdin.map_or(ExecutionInput::Closed, |text| {
+            ExecutionInput::Text(text.to_string())
+        });
+        let (prepared, script) = self.prepare(Invocation {
+            command,
+            script: is_script,
+            input,
+            env: overrides.env.clone(),
+            env_dump: None,
+            working_dir,
+            kind: CommandKind::Background,
+            sandboxed: sandbox.is_some(),
+        })?;
+        let spool_error = |path: &Path, e: std::io::Error| {
+            ExecutionError::ExecutionFailed(format!(
+                "Failed to create job output {}: {}",
+                path.display(),
+                e
+            ))
+        };
+        create_private_dir(&self.job_dir).map_err(|e| spool_error(&self.job_dir, e))?;
+        let spool = format!(
+            "{}-{}",
+            std::process::id(),
+            NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)
+        );
+        let stdout_path = self.job_dir.join(format!("{}.stdout", spool));
+        let stderr_path = self.job_dir.join(format!("{}.stderr", spool));
+        let stdout = create_private_file(&stdout_path).map_err(|e| spool_error(&stdout_path, e))?;
+        let stderr = create_private_file(&stderr_path).map_err(|e| spool_error(&stderr_path, e))?;
 
-        if !working_dir.exists() {
-            warnings.push(format!(
-                "Working directory does not exist: {}",
-                working_dir.display()
-            ));
+        let started_at = Utc::now();
+        let mut child = tokio::process::Command::new(&prepared.program)
+            .args(&prepared.args)
+            .envs(&prepared.env)
+            .current_dir(&prepared.working_dir)
+            .stdin(match prepared.input {
+                ExecutionInput::Text(_) => Stdio::piped(),
+                _ => Stdio::null(),
+            })
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .map_err(|e| spawn_error(&prepared.program, e))?;
+        if let (Some(mut pipe), ExecutionInput::Text(text)) = (child.stdin.take(), prepared.input) {
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                if let Err(e) = pipe.write_all(text.as_bytes()).await {
+                    debug!("Job stopped reading its input: {}", e);
+                }
+            });
         }
+        self.count(CommandKind::Background, "started", None);
 
-        if !working_dir.is_dir() {
-            warnings.push(format!(
-                "Working directory is not a directory: {}",
-                working_dir.display()
-            ));
+        Ok(JobHandle {
+            command: command.to_string(),
+            working_dir: prepared.working_dir,
+            started_at,
+            stdout_path,
+            stderr_path,
+            sandbox,
+            child,
+            started: Instant::now(),
+            script,
+        })
+    }
+
+    /// Whether `with_allowed_rules` allowed the `Warn` rule named `rule`.
+    pub fn allows_rule(&self, rule: &str) -> bool {
+        self.allowed_rules.iter().any(|allowed| allowed == rule)
+    }
+
+    /// Check each line of `command` against `RULES`. Findings of `Warn` rules allowed with
+    /// `with_allowed_rules` stay warnings, for the user to override; those of the others
+    /// block, as do `Block` rules, which cannot be allowed.
+    pub fn validate_command(&self, command: &str) -> ValidationReport {
+        if command.trim().is_empty() {
+            return ValidationReport::from_findings(vec![Finding {
+                rule: "empty".to_string(),
+                span: 0..command.len(),
+                matched: String::new(),
+                severity: Severity::Block,
+                message: "Empty command".to_string(),
+            }]);
+        }
+
+        // ASCII lowercasing keeps byte offsets, so spans index the command as given
+        let lower = command.to_ascii_lowercase();
+        let mut findings = Vec::new();
+        let mut offset = 0;
+        // Each line of a script is checked on its own; comments and shebangs are skipped
+        for line in lower.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            for rule in RULES {
+                let Some(span) = rule.find(line) else {
+                    continue;
+                };
+                let span = start + span.start..start + span.end;
+                let severity = match rule.severity {
+                    Severity::Warn if self.allows_rule(rule.name) => Severity::Warn,
+                    _ => Severity::Block,
+                };
+                findings.push(Finding {
+                    rule: rule.name.to_string(),
+                    matched: command[span.clone()].to_string(),
+                    span,
+                    severity,
+                    message: rule.message.to_string(),
+                });
+            }
         }
+        ValidationReport::from_findings(findings)
+    }
 
-        warnings
+    /// Check `working_dir` before running `plan` in it; see `preflight::check`.
+    pub fn check_prerequisites(
+        &self,
+        working_dir: &Path,
+        plan: &WorkflowPlan,
+    ) -> Vec<PreflightFinding> {
+        preflight::check(working_dir, plan, &self.preflight)
     }
 }
@@ -0,0 +1,217 @@
+//! Checks of the working directory, disk, network, tools and repository a workflow is
+//! about to run in. What a plan needs is guessed from the words of its step descriptions.
+
+use parsec_core::preflight::PreflightFinding;
+use parsec_core::{tools, WorkflowPlan};
+use std::fs::OpenOptions;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Words of step descriptions that suggest fetching or sending something over the network.
+const NETWORK_WORDS: &[&str] = &[
+    "install", "download", "clone", "fetch", "pull", "push", "upload", "deploy", "publish", "curl",
+    "wget",
+];
+
+/// Words of step descriptions that suggest changing files in the working directory.
+const MODIFYING_WORDS: &[&str] = &[
+    "create", "write", "edit", "modify", "update", "change", "delete", "remove", "rename", "move",
+    "refactor", "generate", "add", "commit", "format", "fix", "replace", "init",
+];
+
+/// What `check` probes beyond the working directory.
+#[derive(Debug, Clone)]
+pub struct PreflightOptions {
+    /// Free space below this on the working directory's file system is an error; zero
+    /// turns the check off.
+    pub min_free_bytes: u64,
+    /// `host:port` connected to when the plan looks like it needs the network.
+    pub network_probe: Option<String>,
+    pub probe_timeout: Duration,
+}
+
+impl Default for PreflightOptions {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: 100 * 1024 * 1024,
+            network_probe: None,
+            probe_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+/// The lowercase words of every step description of `plan`, by step.
+fn step_words(plan: &WorkflowPlan) -> Vec<Vec<String>> {
+    plan.steps
+        .iter()
+        .map(|step| {
+            step.description
+                .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect()
+        })
+        .collect()
+}
+
+fn mentions(words: &[Vec<String>], wanted: &[&str]) -> bool {
+    words
+        .iter()
+        .flatten()
+        .any(|word| wanted.contains(&word.as_str()))
+}
+
+/// Check `working_dir` and what `plan` looks like it needs. Stops after the working
+/// directory checks when it is missing.
+pub fn check(
+    working_dir: &Path,
+    plan: &WorkflowPlan,
+    options: &PreflightOptions,
+) -> Vec<PreflightFinding> {
+    if !working_dir.exists() {
+        return vec![PreflightFinding::error(
+            "working-dir",
+            format!(
+                "Working directory does not exist: {}",
+                working_dir.display()
+            ),
+        )];
+    }
+    if !working_dir.is_dir() {
+        return vec![PreflightFinding::error(
+            "working-dir",
+            format!(
+                "Working directory is not a directory: {}",
+                working_dir.display()
+            ),
+        )];
+    }
+
+    let words = step_words(plan);
+    let mut findings = Vec::new();
+    findings.extend(check_writable(working_dir));
+    findings.extend(check_disk_space(working_dir, options.min_free_bytes));
+    if mentions(&words, NETWORK_WORDS) {
+        if let Some(probe) = &options.network_probe {
+            findings.extend(check_network(probe, options.probe_timeout));
+        }
+    }
+    findings.extend(check_tools(&words));
+    if mentions(&words, MODIFYING_WORDS) {
+        findings.extend(check_git(working_dir));
+    }
+    findings
+}
+
+fn check_writable(working_dir: &Path) -> Option<PreflightFinding> {
+    let probe = working_dir.join(format!(".parsec-preflight-{}", std::process::id()));
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|_| std::fs::remove_file(&probe));
+    result.err().map(|e| {
+        PreflightFinding::error(
+            "writable",
+            format!(
+                "Working directory {} is not writable: {}",
+                working_dir.display(),
+                e
+            ),
+        )
+    })
+}
+
+#[cfg(unix)]
+fn check_disk_space(working_dir: &Path, min_free_bytes: u64) -> Option<PreflightFinding> {
+    if min_free_bytes == 0 {
+        return None;
+    }
+    let stats = nix::sys::statvfs::statvfs(working_dir).ok()?;
+    let free = stats.blocks_available() as u64 * stats.fragment_size() as u64;
+    let mb = |bytes: u64| bytes / (1024 * 1024);
+    (free < min_free_bytes).then(|| {
+        PreflightFinding::error(
+            "disk-space",
+            format!(
+                "Only {} MB free on the working directory's disk, under the {} MB required",
+                mb(free),
+                mb(min_free_bytes)
+            ),
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn check_disk_space(_working_dir: &Path, _min_free_bytes: u64) -> Option<PreflightFinding> {
+    None
+}
+
+fn check_network(probe: &str, timeout: Duration) -> Option<PreflightFinding> {
+    let reached = probe
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok()));
+    let problem = match reached {
+        Ok(true) => return None,
+        Ok(false) => "no connection".to_string(),
+        Err(e) => e.to_string(),
+    };
+    Some(PreflightFinding::warning(
+        "network",
+        format!(
+            "Steps look like they need the network, but {} is unreachable: {}",
+            probe, problem
+        ),
+    ))
+}
+
+/// Tools the steps name that are not on PATH; `python` is there when `python3` is.
+fn check_tools(words: &[Vec<String>]) -> Vec<PreflightFinding> {
+    let mut findings: Vec<PreflightFinding> = Vec::new();
+    for (index, step) in words.iter().enumerate() {
+        for tool in tools::DEFAULT_TRACKED_TOOLS {
+            if !step.iter().any(|word| word == tool)
+                || findings
+                    .iter()
+                    .any(|finding| finding.message.contains(tool))
+            {
+                continue;
+            }
+            let mut candidates = vec![tool.to_string()];
+            if *tool == "python" {
+                candidates.push("python3".to_string());
+            }
+            if tools::detect_tools(&candidates).is_empty() {
+                findings.push(PreflightFinding::warning(
+                    "tools",
+                    format!("Step {} uses `{}`, which is not installed", index + 1, tool),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+fn check_git(working_dir: &Path) -> Option<PreflightFinding> {
+    let output = Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let changed = String::from_utf8_lossy(&output.stdout).lines().count();
+    (changed > 0).then(|| {
+        PreflightFinding::warning(
+            "git-clean",
+            format!(
+                "The git working tree has {} uncommitted change{}, and steps look like they modify files",
+                changed,
+                if changed == 1 { "" } else { "s" }
+            ),
+        )
+    })
+}
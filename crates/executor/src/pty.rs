@@ -0,0 +1,266 @@
+//! Running a command on a pseudo-terminal wired to the user's own, for steps that prompt.
+//! The user's keys, Ctrl-C included, go to the command while it runs, window-size
+//! changes follow it, and what it shows is kept as a transcript.
+
+use crate::hooks::PreparedCommand;
+use crate::spawn_error;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::pty::{openpty, Winsize};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use parsec_core::ExecutionError;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+nix::ioctl_read_bad!(get_window_size, nix::libc::TIOCGWINSZ, Winsize);
+nix::ioctl_write_ptr_bad!(set_window_size, nix::libc::TIOCSWINSZ, Winsize);
+
+/// How long, in milliseconds, input forwarding waits for a key before checking the
+/// window size again, and output waits before checking whether the command exited.
+const POLL_MS: u16 = 50;
+
+/// How long, in milliseconds, to wait for more output once the command has exited, from
+/// processes it left holding the terminal.
+const OUTPUT_GRACE_MS: u16 = 100;
+
+/// The first and last bytes a command showed, at most `limit` of them.
+pub(crate) struct Transcript {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_limit: usize,
+    tail_limit: usize,
+    omitted: usize,
+}
+
+impl Transcript {
+    fn new(limit: usize) -> Self {
+        let head_limit = limit / 4;
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            head_limit,
+            tail_limit: limit - head_limit,
+            omitted: 0,
+        }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        let to_head = bytes.len().min(self.head_limit - self.head.len());
+        self.head.extend_from_slice(&bytes[..to_head]);
+        bytes = &bytes[to_head..];
+        self.tail.extend(bytes);
+        let over = self.tail.len().saturating_sub(self.tail_limit);
+        self.tail.drain(..over);
+        self.omitted += over;
+    }
+
+    /// The head, how many bytes were left out after it, and the tail.
+    pub(crate) fn into_parts(self) -> (String, usize, String) {
+        let tail: Vec<u8> = self.tail.into();
+        (
+            String::from_utf8_lossy(&self.head).to_string(),
+            self.omitted,
+            String::from_utf8_lossy(&tail).to_string(),
+        )
+    }
+}
+
+/// The user's terminal in raw mode, so every key reaches the command; restored when
+/// dropped.
+struct RawMode(Termios);
+
+impl RawMode {
+    fn enter(terminal: BorrowedFd<'_>) -> nix::Result<Self> {
+        let original = tcgetattr(terminal)?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(terminal, SetArg::TCSANOW, &raw)?;
+        Ok(Self(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(io::stdin().as_fd(), SetArg::TCSANOW, &self.0);
+    }
+}
+
+fn window_size(terminal: BorrowedFd<'_>) -> Option<Winsize> {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` to the pointer it is given
+    unsafe { get_window_size(terminal.as_raw_fd(), &mut size) }
+        .ok()
+        .map(|_| size)
+}
+
+fn pty_error(e: impl std::fmt::Display) -> ExecutionError {
+    ExecutionError::ExecutionFailed(format!("Failed to attach to the terminal: {}", e))
+}
+
+/// Run `prepared` on a new pseudo-terminal until it exits, showing its output on stdout
+/// and forwarding stdin to it when stdin is a terminal. Returns how it exited and a
+/// transcript of at most `max_output_size` bytes, unsanitized and unredacted.
+pub(crate) fn run_attached(
+    prepared: &PreparedCommand,
+    max_output_size: usize,
+) -> Result<(ExitStatus, Transcript), ExecutionError> {
+    let stdin = io::stdin();
+    let terminal = stdin.is_terminal().then(|| stdin.as_fd());
+    let size = terminal.and_then(window_size);
+    let pty = openpty(size.as_ref(), None).map_err(pty_error)?;
+    let slave = File::from(pty.slave);
+    let stdio = || slave.try_clone().map(Stdio::from).map_err(pty_error);
+
+    let mut command = Command::new(&prepared.program);
+    command
+        .args(&prepared.args)
+        .envs(&prepared.env)
+        .current_dir(&prepared.working_dir)
+        .stdin(stdio()?)
+        .stdout(stdio()?)
+        .stderr(stdio()?);
+    // SAFETY: only async-signal-safe calls between fork and exec. A session of its own
+    // with the pseudo-terminal as its controlling terminal lets Ctrl-C reach the command.
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            if nix::libc::ioctl(0, nix::libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| spawn_error(&prepared.program, e))?;
+    drop(command);
+    drop(slave);
+
+    let mut master = File::from(pty.master);
+    let _raw_mode = match terminal.map(RawMode::enter).transpose() {
+        Ok(raw_mode) => raw_mode,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(pty_error(e));
+        }
+    };
+    let done = Arc::new(AtomicBool::new(false));
+    let forwarder = match (terminal, master.try_clone()) {
+        (Some(_), Ok(input)) => Some(forward_input(input, size, done.clone())),
+        _ => None,
+    };
+
+    let mut transcript = Transcript::new(max_output_size);
+    let mut stdout = io::stdout();
+    let mut buffer = [0u8; 4096];
+    let mut exited = None;
+    loop {
+        let timeout = PollTimeout::from(match exited {
+            Some(_) => OUTPUT_GRACE_MS,
+            None => POLL_MS,
+        });
+        let ready = poll(
+            &mut [PollFd::new(master.as_fd(), PollFlags::POLLIN)],
+            timeout,
+        )
+        .unwrap_or(0);
+        if ready > 0 {
+            // The pseudo-terminal reports an error once every process has closed it
+            match master.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    transcript.push(&buffer[..read]);
+                    let _ = stdout.write_all(&buffer[..read]);
+                    let _ = stdout.flush();
+                }
+            }
+        } else if exited.is_some() {
+            break;
+        }
+        if exited.is_none() {
+            exited = child.try_wait().map_err(pty_error)?;
+        }
+    }
+    let status = match exited {
+        Some(status) => status,
+        None => child.wait().map_err(pty_error)?,
+    };
+
+    done.store(true, Ordering::Relaxed);
+    if let Some(forwarder) = forwarder {
+        let _ = forwarder.join();
+    }
+    Ok((status, transcript))
+}
+
+/// Copy the user's keys to `master` until `done`, resizing the pseudo-terminal whenever
+/// the user's terminal changes size. Polls rather than blocking on stdin, so no key meant
+/// for parsec is taken once the command has exited.
+fn forward_input(
+    mut master: File,
+    mut size: Option<Winsize>,
+    done: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut buffer = [0u8; 1024];
+        while !done.load(Ordering::Relaxed) {
+            let current = window_size(stdin.as_fd());
+            if let Some(current) = current.filter(|current| {
+                size.is_none_or(|size| {
+                    (size.ws_row, size.ws_col) != (current.ws_row, current.ws_col)
+                })
+            }) {
+                // SAFETY: TIOCSWINSZ only reads the `winsize` it is given
+                let _ = unsafe { set_window_size(master.as_raw_fd(), &current) };
+                size = Some(current);
+            }
+            let ready = poll(
+                &mut [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)],
+                POLL_MS,
+            )
+            .unwrap_or(0);
+            if ready == 0 {
+                continue;
+            }
+            match nix::unistd::read(stdin.as_raw_fd(), &mut buffer) {
+                Ok(0) | Err(_) => std::thread::sleep(Duration::from_millis(50)),
+                Ok(read) => {
+                    if master.write_all(&buffer[..read]).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcripts_keep_their_first_and_last_bytes() {
+        let mut transcript = Transcript::new(8);
+        transcript.push(b"abc");
+        transcript.push(b"defghijkl");
+        transcript.push(b"mn");
+
+        assert_eq!(
+            transcript.into_parts(),
+            ("ab".to_string(), 6, "ijklmn".to_string())
+        );
+    }
+}
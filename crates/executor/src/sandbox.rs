@@ -0,0 +1,316 @@
+//! Confining generated commands: a read-only view of the system with only the working
+//! directory writable, and no network unless allowed. Each backend wraps the prepared
+//! command in the program that confines it; commands run unconfined without one.
+
+use crate::hooks::PreparedCommand;
+use parsec_core::{tools, ExecutionError, ExecutionInput};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A way of confining a command.
+pub trait SandboxBackend: Send + Sync {
+    /// Short name recorded on the attempts it confined, such as `bubblewrap`.
+    fn name(&self) -> &'static str;
+
+    /// Fail with what is missing when the backend cannot run here.
+    fn check(&self) -> Result<(), ExecutionError>;
+
+    /// The program and arguments running `prepared` confined, able to write to its working
+    /// directory and to `writable`, and to reach the network when `network` is set.
+    fn wrap(
+        &self,
+        prepared: &PreparedCommand,
+        writable: &[&Path],
+        network: bool,
+    ) -> (String, Vec<String>);
+}
+
+/// The backend called `name` in the configuration, checked: `none` for none, or
+/// `bubblewrap`, `firejail` or `docker`, which runs commands in `image`.
+pub fn named(name: &str, image: &str) -> Result<Option<Arc<dyn SandboxBackend>>, ExecutionError> {
+    let backend: Arc<dyn SandboxBackend> = match name {
+        "none" => return Ok(None),
+        "bubblewrap" => Arc::new(Bubblewrap),
+        "firejail" => Arc::new(Firejail),
+        "docker" => Arc::new(Docker {
+            image: image.to_string(),
+        }),
+        other => {
+            return Err(ExecutionError::SandboxUnavailable(format!(
+                "unknown sandbox {} (expected none, bubblewrap, firejail or docker)",
+                other
+            )))
+        }
+    };
+    backend.check()?;
+    Ok(Some(backend))
+}
+
+fn require(backend: &str, program: &str) -> Result<(), ExecutionError> {
+    if tools::detect_tools(&[program.to_string()]).is_empty() {
+        return Err(ExecutionError::SandboxUnavailable(format!(
+            "the {} sandbox needs `{}`, which is not on PATH",
+            backend, program
+        )));
+    }
+    Ok(())
+}
+
+/// Directories to make writable: the working directory and those of `writable` that
+/// exist, as a sandbox cannot mount what is not there.
+fn writable_dirs(prepared: &PreparedCommand, writable: &[&Path]) -> Vec<String> {
+    std::iter::once(prepared.working_dir.as_path())
+        .chain(writable.iter().copied().filter(|dir| dir.is_dir()))
+        .map(|dir| dir.display().to_string())
+        .collect()
+}
+
+/// Linux namespaces through `bwrap`: the root mounted read-only, a private `/tmp`, and
+/// the writable directories bound back in.
+pub struct Bubblewrap;
+
+impl SandboxBackend for Bubblewrap {
+    fn name(&self) -> &'static str {
+        "bubblewrap"
+    }
+
+    fn check(&self) -> Result<(), ExecutionError> {
+        require(self.name(), "bwrap")
+    }
+
+    fn wrap(
+        &self,
+        prepared: &PreparedCommand,
+        writable: &[&Path],
+        network: bool,
+    ) -> (String, Vec<String>) {
+        let mut args: Vec<String> = ["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]
+            .into_iter()
+            .chain(["--tmpfs", "/tmp", "--die-with-parent"])
+            .map(str::to_string)
+            .collect();
+        for dir in writable_dirs(prepared, writable) {
+            args.extend(["--bind".to_string(), dir.clone(), dir]);
+        }
+        if !network {
+            args.push("--unshare-net".to_string());
+        }
+        // A session of its own keeps the command from typing into the user's terminal,
+        // unless it is attached to one
+        if prepared.input != ExecutionInput::Terminal {
+            args.push("--new-session".to_string());
+        }
+        args.extend([
+            "--chdir".to_string(),
+            prepared.working_dir.display().to_string(),
+            "--".to_string(),
+            prepared.program.clone(),
+        ]);
+        args.extend(prepared.args.iter().cloned());
+        ("bwrap".to_string(), args)
+    }
+}
+
+/// `firejail` without a profile: the root read-only and the writable directories not.
+pub struct Firejail;
+
+impl SandboxBackend for Firejail {
+    fn name(&self) -> &'static str {
+        "firejail"
+    }
+
+    fn check(&self) -> Result<(), ExecutionError> {
+        require(self.name(), "firejail")
+    }
+
+    fn wrap(
+        &self,
+        prepared: &PreparedCommand,
+        writable: &[&Path],
+        network: bool,
+    ) -> (String, Vec<String>) {
+        let mut args = vec![
+            "--quiet".to_string(),
+            "--noprofile".to_string(),
+            "--read-only=/".to_string(),
+        ];
+        args.extend(
+            writable_dirs(prepared, writable)
+                .into_iter()
+                .map(|dir| format!("--read-write={}", dir)),
+        );
+        if !network {
+            args.push("--net=none".to_string());
+        }
+        args.push("--".to_string());
+        args.push(prepared.program.clone());
+        args.extend(prepared.args.iter().cloned());
+        ("firejail".to_string(), args)
+    }
+}
+
+/// A throwaway container of `image`, with the writable directories bind-mounted at the
+/// same paths and the command running as the user. The container has its own
+/// environment: only the variables parsec sets on the command are passed in. A command
+/// killed at its timeout stops the `docker` client, not the container.
+pub struct Docker {
+    pub image: String,
+}
+
+impl SandboxBackend for Docker {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn check(&self) -> Result<(), ExecutionError> {
+        require(self.name(), "docker")
+    }
+
+    fn wrap(
+        &self,
+        prepared: &PreparedCommand,
+        writable: &[&Path],
+        network: bool,
+    ) -> (String, Vec<String>) {
+        let mut args = vec!["run".to_string(), "--rm".to_string(), "--init".to_string()];
+        match prepared.input {
+            ExecutionInput::Closed => {}
+            ExecutionInput::Text(_) => args.push("--interactive".to_string()),
+            ExecutionInput::Terminal => {
+                args.extend(["--interactive".to_string(), "--tty".to_string()])
+            }
+        }
+        if !network {
+            args.extend(["--network".to_string(), "none".to_string()]);
+        }
+        #[cfg(unix)]
+        args.extend([
+            "--user".to_string(),
+            format!("{}:{}", nix::unistd::getuid(), nix::unistd::getgid()),
+        ]);
+        for dir in writable_dirs(prepared, writable) {
+            args.extend(["--volume".to_string(), format!("{}:{}", dir, dir)]);
+        }
+        args.extend([
+            "--workdir".to_string(),
+            prepared.working_dir.display().to_string(),
+        ]);
+        for name in prepared.env.keys() {
+            args.extend(["--env".to_string(), name.clone()]);
+        }
+        args.push(self.image.clone());
+        args.push(prepared.program.clone());
+        args.extend(prepared.args.iter().cloned());
+        ("docker".to_string(), args)
+    }
+}
+
+/// Programs, with the subcommand where it matters, that fetch or send something over
+/// the network.
+const NETWORK_COMMANDS: &[&[&str]] = &[
+    &["curl"],
+    &["wget"],
+    &["ssh"],
+    &["scp"],
+    &["sftp"],
+    &["ping"],
+    &["git", "clone"],
+    &["git", "fetch"],
+    &["git", "pull"],
+    &["git", "push"],
+    &["git", "ls-remote"],
+    &["npm", "install"],
+    &["npm", "i"],
+    &["npm", "ci"],
+    &["npm", "update"],
+    &["npm", "publish"],
+    &["yarn", "add"],
+    &["yarn", "install"],
+    &["pnpm", "add"],
+    &["pnpm", "install"],
+    &["pip", "install"],
+    &["pip", "download"],
+    &["pip3", "install"],
+    &["pip3", "download"],
+    &["cargo", "install"],
+    &["cargo", "fetch"],
+    &["cargo", "update"],
+    &["cargo", "add"],
+    &["cargo", "publish"],
+    &["go", "get"],
+    &["go", "install"],
+    &["go", "mod", "download"],
+    &["apt", "install"],
+    &["apt", "update"],
+    &["apt-get", "install"],
+    &["apt-get", "update"],
+    &["brew", "install"],
+    &["brew", "update"],
+    &["docker", "pull"],
+    &["docker", "push"],
+    &["gh"],
+];
+
+/// Why `command` needs the network, if one of its commands is known to: the first of
+/// `NETWORK_COMMANDS` any line of it runs.
+pub fn network_reason(command: &str) -> Option<String> {
+    command.lines().find_map(|line| {
+        let tokens = tools::tokenize_command(line);
+        tokens
+            .split(|token| matches!(token, tools::CommandToken::Operator(_)))
+            .find_map(|statement| {
+                let words: Vec<&str> = statement
+                    .iter()
+                    .filter_map(|token| match token {
+                        tools::CommandToken::Word(word) => Some(word.as_str()),
+                        tools::CommandToken::Operator(_) => None,
+                    })
+                    .skip_while(|word| {
+                        word.contains('=')
+                            || matches!(*word, "sudo" | "env" | "nice" | "nohup" | "time")
+                    })
+                    .collect();
+                let (program, args) = words.split_first()?;
+                let program = Path::new(program).file_name()?.to_str()?;
+                let known = NETWORK_COMMANDS
+                    .iter()
+                    .find(|known| known[0] == program && args.starts_with(&known[1..]))?;
+                Some(format!("`{}` uses the network", known.join(" ")))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::CommandKind;
+    use std::path::PathBuf;
+
+    #[test]
+    fn commands_are_wrapped_to_write_only_where_they_work() {
+        let prepared = PreparedCommand {
+            command: "make".to_string(),
+            kind: CommandKind::Step,
+            program: "make".to_string(),
+            args: vec!["all".to_string()],
+            working_dir: PathBuf::from("/"),
+            env: Default::default(),
+            input: ExecutionInput::Closed,
+        };
+        let (program, args) = Bubblewrap.wrap(&prepared, &[Path::new("/nonexistent")], false);
+
+        assert_eq!(program, "bwrap");
+        assert!(args.windows(3).any(|bind| bind == ["--bind", "/", "/"]));
+        assert!(!args.iter().any(|arg| arg == "/nonexistent"));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert!(args.ends_with(&["--".to_string(), "make".to_string(), "all".to_string()]));
+
+        assert_eq!(
+            network_reason("cd app\nFOO=1 sudo git clone https://example.com/x.git"),
+            Some("`git clone` uses the network".to_string())
+        );
+        assert_eq!(network_reason("git status && cargo build"), None);
+        assert!(named("chroot", "").is_err());
+        assert!(named("none", "").unwrap().is_none());
+    }
+}
@@ -0,0 +1,151 @@
+//! Cleanup of captured command output before it is stored or shown to the model.
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+/// `raw` as a terminal would finally show it, minus styling: ANSI escape sequences are
+/// removed, lines rewritten with `\r` or erased with `ESC[K` keep only their last state,
+/// backspaces move back, and other control characters and trailing spaces are dropped.
+/// Tabs and newlines stay.
+pub fn sanitize(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut line = Line::default();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                out.push_str(line.finish().trim_end_matches(' '));
+                out.push('\n');
+            }
+            '\r' => line.cursor = 0,
+            '\u{8}' => line.cursor = line.cursor.saturating_sub(1),
+            '\t' => line.write('\t'),
+            ESC => match chars.next() {
+                // Control sequence: parameters, intermediates, then one final byte
+                Some('[') => {
+                    let mut params = String::new();
+                    for c in chars.by_ref() {
+                        if ('\u{40}'..='\u{7e}').contains(&c) {
+                            line.control(c, &params);
+                            break;
+                        }
+                        params.push(c);
+                    }
+                }
+                // Operating system command (titles, hyperlinks), ended by BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == BEL || (c == ESC && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Two-character escapes such as ESC 7 / ESC 8 carry no text
+                _ => {}
+            },
+            c if c.is_control() => {}
+            c => line.write(c),
+        }
+    }
+    out.push_str(line.finish().trim_end_matches(' '));
+    out
+}
+
+/// The line being drawn, with the cursor position `\r`, backspace and `ESC[G` move.
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn write(&mut self, c: char) {
+        if self.cursor < self.chars.len() {
+            self.chars[self.cursor] = c;
+        } else {
+            self.chars.resize(self.cursor, ' ');
+            self.chars.push(c);
+        }
+        self.cursor += 1;
+    }
+
+    /// Apply the cursor and erase sequences that change the final text; colors and
+    /// every other sequence are dropped.
+    fn control(&mut self, command: char, params: &str) {
+        let n = params.parse::<usize>().ok();
+        match command {
+            // Erase in line: to the end, to the start, or all of it
+            'K' => match n.unwrap_or(0) {
+                0 => self.chars.truncate(self.cursor),
+                1 => {
+                    let end = self.cursor.min(self.chars.len());
+                    self.chars[..end].fill(' ');
+                }
+                _ => self.chars.clear(),
+            },
+            // Cursor to column n (1-based)
+            'G' => self.cursor = n.unwrap_or(1).saturating_sub(1),
+            'C' => self.cursor += n.unwrap_or(1),
+            'D' => self.cursor = self.cursor.saturating_sub(n.unwrap_or(1)),
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.chars).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cargo_colors_and_progress_bar_are_removed() {
+        let clean = sanitize(include_str!("../fixtures/cargo_build.txt"));
+
+        assert!(!clean.contains(ESC));
+        assert!(!clean.contains('\r'));
+        assert!(!clean.contains("Building ["));
+        assert!(
+            clean.starts_with("   Compiling fx v0.1.0 (/tmp/fx)\nwarning: unused variable: `x`\n")
+        );
+        assert!(clean.contains("1 | fn main(){ let x = 1;\n"));
+        assert!(clean.contains("\n\nwarning: `fx` (bin \"fx\") generated 1 warning"));
+        assert!(clean.ends_with(
+            "    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.28s\n"
+        ));
+    }
+
+    #[test]
+    fn npm_spinner_is_erased() {
+        let clean = sanitize(include_str!("../fixtures/npm_install.txt"));
+
+        let lines: Vec<&str> = clean.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "npm error code ENOTCACHED");
+        assert!(
+            lines[1].starts_with("npm error request to https://registry.npmjs.org/left-pad failed")
+        );
+        assert!(lines[2].starts_with("npm error A complete log of this run can be found in:"));
+        assert!(clean.ends_with(".log\n"));
+    }
+
+    #[test]
+    fn carriage_returns_keep_the_last_state_of_the_line() {
+        assert_eq!(sanitize("10%\r50%\r100%\ndone"), "100%\ndone");
+        assert_eq!(sanitize("downloading...\rok"), "okwnloading...");
+        assert_eq!(sanitize("downloading...\r\x1b[Kok\n"), "ok\n");
+        assert_eq!(sanitize("abc\x08\x08X\n"), "aXc\n");
+    }
+
+    #[test]
+    fn titles_and_stray_control_characters_are_dropped() {
+        assert_eq!(
+            sanitize("\x1b]0;building\x07\x1b]8;;https://x\x1b\\link\x1b]8;;\x1b\\\x00\tend"),
+            "link\tend"
+        );
+    }
+}
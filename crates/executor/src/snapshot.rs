@@ -0,0 +1,187 @@
+use chrono::{DateTime, Utc};
+use parsec_core::{ArtifactChange, ArtifactInfo};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Directories never walked: version control, dependency caches and build output.
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+/// Bounds on the working-directory walk done around every step command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotOptions {
+    /// Directory levels below the root that are walked.
+    pub max_depth: usize,
+    /// Files recorded before the walk stops.
+    pub max_entries: usize,
+    /// A top-level directory with more changed files than this is recorded as one
+    /// directory artifact instead of file by file.
+    pub collapse_threshold: usize,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 6,
+            max_entries: 10_000,
+            collapse_threshold: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FileState {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Paths, sizes and modification times of the files under a directory.
+#[derive(Debug, Default)]
+pub struct DirSnapshot {
+    /// Relative to the snapshot root.
+    files: HashMap<PathBuf, FileState>,
+}
+
+impl DirSnapshot {
+    /// Walk `root` within `options`' limits. Unreadable entries are skipped.
+    pub fn capture(root: &Path, options: &SnapshotOptions) -> Self {
+        let mut snapshot = Self::default();
+        let mut pending = vec![(root.to_path_buf(), 0)];
+        while let Some((dir, depth)) = pending.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if snapshot.files.len() >= options.max_entries {
+                    debug!(
+                        "Stopped snapshotting {} at {} files",
+                        root.display(),
+                        options.max_entries
+                    );
+                    return snapshot;
+                }
+                // Symlinks are not followed
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let path = entry.path();
+                if file_type.is_dir() {
+                    let skipped = SKIPPED_DIRS.iter().any(|name| entry.file_name() == *name);
+                    if !skipped && depth < options.max_depth {
+                        pending.push((path, depth + 1));
+                    }
+                } else if file_type.is_file() {
+                    let Ok(metadata) = entry.metadata() else {
+                        continue;
+                    };
+                    let Ok(relative) = path.strip_prefix(root) else {
+                        continue;
+                    };
+                    snapshot.files.insert(
+                        relative.to_path_buf(),
+                        FileState {
+                            size: metadata.len(),
+                            modified: metadata.modified().ok(),
+                        },
+                    );
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Files created or modified between `self` and `after`, sorted by path. Top-level
+    /// directories with more than `collapse_threshold` changes become one artifact.
+    pub fn diff(&self, after: &DirSnapshot, collapse_threshold: usize) -> Vec<ArtifactInfo> {
+        let now = Utc::now();
+        let mut by_dir: BTreeMap<Option<PathBuf>, Vec<ArtifactInfo>> = BTreeMap::new();
+        for (path, state) in &after.files {
+            let change = match self.files.get(path) {
+                None => ArtifactChange::Created,
+                Some(before) if before != state => ArtifactChange::Modified,
+                Some(_) => continue,
+            };
+            let top_dir = (path.components().count() > 1)
+                .then(|| path.components().next())
+                .flatten()
+                .map(|component| PathBuf::from(component.as_os_str()));
+            by_dir.entry(top_dir).or_default().push(ArtifactInfo {
+                file_path: path.clone(),
+                artifact_type: artifact_type(path).to_string(),
+                created_at: state.modified.map(DateTime::<Utc>::from).unwrap_or(now),
+                size_bytes: state.size,
+                change,
+            });
+        }
+
+        let mut artifacts = Vec::new();
+        for (dir, mut files) in by_dir {
+            match dir {
+                Some(dir) if files.len() > collapse_threshold => {
+                    // A directory that did not exist before counts as created
+                    let existed = self.files.keys().any(|path| path.starts_with(&dir));
+                    artifacts.push(ArtifactInfo {
+                        artifact_type: "directory".to_string(),
+                        created_at: files
+                            .iter()
+                            .map(|file| file.created_at)
+                            .max()
+                            .unwrap_or(now),
+                        size_bytes: files.iter().map(|file| file.size_bytes).sum(),
+                        change: if existed {
+                            ArtifactChange::Modified
+                        } else {
+                            ArtifactChange::Created
+                        },
+                        file_path: dir,
+                    });
+                }
+                _ => artifacts.append(&mut files),
+            }
+        }
+        artifacts.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        artifacts
+    }
+}
+
+/// Kind of file, inferred from its extension (or name, for a few well-known files).
+pub fn artifact_type(path: &Path) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    match name {
+        "Dockerfile" => return "docker",
+        "Makefile" => return "makefile",
+        _ => {}
+    }
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "hpp" => "cpp",
+        "sh" | "bash" | "zsh" => "shell",
+        "toml" => "toml",
+        "json" => "json",
+        "yml" | "yaml" => "yaml",
+        "md" => "markdown",
+        "txt" => "text",
+        "html" | "htm" => "html",
+        "css" | "scss" => "css",
+        "lock" => "lockfile",
+        "png" | "jpg" | "jpeg" | "gif" | "svg" => "image",
+        "zip" | "tar" | "gz" | "tgz" => "archive",
+        _ => "file",
+    }
+}
@@ -0,0 +1,363 @@
+//! Session store benchmarks, each run against the in-memory and the file store so they
+//! can be compared. The target of each benchmark is printed before it runs.
+
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use parsec_core::search::SearchScope;
+use parsec_core::*;
+use parsec_model::{FileSessionStore, InMemorySessionStore};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A store to benchmark, with the directory the file store keeps its files in.
+struct Store {
+    name: &'static str,
+    store: Box<dyn SessionStore>,
+    dir: Option<PathBuf>,
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// An empty store of each kind.
+fn stores() -> Vec<Store> {
+    let dir = std::env::temp_dir().join(format!("parsec-bench-{}", uuid::Uuid::new_v4()));
+    vec![
+        Store {
+            name: "memory",
+            store: Box::new(InMemorySessionStore::new()),
+            dir: None,
+        },
+        Store {
+            name: "file",
+            store: Box::new(FileSessionStore::new(&dir).unwrap()),
+            dir: Some(dir),
+        },
+    ]
+}
+
+fn target(benchmark: &str, target: &str) {
+    println!("{}: target {}", benchmark, target);
+}
+
+fn text(content: String) -> TruncatedText {
+    TruncatedText {
+        original_length: content.len(),
+        content,
+        truncated: false,
+    }
+}
+
+fn execution(i: usize) -> DirectCommandExecution {
+    DirectCommandExecution {
+        command: format!("cargo test --package crate-{}", i),
+        executed_at: Utc::now(),
+        exit_status: 0,
+        stdout: text(format!("test result: ok. {} passed\n", i).repeat(16)),
+        stderr: text(String::new()),
+        working_directory: ".".into(),
+        classification_overridden: false,
+        duration: Duration::from_millis(20),
+        completed_at: Some(Utc::now()),
+        termination: Some(CommandTermination::Exited(0)),
+    }
+}
+
+fn session(id: String, history: usize, conversations: usize) -> Session {
+    Session {
+        schema_version: SCHEMA_VERSION,
+        conversations: (0..conversations)
+            .map(|i| format!("{}-conversation-{}", id, i))
+            .collect(),
+        id,
+        created_at: Utc::now(),
+        last_active: Utc::now(),
+        command_history: (0..history).map(execution).collect(),
+        global_context: GlobalContext {
+            working_directory: ".".into(),
+            environment_snapshot: HashMap::new(),
+            detected_project_type: None,
+            active_tools: Vec::new(),
+            tools_cache_key: None,
+        },
+        settings: SessionSettings::default(),
+        last_input_kind: None,
+        classification_log: Vec::new(),
+        classification_corrections: Vec::new(),
+        context_items: Vec::new(),
+        provider_health: None,
+        step_timings: Vec::new(),
+    }
+}
+
+fn conversation(id: String, session_id: &str) -> ConversationContext {
+    ConversationContext {
+        schema_version: SCHEMA_VERSION,
+        name: id.clone(),
+        auto_name: id.clone(),
+        id,
+        session_id: session_id.to_string(),
+        user_prompt: "set up the project".to_string(),
+        created_at: Utc::now(),
+        workflow: None,
+        steps: Vec::new(),
+        status: ConversationStatus::Finished,
+        history: Vec::new(),
+        model_provider: "bench".to_string(),
+        context_summary: ContextSummary {
+            key_achievements: Vec::new(),
+            generated_artifacts: Vec::new(),
+            environment_changes: Vec::new(),
+            learned_preferences: HashMap::new(),
+            summarized_steps: Vec::new(),
+        },
+        classification_overridden: false,
+        plan_edited: false,
+        forked_from: None,
+        parent_conversation: None,
+        task: None,
+        tags: Vec::new(),
+        preflight: Vec::new(),
+        revision: 0,
+        outcome: None,
+        notes: Vec::new(),
+        pinned: false,
+        context_free: false,
+        answer: None,
+        attachments: Vec::new(),
+    }
+}
+
+fn attempt(command: String, stdout: String) -> CommandAttempt {
+    CommandAttempt {
+        candidate: GeneratedCommand {
+            command,
+            explanation: String::new(),
+            risk_score: None,
+            expected_duration: None,
+            missing_tools: Vec::new(),
+            kind: CommandKind::Single,
+            stdin: None,
+            interactive: false,
+            undo_command: None,
+        },
+        approved: true,
+        executed: true,
+        exit_status: Some(0),
+        stdout: text(stdout),
+        stderr: text(String::new()),
+        error: None,
+        timestamp: Utc::now(),
+        duration: Duration::from_millis(1500),
+        completed_at: Some(Utc::now()),
+        termination: Some(CommandTermination::Exited(0)),
+        timeout: Some(Duration::from_secs(300)),
+        attached: false,
+        environment_changes: Vec::new(),
+        sandbox: None,
+    }
+}
+
+/// A conversation of 100 steps, each with three attempts that printed 64KB.
+fn large_conversation() -> ConversationContext {
+    let steps: Vec<WorkflowStep> = (1..=100)
+        .map(|i| WorkflowStep {
+            id: i.to_string(),
+            description: format!("Build stage {}", i),
+            depends_on: None,
+            estimated_minutes: None,
+            estimate_source: EstimateSource::Planner,
+        })
+        .collect();
+    let output = "compiling module\n".repeat(64 * 1024 / 17);
+    let mut conversation = conversation("large".to_string(), "session");
+    conversation.steps = steps
+        .iter()
+        .map(|step| WorkflowStepState {
+            step: step.clone(),
+            status: StepStatus::Complete,
+            command_attempts: (0..3)
+                .map(|i| attempt(format!("make stage-{}-{}", step.id, i), output.clone()))
+                .collect(),
+            context_used: StepContext {
+                working_directory: ".".into(),
+                environment_vars: HashMap::new(),
+                previous_outputs: Vec::new(),
+                error_context: None,
+            },
+            artifacts_produced: Vec::new(),
+            user_hints: Vec::new(),
+        })
+        .collect();
+    conversation.workflow = Some(WorkflowPlan { steps });
+    conversation
+}
+
+/// Store overhead of one interactive input: the session loaded and saved back.
+fn input_round_trip(c: &mut Criterion) {
+    target(
+        "input_round_trip",
+        "under 5 ms with 1000 history entries and 50 conversations",
+    );
+    target("list_conversations", "under 1 ms for 50 conversations");
+    let mut round_trip = c.benchmark_group("input_round_trip");
+    let stores = stores();
+    for Store { name, store, .. } in &stores {
+        store
+            .save_session(&session("session".to_string(), 1000, 50))
+            .unwrap();
+        for i in 0..50 {
+            let mut conversation = conversation(format!("session-conversation-{}", i), "session");
+            store.save_conversation(&mut conversation, None).unwrap();
+        }
+        let session_id = "session".to_string();
+        round_trip.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut session = store.load_session(black_box(&session_id)).unwrap();
+                session.last_active = Utc::now();
+                store.save_session(&session).unwrap();
+            })
+        });
+    }
+    round_trip.finish();
+
+    let mut list = c.benchmark_group("list_conversations");
+    let filter = ConversationFilter {
+        session_id: Some("session".to_string()),
+        ..Default::default()
+    };
+    for Store { name, store, .. } in &stores {
+        list.bench_function(*name, |b| {
+            b.iter(|| black_box(store.list_conversations(&filter).unwrap()))
+        });
+    }
+    list.finish();
+}
+
+/// A conversation of about 19MB saved and loaded.
+fn large_conversations(c: &mut Criterion) {
+    target(
+        "save_large_conversation",
+        "under 250 ms for 100 steps of three 64KB attempts",
+    );
+    target(
+        "load_large_conversation",
+        "under 250 ms for 100 steps of three 64KB attempts",
+    );
+    let large = large_conversation();
+    let stores = stores();
+
+    let mut save = c.benchmark_group("save_large_conversation");
+    save.sample_size(10);
+    for Store { name, store, .. } in &stores {
+        save.bench_function(*name, |b| {
+            b.iter_batched(
+                || large.clone(),
+                |mut conversation| store.save_conversation(&mut conversation, None).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    save.finish();
+
+    let mut load = c.benchmark_group("load_large_conversation");
+    load.sample_size(10);
+    for Store { name, store, .. } in &stores {
+        store.save_conversation(&mut large.clone(), None).unwrap();
+        load.bench_function(*name, |b| {
+            b.iter(|| black_box(store.load_conversation(&large.id).unwrap()))
+        });
+    }
+    load.finish();
+}
+
+/// Stores of 500 sessions with 20 history entries each.
+fn many_sessions() -> Vec<Store> {
+    let stores = stores();
+    for Store { store, .. } in &stores {
+        for i in 0..500 {
+            store
+                .save_session(&session(format!("session-{}", i), 20, 0))
+                .unwrap();
+        }
+    }
+    stores
+}
+
+fn listing_and_search(c: &mut Criterion) {
+    target("list_active_sessions", "under 100 ms for 500 sessions");
+    target(
+        "search",
+        "under 100 ms through 500 sessions of 20 commands each",
+    );
+    let stores = many_sessions();
+
+    let mut list = c.benchmark_group("list_active_sessions");
+    for Store { name, store, .. } in &stores {
+        list.bench_function(*name, |b| {
+            b.iter(|| black_box(store.list_active_sessions().unwrap()))
+        });
+    }
+    list.finish();
+
+    let mut search = c.benchmark_group("search");
+    search.sample_size(20);
+    let scope = SearchScope {
+        limit: 20,
+        ..Default::default()
+    };
+    for Store { name, store, .. } in &stores {
+        search.bench_function(*name, |b| {
+            b.iter(|| black_box(store.search("cargo test crate-7", &scope).unwrap()))
+        });
+    }
+    search.finish();
+}
+
+/// 50 expired sessions pruned from among 500 kept.
+fn pruning(c: &mut Criterion) {
+    target(
+        "prune",
+        "under 150 ms to remove 50 expired sessions from among 500",
+    );
+    let policy = RetentionPolicy {
+        session_retention_days: 30,
+        conversation_retention_days: 30,
+        max_sessions: None,
+    };
+    let stores = many_sessions();
+
+    let mut prune = c.benchmark_group("prune");
+    prune.sample_size(10);
+    for Store { name, store, .. } in &stores {
+        prune.bench_function(*name, |b| {
+            b.iter_batched(
+                || {
+                    for i in 0..50 {
+                        let mut expired = session(format!("expired-{}", i), 20, 0);
+                        expired.last_active = Utc::now() - chrono::Duration::days(60);
+                        store.save_session(&expired).unwrap();
+                    }
+                },
+                |()| store.prune_old_context(&policy, None).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    prune.finish();
+}
+
+criterion_group!(
+    benches,
+    input_round_trip,
+    large_conversations,
+    listing_and_search,
+    pruning
+);
+criterion_main!(benches);
@@ -0,0 +1,420 @@
+//! Google application default credentials, for Vertex AI. Credentials come from the file
+//! `GOOGLE_APPLICATION_CREDENTIALS` names, else from the one `gcloud auth
+//! application-default login` writes, else from the metadata server of the machine
+//! parsec runs on. `TokenSource` turns them into OAuth access tokens, fetching a new one
+//! shortly before the current one expires.
+
+use crate::ModelClientError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_HOST: &str = "metadata.google.internal";
+const METADATA_TOKEN_PATH: &str = "/computeMetadata/v1/instance/service-accounts/default/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Tokens are refreshed once they have less than this left.
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// What to run when no credentials were found.
+const LOGIN_HINT: &str =
+    "run `gcloud auth application-default login` or set GOOGLE_APPLICATION_CREDENTIALS";
+
+fn auth_error(message: impl std::fmt::Display) -> ModelClientError {
+    ModelClientError::Auth(message.to_string())
+}
+
+fn default_token_uri() -> String {
+    DEFAULT_TOKEN_URI.to_string()
+}
+
+/// Where access tokens come from.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Credentials {
+    /// A user's refresh token, as `gcloud auth application-default login` saves it.
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+        #[serde(default = "default_token_uri")]
+        token_uri: String,
+        #[serde(default)]
+        quota_project_id: Option<String>,
+    },
+    /// A service account key, exchanged for tokens with a signed assertion.
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        #[serde(default = "default_token_uri")]
+        token_uri: String,
+        #[serde(default)]
+        project_id: Option<String>,
+    },
+    /// The service account of the machine, from its metadata server at `host`.
+    #[serde(skip)]
+    MetadataServer { host: String },
+}
+
+impl Credentials {
+    /// Application default credentials, looked for in the order the module describes.
+    /// Whether the metadata server is there is only known once a token is asked for.
+    pub fn application_default() -> Result<Self, ModelClientError> {
+        if let Some(path) = std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+            return Self::from_file(Path::new(&path));
+        }
+        if let Some(path) = gcloud_credentials_file().filter(|path| path.exists()) {
+            return Self::from_file(&path);
+        }
+        Ok(Self::MetadataServer {
+            host: std::env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.to_string()),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ModelClientError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            auth_error(format!(
+                "cannot read credentials file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        serde_json::from_str(&text).map_err(|e| {
+            auth_error(format!(
+                "{} is not an authorized_user or service_account credentials file: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// The project the credentials name, if any.
+    pub fn project(&self) -> Option<&str> {
+        match self {
+            Self::AuthorizedUser {
+                quota_project_id, ..
+            } => quota_project_id.as_deref(),
+            Self::ServiceAccount { project_id, .. } => project_id.as_deref(),
+            Self::MetadataServer { .. } => None,
+        }
+    }
+}
+
+/// Where `gcloud` keeps application default credentials.
+fn gcloud_credentials_file() -> Option<PathBuf> {
+    let config = match std::env::var_os("CLOUDSDK_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?).join("gcloud"),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config/gcloud"),
+    };
+    Some(config.join("application_default_credentials.json"))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+struct AccessToken {
+    value: String,
+    expires_at: Instant,
+}
+
+/// Access tokens for `credentials`, fetched when first needed and again shortly before
+/// they expire. Concurrent callers wait for one refresh.
+pub struct TokenSource {
+    credentials: Credentials,
+    client: Client,
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl TokenSource {
+    pub fn new(credentials: Credentials, client: Client) -> Self {
+        Self {
+            credentials,
+            client,
+            token: Mutex::new(None),
+        }
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// A token valid for at least a few more minutes.
+    pub async fn token(&self) -> Result<String, ModelClientError> {
+        let mut token = self.token.lock().await;
+        if let Some(current) = token
+            .as_ref()
+            .filter(|current| current.expires_at > Instant::now() + REFRESH_MARGIN)
+        {
+            return Ok(current.value.clone());
+        }
+        let fetched = self.fetch().await?;
+        let value = fetched.access_token.clone();
+        *token = Some(AccessToken {
+            value: fetched.access_token,
+            expires_at: Instant::now() + Duration::from_secs(fetched.expires_in),
+        });
+        Ok(value)
+    }
+
+    async fn fetch(&self) -> Result<TokenResponse, ModelClientError> {
+        let (request, source) = match &self.credentials {
+            Credentials::AuthorizedUser {
+                client_id,
+                client_secret,
+                refresh_token,
+                token_uri,
+                ..
+            } => (
+                self.client.post(token_uri).form(&[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("refresh_token", refresh_token),
+                ]),
+                "refreshing the user credentials",
+            ),
+            Credentials::ServiceAccount {
+                client_email,
+                private_key,
+                token_uri,
+                ..
+            } => {
+                let assertion = sign_assertion(client_email, private_key, token_uri)?;
+                (
+                    self.client
+                        .post(token_uri)
+                        .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", &assertion)]),
+                    "exchanging the service account key",
+                )
+            }
+            Credentials::MetadataServer { host } => (
+                self.client
+                    .get(format!("http://{}{}", host, METADATA_TOKEN_PATH))
+                    .header("Metadata-Flavor", "Google"),
+                "asking the metadata server",
+            ),
+        };
+
+        let response = request.send().await.map_err(|e| {
+            match self.credentials {
+                // Not on Google Cloud: there were no credentials to begin with
+                Credentials::MetadataServer { .. } => auth_error(format!(
+                    "no application default credentials found ({}; the metadata server is unreachable: {})",
+                    LOGIN_HINT, e
+                )),
+                _ => auth_error(format!("{} failed: {}", source, e)),
+            }
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(auth_error(format!(
+                "{} failed ({}): {}; {}",
+                source,
+                status,
+                body.trim(),
+                LOGIN_HINT
+            )));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| auth_error(format!("{} returned no token: {}", source, e)))
+    }
+}
+
+/// An RS256-signed assertion asking `token_uri` for a cloud-platform token for the
+/// service account `client_email`.
+fn sign_assertion(
+    client_email: &str,
+    private_key: &str,
+    token_uri: &str,
+) -> Result<String, ModelClientError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let claims = AssertionClaims {
+        iss: client_email,
+        scope: CLOUD_PLATFORM_SCOPE,
+        aud: token_uri,
+        iat: now,
+        exp: now + 3600,
+    };
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(private_key.as_bytes())
+        .map_err(|e| auth_error(format!("invalid service account private key: {}", e)))?;
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+        &claims,
+        &key,
+    )
+    .map_err(|e| auth_error(format!("cannot sign the service account assertion: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    /// A token endpoint on localhost answering each request with the next of
+    /// `responses`, as status and body, and recording the requests it got.
+    fn token_endpoint(responses: Vec<(u16, String)>) -> (String, Arc<StdMutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let Ok((stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut payload = vec![0; length];
+                reader.read_exact(&mut payload).unwrap();
+                request.push_str(&String::from_utf8_lossy(&payload));
+                recorded.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (address, requests)
+    }
+
+    fn token(value: &str, expires_in: u64) -> (u16, String) {
+        (
+            200,
+            format!(
+                r#"{{"access_token":"{}","expires_in":{},"token_type":"Bearer"}}"#,
+                value, expires_in
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn tokens_are_reused_until_they_near_expiry() {
+        let (address, requests) = token_endpoint(vec![token("first", 120), token("second", 3600)]);
+        let credentials = Credentials::AuthorizedUser {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            refresh_token: "refresh".to_string(),
+            token_uri: format!("http://{}/token", address),
+            quota_project_id: Some("project".to_string()),
+        };
+        let tokens = TokenSource::new(credentials, Client::new());
+
+        // Two minutes left is inside the refresh margin
+        assert_eq!(tokens.token().await.unwrap(), "first");
+        assert_eq!(tokens.token().await.unwrap(), "second");
+        assert_eq!(tokens.token().await.unwrap(), "second");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("POST /token"));
+        assert!(requests[0].contains("grant_type=refresh_token"));
+        assert!(requests[0].contains("refresh_token=refresh"));
+        assert_eq!(tokens.credentials().project(), Some("project"));
+    }
+
+    #[tokio::test]
+    async fn metadata_server_tokens_and_refresh_failures() {
+        let (address, requests) = token_endpoint(vec![
+            token("instance", 3600),
+            (400, r#"{"error":"invalid_grant"}"#.to_string()),
+        ]);
+        let metadata = TokenSource::new(
+            Credentials::MetadataServer {
+                host: address.clone(),
+            },
+            Client::new(),
+        );
+        assert_eq!(metadata.token().await.unwrap(), "instance");
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with(&format!("GET {}", METADATA_TOKEN_PATH)));
+        assert!(request.to_lowercase().contains("metadata-flavor: google"));
+
+        let revoked = TokenSource::new(
+            Credentials::AuthorizedUser {
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+                refresh_token: "revoked".to_string(),
+                token_uri: format!("http://{}/token", address),
+                quota_project_id: None,
+            },
+            Client::new(),
+        );
+        let error = revoked.token().await.unwrap_err().to_string();
+        assert!(error.contains("invalid_grant"), "{}", error);
+        assert!(error.contains("gcloud auth application-default login"));
+
+        // Nothing listens here any more
+        let unreachable = TokenSource::new(
+            Credentials::MetadataServer {
+                host: "127.0.0.1:9".to_string(),
+            },
+            Client::new(),
+        );
+        let error = unreachable.token().await.unwrap_err().to_string();
+        assert!(error.contains("no application default credentials found"));
+    }
+
+    #[test]
+    fn credential_files_are_told_apart_by_type() {
+        let dir = std::env::temp_dir().join(format!("parsec-adc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.json");
+        std::fs::write(
+            &user,
+            r#"{"type":"authorized_user","client_id":"id","client_secret":"s","refresh_token":"r"}"#,
+        )
+        .unwrap();
+        let external = dir.join("external.json");
+        std::fs::write(&external, r#"{"type":"external_account"}"#).unwrap();
+
+        assert!(matches!(
+            Credentials::from_file(&user).unwrap(),
+            Credentials::AuthorizedUser { token_uri, .. } if token_uri == DEFAULT_TOKEN_URI
+        ));
+        assert!(Credentials::from_file(&external).is_err());
+        assert!(Credentials::from_file(&dir.join("missing.json")).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
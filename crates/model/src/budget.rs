@@ -0,0 +1,403 @@
+use chrono::{DateTime, Utc};
+use parsec_core::context::{self, ContextSelection};
+use parsec_core::injection;
+use parsec_core::{CommandAttempt, DirectCommandExecution, TruncatedText};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Size limit for a rendered command-generation prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptBudget {
+    pub max_chars: usize,
+    /// Most recent prior steps whose last attempt is always shown in full when it fits.
+    pub verbatim_steps: usize,
+    /// How many of the session's context items planning and command prompts include.
+    pub context: ContextSelection,
+    /// How many of the commands the user ran directly they include.
+    pub shell_history: ShellHistory,
+}
+
+impl Default for PromptBudget {
+    fn default() -> Self {
+        Self {
+            max_chars: 24_000,
+            verbatim_steps: 3,
+            context: ContextSelection::default(),
+            shell_history: ShellHistory::default(),
+        }
+    }
+}
+
+/// How `shell_history` picks the session's direct commands for a prompt: by recency and
+/// by the words and path components they share with the task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellHistory {
+    /// 0 leaves direct commands out of prompts.
+    pub max_commands: usize,
+    /// Characters the rendered commands may take in total.
+    pub max_chars: usize,
+    /// Age at which a command's recency weight has halved.
+    pub half_life: Duration,
+}
+
+impl Default for ShellHistory {
+    fn default() -> Self {
+        Self {
+            max_commands: 6,
+            max_chars: 1_500,
+            half_life: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Commands scoring below this are left out however few there are: old ones that share
+/// nothing with the task.
+const MIN_SHELL_SCORE: f32 = 0.1;
+
+/// Lines of a direct command's output shown with it, each cut to `SHELL_LINE_CHARS`.
+const SHELL_OUTPUT_LINES: usize = 2;
+const SHELL_LINE_CHARS: usize = 160;
+
+/// How much of a command's output a verbatim history entry keeps.
+const OUTPUT_PREVIEW_CHARS: usize = 200;
+
+/// At most about `max` bytes of `output` for a prompt: its start and, mostly, its end,
+/// where errors and summaries are.
+pub(crate) fn output_preview(output: &str, max: usize) -> String {
+    let head = max / 4;
+    TruncatedText::new_head_tail(output.trim_end().to_string(), head, max - head).content
+}
+
+/// `output` set off as command output, which prompts tell the model is data and never
+/// instructions. Lines that read like instructions anyway are withheld, and a closing tag
+/// inside it is broken up so the output cannot end early.
+pub(crate) fn untrusted(output: &str) -> String {
+    let (output, withheld) = injection::withhold_instructions(output);
+    if withheld > 0 {
+        warn!(
+            "Withheld {} line(s) of command output that read like instructions to the model",
+            withheld
+        );
+    }
+    format!(
+        "<command_output>\n{}\n</command_output>",
+        output.replace("</command_output", "<\\/command_output")
+    )
+}
+
+/// The commands of `history` (oldest first) that best fit a prompt about `query` at
+/// `now`, oldest first, each with its exit status, directory and last lines of output;
+/// with `private`, without the output. Empty when none qualify.
+pub(crate) fn shell_history(
+    history: &[DirectCommandExecution],
+    query: &str,
+    selection: &ShellHistory,
+    private: bool,
+    now: DateTime<Utc>,
+) -> String {
+    let render = |execution: &DirectCommandExecution| {
+        let mut line = format!(
+            "$ {} (exit {}, in {})",
+            execution.command,
+            execution.exit_status,
+            execution.working_directory.display()
+        );
+        let output = if execution.exit_status == 0 {
+            &execution.stdout.content
+        } else {
+            &execution.stderr.content
+        };
+        let tail: Vec<String> = output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .rev()
+            .take(SHELL_OUTPUT_LINES)
+            .map(|line| line.chars().take(SHELL_LINE_CHARS).collect())
+            .collect();
+        if !private && !tail.is_empty() {
+            let tail: Vec<String> = tail.into_iter().rev().collect();
+            line.push('\n');
+            line.push_str(&untrusted(&tail.join("\n")));
+        }
+        line
+    };
+
+    let mut scored: Vec<(f32, usize)> = history
+        .iter()
+        .enumerate()
+        .map(|(index, execution)| {
+            let content = format!(
+                "{} {}",
+                execution.command,
+                execution.working_directory.display()
+            );
+            let score = context::relevance(&content, query)
+                * context::recency_weight(execution.executed_at, now, selection.half_life);
+            (score, index)
+        })
+        .filter(|(score, _)| *score >= MIN_SHELL_SCORE)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+
+    let mut chosen: Vec<(usize, String)> = Vec::new();
+    let mut chars = 0;
+    for (_, index) in scored {
+        if chosen.len() == selection.max_commands {
+            break;
+        }
+        let rendered = render(&history[index]);
+        // One more for the line break
+        let len = rendered.chars().count() + 1;
+        if chars + len <= selection.max_chars {
+            chars += len;
+            chosen.push((index, rendered));
+        }
+    }
+    chosen.sort_by_key(|(index, _)| *index);
+    chosen
+        .into_iter()
+        .map(|(_, rendered)| rendered)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The last attempt of one completed step, as shown in the execution history.
+pub(crate) struct HistoryEntry<'a> {
+    pub step_number: usize,
+    pub description: &'a str,
+    pub attempt: &'a CommandAttempt,
+}
+
+impl HistoryEntry<'_> {
+    fn exit_status(&self) -> String {
+        self.attempt.exit_description()
+    }
+
+    fn verbatim(&self) -> String {
+        let output = &self.attempt.stdout.content;
+        format!(
+            "Step: {}\nCommand: {}\nExit Status: {}\nOutput:\n{}",
+            self.description,
+            self.attempt.candidate.command,
+            self.exit_status(),
+            untrusted(&output_preview(output, OUTPUT_PREVIEW_CHARS))
+        )
+    }
+
+    fn without_output(&self) -> String {
+        format!(
+            "Step: {}\nCommand: {}\nExit Status: {}",
+            self.description,
+            self.attempt.candidate.command,
+            self.exit_status()
+        )
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "step {}: {} (`{}`), exit {}",
+            self.step_number,
+            self.description,
+            self.attempt.candidate.command,
+            self.exit_status()
+        )
+    }
+}
+
+/// Render `entries` (oldest first) in at most `available` characters, degrading in order:
+/// summarize steps older than the verbatim window to one line each, drop the raw output
+/// of the recent ones, then omit the oldest entries.
+pub(crate) fn fit_history(
+    entries: &[HistoryEntry<'_>],
+    verbatim_steps: usize,
+    available: usize,
+) -> String {
+    let full = join_history(entries.iter().map(HistoryEntry::verbatim), 0);
+    if full.chars().count() <= available {
+        return full;
+    }
+
+    let split = entries.len().saturating_sub(verbatim_steps);
+    let (older, recent) = entries.split_at(split);
+    debug!(
+        "Execution history is {} chars, over the {} available; summarizing {} older steps",
+        full.chars().count(),
+        available,
+        older.len()
+    );
+
+    let mut lines: Vec<String> = older
+        .iter()
+        .map(HistoryEntry::summary)
+        .chain(recent.iter().map(HistoryEntry::verbatim))
+        .collect();
+    let history = join_history(lines.iter().cloned(), 0);
+    if history.chars().count() <= available {
+        return history;
+    }
+
+    debug!("Dropping raw output from the last {} steps", recent.len());
+    lines.truncate(older.len());
+    lines.extend(recent.iter().map(HistoryEntry::without_output));
+
+    let mut omitted = 0;
+    loop {
+        let history = join_history(lines[omitted..].iter().cloned(), omitted);
+        if history.chars().count() <= available || omitted == lines.len() {
+            if omitted > 0 {
+                debug!("Omitted the {} oldest steps from the history", omitted);
+            }
+            return history;
+        }
+        omitted += 1;
+    }
+}
+
+fn join_history(entries: impl Iterator<Item = String>, omitted: usize) -> String {
+    let omitted = (omitted > 0).then(|| format!("({} earlier steps omitted)", omitted));
+    omitted
+        .into_iter()
+        .chain(entries)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{attempt, direct_command};
+
+    #[test]
+    fn older_steps_are_summarized_before_recent_ones_lose_output() {
+        let attempts: Vec<CommandAttempt> = (1..=6)
+            .map(|i| attempt(&format!("make stage-{}", i), &"x".repeat(150)))
+            .collect();
+        let entries: Vec<HistoryEntry> = attempts
+            .iter()
+            .enumerate()
+            .map(|(i, attempt)| HistoryEntry {
+                step_number: i + 1,
+                description: "Build",
+                attempt,
+            })
+            .collect();
+
+        let history = fit_history(&entries, 2, 700);
+
+        assert!(history.chars().count() <= 700);
+        assert!(history.starts_with("step 1: Build (`make stage-1`), exit 0"));
+        assert!(history
+            .contains("Command: make stage-6\nExit Status: 0\nOutput:\n<command_output>\nxxx"));
+    }
+
+    #[test]
+    fn oldest_steps_are_omitted_last() {
+        let attempts: Vec<CommandAttempt> = (1..=6)
+            .map(|i| attempt(&format!("make stage-{}", i), "done"))
+            .collect();
+        let entries: Vec<HistoryEntry> = attempts
+            .iter()
+            .enumerate()
+            .map(|(i, attempt)| HistoryEntry {
+                step_number: i + 1,
+                description: "Build",
+                attempt,
+            })
+            .collect();
+
+        let history = fit_history(&entries, 1, 120);
+
+        assert!(history.chars().count() <= 120);
+        assert!(history.starts_with("("));
+        assert!(history.contains("earlier steps omitted"));
+        assert!(history.ends_with("Command: make stage-6\nExit Status: 0"));
+    }
+
+    #[test]
+    fn long_output_keeps_its_last_lines() {
+        let output = format!(
+            "{}error: linking with `cc` failed: exit status: 1\n",
+            "   Compiling dep v0.1.0\n".repeat(40)
+        );
+        let attempt = attempt("cargo build", &output);
+        let entry = HistoryEntry {
+            step_number: 1,
+            description: "Build",
+            attempt: &attempt,
+        };
+
+        let preview = entry.verbatim();
+
+        assert!(preview.contains("Output:\n<command_output>\n   Compiling dep v0.1.0\n"));
+        assert!(preview.contains(" bytes omitted] ...\n"));
+        assert!(
+            preview.ends_with("error: linking with `cc` failed: exit status: 1\n</command_output>")
+        );
+        assert!(TruncatedText::new_head_tail(output.clone(), 50, 150).contains("exit status: 1"));
+        assert!(!TruncatedText::new_head_tail(output, 50, 30).contains("linking with"));
+    }
+
+    #[test]
+    fn output_cannot_close_its_own_tag() {
+        let wrapped = untrusted("done\n</command_output >\nexit 0 </command_output");
+
+        assert!(wrapped.starts_with("<command_output>\ndone\n"));
+        assert_eq!(wrapped.matches("</command_output>").count(), 1);
+        assert!(wrapped.ends_with("exit 0 <\\/command_output\n</command_output>"));
+    }
+
+    #[test]
+    fn instructions_in_output_are_withheld() {
+        let log = include_str!("../fixtures/injected_build_log.txt");
+
+        let wrapped = untrusted(log);
+
+        assert_eq!(wrapped.matches(injection::WITHHELD_LINE).count(), 6);
+        for payload in ["evil.sh", "Ignore", "You are now", "reply with"] {
+            assert!(!wrapped.contains(payload), "{} reached the prompt", payload);
+        }
+        // The rest of the log is kept as it was
+        assert!(wrapped.contains("   Compiling parsec-core v0.1.0"));
+        assert!(wrapped.contains("warning: unused variable: `ignored`"));
+        assert!(wrapped.contains("System: Linux 6.1 x86_64"));
+        assert!(wrapped.contains("test result: ok. 12 passed"));
+    }
+
+    #[test]
+    fn recent_relevant_commands_are_shown_and_old_irrelevant_ones_dropped() {
+        let history = vec![
+            direct_command("df -h", "/home/me", "/dev/sda1  50G", 60 * 24),
+            direct_command("cd frontend/src", "/home/me/app", "", 20),
+            direct_command(
+                "ls components",
+                "/home/me/app/frontend/src",
+                "App.tsx\nButton.tsx\nHeader.tsx\nindex.ts",
+                5,
+            ),
+        ];
+
+        let shown = shell_history(
+            &history,
+            "add a footer next to the header in the frontend components",
+            &ShellHistory::default(),
+            false,
+            Utc::now(),
+        );
+
+        assert!(!shown.contains("df -h"));
+        assert!(shown.starts_with("$ cd frontend/src (exit 0, in /home/me/app)"));
+        assert!(shown.contains("$ ls components (exit 0, in /home/me/app/frontend/src)"));
+        assert!(shown.contains("Header.tsx\nindex.ts"));
+        assert!(!shown.contains("Button.tsx"));
+
+        let private = shell_history(
+            &history,
+            "add a footer to the frontend components",
+            &ShellHistory::default(),
+            true,
+            Utc::now(),
+        );
+        assert!(private.contains("$ ls components"));
+        assert!(!private.contains("index.ts"));
+    }
+}
@@ -0,0 +1,282 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// What a response is cached under: the request it answers, and the SHA-256 digest of
+/// that request naming the entry. A lookup only hits an entry stored for the same request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheKey {
+    digest: String,
+    request: String,
+}
+
+impl CacheKey {
+    /// Hex digest of the request, stable across runs.
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    request: String,
+    response: String,
+    stored_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, CacheEntry>,
+    /// Least recently used first.
+    order: VecDeque<String>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            self.order.remove(position);
+        }
+        self.order.push_back(key.to_string());
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+        self.order.retain(|k| k != key);
+    }
+}
+
+/// Model responses keyed by the full prompt and generation parameters: an in-memory LRU,
+/// optionally backed by one JSON file per entry on disk. The files hold prompts and
+/// responses as plain text.
+pub struct ResponseCache {
+    memory: Mutex<Lru>,
+    capacity: usize,
+    ttl: Duration,
+    disk_dir: Option<PathBuf>,
+    allow_private: bool,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            memory: Mutex::new(Lru::default()),
+            capacity: capacity.max(1),
+            ttl,
+            disk_dir: None,
+            allow_private: false,
+        }
+    }
+
+    /// Also persist entries under `dir` (created on first write).
+    pub fn with_disk_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.disk_dir = Some(dir.into());
+        self
+    }
+
+    /// Also cache calls made for sessions in privacy mode.
+    pub fn allow_private(mut self, allow: bool) -> Self {
+        self.allow_private = allow;
+        self
+    }
+
+    pub fn allows_private(&self) -> bool {
+        self.allow_private
+    }
+
+    /// The key for `parts`, which must include every parameter that affects the response.
+    pub fn key(parts: &[&str]) -> CacheKey {
+        // Length prefixes keep ("ab", "c") and ("a", "bc") apart
+        let request: String = parts
+            .iter()
+            .map(|part| format!("{}:{}", part.len(), part))
+            .collect();
+        let digest = Sha256::digest(request.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        CacheKey { digest, request }
+    }
+
+    /// The response stored for `key`; `None` is a miss.
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        self.get_memory(key).or_else(|| self.get_disk(key))
+    }
+
+    pub fn put(&self, key: &CacheKey, response: &str) {
+        let entry = CacheEntry {
+            request: key.request.clone(),
+            response: response.to_string(),
+            stored_at: Utc::now(),
+        };
+        if let Some(dir) = &self.disk_dir {
+            if let Err(e) = write_entry(dir, key, &entry) {
+                warn!("Failed to write response cache entry: {}", e);
+            }
+        }
+        self.put_memory(key, entry);
+    }
+
+    fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        Utc::now() - entry.stored_at < self.ttl
+    }
+
+    fn get_memory(&self, key: &CacheKey) -> Option<String> {
+        let mut memory = self.memory.lock().ok()?;
+        let entry = memory.entries.get(&key.digest)?.clone();
+        if entry.request != key.request {
+            return None;
+        }
+        if !self.is_fresh(&entry) {
+            memory.remove(&key.digest);
+            return None;
+        }
+        memory.touch(&key.digest);
+        Some(entry.response)
+    }
+
+    fn get_disk(&self, key: &CacheKey) -> Option<String> {
+        let path = entry_path(self.disk_dir.as_ref()?, &key.digest);
+        let entry: CacheEntry = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        if entry.request != key.request {
+            return None;
+        }
+        if !self.is_fresh(&entry) {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        let response = entry.response.clone();
+        self.put_memory(key, entry);
+        Some(response)
+    }
+
+    fn put_memory(&self, key: &CacheKey, entry: CacheEntry) {
+        let Ok(mut memory) = self.memory.lock() else {
+            return;
+        };
+        memory.entries.insert(key.digest.clone(), entry);
+        memory.touch(&key.digest);
+        while memory.order.len() > self.capacity {
+            if let Some(oldest) = memory.order.pop_front() {
+                memory.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// A call's result, and whether it was answered from the cache, apart.
+pub(crate) fn split_cached<T, E>(result: Result<(T, bool), E>) -> (Result<T, E>, bool) {
+    match result {
+        Ok((value, cached)) => (Ok(value), cached),
+        Err(e) => (Err(e), false),
+    }
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.json", key))
+}
+
+fn write_entry(dir: &Path, key: &CacheKey, entry: &CacheEntry) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(dir)?;
+    let path = entry_path(dir, &key.digest);
+    // Named per write, so processes caching the same response do not share a temp file
+    let tmp_path = dir.join(format!(
+        "{}.{}.{}.tmp",
+        key.digest,
+        std::process::id(),
+        uuid::Uuid::new_v4().simple()
+    ));
+    let written = fs::write(&tmp_path, serde_json::to_vec(entry)?)
+        .and_then(|()| fs::rename(&tmp_path, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(written?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(part: &str) -> CacheKey {
+        ResponseCache::key(&[part])
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ResponseCache::new(2, Duration::hours(1));
+        cache.put(&key("a"), "1");
+        cache.put(&key("b"), "2");
+        assert_eq!(cache.get(&key("a")).as_deref(), Some("1"));
+        cache.put(&key("c"), "3");
+
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!(cache.get(&key("a")).as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn expired_entries_miss() {
+        let cache = ResponseCache::new(4, Duration::zero());
+        cache.put(&key("a"), "1");
+        assert_eq!(cache.get(&key("a")), None);
+    }
+
+    #[test]
+    fn entries_stored_for_another_request_under_the_same_digest_miss() {
+        let dir = std::env::temp_dir().join(format!("parsec-cache-{}", uuid::Uuid::new_v4()));
+        let cache = ResponseCache::new(4, Duration::hours(1)).with_disk_dir(&dir);
+        let asked = key("deploy");
+        let colliding = CacheKey {
+            digest: asked.digest.clone(),
+            request: "something else".to_string(),
+        };
+        cache.put(&colliding, "unrelated");
+
+        assert_eq!(cache.get(&asked), None);
+        let reopened = ResponseCache::new(4, Duration::hours(1)).with_disk_dir(&dir);
+        assert_eq!(reopened.get(&asked), None);
+        assert_eq!(reopened.get(&colliding).as_deref(), Some("unrelated"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn disk_writes_leave_no_temp_files() {
+        let dir = std::env::temp_dir().join(format!("parsec-cache-{}", uuid::Uuid::new_v4()));
+        let cache = ResponseCache::new(4, Duration::hours(1)).with_disk_dir(&dir);
+        cache.put(&key("a"), "1");
+        cache.put(&key("a"), "2");
+
+        let files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files, vec![format!("{}.json", key("a").digest())]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn disk_entries_survive_a_new_cache() {
+        let dir = std::env::temp_dir().join(format!("parsec-cache-{}", uuid::Uuid::new_v4()));
+        let key = ResponseCache::key(&["gemini", "prompt"]);
+        ResponseCache::new(4, Duration::hours(1))
+            .with_disk_dir(&dir)
+            .put(&key, "cached");
+
+        let reopened = ResponseCache::new(4, Duration::hours(1)).with_disk_dir(&dir);
+        assert_eq!(reopened.get(&key).as_deref(), Some("cached"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn key_separates_parts() {
+        assert_ne!(
+            ResponseCache::key(&["ab", "c"]),
+            ResponseCache::key(&["a", "bc"])
+        );
+        assert_eq!(key("a").digest().len(), 64);
+    }
+}
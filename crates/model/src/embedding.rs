@@ -0,0 +1,312 @@
+//! Embedding-based relevance for context items. An `Embedder` turns text into vectors
+//! (`GoogleAiClient` through the Gemini embeddings endpoint; local models can implement
+//! it as well) and an `EmbeddingIndex` scores texts by cosine similarity to a prompt,
+//! embedding each text once. Vectors are kept in a `VectorStore`, such as
+//! `FileSessionStore`, keyed by a hash of model and text; those unused for `max_age` or
+//! beyond `max_vectors` are evicted least recently used first.
+
+use crate::cache::ResponseCache;
+use crate::ModelClientError;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use parsec_core::StoreError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Name of the model; vectors of different models are never compared.
+    fn embedding_model(&self) -> &str;
+
+    /// One vector per text, in order.
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ModelClientError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredVector {
+    pub vector: Vec<f32>,
+    pub last_used: DateTime<Utc>,
+}
+
+/// Vectors by `EmbeddingIndex::key` of their model and text.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoredVectors {
+    pub vectors: HashMap<String, StoredVector>,
+}
+
+impl StoredVectors {
+    /// Drop vectors unused since `now - max_age`, then the least recently used beyond
+    /// `max_vectors`. Returns how many went.
+    pub fn evict(&mut self, max_vectors: usize, max_age: Duration, now: DateTime<Utc>) -> usize {
+        let before = self.vectors.len();
+        self.vectors
+            .retain(|_, stored| now - stored.last_used <= max_age);
+        if self.vectors.len() > max_vectors {
+            let mut by_use: Vec<(String, DateTime<Utc>)> = self
+                .vectors
+                .iter()
+                .map(|(key, stored)| (key.clone(), stored.last_used))
+                .collect();
+            by_use.sort_by_key(|(_, last_used)| std::cmp::Reverse(*last_used));
+            for (key, _) in by_use.into_iter().skip(max_vectors) {
+                self.vectors.remove(&key);
+            }
+        }
+        before - self.vectors.len()
+    }
+}
+
+/// Where an `EmbeddingIndex` keeps its vectors between runs.
+pub trait VectorStore: Send + Sync {
+    /// The saved vectors; empty if none were saved yet.
+    fn load_vectors(&self) -> Result<StoredVectors, StoreError>;
+    fn save_vectors(&self, vectors: &StoredVectors) -> Result<(), StoreError>;
+}
+
+/// Cosine of the angle between `a` and `b`; 0 if either is empty or zero or their
+/// lengths differ.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[derive(Default)]
+struct IndexState {
+    loaded: bool,
+    vectors: StoredVectors,
+    /// Vectors of private texts, never saved.
+    private: StoredVectors,
+}
+
+pub struct EmbeddingIndex {
+    embedder: Arc<dyn Embedder>,
+    store: Option<Arc<dyn VectorStore>>,
+    max_vectors: usize,
+    max_age: Duration,
+    state: Mutex<IndexState>,
+}
+
+impl EmbeddingIndex {
+    pub fn new(embedder: Arc<dyn Embedder>) -> Self {
+        Self {
+            embedder,
+            store: None,
+            max_vectors: 1_000,
+            max_age: Duration::days(30),
+            state: Mutex::new(IndexState::default()),
+        }
+    }
+
+    /// Load vectors from `store` on first use and save new ones to it.
+    pub fn with_store(mut self, store: Arc<dyn VectorStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Keep at most `max_vectors`, dropping those unused for `max_age` first.
+    pub fn with_eviction(mut self, max_vectors: usize, max_age: Duration) -> Self {
+        self.max_vectors = max_vectors;
+        self.max_age = max_age;
+        self
+    }
+
+    fn key(&self, text: &str) -> String {
+        ResponseCache::key(&[self.embedder.embedding_model(), text])
+            .digest()
+            .to_string()
+    }
+
+    /// Cosine similarity of each of `texts` to `query`. Texts not seen before are embedded
+    /// with the query in one call; with `private` their vectors are kept in memory only.
+    pub async fn similarities(
+        &self,
+        query: &str,
+        texts: &[String],
+        private: bool,
+    ) -> Result<Vec<f32>, ModelClientError> {
+        let keys: Vec<String> = texts.iter().map(|text| self.key(text)).collect();
+        let now = Utc::now();
+        let mut known = self.known_vectors(&keys, now);
+
+        let mut missing: Vec<String> = Vec::new();
+        for (key, text) in keys.iter().zip(texts) {
+            if !known.contains_key(key) && !missing.contains(text) {
+                missing.push(text.clone());
+            }
+        }
+        let mut request = vec![query.to_string()];
+        request.extend(missing.iter().cloned());
+        let mut embedded = self.embedder.embed(&request).await?;
+        if embedded.len() != request.len() {
+            return Err(ModelClientError::Api(format!(
+                "Expected {} embeddings, got {}",
+                request.len(),
+                embedded.len()
+            )));
+        }
+        let query_vector = embedded.remove(0);
+
+        let fresh: Vec<(String, StoredVector)> = missing
+            .iter()
+            .zip(embedded)
+            .map(|(text, vector)| {
+                let stored = StoredVector {
+                    vector,
+                    last_used: now,
+                };
+                (self.key(text), stored)
+            })
+            .collect();
+        for (key, stored) in &fresh {
+            known.insert(key.clone(), stored.vector.clone());
+        }
+        self.remember(fresh, private, now);
+
+        Ok(keys
+            .iter()
+            .map(|key| {
+                known
+                    .get(key)
+                    .map_or(0.0, |vector| cosine_similarity(&query_vector, vector))
+            })
+            .collect())
+    }
+
+    /// The stored vectors among `keys`, marked as used at `now`.
+    fn known_vectors(&self, keys: &[String], now: DateTime<Utc>) -> HashMap<String, Vec<f32>> {
+        let Ok(mut state) = self.state.lock() else {
+            return HashMap::new();
+        };
+        if !state.loaded {
+            state.loaded = true;
+            if let Some(store) = &self.store {
+                match store.load_vectors() {
+                    Ok(vectors) => state.vectors = vectors,
+                    Err(e) => warn!("Failed to load embedding vectors: {}", e),
+                }
+            }
+        }
+        let state = &mut *state;
+        let mut known = HashMap::new();
+        for key in keys {
+            let stored = state
+                .vectors
+                .vectors
+                .get_mut(key)
+                .or_else(|| state.private.vectors.get_mut(key));
+            if let Some(stored) = stored {
+                stored.last_used = now;
+                known.insert(key.clone(), stored.vector.clone());
+            }
+        }
+        known
+    }
+
+    fn remember(&self, fresh: Vec<(String, StoredVector)>, private: bool, now: DateTime<Utc>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let vectors = if private {
+            &mut state.private
+        } else {
+            &mut state.vectors
+        };
+        vectors.vectors.extend(fresh);
+        vectors.evict(self.max_vectors, self.max_age, now);
+        if private {
+            return;
+        }
+        // Saved even without new vectors, so last use stays current for eviction
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save_vectors(&state.vectors) {
+                warn!("Failed to save embedding vectors: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::WordEmbedder;
+    use crate::InMemorySessionStore;
+
+    #[test]
+    fn similarity_is_the_cosine_of_the_angle() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn eviction_drops_stale_then_least_recently_used_vectors() {
+        let now = Utc::now();
+        let mut vectors = StoredVectors::default();
+        for (key, age) in [("a", 40), ("b", 3), ("c", 2), ("d", 1)] {
+            vectors.vectors.insert(
+                key.to_string(),
+                StoredVector {
+                    vector: vec![1.0],
+                    last_used: now - Duration::days(age),
+                },
+            );
+        }
+        assert_eq!(vectors.evict(2, Duration::days(30), now), 2);
+        let mut kept: Vec<_> = vectors.vectors.keys().cloned().collect();
+        kept.sort();
+        assert_eq!(kept, ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn texts_are_embedded_once_and_kept_in_the_store() {
+        let embedder = Arc::new(WordEmbedder::new());
+        let store = Arc::new(InMemorySessionStore::new());
+        let index = EmbeddingIndex::new(embedder.clone()).with_store(store.clone());
+        let texts = vec![
+            "built the docker image".to_string(),
+            "ran the test suite".to_string(),
+        ];
+
+        let similarities = index
+            .similarities("deploy the docker image", &texts, false)
+            .await
+            .unwrap();
+        assert!(similarities[0] > 0.8);
+        assert_eq!(similarities[1], 0.0);
+        index
+            .similarities("run the test again", &texts, false)
+            .await
+            .unwrap();
+        let batches = embedder.batches.lock().unwrap().clone();
+        assert_eq!(batches[0].len(), 3);
+        assert_eq!(batches[1], ["run the test again"]);
+        assert_eq!(store.load_vectors().unwrap().vectors.len(), 2);
+
+        // A new index picks the vectors up from the store
+        let index = EmbeddingIndex::new(embedder.clone()).with_store(store.clone());
+        index
+            .similarities("deploy", &texts[..1], false)
+            .await
+            .unwrap();
+        assert_eq!(embedder.batches.lock().unwrap()[2], ["deploy"]);
+
+        // Private texts are never saved
+        index
+            .similarities("deploy", &["private docker notes".to_string()], true)
+            .await
+            .unwrap();
+        assert_eq!(store.load_vectors().unwrap().vectors.len(), 2);
+    }
+}
@@ -0,0 +1,296 @@
+//! Encryption at rest for `FileSessionStore`.
+//!
+//! Each file is sealed with ChaCha20-Poly1305 under a fresh random nonce, behind a
+//! header of magic bytes, format version, schema version and nonce that is
+//! authenticated along with the contents. The key is derived from a passphrase with
+//! Argon2id, or supplied directly (from the OS keyring with the `keyring` feature).
+//! `KEY_FILE` in the store root holds the salt and a sealed check value, so a wrong
+//! passphrase is reported when the store is opened rather than as corrupt files.
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use parsec_core::StoreError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Salt, KDF parameters and check value of an encrypted store, beside its data.
+pub const KEY_FILE: &str = "store-key.json";
+
+const MAGIC: &[u8; 8] = b"PARSEC\0E";
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + NONCE_LEN;
+const CHECK_PLAINTEXT: &[u8] = b"parsec store key check";
+
+/// A 256-bit store key. `Debug` does not show it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct StoreKey([u8; 32]);
+
+impl fmt::Debug for StoreKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StoreKey(..)")
+    }
+}
+
+impl StoreKey {
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut key = [0; 32];
+        for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self(key))
+    }
+
+    /// The key kept in the OS keyring for the store in `root`, created on first use.
+    /// A store that already has a key file but no keyring entry is an error, not a
+    /// reason to start over with a new key.
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(root: &Path) -> Result<Self, StoreError> {
+        let keyring_error =
+            |e: keyring::Error| StoreError::StorageError(format!("OS keyring: {}", e));
+        let entry =
+            keyring::Entry::new("parsec", &root.display().to_string()).map_err(keyring_error)?;
+        match entry.get_password() {
+            Ok(hex) => Self::from_hex(&hex).ok_or_else(|| {
+                StoreError::StorageError("OS keyring entry is not a parsec store key".to_string())
+            }),
+            Err(keyring::Error::NoEntry) if root.join(KEY_FILE).exists() => {
+                Err(StoreError::WrongKey(format!(
+                    "{} (its key is not in the OS keyring)",
+                    root.display()
+                )))
+            }
+            Err(keyring::Error::NoEntry) => {
+                let key = Self::generate();
+                entry.set_password(&key.to_hex()).map_err(keyring_error)?;
+                Ok(key)
+            }
+            Err(e) => Err(keyring_error(e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum KeySource {
+    Passphrase,
+    Key,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KdfParams {
+    /// Always `argon2id`.
+    algorithm: String,
+    salt: Vec<u8>,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let mut salt = vec![0; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            algorithm: "argon2id".to_string(),
+            salt,
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<StoreKey, StoreError> {
+        let kdf_error = |e: argon2::Error| StoreError::StorageError(format!("Argon2: {}", e));
+        if self.algorithm != "argon2id" {
+            return Err(StoreError::StorageError(format!(
+                "Unsupported key derivation: {}",
+                self.algorithm
+            )));
+        }
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(kdf_error)?;
+        let mut key = [0; 32];
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(kdf_error)?;
+        Ok(StoreKey(key))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    version: u8,
+    source: KeySource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<KdfParams>,
+    /// `CHECK_PLAINTEXT` sealed under the key.
+    check: Vec<u8>,
+}
+
+/// Seals and opens the files of one encrypted store.
+pub(crate) struct StoreCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl StoreCipher {
+    fn new(key: &StoreKey) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key.0)),
+        }
+    }
+
+    /// The cipher for the store in `root` under `passphrase`, setting the store up for
+    /// it if it has no key file yet.
+    pub(crate) fn with_passphrase(root: &Path, passphrase: &str) -> Result<Self, StoreError> {
+        match read_key_file(root)? {
+            Some(key_file) => {
+                let kdf = match (key_file.source, &key_file.kdf) {
+                    (KeySource::Passphrase, Some(kdf)) => kdf,
+                    _ => return Err(source_mismatch(root, "a key, not a passphrase")),
+                };
+                Self::verified(root, &kdf.derive(passphrase)?, &key_file)
+            }
+            None => {
+                let kdf = KdfParams::generate();
+                let cipher = Self::new(&kdf.derive(passphrase)?);
+                cipher.write_key_file(root, KeySource::Passphrase, Some(kdf))?;
+                Ok(cipher)
+            }
+        }
+    }
+
+    /// The cipher for the store in `root` under `key`, setting the store up for it if it
+    /// has no key file yet.
+    pub(crate) fn with_key(root: &Path, key: &StoreKey) -> Result<Self, StoreError> {
+        match read_key_file(root)? {
+            Some(key_file) if key_file.source == KeySource::Key => {
+                Self::verified(root, key, &key_file)
+            }
+            Some(_) => Err(source_mismatch(root, "a passphrase, not a key")),
+            None => {
+                let cipher = Self::new(key);
+                cipher.write_key_file(root, KeySource::Key, None)?;
+                Ok(cipher)
+            }
+        }
+    }
+
+    fn verified(root: &Path, key: &StoreKey, key_file: &KeyFile) -> Result<Self, StoreError> {
+        let cipher = Self::new(key);
+        match cipher.open(&key_file.check) {
+            Ok(check) if check == CHECK_PLAINTEXT => Ok(cipher),
+            _ => Err(StoreError::WrongKey(root.display().to_string())),
+        }
+    }
+
+    fn write_key_file(
+        &self,
+        root: &Path,
+        source: KeySource,
+        kdf: Option<KdfParams>,
+    ) -> Result<(), StoreError> {
+        let key_file = KeyFile {
+            version: FORMAT_VERSION,
+            source,
+            kdf,
+            check: self.seal(0, CHECK_PLAINTEXT),
+        };
+        fs::create_dir_all(root)?;
+        fs::write(root.join(KEY_FILE), serde_json::to_vec_pretty(&key_file)?)?;
+        Ok(())
+    }
+
+    /// `plaintext` behind a header recording `schema_version`.
+    pub(crate) fn seal(&self, schema_version: u32, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        sealed.extend_from_slice(MAGIC);
+        sealed.push(FORMAT_VERSION);
+        sealed.extend_from_slice(&schema_version.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &sealed,
+                },
+            )
+            .expect("ChaCha20-Poly1305 encryption does not fail for in-memory buffers");
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// The plaintext of `sealed`, or `Err(())` if it is not intact.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, ()> {
+        if !is_encrypted(sealed)
+            || sealed.len() < HEADER_LEN
+            || sealed[MAGIC.len()] != FORMAT_VERSION
+        {
+            return Err(());
+        }
+        let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&header[HEADER_LEN - NONCE_LEN..]),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| ())
+    }
+}
+
+/// Whether `bytes` start like a sealed file.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The schema version in the header of a sealed file. Only authenticated once the file
+/// is opened.
+pub(crate) fn schema_version(sealed: &[u8]) -> Option<u32> {
+    let start = MAGIC.len() + 1;
+    let bytes = sealed.get(start..start + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_key_file(root: &Path) -> Result<Option<KeyFile>, StoreError> {
+    match fs::read(root.join(KEY_FILE)) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn source_mismatch(root: &Path, expected: &str) -> StoreError {
+    StoreError::WrongKey(format!(
+        "{} (it is encrypted with {})",
+        root.display(),
+        expected
+    ))
+}
@@ -0,0 +1,545 @@
+use async_trait::async_trait;
+use parsec_core::*;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Error text meaning the provider is out of quota or temporarily down.
+const UNAVAILABLE_MARKERS: &[&str] = &[
+    "429",
+    "resource_exhausted",
+    "quota",
+    "rate limit",
+    "500",
+    "502",
+    "503",
+    "504",
+    "unavailable",
+    "overloaded",
+    "timed out",
+    "error sending request",
+];
+
+/// Error text meaning the credentials or request are wrong; these never fall back.
+const CONFIG_MARKERS: &[&str] = &[
+    "401",
+    "403",
+    "unauthenticated",
+    "permission_denied",
+    "api key",
+    "api_key_invalid",
+    "authentication failed",
+    "invalid_argument",
+];
+
+/// Whether a `ModelError` message describes a quota or availability failure.
+pub fn is_unavailable_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    !CONFIG_MARKERS.iter().any(|marker| message.contains(marker))
+        && UNAVAILABLE_MARKERS
+            .iter()
+            .any(|marker| message.contains(marker))
+}
+
+/// Tries each provider in order, moving on only when one is out of quota or unavailable.
+/// Calls are expected to be sequential: `fallback_used` and `last_exchange` report on the
+/// most recent one.
+pub struct FallbackProvider {
+    providers: Vec<(String, Arc<dyn ModelProvider>)>,
+    /// Index of the provider that answered, or failed, the most recent call.
+    served_by: Mutex<usize>,
+}
+
+impl FallbackProvider {
+    pub fn new(label: impl Into<String>, primary: Arc<dyn ModelProvider>) -> Self {
+        Self {
+            providers: vec![(label.into(), primary)],
+            served_by: Mutex::new(0),
+        }
+    }
+
+    pub fn with_fallback(
+        mut self,
+        label: impl Into<String>,
+        provider: Arc<dyn ModelProvider>,
+    ) -> Self {
+        self.providers.push((label.into(), provider));
+        self
+    }
+
+    fn set_served_by(&self, index: usize) {
+        if let Ok(mut served_by) = self.served_by.lock() {
+            *served_by = index;
+        }
+    }
+
+    fn served_by(&self) -> usize {
+        self.served_by.lock().map(|index| *index).unwrap_or(0)
+    }
+
+    fn should_fall_back(&self, index: usize, message: &str) -> bool {
+        if index + 1 >= self.providers.len() || !is_unavailable_error(message) {
+            return false;
+        }
+        warn!(
+            "Model provider {} unavailable, trying {}: {}",
+            self.providers[index].0,
+            self.providers[index + 1].0,
+            message
+        );
+        true
+    }
+}
+
+#[async_trait]
+impl WorkflowPlanner for FallbackProvider {
+    async fn plan(
+        &self,
+        user_prompt: &str,
+        session_context: &Session,
+        opts: PlanningOptions,
+    ) -> Result<WorkflowPlan, PlanError> {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            match provider
+                .planner()
+                .plan(user_prompt, session_context, opts.clone())
+                .await
+            {
+                Ok(plan) => {
+                    self.set_served_by(index);
+                    return Ok(plan);
+                }
+                Err(PlanError::ModelError(message)) if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(PlanError::ModelError(
+            "No model providers configured".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl StepCommandGenerator for FallbackProvider {
+    async fn generate_command(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+        step_index: usize,
+        opts: CommandGenOptions,
+    ) -> Result<GeneratedCommands, CommandGenError> {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            match provider
+                .step_generator()
+                .generate_command(ctx, session, step_index, opts.clone())
+                .await
+            {
+                Ok(commands) => {
+                    self.set_served_by(index);
+                    return Ok(commands);
+                }
+                Err(CommandGenError::ModelError(message))
+                    if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(CommandGenError::ModelError(
+            "No model providers configured".to_string(),
+        ))
+    }
+
+    async fn suggest_rollback(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+    ) -> Result<Vec<RollbackSuggestion>, CommandGenError> {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            match provider
+                .step_generator()
+                .suggest_rollback(ctx, session)
+                .await
+            {
+                Ok(suggestions) => {
+                    self.set_served_by(index);
+                    return Ok(suggestions);
+                }
+                Err(CommandGenError::ModelError(message))
+                    if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(CommandGenError::ModelError(
+            "No model providers configured".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl Summarizer for FallbackProvider {
+    async fn summarize(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+        steps: std::ops::Range<usize>,
+    ) -> Result<Vec<String>, CommandGenError> {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            match provider
+                .summarizer()
+                .summarize(ctx, session, steps.clone())
+                .await
+            {
+                Ok(summary) => {
+                    self.set_served_by(index);
+                    return Ok(summary);
+                }
+                Err(CommandGenError::ModelError(message))
+                    if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(CommandGenError::ModelError(
+            "No model providers configured".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl ConversationNamer for FallbackProvider {
+    async fn name_conversation(
+        &self,
+        ctx: &ConversationContext,
+        session: &Session,
+    ) -> Result<String, CommandGenError> {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            match provider.namer().name_conversation(ctx, session).await {
+                Ok(name) => {
+                    self.set_served_by(index);
+                    return Ok(name);
+                }
+                Err(CommandGenError::ModelError(message))
+                    if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(CommandGenError::ModelError(
+            "No model providers configured".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl Explainer for FallbackProvider {
+    async fn explain(
+        &self,
+        question: &str,
+        session: &Session,
+        opts: PlanningOptions,
+    ) -> Result<String, PlanError> {
+        // Providers that do not answer questions are passed over
+        let explainers = self
+            .providers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, provider))| Some((index, provider.explainer()?)));
+        for (index, explainer) in explainers {
+            match explainer.explain(question, session, opts.clone()).await {
+                Ok(answer) => {
+                    self.set_served_by(index);
+                    return Ok(answer);
+                }
+                Err(PlanError::ModelError(message)) if self.should_fall_back(index, &message) => {}
+                Err(e) => {
+                    self.set_served_by(index);
+                    return Err(e);
+                }
+            }
+        }
+        Err(PlanError::ModelError(
+            "No model provider answers questions".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl ModelProvider for FallbackProvider {
+    fn planner(&self) -> &dyn WorkflowPlanner {
+        self
+    }
+
+    fn step_generator(&self) -> &dyn StepCommandGenerator {
+        self
+    }
+
+    fn summarizer(&self) -> &dyn Summarizer {
+        self
+    }
+
+    fn namer(&self) -> &dyn ConversationNamer {
+        self
+    }
+
+    fn explainer(&self) -> Option<&dyn Explainer> {
+        self.providers
+            .iter()
+            .any(|(_, provider)| provider.explainer().is_some())
+            .then_some(self as &dyn Explainer)
+    }
+
+    /// The primary provider's name; fallbacks are reported through `fallback_used`.
+    fn name(&self) -> &'static str {
+        self.providers[0].1.name()
+    }
+
+    fn fallback_used(&self) -> Option<String> {
+        let index = self.served_by();
+        (index > 0).then(|| self.providers[index].0.clone())
+    }
+
+    fn last_call_cached(&self) -> bool {
+        self.providers[self.served_by()].1.last_call_cached()
+    }
+
+    fn last_exchange(&self) -> Option<ModelExchange> {
+        self.providers[self.served_by()].1.last_exchange()
+    }
+
+    fn tokens_used(&self) -> u64 {
+        self.providers
+            .iter()
+            .map(|(_, provider)| provider.tokens_used())
+            .sum()
+    }
+
+    /// The first provider that passes, moving on like calls do: only when one is out of
+    /// quota or unreachable.
+    async fn health_check(&self) -> Result<ProviderHealth, HealthError> {
+        for (index, (label, provider)) in self.providers.iter().enumerate() {
+            match provider.health_check().await {
+                Ok(health) if index == 0 => return Ok(health),
+                Ok(health) => {
+                    return Ok(ProviderHealth {
+                        provider: label.clone(),
+                        ..health
+                    })
+                }
+                Err(e @ (HealthError::Quota(_) | HealthError::Network(_)))
+                    if index + 1 < self.providers.len() =>
+                {
+                    warn!(
+                        "Model provider {} failed its health check, trying {}: {}",
+                        label,
+                        self.providers[index + 1].0,
+                        e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(HealthError::Failed(
+            "No model providers configured".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{conversation, session};
+    use crate::ModelClientError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct MockProvider {
+        error: Option<String>,
+        calls: AtomicUsize,
+    }
+
+    impl MockProvider {
+        fn ok() -> Arc<Self> {
+            Arc::new(Self {
+                error: None,
+                calls: AtomicUsize::new(0),
+            })
+        }
+
+        fn failing(message: &str) -> Arc<Self> {
+            Arc::new(Self {
+                error: Some(message.to_string()),
+                calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl WorkflowPlanner for MockProvider {
+        async fn plan(
+            &self,
+            _user_prompt: &str,
+            _session_context: &Session,
+            _opts: PlanningOptions,
+        ) -> Result<WorkflowPlan, PlanError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match &self.error {
+                Some(message) => Err(PlanError::ModelError(message.clone())),
+                None => Ok(WorkflowPlan {
+                    steps: vec![WorkflowStep {
+                        id: "1".to_string(),
+                        description: "Do the thing".to_string(),
+                        depends_on: None,
+                        estimated_minutes: None,
+                        estimate_source: EstimateSource::Planner,
+                    }],
+                }),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl StepCommandGenerator for MockProvider {
+        async fn generate_command(
+            &self,
+            _ctx: &ConversationContext,
+            _session: &Session,
+            _step_index: usize,
+            _opts: CommandGenOptions,
+        ) -> Result<GeneratedCommands, CommandGenError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match &self.error {
+                Some(message) => Err(CommandGenError::ModelError(message.clone())),
+                None => Ok(GeneratedCommands {
+                    commands: Vec::new(),
+                    done: true,
+                    candidates: None,
+                }),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ModelProvider for MockProvider {
+        fn planner(&self) -> &dyn WorkflowPlanner {
+            self
+        }
+
+        fn step_generator(&self) -> &dyn StepCommandGenerator {
+            self
+        }
+
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn health_check(&self) -> Result<ProviderHealth, HealthError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match &self.error {
+                Some(message) => Err(ModelClientError::Api(message.clone()).into()),
+                None => Ok(ProviderHealth {
+                    provider: "mock".to_string(),
+                    model: "mock-1".to_string(),
+                    latency: std::time::Duration::from_millis(5),
+                    checked_at: chrono::Utc::now(),
+                }),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn quota_errors_fall_back_to_the_next_provider() {
+        let primary = MockProvider::failing("Google AI API error (429): RESOURCE_EXHAUSTED");
+        let backup = MockProvider::ok();
+        let provider = FallbackProvider::new("primary", primary.clone())
+            .with_fallback("backup", backup.clone());
+
+        let plan = provider
+            .plan("do the thing", &session(), PlanningOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(provider.fallback_used().as_deref(), Some("backup"));
+
+        provider
+            .generate_command(&conversation(), &session(), 0, CommandGenOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(backup.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn auth_errors_do_not_fall_back() {
+        let primary = MockProvider::failing("Google AI API error (403): API key not valid");
+        let backup = MockProvider::ok();
+        let provider =
+            FallbackProvider::new("primary", primary).with_fallback("backup", backup.clone());
+
+        let result = provider
+            .plan("do the thing", &session(), PlanningOptions::default())
+            .await;
+        assert!(matches!(result, Err(PlanError::ModelError(_))));
+        assert_eq!(backup.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn primary_success_reports_no_fallback() {
+        let provider = FallbackProvider::new("primary", MockProvider::ok())
+            .with_fallback("backup", MockProvider::ok());
+
+        provider
+            .plan("do the thing", &session(), PlanningOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(provider.fallback_used(), None);
+    }
+
+    #[tokio::test]
+    async fn last_provider_error_is_returned() {
+        let provider = FallbackProvider::new("primary", MockProvider::failing("503 UNAVAILABLE"))
+            .with_fallback("backup", MockProvider::failing("429 quota exceeded"));
+
+        match provider
+            .plan("do the thing", &session(), PlanningOptions::default())
+            .await
+        {
+            Err(PlanError::ModelError(message)) => assert!(message.contains("quota")),
+            other => panic!("expected a model error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn health_checks_fall_back_on_quota_but_not_on_rejected_keys() {
+        let provider = FallbackProvider::new(
+            "primary",
+            MockProvider::failing("Google AI API error (429): RESOURCE_EXHAUSTED"),
+        )
+        .with_fallback("backup", MockProvider::ok());
+        let health = provider.health_check().await.unwrap();
+        assert_eq!(health.provider, "backup");
+        assert_eq!(health.model, "mock-1");
+
+        let backup = MockProvider::ok();
+        let provider = FallbackProvider::new(
+            "primary",
+            MockProvider::failing(
+                "Google AI API error (400): API key not valid. Please pass a valid API key.",
+            ),
+        )
+        .with_fallback("backup", backup.clone());
+        let error = provider.health_check().await.unwrap_err();
+        assert!(matches!(error, HealthError::InvalidCredentials(_)));
+        assert!(error.to_string().contains("check the configured API key"));
+        assert_eq!(backup.calls.load(Ordering::SeqCst), 0);
+    }
+}
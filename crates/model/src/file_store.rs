@@ -0,0 +1,1578 @@
+use crate::embedding::{StoredVectors, VectorStore};
+use crate::encryption::{self, StoreCipher, StoreKey};
+use crate::migrations::{self, Versioned};
+use crate::{check_revision, sessions_covering, sessions_to_evict};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use parsec_core::search::{self, SearchHit, SearchQuery, SearchScope};
+use parsec_core::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const SESSIONS_DIR: &str = "sessions";
+const CONVERSATIONS_DIR: &str = "conversations";
+/// Embedding vectors of context items, for `EmbeddingIndex`.
+const VECTORS_FILE: &str = "embeddings.json";
+/// Archived sessions and conversations keep the same layout under this directory.
+pub const ARCHIVE_DIR: &str = "archive";
+/// Sessions and conversations kept parsed in memory; the cache is emptied when full.
+const CACHE_CAPACITY: usize = 256;
+
+/// Whether a conversation is pinned and when it was created; listings sort by it, highest
+/// first.
+type ListingOrder = (bool, DateTime<Utc>);
+
+/// Session store persisting one JSON file per session and conversation.
+///
+/// Writes go to a temp file in the same directory and are renamed into place,
+/// keeping the previous version as `<id>.json.bak`. Loads that fail to parse
+/// fall back to the backup and record a warning retrievable via `take_warnings`.
+/// Files saved under an older schema version are migrated and rewritten on load.
+/// Sessions are compacted on save by their own settings, conversations by `compaction`.
+///
+/// With `with_passphrase` or `with_key` every file is written encrypted (see
+/// `encryption`); plaintext files left from before are encrypted as they are loaded.
+/// The vectors of an `EmbeddingIndex` are kept in `embeddings.json`, encrypted alike.
+///
+/// Sessions and conversations are cached as they are loaded and saved. A cached copy is
+/// used only while its file keeps the modification time and size it had, so a file
+/// another process wrote is read again.
+pub struct FileSessionStore {
+    root: PathBuf,
+    warnings: Mutex<Vec<StoreError>>,
+    compaction: CompactionPolicy,
+    cipher: Option<StoreCipher>,
+    cache: FileCache,
+}
+
+/// Parsed files by path, with the modification time and size they had.
+#[derive(Default)]
+struct FileCache {
+    entries: Mutex<HashMap<PathBuf, CachedFile>>,
+}
+
+struct CachedFile {
+    stamp: (SystemTime, u64),
+    value: Box<dyn Any + Send>,
+}
+
+/// When `path` was last modified and its size, or `None` if it cannot be read.
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl FileCache {
+    /// `view` of the cached `path`, if the file has not changed since.
+    fn get<T: 'static, R>(&self, path: &Path, view: impl FnOnce(&T) -> R) -> Option<R> {
+        let stamp = file_stamp(path);
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(path) {
+            Some(cached) if Some(cached.stamp) == stamp => cached.value.downcast_ref().map(view),
+            Some(_) => {
+                entries.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Whether `path` is cached as it is on disk, and so known to be readable.
+    fn is_fresh(&self, path: &Path) -> bool {
+        let stamp = file_stamp(path);
+        self.entries.lock().is_ok_and(|entries| {
+            entries
+                .get(path)
+                .is_some_and(|cached| Some(cached.stamp) == stamp)
+        })
+    }
+
+    /// Cache `value` as the current contents of `path`.
+    fn insert<T: Send + 'static>(&self, path: &Path, value: T) {
+        let Some(stamp) = file_stamp(path) else {
+            return;
+        };
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= CACHE_CAPACITY && !entries.contains_key(path) {
+                entries.clear();
+            }
+            entries.insert(
+                path.to_path_buf(),
+                CachedFile {
+                    stamp,
+                    value: Box::new(value),
+                },
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IntegrityIssue {
+    pub path: PathBuf,
+    pub error: String,
+    pub backup_valid: bool,
+}
+
+impl FileSessionStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let root = root.into();
+        fs::create_dir_all(root.join(SESSIONS_DIR))?;
+        fs::create_dir_all(root.join(CONVERSATIONS_DIR))?;
+
+        Ok(Self {
+            root,
+            warnings: Mutex::new(Vec::new()),
+            compaction: CompactionPolicy::default(),
+            cipher: None,
+            cache: FileCache::default(),
+        })
+    }
+
+    /// Encrypt with a key derived from `passphrase`. A store without a key file is set up
+    /// for it; otherwise a wrong passphrase fails with `StoreError::WrongKey`.
+    pub fn with_passphrase(mut self, passphrase: &str) -> Result<Self, StoreError> {
+        self.cipher = Some(StoreCipher::with_passphrase(&self.root, passphrase)?);
+        // Files cached as plaintext are to be encrypted as they are loaded
+        self.cache = FileCache::default();
+        Ok(self)
+    }
+
+    /// Encrypt with `key`, as `with_passphrase` does.
+    pub fn with_key(mut self, key: &StoreKey) -> Result<Self, StoreError> {
+        self.cipher = Some(StoreCipher::with_key(&self.root, key)?);
+        self.cache = FileCache::default();
+        Ok(self)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Limit conversation history with `policy` instead of the default settings.
+    pub fn with_compaction(mut self, policy: CompactionPolicy) -> Self {
+        self.compaction = policy;
+        self
+    }
+
+    /// `$XDG_DATA_HOME/parsec`, falling back to `~/.local/share/parsec`.
+    pub fn default_data_dir() -> PathBuf {
+        parsec_core::default_data_dir()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Drain recovery and corruption warnings recorded since the last call.
+    pub fn take_warnings(&self) -> Vec<StoreError> {
+        self.warnings
+            .lock()
+            .map(|mut warnings| std::mem::take(&mut *warnings))
+            .unwrap_or_default()
+    }
+
+    /// Scan the data directory and report every file that no longer parses.
+    pub fn check_integrity(&self) -> Result<Vec<IntegrityIssue>, StoreError> {
+        let mut issues = Vec::new();
+
+        for path in Self::json_files(&self.root.join(SESSIONS_DIR))? {
+            if let Err(e) = self.read_versioned::<Session>(&path) {
+                issues.push(IntegrityIssue {
+                    backup_valid: self.read_versioned::<Session>(&backup_path(&path)).is_ok(),
+                    path,
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        for path in Self::json_files(&self.root.join(CONVERSATIONS_DIR))? {
+            if let Err(e) = self.read_versioned::<ConversationContext>(&path) {
+                issues.push(IntegrityIssue {
+                    backup_valid: self
+                        .read_versioned::<ConversationContext>(&backup_path(&path))
+                        .is_ok(),
+                    path,
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Compact every stored session and conversation in place, for data saved before
+    /// the current limits applied. Files that are already within them are not rewritten.
+    pub fn compact(&self) -> Result<CompactionReport, StoreError> {
+        let mut report = CompactionReport::default();
+
+        for mut session in self.load_all_sessions()? {
+            let compacted = session.compact();
+            if !compacted.is_empty() {
+                self.write(&self.session_path(&session.id)?, &session)?;
+                report.add(compacted);
+            }
+        }
+
+        for path in Self::json_files(&self.root.join(CONVERSATIONS_DIR))? {
+            let lock = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(lock_path(&path))?;
+            lock.lock_exclusive()?;
+
+            let mut conversation = match self.read::<ConversationContext>(&path) {
+                Ok(Some(conversation)) => conversation,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.record_warning(e);
+                    continue;
+                }
+            };
+            let compacted = conversation.compact(&self.compaction);
+            if !compacted.is_empty() {
+                self.write(&path, &conversation)?;
+                report.add(compacted);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Remove a session and its conversations, backups included. Returns how many
+    /// conversations went with it.
+    pub fn delete_session(&self, session_id: &SessionId) -> Result<usize, StoreError> {
+        let session = self.load_session(session_id)?;
+        let mut removed = 0;
+        for conversation_id in &session.conversations {
+            let path = self.conversation_path(conversation_id)?;
+            if Self::remove(&path) > 0 {
+                removed += 1;
+            }
+            let _ = fs::remove_file(lock_path(&path));
+        }
+        Self::remove(&self.session_path(session_id)?);
+        Ok(removed)
+    }
+
+    /// Move a session and its conversations under `archive/`, where they are neither
+    /// listed nor pruned. Returns how many conversations went with it.
+    pub fn archive_session(&self, session_id: &SessionId) -> Result<usize, StoreError> {
+        let session = self.load_session(session_id)?;
+        let archive = self.root.join(ARCHIVE_DIR);
+        fs::create_dir_all(archive.join(SESSIONS_DIR))?;
+        fs::create_dir_all(archive.join(CONVERSATIONS_DIR))?;
+
+        let mut archived = 0;
+        for conversation_id in &session.conversations {
+            let path = self.conversation_path(conversation_id)?;
+            let Some(conversation) = self.read::<ConversationContext>(&path)? else {
+                continue;
+            };
+            self.write(
+                &Self::entry_path(&archive.join(CONVERSATIONS_DIR), conversation_id)?,
+                &conversation,
+            )?;
+            Self::remove(&path);
+            let _ = fs::remove_file(lock_path(&path));
+            archived += 1;
+        }
+        self.write(
+            &Self::entry_path(&archive.join(SESSIONS_DIR), session_id)?,
+            &session,
+        )?;
+        Self::remove(&self.session_path(session_id)?);
+        Ok(archived)
+    }
+
+    fn session_path(&self, session_id: &str) -> Result<PathBuf, StoreError> {
+        Self::entry_path(&self.root.join(SESSIONS_DIR), session_id)
+    }
+
+    fn conversation_path(&self, conversation_id: &str) -> Result<PathBuf, StoreError> {
+        Self::entry_path(&self.root.join(CONVERSATIONS_DIR), conversation_id)
+    }
+
+    fn entry_path(dir: &Path, id: &str) -> Result<PathBuf, StoreError> {
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            return Err(StoreError::StorageError(format!("Invalid id: {}", id)));
+        }
+        Ok(dir.join(format!("{}.json", id)))
+    }
+
+    fn json_files(dir: &Path) -> Result<Vec<PathBuf>, StoreError> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn record_warning(&self, warning: StoreError) {
+        if let Ok(mut warnings) = self.warnings.lock() {
+            warnings.push(warning);
+        }
+    }
+
+    fn write<T: Serialize>(&self, path: &Path, value: &T) -> Result<(), StoreError> {
+        let json = serde_json::to_vec_pretty(value)?;
+        let bytes = match &self.cipher {
+            Some(cipher) => cipher.seal(SCHEMA_VERSION, &json),
+            None => json,
+        };
+        // Only a readable primary becomes the backup; never overwrite a good backup with
+        // junk, nor keep plaintext beside an encrypted store. A cached file was read, and
+        // rewritten if it was plaintext.
+        let keep_backup = self.cache.is_fresh(path)
+            || matches!(self.read_json::<serde_json::Value>(path), Ok((_, false)));
+        write_atomic(path, &bytes, keep_backup)?;
+
+        let backup = backup_path(path);
+        if self.cipher.is_some()
+            && fs::read(&backup).is_ok_and(|bytes| !encryption::is_encrypted(&bytes))
+        {
+            fs::remove_file(&backup)?;
+        }
+        Ok(())
+    }
+
+    /// The JSON in `path`, decrypted, and whether it is plaintext in an encrypted store
+    /// and so should be rewritten.
+    fn read_json<T: DeserializeOwned>(&self, path: &Path) -> Result<(T, bool), StoreError> {
+        let bytes = fs::read(path)?;
+        if !encryption::is_encrypted(&bytes) {
+            return Ok((serde_json::from_slice(&bytes)?, self.cipher.is_some()));
+        }
+        let Some(cipher) = &self.cipher else {
+            return Err(StoreError::Encrypted(path.display().to_string()));
+        };
+        let json = cipher
+            .open(&bytes)
+            .map_err(|()| StoreError::Tampered(path.display().to_string()))?;
+        if let Some(found) = encryption::schema_version(&bytes).filter(|v| *v > SCHEMA_VERSION) {
+            return Err(StoreError::NewerSchema {
+                path: path.display().to_string(),
+                found,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        Ok((serde_json::from_slice(&json)?, false))
+    }
+
+    /// Load `path` as `T`, migrated to the current schema version, and whether it needs
+    /// rewriting for that or to encrypt it.
+    fn read_versioned<T: Versioned>(&self, path: &Path) -> Result<(T, bool), StoreError> {
+        let (mut value, plaintext) = self.read_json::<serde_json::Value>(path)?;
+        let migrated = migrations::migrate::<T>(&mut value, &path.display().to_string())?;
+        Ok((serde_json::from_value(value)?, migrated || plaintext))
+    }
+
+    /// `read`, through the cache.
+    fn read_cached<T: Versioned + Serialize + Clone + Send + 'static>(
+        &self,
+        path: &Path,
+    ) -> Result<Option<T>, StoreError> {
+        self.read_cached_as(path, T::clone)
+    }
+
+    /// `view` of what `read` returns, through the cache; the whole value is not copied
+    /// out of it.
+    fn read_cached_as<T: Versioned + Serialize + Send + 'static, R>(
+        &self,
+        path: &Path,
+        view: impl Fn(&T) -> R,
+    ) -> Result<Option<R>, StoreError> {
+        if let Some(viewed) = self.cache.get(path, &view) {
+            return Ok(Some(viewed));
+        }
+        let Some(value) = self.read::<T>(path)? else {
+            return Ok(None);
+        };
+        let viewed = view(&value);
+        self.cache.insert(path, value);
+        Ok(Some(viewed))
+    }
+
+    /// `view` of the conversations of the sessions `filter` picks that match it, most
+    /// recent first.
+    fn filtered_conversations<R>(
+        &self,
+        filter: &ConversationFilter,
+        view: impl Fn(&ConversationContext) -> R,
+    ) -> Result<Vec<(ListingOrder, R)>, StoreError> {
+        let conversation_ids: Vec<ConversationId> = match &filter.session_id {
+            Some(session_id) => self.load_session(session_id)?.conversations,
+            None => Self::json_files(&self.root.join(SESSIONS_DIR))?
+                .iter()
+                .filter_map(|path| {
+                    self.read_cached_as(path, |session: &Session| session.conversations.clone())
+                        .unwrap_or_else(|e| {
+                            self.record_warning(e);
+                            None
+                        })
+                })
+                .flatten()
+                .collect(),
+        };
+        let mut found = Vec::new();
+        for conversation_id in &conversation_ids {
+            let viewed = self.read_cached_as(
+                &self.conversation_path(conversation_id)?,
+                |conversation: &ConversationContext| {
+                    filter.matches(conversation).then(|| {
+                        let order = (conversation.pinned, conversation.created_at);
+                        (order, view(conversation))
+                    })
+                },
+            );
+            match viewed {
+                Ok(Some(Some(viewed))) => found.push(viewed),
+                Ok(_) => {}
+                Err(e) => self.record_warning(e),
+            }
+        }
+        found.sort_by_key(|(order, _)| std::cmp::Reverse(*order));
+        Ok(found)
+    }
+
+    /// Load `path`, falling back to its backup if the primary is missing or corrupt.
+    /// Returns `Ok(None)` when neither file exists. Data from a newer schema version is
+    /// an error rather than a reason to fall back.
+    fn read<T: Versioned + Serialize>(&self, path: &Path) -> Result<Option<T>, StoreError> {
+        let backup = backup_path(path);
+        if !path.exists() && !backup.exists() {
+            return Ok(None);
+        }
+
+        let primary_error = match self.read_versioned::<T>(path) {
+            Ok((value, rewrite)) => {
+                // The pre-migration file becomes the backup
+                if rewrite {
+                    self.write(path, &value)?;
+                }
+                return Ok(Some(value));
+            }
+            Err(e @ (StoreError::NewerSchema { .. } | StoreError::Encrypted(_))) => return Err(e),
+            Err(e) => e,
+        };
+
+        match self.read_versioned::<T>(&backup) {
+            Ok((value, _)) => {
+                // Put the recovered version back in place so the next load is clean
+                self.write(path, &value)?;
+                self.record_warning(StoreError::RecoveredFromBackup(format!(
+                    "{} ({})",
+                    path.display(),
+                    primary_error
+                )));
+                Ok(Some(value))
+            }
+            Err(_) => Err(StoreError::Corrupted(format!(
+                "{} ({})",
+                path.display(),
+                primary_error
+            ))),
+        }
+    }
+
+    fn remove(path: &Path) -> u64 {
+        let mut reclaimed = 0;
+        for file in [path.to_path_buf(), backup_path(path)] {
+            if let Ok(metadata) = fs::metadata(&file) {
+                if fs::remove_file(&file).is_ok() {
+                    reclaimed += metadata.len();
+                }
+            }
+        }
+        reclaimed
+    }
+
+    fn load_all_sessions(&self) -> Result<Vec<Session>, StoreError> {
+        let mut sessions = Vec::new();
+        for path in Self::json_files(&self.root.join(SESSIONS_DIR))? {
+            match self.read_cached::<Session>(&path) {
+                Ok(Some(session)) => sessions.push(session),
+                Ok(None) => {}
+                Err(e) => self.record_warning(e),
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn save_session(&self, session: &Session) -> Result<(), StoreError> {
+        let mut session = session.clone();
+        session.compact();
+        let path = self.session_path(&session.id)?;
+        self.write(&path, &session)?;
+        self.cache.insert(&path, session);
+        Ok(())
+    }
+
+    fn load_session(&self, session_id: &SessionId) -> Result<Session, StoreError> {
+        self.read_cached(&self.session_path(session_id)?)?
+            .ok_or_else(|| StoreError::StorageError(format!("Session {} not found", session_id)))
+    }
+
+    fn save_conversation(
+        &self,
+        conversation: &mut ConversationContext,
+        expected_revision: Option<u64>,
+    ) -> Result<(), StoreError> {
+        let path = self.conversation_path(&conversation.id)?;
+        // Held until the write is renamed into place, so the revision check and the
+        // write are atomic across processes sharing the data directory
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path(&path))?;
+        lock.lock_exclusive()?;
+
+        let stored = self
+            .read_cached::<ConversationContext>(&path)?
+            .map(|stored| stored.revision);
+        check_revision(conversation, stored, expected_revision)?;
+        conversation.compact(&self.compaction);
+        conversation.revision += 1;
+        if let Err(e) = self.write(&path, conversation) {
+            conversation.revision -= 1;
+            return Err(e);
+        }
+        self.cache.insert(&path, conversation.clone());
+        Ok(())
+    }
+
+    fn load_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Result<ConversationContext, StoreError> {
+        self.read_cached(&self.conversation_path(conversation_id)?)?
+            .ok_or_else(|| {
+                StoreError::StorageError(format!("Conversation {} not found", conversation_id))
+            })
+    }
+
+    fn list_active_sessions(&self) -> Result<Vec<SessionSummary>, StoreError> {
+        let mut summaries = Vec::new();
+        for path in Self::json_files(&self.root.join(SESSIONS_DIR))? {
+            let summary = self.read_cached_as(&path, |session: &Session| SessionSummary {
+                id: session.id.clone(),
+                created_at: session.created_at,
+                last_active: session.last_active,
+                conversation_count: session.conversations.len(),
+                working_directory: session.global_context.working_directory.clone(),
+            });
+            match summary {
+                Ok(Some(summary)) => summaries.push(summary),
+                Ok(None) => {}
+                Err(e) => self.record_warning(e),
+            }
+        }
+        Ok(summaries)
+    }
+
+    fn prune_old_context(
+        &self,
+        retention_policy: &RetentionPolicy,
+        active_session: Option<&SessionId>,
+    ) -> Result<PruneReport, StoreError> {
+        let mut report = PruneReport::default();
+        let sessions = self.load_all_sessions()?;
+        let conversation_paths = Self::json_files(&self.root.join(CONVERSATIONS_DIR))?;
+        let pinned: HashSet<SessionId> = conversation_paths
+            .iter()
+            .filter_map(|path| {
+                self.read_cached_as(path, |conversation: &ConversationContext| {
+                    conversation.pinned.then(|| conversation.session_id.clone())
+                })
+                .ok()
+                .flatten()
+                .flatten()
+            })
+            .collect();
+        let expired = sessions_to_evict(sessions.iter(), retention_policy, active_session, &pinned);
+
+        for session_id in &expired {
+            report.bytes_reclaimed += Self::remove(&self.session_path(session_id)?);
+            report.sessions_removed += 1;
+        }
+
+        let conversation_cutoff = Utc::now()
+            - chrono::Duration::days(retention_policy.conversation_retention_days as i64);
+
+        for path in conversation_paths {
+            let conversation = match self.read::<ConversationContext>(&path) {
+                Ok(Some(conversation)) => conversation,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.record_warning(e);
+                    continue;
+                }
+            };
+
+            let owner_removed = expired.contains(&conversation.session_id);
+            if conversation.pinned
+                || (!owner_removed && conversation.last_activity() > conversation_cutoff)
+            {
+                continue;
+            }
+
+            report.bytes_reclaimed += Self::remove(&path);
+            let _ = fs::remove_file(lock_path(&path));
+            report.conversations_removed += 1;
+
+            if !owner_removed {
+                if let Some(mut session) =
+                    self.read::<Session>(&self.session_path(&conversation.session_id)?)?
+                {
+                    session.conversations.retain(|id| id != &conversation.id);
+                    self.save_session(&session)?;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// One file at a time, so memory use does not grow with the store. Files that cannot
+    /// be read are skipped with a warning.
+    fn search(&self, query: &str, scope: &SearchScope) -> Result<Vec<SearchHit>, StoreError> {
+        let query = SearchQuery::new(query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let session_paths = match &scope.session_id {
+            Some(session_id) => vec![self.session_path(session_id)?],
+            None => Self::json_files(&self.root.join(SESSIONS_DIR))?,
+        };
+        let mut hits = Vec::new();
+        let mut private_environments = HashMap::new();
+        let mut conversation_ids = Vec::new();
+        for path in session_paths {
+            let session = match self.read::<Session>(&path) {
+                Ok(Some(session)) => session,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.record_warning(e);
+                    continue;
+                }
+            };
+            hits.extend(search::session_hits(&session, &query, scope));
+            if let Some(environment) = search::private_environment(&session) {
+                private_environments.insert(session.id.clone(), environment.clone());
+            }
+            if scope.session_id.is_some() {
+                conversation_ids = session.conversations;
+            }
+        }
+
+        let conversation_paths = match scope.session_id {
+            Some(_) => conversation_ids
+                .iter()
+                .map(|id| self.conversation_path(id))
+                .collect::<Result<_, _>>()?,
+            None => Self::json_files(&self.root.join(CONVERSATIONS_DIR))?,
+        };
+        for path in conversation_paths {
+            let conversation = match self.read::<ConversationContext>(&path) {
+                Ok(Some(conversation)) => conversation,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.record_warning(e);
+                    continue;
+                }
+            };
+            hits.extend(search::conversation_hits(
+                &conversation,
+                private_environments.get(&conversation.session_id),
+                &query,
+                scope,
+            ));
+        }
+
+        Ok(search::rank(hits, scope))
+    }
+
+    /// Conversations that cannot be read are skipped with a warning.
+    fn find_conversations(
+        &self,
+        filter: &ConversationFilter,
+    ) -> Result<Vec<ConversationContext>, StoreError> {
+        Ok(self
+            .filtered_conversations(filter, ConversationContext::clone)?
+            .into_iter()
+            .map(|(_, conversation)| conversation)
+            .collect())
+    }
+
+    /// Summarized from cached conversations without copying them.
+    fn list_conversations(
+        &self,
+        filter: &ConversationFilter,
+    ) -> Result<Vec<ConversationSummary>, StoreError> {
+        Ok(self
+            .filtered_conversations(filter, ConversationSummary::of)?
+            .into_iter()
+            .map(|(_, summary)| summary)
+            .collect())
+    }
+
+    fn find_sessions_by_directory(&self, dir: &Path) -> Result<Vec<SessionSummary>, StoreError> {
+        Ok(sessions_covering(self.list_active_sessions()?, dir))
+    }
+
+    /// Bytes of the session and conversation files on disk, not counting backups.
+    fn stats(&self) -> Result<StoreStats, StoreError> {
+        let sessions = Self::json_files(&self.root.join(SESSIONS_DIR))?;
+        let conversations = Self::json_files(&self.root.join(CONVERSATIONS_DIR))?;
+        let mut stats = StoreStats {
+            sessions: sessions.len(),
+            conversations: conversations.len(),
+            ..Default::default()
+        };
+        for path in &sessions {
+            stats.total_bytes += fs::metadata(path)?.len();
+        }
+        for path in &conversations {
+            let bytes = fs::metadata(path)?.len();
+            stats.total_bytes += bytes;
+            let id = path.file_stem().unwrap_or_default().to_string_lossy();
+            if stats
+                .largest_conversation
+                .as_ref()
+                .is_none_or(|(_, largest)| bytes > *largest)
+            {
+                stats.largest_conversation = Some((id.to_string(), bytes));
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl VectorStore for FileSessionStore {
+    fn load_vectors(&self) -> Result<StoredVectors, StoreError> {
+        match self.read_json(&self.root.join(VECTORS_FILE)) {
+            Ok((vectors, _)) => Ok(vectors),
+            Err(StoreError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(StoredVectors::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save_vectors(&self, vectors: &StoredVectors) -> Result<(), StoreError> {
+        self.write(&self.root.join(VECTORS_FILE), vectors)
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// Write `bytes` to a temp file beside `path`, fsync it, keep the current file as a
+/// backup if `keep_backup`, rename into place, then fsync the directory so the rename is
+/// durable. The temp file is named for this process and write, so writers of the same
+/// file never share one.
+fn write_atomic(path: &Path, bytes: &[u8], keep_backup: bool) -> Result<(), StoreError> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| StoreError::StorageError(format!("No parent dir: {}", path.display())))?;
+
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        uuid::Uuid::new_v4().simple()
+    ));
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let written = (|| {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        if keep_backup {
+            fs::copy(path, backup_path(path))?;
+        }
+        fs::rename(&tmp_path, path)
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{conversation, session};
+    use parsec_core::search::SearchHitKind;
+
+    fn store() -> (FileSessionStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("parsec-store-{}", uuid::Uuid::new_v4()));
+        (FileSessionStore::new(&dir).unwrap(), dir)
+    }
+
+    fn execution(i: usize) -> DirectCommandExecution {
+        let output = "x".repeat(2048);
+        DirectCommandExecution {
+            command: format!("echo {}", i),
+            executed_at: Utc::now(),
+            exit_status: 0,
+            stdout: TruncatedText {
+                content: output.clone(),
+                truncated: false,
+                original_length: output.len(),
+            },
+            stderr: TruncatedText {
+                content: String::new(),
+                truncated: false,
+                original_length: 0,
+            },
+            working_directory: ".".into(),
+            classification_overridden: false,
+            duration: std::time::Duration::from_millis(20),
+            completed_at: Some(Utc::now()),
+            termination: Some(CommandTermination::Exited(0)),
+        }
+    }
+
+    #[test]
+    fn saved_sessions_stay_bounded_as_commands_accumulate() {
+        let (store, dir) = store();
+        let mut session = session();
+        session.command_history = (0..10_000).map(execution).collect();
+
+        store.save_session(&session).unwrap();
+
+        let size = fs::metadata(store.session_path(&session.id).unwrap())
+            .unwrap()
+            .len();
+        assert!(size < 1024 * 1024, "session file is {} bytes", size);
+        let stored = store.load_session(&session.id).unwrap();
+        let history = &stored.command_history;
+        assert_eq!(history.len(), session.settings.history_limit);
+        assert_eq!(history.last().unwrap().command, "echo 9999");
+        assert_eq!(
+            history.last().unwrap().duration,
+            std::time::Duration::from_millis(20)
+        );
+        assert_eq!(history[history.len() - 100].stdout.content.len(), 2048);
+        let compacted = &history[history.len() - 101];
+        assert!(compacted.stdout.content.is_empty() && compacted.stdout.truncated);
+        assert_eq!(compacted.stdout.original_length, 2048);
+
+        let report = session.compact();
+        assert_eq!(report.commands_removed, 9_000);
+        assert_eq!(report.outputs_dropped, 900);
+        assert!(report.bytes_saved > 18 * 1024 * 1024);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn conversation_history_keeps_the_latest_events() {
+        let (store, dir) = store();
+        let store = store.with_compaction(CompactionPolicy {
+            max_conversation_events: 3,
+            ..CompactionPolicy::default()
+        });
+        let mut conversation = conversation();
+        conversation.history = (0..5)
+            .map(|i| ConversationEvent {
+                event_type: format!("event_{}", i),
+                timestamp: Utc::now(),
+                data: serde_json::json!({}),
+            })
+            .collect();
+
+        store.save_conversation(&mut conversation, Some(0)).unwrap();
+
+        let events: Vec<String> = store
+            .load_conversation(&conversation.id)
+            .unwrap()
+            .history
+            .into_iter()
+            .map(|event| event.event_type)
+            .collect();
+        assert_eq!(events, ["event_2", "event_3", "event_4"]);
+        assert_eq!(conversation.history.len(), 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sessions_are_deleted_or_archived_with_their_conversations() {
+        let (store, dir) = store();
+        let (mut kept, mut archived) = (session(), session());
+        archived.id = "archived".to_string();
+        for session in [&mut kept, &mut archived] {
+            let mut conversation = conversation();
+            conversation.id = format!("{}-conversation", session.id);
+            conversation.session_id = session.id.clone();
+            session.conversations = vec![conversation.id.clone()];
+            store.save_conversation(&mut conversation, Some(0)).unwrap();
+            store.save_session(session).unwrap();
+        }
+
+        assert_eq!(store.archive_session(&archived.id).unwrap(), 1);
+        assert_eq!(store.delete_session(&kept.id).unwrap(), 1);
+
+        assert!(store.list_active_sessions().unwrap().is_empty());
+        assert!(store.load_conversation(&archived.conversations[0]).is_err());
+        assert!(dir
+            .join("archive/conversations/archived-conversation.json")
+            .exists());
+        assert!(dir.join("archive/sessions/archived.json").exists());
+        assert!(fs::read_dir(dir.join(CONVERSATIONS_DIR))
+            .unwrap()
+            .next()
+            .is_none());
+        assert!(store.delete_session(&kept.id).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pruning_keeps_pinned_conversations_and_their_sessions() {
+        let (store, dir) = store();
+        let policy = RetentionPolicy {
+            session_retention_days: 30,
+            conversation_retention_days: 30,
+            max_sessions: None,
+        };
+        let (mut old, mut pinned_owner) = (session(), session());
+        pinned_owner.id = "pinned-owner".to_string();
+        for session in [&mut old, &mut pinned_owner] {
+            session.last_active = Utc::now() - chrono::Duration::days(60);
+            let mut conversation = conversation();
+            conversation.id = format!("{}-conversation", session.id);
+            conversation.session_id = session.id.clone();
+            conversation.created_at = session.last_active;
+            conversation.pinned = session.id == "pinned-owner";
+            session.conversations = vec![conversation.id.clone()];
+            store.save_conversation(&mut conversation, Some(0)).unwrap();
+            store.save_session(session).unwrap();
+        }
+        let mut recent = conversation();
+        recent.id = "recent".to_string();
+        recent.session_id = pinned_owner.id.clone();
+        store.save_conversation(&mut recent, Some(0)).unwrap();
+        pinned_owner.conversations.push(recent.id.clone());
+        store.save_session(&pinned_owner).unwrap();
+
+        let report = store.prune_old_context(&policy, None).unwrap();
+
+        assert_eq!(report.sessions_removed, 1);
+        assert_eq!(report.conversations_removed, 1);
+        let listed: Vec<(String, bool)> = store
+            .list_conversations(&ConversationFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|summary| (summary.id, summary.pinned))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("pinned-owner-conversation".to_string(), true),
+                ("recent".to_string(), false)
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_active_session_is_never_evicted_by_the_session_cap() {
+        let (store, dir) = store();
+        let policy = RetentionPolicy {
+            session_retention_days: 30,
+            conversation_retention_days: 30,
+            max_sessions: Some(0),
+        };
+        let (mut active, mut other) = (session(), session());
+        active.id = "active".to_string();
+        other.id = "other".to_string();
+        // Less recently active than the other, so it would go first by age
+        active.last_active = Utc::now() - chrono::Duration::days(2);
+        for session in [&active, &other] {
+            store.save_session(session).unwrap();
+        }
+
+        let report = store.prune_old_context(&policy, Some(&active.id)).unwrap();
+
+        assert_eq!(report.sessions_removed, 1);
+        assert!(store.load_session(&active.id).is_ok());
+        assert!(store.load_session(&other.id).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Sessions last active 10 days ago, a day ago and now, the newest holding a
+    /// conversation from 10 days ago and one from now.
+    fn retention_fixture() -> (FileSessionStore, PathBuf) {
+        let (store, dir) = store();
+        for (id, days) in [("old", 10), ("recent", 1), ("newest", 0)] {
+            let mut session = session();
+            session.id = id.to_string();
+            session.last_active = Utc::now() - chrono::Duration::days(days);
+            if id == "newest" {
+                session.conversations = vec!["stale".to_string(), "fresh".to_string()];
+            }
+            store.save_session(&session).unwrap();
+        }
+        for (id, days) in [("stale", 10), ("fresh", 0)] {
+            let mut conversation = conversation();
+            conversation.id = id.to_string();
+            conversation.session_id = "newest".to_string();
+            conversation.created_at = Utc::now() - chrono::Duration::days(days);
+            store.save_conversation(&mut conversation, None).unwrap();
+        }
+        (store, dir)
+    }
+
+    fn policy(
+        session_retention_days: u32,
+        conversation_retention_days: u32,
+        max_sessions: Option<usize>,
+    ) -> RetentionPolicy {
+        RetentionPolicy::from_settings(&SessionSettings {
+            session_retention_days,
+            conversation_retention_days,
+            max_sessions,
+            ..SessionSettings::default()
+        })
+    }
+
+    fn remaining(store: &FileSessionStore) -> (Vec<SessionId>, Vec<String>) {
+        let mut sessions: Vec<SessionId> = store
+            .load_all_sessions()
+            .unwrap()
+            .into_iter()
+            .map(|session| session.id)
+            .collect();
+        sessions.sort();
+        let mut conversations: Vec<String> = ["stale", "fresh"]
+            .into_iter()
+            .filter(|id| store.load_conversation(&id.to_string()).is_ok())
+            .map(str::to_string)
+            .collect();
+        conversations.sort();
+        (sessions, conversations)
+    }
+
+    #[test]
+    fn session_retention_alone_prunes_inactive_sessions() {
+        let (store, dir) = retention_fixture();
+
+        store
+            .prune_old_context(&policy(7, 365, None), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (
+                vec!["newest".to_string(), "recent".to_string()],
+                vec!["fresh".to_string(), "stale".to_string()]
+            )
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn conversation_retention_alone_prunes_inactive_conversations() {
+        let (store, dir) = retention_fixture();
+
+        store
+            .prune_old_context(&policy(365, 7, None), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (
+                vec![
+                    "newest".to_string(),
+                    "old".to_string(),
+                    "recent".to_string()
+                ],
+                vec!["fresh".to_string()]
+            )
+        );
+        let newest = store.load_session(&"newest".to_string()).unwrap();
+        assert_eq!(newest.conversations, vec!["fresh".to_string()]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_session_cap_alone_prunes_the_least_recently_active() {
+        let (store, dir) = retention_fixture();
+
+        store
+            .prune_old_context(&policy(365, 365, Some(1)), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (
+                vec!["newest".to_string()],
+                vec!["fresh".to_string(), "stale".to_string()]
+            )
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn retention_dimensions_combine() {
+        let (store, dir) = retention_fixture();
+
+        let report = store
+            .prune_old_context(&policy(7, 7, Some(1)), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest".to_string()], vec!["fresh".to_string()])
+        );
+        assert_eq!(report.sessions_removed, 2);
+        assert_eq!(report.conversations_removed, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncated_files_are_recovered_from_their_backup() {
+        let (store, dir) = store();
+        let mut session = session();
+        store.save_session(&session).unwrap();
+        session.last_input_kind = Some(InputKind::Shell);
+        store.save_session(&session).unwrap();
+        let path = store.session_path(&session.id).unwrap();
+        let json = fs::read(&path).unwrap();
+        fs::write(&path, &json[..json.len() / 2]).unwrap();
+
+        let recovered = store.load_session(&session.id).unwrap();
+
+        // The backup holds the version before the last save
+        assert_eq!(recovered.id, session.id);
+        assert_eq!(recovered.last_input_kind, None);
+        assert!(matches!(
+            store.take_warnings().as_slice(),
+            [StoreError::RecoveredFromBackup(_)]
+        ));
+        // The recovered version was put back, so the next load is clean
+        let reloaded = FileSessionStore::new(&dir).unwrap();
+        assert!(reloaded.load_session(&session.id).is_ok());
+        assert!(reloaded.take_warnings().is_empty());
+
+        // Without a usable backup the session is reported corrupt
+        fs::write(&path, b"{\"id\": ").unwrap();
+        fs::write(backup_path(&path), b"").unwrap();
+        assert!(matches!(
+            FileSessionStore::new(&dir)
+                .unwrap()
+                .load_session(&session.id),
+            Err(StoreError::Corrupted(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_writes_of_one_file_do_not_share_a_temp_file() {
+        let (store, dir) = store();
+        let session = session();
+        store.save_session(&session).unwrap();
+        let path = store.session_path(&session.id).unwrap();
+
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        let bytes = format!("{{\"writer\": {}}}", i);
+                        write_atomic(path, bytes.as_bytes(), false).unwrap();
+                    }
+                });
+            }
+        });
+
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert!(written["writer"].is_u64());
+        let leftovers: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn old_sessions_are_migrated_and_rewritten_on_load() {
+        let (store, dir) = store();
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/session_v1.json");
+        let id = "2f0c6a52-0d7e-4a1e-9a55-8a8f2f1b6c10".to_string();
+        let path = store.session_path(&id).unwrap();
+        fs::copy(fixture, &path).unwrap();
+
+        let session = store.load_session(&id).unwrap();
+
+        assert_eq!(session.schema_version, SCHEMA_VERSION);
+        let (rewritten, _): (serde_json::Value, _) = store.read_json(&path).unwrap();
+        assert_eq!(rewritten["schema_version"], SCHEMA_VERSION);
+        assert!(rewritten["global_context"]["environment_snapshot"]
+            .get("GITHUB_TOKEN")
+            .is_none());
+        assert_eq!(
+            fs::read(backup_path(&path)).unwrap(),
+            fs::read(fixture).unwrap()
+        );
+
+        // A newer file is refused rather than replaced by its older backup
+        let mut newer = rewritten;
+        newer["schema_version"] = (SCHEMA_VERSION + 1).into();
+        fs::write(&path, newer.to_string()).unwrap();
+        assert!(matches!(
+            store.load_session(&id),
+            Err(StoreError::NewerSchema { .. })
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn encrypted_stores_round_trip_without_plaintext_on_disk() {
+        let (store, dir) = store();
+        // Data saved before encryption was turned on is encrypted as it is loaded
+        let mut plain = session();
+        plain.id = "plain".to_string();
+        store.save_session(&plain).unwrap();
+        store.save_session(&plain).unwrap();
+        let store = store.with_passphrase("correct horse").unwrap();
+        let mut session = session();
+        session.global_context.working_directory = "/home/me/secret-project".into();
+        let mut conversation = conversation();
+
+        store.save_session(&session).unwrap();
+        store.save_conversation(&mut conversation, None).unwrap();
+        store.save_conversation(&mut conversation, None).unwrap();
+
+        let mut listed: Vec<String> = store
+            .list_active_sessions()
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.id)
+            .collect();
+        listed.sort();
+        assert_eq!(listed, vec![plain.id.clone(), session.id.clone()]);
+        assert_eq!(
+            store
+                .load_conversation(&conversation.id)
+                .unwrap()
+                .user_prompt,
+            conversation.user_prompt
+        );
+        for dir in [SESSIONS_DIR, CONVERSATIONS_DIR] {
+            for entry in fs::read_dir(store.root().join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "lock") {
+                    continue;
+                }
+                let bytes = fs::read(&path).unwrap();
+                assert!(encryption::is_encrypted(&bytes), "{}", path.display());
+                assert!(!String::from_utf8_lossy(&bytes).contains("secret-project"));
+            }
+        }
+
+        // Reopening needs the same passphrase
+        let reopened = FileSessionStore::new(&dir)
+            .unwrap()
+            .with_passphrase("correct horse")
+            .unwrap();
+        assert_eq!(
+            reopened
+                .load_session(&session.id)
+                .unwrap()
+                .global_context
+                .working_directory,
+            session.global_context.working_directory
+        );
+        assert!(matches!(
+            FileSessionStore::new(&dir)
+                .unwrap()
+                .with_passphrase("wrong"),
+            Err(StoreError::WrongKey(_))
+        ));
+        assert!(matches!(
+            FileSessionStore::new(&dir)
+                .unwrap()
+                .load_session(&session.id),
+            Err(StoreError::Encrypted(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tampered_files_fail_authentication() {
+        let (store, dir) = store();
+        let store = store.with_key(&StoreKey::generate()).unwrap();
+        let mut session = session();
+        store.save_session(&session).unwrap();
+        session.last_input_kind = Some(InputKind::Shell);
+        store.save_session(&session).unwrap();
+        let path = store.session_path(&session.id).unwrap();
+
+        // A flipped ciphertext byte falls back to the backup, with a warning
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(store
+            .load_session(&session.id)
+            .unwrap()
+            .last_input_kind
+            .is_none());
+        assert!(matches!(
+            store.take_warnings().as_slice(),
+            [StoreError::RecoveredFromBackup(message)] if message.contains("authentication")
+        ));
+
+        // So does a header claiming another schema version; with both tampered, loading fails
+        for file in [path.clone(), backup_path(&path)] {
+            let mut bytes = fs::read(&file).unwrap();
+            bytes[9] ^= 1;
+            fs::write(&file, &bytes).unwrap();
+        }
+        assert!(matches!(
+            store.load_session(&session.id),
+            Err(StoreError::Corrupted(message)) if message.contains("authentication")
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn search_ranks_matches_across_sessions_and_conversations() {
+        let (store, dir) = store();
+        let mut session = session();
+        let mut failed = execution(0);
+        failed.command = "ffmpeg -i a.mp4 out.webm".to_string();
+        failed.exit_status = 1;
+        session.command_history = vec![failed, execution(1)];
+        let mut conversation = crate::test_support::completed_conversation(2, "");
+        conversation.user_prompt = "convert the videos with FFmpeg".to_string();
+        conversation.steps[0].command_attempts[0].candidate.command =
+            "ffmpeg -i in.mp4 -c:v libvpx out.webm".to_string();
+        session.conversations.push(conversation.id.clone());
+        store.save_session(&session).unwrap();
+        store.save_conversation(&mut conversation, None).unwrap();
+
+        let mut private = crate::test_support::session();
+        private.id = "private".to_string();
+        private.settings.privacy_mode = true;
+        private.global_context.environment_snapshot =
+            HashMap::from([("BUCKET".to_string(), "s3://acme-private".to_string())]);
+        let mut upload = execution(2);
+        upload.command = "aws s3 cp out.webm s3://acme-private/".to_string();
+        private.command_history = vec![upload];
+        store.save_session(&private).unwrap();
+
+        let hits = store.search("ffmpeg", &SearchScope::default()).unwrap();
+        let kinds: Vec<_> = hits.iter().map(|hit| hit.kind).collect();
+        // The command that worked comes first
+        assert_eq!(
+            kinds,
+            [
+                SearchHitKind::WorkflowCommand,
+                SearchHitKind::ConversationPrompt,
+                SearchHitKind::DirectCommand,
+            ]
+        );
+        assert_eq!(hits[0].conversation_id.as_deref(), Some("conversation"));
+        assert_eq!(hits[0].step_index, Some(0));
+
+        // Every word must match
+        let hits = store
+            .search("ffmpeg webm", &SearchScope::default())
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.kind.is_command()));
+
+        let scope = SearchScope {
+            session_id: Some("private".to_string()),
+            ..Default::default()
+        };
+        assert!(store.search("ffmpeg", &scope).unwrap().is_empty());
+        let scope = SearchScope {
+            kinds: vec![SearchHitKind::ConversationPrompt],
+            limit: 1,
+            ..Default::default()
+        };
+        assert_eq!(store.search("webm FFMPEG videos", &scope).unwrap().len(), 0);
+        assert_eq!(store.search("ffmpeg", &scope).unwrap().len(), 1);
+
+        // Environment values of a session in privacy mode are neither matched nor shown
+        assert!(store
+            .search("acme", &SearchScope::default())
+            .unwrap()
+            .is_empty());
+        let hits = store.search("aws", &SearchScope::default()).unwrap();
+        assert_eq!(hits[0].snippet, "aws s3 cp out.webm [redacted]/");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn conversations_are_found_by_session_and_tag() {
+        let (store, dir) = store();
+        let mut session = session();
+        let mut other = session.clone();
+        other.id = "other".to_string();
+        let conversations = [
+            ("deploy", "session", vec![" Deploy", "fly"]),
+            ("build", "session", vec!["build"]),
+            ("elsewhere", "other", vec!["deploy"]),
+        ];
+        for (i, (id, session_id, tags)) in conversations.into_iter().enumerate() {
+            let mut conversation = crate::test_support::conversation();
+            conversation.id = id.to_string();
+            conversation.session_id = session_id.to_string();
+            conversation.created_at = Utc::now() + chrono::Duration::seconds(i as i64);
+            for tag in tags {
+                conversation.add_tag(tag);
+            }
+            store.save_conversation(&mut conversation, None).unwrap();
+            match session_id {
+                "session" => session.conversations.push(id.to_string()),
+                _ => other.conversations.push(id.to_string()),
+            }
+        }
+        store.save_session(&session).unwrap();
+        store.save_session(&other).unwrap();
+
+        let ids = |filter: ConversationFilter| -> Vec<String> {
+            let found = store.find_conversations(&filter).unwrap();
+            found
+                .into_iter()
+                .map(|conversation| conversation.id)
+                .collect()
+        };
+        assert_eq!(
+            ids(ConversationFilter::default()),
+            ["elsewhere", "build", "deploy"]
+        );
+        let deploy = |session_id: Option<&str>| ConversationFilter {
+            session_id: session_id.map(str::to_string),
+            tags: vec!["DEPLOY ".to_string()],
+        };
+        assert_eq!(ids(deploy(None)), ["elsewhere", "deploy"]);
+        assert_eq!(ids(deploy(Some("session"))), ["deploy"]);
+        let both = ConversationFilter {
+            tags: vec!["deploy".to_string(), "build".to_string()],
+            ..Default::default()
+        };
+        assert!(ids(both).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cached_loads_see_what_other_stores_wrote() {
+        let (store, dir) = store();
+        let other = FileSessionStore::new(&dir).unwrap();
+        let mut session = session();
+        let mut conversation = conversation();
+        session.conversations.push(conversation.id.clone());
+        store.save_session(&session).unwrap();
+        store.save_conversation(&mut conversation, None).unwrap();
+        assert!(store
+            .load_session(&session.id)
+            .unwrap()
+            .command_history
+            .is_empty());
+
+        session.command_history.push(execution(1));
+        other.save_session(&session).unwrap();
+        conversation.add_tag("deploy");
+        other.save_conversation(&mut conversation, None).unwrap();
+
+        assert_eq!(
+            store
+                .load_session(&session.id)
+                .unwrap()
+                .command_history
+                .len(),
+            1
+        );
+        let summaries = store
+            .list_conversations(&ConversationFilter::default())
+            .unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].tags, ["deploy"]);
+        assert_eq!(summaries[0].steps_total, 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stats_count_stored_files_and_the_largest_conversation() {
+        let (store, dir) = store();
+        store.save_session(&session()).unwrap();
+        let mut small = conversation();
+        let mut large = crate::test_support::completed_conversation(3, "output");
+        large.id = "large".to_string();
+        store.save_conversation(&mut small, None).unwrap();
+        store.save_conversation(&mut large, None).unwrap();
+
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.sessions, stats.conversations), (1, 2));
+        let (largest, bytes) = stats.largest_conversation.unwrap();
+        assert_eq!(largest, "large");
+        let on_disk = fs::metadata(dir.join(CONVERSATIONS_DIR).join("large.json")).unwrap();
+        assert_eq!(bytes, on_disk.len());
+        assert!(stats.total_bytes > bytes);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sessions_are_found_by_directory_closest_first() {
+        let (store, dir) = store();
+        let sessions = [
+            ("root", "/work", 0),
+            ("project", "/work/project", 0),
+            ("project-recent", "/work/project", 1),
+            ("sibling", "/work/project-two", 2),
+        ];
+        for (id, working_directory, minutes) in sessions {
+            let mut session = session();
+            session.id = id.to_string();
+            session.global_context.working_directory = working_directory.into();
+            session.last_active = Utc::now() + chrono::Duration::minutes(minutes);
+            store.save_session(&session).unwrap();
+        }
+
+        let ids = |path: &str| -> Vec<String> {
+            let found = store.find_sessions_by_directory(Path::new(path)).unwrap();
+            found.into_iter().map(|summary| summary.id).collect()
+        };
+        assert_eq!(
+            ids("/work/project/src"),
+            ["project-recent", "project", "root"]
+        );
+        assert_eq!(ids("/work"), ["root"]);
+        assert!(ids("/elsewhere").is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
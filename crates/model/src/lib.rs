@@ -1,65 +1,188 @@
 use chrono::Utc;
+use embedding::{StoredVectors, VectorStore};
+use parsec_core::search::{self, SearchHit, SearchQuery, SearchScope};
 use parsec_core::*;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
 
+pub mod adc;
+pub mod budget;
+pub mod bundle;
+pub mod cache;
+pub mod embedding;
+pub mod encryption;
+pub mod fallback;
+pub mod file_store;
 pub mod google_ai;
+pub mod migrations;
+pub mod openai;
+pub mod prompts;
+pub mod recording;
 
-pub use google_ai::GoogleAiProvider;
+#[cfg(test)]
+mod test_support;
 
-#[derive(Debug, Serialize)]
-struct PlanningPrompt {
-    system: String,
-    session_context: String,
-    conversation_history: String,
-    user_prompt: String,
-    constraints: String,
-}
+pub use budget::PromptBudget;
+pub use cache::ResponseCache;
+pub use embedding::{Embedder, EmbeddingIndex};
+pub use encryption::StoreKey;
+pub use fallback::FallbackProvider;
+pub use file_store::FileSessionStore;
+pub use google_ai::{GoogleAiClient, GoogleAiProvider};
+pub use openai::AzureOpenAiClient;
+pub use prompts::{Prompt, PromptTemplates};
+
+/// Backstop for every HTTP request a client makes. Planning and command generation are
+/// usually given up on sooner, by their own timeouts.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
 
-#[derive(Debug, Serialize)]
-struct StepCommandPrompt {
-    system: String,
-    session_context: String,
-    conversation_context: String,
-    workflow: String,
-    current_step: String,
-    execution_history: String,
-    error_context: Option<String>,
+/// Generation parameters for one `ModelClient` call.
+#[derive(Debug, Clone)]
+pub struct GenerationOptions {
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub stop_sequences: Vec<String>,
+    /// The prompt comes from a privacy-mode session, so it is only cached if the cache allows it.
+    pub private: bool,
+    /// Abandons the call when cancelled; it then fails with `ModelClientError::Cancelled`.
+    pub cancel: CancellationToken,
 }
 
-#[derive(Debug, Deserialize)]
-struct PlanningResponse {
-    steps: Vec<StepDescription>,
+// The cancellation token is not a generation parameter
+impl PartialEq for GenerationOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_tokens == other.max_tokens
+            && self.temperature == other.temperature
+            && self.stop_sequences == other.stop_sequences
+            && self.private == other.private
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct StepDescription {
-    description: String,
+impl Default for GenerationOptions {
+    fn default() -> Self {
+        Self {
+            max_tokens: 2048,
+            temperature: 0.1,
+            stop_sequences: Vec::new(),
+            private: false,
+            cancel: CancellationToken::new(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct CommandGenerationResponse {
-    commands: Vec<CommandDescription>,
-    done: bool,
+#[derive(Debug, thiserror::Error)]
+pub enum ModelClientError {
+    #[error(
+        "Prompt of ~{prompt_tokens} tokens exceeds the {context_window}-token context of {model}"
+    )]
+    ContextLengthExceeded {
+        model: String,
+        prompt_tokens: usize,
+        context_window: usize,
+    },
+    #[error("{0}")]
+    Api(String),
+    #[error("Request failed: {0}")]
+    Request(String),
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+    #[error("Request cancelled")]
+    Cancelled,
 }
 
-#[derive(Debug, Deserialize)]
-struct CommandDescription {
-    command: String,
-    explanation: String,
+/// Rough token count for context checks: about four characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
 }
 
+#[async_trait::async_trait]
 pub trait ModelClient: Send + Sync {
-    fn generate_text(
+    async fn generate_text(
         &self,
         prompt: &str,
-        max_tokens: Option<usize>,
-    ) -> Result<String, anyhow::Error>;
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError>;
+
+    /// A response to a prompt of system instructions and turns. Clients without roles
+    /// get the sections as one text.
+    async fn generate(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        self.generate_text(&prompt.text(), opts).await
+    }
+
+    /// Up to `count` alternative responses to `prompt`, the model's preferred first.
+    /// Clients that cannot ask for several return the one `generate` gives.
+    async fn generate_candidates(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+        _count: u32,
+    ) -> Result<Vec<String>, ModelClientError> {
+        self.generate(prompt, opts).await.map(|text| vec![text])
+    }
+
+    /// Whether the most recent call was answered from a response cache.
+    fn last_call_cached(&self) -> bool {
+        false
+    }
+
+    /// The model calls go to, for health reports.
+    fn model(&self) -> &str {
+        "unknown"
+    }
+
+    /// The cheapest request that shows the model is reachable and accepts the credentials:
+    /// by default a one-token generation.
+    async fn ping(&self) -> Result<(), ModelClientError> {
+        let opts = GenerationOptions {
+            max_tokens: 1,
+            temperature: 0.0,
+            ..GenerationOptions::default()
+        };
+        self.generate_text(PING_PROMPT, &opts).await.map(|_| ())
+    }
+}
+
+/// What `ModelClient::ping` asks for.
+pub const PING_PROMPT: &str = "Reply with OK.";
+
+/// Error text meaning the credentials were rejected.
+const CREDENTIAL_MARKERS: &[&str] = &[
+    "401",
+    "403",
+    "unauthenticated",
+    "permission_denied",
+    "api_key_invalid",
+    "api key not valid",
+];
+
+/// Error text meaning the quota or rate limit ran out.
+const QUOTA_MARKERS: &[&str] = &["429", "resource_exhausted", "quota", "rate limit"];
+
+impl From<ModelClientError> for HealthError {
+    fn from(e: ModelClientError) -> Self {
+        let message = e.to_string();
+        let lowercase = message.to_lowercase();
+        let mentions = |markers: &[&str]| markers.iter().any(|marker| lowercase.contains(marker));
+        match e {
+            ModelClientError::Auth(_) => HealthError::InvalidCredentials(message),
+            ModelClientError::Request(_) => HealthError::Network(message),
+            _ if mentions(CREDENTIAL_MARKERS) => HealthError::InvalidCredentials(message),
+            _ if mentions(QUOTA_MARKERS) => HealthError::Quota(message),
+            _ => HealthError::Failed(message),
+        }
+    }
 }
 
 pub struct InMemorySessionStore {
     sessions: std::sync::RwLock<HashMap<SessionId, Session>>,
     conversations: std::sync::RwLock<HashMap<ConversationId, ConversationContext>>,
+    vectors: std::sync::RwLock<StoredVectors>,
+    compaction: CompactionPolicy,
 }
 
 impl InMemorySessionStore {
@@ -67,8 +190,22 @@ impl InMemorySessionStore {
         Self {
             sessions: std::sync::RwLock::new(HashMap::new()),
             conversations: std::sync::RwLock::new(HashMap::new()),
+            vectors: std::sync::RwLock::new(StoredVectors::default()),
+            compaction: CompactionPolicy::default(),
         }
     }
+
+    /// Limit conversation history with `policy` instead of the default settings.
+    pub fn with_compaction(mut self, policy: CompactionPolicy) -> Self {
+        self.compaction = policy;
+        self
+    }
+}
+
+impl Default for InMemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SessionStore for InMemorySessionStore {
@@ -77,7 +214,9 @@ impl SessionStore for InMemorySessionStore {
             .sessions
             .write()
             .map_err(|_| StoreError::StorageError("Failed to acquire write lock".to_string()))?;
-        sessions.insert(session.id.clone(), session.clone());
+        let mut session = session.clone();
+        session.compact();
+        sessions.insert(session.id.clone(), session);
         Ok(())
     }
 
@@ -92,11 +231,19 @@ impl SessionStore for InMemorySessionStore {
             .ok_or_else(|| StoreError::StorageError(format!("Session {} not found", session_id)))
     }
 
-    fn save_conversation(&self, conversation: &ConversationContext) -> Result<(), StoreError> {
+    fn save_conversation(
+        &self,
+        conversation: &mut ConversationContext,
+        expected_revision: Option<u64>,
+    ) -> Result<(), StoreError> {
         let mut conversations = self
             .conversations
             .write()
             .map_err(|_| StoreError::StorageError("Failed to acquire write lock".to_string()))?;
+        let stored = conversations.get(&conversation.id).map(|c| c.revision);
+        check_revision(conversation, stored, expected_revision)?;
+        conversation.compact(&self.compaction);
+        conversation.revision += 1;
         conversations.insert(conversation.id.clone(), conversation.clone());
         Ok(())
     }
@@ -134,17 +281,418 @@ impl SessionStore for InMemorySessionStore {
         Ok(summaries)
     }
 
-    fn prune_old_context(&self, retention_policy: &RetentionPolicy) -> Result<(), StoreError> {
-        let cutoff_date =
-            Utc::now() - chrono::Duration::days(retention_policy.session_retention_days as i64);
-
+    fn prune_old_context(
+        &self,
+        retention_policy: &RetentionPolicy,
+        active_session: Option<&SessionId>,
+    ) -> Result<PruneReport, StoreError> {
         let mut sessions = self
             .sessions
             .write()
             .map_err(|_| StoreError::StorageError("Failed to acquire write lock".to_string()))?;
+        let mut conversations = self
+            .conversations
+            .write()
+            .map_err(|_| StoreError::StorageError("Failed to acquire write lock".to_string()))?;
 
-        sessions.retain(|_, session| session.last_active > cutoff_date);
+        let mut report = PruneReport::default();
+        let pinned: HashSet<SessionId> = conversations
+            .values()
+            .filter(|conversation| conversation.pinned)
+            .map(|conversation| conversation.session_id.clone())
+            .collect();
+        let expired =
+            sessions_to_evict(sessions.values(), retention_policy, active_session, &pinned);
+
+        for session_id in expired {
+            if let Some(session) = sessions.remove(&session_id) {
+                report.bytes_reclaimed += serialized_size(&session);
+                report.sessions_removed += 1;
+                for conversation_id in &session.conversations {
+                    if let Some(conversation) = conversations.remove(conversation_id) {
+                        report.bytes_reclaimed += serialized_size(&conversation);
+                        report.conversations_removed += 1;
+                    }
+                }
+            }
+        }
+
+        // Conversations past their own retention window
+        let conversation_cutoff = Utc::now()
+            - chrono::Duration::days(retention_policy.conversation_retention_days as i64);
+        let stale: Vec<ConversationId> = conversations
+            .values()
+            .filter(|conversation| {
+                !conversation.pinned && conversation.last_activity() <= conversation_cutoff
+            })
+            .map(|conversation| conversation.id.clone())
+            .collect();
 
+        for conversation_id in stale {
+            if let Some(conversation) = conversations.remove(&conversation_id) {
+                if let Some(session) = sessions.get_mut(&conversation.session_id) {
+                    session.conversations.retain(|id| id != &conversation_id);
+                }
+                report.bytes_reclaimed += serialized_size(&conversation);
+                report.conversations_removed += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn search(&self, query: &str, scope: &SearchScope) -> Result<Vec<SearchHit>, StoreError> {
+        let query = SearchQuery::new(query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sessions = self
+            .sessions
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+        let conversations = self
+            .conversations
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+
+        let mut hits = Vec::new();
+        for session in sessions.values() {
+            if scope.session_id.as_ref().is_none_or(|id| *id == session.id) {
+                hits.extend(search::session_hits(session, &query, scope));
+            }
+        }
+        for conversation in conversations.values() {
+            if scope
+                .session_id
+                .as_ref()
+                .is_some_and(|id| *id != conversation.session_id)
+            {
+                continue;
+            }
+            let private_environment = sessions
+                .get(&conversation.session_id)
+                .and_then(search::private_environment);
+            hits.extend(search::conversation_hits(
+                conversation,
+                private_environment,
+                &query,
+                scope,
+            ));
+        }
+        Ok(search::rank(hits, scope))
+    }
+
+    fn find_conversations(
+        &self,
+        filter: &ConversationFilter,
+    ) -> Result<Vec<ConversationContext>, StoreError> {
+        let sessions = self
+            .sessions
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+        let conversations = self
+            .conversations
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+
+        let mut found: Vec<ConversationContext> = sessions
+            .values()
+            .flat_map(|session| &session.conversations)
+            .filter_map(|id| conversations.get(id))
+            .filter(|conversation| filter.matches(conversation))
+            .cloned()
+            .collect();
+        found.sort_by_key(|conversation| {
+            (
+                std::cmp::Reverse(conversation.pinned),
+                std::cmp::Reverse(conversation.created_at),
+            )
+        });
+        Ok(found)
+    }
+
+    fn find_sessions_by_directory(&self, dir: &Path) -> Result<Vec<SessionSummary>, StoreError> {
+        Ok(sessions_covering(self.list_active_sessions()?, dir))
+    }
+
+    /// Bytes as the sessions and conversations would serialize.
+    fn stats(&self) -> Result<StoreStats, StoreError> {
+        let sessions = self
+            .sessions
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+        let conversations = self
+            .conversations
+            .read()
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))?;
+
+        let mut stats = StoreStats {
+            sessions: sessions.len(),
+            conversations: conversations.len(),
+            total_bytes: sessions.values().map(serialized_size).sum(),
+            largest_conversation: None,
+        };
+        for conversation in conversations.values() {
+            let bytes = serialized_size(conversation);
+            stats.total_bytes += bytes;
+            if stats
+                .largest_conversation
+                .as_ref()
+                .is_none_or(|(_, largest)| bytes > *largest)
+            {
+                stats.largest_conversation = Some((conversation.id.clone(), bytes));
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl VectorStore for InMemorySessionStore {
+    fn load_vectors(&self) -> Result<StoredVectors, StoreError> {
+        self.vectors
+            .read()
+            .map(|vectors| vectors.clone())
+            .map_err(|_| StoreError::StorageError("Failed to acquire read lock".to_string()))
+    }
+
+    fn save_vectors(&self, vectors: &StoredVectors) -> Result<(), StoreError> {
+        let mut stored = self
+            .vectors
+            .write()
+            .map_err(|_| StoreError::StorageError("Failed to acquire write lock".to_string()))?;
+        *stored = vectors.clone();
         Ok(())
     }
 }
+
+/// Reject a save expecting a revision other than the stored one (`None` if nothing is stored yet).
+pub(crate) fn check_revision(
+    conversation: &ConversationContext,
+    stored: Option<u64>,
+    expected: Option<u64>,
+) -> Result<(), StoreError> {
+    let found = stored.unwrap_or(0);
+    match expected {
+        Some(expected) if expected != found => Err(StoreError::Conflict {
+            conversation_id: conversation.id.clone(),
+            expected,
+            found,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The sessions of `summaries` whose working directory is `dir` or an ancestor of it,
+/// ordered as `SessionStore::find_sessions_by_directory` returns them.
+pub(crate) fn sessions_covering(summaries: Vec<SessionSummary>, dir: &Path) -> Vec<SessionSummary> {
+    let mut covering: Vec<SessionSummary> = summaries
+        .into_iter()
+        .filter(|summary| dir.starts_with(&summary.working_directory))
+        .collect();
+    covering.sort_by_key(|summary| {
+        (
+            std::cmp::Reverse(summary.working_directory.components().count()),
+            std::cmp::Reverse(summary.last_active),
+        )
+    });
+    covering
+}
+
+/// Sessions that fall outside the retention window or beyond `max_sessions`.
+/// The active session and the sessions in `pinned`, which hold pinned conversations,
+/// are never selected; they count toward the cap before any other session.
+pub(crate) fn sessions_to_evict<'a>(
+    sessions: impl Iterator<Item = &'a Session>,
+    retention_policy: &RetentionPolicy,
+    active_session: Option<&SessionId>,
+    pinned: &HashSet<SessionId>,
+) -> Vec<SessionId> {
+    let session_cutoff =
+        Utc::now() - chrono::Duration::days(retention_policy.session_retention_days as i64);
+    let kept = |id: &SessionId| active_session == Some(id) || pinned.contains(id);
+
+    let (mut expired, mut remaining): (Vec<&Session>, Vec<&Session>) =
+        sessions.partition(|session| session.last_active <= session_cutoff && !kept(&session.id));
+
+    // Oldest sessions beyond the cap, by last activity, after the ones that are kept
+    if let Some(max_sessions) = retention_policy.max_sessions {
+        if remaining.len() > max_sessions {
+            remaining.sort_by_key(|session| {
+                (!kept(&session.id), std::cmp::Reverse(session.last_active))
+            });
+            expired.extend(
+                remaining
+                    .into_iter()
+                    .skip(max_sessions)
+                    .filter(|session| !kept(&session.id)),
+            );
+        }
+    }
+
+    expired
+        .into_iter()
+        .map(|session| session.id.clone())
+        .collect()
+}
+
+pub(crate) fn serialized_size<T: serde::Serialize>(value: &T) -> u64 {
+    serde_json::to_vec(value)
+        .map(|bytes| bytes.len() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{conversation, session};
+
+    /// Sessions `old`, `recent` and `newest`, last active 10, 1 and 0 days ago, the newest
+    /// owning conversations `stale` and `fresh`, created 10 and 0 days ago.
+    fn retention_fixture() -> InMemorySessionStore {
+        let store = InMemorySessionStore::new();
+        for (id, days) in [("old", 10), ("recent", 1), ("newest", 0)] {
+            let mut session = session();
+            session.id = id.to_string();
+            session.last_active = Utc::now() - chrono::Duration::days(days);
+            if id == "newest" {
+                session.conversations = vec!["stale".to_string(), "fresh".to_string()];
+            }
+            store.save_session(&session).unwrap();
+        }
+        for (id, days) in [("stale", 10), ("fresh", 0)] {
+            let mut conversation = conversation();
+            conversation.id = id.to_string();
+            conversation.session_id = "newest".to_string();
+            conversation.created_at = Utc::now() - chrono::Duration::days(days);
+            store.save_conversation(&mut conversation, None).unwrap();
+        }
+        store
+    }
+
+    fn policy(
+        session_retention_days: u32,
+        conversation_retention_days: u32,
+        max_sessions: Option<usize>,
+    ) -> RetentionPolicy {
+        RetentionPolicy::from_settings(&SessionSettings {
+            session_retention_days,
+            conversation_retention_days,
+            max_sessions,
+            ..SessionSettings::default()
+        })
+    }
+
+    /// The ids of the sessions and conversations left, sorted.
+    fn remaining(store: &InMemorySessionStore) -> (Vec<&'static str>, Vec<&'static str>) {
+        let sessions = ["newest", "old", "recent"]
+            .into_iter()
+            .filter(|id| store.load_session(&id.to_string()).is_ok())
+            .collect();
+        let conversations = ["fresh", "stale"]
+            .into_iter()
+            .filter(|id| store.load_conversation(&id.to_string()).is_ok())
+            .collect();
+        (sessions, conversations)
+    }
+
+    #[test]
+    fn session_retention_alone_prunes_inactive_sessions() {
+        let store = retention_fixture();
+
+        let report = store
+            .prune_old_context(&policy(7, 365, None), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest", "recent"], vec!["fresh", "stale"])
+        );
+        assert_eq!(
+            (report.sessions_removed, report.conversations_removed),
+            (1, 0)
+        );
+        assert!(report.bytes_reclaimed > 0);
+    }
+
+    #[test]
+    fn conversation_retention_alone_prunes_inactive_conversations() {
+        let store = retention_fixture();
+
+        let report = store
+            .prune_old_context(&policy(365, 7, None), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest", "old", "recent"], vec!["fresh"])
+        );
+        assert_eq!(
+            (report.sessions_removed, report.conversations_removed),
+            (0, 1)
+        );
+        let owner = store.load_session(&"newest".to_string()).unwrap();
+        assert_eq!(owner.conversations, ["fresh"]);
+    }
+
+    #[test]
+    fn the_session_cap_alone_prunes_the_least_recently_active() {
+        let store = retention_fixture();
+
+        store
+            .prune_old_context(&policy(365, 365, Some(2)), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest", "recent"], vec!["fresh", "stale"])
+        );
+    }
+
+    #[test]
+    fn retention_dimensions_combine() {
+        let store = retention_fixture();
+
+        let report = store
+            .prune_old_context(&policy(7, 7, Some(1)), None)
+            .unwrap();
+
+        assert_eq!(remaining(&store), (vec!["newest"], vec!["fresh"]));
+        assert_eq!(
+            (report.sessions_removed, report.conversations_removed),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn an_active_session_among_the_oldest_counts_toward_the_cap() {
+        let store = retention_fixture();
+
+        store
+            .prune_old_context(&policy(7, 365, Some(2)), Some(&"old".to_string()))
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest", "old"], vec!["fresh", "stale"])
+        );
+    }
+
+    #[test]
+    fn a_pinned_session_among_the_oldest_counts_toward_the_cap() {
+        let store = retention_fixture();
+        let mut pinned = conversation();
+        pinned.id = "pinned".to_string();
+        pinned.session_id = "old".to_string();
+        pinned.pinned = true;
+        store.save_conversation(&mut pinned, None).unwrap();
+
+        store
+            .prune_old_context(&policy(365, 365, Some(2)), None)
+            .unwrap();
+
+        assert_eq!(
+            remaining(&store),
+            (vec!["newest", "old"], vec!["fresh", "stale"])
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Upgrades of persisted sessions and conversations saved by older versions of parsec.
+//!
+//! Each migration takes the JSON of one schema version to the next, so data of any older
+//! version reaches `SCHEMA_VERSION` by running the migrations after its own in order.
+
+use parsec_core::environment::is_secret_name;
+use parsec_core::*;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// Upgrades schema version `i + 1` to `i + 2`.
+type Migration = fn(&mut Map<String, Value>);
+
+/// Data the stores persist under a schema version.
+pub trait Versioned: DeserializeOwned {
+    /// `MIGRATIONS.len() + 1` must equal `SCHEMA_VERSION`.
+    const MIGRATIONS: &'static [Migration];
+}
+
+impl Versioned for Session {
+    const MIGRATIONS: &'static [Migration] = &[session_v1_to_v2];
+}
+
+impl Versioned for ConversationContext {
+    const MIGRATIONS: &'static [Migration] = &[conversation_v1_to_v2];
+}
+
+/// Bring `value` up to `SCHEMA_VERSION`, returning whether it changed. Data from a newer
+/// version is refused, since older code would silently drop the fields it does not know.
+pub fn migrate<T: Versioned>(value: &mut Value, path: &str) -> Result<bool, StoreError> {
+    let Value::Object(object) = value else {
+        return Err(StoreError::StorageError(format!(
+            "{}: expected a JSON object",
+            path
+        )));
+    };
+    let version = match object.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| {
+                StoreError::StorageError(format!("{}: invalid schema_version {}", path, version))
+            })?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(StoreError::NewerSchema {
+            path: path.to_string(),
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(false);
+    }
+
+    for migration in &T::MIGRATIONS[version as usize - 1..] {
+        migration(object);
+    }
+    object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(true)
+}
+
+/// Drop credential-like variables from the environment snapshot, which used to hold the
+/// whole process environment.
+fn session_v1_to_v2(session: &mut Map<String, Value>) {
+    if let Some(Value::Object(snapshot)) = session
+        .get_mut("global_context")
+        .and_then(|context| context.get_mut("environment_snapshot"))
+    {
+        snapshot.retain(|name, _| !is_secret_name(name));
+    }
+}
+
+/// Steps used to copy the whole session environment; they now keep only the variables
+/// that differ from it, and none did.
+fn conversation_v1_to_v2(conversation: &mut Map<String, Value>) {
+    let Some(Value::Array(steps)) = conversation.get_mut("steps") else {
+        return;
+    };
+    for step in steps {
+        if let Some(Value::Object(context)) = step.get_mut("context_used") {
+            context.remove("environment_vars");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Value {
+        let path = format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn migrations_cover_every_older_version() {
+        assert_eq!(Session::MIGRATIONS.len() as u32 + 1, SCHEMA_VERSION);
+        assert_eq!(
+            ConversationContext::MIGRATIONS.len() as u32 + 1,
+            SCHEMA_VERSION
+        );
+    }
+
+    #[test]
+    fn current_fixtures_load_unchanged() {
+        let mut session = fixture("session_v2.json");
+        let mut conversation = fixture("conversation_v2.json");
+
+        assert!(!migrate::<Session>(&mut session, "session_v2.json").unwrap());
+        assert!(
+            !migrate::<ConversationContext>(&mut conversation, "conversation_v2.json").unwrap()
+        );
+        let session: Session = serde_json::from_value(session).unwrap();
+        let conversation: ConversationContext = serde_json::from_value(conversation).unwrap();
+        assert_eq!(
+            session.global_context.environment_snapshot["EDITOR"],
+            "nvim"
+        );
+        assert_eq!(conversation.steps[0].step.description, "Build the project");
+    }
+
+    #[test]
+    fn version_1_data_is_upgraded() {
+        let mut session = fixture("session_v1.json");
+        let mut conversation = fixture("conversation_v1.json");
+
+        assert!(migrate::<Session>(&mut session, "session_v1.json").unwrap());
+        assert!(migrate::<ConversationContext>(&mut conversation, "conversation_v1.json").unwrap());
+        let session: Session = serde_json::from_value(session).unwrap();
+        let conversation: ConversationContext = serde_json::from_value(conversation).unwrap();
+        assert_eq!(session.schema_version, SCHEMA_VERSION);
+        let snapshot = &session.global_context.environment_snapshot;
+        assert!(snapshot.contains_key("PATH") && !snapshot.contains_key("GITHUB_TOKEN"));
+        assert_eq!(conversation.schema_version, SCHEMA_VERSION);
+        assert!(conversation.steps[0]
+            .context_used
+            .environment_vars
+            .is_empty());
+    }
+
+    #[test]
+    fn newer_data_asks_for_an_upgrade() {
+        let mut session = fixture("session_v2.json");
+        session["schema_version"] = (SCHEMA_VERSION + 1).into();
+
+        let error = migrate::<Session>(&mut session, "session.json").unwrap_err();
+
+        assert!(
+            matches!(error, StoreError::NewerSchema { found, .. } if found == SCHEMA_VERSION + 1)
+        );
+        assert!(error.to_string().ends_with("upgrade parsec"));
+    }
+}
@@ -0,0 +1,433 @@
+//! OpenAI chat completions, as Azure OpenAI serves them: one URL per deployment of a
+//! resource, with the key in an `api-key` header. The deployment picks the model.
+
+use crate::cache::{self, ResponseCache};
+use crate::prompts::{Prompt, Role};
+use crate::{
+    estimate_tokens, GenerationOptions, ModelClient, ModelClientError, PING_PROMPT, REQUEST_TIMEOUT,
+};
+use async_trait::async_trait;
+use parsec_core::metrics::{self, Metrics};
+use parsec_core::*;
+use reqwest::{Client, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Stop sequences the chat completions API takes at most.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+#[derive(Debug, Serialize)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub top_p: f32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatResponse {
+    pub choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatChoice {
+    pub message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl ChatRequest {
+    /// The system sections of `prompt` as one system message, then a `user` or
+    /// `assistant` message for each turn.
+    pub fn new(prompt: &Prompt, opts: &GenerationOptions) -> Self {
+        let message = |role: &str, parts: &[String]| ChatMessage {
+            role: role.to_string(),
+            content: Some(parts.join("\n\n")),
+        };
+        let system = (!prompt.system.is_empty()).then(|| message("system", &prompt.system));
+        let turns = prompt.turns.iter().map(|turn| {
+            let role = match turn.role {
+                Role::Model => "assistant",
+                Role::System | Role::User => "user",
+            };
+            message(role, &turn.parts)
+        });
+        Self {
+            messages: system.into_iter().chain(turns).collect(),
+            max_tokens: opts.max_tokens,
+            temperature: opts.temperature,
+            top_p: 0.95,
+            stop: opts
+                .stop_sequences
+                .iter()
+                .take(MAX_STOP_SEQUENCES)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Where an Azure OpenAI deployment is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AzureDeployment {
+    /// The resource name, the first label of `<resource>.openai.azure.com`.
+    pub resource: String,
+    pub deployment: String,
+    pub api_version: String,
+}
+
+impl AzureDeployment {
+    pub fn chat_completions_url(&self) -> String {
+        format!(
+            "https://{}.openai.azure.com/openai/deployments/{}/chat/completions?api-version={}",
+            self.resource, self.deployment, self.api_version
+        )
+    }
+}
+
+pub struct AzureOpenAiClient {
+    client: Client,
+    api_key: String,
+    deployment: AzureDeployment,
+    cache: Option<Arc<ResponseCache>>,
+    /// Whether the most recent call was answered from `cache`.
+    last_cached: AtomicBool,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl AzureOpenAiClient {
+    pub fn new(api_key: String, deployment: AzureDeployment) -> Result<Self, InitError> {
+        for (name, value) in [
+            ("resource", &deployment.resource),
+            ("deployment", &deployment.deployment),
+            ("API version", &deployment.api_version),
+        ] {
+            if value.is_empty() {
+                return Err(InitError::InitError(format!(
+                    "Azure OpenAI needs a {}",
+                    name
+                )));
+            }
+        }
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| InitError::InitError(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            api_key,
+            deployment,
+            cache: None,
+            last_cached: AtomicBool::new(false),
+            metrics: metrics::noop(),
+        })
+    }
+
+    pub fn with_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Count calls, estimated tokens and latencies under provider `azure-openai`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn request(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+    ) -> Result<Request, ModelClientError> {
+        self.client
+            .post(self.deployment.chat_completions_url())
+            .header("api-key", &self.api_key)
+            .json(&ChatRequest::new(prompt, opts))
+            .build()
+            .map_err(|e| ModelClientError::Request(e.to_string()))
+    }
+
+    /// What a failed response means: a 404 is a deployment name the resource does not
+    /// have, a 401 a key it does not accept.
+    fn status_error(&self, status: StatusCode, body: &str) -> ModelClientError {
+        let message = serde_json::from_str::<ErrorResponse>(body)
+            .map(|error| error.error.message)
+            .unwrap_or_else(|_| body.trim().to_string());
+        match status {
+            StatusCode::UNAUTHORIZED => ModelClientError::Auth(format!(
+                "Azure OpenAI resource {} rejected the API key (401): {}",
+                self.deployment.resource, message
+            )),
+            StatusCode::NOT_FOUND => ModelClientError::Api(format!(
+                "Azure OpenAI resource {} has no deployment named {} (404); check the deployment name and API version: {}",
+                self.deployment.resource, self.deployment.deployment, message
+            )),
+            status => ModelClientError::Api(format!(
+                "Azure OpenAI API error ({}): {}",
+                status, message
+            )),
+        }
+    }
+
+    async fn call(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+    ) -> Result<(String, bool), ModelClientError> {
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|cache| !opts.private || cache.allows_private());
+        let cache_key = cache.map(|_| {
+            let mut parts = vec![
+                "azure-openai".to_string(),
+                self.deployment.resource.clone(),
+                self.deployment.deployment.clone(),
+                opts.temperature.to_string(),
+                opts.max_tokens.to_string(),
+            ];
+            parts.extend(opts.stop_sequences.iter().cloned());
+            parts.extend(prompt.system.iter().map(|text| format!("system: {}", text)));
+            for turn in &prompt.turns {
+                parts.extend(
+                    turn.parts
+                        .iter()
+                        .map(|text| format!("{:?}: {}", turn.role, text)),
+                );
+            }
+            ResponseCache::key(&parts.iter().map(String::as_str).collect::<Vec<_>>())
+        });
+        if let (Some(cache), Some(key)) = (cache, &cache_key) {
+            if let Some(response) = cache.get(key) {
+                return Ok((response, true));
+            }
+        }
+
+        let request = self.request(prompt, opts)?;
+        let response = async {
+            let response = self
+                .client
+                .execute(request)
+                .await
+                .map_err(|e| ModelClientError::Request(e.to_string()))?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(self.status_error(status, &body));
+            }
+            response
+                .json::<ChatResponse>()
+                .await
+                .map_err(|e| ModelClientError::Request(e.to_string()))
+        };
+        let response = tokio::select! {
+            biased;
+            _ = opts.cancel.cancelled() => return Err(ModelClientError::Cancelled),
+            response = response => response?,
+        };
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| {
+                ModelClientError::Api("No response content from Azure OpenAI".to_string())
+            })?;
+
+        if let (Some(cache), Some(key)) = (cache, &cache_key) {
+            cache.put(key, &text);
+        }
+        Ok((text, false))
+    }
+}
+
+#[async_trait]
+impl ModelClient for AzureOpenAiClient {
+    async fn generate_text(
+        &self,
+        prompt: &str,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        self.generate(&Prompt::user(prompt), opts).await
+    }
+
+    /// Sends the system sections as a system message and model turns as the assistant's.
+    async fn generate(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        let started = Instant::now();
+        let (result, cached) = cache::split_cached(self.call(prompt, opts).await);
+        self.last_cached.store(cached, Ordering::SeqCst);
+        let labels = [
+            ("provider", "azure-openai"),
+            ("model", self.deployment.deployment.as_str()),
+        ];
+        let outcome = match &result {
+            Ok(_) if cached => "cached",
+            Ok(_) => "ok",
+            Err(_) => "error",
+        };
+        self.metrics.increment(
+            metrics::MODEL_CALLS,
+            &[labels[0], labels[1], ("outcome", outcome)],
+            1,
+        );
+        if let (Ok(text), "ok") = (&result, outcome) {
+            for (direction, tokens) in [
+                ("prompt", estimate_tokens(&prompt.text())),
+                ("response", estimate_tokens(text)),
+            ] {
+                self.metrics.increment(
+                    metrics::MODEL_TOKENS,
+                    &[labels[0], labels[1], ("direction", direction)],
+                    tokens as u64,
+                );
+            }
+        }
+        self.metrics.observe(
+            metrics::MODEL_LATENCY_MS,
+            &labels,
+            started.elapsed().as_secs_f64() * 1000.0,
+        );
+        result
+    }
+
+    fn last_call_cached(&self) -> bool {
+        self.last_cached.load(Ordering::SeqCst)
+    }
+
+    fn model(&self) -> &str {
+        &self.deployment.deployment
+    }
+
+    /// A one-token completion past the cache, whatever it says.
+    async fn ping(&self) -> Result<(), ModelClientError> {
+        let opts = GenerationOptions {
+            max_tokens: 1,
+            temperature: 0.0,
+            ..GenerationOptions::default()
+        };
+        let response = self
+            .client
+            .execute(self.request(&Prompt::user(PING_PROMPT), &opts)?)
+            .await
+            .map_err(|e| ModelClientError::Request(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(self.status_error(status, &body));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> AzureOpenAiClient {
+        AzureOpenAiClient::new(
+            "key".to_string(),
+            AzureDeployment {
+                resource: "contoso".to_string(),
+                deployment: "gpt-4o".to_string(),
+                api_version: "2024-06-01".to_string(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn requests_go_to_the_deployment_with_the_key_in_a_header() {
+        let opts = GenerationOptions {
+            stop_sequences: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let request = client()
+            .request(&Prompt::user("list files"), &opts)
+            .unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://contoso.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01"
+        );
+        assert_eq!(request.headers()["api-key"], "key");
+        assert!(request.headers().get("authorization").is_none());
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["messages"][0]["content"], "list files");
+        assert_eq!(body["max_tokens"], 2048);
+        assert_eq!(body["stop"].as_array().unwrap().len(), 4);
+
+        assert!(AzureOpenAiClient::new(
+            "key".to_string(),
+            AzureDeployment {
+                resource: "contoso".to_string(),
+                deployment: String::new(),
+                api_version: "2024-06-01".to_string(),
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn prompts_become_system_user_and_assistant_messages() {
+        let mut prompt = Prompt::default();
+        prompt.push(Role::System, "Output JSON.".to_string());
+        prompt.push(Role::User, "Step 1 - Build".to_string());
+        prompt.push(Role::Model, r#"{"commands":[]}"#.to_string());
+        prompt.push(Role::User, "That failed.".to_string());
+
+        let request = ChatRequest::new(&prompt, &GenerationOptions::default());
+        let roles: Vec<&str> = request.messages.iter().map(|m| m.role.as_str()).collect();
+
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert_eq!(request.messages[0].content.as_deref(), Some("Output JSON."));
+    }
+
+    #[test]
+    fn missing_deployments_and_rejected_keys_fail_differently() {
+        let client = client();
+        let not_found = client.status_error(
+            StatusCode::NOT_FOUND,
+            r#"{"error":{"code":"DeploymentNotFound","message":"The API deployment for this resource does not exist."}}"#,
+        );
+        let unauthorized = client.status_error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":{"code":"401","message":"Access denied due to invalid subscription key."}}"#,
+        );
+
+        assert!(matches!(not_found, ModelClientError::Api(_)));
+        assert!(not_found.to_string().contains("no deployment named gpt-4o"));
+        assert!(matches!(unauthorized, ModelClientError::Auth(_)));
+        assert!(unauthorized
+            .to_string()
+            .contains("invalid subscription key"));
+        assert!(!crate::fallback::is_unavailable_error(
+            &not_found.to_string()
+        ));
+    }
+}
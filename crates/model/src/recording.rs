@@ -0,0 +1,175 @@
+use crate::{estimate_tokens, GenerationOptions, ModelClient, ModelClientError, Prompt};
+use async_trait::async_trait;
+use parsec_core::ModelExchange;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Wraps a client and keeps the most recent prompt and raw response, and a count of the
+/// tokens its calls sent and received.
+pub struct RecordingClient {
+    inner: Arc<dyn ModelClient>,
+    last: Mutex<Option<ModelExchange>>,
+    tokens: AtomicU64,
+}
+
+impl RecordingClient {
+    pub fn new(inner: Arc<dyn ModelClient>) -> Self {
+        Self {
+            inner,
+            last: Mutex::new(None),
+            tokens: AtomicU64::new(0),
+        }
+    }
+
+    pub fn last_exchange(&self) -> Option<ModelExchange> {
+        self.last.lock().ok().and_then(|last| last.clone())
+    }
+
+    /// Estimated tokens of the prompts and responses of calls that reached the model;
+    /// cached responses and failed calls are not counted.
+    pub fn tokens_used(&self) -> u64 {
+        self.tokens.load(Ordering::Relaxed)
+    }
+
+    fn count(&self, prompt: &str, responses: &[&str]) {
+        if self.inner.last_call_cached() {
+            return;
+        }
+        let tokens = estimate_tokens(prompt)
+            + responses
+                .iter()
+                .map(|response| estimate_tokens(response))
+                .sum::<usize>();
+        self.tokens.fetch_add(tokens as u64, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl ModelClient for RecordingClient {
+    async fn generate_text(
+        &self,
+        prompt: &str,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        let result = self.inner.generate_text(prompt, opts).await;
+        if let Ok(text) = &result {
+            self.count(prompt, &[text]);
+        }
+        if let Ok(mut last) = self.last.lock() {
+            *last = Some(ModelExchange {
+                prompt: prompt.to_string(),
+                response: result.as_ref().ok().cloned(),
+            });
+        }
+        result
+    }
+
+    /// Records the sections of `prompt` as one text.
+    async fn generate(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        let result = self.inner.generate(prompt, opts).await;
+        if let Ok(text) = &result {
+            self.count(&prompt.text(), &[text]);
+        }
+        if let Ok(mut last) = self.last.lock() {
+            *last = Some(ModelExchange {
+                prompt: prompt.text(),
+                response: result.as_ref().ok().cloned(),
+            });
+        }
+        result
+    }
+
+    /// Records the candidates one after another, separated by `---` lines.
+    async fn generate_candidates(
+        &self,
+        prompt: &Prompt,
+        opts: &GenerationOptions,
+        count: u32,
+    ) -> Result<Vec<String>, ModelClientError> {
+        let result = self.inner.generate_candidates(prompt, opts, count).await;
+        if let Ok(texts) = &result {
+            self.count(
+                &prompt.text(),
+                &texts.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
+        }
+        if let Ok(mut last) = self.last.lock() {
+            *last = Some(ModelExchange {
+                prompt: prompt.text(),
+                response: result.as_ref().ok().map(|texts| texts.join("\n---\n")),
+            });
+        }
+        result
+    }
+
+    fn last_call_cached(&self) -> bool {
+        self.inner.last_call_cached()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    // Pings are not exchanges worth debugging, so they are not recorded
+    async fn ping(&self) -> Result<(), ModelClientError> {
+        self.inner.ping().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeModelClient;
+
+    #[tokio::test]
+    async fn keeps_the_latest_exchange_including_failures() {
+        let client = RecordingClient::new(Arc::new(
+            FakeModelClient::new().respond("{}").fail("503 UNAVAILABLE"),
+        ));
+
+        client
+            .generate_text("first", &GenerationOptions::default())
+            .await
+            .unwrap();
+        let exchange = client.last_exchange().unwrap();
+        assert_eq!(exchange.prompt, "first");
+        assert_eq!(exchange.response.as_deref(), Some("{}"));
+
+        assert!(client
+            .generate_text("second", &GenerationOptions::default())
+            .await
+            .is_err());
+        let exchange = client.last_exchange().unwrap();
+        assert_eq!(exchange.prompt, "second");
+        assert_eq!(exchange.response, None);
+    }
+
+    #[tokio::test]
+    async fn counts_tokens_of_answered_calls() {
+        let client = RecordingClient::new(Arc::new(
+            FakeModelClient::new()
+                .respond("12345678")
+                .fail("503 UNAVAILABLE"),
+        ));
+
+        client
+            .generate_text("abcd", &GenerationOptions::default())
+            .await
+            .unwrap();
+        let after_one = client.tokens_used();
+        assert_eq!(
+            after_one,
+            (estimate_tokens("abcd") + estimate_tokens("12345678")) as u64
+        );
+
+        assert!(client
+            .generate_text("abcd", &GenerationOptions::default())
+            .await
+            .is_err());
+        assert_eq!(client.tokens_used(), after_one);
+    }
+}
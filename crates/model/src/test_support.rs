@@ -0,0 +1,290 @@
+//! Fakes and fixtures shared by the model crate's tests.
+
+use crate::embedding::Embedder;
+use crate::{GenerationOptions, ModelClient, ModelClientError};
+use async_trait::async_trait;
+use chrono::Utc;
+use parsec_core::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Embeds text as how often it mentions each of a few topics, recording every batch it
+/// is sent.
+pub(crate) struct WordEmbedder {
+    pub(crate) batches: Mutex<Vec<Vec<String>>>,
+    fail: bool,
+}
+
+const TOPICS: [&[&str]; 2] = [
+    &["docker", "image", "container", "deploy"],
+    &["test", "npm", "jest"],
+];
+
+impl WordEmbedder {
+    pub(crate) fn new() -> Self {
+        Self {
+            batches: Mutex::new(Vec::new()),
+            fail: false,
+        }
+    }
+
+    /// Fail every call with a `ModelClientError::Api`.
+    pub(crate) fn failing() -> Self {
+        Self {
+            fail: true,
+            ..Self::new()
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for WordEmbedder {
+    fn embedding_model(&self) -> &str {
+        "words"
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ModelClientError> {
+        self.batches.lock().unwrap().push(texts.to_vec());
+        if self.fail {
+            return Err(ModelClientError::Api("embeddings unavailable".to_string()));
+        }
+        Ok(texts
+            .iter()
+            .map(|text| {
+                TOPICS
+                    .iter()
+                    .map(|words| {
+                        words
+                            .iter()
+                            .map(|word| text.matches(word).count() as f32)
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+/// Replays canned responses in order and records every request it receives.
+pub(crate) struct FakeModelClient {
+    responses: Mutex<VecDeque<Result<String, String>>>,
+    pub(crate) requests: Mutex<Vec<(String, GenerationOptions)>>,
+    delay: Duration,
+}
+
+impl FakeModelClient {
+    pub(crate) fn new() -> Self {
+        Self {
+            responses: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn respond(self, response: &str) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back(Ok(response.to_string()));
+        self
+    }
+
+    /// Answer the next request with a `ModelClientError::Api` carrying `message`.
+    pub(crate) fn fail(self, message: &str) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back(Err(message.to_string()));
+        self
+    }
+
+    /// Wait `delay` before answering each request.
+    pub(crate) fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub(crate) fn last_request(&self) -> Option<(String, GenerationOptions)> {
+        self.requests.lock().unwrap().last().cloned()
+    }
+}
+
+#[async_trait]
+impl ModelClient for FakeModelClient {
+    async fn generate_text(
+        &self,
+        prompt: &str,
+        opts: &GenerationOptions,
+    ) -> Result<String, ModelClientError> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((prompt.to_string(), opts.clone()));
+        tokio::time::sleep(self.delay).await;
+        match self.responses.lock().unwrap().pop_front() {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(message)) => Err(ModelClientError::Api(message)),
+            None => Err(ModelClientError::Api(
+                "FakeModelClient has no response queued".to_string(),
+            )),
+        }
+    }
+}
+
+pub(crate) fn session() -> Session {
+    Session {
+        schema_version: SCHEMA_VERSION,
+        id: "session".to_string(),
+        created_at: Utc::now(),
+        last_active: Utc::now(),
+        conversations: Vec::new(),
+        command_history: Vec::new(),
+        global_context: GlobalContext {
+            working_directory: ".".into(),
+            environment_snapshot: HashMap::new(),
+            detected_project_type: None,
+            active_tools: Vec::new(),
+            tools_cache_key: None,
+        },
+        settings: SessionSettings::default(),
+        last_input_kind: None,
+        classification_log: Vec::new(),
+        classification_corrections: Vec::new(),
+        context_items: Vec::new(),
+        provider_health: None,
+        step_timings: Vec::new(),
+    }
+}
+
+pub(crate) fn conversation() -> ConversationContext {
+    ConversationContext {
+        schema_version: SCHEMA_VERSION,
+        id: "conversation".to_string(),
+        session_id: "session".to_string(),
+        name: "Test".to_string(),
+        auto_name: "Test".to_string(),
+        user_prompt: "do the thing".to_string(),
+        created_at: Utc::now(),
+        workflow: None,
+        steps: Vec::new(),
+        status: ConversationStatus::Ready,
+        history: Vec::new(),
+        model_provider: "mock".to_string(),
+        context_summary: ContextSummary {
+            key_achievements: Vec::new(),
+            generated_artifacts: Vec::new(),
+            environment_changes: Vec::new(),
+            learned_preferences: HashMap::new(),
+            summarized_steps: Vec::new(),
+        },
+        classification_overridden: false,
+        plan_edited: false,
+        forked_from: None,
+        parent_conversation: None,
+        task: None,
+        tags: Vec::new(),
+        preflight: Vec::new(),
+        revision: 0,
+        outcome: None,
+        notes: Vec::new(),
+        pinned: false,
+        context_free: false,
+        answer: None,
+        attachments: Vec::new(),
+    }
+}
+
+/// An executed attempt of `command` that printed `stdout`.
+pub(crate) fn attempt(command: &str, stdout: &str) -> CommandAttempt {
+    CommandAttempt {
+        candidate: GeneratedCommand {
+            command: command.to_string(),
+            explanation: String::new(),
+            risk_score: None,
+            expected_duration: None,
+            missing_tools: Vec::new(),
+            kind: CommandKind::Single,
+            stdin: None,
+            interactive: false,
+            undo_command: None,
+        },
+        approved: true,
+        executed: true,
+        exit_status: Some(0),
+        stdout: TruncatedText {
+            content: stdout.to_string(),
+            truncated: false,
+            original_length: stdout.len(),
+        },
+        stderr: TruncatedText {
+            content: String::new(),
+            truncated: false,
+            original_length: 0,
+        },
+        error: None,
+        timestamp: Utc::now(),
+        duration: std::time::Duration::from_millis(1500),
+        completed_at: Some(Utc::now()),
+        termination: Some(CommandTermination::Exited(0)),
+        timeout: Some(std::time::Duration::from_secs(300)),
+        attached: false,
+        environment_changes: Vec::new(),
+        sandbox: None,
+    }
+}
+
+/// `command` run directly in `directory` `age_mins` minutes ago, printing `stdout`.
+pub(crate) fn direct_command(
+    command: &str,
+    directory: &str,
+    stdout: &str,
+    age_mins: i64,
+) -> DirectCommandExecution {
+    DirectCommandExecution {
+        command: command.to_string(),
+        executed_at: Utc::now() - chrono::Duration::minutes(age_mins),
+        exit_status: 0,
+        stdout: TruncatedText::new(stdout.to_string(), 1_000),
+        stderr: TruncatedText::new(String::new(), 1_000),
+        working_directory: directory.into(),
+        classification_overridden: false,
+        duration: Duration::from_millis(10),
+        completed_at: None,
+        termination: None,
+    }
+}
+
+/// A conversation whose workflow has `steps` steps, each completed by one attempt.
+pub(crate) fn completed_conversation(steps: usize, stdout: &str) -> ConversationContext {
+    let workflow_steps: Vec<WorkflowStep> = (1..=steps)
+        .map(|i| WorkflowStep {
+            id: i.to_string(),
+            description: format!("Run stage {}", i),
+            depends_on: None,
+            estimated_minutes: None,
+            estimate_source: EstimateSource::Planner,
+        })
+        .collect();
+    let mut conversation = conversation();
+    conversation.steps = workflow_steps
+        .iter()
+        .map(|step| WorkflowStepState {
+            step: step.clone(),
+            status: StepStatus::Complete,
+            command_attempts: vec![attempt(&format!("make stage-{}", step.id), stdout)],
+            context_used: StepContext {
+                working_directory: ".".into(),
+                environment_vars: HashMap::new(),
+                previous_outputs: Vec::new(),
+                error_context: None,
+            },
+            artifacts_produced: Vec::new(),
+            user_hints: Vec::new(),
+        })
+        .collect();
+    conversation.workflow = Some(WorkflowPlan {
+        steps: workflow_steps,
+    });
+    conversation
+}
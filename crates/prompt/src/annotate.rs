@@ -0,0 +1,109 @@
+//! Changes the user makes to how a conversation is listed and remembered: its name, its
+//! notes and its pin. They need only the store, so the CLI makes them without a model
+//! provider; `PromptOrchestrator` wraps them to tell its observers. Each change is saved
+//! with an event recording it, the last of the returned conversation's history.
+
+use crate::OrchestratorError;
+use chrono::Utc;
+use parsec_core::naming;
+use parsec_core::*;
+
+/// Rename a conversation; parsec no longer renames it once the user has.
+pub fn rename(
+    store: &dyn SessionStore,
+    conversation_id: &ConversationId,
+    name: &str,
+) -> Result<ConversationContext, OrchestratorError> {
+    let name = naming::truncated(&name.split_whitespace().collect::<Vec<_>>().join(" "));
+    if name.is_empty() {
+        return Err(OrchestratorError::InvalidName(
+            "a name needs at least one word".to_string(),
+        ));
+    }
+    let mut conversation = store.load_conversation(conversation_id)?;
+    let data = serde_json::json!({ "from": conversation.name, "to": name });
+    conversation.name = name;
+    save_with_event(store, conversation, "conversation_renamed", data)
+}
+
+/// Attach a note to a conversation, after those it has.
+pub fn add_note(
+    store: &dyn SessionStore,
+    conversation_id: &ConversationId,
+    text: &str,
+) -> Result<ConversationContext, OrchestratorError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(OrchestratorError::InvalidNote(
+            "a note needs some text".to_string(),
+        ));
+    }
+    let mut conversation = store.load_conversation(conversation_id)?;
+    conversation.notes.push(ConversationNote {
+        text: text.to_string(),
+        added_at: Utc::now(),
+    });
+    let data = serde_json::json!({ "note": text });
+    save_with_event(store, conversation, "conversation_noted", data)
+}
+
+/// Remove note `number`, counted from 1, or with `None` every note.
+pub fn remove_notes(
+    store: &dyn SessionStore,
+    conversation_id: &ConversationId,
+    number: Option<usize>,
+) -> Result<ConversationContext, OrchestratorError> {
+    let mut conversation = store.load_conversation(conversation_id)?;
+    let removed = match number {
+        None if !conversation.notes.is_empty() => std::mem::take(&mut conversation.notes),
+        Some(number) if (1..=conversation.notes.len()).contains(&number) => {
+            vec![conversation.notes.remove(number - 1)]
+        }
+        _ => {
+            return Err(OrchestratorError::InvalidNote(format!(
+                "conversation {} has {} notes",
+                conversation.id,
+                conversation.notes.len()
+            )))
+        }
+    };
+    let data = serde_json::json!({
+        "removed": removed.iter().map(|note| &note.text).collect::<Vec<_>>(),
+    });
+    save_with_event(store, conversation, "conversation_notes_removed", data)
+}
+
+/// Pin or unpin a conversation; one already so is left alone, without an event.
+pub fn set_pinned(
+    store: &dyn SessionStore,
+    conversation_id: &ConversationId,
+    pinned: bool,
+) -> Result<ConversationContext, OrchestratorError> {
+    let mut conversation = store.load_conversation(conversation_id)?;
+    if conversation.pinned == pinned {
+        return Ok(conversation);
+    }
+    conversation.pinned = pinned;
+    let event_type = if pinned {
+        "conversation_pinned"
+    } else {
+        "conversation_unpinned"
+    };
+    save_with_event(store, conversation, event_type, serde_json::json!({}))
+}
+
+fn save_with_event(
+    store: &dyn SessionStore,
+    mut conversation: ConversationContext,
+    event_type: &str,
+    data: serde_json::Value,
+) -> Result<ConversationContext, OrchestratorError> {
+    conversation.history.push(ConversationEvent {
+        event_type: event_type.to_string(),
+        timestamp: Utc::now(),
+        data,
+    });
+    let expected = conversation.revision;
+    store.save_conversation(&mut conversation, Some(expected))?;
+    Ok(conversation)
+}
@@ -0,0 +1,293 @@
+use crate::{OrchestratorError, StepExecution, WorkflowPause};
+use async_trait::async_trait;
+use parsec_core::command_rules::Finding;
+use parsec_core::*;
+use parsec_executor::jobs::JobHandle;
+use parsec_executor::DEFAULT_BLOCK_THRESHOLD;
+use std::time::Duration;
+
+/// Risk score above which approving a command takes a typed confirmation.
+pub const DEFAULT_CONFIRM_THRESHOLD: f32 = 0.6;
+
+/// What to do with the command a step is about to run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovalDecision {
+    Approve,
+    /// Do not run it; the step is skipped and the reason recorded.
+    Deny {
+        reason: String,
+    },
+    /// Run this command line instead.
+    Edit(String),
+    Skip,
+    Abort,
+    /// Reject the command and ask the model for others, guided by the hint. An empty
+    /// hint asks again about the same commands.
+    Hint(String),
+    /// Run it as a background job, handed to `ApprovalPolicy::on_background`.
+    Background,
+    /// Run it on a terminal of its own wired to the user's, for commands that prompt.
+    /// Its output is shown as it runs and kept as a transcript.
+    Interactive,
+}
+
+/// What `PromptOrchestrator::run_workflow` is doing, for policies that show progress.
+#[derive(Debug)]
+pub enum WorkflowProgress<'a> {
+    /// Commands are about to be generated for a step.
+    StepStarted { step_index: usize },
+    Commands {
+        step_index: usize,
+        commands: &'a GeneratedCommands,
+    },
+    Executed {
+        step_index: usize,
+        execution: &'a StepExecution,
+    },
+    /// The approved command could not be run or started.
+    ExecutionFailed {
+        step_index: usize,
+        error: &'a OrchestratorError,
+    },
+    /// A step that used up its attempts was resolved this way.
+    Escalated {
+        step_index: usize,
+        escalation: StepEscalation,
+    },
+    /// Rate limits held back the next command.
+    Paused(&'a WorkflowPause),
+    /// Every command generated for the step is above the block threshold, so the step
+    /// needs the user's attention; `command` is the first of them.
+    NeedsAttention {
+        step_index: usize,
+        command: &'a GeneratedCommand,
+        threshold: f32,
+    },
+}
+
+/// What approving a command takes beyond the policy's decision.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Confirmation {
+    NotNeeded,
+    /// The literal word `yes`, or `phrase` retyped: the part of the command that would
+    /// do the damage, such as its target path.
+    Typed {
+        phrase: String,
+    },
+    /// Scored above the block threshold while dangerous commands are not allowed, so it
+    /// is never offered and never runs.
+    Blocked,
+    /// The word `override`, to run a command that matched these `Warn` validation rules.
+    Override {
+        findings: Vec<Finding>,
+    },
+    /// The word `yes`, to run a command that needs a terminal attached to the user's:
+    /// its output is shown rather than recorded, and it has no timeout.
+    Terminal {
+        reason: String,
+    },
+    /// `y` or `yes`, to run a command outside the sandbox, which would keep it off the
+    /// network it needs.
+    Unsandboxed {
+        reason: String,
+    },
+}
+
+impl Confirmation {
+    /// Whether `typed` confirms the command.
+    pub fn accepts(&self, typed: &str) -> bool {
+        match self {
+            Confirmation::NotNeeded => true,
+            Confirmation::Typed { phrase } => {
+                let typed = typed.trim();
+                typed == "yes" || typed == phrase
+            }
+            Confirmation::Blocked => false,
+            Confirmation::Override { .. } => typed.trim() == "override",
+            Confirmation::Terminal { .. } => typed.trim() == "yes",
+            Confirmation::Unsandboxed { .. } => matches!(typed.trim(), "y" | "yes"),
+        }
+    }
+}
+
+/// The confirmation tiers commands fall into by risk score, applied by `run_workflow` to
+/// every policy's approvals.
+#[derive(Debug, Clone, Copy)]
+pub struct RiskTiers {
+    confirm_threshold: f32,
+    block_threshold: f32,
+    allow_dangerous: bool,
+}
+
+impl Default for RiskTiers {
+    fn default() -> Self {
+        Self {
+            confirm_threshold: DEFAULT_CONFIRM_THRESHOLD,
+            block_threshold: DEFAULT_BLOCK_THRESHOLD,
+            allow_dangerous: false,
+        }
+    }
+}
+
+impl RiskTiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_confirm_threshold(mut self, threshold: f32) -> Self {
+        self.confirm_threshold = threshold;
+        self
+    }
+
+    /// Never offer commands scored above `threshold` unless dangerous commands are
+    /// allowed. The orchestrator's executor refuses them by the same threshold.
+    pub fn with_block_threshold(mut self, threshold: f32) -> Self {
+        self.block_threshold = threshold;
+        self
+    }
+
+    pub fn block_threshold(&self) -> f32 {
+        self.block_threshold
+    }
+
+    /// Let commands above the block threshold run once confirmed, as `--allow-dangerous`
+    /// does. The orchestrator's executor then runs them too.
+    pub fn with_allow_dangerous(mut self, allow: bool) -> Self {
+        self.allow_dangerous = allow;
+        self
+    }
+
+    pub fn allows_dangerous(&self) -> bool {
+        self.allow_dangerous
+    }
+
+    pub fn confirmation(&self, command: &GeneratedCommand) -> Confirmation {
+        let risk_score = command.risk_score.unwrap_or(0.0);
+        if risk_score > self.block_threshold && !self.allow_dangerous {
+            Confirmation::Blocked
+        } else if risk_score > self.confirm_threshold {
+            Confirmation::Typed {
+                phrase: confirmation_phrase(&command.command),
+            }
+        } else {
+            Confirmation::NotNeeded
+        }
+    }
+}
+
+/// The part of `command` to retype: its last path argument (`/` in `rm -rf ./build /`),
+/// else its last argument that is not a flag, else the whole command.
+pub fn confirmation_phrase(command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let arguments: Vec<&str> = words
+        .iter()
+        .skip(1)
+        .filter(|word| !word.starts_with('-'))
+        .copied()
+        .collect();
+    arguments
+        .iter()
+        .rev()
+        .find(|word| word.contains('/') || word.starts_with('.') || word.starts_with('~'))
+        .or_else(|| arguments.last())
+        .map(|word| word.to_string())
+        .unwrap_or_else(|| command.trim().to_string())
+}
+
+/// Decides on the commands `PromptOrchestrator::run_workflow` runs, so each frontend
+/// supplies its own approval flow: a terminal prompt, a web form, or a fixed rule.
+#[async_trait]
+pub trait ApprovalPolicy: Send + Sync {
+    async fn decide(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> ApprovalDecision;
+
+    /// A longer timeout to rerun a command that timed out under; `None` moves on.
+    async fn retry_timeout(
+        &self,
+        _conversation: &ConversationContext,
+        _step_index: usize,
+        _attempt: &CommandAttempt,
+    ) -> Option<Duration> {
+        None
+    }
+
+    /// How to resolve a step that used up its attempts.
+    async fn escalate(
+        &self,
+        _conversation: &ConversationContext,
+        _step_index: usize,
+    ) -> StepEscalation {
+        StepEscalation::Abort
+    }
+
+    /// Ask for the typed confirmation a high-risk command, or one matching `Warn`
+    /// validation rules, needs and return exactly what was typed; `run_workflow` checks
+    /// it with `Confirmation::accepts`. The default returns `None`, so policies that
+    /// cannot ask never run such commands.
+    async fn confirm(
+        &self,
+        _conversation: &ConversationContext,
+        _step_index: usize,
+        _command: &GeneratedCommand,
+        _confirmation: &Confirmation,
+    ) -> Option<String> {
+        None
+    }
+
+    /// Whether to go on after the circuit breaker paused the workflow; the run stops
+    /// otherwise. The default stops, so going on always takes a person.
+    async fn resume(&self, _conversation: &ConversationContext, _pause: &WorkflowPause) -> bool {
+        false
+    }
+
+    /// Take the job started for a `Background` decision; the step stays Running until
+    /// `finish_background_command` records it. Policies that never decide `Background`
+    /// need not implement this.
+    fn on_background(
+        &self,
+        _conversation: &ConversationContext,
+        _step_index: usize,
+        _command: &GeneratedCommand,
+        _job: JobHandle,
+    ) {
+    }
+
+    fn on_progress(&self, _conversation: &ConversationContext, _progress: WorkflowProgress<'_>) {}
+}
+
+/// Approves commands up to a risk score and denies the rest, as `--auto-approve` does.
+pub struct RiskThresholdPolicy {
+    threshold: f32,
+}
+
+impl RiskThresholdPolicy {
+    pub fn new(threshold: f32) -> Self {
+        Self { threshold }
+    }
+}
+
+#[async_trait]
+impl ApprovalPolicy for RiskThresholdPolicy {
+    async fn decide(
+        &self,
+        _conversation: &ConversationContext,
+        _step_index: usize,
+        command: &GeneratedCommand,
+    ) -> ApprovalDecision {
+        let risk_score = command.risk_score.unwrap_or(0.0);
+        if risk_score > self.threshold {
+            ApprovalDecision::Deny {
+                reason: format!(
+                    "Risk score {:.2} exceeds threshold {:.2}; not auto-approving",
+                    risk_score, self.threshold
+                ),
+            }
+        } else {
+            ApprovalDecision::Approve
+        }
+    }
+}
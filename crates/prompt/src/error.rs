@@ -0,0 +1,57 @@
+use parsec_core::bindings::BindingError;
+use parsec_core::*;
+
+/// Everything `PromptOrchestrator` can fail with. Model, execution and store errors are
+/// wrapped as-is so callers can tell "try again later" from a caller mistake.
+#[derive(Debug, thiserror::Error)]
+pub enum OrchestratorError {
+    #[error(transparent)]
+    Plan(#[from] PlanError),
+    #[error(transparent)]
+    CommandGen(#[from] CommandGenError),
+    #[error(transparent)]
+    Health(#[from] HealthError),
+    #[error(transparent)]
+    Execution(#[from] ExecutionError),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Binding(#[from] BindingError),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Step index {0} out of range")]
+    InvalidStepIndex(usize),
+    #[error("Step {0} is no longer part of the plan")]
+    StepNotFound(StepId),
+    #[error("Step {} has already started", .0 + 1)]
+    StepAlreadyStarted(usize),
+    #[error("Step {} has not failed", .0 + 1)]
+    StepNotFailed(usize),
+    #[error("Step {} used all {attempts} attempts; skip it, replan or abort", .step_index + 1)]
+    AttemptsExhausted { step_index: usize, attempts: usize },
+    #[error("Conversation {id} is {status:?} and cannot be resumed")]
+    ConversationNotResumable {
+        id: ConversationId,
+        status: ConversationStatus,
+    },
+    #[error("Conversation was not forked")]
+    NotForked,
+    #[error("{0}")]
+    InvalidPlanEdit(String),
+    #[error("Invalid conversation name: {0}")]
+    InvalidName(String),
+    #[error("Invalid tag: {0}")]
+    InvalidTag(String),
+    #[error("Invalid note: {0}")]
+    InvalidNote(String),
+    #[error("Preflight checks failed: {}; run with --force to start anyway", describe(.0))]
+    Preflight(Vec<preflight::PreflightFinding>),
+}
+
+fn describe(findings: &[preflight::PreflightFinding]) -> String {
+    findings
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -0,0 +1,114 @@
+//! What happens to a step that used up its attempts: skipped, replanned from, or the end
+//! of the conversation, as the approval policy decided.
+
+use crate::{OrchestratorError, PromptOrchestrator};
+use parsec_core::*;
+
+impl PromptOrchestrator {
+    /// Resolve a step that exhausted its attempts.
+    pub async fn handle_step_failure(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        escalation: StepEscalation,
+    ) -> Result<(), OrchestratorError> {
+        if step_index >= conversation.steps.len() {
+            return Err(OrchestratorError::InvalidStepIndex(step_index));
+        }
+        if conversation.steps[step_index].status != StepStatus::Failed {
+            return Err(OrchestratorError::StepNotFailed(step_index));
+        }
+
+        self.record_event(
+            conversation,
+            "step_escalated",
+            serde_json::json!({
+                "step_index": step_index,
+                "escalation": escalation,
+            }),
+        );
+
+        match escalation {
+            StepEscalation::Skip => {
+                self.transition_conversation(conversation, ConversationStatus::InProgress);
+                self.transition_step(conversation, step_index, StepStatus::Skipped);
+            }
+            StepEscalation::Replan => self.replan_after(conversation, session, step_index).await?,
+            StepEscalation::Abort => return self.abort_conversation(conversation),
+        }
+
+        self.save(conversation)?;
+        Ok(())
+    }
+
+    /// Keep steps up to the failed one and ask the planner for the rest of the work.
+    async fn replan_after(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+    ) -> Result<(), OrchestratorError> {
+        let failed = &conversation.steps[step_index];
+        let completed: Vec<String> = conversation.steps[..step_index]
+            .iter()
+            .filter(|step| step.status == StepStatus::Complete)
+            .map(|step| format!("- {}", step.step.description))
+            .collect();
+        let last_error = failed
+            .command_attempts
+            .last()
+            .map(|attempt| attempt.stderr.content.trim().to_string())
+            .unwrap_or_default();
+        let prompt = format!(
+            "{}\n\nAlready completed:\n{}\n\nThe step \"{}\" failed after repeated attempts \
+             (last error: {}). Plan only the remaining work, avoiding that approach.",
+            conversation.user_prompt,
+            if completed.is_empty() {
+                "- nothing".to_string()
+            } else {
+                completed.join("\n")
+            },
+            failed.step.description,
+            last_error
+        );
+
+        let workflow = self
+            .plan(
+                conversation,
+                session,
+                &prompt,
+                &CancellationToken::new(),
+                None,
+            )
+            .await?;
+
+        let dropped = conversation.steps.len() - step_index - 1;
+        conversation.steps.truncate(step_index + 1);
+        conversation.steps.extend(
+            workflow
+                .steps
+                .iter()
+                .map(|step| Self::new_step_state(step, session)),
+        );
+        conversation.workflow = Some(WorkflowPlan {
+            steps: conversation
+                .steps
+                .iter()
+                .map(|state| state.step.clone())
+                .collect(),
+        });
+
+        self.record_event(
+            conversation,
+            "workflow_replanned",
+            serde_json::json!({
+                "after_step": step_index,
+                "steps_dropped": dropped,
+                "steps_added": workflow.steps.len(),
+            }),
+        );
+        self.transition_conversation(conversation, ConversationStatus::InProgress);
+        Ok(())
+    }
+}
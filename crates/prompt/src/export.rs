@@ -0,0 +1,446 @@
+use parsec_core::*;
+use std::fmt::Write;
+use std::str::FromStr;
+
+const MAX_OUTPUT_LINES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!(
+                "Unknown export format: {} (expected md or json)",
+                other
+            )),
+        }
+    }
+}
+
+/// Load a conversation from the store and render it. Environment values are
+/// redacted when the owning session has privacy mode enabled.
+pub fn export_conversation(
+    store: &dyn SessionStore,
+    conversation_id: &ConversationId,
+    format: ExportFormat,
+) -> Result<String, crate::OrchestratorError> {
+    let conversation = store.load_conversation(conversation_id)?;
+    let privacy_mode = store
+        .load_session(&conversation.session_id)
+        .map(|session| session.settings.privacy_mode)
+        .unwrap_or(false);
+
+    render_conversation(&conversation, format, privacy_mode)
+}
+
+pub fn render_conversation(
+    conversation: &ConversationContext,
+    format: ExportFormat,
+    privacy_mode: bool,
+) -> Result<String, crate::OrchestratorError> {
+    let mut conversation = conversation.clone();
+    if privacy_mode {
+        redact_environment(&mut conversation);
+    }
+
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&conversation)?),
+        ExportFormat::Markdown => Ok(render_markdown(&conversation)),
+    }
+}
+
+fn redact_environment(conversation: &mut ConversationContext) {
+    for step in &mut conversation.steps {
+        for value in step.context_used.environment_vars.values_mut() {
+            *value = secrets::REDACTED_VALUE.to_string();
+        }
+    }
+    for change in &mut conversation.context_summary.environment_changes {
+        change.old_value = change
+            .old_value
+            .as_ref()
+            .map(|_| secrets::REDACTED_VALUE.to_string());
+        change.new_value = secrets::REDACTED_VALUE.to_string();
+    }
+}
+
+fn render_markdown(conversation: &ConversationContext) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# {}\n", conversation.name);
+    let _ = writeln!(out, "- **Conversation:** `{}`", conversation.id);
+    let _ = writeln!(out, "- **Status:** {:?}", conversation.status);
+    let _ = writeln!(out, "- **Provider:** {}", conversation.model_provider);
+    if !conversation.tags.is_empty() {
+        let _ = writeln!(out, "- **Tags:** {}", conversation.tags.join(", "));
+    }
+    if conversation.pinned {
+        let _ = writeln!(out, "- **Pinned**");
+    }
+    if conversation.context_free {
+        let _ = writeln!(out, "- **Planned without the session's context**");
+    }
+    if let Some(source) = &conversation.forked_from {
+        let _ = writeln!(out, "- **Forked from:** `{}`", source);
+    }
+    if let Some(parent) = &conversation.parent_conversation {
+        let _ = writeln!(out, "- **Follows up on:** `{}`", parent);
+    }
+    if conversation.plan_edited {
+        let _ = writeln!(out, "- **Plan:** edited by the user");
+    }
+    let _ = writeln!(
+        out,
+        "- **Created:** {}",
+        conversation.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    let _ = writeln!(
+        out,
+        "- **Command time:** {}\n",
+        format_duration(conversation.command_time())
+    );
+
+    let _ = writeln!(out, "## Prompt\n\n{}\n", quote(&conversation.user_prompt));
+
+    if !conversation.attachments.is_empty() {
+        let _ = writeln!(out, "## Attached\n");
+        for attachment in &conversation.attachments {
+            let _ = writeln!(
+                out,
+                "<details><summary>{}: {}</summary>\n",
+                attachment.reference, attachment.source
+            );
+            let _ = writeln!(
+                out,
+                "```\n{}\n```\n</details>\n",
+                trim_output(&attachment.content)
+            );
+        }
+    }
+
+    if !conversation.notes.is_empty() {
+        let _ = writeln!(out, "## Notes\n");
+        for note in &conversation.notes {
+            let _ = writeln!(
+                out,
+                "- {} _({})_",
+                note.text,
+                note.added_at.format("%Y-%m-%d %H:%M")
+            );
+        }
+        let _ = writeln!(out);
+    }
+
+    if let Some(answer) = &conversation.answer {
+        let _ = writeln!(out, "## Answer\n\n{}\n", answer);
+        return out;
+    }
+
+    let _ = writeln!(out, "## Plan\n");
+    if conversation.steps.is_empty() {
+        let _ = writeln!(out, "_No workflow was planned._\n");
+    }
+    for (i, step) in conversation.steps.iter().enumerate() {
+        let time = step.command_time();
+        let _ = writeln!(
+            out,
+            "{}. {} — _{:?}_{}",
+            i + 1,
+            step.step.description,
+            step.status,
+            if time.is_zero() {
+                String::new()
+            } else {
+                format!(" ({})", format_duration(time))
+            }
+        );
+    }
+
+    for (i, step) in conversation.steps.iter().enumerate() {
+        let _ = writeln!(out, "\n## Step {}: {}\n", i + 1, step.step.description);
+        for hint in &step.user_hints {
+            let _ = writeln!(out, "{}\n", quote(&format!("**Hint:** {}", hint)));
+        }
+        if step.command_attempts.is_empty() {
+            let _ = writeln!(out, "_No commands were run._");
+            continue;
+        }
+
+        for (attempt, repeats) in collapse_attempts(&step.command_attempts) {
+            render_attempt(&mut out, attempt, repeats);
+        }
+    }
+
+    if !conversation.context_summary.environment_changes.is_empty() {
+        let _ = writeln!(out, "\n## Environment changes\n");
+        for change in &conversation.context_summary.environment_changes {
+            let _ = writeln!(out, "- `{}={}`", change.variable_name, change.new_value);
+        }
+    }
+
+    out
+}
+
+fn render_attempt(out: &mut String, attempt: &CommandAttempt, repeats: usize) {
+    let status = match (attempt.executed, attempt.exit_status) {
+        (false, _) if !attempt.approved && attempt.error.is_none() => {
+            "declined by user".to_string()
+        }
+        (false, _) => "not executed".to_string(),
+        (true, _) if attempt.termination.is_some() => format!(
+            "exit {} in {}",
+            attempt.exit_description(),
+            format_duration(attempt.duration)
+        ),
+        (true, Some(code)) => format!("exit {} in {}", code, format_duration(attempt.duration)),
+        (true, None) => "exit unknown".to_string(),
+    };
+    let status = match &attempt.sandbox {
+        Some(sandbox) => format!("{}, sandboxed by {}", status, sandbox),
+        None => status,
+    };
+
+    let _ = writeln!(out, "```sh\n$ {}\n```", attempt.candidate.command);
+    if let Some(undo) = &attempt.candidate.undo_command {
+        let _ = writeln!(out, "- undo: `{}`", undo);
+    }
+    if repeats > 1 {
+        let _ = writeln!(
+            out,
+            "- {} at {} (repeated {} times)",
+            status,
+            attempt.timestamp.format("%H:%M:%S"),
+            repeats
+        );
+    } else {
+        let _ = writeln!(
+            out,
+            "- {} at {}",
+            status,
+            attempt.timestamp.format("%H:%M:%S")
+        );
+    }
+
+    for (label, text) in [("stdout", &attempt.stdout), ("stderr", &attempt.stderr)] {
+        if text.content.trim().is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n<details><summary>{}</summary>\n", label);
+        let _ = writeln!(out, "```\n{}\n```\n</details>\n", trim_output(text));
+    }
+}
+
+/// Collapse runs of identical failing attempts (same command and exit status) into one entry.
+fn collapse_attempts(attempts: &[CommandAttempt]) -> Vec<(&CommandAttempt, usize)> {
+    let mut collapsed: Vec<(&CommandAttempt, usize)> = Vec::new();
+    for attempt in attempts {
+        if let Some((last, count)) = collapsed.last_mut() {
+            if attempt.error.is_some()
+                && last.error.is_some()
+                && last.candidate.command == attempt.candidate.command
+                && last.exit_status == attempt.exit_status
+            {
+                *last = attempt;
+                *count += 1;
+                continue;
+            }
+        }
+        collapsed.push((attempt, 1));
+    }
+    collapsed
+}
+
+/// `text` as a Markdown block quote, every line of it quoted.
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}", line).trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn trim_output(text: &TruncatedText) -> String {
+    let lines: Vec<&str> = text.content.trim_end().lines().collect();
+    let mut trimmed = lines
+        .iter()
+        .take(MAX_OUTPUT_LINES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if lines.len() > MAX_OUTPUT_LINES || text.truncated {
+        let _ = write!(
+            trimmed,
+            "\n… ({} bytes total)",
+            text.original_length.max(text.content.len())
+        );
+    }
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PromptOrchestrator;
+    use chrono::Utc;
+    use parsec_model::{GoogleAiProvider, InMemorySessionStore};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn conversation(prompt: &str) -> ConversationContext {
+        let orchestrator = PromptOrchestrator::new(
+            Arc::new(GoogleAiProvider::new("test-key".to_string()).unwrap()),
+            Arc::new(InMemorySessionStore::new()),
+        );
+        orchestrator
+            .create_conversation(&"session".to_string(), prompt.to_string())
+            .unwrap()
+    }
+
+    fn text(content: &str) -> TruncatedText {
+        TruncatedText::new(content.to_string(), 1000)
+    }
+
+    /// An attempt of `command` that exited with `exit_status`.
+    fn attempt(command: &str, exit_status: i32) -> CommandAttempt {
+        CommandAttempt {
+            candidate: GeneratedCommand {
+                command: command.to_string(),
+                explanation: String::new(),
+                risk_score: None,
+                expected_duration: None,
+                missing_tools: Vec::new(),
+                kind: CommandKind::Single,
+                stdin: None,
+                interactive: false,
+                undo_command: None,
+            },
+            approved: true,
+            executed: true,
+            exit_status: Some(exit_status),
+            stdout: text(""),
+            stderr: text(&format!("exited with {}", exit_status)),
+            error: (exit_status != 0).then(|| {
+                ExecutionError::ExecutionFailed(format!("Command exited with {}", exit_status))
+            }),
+            timestamp: Utc::now(),
+            duration: std::time::Duration::from_millis(10),
+            completed_at: Some(Utc::now()),
+            termination: None,
+            timeout: None,
+            attached: false,
+            environment_changes: Vec::new(),
+            sandbox: None,
+        }
+    }
+
+    fn step(
+        attempts: Vec<CommandAttempt>,
+        environment_vars: HashMap<String, String>,
+    ) -> WorkflowStepState {
+        WorkflowStepState {
+            step: WorkflowStep {
+                id: "step-1".to_string(),
+                description: "Run the tests".to_string(),
+                depends_on: None,
+                estimated_minutes: None,
+                estimate_source: EstimateSource::Planner,
+            },
+            status: StepStatus::Complete,
+            command_attempts: attempts,
+            context_used: StepContext {
+                working_directory: ".".into(),
+                environment_vars,
+                previous_outputs: Vec::new(),
+                error_context: None,
+            },
+            artifacts_produced: Vec::new(),
+            user_hints: Vec::new(),
+        }
+    }
+
+    fn markdown(conversation: &ConversationContext, privacy_mode: bool) -> String {
+        render_conversation(conversation, ExportFormat::Markdown, privacy_mode).unwrap()
+    }
+
+    #[test]
+    fn runs_of_the_same_failure_collapse_into_one_entry() {
+        let mut conversation = conversation("run the tests");
+        let mut attempts: Vec<CommandAttempt> = (0..300).map(|_| attempt("make test", 2)).collect();
+        attempts.push(attempt("make test", 0));
+        conversation.steps.push(step(attempts, HashMap::new()));
+
+        let report = markdown(&conversation, false);
+
+        assert_eq!(report.matches("$ make test").count(), 2);
+        assert_eq!(report.matches("(repeated 300 times)").count(), 1);
+        assert_eq!(report.matches("exited with 2").count(), 1);
+    }
+
+    #[test]
+    fn failures_that_differ_are_kept_apart() {
+        let mut conversation = conversation("run the tests");
+        conversation.steps.push(step(
+            vec![
+                attempt("make test", 2),
+                attempt("make test", 1),
+                attempt("make check", 1),
+                attempt("make test", 2),
+            ],
+            HashMap::new(),
+        ));
+
+        let report = markdown(&conversation, false);
+
+        assert_eq!(report.matches("$ make").count(), 4);
+        assert!(!report.contains("repeated"));
+    }
+
+    #[test]
+    fn privacy_mode_redacts_environment_values() {
+        let mut conversation = conversation("deploy it");
+        conversation.steps.push(step(
+            vec![attempt("make deploy", 0)],
+            HashMap::from([("DEPLOY_TOKEN".to_string(), "step-value".to_string())]),
+        ));
+        conversation
+            .context_summary
+            .environment_changes
+            .push(EnvironmentChange {
+                variable_name: "DEPLOY_TOKEN".to_string(),
+                old_value: Some("old-value".to_string()),
+                new_value: "new-value".to_string(),
+                changed_at: Utc::now(),
+            });
+
+        for format in [ExportFormat::Markdown, ExportFormat::Json] {
+            let private = render_conversation(&conversation, format, true).unwrap();
+            for value in ["step-value", "old-value", "new-value"] {
+                assert!(!private.contains(value), "{:?} shows {}", format, value);
+            }
+            assert!(private.contains("DEPLOY_TOKEN"));
+            assert!(private.contains(secrets::REDACTED_VALUE));
+        }
+        let shared = render_conversation(&conversation, ExportFormat::Json, false).unwrap();
+        for value in ["step-value", "old-value", "new-value"] {
+            assert!(shared.contains(value));
+        }
+    }
+
+    #[test]
+    fn every_line_of_a_prompt_is_quoted() {
+        let conversation = conversation("set up the project\n\n# then\n- run the tests");
+
+        let report = markdown(&conversation, false);
+
+        assert!(report
+            .contains("## Prompt\n\n> set up the project\n>\n> # then\n> - run the tests\n\n"));
+    }
+}
@@ -0,0 +1,121 @@
+use parsec_core::*;
+
+/// Re-apply what `local` appended since `base` onto `stored`, a newer copy saved by
+/// another writer: new events, command attempts, hints, artifacts and step summaries,
+/// plus any step or conversation status `local` changed. Returns false when that cannot
+/// be done without overwriting the other writer, i.e. both changed the same status or a
+/// step `local` touched is gone.
+pub(crate) fn merge_appended(
+    stored: &mut ConversationContext,
+    base: &ConversationContext,
+    local: &ConversationContext,
+) -> bool {
+    for local_step in &local.steps {
+        let Some(base_step) = base
+            .steps
+            .iter()
+            .find(|step| step.step.id == local_step.step.id)
+        else {
+            continue;
+        };
+        let attempts = appended(&base_step.command_attempts, &local_step.command_attempts);
+        let hints = appended(&base_step.user_hints, &local_step.user_hints);
+        let artifacts = appended(
+            &base_step.artifacts_produced,
+            &local_step.artifacts_produced,
+        );
+        if attempts.is_empty()
+            && hints.is_empty()
+            && artifacts.is_empty()
+            && local_step.status == base_step.status
+        {
+            continue;
+        }
+
+        let Some(stored_step) = stored
+            .steps
+            .iter_mut()
+            .find(|step| step.step.id == local_step.step.id)
+        else {
+            return false;
+        };
+        if !merge_status(
+            &mut stored_step.status,
+            &base_step.status,
+            &local_step.status,
+        ) {
+            return false;
+        }
+        stored_step.command_attempts.extend_from_slice(attempts);
+        stored_step.user_hints.extend_from_slice(hints);
+        stored_step.artifacts_produced.extend_from_slice(artifacts);
+    }
+
+    for artifact in appended(
+        &base.context_summary.generated_artifacts,
+        &local.context_summary.generated_artifacts,
+    ) {
+        let summary = &mut stored.context_summary.generated_artifacts;
+        if !summary
+            .iter()
+            .any(|known| known.file_path == artifact.file_path)
+        {
+            summary.push(artifact.clone());
+        }
+    }
+
+    stored
+        .context_summary
+        .environment_changes
+        .extend_from_slice(appended(
+            &base.context_summary.environment_changes,
+            &local.context_summary.environment_changes,
+        ));
+
+    // Summaries of steps the other writer already compressed are redundant
+    let summary = &local.context_summary;
+    let newly_summarized = appended(
+        &base.context_summary.summarized_steps,
+        &summary.summarized_steps,
+    );
+    if !newly_summarized
+        .iter()
+        .any(|id| stored.context_summary.summarized_steps.contains(id))
+    {
+        stored
+            .context_summary
+            .key_achievements
+            .extend_from_slice(appended(
+                &base.context_summary.key_achievements,
+                &summary.key_achievements,
+            ));
+        stored
+            .context_summary
+            .summarized_steps
+            .extend_from_slice(newly_summarized);
+    }
+
+    if !merge_status(&mut stored.status, &base.status, &local.status) {
+        return false;
+    }
+    stored
+        .history
+        .extend_from_slice(appended(&base.history, &local.history));
+    true
+}
+
+fn appended<'a, T>(base: &[T], local: &'a [T]) -> &'a [T] {
+    local.get(base.len()..).unwrap_or_default()
+}
+
+/// Three-way merge of one status: keep `local`'s change unless `stored` moved elsewhere.
+fn merge_status<T: PartialEq + Clone>(stored: &mut T, base: &T, local: &T) -> bool {
+    if local == base || stored == local {
+        return true;
+    }
+    if stored == base {
+        *stored = local.clone();
+        return true;
+    }
+    false
+}
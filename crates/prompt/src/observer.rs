@@ -0,0 +1,230 @@
+use parsec_core::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Receives orchestrator activity as it happens. All methods default to no-ops
+/// so frontends only implement what they render. Implementations must return
+/// quickly; they run inline on the orchestrator's thread.
+pub trait OrchestratorObserver: Send + Sync {
+    fn on_event(&self, _conversation_id: &ConversationId, _event: &ConversationEvent) {}
+
+    fn on_step_status_change(
+        &self,
+        _conversation_id: &ConversationId,
+        _step_index: usize,
+        _from: &StepStatus,
+        _to: &StepStatus,
+    ) {
+    }
+
+    /// What one stream of a finished command attempt printed, once the attempt is
+    /// recorded; commands do not stream their output while they run.
+    fn on_command_output(
+        &self,
+        _conversation_id: &ConversationId,
+        _step_index: usize,
+        _stream: OutputStream,
+        _output: &str,
+    ) {
+    }
+}
+
+/// Notification forwarded by `ChannelObserver`.
+#[derive(Debug, Clone)]
+pub enum OrchestratorNotification {
+    Event {
+        conversation_id: ConversationId,
+        event: ConversationEvent,
+    },
+    StepStatusChanged {
+        conversation_id: ConversationId,
+        step_index: usize,
+        from: StepStatus,
+        to: StepStatus,
+    },
+    CommandOutput {
+        conversation_id: ConversationId,
+        step_index: usize,
+        stream: OutputStream,
+        output: String,
+    },
+}
+
+/// Forwards notifications over a tokio channel so async UIs can consume a stream.
+/// Uses `try_send`, so a full or closed channel drops the notification instead of blocking.
+pub struct ChannelObserver {
+    sender: mpsc::Sender<OrchestratorNotification>,
+}
+
+impl ChannelObserver {
+    pub fn new(sender: mpsc::Sender<OrchestratorNotification>) -> Self {
+        Self { sender }
+    }
+
+    /// Convenience constructor returning the observer and the receiving end.
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<OrchestratorNotification>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Self::new(sender), receiver)
+    }
+
+    fn forward(&self, notification: OrchestratorNotification) {
+        if let Err(e) = self.sender.try_send(notification) {
+            warn!("Dropping orchestrator notification: {}", e);
+        }
+    }
+}
+
+impl OrchestratorObserver for ChannelObserver {
+    fn on_event(&self, conversation_id: &ConversationId, event: &ConversationEvent) {
+        self.forward(OrchestratorNotification::Event {
+            conversation_id: conversation_id.clone(),
+            event: event.clone(),
+        });
+    }
+
+    fn on_step_status_change(
+        &self,
+        conversation_id: &ConversationId,
+        step_index: usize,
+        from: &StepStatus,
+        to: &StepStatus,
+    ) {
+        self.forward(OrchestratorNotification::StepStatusChanged {
+            conversation_id: conversation_id.clone(),
+            step_index,
+            from: from.clone(),
+            to: to.clone(),
+        });
+    }
+
+    fn on_command_output(
+        &self,
+        conversation_id: &ConversationId,
+        step_index: usize,
+        stream: OutputStream,
+        output: &str,
+    ) {
+        self.forward(OrchestratorNotification::CommandOutput {
+            conversation_id: conversation_id.clone(),
+            step_index,
+            stream,
+            output: output.to_string(),
+        });
+    }
+}
+
+/// The orchestrator's subscriber list. A panicking observer is logged and skipped
+/// so it can never interrupt execution.
+#[derive(Default)]
+pub(crate) struct Observers {
+    observers: RwLock<Vec<Arc<dyn OrchestratorObserver>>>,
+}
+
+impl Observers {
+    pub(crate) fn subscribe(&self, observer: Arc<dyn OrchestratorObserver>) {
+        match self.observers.write() {
+            Ok(mut observers) => observers.push(observer),
+            Err(_) => warn!("Observer list poisoned; subscription dropped"),
+        }
+    }
+
+    pub(crate) fn notify(&self, f: impl Fn(&dyn OrchestratorObserver)) {
+        let Ok(observers) = self.observers.read() else {
+            warn!("Observer list poisoned; notification dropped");
+            return;
+        };
+        for observer in observers.iter() {
+            if catch_unwind(AssertUnwindSafe(|| f(observer.as_ref()))).is_err() {
+                warn!("Orchestrator observer panicked; notification dropped");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_change(observer: &dyn OrchestratorObserver, step_index: usize) {
+        observer.on_step_status_change(
+            &"conversation".to_string(),
+            step_index,
+            &StepStatus::Pending,
+            &StepStatus::Running,
+        );
+    }
+
+    #[test]
+    fn a_full_channel_drops_notifications_without_blocking() {
+        let (observer, mut receiver) = ChannelObserver::channel(1);
+
+        status_change(&observer, 0);
+        status_change(&observer, 1);
+        observer.on_command_output(&"conversation".to_string(), 1, OutputStream::Stdout, "ok");
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(OrchestratorNotification::StepStatusChanged { step_index: 0, .. })
+        ));
+        assert!(receiver.try_recv().is_err());
+        // Room again once the receiver caught up
+        status_change(&observer, 2);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(OrchestratorNotification::StepStatusChanged { step_index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn a_closed_channel_drops_notifications_without_panicking() {
+        let (observer, receiver) = ChannelObserver::channel(4);
+        drop(receiver);
+        let observers = Observers::default();
+        observers.subscribe(Arc::new(observer));
+
+        observers.notify(|observer| status_change(observer, 0));
+        observers.notify(|observer| {
+            observer.on_command_output(
+                &"conversation".to_string(),
+                0,
+                OutputStream::Stderr,
+                "failed",
+            )
+        });
+    }
+
+    #[test]
+    fn a_panicking_observer_does_not_stop_the_others() {
+        struct Panicking;
+        impl OrchestratorObserver for Panicking {
+            fn on_step_status_change(
+                &self,
+                _: &ConversationId,
+                _: usize,
+                _: &StepStatus,
+                _: &StepStatus,
+            ) {
+                panic!("observer bug");
+            }
+        }
+        let (observer, mut receiver) = ChannelObserver::channel(4);
+        let observers = Observers::default();
+        observers.subscribe(Arc::new(Panicking));
+        observers.subscribe(Arc::new(observer));
+
+        observers.notify(|observer| status_change(observer, 3));
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(OrchestratorNotification::StepStatusChanged { step_index: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+//! Changes the user makes to a plan before its steps run: editing, inserting, removing
+//! and reordering steps, and replanning with feedback. Each is recorded as a plan edit.
+
+use crate::{OrchestratorError, PromptOrchestrator};
+use parsec_core::*;
+use uuid::Uuid;
+
+impl PromptOrchestrator {
+    /// Change a pending step's description.
+    pub fn edit_step(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        description: String,
+    ) -> Result<(), OrchestratorError> {
+        Self::check_editable(conversation, step_index)?;
+
+        let step = &mut conversation.steps[step_index].step;
+        let previous = std::mem::replace(&mut step.description, description.clone());
+        self.record_plan_edit(
+            conversation,
+            "plan_step_edited",
+            serde_json::json!({
+                "step_index": step_index,
+                "from": previous,
+                "to": description,
+            }),
+        )
+    }
+
+    /// Insert a new pending step so it ends up at `step_index`.
+    pub fn insert_step(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        description: String,
+    ) -> Result<(), OrchestratorError> {
+        if step_index > conversation.steps.len() {
+            return Err(OrchestratorError::InvalidStepIndex(step_index));
+        }
+        // Nothing may be inserted before work that already happened
+        if conversation.steps[step_index..]
+            .iter()
+            .any(|step| step.status != StepStatus::Pending)
+        {
+            return Err(OrchestratorError::InvalidPlanEdit(
+                "Cannot insert before a step that has already started".to_string(),
+            ));
+        }
+
+        let step = WorkflowStep {
+            id: Uuid::new_v4().to_string(),
+            description: description.clone(),
+            depends_on: None,
+            estimated_minutes: None,
+            estimate_source: EstimateSource::Planner,
+        };
+        conversation
+            .steps
+            .insert(step_index, Self::new_step_state(&step, session));
+        self.record_plan_edit(
+            conversation,
+            "plan_step_inserted",
+            serde_json::json!({
+                "step_index": step_index,
+                "description": description,
+            }),
+        )
+    }
+
+    /// Remove a pending step; at least one step must remain.
+    pub fn remove_step(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+    ) -> Result<(), OrchestratorError> {
+        Self::check_editable(conversation, step_index)?;
+        if conversation.steps.len() == 1 {
+            return Err(OrchestratorError::InvalidPlanEdit(
+                "A plan needs at least one step".to_string(),
+            ));
+        }
+
+        let removed = conversation.steps.remove(step_index);
+        for step in &mut conversation.steps {
+            if let Some(ids) = &mut step.step.depends_on {
+                ids.retain(|id| *id != removed.step.id);
+            }
+        }
+        self.record_plan_edit(
+            conversation,
+            "plan_step_removed",
+            serde_json::json!({
+                "step_index": step_index,
+                "description": removed.step.description,
+            }),
+        )
+    }
+
+    /// Reorder steps; `order` lists every current step index once, in the new order.
+    pub fn reorder_steps(
+        &self,
+        conversation: &mut ConversationContext,
+        order: &[usize],
+    ) -> Result<(), OrchestratorError> {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        if sorted != (0..conversation.steps.len()).collect::<Vec<_>>() {
+            return Err(OrchestratorError::InvalidPlanEdit(format!(
+                "Order must list each of the {} steps exactly once",
+                conversation.steps.len()
+            )));
+        }
+        for (position, &index) in order.iter().enumerate() {
+            if position != index {
+                Self::check_editable(conversation, index)?;
+            }
+        }
+
+        let reordered: Vec<&WorkflowStepState> = order
+            .iter()
+            .map(|&index| &conversation.steps[index])
+            .collect();
+        // Steps without dependencies need those now before them
+        dependencies::check(&reordered).map_err(OrchestratorError::InvalidPlanEdit)?;
+
+        let mut steps: Vec<Option<WorkflowStepState>> =
+            conversation.steps.drain(..).map(Some).collect();
+        conversation.steps = order
+            .iter()
+            .filter_map(|&index| steps[index].take())
+            .collect();
+        self.record_plan_edit(
+            conversation,
+            "plan_steps_reordered",
+            serde_json::json!({ "order": order }),
+        )
+    }
+
+    /// Replace an unstarted plan with a new one that takes `feedback` into account.
+    pub async fn replan_with_feedback(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        feedback: &str,
+    ) -> Result<(), OrchestratorError> {
+        if conversation
+            .steps
+            .iter()
+            .any(|step| step.status != StepStatus::Pending)
+        {
+            return Err(OrchestratorError::InvalidPlanEdit(
+                "Cannot regenerate a plan that has already started".to_string(),
+            ));
+        }
+
+        let previous_plan: Vec<String> = conversation
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| format!("{}. {}", i + 1, step.step.description))
+            .collect();
+        let prompt = format!(
+            "{}\n\nA previous plan was:\n{}\n\nRevise it according to this feedback: {}",
+            conversation.user_prompt,
+            previous_plan.join("\n"),
+            feedback
+        );
+
+        let workflow = self
+            .plan(
+                conversation,
+                session,
+                &prompt,
+                &CancellationToken::new(),
+                None,
+            )
+            .await?;
+
+        conversation.steps = workflow
+            .steps
+            .iter()
+            .map(|step| Self::new_step_state(step, session))
+            .collect();
+        self.record_plan_edit(
+            conversation,
+            "plan_regenerated",
+            serde_json::json!({
+                "feedback": feedback,
+                "step_count": conversation.steps.len(),
+            }),
+        )
+    }
+
+    fn check_editable(
+        conversation: &ConversationContext,
+        step_index: usize,
+    ) -> Result<(), OrchestratorError> {
+        match conversation.steps.get(step_index) {
+            None => Err(OrchestratorError::InvalidStepIndex(step_index)),
+            Some(step) if step.status != StepStatus::Pending => {
+                Err(OrchestratorError::StepAlreadyStarted(step_index))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Mirror `conversation.steps` into the workflow plan, mark the plan edited and record it.
+    fn record_plan_edit(
+        &self,
+        conversation: &mut ConversationContext,
+        event_type: &str,
+        data: serde_json::Value,
+    ) -> Result<(), OrchestratorError> {
+        conversation.workflow = Some(WorkflowPlan {
+            steps: conversation
+                .steps
+                .iter()
+                .map(|state| state.step.clone())
+                .collect(),
+        });
+        conversation.plan_edited = true;
+        self.record_event(conversation, event_type, data);
+        self.save(conversation)?;
+        Ok(())
+    }
+}
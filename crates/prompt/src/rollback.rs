@@ -0,0 +1,217 @@
+//! Undoing what a conversation changed: rollback commands suggested after a failure, and
+//! the recorded undo of the most recent step. Nothing runs without approval.
+
+use crate::{OrchestratorError, PromptOrchestrator};
+use parsec_core::command_rules::Verdict;
+use parsec_core::*;
+use tracing::warn;
+
+impl PromptOrchestrator {
+    /// Ask the step generator how to undo every step that changed something, most recent
+    /// first, and record what was offered. Nothing is run: each command needs the user's
+    /// approval through `run_rollback` or is recorded with `decline_rollback`. If the model
+    /// cannot help, every such step is listed for manual cleanup instead.
+    pub async fn suggest_rollback(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+    ) -> Result<Vec<RollbackSuggestion>, OrchestratorError> {
+        if !conversation
+            .steps
+            .iter()
+            .any(WorkflowStepState::needs_rollback)
+        {
+            return Ok(Vec::new());
+        }
+
+        let result = self
+            .model_provider
+            .step_generator()
+            .suggest_rollback(conversation, session)
+            .await;
+
+        let base = conversation.clone();
+        self.record_exchange(conversation, session, "rollback", None);
+        let suggestions = match result {
+            Ok(suggestions) => {
+                self.record_model_call(conversation, "rollback", None);
+                suggestions
+            }
+            Err(e) => {
+                warn!("Could not generate rollback commands: {}", e);
+                conversation
+                    .steps
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .filter(|(_, state)| state.needs_rollback())
+                    .map(|(index, state)| {
+                        RollbackSuggestion::manual_cleanup(
+                            index,
+                            state,
+                            format!("Rollback suggestions unavailable: {}", e),
+                        )
+                    })
+                    .collect()
+            }
+        };
+
+        self.record_event(
+            conversation,
+            "rollback_offered",
+            serde_json::json!({ "suggestions": suggestions }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(suggestions)
+    }
+
+    /// Run a rollback command the user approved.
+    pub fn run_rollback(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> Result<CommandAttempt, OrchestratorError> {
+        self.run_approved_undo(
+            conversation,
+            session,
+            step_index,
+            command,
+            "rollback_accepted",
+        )
+    }
+
+    pub fn decline_rollback(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> Result<(), OrchestratorError> {
+        self.record_declined_undo(conversation, step_index, command, "rollback_declined")
+    }
+
+    /// How to undo the step that most recently ran commands and was not undone since:
+    /// the undo commands generated with them, unless validation blocks them. `None`
+    /// when no step is left to undo.
+    pub fn undo_last_step(
+        &self,
+        conversation: &ConversationContext,
+        session: &Session,
+    ) -> Option<RollbackSuggestion> {
+        let undone: Vec<u64> = conversation
+            .history
+            .iter()
+            .filter(|event| event.event_type == "undo_accepted" && event.data["success"] == true)
+            .filter_map(|event| event.data["step_index"].as_u64())
+            .collect();
+        let (step_index, state) = conversation
+            .steps
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !undone.contains(&(*index as u64)))
+            .filter_map(|(index, state)| {
+                let last_run = state
+                    .command_attempts
+                    .iter()
+                    .filter(|attempt| attempt.executed)
+                    .map(|attempt| attempt.timestamp)
+                    .max()?;
+                Some((last_run, index, state))
+            })
+            .max_by_key(|(last_run, _, _)| *last_run)
+            .map(|(_, index, state)| (index, state))?;
+
+        let Some(undo) = state.recorded_undo() else {
+            return Some(RollbackSuggestion::manual_cleanup(
+                step_index,
+                state,
+                "No undo command was recorded with the step's commands",
+            ));
+        };
+        if let Verdict::Block(findings) = self.validate(conversation, session, &undo).verdict {
+            return Some(RollbackSuggestion::manual_cleanup(
+                step_index,
+                state,
+                format!(
+                    "The recorded undo `{}` is blocked: {}",
+                    undo.command,
+                    command_rules::describe(&findings)
+                ),
+            ));
+        }
+        Some(RollbackSuggestion {
+            action: RollbackAction::Command(undo),
+            ..RollbackSuggestion::manual_cleanup(step_index, state, "")
+        })
+    }
+
+    /// Run the recorded undo of a step the user approved.
+    pub fn run_undo(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> Result<CommandAttempt, OrchestratorError> {
+        self.run_approved_undo(conversation, session, step_index, command, "undo_accepted")
+    }
+
+    pub fn decline_undo(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> Result<(), OrchestratorError> {
+        self.record_declined_undo(conversation, step_index, command, "undo_declined")
+    }
+
+    fn run_approved_undo(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        command: &GeneratedCommand,
+        event_type: &str,
+    ) -> Result<CommandAttempt, OrchestratorError> {
+        let attempt = self.run_command(
+            conversation,
+            session,
+            command,
+            &ExecutionOverrides::default(),
+        )?;
+
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            event_type,
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+                "exit_status": attempt.exit_status,
+                "success": attempt.error.is_none(),
+            }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(attempt)
+    }
+
+    fn record_declined_undo(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        event_type: &str,
+    ) -> Result<(), OrchestratorError> {
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            event_type,
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+            }),
+        );
+        self.save_appended(conversation, base)
+    }
+}
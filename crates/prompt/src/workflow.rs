@@ -0,0 +1,1052 @@
+use crate::approval::{ApprovalDecision, ApprovalPolicy, Confirmation, WorkflowProgress};
+use crate::{OrchestratorError, PromptOrchestrator, StepDecision, StepExecution, StepHandle};
+use chrono::Utc;
+use parsec_core::command_rules::Verdict;
+use parsec_core::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Limits on a `PromptOrchestrator::run_workflow` call. The default runs every pending step.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Return once this many steps have finished, whether completed, failed or skipped.
+    pub max_steps: Option<usize>,
+    /// Return once a step fails instead of moving on to the next one.
+    pub stop_on_failure: bool,
+    /// Run even when preflight checks find errors.
+    pub force: bool,
+    /// Used instead of the session's `rate_limits` for this run.
+    pub rate_limits: Option<RateLimits>,
+    /// Cancelled to stop the run before its next command: a model call or cooldown in
+    /// flight is abandoned and the run returns `Stopped`. Running commands are not stopped.
+    pub cancel: CancellationToken,
+}
+
+/// Why `run_workflow` held back commands, recorded as a `rate_limit` event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkflowPause {
+    /// Waiting out `RateLimits::min_command_interval` before the next command.
+    Cooldown { wait: Duration },
+    /// The conversation executed `RateLimits::max_commands_per_conversation` commands;
+    /// the run stops.
+    CommandCap { limit: usize },
+    /// This many commands failed in a row within `RateLimits::failure_window`; the run
+    /// stops unless the policy resumes it.
+    CircuitBreaker { failures: usize },
+}
+
+impl WorkflowPause {
+    /// Short name for the event, such as `circuit_breaker`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkflowPause::Cooldown { .. } => "cooldown",
+            WorkflowPause::CommandCap { .. } => "command_cap",
+            WorkflowPause::CircuitBreaker { .. } => "circuit_breaker",
+        }
+    }
+}
+
+impl std::fmt::Display for WorkflowPause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkflowPause::Cooldown { wait } => {
+                write!(
+                    f,
+                    "cooldown: waiting {:.1}s before the next command",
+                    wait.as_secs_f32()
+                )
+            }
+            WorkflowPause::CommandCap { limit } => {
+                write!(f, "command cap: {} commands executed, stopping", limit)
+            }
+            WorkflowPause::CircuitBreaker { failures } => {
+                write!(
+                    f,
+                    "circuit breaker: {} consecutive failures, pausing",
+                    failures
+                )
+            }
+        }
+    }
+}
+
+/// Applies `RateLimits` over one `run_workflow` call.
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    last_command: Option<Instant>,
+    /// When each command of the current run of failures ended.
+    failures: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            last_command: None,
+            failures: VecDeque::new(),
+        }
+    }
+
+    /// Commands the conversation may still execute, having executed `executed`.
+    pub(crate) fn remaining(&self, executed: usize) -> Option<usize> {
+        self.limits
+            .max_commands_per_conversation
+            .map(|limit| limit.saturating_sub(executed))
+    }
+
+    pub(crate) fn cap(&self) -> Option<usize> {
+        self.limits.max_commands_per_conversation
+    }
+
+    /// How long to wait before the next command may start.
+    pub(crate) fn cooldown(&self) -> Option<Duration> {
+        let since = self.last_command?.elapsed();
+        Some(self.limits.min_command_interval.saturating_sub(since)).filter(|wait| !wait.is_zero())
+    }
+
+    pub(crate) fn command_started(&mut self) {
+        self.last_command = Some(Instant::now());
+    }
+
+    pub(crate) fn command_finished(&mut self, succeeded: bool) {
+        if succeeded {
+            self.failures.clear();
+        } else {
+            self.failures.push_back(Instant::now());
+        }
+    }
+
+    /// Consecutive recent failures, once there are enough to trip the circuit breaker.
+    pub(crate) fn tripped(&mut self) -> Option<usize> {
+        let window = self.limits.failure_window;
+        while self
+            .failures
+            .front()
+            .is_some_and(|failed| failed.elapsed() > window)
+        {
+            self.failures.pop_front();
+        }
+        let failures = self.failures.len();
+        (self.limits.breaker_failures > 0 && failures >= self.limits.breaker_failures)
+            .then_some(failures)
+    }
+
+    pub(crate) fn reset_breaker(&mut self) {
+        self.failures.clear();
+    }
+}
+
+/// The conversation as a run found it, to tell what the run did.
+pub(crate) struct RunStart {
+    started: Instant,
+    /// Status and artifact count of each step, by id.
+    steps: HashMap<StepId, (StepStatus, usize)>,
+    commands: usize,
+    environment_changes: usize,
+    tokens: u64,
+}
+
+impl RunStart {
+    /// `tokens` is `ModelProvider::tokens_used` as the run starts.
+    pub(crate) fn new(conversation: &ConversationContext, tokens: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            steps: conversation
+                .steps
+                .iter()
+                .map(|step| {
+                    (
+                        step.step.id.clone(),
+                        (step.status.clone(), step.artifacts_produced.len()),
+                    )
+                })
+                .collect(),
+            commands: executed_commands(conversation),
+            environment_changes: conversation.context_summary.environment_changes.len(),
+            tokens,
+        }
+    }
+
+    /// What the run did so far, with `tokens` the provider's count by now.
+    pub(crate) fn outcome(
+        &self,
+        conversation: &ConversationContext,
+        status: WorkflowStatus,
+        tokens: u64,
+    ) -> WorkflowOutcome {
+        let mut outcome = WorkflowOutcome {
+            status,
+            steps_completed: 0,
+            steps_failed: 0,
+            steps_skipped: 0,
+            steps: Vec::new(),
+            commands_executed: executed_commands(conversation).saturating_sub(self.commands),
+            duration: self.started.elapsed(),
+            artifacts: Vec::new(),
+            environment_changes: conversation
+                .context_summary
+                .environment_changes
+                .iter()
+                .skip(self.environment_changes)
+                .cloned()
+                .collect(),
+            tokens_used: tokens.saturating_sub(self.tokens),
+            follow_up: Vec::new(),
+            finished_at: Utc::now(),
+        };
+        let mut pending = 0;
+        for (index, step) in conversation.steps.iter().enumerate() {
+            // Steps inserted during the run started out pending, with nothing produced
+            let (before, artifacts_before) = self
+                .steps
+                .get(&step.step.id)
+                .cloned()
+                .unwrap_or((StepStatus::Pending, 0));
+            outcome.artifacts.extend(
+                step.artifacts_produced
+                    .iter()
+                    .skip(artifacts_before)
+                    .cloned(),
+            );
+            let this_run = step.status != before;
+            outcome.steps.push(StepOutcome {
+                step_index: index,
+                description: step.step.description.clone(),
+                status: step.status.clone(),
+                this_run,
+            });
+            let number = index + 1;
+            match step.status {
+                StepStatus::Pending | StepStatus::CommandSuggested => pending += 1,
+                StepStatus::Running => outcome.follow_up.push(format!(
+                    "Step {} is still running in the background",
+                    number
+                )),
+                StepStatus::NeedsAttention => outcome.follow_up.push(format!(
+                    "Step {} needs attention: every command generated for it is above the block threshold",
+                    number
+                )),
+                _ => {}
+            }
+            if !this_run {
+                continue;
+            }
+            match step.status {
+                StepStatus::Complete => outcome.steps_completed += 1,
+                StepStatus::Failed => {
+                    outcome.steps_failed += 1;
+                    outcome
+                        .follow_up
+                        .push(format!("Step {} failed: {}", number, step.step.description));
+                }
+                StepStatus::Skipped => {
+                    outcome.steps_skipped += 1;
+                    outcome.follow_up.push(format!(
+                        "Step {} was skipped: {}",
+                        number, step.step.description
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if pending > 0 {
+            outcome.follow_up.push(match status {
+                WorkflowStatus::Aborted => format!("{} step(s) were not run", pending),
+                _ => format!(
+                    "{} step(s) still pending; run the workflow again to go on",
+                    pending
+                ),
+            });
+        }
+        outcome
+    }
+}
+
+/// Commands the conversation has executed, in every step.
+pub(crate) fn executed_commands(conversation: &ConversationContext) -> usize {
+    conversation
+        .steps
+        .iter()
+        .flat_map(|step| &step.command_attempts)
+        .filter(|attempt| attempt.executed)
+        .count()
+}
+
+impl PromptOrchestrator {
+    /// Run the pending steps as their dependencies allow: generate commands for each, let
+    /// `policy` decide on the first one and act on the decision, retrying and escalating
+    /// failed steps as it directs. A failed command leaves its step pending, so the next
+    /// commands are generated with its output in the prompt, and steps that depend on a
+    /// failed or skipped step are skipped. With `with_parallel_steps`, up to that many
+    /// ready steps are decided on one after another and their commands run at once.
+    /// Returns once no step is pending, the conversation is aborted or `opts` ends the
+    /// run. Fails without running anything when preflight checks find errors, unless
+    /// `opts` forces the run. The session's `rate_limits`, or those of `opts`, space out
+    /// commands, cap how many the conversation runs and pause the run after a streak of
+    /// failures until `policy` resumes it.
+    pub async fn run_workflow(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &mut Session,
+        policy: &dyn ApprovalPolicy,
+        opts: RunOptions,
+    ) -> Result<WorkflowOutcome, OrchestratorError> {
+        let errors = preflight::errors(&self.preflight(conversation, session));
+        if !errors.is_empty() && !opts.force {
+            self.save(conversation)?;
+            return Err(OrchestratorError::Preflight(errors));
+        }
+        // Their commands are generated again, and may now be allowed
+        for step_index in 0..conversation.steps.len() {
+            if conversation.steps[step_index].status == StepStatus::NeedsAttention {
+                self.set_step_status(conversation, step_index, StepStatus::Pending)?;
+            }
+        }
+        let start = RunStart::new(conversation, self.model_provider.tokens_used());
+        let mut limiter =
+            RateLimiter::new(opts.rate_limits.unwrap_or(session.settings.rate_limits));
+
+        loop {
+            self.skip_blocked_steps(conversation)?;
+            let ready = conversation.ready_steps();
+            if ready.is_empty() {
+                break;
+            }
+
+            let so_far = start.outcome(
+                conversation,
+                WorkflowStatus::Stopped,
+                self.model_provider.tokens_used(),
+            );
+            if opts.stop_on_failure && so_far.steps_failed > 0
+                || opts
+                    .max_steps
+                    .is_some_and(|max| so_far.steps_finished() >= max)
+            {
+                self.update_session_context(session, conversation)?;
+                return self.finish_run(conversation, so_far);
+            }
+            let mut batch_size = opts.max_steps.map_or(self.parallel_steps, |max| {
+                self.parallel_steps.min(max - so_far.steps_finished())
+            });
+            if let Some(remaining) = limiter.remaining(executed_commands(conversation)) {
+                if remaining == 0 {
+                    let limit = limiter.cap().unwrap_or_default();
+                    self.rate_limited(conversation, policy, WorkflowPause::CommandCap { limit });
+                    self.update_session_context(session, conversation)?;
+                    return self.end_run(conversation, &start, WorkflowStatus::Stopped);
+                }
+                batch_size = batch_size.min(remaining);
+            }
+
+            let handles = ready
+                .into_iter()
+                .take(batch_size)
+                .map(|step_index| StepHandle::new(conversation, step_index))
+                .collect::<Result<Vec<_>, _>>()?;
+            let aborted = match self
+                .run_steps(
+                    conversation,
+                    session,
+                    policy,
+                    handles,
+                    &mut limiter,
+                    &opts.cancel,
+                )
+                .await
+            {
+                Err(OrchestratorError::CommandGen(CommandGenError::Cancelled)) => false,
+                result => result?,
+            };
+            if aborted {
+                self.update_session_context(session, conversation)?;
+                return self.end_run(conversation, &start, WorkflowStatus::Aborted);
+            }
+            self.update_session_context(session, conversation)?;
+            if opts.cancel.is_cancelled() {
+                return self.end_run(conversation, &start, WorkflowStatus::Stopped);
+            }
+
+            if let Some(failures) = limiter.tripped() {
+                let pause = WorkflowPause::CircuitBreaker { failures };
+                self.rate_limited(conversation, policy, pause.clone());
+                if !policy.resume(conversation, &pause).await {
+                    return self.end_run(conversation, &start, WorkflowStatus::Stopped);
+                }
+                limiter.reset_breaker();
+                self.record_event(
+                    conversation,
+                    "rate_limit_resumed",
+                    serde_json::json!({ "kind": pause.kind() }),
+                );
+            }
+        }
+
+        // The last step may have completed without running anything
+        self.update_session_context(session, conversation)?;
+        // Steps may still wait on one running in the background or needing attention
+        let status = if conversation.steps.iter().any(|step| {
+            matches!(
+                step.status,
+                StepStatus::Pending | StepStatus::NeedsAttention
+            )
+        }) {
+            WorkflowStatus::Stopped
+        } else {
+            WorkflowStatus::Completed
+        };
+        self.end_run(conversation, &start, status)
+    }
+
+    fn end_run(
+        &self,
+        conversation: &mut ConversationContext,
+        start: &RunStart,
+        status: WorkflowStatus,
+    ) -> Result<WorkflowOutcome, OrchestratorError> {
+        let outcome = start.outcome(conversation, status, self.model_provider.tokens_used());
+        self.finish_run(conversation, outcome)
+    }
+
+    /// Keep `outcome` on the conversation, for listings and `show`, and return it.
+    fn finish_run(
+        &self,
+        conversation: &mut ConversationContext,
+        outcome: WorkflowOutcome,
+    ) -> Result<WorkflowOutcome, OrchestratorError> {
+        conversation.outcome = Some(outcome.clone());
+        self.save(conversation)?;
+        Ok(outcome)
+    }
+
+    /// Record that rate limits held back the next command and tell `policy`.
+    fn rate_limited(
+        &self,
+        conversation: &mut ConversationContext,
+        policy: &dyn ApprovalPolicy,
+        pause: WorkflowPause,
+    ) {
+        warn!("Conversation {}: {}", conversation.id, pause);
+        let mut data = serde_json::json!({ "kind": pause.kind(), "message": pause.to_string() });
+        match pause {
+            WorkflowPause::Cooldown { wait } => data["wait_ms"] = (wait.as_millis() as u64).into(),
+            WorkflowPause::CommandCap { limit } => data["limit"] = limit.into(),
+            WorkflowPause::CircuitBreaker { failures } => data["failures"] = failures.into(),
+        }
+        self.record_event(conversation, "rate_limit", data);
+        policy.on_progress(conversation, WorkflowProgress::Paused(&pause));
+    }
+
+    /// Skip pending steps that depend on a failed or skipped step, and so can never run.
+    fn skip_blocked_steps(
+        &self,
+        conversation: &mut ConversationContext,
+    ) -> Result<(), OrchestratorError> {
+        // Skipping a step can block the steps depending on it in turn
+        while let Some((step_index, dependency)) = (0..conversation.steps.len())
+            .filter(|&index| conversation.steps[index].status == StepStatus::Pending)
+            .find_map(|index| Some((index, conversation.unmet_dependency(index)?)))
+        {
+            self.record_event(
+                conversation,
+                "step_blocked",
+                serde_json::json!({
+                    "step_index": step_index,
+                    "dependency_index": dependency,
+                    "dependency_status": conversation.steps[dependency].status,
+                }),
+            );
+            self.skip_step(conversation, step_index)?;
+        }
+        Ok(())
+    }
+
+    /// Generate commands for `steps` and get `policy`'s decision on each, one step after
+    /// another, then run the approved commands: at once if there are several, in which
+    /// case their artifacts are not tracked. Returns whether the conversation was aborted.
+    async fn run_steps(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        policy: &dyn ApprovalPolicy,
+        steps: Vec<StepHandle>,
+        limiter: &mut RateLimiter,
+        cancel: &CancellationToken,
+    ) -> Result<bool, OrchestratorError> {
+        let mut approved: Vec<(StepHandle, GeneratedCommand, ExecutionOverrides)> = Vec::new();
+        // Steps to complete once their command has run, however it went
+        let mut finishing: Vec<StepHandle> = Vec::new();
+        for handle in steps {
+            match self
+                .decide_step(conversation, session, policy, &handle, cancel)
+                .await?
+            {
+                StepDecision::Run(command, overrides) => {
+                    approved.push((handle, command, overrides))
+                }
+                StepDecision::Finish(command, overrides) => {
+                    finishing.push(handle.clone());
+                    approved.push((handle, command, overrides))
+                }
+                StepDecision::Settled => {}
+                StepDecision::Aborted => return Ok(true),
+            }
+        }
+
+        if approved.is_empty() {
+            return Ok(false);
+        }
+        if let Some(wait) = limiter.cooldown() {
+            self.rate_limited(conversation, policy, WorkflowPause::Cooldown { wait });
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                // The approved commands stay pending for the next run
+                _ = cancel.cancelled() => return Ok(false),
+            }
+        }
+        limiter.command_started();
+        let executions: Vec<Result<StepExecution, OrchestratorError>> = if approved.len() == 1 {
+            let (handle, command, overrides) = &approved[0];
+            vec![self.execute_step_command(conversation, session, handle, command, overrides)]
+        } else {
+            std::thread::scope(|scope| {
+                let running: Vec<_> = approved
+                    .iter()
+                    .map(|(handle, command, overrides)| {
+                        Self::check_runnable(conversation, session, handle).map(|step_index| {
+                            // Validated up front: the threads cannot borrow the conversation
+                            let blocking =
+                                self.blocking_findings(conversation, session, command, overrides);
+                            let thread = scope.spawn(move || {
+                                self.run_unless_blocked(session, command, overrides, blocking)
+                            });
+                            (step_index, thread)
+                        })
+                    })
+                    .collect();
+                running
+                    .into_iter()
+                    .map(|running| {
+                        let (step_index, thread) = running?;
+                        let attempt = thread.join().unwrap_or_else(|_| {
+                            Err(ExecutionError::ExecutionFailed(
+                                "Command thread panicked".to_string(),
+                            )
+                            .into())
+                        })?;
+                        self.record_attempt(conversation, session, step_index, attempt, Vec::new())
+                    })
+                    .collect()
+            })
+        };
+
+        for execution in &executions {
+            limiter.command_finished(execution.as_ref().is_ok_and(|execution| {
+                execution.attempt().exit_status == Some(0) && execution.attempt().error.is_none()
+            }));
+        }
+        for ((handle, command, _), execution) in approved.into_iter().zip(executions) {
+            if finishing.contains(&handle) {
+                self.finish_done_step(conversation, policy, &handle, execution)?;
+            } else if self
+                .finish_step_execution(conversation, session, policy, &handle, &command, execution)
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Generate commands for `step` and have `policy` decide on the first, acting on every
+    /// decision but running the command.
+    async fn decide_step(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        policy: &dyn ApprovalPolicy,
+        handle: &StepHandle,
+        cancel: &CancellationToken,
+    ) -> Result<StepDecision, OrchestratorError> {
+        let step_index = handle.index_in(conversation)?;
+        policy.on_progress(conversation, WorkflowProgress::StepStarted { step_index });
+        let mut generated = self
+            .generate_commands(conversation, session, handle, cancel)
+            .await?;
+
+        loop {
+            let step_index = handle.index_in(conversation)?;
+            let blocked = self.drop_blocked_commands(conversation, step_index, &mut generated)?;
+            policy.on_progress(
+                conversation,
+                WorkflowProgress::Commands {
+                    step_index,
+                    commands: &generated,
+                },
+            );
+
+            if generated.commands.is_empty() {
+                if generated.done {
+                    self.complete_step(conversation, step_index)?;
+                } else if let Some(command) = blocked {
+                    self.set_step_status(conversation, step_index, StepStatus::NeedsAttention)?;
+                    policy.on_progress(
+                        conversation,
+                        WorkflowProgress::NeedsAttention {
+                            step_index,
+                            command: &command,
+                            threshold: self.risk_tiers.block_threshold(),
+                        },
+                    );
+                } else {
+                    warn!("No commands generated for step {}", step_index + 1);
+                    self.set_step_status(conversation, step_index, StepStatus::Failed)?;
+                }
+                return Ok(StepDecision::Settled);
+            }
+            // The step is done either way; its command is only offered
+            let finishing = generated.done;
+
+            let primary = &generated.commands[0];
+            let decision = policy.decide(conversation, step_index, primary).await;
+            let command = match &decision {
+                ApprovalDecision::Edit(edited) => GeneratedCommand {
+                    kind: CommandKind::of(edited),
+                    command: edited.clone(),
+                    explanation: format!("Edited from `{}`", primary.command),
+                    risk_score: None,
+                    expected_duration: primary.expected_duration,
+                    missing_tools: Vec::new(),
+                    stdin: primary.stdin.clone(),
+                    interactive: primary.interactive,
+                    // The undo proposed with the command may not reverse the edit
+                    undo_command: None,
+                },
+                _ => primary.clone(),
+            };
+            let mut overrides = ExecutionOverrides::default();
+            let decision = match decision {
+                ApprovalDecision::Approve
+                | ApprovalDecision::Edit(_)
+                | ApprovalDecision::Background
+                | ApprovalDecision::Interactive => {
+                    overrides.override_warnings = self
+                        .confirm_override(conversation, session, step_index, &command, policy)
+                        .await?;
+                    overrides.unsandboxed = self
+                        .confirm_unsandboxed(conversation, step_index, &command, policy)
+                        .await?;
+                    overrides.attach_terminal = match decision {
+                        // Choosing to run it interactively is the approval
+                        ApprovalDecision::Interactive => true,
+                        ApprovalDecision::Background => false,
+                        _ => {
+                            self.confirm_terminal(conversation, step_index, &command, policy)
+                                .await?
+                        }
+                    };
+                    match self
+                        .confirm_command(conversation, step_index, &command, policy)
+                        .await?
+                    {
+                        Some(reason) => ApprovalDecision::Deny { reason },
+                        None => decision,
+                    }
+                }
+                decision => decision,
+            };
+
+            match decision {
+                ApprovalDecision::Approve
+                | ApprovalDecision::Edit(_)
+                | ApprovalDecision::Interactive => {
+                    return Ok(if finishing {
+                        StepDecision::Finish(command, overrides)
+                    } else {
+                        StepDecision::Run(command, overrides)
+                    });
+                }
+                ApprovalDecision::Background => {
+                    match self.start_background_command(
+                        conversation,
+                        session,
+                        handle,
+                        &command,
+                        &overrides,
+                    ) {
+                        Ok(job) => policy.on_background(conversation, step_index, &command, job),
+                        Err(e) => policy.on_progress(
+                            conversation,
+                            WorkflowProgress::ExecutionFailed {
+                                step_index,
+                                error: &e,
+                            },
+                        ),
+                    }
+                    if finishing {
+                        self.complete_step(conversation, handle.index_in(conversation)?)?;
+                    }
+                }
+                ApprovalDecision::Deny { reason } => {
+                    self.record_event(
+                        conversation,
+                        "command_denied",
+                        serde_json::json!({
+                            "step_index": step_index,
+                            "command": command.command,
+                            "reason": reason,
+                        }),
+                    );
+                    if finishing {
+                        self.complete_step(conversation, step_index)?;
+                    } else {
+                        self.skip_step(conversation, step_index)?;
+                    }
+                }
+                ApprovalDecision::Skip if finishing => {
+                    self.complete_step(conversation, step_index)?;
+                }
+                ApprovalDecision::Skip => self.skip_step(conversation, step_index)?,
+                ApprovalDecision::Abort => {
+                    self.abort_conversation(conversation)?;
+                    return Ok(StepDecision::Aborted);
+                }
+                ApprovalDecision::Hint(hint) => {
+                    let hint = hint.trim();
+                    if !hint.is_empty() {
+                        generated = self
+                            .regenerate_with_hint(conversation, session, handle, &command, hint)
+                            .await?;
+                    }
+                    continue;
+                }
+            }
+            return Ok(StepDecision::Settled);
+        }
+    }
+
+    /// Report how running `command` for `handle` went and follow up on it: rerun it with a
+    /// longer timeout if it timed out and `policy` wants that, and escalate the step if it
+    /// used up its attempts. Returns whether the conversation was aborted.
+    async fn finish_step_execution(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        policy: &dyn ApprovalPolicy,
+        handle: &StepHandle,
+        command: &GeneratedCommand,
+        mut execution: Result<StepExecution, OrchestratorError>,
+    ) -> Result<bool, OrchestratorError> {
+        loop {
+            let step_index = handle.index_in(conversation)?;
+            let done = match execution {
+                Ok(done) => done,
+                Err(e) => {
+                    warn!("Failed to execute command: {}", e);
+                    policy.on_progress(
+                        conversation,
+                        WorkflowProgress::ExecutionFailed {
+                            step_index,
+                            error: &e,
+                        },
+                    );
+                    return Ok(false);
+                }
+            };
+            policy.on_progress(
+                conversation,
+                WorkflowProgress::Executed {
+                    step_index,
+                    execution: &done,
+                },
+            );
+            match &done {
+                StepExecution::Attempted(
+                    attempt @ CommandAttempt {
+                        error: Some(ExecutionError::Timeout(_)),
+                        ..
+                    },
+                ) => {
+                    if let Some(timeout) = policy
+                        .retry_timeout(conversation, step_index, attempt)
+                        .await
+                    {
+                        // Already approved and confirmed before it timed out
+                        let overrides = ExecutionOverrides {
+                            timeout: Some(timeout),
+                            ..ExecutionOverrides::default()
+                        };
+                        execution = self.execute_step_command(
+                            conversation,
+                            session,
+                            handle,
+                            command,
+                            &overrides,
+                        );
+                        continue;
+                    }
+                }
+                StepExecution::Exhausted(_) => {
+                    // The policy may have taken a while; the plan may have changed
+                    let step_index = handle.index_in(conversation)?;
+                    let escalation = policy.escalate(conversation, step_index).await;
+                    self.handle_step_failure(conversation, session, step_index, escalation)
+                        .await?;
+                    policy.on_progress(
+                        conversation,
+                        WorkflowProgress::Escalated {
+                            step_index,
+                            escalation,
+                        },
+                    );
+                    if escalation == StepEscalation::Abort {
+                        return Ok(true);
+                    }
+                }
+                StepExecution::Attempted(_) => {}
+            }
+            return Ok(false);
+        }
+    }
+
+    /// Drop the commands above the block threshold, so that none is offered and approving
+    /// a command always lets it run, recording them in a `commands_blocked` event. Returns
+    /// the first dropped, if any.
+    fn drop_blocked_commands(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        generated: &mut GeneratedCommands,
+    ) -> Result<Option<GeneratedCommand>, OrchestratorError> {
+        let (blocked, offered): (Vec<_>, Vec<_>) = std::mem::take(&mut generated.commands)
+            .into_iter()
+            .partition(|command| self.risk_tiers.confirmation(command) == Confirmation::Blocked);
+        generated.commands = offered;
+        if blocked.is_empty() {
+            return Ok(None);
+        }
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            "commands_blocked",
+            serde_json::json!({
+                "step_index": step_index,
+                "commands": blocked
+                    .iter()
+                    .map(|command| serde_json::json!({
+                        "command": command.command,
+                        "risk_score": command.risk_score,
+                    }))
+                    .collect::<Vec<_>>(),
+                "threshold": self.risk_tiers.block_threshold(),
+            }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(blocked.into_iter().next())
+    }
+
+    /// Report how running the finishing command of a step the model said is done went,
+    /// then complete the step even if the command failed.
+    fn finish_done_step(
+        &self,
+        conversation: &mut ConversationContext,
+        policy: &dyn ApprovalPolicy,
+        handle: &StepHandle,
+        execution: Result<StepExecution, OrchestratorError>,
+    ) -> Result<(), OrchestratorError> {
+        let step_index = handle.index_in(conversation)?;
+        match &execution {
+            Ok(done) => policy.on_progress(
+                conversation,
+                WorkflowProgress::Executed {
+                    step_index,
+                    execution: done,
+                },
+            ),
+            Err(e) => {
+                warn!("Failed to execute the finishing command: {}", e);
+                policy.on_progress(
+                    conversation,
+                    WorkflowProgress::ExecutionFailed {
+                        step_index,
+                        error: e,
+                    },
+                );
+            }
+        }
+        if conversation.steps[step_index].status != StepStatus::Complete {
+            self.complete_step(conversation, step_index)?;
+        }
+        Ok(())
+    }
+
+    /// Ask `policy` to override the `Warn` validation rules an approved `command` matches
+    /// and record the answer. Returns whether they were overridden; commands that were
+    /// not are recorded as blocked attempts when run.
+    async fn confirm_override(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        step_index: usize,
+        command: &GeneratedCommand,
+        policy: &dyn ApprovalPolicy,
+    ) -> Result<bool, OrchestratorError> {
+        let Verdict::Warn(findings) = self.validate(conversation, session, command).verdict else {
+            return Ok(false);
+        };
+        let rules: Vec<String> = findings
+            .iter()
+            .map(|finding| finding.rule.clone())
+            .collect();
+        let confirmation = Confirmation::Override { findings };
+        let typed = policy
+            .confirm(conversation, step_index, command, &confirmation)
+            .await;
+        let confirmed = typed
+            .as_deref()
+            .is_some_and(|typed| confirmation.accepts(typed));
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            "validation_override",
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+                "rules": rules,
+                "typed": typed,
+                "confirmed": confirmed,
+            }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(confirmed)
+    }
+
+    /// Ask `policy` to run an approved `command` that needs a terminal attached to the
+    /// user's, and record the answer. Returns whether it was approved; commands that were
+    /// not are recorded as attempts that never ran.
+    async fn confirm_terminal(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        policy: &dyn ApprovalPolicy,
+    ) -> Result<bool, OrchestratorError> {
+        let Some(reason) = self.executor.step_needs_terminal(command) else {
+            return Ok(false);
+        };
+        let confirmation = Confirmation::Terminal {
+            reason: reason.clone(),
+        };
+        let typed = policy
+            .confirm(conversation, step_index, command, &confirmation)
+            .await;
+        let confirmed = typed
+            .as_deref()
+            .is_some_and(|typed| confirmation.accepts(typed));
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            "terminal_attach",
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+                "reason": reason,
+                "typed": typed,
+                "confirmed": confirmed,
+            }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(confirmed)
+    }
+
+    /// Ask `policy` to run an approved `command` the sandbox would keep off the network
+    /// outside it, and record the answer. Returns whether it was approved; commands that
+    /// were not are recorded as attempts that never ran.
+    async fn confirm_unsandboxed(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        policy: &dyn ApprovalPolicy,
+    ) -> Result<bool, OrchestratorError> {
+        let Some(reason) = self.executor.needs_unsandboxing(&command.command) else {
+            return Ok(false);
+        };
+        let confirmation = Confirmation::Unsandboxed {
+            reason: reason.clone(),
+        };
+        let typed = policy
+            .confirm(conversation, step_index, command, &confirmation)
+            .await;
+        let confirmed = typed
+            .as_deref()
+            .is_some_and(|typed| confirmation.accepts(typed));
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            "sandbox_escape",
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+                "reason": reason,
+                "typed": typed,
+                "confirmed": confirmed,
+            }),
+        );
+        self.save_appended(conversation, base)?;
+        Ok(confirmed)
+    }
+
+    /// Hold an approved `command` to its risk tier: get the typed confirmation it needs
+    /// from `policy` and record it. Returns why the command may not run, if it may not.
+    async fn confirm_command(
+        &self,
+        conversation: &mut ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        policy: &dyn ApprovalPolicy,
+    ) -> Result<Option<String>, OrchestratorError> {
+        let confirmation = self.risk_tiers.confirmation(command);
+        let phrase = match &confirmation {
+            // Risk tiers never ask for these; the other `confirm_*` methods do
+            Confirmation::NotNeeded
+            | Confirmation::Override { .. }
+            | Confirmation::Terminal { .. }
+            | Confirmation::Unsandboxed { .. } => return Ok(None),
+            Confirmation::Blocked => {
+                return Ok(Some(format!(
+                    "Risk score {:.2} is above the block threshold and dangerous commands are not allowed",
+                    command.risk_score.unwrap_or(0.0)
+                )))
+            }
+            Confirmation::Typed { phrase } => phrase.clone(),
+        };
+
+        let typed = policy
+            .confirm(conversation, step_index, command, &confirmation)
+            .await;
+        let confirmed = typed
+            .as_deref()
+            .is_some_and(|typed| confirmation.accepts(typed));
+        let base = conversation.clone();
+        self.record_event(
+            conversation,
+            "high_risk_confirmation",
+            serde_json::json!({
+                "step_index": step_index,
+                "command": command.command,
+                "risk_score": command.risk_score,
+                "phrase": phrase,
+                "typed": typed,
+                "confirmed": confirmed,
+            }),
+        );
+        self.save_appended(conversation, base)?;
+
+        Ok((!confirmed).then(|| match typed {
+            Some(_) => "Typed confirmation did not match".to_string(),
+            None => "High-risk command was not confirmed".to_string(),
+        }))
+    }
+}
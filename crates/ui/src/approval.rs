@@ -0,0 +1,691 @@
+//! Approving the commands of a running workflow at the terminal, and deciding what to do
+//! about failed and blocked steps.
+
+use async_trait::async_trait;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+
+use parsec_core::command_rules::{Finding, Severity};
+use parsec_core::injection;
+use parsec_core::*;
+use parsec_executor::jobs::{JobHandle, JobId};
+use parsec_executor::{is_overridable, ScriptFile};
+use parsec_prompt::{
+    ApprovalDecision, ApprovalPolicy, Confirmation, RiskThresholdPolicy, StepHandle, WorkflowPause,
+    WorkflowProgress,
+};
+
+use crate::audit::{Approval, AuditRecord};
+use crate::plan::rough_minutes;
+use crate::{BackgroundStep, OutputMode, ParsecApp, MULTI_LINE_QUOTE};
+
+/// The CLI's approval flow: asks at the terminal about each step's command, or under
+/// `--auto-approve` and `--dry-run` decides without asking. It also prints the workflow's
+/// progress and writes the audit log for the commands it approves.
+pub(crate) struct TerminalApproval<'a> {
+    app: &'a ParsecApp,
+    auto: Option<RiskThresholdPolicy>,
+    session_id: SessionId,
+    working_directory: PathBuf,
+    max_attempts: usize,
+    /// The `started` audit record of the command being run.
+    audit: Mutex<Option<AuditRecord>>,
+    /// Jobs started with `b`, to move into `ParsecApp::background_steps`.
+    background: Mutex<Vec<(JobId, BackgroundStep)>>,
+}
+
+impl<'a> TerminalApproval<'a> {
+    pub(crate) fn new(app: &'a ParsecApp, session: &Session) -> Self {
+        Self {
+            app,
+            auto: app
+                .auto_approve
+                .then(|| RiskThresholdPolicy::new(app.orchestrator.risk_threshold())),
+            session_id: session.id.clone(),
+            working_directory: session.global_context.working_directory.clone(),
+            max_attempts: session.settings.max_attempts_per_step,
+            audit: Mutex::new(None),
+            background: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn into_background(self) -> Vec<(JobId, BackgroundStep)> {
+        self.background.into_inner().unwrap_or_default()
+    }
+
+    fn approval(&self) -> Approval {
+        if self.auto.is_some() {
+            Approval::Auto
+        } else {
+            Approval::User
+        }
+    }
+
+    fn audit_start(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        command: &str,
+        confirmation: Option<&str>,
+    ) {
+        let record = self.app.audit_start(
+            &self.session_id,
+            &self.working_directory,
+            Some((conversation, step_index)),
+            command,
+            self.approval(),
+            confirmation,
+        );
+        *self.audit.lock().unwrap() = record;
+    }
+
+    fn take_audit(&self) -> Option<AuditRecord> {
+        self.audit.lock().unwrap().take()
+    }
+
+    fn ask(&self, command: &GeneratedCommand) -> Result<ApprovalDecision, anyhow::Error> {
+        let app = self.app;
+        if app.dry_run {
+            say!(app, "  Dry run: command not executed");
+            return Ok(ApprovalDecision::Skip);
+        }
+        let interactive = app.executor.step_needs_terminal(command).is_some();
+        let response = app
+            .read_line(if interactive {
+                "  Execute? (y/n/a/s/e/h/b/i) [y=yes, n=no, a=abort, s=skip, e=edit, h=hint, b=background, i=interactive]: "
+            } else {
+                "  Execute? (y/n/a/s/e/h/b) [y=yes, n=no, a=abort, s=skip, e=edit, h=hint, b=background]: "
+            })?
+            .trim()
+            .to_lowercase();
+        Ok(match response.as_str() {
+            "y" | "yes" | "" => ApprovalDecision::Approve,
+            "b" | "background" => ApprovalDecision::Background,
+            "i" | "interactive" => ApprovalDecision::Interactive,
+            "n" | "no" => {
+                say!(app, "  Command skipped by user");
+                ApprovalDecision::Deny {
+                    reason: "Rejected by the user".to_string(),
+                }
+            }
+            "a" | "abort" => {
+                say!(app, "  Conversation aborted by user");
+                ApprovalDecision::Abort
+            }
+            "s" | "skip" => {
+                say!(app, "  Step skipped by user");
+                ApprovalDecision::Skip
+            }
+            "e" | "edit" => {
+                let edited = match command.kind {
+                    CommandKind::Script => self.edit_script(&command.command)?,
+                    CommandKind::Single => app.read_line("  Command to run instead: ")?,
+                };
+                let edited = edited.trim();
+                if edited.is_empty() {
+                    say!(app, "  No command given; asking again");
+                    return Ok(ApprovalDecision::Hint(String::new()));
+                }
+                ApprovalDecision::Edit(edited.to_string())
+            }
+            "h" | "hint" => {
+                let hint = app.read_line("  Hint for the model: ")?;
+                if hint.trim().is_empty() {
+                    say!(app, "  No hint given; asking again");
+                }
+                ApprovalDecision::Hint(hint.trim().to_string())
+            }
+            _ => {
+                say!(app, "  Invalid response, skipping command");
+                ApprovalDecision::Skip
+            }
+        })
+    }
+
+    /// A replacement for `script`, edited in `$VISUAL` or `$EDITOR` if either is set, else
+    /// typed in up to a `"""` line.
+    fn edit_script(&self, script: &str) -> Result<String, anyhow::Error> {
+        let app = self.app;
+        let editor = ["VISUAL", "EDITOR"]
+            .into_iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|editor| !editor.trim().is_empty());
+        if let Some(editor) = editor {
+            let file = ScriptFile::write(&env::temp_dir(), script)?;
+            let mut words = editor.split_whitespace();
+            let program = words.next().unwrap_or_default();
+            let status = std::process::Command::new(program)
+                .args(words)
+                .arg(file.path())
+                .status()
+                .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("{} exited with {}", program, status));
+            }
+            return Ok(std::fs::read_to_string(file.path())?);
+        }
+
+        say!(
+            app,
+            "  Script to run instead, ending with a line of {}:",
+            MULTI_LINE_QUOTE
+        );
+        let mut edited = String::new();
+        loop {
+            let line = app.read_line("  ... ")?;
+            // An empty read is the end of stdin
+            if line.is_empty() || line.trim() == MULTI_LINE_QUOTE {
+                return Ok(edited);
+            }
+            edited.push_str(&line);
+        }
+    }
+
+    /// Show the `Warn` rules a command matched and ask for `override`.
+    fn ask_override(&self, findings: &[Finding]) -> Option<String> {
+        let app = self.app;
+        say!(app, "  {}", app.render.warning("Validation rules matched:"));
+        for finding in findings {
+            say!(app, "    {}", finding);
+        }
+        if self.auto.is_some() {
+            say!(
+                app,
+                "  {}",
+                app.render
+                    .failure("Not overriding them unattended; overrides are typed at the prompt")
+            );
+            return None;
+        }
+        match app.read_line("  Type `override` to run it anyway: ") {
+            Ok(line) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => {
+                error!("Failed to read the override: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Ask whether to go on after the circuit breaker tripped; even auto-approved runs
+    /// ask, since the breaker is there for them, but only with someone at the terminal.
+    fn ask_resume(&self) -> bool {
+        let app = self.app;
+        if app.output != OutputMode::Text || !io::stdin().is_terminal() {
+            say!(app, "  {}", app.render.failure("Stopping the workflow"));
+            return false;
+        }
+        match app.read_line("  Resume the workflow? [y/N] ") {
+            Ok(line) => matches!(line.trim().to_lowercase().as_str(), "y" | "yes"),
+            Err(e) => {
+                error!("Failed to read the answer: {}", e);
+                false
+            }
+        }
+    }
+
+    fn ask_unsandboxed(&self, reason: &str) -> Option<String> {
+        let app = self.app;
+        say!(
+            app,
+            "  {} {}",
+            app.render.warning("Needs the network:"),
+            reason
+        );
+        if self.auto.is_some() || app.output != OutputMode::Text {
+            say!(
+                app,
+                "  {}",
+                app.render.failure("Not leaving the sandbox unattended")
+            );
+            return None;
+        }
+        match app.read_line("  Run it unsandboxed? [y/N] ") {
+            Ok(line) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => {
+                error!("Failed to read the answer: {}", e);
+                None
+            }
+        }
+    }
+
+    fn ask_terminal(&self, reason: &str) -> Option<String> {
+        let app = self.app;
+        say!(
+            app,
+            "  {} {}",
+            app.render.warning("Needs a terminal:"),
+            reason
+        );
+        if self.auto.is_some() || app.output != OutputMode::Text {
+            say!(
+                app,
+                "  {}",
+                app.render
+                    .failure("Not attaching it to the terminal unattended")
+            );
+            return None;
+        }
+        match app.read_line("  Type `yes` to run it attached to this terminal: ") {
+            Ok(line) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => {
+                error!("Failed to read the answer: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ApprovalPolicy for TerminalApproval<'_> {
+    async fn decide(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+    ) -> ApprovalDecision {
+        let confirmation = self.app.orchestrator.risk_tiers().confirmation(command);
+        let decision = match &self.auto {
+            Some(auto) => auto.decide(conversation, step_index, command).await,
+            None => self.ask(command).unwrap_or_else(|e| {
+                error!("Failed to read the approval: {}", e);
+                ApprovalDecision::Abort
+            }),
+        };
+        match &decision {
+            // High-risk commands are logged once confirmed, with what was typed
+            ApprovalDecision::Approve
+            | ApprovalDecision::Background
+            | ApprovalDecision::Interactive
+                if confirmation == Confirmation::NotNeeded =>
+            {
+                self.audit_start(conversation, step_index, &command.command, None)
+            }
+            ApprovalDecision::Edit(edited) => {
+                self.audit_start(conversation, step_index, edited, None)
+            }
+            ApprovalDecision::Deny { reason } if self.auto.is_some() => {
+                say!(self.app, "  {}", reason)
+            }
+            _ => {}
+        }
+        decision
+    }
+
+    async fn retry_timeout(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        attempt: &CommandAttempt,
+    ) -> Option<Duration> {
+        let timeout = self.app.ask_longer_timeout().unwrap_or_else(|e| {
+            error!("Failed to read the answer: {}", e);
+            None
+        })?;
+        self.audit_start(conversation, step_index, &attempt.candidate.command, None);
+        Some(timeout)
+    }
+
+    /// Ask for `yes` or the highlighted phrase, `override` for validation rules, `yes` to
+    /// attach a command to the terminal, or `y` to run it outside the sandbox; unattended
+    /// runs never confirm.
+    async fn confirm(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        confirmation: &Confirmation,
+    ) -> Option<String> {
+        let phrase = match confirmation {
+            Confirmation::Typed { phrase } => phrase,
+            Confirmation::Override { findings } => return self.ask_override(findings),
+            Confirmation::Terminal { reason } => return self.ask_terminal(reason),
+            Confirmation::Unsandboxed { reason } => return self.ask_unsandboxed(reason),
+            Confirmation::NotNeeded | Confirmation::Blocked => return None,
+        };
+        let app = self.app;
+        if self.auto.is_some() {
+            say!(
+                app,
+                "  {}",
+                app.render
+                    .failure("High-risk commands need a typed confirmation; not auto-approving")
+            );
+            return None;
+        }
+        say!(
+            app,
+            "  {} {}",
+            app.render.warning("High-risk command:"),
+            app.render.highlight(&command.command, phrase)
+        );
+        let typed = match app.read_line(&format!("  Type `yes` or `{}` to run it: ", phrase)) {
+            Ok(line) => line.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => {
+                error!("Failed to read the confirmation: {}", e);
+                return None;
+            }
+        };
+        self.audit_start(conversation, step_index, &command.command, Some(&typed));
+        if !confirmation.accepts(&typed) {
+            let reason = "Typed confirmation did not match";
+            say!(app, "  {}", app.render.failure(reason));
+            app.audit_finish(
+                self.take_audit(),
+                None,
+                Duration::ZERO,
+                Some(reason.to_string()),
+            );
+        }
+        Some(typed)
+    }
+
+    /// Ask how to resolve the step; unattended runs abort.
+    async fn escalate(
+        &self,
+        _conversation: &ConversationContext,
+        step_index: usize,
+    ) -> StepEscalation {
+        let app = self.app;
+        say!(
+            app,
+            "  {}",
+            app.render.failure(format_args!(
+                "Step {} used up its {} attempts",
+                step_index + 1,
+                self.max_attempts
+            ))
+        );
+        if !app.is_interactive() {
+            return StepEscalation::Abort;
+        }
+        loop {
+            let answer = match app.read_line("  Skip, replan or abort? (s/r/a): ") {
+                Ok(answer) => answer,
+                Err(e) => {
+                    error!("Failed to read the answer: {}", e);
+                    return StepEscalation::Abort;
+                }
+            };
+            match answer.trim().to_lowercase().as_str() {
+                "s" | "skip" => return StepEscalation::Skip,
+                "r" | "replan" => return StepEscalation::Replan,
+                "a" | "abort" => return StepEscalation::Abort,
+                _ => say!(app, "  Please answer s, r or a"),
+            }
+        }
+    }
+
+    fn on_background(
+        &self,
+        conversation: &ConversationContext,
+        step_index: usize,
+        command: &GeneratedCommand,
+        job: JobHandle,
+    ) {
+        let handle = match StepHandle::new(conversation, step_index) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to track background step {}: {}", step_index + 1, e);
+                return;
+            }
+        };
+        let id = self.app.jobs.register(job);
+        self.background.lock().unwrap().push((
+            id,
+            BackgroundStep {
+                conversation_id: conversation.id.clone(),
+                handle,
+                command: command.clone(),
+                audit: self.take_audit(),
+            },
+        ));
+        say!(
+            self.app,
+            "  ⏳ Running in the background as job {}; `tail {}`, `wait {}` or `kill {}`",
+            id,
+            id,
+            id,
+            id
+        );
+    }
+
+    async fn resume(&self, _conversation: &ConversationContext, _pause: &WorkflowPause) -> bool {
+        self.ask_resume()
+    }
+
+    fn on_progress(&self, conversation: &ConversationContext, progress: WorkflowProgress<'_>) {
+        let app = self.app;
+        match &progress {
+            WorkflowProgress::StepStarted { step_index }
+            | WorkflowProgress::Commands { step_index, .. } => {
+                app.guard.step_started(conversation, *step_index)
+            }
+            _ => app.guard.conversation_changed(conversation),
+        }
+        match progress {
+            WorkflowProgress::StepStarted { step_index } => {
+                say!(
+                    app,
+                    "\n{}",
+                    app.render.step_header(
+                        step_index + 1,
+                        &conversation.steps[step_index].step.description
+                    )
+                );
+                if let Some(eta) = estimate::eta(conversation) {
+                    let unestimated = match eta.unestimated {
+                        0 => String::new(),
+                        1 => " plus a step without an estimate".to_string(),
+                        n => format!(" plus {} steps without an estimate", n),
+                    };
+                    say!(
+                        app,
+                        "{}",
+                        app.render.dim(format_args!(
+                            "  ETA: {} left{}, {} so far",
+                            rough_minutes(eta.remaining),
+                            unestimated,
+                            rough_minutes(eta.spent)
+                        ))
+                    );
+                }
+            }
+            WorkflowProgress::Commands {
+                step_index,
+                commands,
+            } => {
+                app.emit(OutputRecord::Commands {
+                    conversation_id: conversation.id.clone(),
+                    step_index,
+                    step_id: conversation.steps[step_index].step.id.clone(),
+                    commands: commands.commands.clone(),
+                    done: commands.done,
+                });
+                let Some(primary) = commands.commands.first() else {
+                    if commands.done {
+                        say!(app, "  Step completed without commands.");
+                    }
+                    return;
+                };
+                if commands.done {
+                    say!(
+                        app,
+                        "  Step done; the command below only finishes it off, and the step \
+                         completes whether or not it runs."
+                    );
+                }
+                if primary.kind == CommandKind::Script {
+                    say!(
+                        app,
+                        "  Script:\n{}",
+                        app.render.script_block(&primary.command)
+                    );
+                } else {
+                    say!(app, "  Command: {}", app.render.command(&primary.command));
+                }
+                say!(app, "  {}", app.render.dim(&primary.explanation));
+                if let Some(badge) = primary.risk_score.and_then(|risk_score| {
+                    app.render
+                        .risk_badge(risk_score, app.orchestrator.risk_threshold())
+                }) {
+                    say!(app, "  {}", badge);
+                }
+                if !primary.missing_tools.is_empty() {
+                    say!(
+                        app,
+                        "  {}",
+                        app.render.warning(format_args!(
+                            "Not installed: {}",
+                            primary.missing_tools.join(", ")
+                        ))
+                    );
+                }
+            }
+            WorkflowProgress::Executed {
+                step_index,
+                execution,
+            } => {
+                let attempt = execution.attempt();
+                app.audit_attempt(self.take_audit(), attempt);
+                let suspicious = [&attempt.stdout, &attempt.stderr]
+                    .into_iter()
+                    .flat_map(|text| injection::instruction_like_lines(&text.content))
+                    .count();
+                if suspicious > 0 {
+                    say!(
+                        app,
+                        "  {}",
+                        app.render.warning(format_args!(
+                            "{} line(s) of output read like instructions to the model; they are withheld from prompts",
+                            suspicious
+                        ))
+                    );
+                }
+                app.emit(OutputRecord::Execution {
+                    conversation_id: Some(conversation.id.clone()),
+                    step_index: Some(step_index),
+                    command: attempt.candidate.command.clone(),
+                    executed: attempt.executed,
+                    exit_status: attempt.exit_status,
+                    risk_score: attempt.candidate.risk_score,
+                    stdout: attempt.stdout.clone(),
+                    stderr: attempt.stderr.clone(),
+                });
+                if let Some(ExecutionError::Blocked(findings)) = &attempt.error {
+                    say!(app, "  {}", app.render.failure("Blocked by validation:"));
+                    for finding in findings {
+                        say!(app, "    {}", finding);
+                    }
+                    let overridable: Vec<&str> = findings
+                        .iter()
+                        .filter(|finding| finding.severity == Severity::Block)
+                        .map(|finding| finding.rule.as_str())
+                        .filter(|rule| is_overridable(rule))
+                        .collect();
+                    if !overridable.is_empty() {
+                        say!(
+                            app,
+                            "  {}",
+                            app.render.dim(format_args!(
+                                "List {} in executor.allow_rules to be asked to override it",
+                                overridable.join(", ")
+                            ))
+                        );
+                    }
+                } else if attempt.error.is_none() {
+                    say!(
+                        app,
+                        "  {}",
+                        app.render.success(format_args!(
+                            "done in {}{}",
+                            format_duration(attempt.duration),
+                            attempt
+                                .sandbox
+                                .as_ref()
+                                .map(|sandbox| format!(", sandboxed by {}", sandbox))
+                                .unwrap_or_default()
+                        ))
+                    );
+                    // Attached commands showed their output as they ran
+                    if !attempt.stdout.content.is_empty() && !attempt.attached {
+                        say!(app, "{}", app.render.output_block(&attempt.stdout.content));
+                    }
+                } else {
+                    say!(
+                        app,
+                        "  {}",
+                        app.render.failure(format_args!(
+                            "Command failed after {}: {:?}",
+                            format_duration(attempt.duration),
+                            attempt.error
+                        ))
+                    );
+                    if !attempt.stderr.content.is_empty() {
+                        say!(app, "{}", app.render.output_block(&attempt.stderr.content));
+                    }
+                }
+            }
+            WorkflowProgress::ExecutionFailed { error, .. } => {
+                app.audit_finish(
+                    self.take_audit(),
+                    None,
+                    Duration::ZERO,
+                    Some(error.to_string()),
+                );
+                say!(
+                    app,
+                    "  {}",
+                    app.render
+                        .failure(format_args!("Execution error: {}", error))
+                );
+            }
+            WorkflowProgress::Escalated {
+                step_index,
+                escalation,
+            } => match escalation {
+                StepEscalation::Skip => say!(app, "  Step skipped"),
+                StepEscalation::Abort => say!(app, "  Conversation aborted"),
+                StepEscalation::Replan => {
+                    say!(
+                        app,
+                        "  {}",
+                        app.render.success("Replanned the remaining work:")
+                    );
+                    for (i, step) in conversation.steps.iter().enumerate().skip(step_index + 1) {
+                        say!(app, "  {}. {}", i + 1, step.step.description);
+                    }
+                    app.emit(OutputRecord::Plan {
+                        conversation_id: conversation.id.clone(),
+                        name: conversation.name.clone(),
+                        steps: conversation
+                            .steps
+                            .iter()
+                            .map(|state| state.step.clone())
+                            .collect(),
+                    });
+                }
+            },
+            WorkflowProgress::Paused(pause @ WorkflowPause::Cooldown { .. }) => {
+                say!(app, "  {}", app.render.dim(pause))
+            }
+            WorkflowProgress::Paused(pause) => say!(app, "  {}", app.render.warning(pause)),
+            WorkflowProgress::NeedsAttention {
+                command, threshold, ..
+            } => say!(
+                app,
+                "  {}",
+                app.render.warning(format_args!(
+                    "⚠ Needs attention: `{}` scores {:.2}, above the block threshold of {:.2}; \
+                     rerun with --allow-dangerous to be asked to confirm it",
+                    command.command,
+                    command.risk_score.unwrap_or(0.0),
+                    threshold
+                ))
+            ),
+        }
+    }
+}
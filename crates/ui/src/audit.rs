@@ -0,0 +1,259 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Name of the file records are appended to; rotated files are `audit-<time>.jsonl`.
+const CURRENT_FILE: &str = "audit.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Approval {
+    /// Typed by the user or approved at the prompt.
+    User,
+    /// Run under `--auto-approve` / `executor.auto_approve`.
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    /// Written before the command runs, so a run that never finishes is still on record.
+    Started,
+    Finished,
+}
+
+/// One line of the audit log. A command gets a `started` line and, once it ends, a
+/// `finished` line with the same `id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub id: String,
+    pub event: AuditEvent,
+    pub timestamp: DateTime<Utc>,
+    pub session_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_id: Option<String>,
+    pub command: String,
+    pub working_directory: PathBuf,
+    pub approval: Approval,
+    /// Exactly what the user typed to confirm a high-risk command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Why the command failed to run or did not exit cleanly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn started(
+        session_id: &str,
+        conversation_id: Option<&str>,
+        step_id: Option<&str>,
+        command: &str,
+        working_directory: &Path,
+        approval: Approval,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event: AuditEvent::Started,
+            timestamp: Utc::now(),
+            session_id: session_id.to_string(),
+            conversation_id: conversation_id.map(str::to_string),
+            step_id: step_id.map(str::to_string),
+            command: command.to_string(),
+            working_directory: working_directory.to_path_buf(),
+            approval,
+            confirmation: None,
+            exit_status: None,
+            duration_ms: None,
+            error: None,
+        }
+    }
+
+    pub fn with_confirmation(mut self, typed: Option<&str>) -> Self {
+        self.confirmation = typed.map(str::to_string);
+        self
+    }
+
+    /// The `finished` line for this command.
+    pub fn finished(
+        &self,
+        exit_status: Option<i32>,
+        duration: Duration,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            event: AuditEvent::Finished,
+            timestamp: Utc::now(),
+            exit_status,
+            duration_ms: Some(duration.as_millis() as u64),
+            error,
+            ..self.clone()
+        }
+    }
+
+    /// One line for `parsec audit tail` and `search`.
+    pub fn render(&self) -> String {
+        let outcome = match (self.event, self.exit_status) {
+            (AuditEvent::Started, _) => "unfinished".to_string(),
+            (AuditEvent::Finished, Some(status)) => format!("exit {}", status),
+            (AuditEvent::Finished, None) => "not run".to_string(),
+        };
+        let duration = self
+            .duration_ms
+            .map(|ms| parsec_core::format_duration(Duration::from_millis(ms)))
+            .unwrap_or_else(|| "-".to_string());
+        let step = match (&self.conversation_id, &self.step_id) {
+            (Some(conversation), Some(step)) => format!(" {}/{}", conversation, step),
+            (Some(conversation), None) => format!(" {}", conversation),
+            _ => String::new(),
+        };
+        let mut line = format!(
+            "{}  {:<10} {:>7}  {:<4}  {}{}  {}$ {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            outcome,
+            duration,
+            match self.approval {
+                Approval::User => "user",
+                Approval::Auto => "auto",
+            },
+            self.session_id,
+            step,
+            self.working_directory.display(),
+            self.command
+        );
+        if let Some(typed) = &self.confirmation {
+            line.push_str(&format!("  [confirmed with {:?}]", typed));
+        }
+        if let Some(error) = &self.error {
+            line.push_str(&format!("  ({})", error));
+        }
+        line
+    }
+}
+
+/// Append-only JSONL record of every command parsec runs, kept apart from the session
+/// store so pruning or an in-memory store cannot lose it. Each line is synced to disk
+/// before the call returns.
+pub struct AuditLog {
+    dir: PathBuf,
+    /// The current file is rotated once it reaches this size.
+    max_file_bytes: u64,
+    /// Rotated files to keep; 0 keeps them all.
+    max_files: usize,
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    pub fn new(dir: PathBuf, max_file_bytes: u64, max_files: usize) -> Self {
+        Self {
+            dir,
+            max_file_bytes,
+            max_files,
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn append(&self, record: &AuditRecord) {
+        if let Err(e) = self.write(record) {
+            warn!("Failed to write audit log in {}: {}", self.dir.display(), e);
+        }
+    }
+
+    fn write(&self, record: &AuditRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        let path = self.dir.join(CURRENT_FILE);
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_file_bytes {
+            *file = None;
+            self.rotate(&path)?;
+        }
+        if file.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            *file = Some(OpenOptions::new().create(true).append(true).open(&path)?);
+        }
+        let file = file.as_mut().expect("opened above");
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn rotate(&self, path: &Path) -> anyhow::Result<()> {
+        let rotated = self.dir.join(format!(
+            "audit-{}.jsonl",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f")
+        ));
+        fs::rename(path, rotated)?;
+        if self.max_files > 0 {
+            let files = log_files(&self.dir);
+            let rotated = &files[..files.len().saturating_sub(1)];
+            for old in &rotated[..rotated.len().saturating_sub(self.max_files)] {
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Audit files in `dir`, oldest first; the current file, if any, is last.
+fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("audit-") && name.ends_with(".jsonl"))
+        })
+        .collect();
+    rotated.sort();
+    let current = dir.join(CURRENT_FILE);
+    if current.exists() {
+        rotated.push(current);
+    }
+    rotated
+}
+
+/// Every command in the log, oldest first, each as its `finished` record or, for one that
+/// never finished, its `started` record. Unreadable lines are skipped with a warning.
+pub fn read_commands(dir: &Path) -> anyhow::Result<Vec<AuditRecord>> {
+    let mut commands: Vec<AuditRecord> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for path in log_files(dir) {
+        for (number, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            let record: AuditRecord = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Skipping {}:{}: {}", path.display(), number + 1, e);
+                    continue;
+                }
+            };
+            match positions.get(&record.id) {
+                Some(&position) => commands[position] = record,
+                None => {
+                    positions.insert(record.id.clone(), commands.len());
+                    commands.push(record);
+                }
+            }
+        }
+    }
+    Ok(commands)
+}
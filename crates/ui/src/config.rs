@@ -0,0 +1,1674 @@
+use parsec_core::context_files::{self, ContextFiles};
+use parsec_core::environment::EnvFilter;
+use parsec_core::secrets::SecretRedactor;
+use parsec_core::tools::default_tracked_tools;
+use parsec_core::{
+    default_history_full_output, default_history_limit, default_max_attempts_per_step, RateLimits,
+    RetentionPolicy, SessionSettings,
+};
+use parsec_executor::snapshot::SnapshotOptions;
+use parsec_executor::DEFAULT_BLOCK_THRESHOLD;
+use parsec_model::{FileSessionStore, PromptBudget};
+use parsec_prompt::approval::DEFAULT_CONFIRM_THRESHOLD;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Every key a config file may set, as `section.key`. Anything else is warned about.
+const KNOWN_KEYS: &[&str] = &[
+    "model.provider",
+    "model.name",
+    "model.fallbacks",
+    "model.prompt_budget_chars",
+    "model.verbatim_history_steps",
+    "model.shell_history_commands",
+    "model.context_compression_threshold",
+    "model.command_candidates",
+    "model.auth",
+    "model.vertex_project",
+    "model.vertex_location",
+    "model.planning_timeout_secs",
+    "model.command_timeout_secs",
+    "azure_openai.resource",
+    "azure_openai.deployment",
+    "azure_openai.api_version",
+    "azure_openai.planning_timeout_secs",
+    "azure_openai.command_timeout_secs",
+    "cache.enabled",
+    "cache.disk",
+    "cache.ttl_secs",
+    "cache.capacity",
+    "cache.allow_with_privacy_mode",
+    "classifier.kind",
+    "classifier.confirm_below",
+    "classifier.default_kind",
+    "classifier.answer_questions",
+    "classifier.extra_shell_commands",
+    "classifier.removed_shell_commands",
+    "classifier.extra_prompt_indicators",
+    "classifier.removed_prompt_indicators",
+    "classifier.shell_cutoff",
+    "classifier.prompt_cutoff",
+    "classifier.remote_timeout_ms",
+    "classifier.local_model_dir",
+    "classifier.loading_wait_secs",
+    "classifier.loading_retries",
+    "classifier.python_script",
+    "executor.timeout_secs",
+    "executor.max_output_size",
+    "executor.risk_threshold",
+    "executor.confirm_threshold",
+    "executor.block_threshold",
+    "executor.auto_approve",
+    "executor.max_attempts_per_step",
+    "executor.parallel_steps",
+    "executor.timeout_from_estimates",
+    "executor.allow_rules",
+    "executor.shell",
+    "executor.sandbox",
+    "executor.sandbox_image",
+    "executor.sandbox_network",
+    "executor.min_free_disk_mb",
+    "executor.network_probe",
+    "executor.min_command_interval_ms",
+    "executor.max_commands_per_conversation",
+    "executor.breaker_failures",
+    "executor.breaker_window_secs",
+    "retention.session_retention_days",
+    "retention.conversation_retention_days",
+    "retention.max_sessions",
+    "storage.data_dir",
+    "storage.encryption",
+    "tools.tracked",
+    "history.limit",
+    "history.full_output",
+    "artifacts.enabled",
+    "artifacts.max_depth",
+    "artifacts.max_entries",
+    "artifacts.collapse_threshold",
+    "jobs.kill_on_exit",
+    "followup.window_secs",
+    "followup.max_depth",
+    "audit.enabled",
+    "audit.max_file_mb",
+    "audit.max_files",
+    "metrics.enabled",
+    "embeddings.provider",
+    "embeddings.model",
+    "embeddings.max_vectors",
+    "embeddings.max_age_days",
+    "output.sanitize",
+    "output.keep_raw",
+    "output.redact_secrets",
+    "output.secret_allowlist",
+    "environment.allow",
+    "environment.deny",
+    "context_files.manifests",
+    "context_files.refused",
+    "context_files.max_file_bytes",
+    "context_files.budget_bytes",
+    "debug.record_prompts",
+];
+
+/// Keys a project's `.parsec.toml` may not set, since any cloned repository has one: they
+/// decide what runs without asking, with which shell or script, and what is redacted,
+/// audited or stored where. Only the user config and the command line set them.
+const USER_ONLY_KEYS: &[&str] = &[
+    "azure_openai.resource",
+    "classifier.python_script",
+    "classifier.local_model_dir",
+    "executor.auto_approve",
+    "executor.risk_threshold",
+    "executor.confirm_threshold",
+    "executor.block_threshold",
+    "executor.allow_rules",
+    "executor.shell",
+    "executor.sandbox",
+    "executor.sandbox_image",
+    "executor.sandbox_network",
+    "storage.data_dir",
+    "storage.encryption",
+    "audit.enabled",
+    "output.redact_secrets",
+    "output.secret_allowlist",
+    "context_files.refused",
+    "debug.record_prompts",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::User(path) => write!(f, "user config {}", path.display()),
+            ConfigSource::Project(path) => write!(f, "project config {}", path.display()),
+            ConfigSource::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Effective configuration: CLI flag > project config > user config > defaults.
+#[derive(Debug, Clone)]
+pub struct ParsecConfig {
+    pub provider: String,
+    pub model: String,
+    /// Tried in order when the primary is out of quota or unavailable, as
+    /// `provider` or `provider:model` (the model defaults to `model.name`).
+    pub model_fallbacks: Vec<String>,
+    /// Command prompts longer than this have their execution history trimmed, keeping
+    /// the last `verbatim_history_steps` steps in full where possible.
+    pub prompt_budget_chars: usize,
+    pub verbatim_history_steps: usize,
+    /// Commands the user ran directly that planning and command prompts show, picked by
+    /// recency and by what they share with the task; 0 shows none.
+    pub shell_history_commands: usize,
+    /// Summarize older steps once their raw history passes this share of the budget;
+    /// 1.0 turns it off.
+    pub context_compression_threshold: f32,
+    /// Candidate responses to ask for when generating step commands; more than one
+    /// merges their commands, on providers that can return several.
+    pub command_candidates: u32,
+    /// `api_key` for the Gemini API, or `vertex_adc` for Vertex AI with application
+    /// default credentials.
+    pub model_auth: String,
+    /// Empty takes the project from the credentials or `GOOGLE_CLOUD_PROJECT`.
+    pub vertex_project: String,
+    pub vertex_location: String,
+    /// Seconds `google-ai` may take to plan, and to generate a step's commands; 0 waits
+    /// as long as the request does.
+    pub planning_timeout_secs: u64,
+    pub command_timeout_secs: u64,
+    /// Where `azure-openai` sends requests; empty values fall back to
+    /// `AZURE_OPENAI_RESOURCE`, `AZURE_OPENAI_DEPLOYMENT` and `AZURE_OPENAI_API_VERSION`.
+    pub azure_resource: String,
+    pub azure_deployment: String,
+    pub azure_api_version: String,
+    /// `planning_timeout_secs` and `command_timeout_secs` for `azure-openai`.
+    pub azure_planning_timeout_secs: u64,
+    pub azure_command_timeout_secs: u64,
+    /// Reuse model responses for identical prompts; `disk` also keeps them under
+    /// `<data_dir>/cache` across runs, as plain text even with `storage.encryption`, so
+    /// it is off by default.
+    pub cache_enabled: bool,
+    pub cache_disk: bool,
+    pub cache_ttl_secs: u64,
+    pub cache_capacity: usize,
+    /// Cache calls for sessions in privacy mode too; off by default.
+    pub cache_allow_with_privacy_mode: bool,
+    pub classifier: String,
+    /// Ask the user to confirm classifications less confident than this.
+    pub classifier_confirm_below: f32,
+    /// `shell` or `prompt`: what unsure inputs become when nobody can be asked.
+    pub classifier_default_kind: String,
+    /// Answer prompts phrased as questions in one model call instead of planning them.
+    pub answer_questions: bool,
+    pub extra_shell_commands: Vec<String>,
+    pub removed_shell_commands: Vec<String>,
+    pub extra_prompt_indicators: Vec<String>,
+    pub removed_prompt_indicators: Vec<String>,
+    /// Ensemble: heuristic answers at least this confident skip the remote classifier.
+    pub shell_cutoff: f32,
+    pub prompt_cutoff: f32,
+    pub remote_timeout_ms: u64,
+    /// Directory holding `model.onnx` and `tokenizer.json` for the local classifier.
+    pub local_model_dir: PathBuf,
+    /// Hugging Face: longest single wait for a cold model, and how often to retry.
+    pub loading_wait_secs: u64,
+    pub loading_retries: u32,
+    /// Script (or directory holding `classifier.py`) for the python classifier;
+    /// unset falls back to `PARSEC_CLASSIFIER_SCRIPT`, then `./py`.
+    pub python_script: Option<PathBuf>,
+    pub executor_timeout_secs: u64,
+    pub max_output_size: usize,
+    pub risk_threshold: f32,
+    /// Commands scored above this need the user to type a confirmation.
+    pub confirm_threshold: f32,
+    /// Commands scored above this are never offered unless dangerous commands are allowed.
+    pub block_threshold: f32,
+    pub auto_approve: bool,
+    pub max_attempts_per_step: usize,
+    /// Steps whose commands may run at once when their dependencies allow.
+    pub parallel_steps: usize,
+    /// Time out step commands after a multiple of their step's estimated run time.
+    pub timeout_from_estimates: bool,
+    /// `Warn` validation rules, such as `shutdown`, that a typed override lets through; the
+    /// others block.
+    pub allowed_rules: Vec<String>,
+    /// Runs generated scripts that have no shebang line, with `-e`, and command lines using
+    /// shell syntax, with `-c`.
+    pub shell: String,
+    /// `none`, `bubblewrap`, `firejail` or `docker`: what confines step commands.
+    pub sandbox: String,
+    /// The image the `docker` sandbox runs commands in.
+    pub sandbox_image: String,
+    /// Let sandboxed commands reach the network.
+    pub sandbox_network: bool,
+    /// Less free disk space than this in the working directory stops a workflow; 0 skips
+    /// the check.
+    pub min_free_disk_mb: u64,
+    /// `host:port` connected to before running a plan that looks like it needs the
+    /// network; empty skips the check.
+    pub network_probe: String,
+    /// Spacing, cap and circuit breaker for the commands of each workflow.
+    pub rate_limits: RateLimits,
+    pub retention: RetentionPolicy,
+    pub data_dir: PathBuf,
+    /// `none`, `passphrase` (from `PARSEC_STORE_PASSPHRASE` or asked for) or `keyring`:
+    /// how saved sessions and conversations are encrypted.
+    pub storage_encryption: String,
+    pub tracked_tools: Vec<String>,
+    /// Direct commands a session remembers for the `history` builtin.
+    pub history_limit: usize,
+    /// How many of those keep their captured output.
+    pub history_full_output: usize,
+    /// Snapshot the working directory around step commands to record the files they
+    /// create or modify, walking at most `max_depth` levels and `max_entries` files.
+    pub artifacts_enabled: bool,
+    pub artifacts_max_depth: usize,
+    pub artifacts_max_entries: usize,
+    /// A top-level directory with more changed files than this is listed as one artifact.
+    pub artifacts_collapse_threshold: usize,
+    /// Kill background jobs still running when parsec exits; otherwise leave them
+    /// running, writing to their spool files under `<data_dir>/jobs`.
+    pub jobs_kill_on_exit: bool,
+    /// A prompt this soon after the session's last conversation finished is offered as a
+    /// follow-up to it (0 turns the offer off; `followup:` still works).
+    pub follow_up_window_secs: u64,
+    /// Ancestors of a follow-up described in full to its planner.
+    pub follow_up_max_depth: usize,
+    /// Record every executed command under `<data_dir>/audit`, rotating the file at
+    /// `max_file_mb` and keeping `max_files` rotated files (0 keeps all of them).
+    pub audit_enabled: bool,
+    pub audit_max_file_mb: u64,
+    pub audit_max_files: usize,
+    /// Count model calls, executions, classifications and planning time into
+    /// `<data_dir>/metrics.json` for `parsec stats`.
+    pub metrics_enabled: bool,
+    /// Rank context items for planning by embedding similarity to the prompt, through
+    /// `embeddings_provider` (`none` matches words instead). At most `max_vectors` vectors
+    /// are kept, in `<data_dir>/embeddings.json`, dropping those unused for `max_age_days`.
+    pub embeddings_provider: String,
+    pub embeddings_model: String,
+    pub embeddings_max_vectors: usize,
+    pub embeddings_max_age_days: u32,
+    /// Strip color codes and progress-bar rewrites from captured output before it is
+    /// stored and shown to the model; `keep_raw` also saves the original under
+    /// `<data_dir>/raw-output` whenever that changed it.
+    pub output_sanitize: bool,
+    pub output_keep_raw: bool,
+    /// Replace credentials such as API keys, tokens and `PASSWORD=...` values in captured
+    /// output and model prompts with placeholders, except the allowlisted values.
+    pub output_redact_secrets: bool,
+    pub output_secret_allowlist: Vec<String>,
+    /// Variables or `PREFIX*` patterns recorded in a session's environment snapshot on
+    /// top of the built-in list, and ones never recorded. Credential-like names are
+    /// never recorded either way.
+    pub environment_allow: Vec<String>,
+    pub environment_deny: Vec<String>,
+    /// File names attached to every prompt when they are in the working directory of a
+    /// detected project, and file names or `*` patterns never attached, even when asked
+    /// for. Each file may hold `max_file_bytes` and a prompt's files `budget_bytes`.
+    pub context_manifests: Vec<String>,
+    pub context_refused: Vec<String>,
+    pub context_max_file_bytes: u64,
+    pub context_budget_bytes: u64,
+    /// Store every model prompt and raw response in the conversation history.
+    pub record_prompts: bool,
+    pub sources: BTreeMap<&'static str, ConfigSource>,
+    pub warnings: Vec<String>,
+}
+
+/// Values the command line may override.
+#[derive(Debug, Default)]
+pub struct CliOverrides {
+    pub classifier: Option<String>,
+    pub python_script: Option<PathBuf>,
+    pub auto_approve: Option<bool>,
+    pub data_dir: Option<PathBuf>,
+    pub cache_enabled: Option<bool>,
+    pub record_prompts: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FileConfig {
+    model: Option<ModelSection>,
+    azure_openai: Option<AzureOpenAiSection>,
+    cache: Option<CacheSection>,
+    classifier: Option<ClassifierSection>,
+    executor: Option<ExecutorSection>,
+    retention: Option<RetentionSection>,
+    storage: Option<StorageSection>,
+    tools: Option<ToolsSection>,
+    history: Option<HistorySection>,
+    artifacts: Option<ArtifactsSection>,
+    jobs: Option<JobsSection>,
+    followup: Option<FollowUpSection>,
+    audit: Option<AuditSection>,
+    metrics: Option<MetricsSection>,
+    embeddings: Option<EmbeddingsSection>,
+    output: Option<OutputSection>,
+    environment: Option<EnvironmentSection>,
+    context_files: Option<ContextFilesSection>,
+    debug: Option<DebugSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ModelSection {
+    provider: Option<String>,
+    name: Option<String>,
+    fallbacks: Option<Vec<String>>,
+    prompt_budget_chars: Option<usize>,
+    verbatim_history_steps: Option<usize>,
+    shell_history_commands: Option<usize>,
+    context_compression_threshold: Option<f32>,
+    command_candidates: Option<u32>,
+    auth: Option<String>,
+    vertex_project: Option<String>,
+    vertex_location: Option<String>,
+    planning_timeout_secs: Option<u64>,
+    command_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AzureOpenAiSection {
+    resource: Option<String>,
+    deployment: Option<String>,
+    api_version: Option<String>,
+    planning_timeout_secs: Option<u64>,
+    command_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CacheSection {
+    enabled: Option<bool>,
+    disk: Option<bool>,
+    ttl_secs: Option<u64>,
+    capacity: Option<usize>,
+    allow_with_privacy_mode: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ClassifierSection {
+    kind: Option<String>,
+    confirm_below: Option<f32>,
+    default_kind: Option<String>,
+    answer_questions: Option<bool>,
+    extra_shell_commands: Option<Vec<String>>,
+    removed_shell_commands: Option<Vec<String>>,
+    extra_prompt_indicators: Option<Vec<String>>,
+    removed_prompt_indicators: Option<Vec<String>>,
+    shell_cutoff: Option<f32>,
+    prompt_cutoff: Option<f32>,
+    remote_timeout_ms: Option<u64>,
+    local_model_dir: Option<PathBuf>,
+    loading_wait_secs: Option<u64>,
+    loading_retries: Option<u32>,
+    python_script: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExecutorSection {
+    timeout_secs: Option<u64>,
+    max_output_size: Option<usize>,
+    risk_threshold: Option<f32>,
+    confirm_threshold: Option<f32>,
+    block_threshold: Option<f32>,
+    auto_approve: Option<bool>,
+    max_attempts_per_step: Option<usize>,
+    parallel_steps: Option<usize>,
+    timeout_from_estimates: Option<bool>,
+    allow_rules: Option<Vec<String>>,
+    shell: Option<String>,
+    sandbox: Option<String>,
+    sandbox_image: Option<String>,
+    sandbox_network: Option<bool>,
+    min_free_disk_mb: Option<u64>,
+    network_probe: Option<String>,
+    min_command_interval_ms: Option<u64>,
+    max_commands_per_conversation: Option<usize>,
+    breaker_failures: Option<usize>,
+    breaker_window_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RetentionSection {
+    session_retention_days: Option<u32>,
+    conversation_retention_days: Option<u32>,
+    max_sessions: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StorageSection {
+    data_dir: Option<PathBuf>,
+    encryption: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ToolsSection {
+    tracked: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HistorySection {
+    limit: Option<usize>,
+    full_output: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ArtifactsSection {
+    enabled: Option<bool>,
+    max_depth: Option<usize>,
+    max_entries: Option<usize>,
+    collapse_threshold: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct JobsSection {
+    kill_on_exit: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FollowUpSection {
+    window_secs: Option<u64>,
+    max_depth: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AuditSection {
+    enabled: Option<bool>,
+    max_file_mb: Option<u64>,
+    max_files: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MetricsSection {
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EmbeddingsSection {
+    provider: Option<String>,
+    model: Option<String>,
+    max_vectors: Option<usize>,
+    max_age_days: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OutputSection {
+    sanitize: Option<bool>,
+    keep_raw: Option<bool>,
+    redact_secrets: Option<bool>,
+    secret_allowlist: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EnvironmentSection {
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ContextFilesSection {
+    manifests: Option<Vec<String>>,
+    refused: Option<Vec<String>>,
+    max_file_bytes: Option<u64>,
+    budget_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DebugSection {
+    record_prompts: Option<bool>,
+}
+
+impl Default for ParsecConfig {
+    fn default() -> Self {
+        let sources = KNOWN_KEYS
+            .iter()
+            .map(|key| (*key, ConfigSource::Default))
+            .collect();
+
+        Self {
+            provider: "google-ai".to_string(),
+            model: "gemini-1.5-flash".to_string(),
+            model_fallbacks: Vec::new(),
+            prompt_budget_chars: PromptBudget::default().max_chars,
+            verbatim_history_steps: PromptBudget::default().verbatim_steps,
+            shell_history_commands: PromptBudget::default().shell_history.max_commands,
+            context_compression_threshold: SessionSettings::default().context_compression_threshold,
+            command_candidates: 1,
+            model_auth: "api_key".to_string(),
+            vertex_project: String::new(),
+            vertex_location: "us-central1".to_string(),
+            planning_timeout_secs: 45,
+            command_timeout_secs: 30,
+            azure_resource: String::new(),
+            azure_deployment: String::new(),
+            azure_api_version: String::new(),
+            azure_planning_timeout_secs: 45,
+            azure_command_timeout_secs: 30,
+            cache_enabled: true,
+            cache_disk: false,
+            cache_ttl_secs: 24 * 60 * 60,
+            cache_capacity: 256,
+            cache_allow_with_privacy_mode: false,
+            classifier: "heuristic".to_string(),
+            classifier_confirm_below: 0.6,
+            classifier_default_kind: "prompt".to_string(),
+            answer_questions: true,
+            extra_shell_commands: Vec::new(),
+            removed_shell_commands: Vec::new(),
+            extra_prompt_indicators: Vec::new(),
+            removed_prompt_indicators: Vec::new(),
+            shell_cutoff: 0.85,
+            prompt_cutoff: 0.85,
+            remote_timeout_ms: 1500,
+            local_model_dir: FileSessionStore::default_data_dir()
+                .join("models")
+                .join("classifier"),
+            loading_wait_secs: 10,
+            loading_retries: 2,
+            python_script: None,
+            executor_timeout_secs: 300,
+            max_output_size: 64 * 1024,
+            risk_threshold: 0.7,
+            confirm_threshold: DEFAULT_CONFIRM_THRESHOLD,
+            block_threshold: DEFAULT_BLOCK_THRESHOLD,
+            auto_approve: false,
+            max_attempts_per_step: default_max_attempts_per_step(),
+            parallel_steps: 1,
+            timeout_from_estimates: false,
+            allowed_rules: Vec::new(),
+            shell: "sh".to_string(),
+            sandbox: "none".to_string(),
+            sandbox_image: "debian:stable-slim".to_string(),
+            sandbox_network: false,
+            min_free_disk_mb: 100,
+            network_probe: "github.com:443".to_string(),
+            rate_limits: RateLimits::default(),
+            retention: RetentionPolicy {
+                session_retention_days: 30,
+                conversation_retention_days: 30,
+                max_sessions: None,
+            },
+            data_dir: FileSessionStore::default_data_dir(),
+            storage_encryption: "none".to_string(),
+            tracked_tools: default_tracked_tools(),
+            history_limit: default_history_limit(),
+            history_full_output: default_history_full_output(),
+            artifacts_enabled: true,
+            artifacts_max_depth: SnapshotOptions::default().max_depth,
+            artifacts_max_entries: SnapshotOptions::default().max_entries,
+            artifacts_collapse_threshold: SnapshotOptions::default().collapse_threshold,
+            jobs_kill_on_exit: true,
+            follow_up_window_secs: 600,
+            follow_up_max_depth: 3,
+            audit_enabled: false,
+            audit_max_file_mb: 10,
+            audit_max_files: 0,
+            metrics_enabled: true,
+            embeddings_provider: "none".to_string(),
+            embeddings_model: "text-embedding-004".to_string(),
+            embeddings_max_vectors: 1_000,
+            embeddings_max_age_days: 30,
+            output_sanitize: true,
+            output_keep_raw: false,
+            output_redact_secrets: true,
+            output_secret_allowlist: Vec::new(),
+            environment_allow: Vec::new(),
+            environment_deny: Vec::new(),
+            context_manifests: context_files::DEFAULT_MANIFESTS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            context_refused: context_files::DEFAULT_REFUSED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            context_max_file_bytes: context_files::DEFAULT_MAX_FILE_BYTES,
+            context_budget_bytes: context_files::DEFAULT_BUDGET_BYTES,
+            record_prompts: false,
+            sources,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl ParsecConfig {
+    /// `$XDG_CONFIG_HOME/parsec/config.toml`, falling back to `~/.config/parsec/config.toml`.
+    pub fn user_config_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("parsec").join("config.toml"))
+    }
+
+    /// Prompt template overrides, next to the user config file.
+    pub fn user_prompts_dir() -> Option<PathBuf> {
+        Self::user_config_path().and_then(|path| path.parent().map(|dir| dir.join("prompts")))
+    }
+
+    pub fn project_config_path(working_dir: &Path) -> PathBuf {
+        working_dir.join(".parsec.toml")
+    }
+
+    /// Set `key` in the user config file, keeping the rest of the file and its comments.
+    /// `value` is read as TOML (`0.5`, `true`, `["a", "b"]`), or else as a string.
+    /// Returns the file written.
+    pub fn set_user_value(key: &str, value: &str) -> Result<PathBuf, anyhow::Error> {
+        let Some((section, name)) = key.split_once('.').filter(|_| KNOWN_KEYS.contains(&key))
+        else {
+            return Err(anyhow::anyhow!(
+                "Unknown config key `{}`; `parsec config show` lists them",
+                key
+            ));
+        };
+        let path = Self::user_config_path()
+            .ok_or_else(|| anyhow::anyhow!("No user config directory; set HOME"))?;
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(anyhow::anyhow!("{}: {}", path.display(), e)),
+        };
+        let mut document: toml_edit::DocumentMut = text
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+        let parsed = format!("value = {}", value)
+            .parse::<toml_edit::DocumentMut>()
+            .ok()
+            .and_then(|parsed| {
+                parsed
+                    .get("value")
+                    .and_then(|item| item.as_value().cloned())
+            });
+        let value = parsed.unwrap_or_else(|| value.into());
+        if !document.contains_key(section) {
+            document[section] = toml_edit::table();
+        }
+        document[section][name] = toml_edit::value(value);
+
+        let text = document.to_string();
+        toml::from_str::<FileConfig>(&text).map_err(|e| anyhow::anyhow!("{}: {}", key, e))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, text)?;
+        Ok(path)
+    }
+
+    /// Load and merge the user and project config files, then apply CLI overrides.
+    pub fn load(working_dir: &Path, cli: CliOverrides) -> Result<Self, anyhow::Error> {
+        Self::load_from(Self::user_config_path().as_deref(), working_dir, cli)
+    }
+
+    /// `load` with the user config file at `user_config`.
+    fn load_from(
+        user_config: Option<&Path>,
+        working_dir: &Path,
+        cli: CliOverrides,
+    ) -> Result<Self, anyhow::Error> {
+        let mut config = Self::default();
+
+        if let Some(path) = user_config {
+            config.merge_file(path, ConfigSource::User(path.to_path_buf()))?;
+        }
+        let project = Self::project_config_path(working_dir);
+        config.merge_file(&project, ConfigSource::Project(project.clone()))?;
+
+        config.apply_cli(cli);
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, path: &Path, source: ConfigSource) -> Result<(), anyhow::Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(anyhow::anyhow!("{}: {}", path.display(), e)),
+        };
+
+        // Type errors carry the line and column from the TOML parser
+        let mut file: FileConfig =
+            toml::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+        if let Ok(mut table) = text.parse::<toml::Table>() {
+            for key in unknown_keys(&table) {
+                self.warnings.push(format!(
+                    "{}: unknown config key `{}` ignored",
+                    path.display(),
+                    key
+                ));
+            }
+            if matches!(source, ConfigSource::Project(_)) {
+                let mut removed = false;
+                for key in USER_ONLY_KEYS {
+                    if remove_key(&mut table, key) {
+                        removed = true;
+                        self.warnings.push(format!(
+                            "{}: `{}` is only read from the user config or the command line; ignored",
+                            path.display(),
+                            key
+                        ));
+                    }
+                }
+                if removed {
+                    file = table
+                        .try_into()
+                        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                }
+            }
+        }
+
+        self.merge(file, source);
+        Ok(())
+    }
+
+    fn merge(&mut self, file: FileConfig, source: ConfigSource) {
+        let mut set = |key: &'static str| {
+            self.sources.insert(key, source.clone());
+        };
+
+        let model = file.model.unwrap_or_default();
+        if let Some(provider) = model.provider {
+            self.provider = provider;
+            set("model.provider");
+        }
+        if let Some(name) = model.name {
+            self.model = name;
+            set("model.name");
+        }
+        if let Some(fallbacks) = model.fallbacks {
+            self.model_fallbacks = fallbacks;
+            set("model.fallbacks");
+        }
+        if let Some(chars) = model.prompt_budget_chars {
+            self.prompt_budget_chars = chars;
+            set("model.prompt_budget_chars");
+        }
+        if let Some(steps) = model.verbatim_history_steps {
+            self.verbatim_history_steps = steps;
+            set("model.verbatim_history_steps");
+        }
+        if let Some(commands) = model.shell_history_commands {
+            self.shell_history_commands = commands;
+            set("model.shell_history_commands");
+        }
+        if let Some(threshold) = model.context_compression_threshold {
+            self.context_compression_threshold = threshold;
+            set("model.context_compression_threshold");
+        }
+        if let Some(candidates) = model.command_candidates {
+            self.command_candidates = candidates;
+            set("model.command_candidates");
+        }
+        if let Some(auth) = model.auth {
+            self.model_auth = auth;
+            set("model.auth");
+        }
+        if let Some(project) = model.vertex_project {
+            self.vertex_project = project;
+            set("model.vertex_project");
+        }
+        if let Some(location) = model.vertex_location {
+            self.vertex_location = location;
+            set("model.vertex_location");
+        }
+        if let Some(secs) = model.planning_timeout_secs {
+            self.planning_timeout_secs = secs;
+            set("model.planning_timeout_secs");
+        }
+        if let Some(secs) = model.command_timeout_secs {
+            self.command_timeout_secs = secs;
+            set("model.command_timeout_secs");
+        }
+
+        let azure = file.azure_openai.unwrap_or_default();
+        if let Some(resource) = azure.resource {
+            self.azure_resource = resource;
+            set("azure_openai.resource");
+        }
+        if let Some(deployment) = azure.deployment {
+            self.azure_deployment = deployment;
+            set("azure_openai.deployment");
+        }
+        if let Some(version) = azure.api_version {
+            self.azure_api_version = version;
+            set("azure_openai.api_version");
+        }
+        if let Some(secs) = azure.planning_timeout_secs {
+            self.azure_planning_timeout_secs = secs;
+            set("azure_openai.planning_timeout_secs");
+        }
+        if let Some(secs) = azure.command_timeout_secs {
+            self.azure_command_timeout_secs = secs;
+            set("azure_openai.command_timeout_secs");
+        }
+
+        let cache = file.cache.unwrap_or_default();
+        if let Some(enabled) = cache.enabled {
+            self.cache_enabled = enabled;
+            set("cache.enabled");
+        }
+        if let Some(disk) = cache.disk {
+            self.cache_disk = disk;
+            set("cache.disk");
+        }
+        if let Some(secs) = cache.ttl_secs {
+            self.cache_ttl_secs = secs;
+            set("cache.ttl_secs");
+        }
+        if let Some(capacity) = cache.capacity {
+            self.cache_capacity = capacity;
+            set("cache.capacity");
+        }
+        if let Some(allow) = cache.allow_with_privacy_mode {
+            self.cache_allow_with_privacy_mode = allow;
+            set("cache.allow_with_privacy_mode");
+        }
+
+        let classifier = file.classifier.unwrap_or_default();
+        if let Some(kind) = classifier.kind {
+            self.classifier = kind;
+            set("classifier.kind");
+        }
+        if let Some(confirm_below) = classifier.confirm_below {
+            self.classifier_confirm_below = confirm_below;
+            set("classifier.confirm_below");
+        }
+        if let Some(kind) = classifier.default_kind {
+            self.classifier_default_kind = kind;
+            set("classifier.default_kind");
+        }
+        if let Some(answer_questions) = classifier.answer_questions {
+            self.answer_questions = answer_questions;
+            set("classifier.answer_questions");
+        }
+        if let Some(commands) = classifier.extra_shell_commands {
+            self.extra_shell_commands = commands;
+            set("classifier.extra_shell_commands");
+        }
+        if let Some(commands) = classifier.removed_shell_commands {
+            self.removed_shell_commands = commands;
+            set("classifier.removed_shell_commands");
+        }
+        if let Some(indicators) = classifier.extra_prompt_indicators {
+            self.extra_prompt_indicators = indicators;
+            set("classifier.extra_prompt_indicators");
+        }
+        if let Some(indicators) = classifier.removed_prompt_indicators {
+            self.removed_prompt_indicators = indicators;
+            set("classifier.removed_prompt_indicators");
+        }
+        if let Some(cutoff) = classifier.shell_cutoff {
+            self.shell_cutoff = cutoff;
+            set("classifier.shell_cutoff");
+        }
+        if let Some(cutoff) = classifier.prompt_cutoff {
+            self.prompt_cutoff = cutoff;
+            set("classifier.prompt_cutoff");
+        }
+        if let Some(timeout) = classifier.remote_timeout_ms {
+            self.remote_timeout_ms = timeout;
+            set("classifier.remote_timeout_ms");
+        }
+        if let Some(dir) = classifier.local_model_dir {
+            self.local_model_dir = expand_home(&dir);
+            set("classifier.local_model_dir");
+        }
+        if let Some(secs) = classifier.loading_wait_secs {
+            self.loading_wait_secs = secs;
+            set("classifier.loading_wait_secs");
+        }
+        if let Some(retries) = classifier.loading_retries {
+            self.loading_retries = retries;
+            set("classifier.loading_retries");
+        }
+        if let Some(script) = classifier.python_script {
+            self.python_script = Some(expand_home(&script));
+            set("classifier.python_script");
+        }
+
+        let executor = file.executor.unwrap_or_default();
+        if let Some(timeout) = executor.timeout_secs {
+            self.executor_timeout_secs = timeout;
+            set("executor.timeout_secs");
+        }
+        if let Some(size) = executor.max_output_size {
+            self.max_output_size = size;
+            set("executor.max_output_size");
+        }
+        if let Some(threshold) = executor.risk_threshold {
+            self.risk_threshold = threshold;
+            set("executor.risk_threshold");
+        }
+        if let Some(threshold) = executor.confirm_threshold {
+            self.confirm_threshold = threshold;
+            set("executor.confirm_threshold");
+        }
+        if let Some(threshold) = executor.block_threshold {
+            self.block_threshold = threshold;
+            set("executor.block_threshold");
+        }
+        if let Some(auto_approve) = executor.auto_approve {
+            self.auto_approve = auto_approve;
+            set("executor.auto_approve");
+        }
+        if let Some(max) = executor.max_attempts_per_step {
+            self.max_attempts_per_step = max;
+            set("executor.max_attempts_per_step");
+        }
+        if let Some(parallel_steps) = executor.parallel_steps {
+            self.parallel_steps = parallel_steps.max(1);
+            set("executor.parallel_steps");
+        }
+        if let Some(timeout_from_estimates) = executor.timeout_from_estimates {
+            self.timeout_from_estimates = timeout_from_estimates;
+            set("executor.timeout_from_estimates");
+        }
+        if let Some(rules) = executor.allow_rules {
+            self.allowed_rules = rules;
+            set("executor.allow_rules");
+        }
+        if let Some(shell) = executor.shell {
+            self.shell = shell;
+            set("executor.shell");
+        }
+        if let Some(sandbox) = executor.sandbox {
+            self.sandbox = sandbox;
+            set("executor.sandbox");
+        }
+        if let Some(image) = executor.sandbox_image {
+            self.sandbox_image = image;
+            set("executor.sandbox_image");
+        }
+        if let Some(network) = executor.sandbox_network {
+            self.sandbox_network = network;
+            set("executor.sandbox_network");
+        }
+        if let Some(mb) = executor.min_free_disk_mb {
+            self.min_free_disk_mb = mb;
+            set("executor.min_free_disk_mb");
+        }
+        if let Some(probe) = executor.network_probe {
+            self.network_probe = probe;
+            set("executor.network_probe");
+        }
+        if let Some(ms) = executor.min_command_interval_ms {
+            self.rate_limits.min_command_interval = Duration::from_millis(ms);
+            set("executor.min_command_interval_ms");
+        }
+        if let Some(max) = executor.max_commands_per_conversation {
+            self.rate_limits.max_commands_per_conversation = Some(max).filter(|&max| max > 0);
+            set("executor.max_commands_per_conversation");
+        }
+        if let Some(failures) = executor.breaker_failures {
+            self.rate_limits.breaker_failures = failures;
+            set("executor.breaker_failures");
+        }
+        if let Some(secs) = executor.breaker_window_secs {
+            self.rate_limits.failure_window = Duration::from_secs(secs);
+            set("executor.breaker_window_secs");
+        }
+
+        let retention = file.retention.unwrap_or_default();
+        if let Some(days) = retention.session_retention_days {
+            self.retention.session_retention_days = days;
+            set("retention.session_retention_days");
+        }
+        if let Some(days) = retention.conversation_retention_days {
+            self.retention.conversation_retention_days = days;
+            set("retention.conversation_retention_days");
+        }
+        if let Some(max) = retention.max_sessions {
+            self.retention.max_sessions = Some(max);
+            set("retention.max_sessions");
+        }
+
+        let storage = file.storage.unwrap_or_default();
+        if let Some(dir) = storage.data_dir {
+            self.data_dir = expand_home(&dir);
+            set("storage.data_dir");
+        }
+        if let Some(encryption) = storage.encryption {
+            self.storage_encryption = encryption;
+            set("storage.encryption");
+        }
+
+        if let Some(tracked) = file.tools.unwrap_or_default().tracked {
+            self.tracked_tools = tracked;
+            set("tools.tracked");
+        }
+
+        let history = file.history.unwrap_or_default();
+        if let Some(limit) = history.limit {
+            self.history_limit = limit;
+            set("history.limit");
+        }
+        if let Some(full_output) = history.full_output {
+            self.history_full_output = full_output;
+            set("history.full_output");
+        }
+
+        let artifacts = file.artifacts.unwrap_or_default();
+        if let Some(enabled) = artifacts.enabled {
+            self.artifacts_enabled = enabled;
+            set("artifacts.enabled");
+        }
+        if let Some(depth) = artifacts.max_depth {
+            self.artifacts_max_depth = depth;
+            set("artifacts.max_depth");
+        }
+        if let Some(entries) = artifacts.max_entries {
+            self.artifacts_max_entries = entries;
+            set("artifacts.max_entries");
+        }
+        if let Some(threshold) = artifacts.collapse_threshold {
+            self.artifacts_collapse_threshold = threshold;
+            set("artifacts.collapse_threshold");
+        }
+
+        if let Some(kill) = file.jobs.unwrap_or_default().kill_on_exit {
+            self.jobs_kill_on_exit = kill;
+            set("jobs.kill_on_exit");
+        }
+
+        let followup = file.followup.unwrap_or_default();
+        if let Some(window_secs) = followup.window_secs {
+            self.follow_up_window_secs = window_secs;
+            set("followup.window_secs");
+        }
+        if let Some(max_depth) = followup.max_depth {
+            self.follow_up_max_depth = max_depth.max(1);
+            set("followup.max_depth");
+        }
+
+        let audit = file.audit.unwrap_or_default();
+        if let Some(enabled) = audit.enabled {
+            self.audit_enabled = enabled;
+            set("audit.enabled");
+        }
+        if let Some(max_file_mb) = audit.max_file_mb {
+            self.audit_max_file_mb = max_file_mb;
+            set("audit.max_file_mb");
+        }
+        if let Some(max_files) = audit.max_files {
+            self.audit_max_files = max_files;
+            set("audit.max_files");
+        }
+
+        if let Some(enabled) = file.metrics.unwrap_or_default().enabled {
+            self.metrics_enabled = enabled;
+            set("metrics.enabled");
+        }
+
+        let embeddings = file.embeddings.unwrap_or_default();
+        if let Some(provider) = embeddings.provider {
+            self.embeddings_provider = provider;
+            set("embeddings.provider");
+        }
+        if let Some(model) = embeddings.model {
+            self.embeddings_model = model;
+            set("embeddings.model");
+        }
+        if let Some(max) = embeddings.max_vectors {
+            self.embeddings_max_vectors = max;
+            set("embeddings.max_vectors");
+        }
+        if let Some(days) = embeddings.max_age_days {
+            self.embeddings_max_age_days = days;
+            set("embeddings.max_age_days");
+        }
+
+        let output = file.output.unwrap_or_default();
+        if let Some(sanitize) = output.sanitize {
+            self.output_sanitize = sanitize;
+            set("output.sanitize");
+        }
+        if let Some(keep_raw) = output.keep_raw {
+            self.output_keep_raw = keep_raw;
+            set("output.keep_raw");
+        }
+        if let Some(redact) = output.redact_secrets {
+            self.output_redact_secrets = redact;
+            set("output.redact_secrets");
+        }
+        if let Some(allowlist) = output.secret_allowlist {
+            self.output_secret_allowlist = allowlist;
+            set("output.secret_allowlist");
+        }
+
+        let environment = file.environment.unwrap_or_default();
+        if let Some(allow) = environment.allow {
+            self.environment_allow = allow;
+            set("environment.allow");
+        }
+        if let Some(deny) = environment.deny {
+            self.environment_deny = deny;
+            set("environment.deny");
+        }
+
+        let context_files = file.context_files.unwrap_or_default();
+        if let Some(manifests) = context_files.manifests {
+            self.context_manifests = manifests;
+            set("context_files.manifests");
+        }
+        if let Some(refused) = context_files.refused {
+            self.context_refused = refused;
+            set("context_files.refused");
+        }
+        if let Some(bytes) = context_files.max_file_bytes {
+            self.context_max_file_bytes = bytes;
+            set("context_files.max_file_bytes");
+        }
+        if let Some(bytes) = context_files.budget_bytes {
+            self.context_budget_bytes = bytes;
+            set("context_files.budget_bytes");
+        }
+
+        if let Some(record) = file.debug.unwrap_or_default().record_prompts {
+            self.record_prompts = record;
+            set("debug.record_prompts");
+        }
+    }
+
+    /// How long `provider` may take to plan and to generate a step's commands, `None`
+    /// where its setting is 0.
+    pub fn model_timeouts(&self, provider: &str) -> (Option<Duration>, Option<Duration>) {
+        let (planning, commands) = match provider {
+            "azure-openai" => (
+                self.azure_planning_timeout_secs,
+                self.azure_command_timeout_secs,
+            ),
+            _ => (self.planning_timeout_secs, self.command_timeout_secs),
+        };
+        let timeout = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        (timeout(planning), timeout(commands))
+    }
+
+    /// The redactor for captured output and prompts, or `None` with redaction off.
+    pub fn secret_redactor(&self) -> Option<SecretRedactor> {
+        self.output_redact_secrets
+            .then(|| SecretRedactor::new().with_allowlist(self.output_secret_allowlist.clone()))
+    }
+
+    /// The filter for the environment variables a new session records.
+    pub fn env_filter(&self) -> EnvFilter {
+        EnvFilter::new()
+            .with_allowed(self.environment_allow.clone())
+            .with_denied(self.environment_deny.clone())
+    }
+
+    /// The files attached to prompts besides those they name, before `--context-file`.
+    pub fn context_files(&self) -> ContextFiles {
+        ContextFiles::new()
+            .with_manifests(self.context_manifests.clone())
+            .with_refused(self.context_refused.clone())
+            .with_max_file_bytes(self.context_max_file_bytes)
+            .with_budget_bytes(self.context_budget_bytes)
+    }
+
+    fn apply_cli(&mut self, cli: CliOverrides) {
+        if let Some(classifier) = cli.classifier {
+            self.classifier = classifier;
+            self.sources.insert("classifier.kind", ConfigSource::Cli);
+        }
+        if let Some(script) = cli.python_script {
+            self.python_script = Some(script);
+            self.sources
+                .insert("classifier.python_script", ConfigSource::Cli);
+        }
+        if let Some(auto_approve) = cli.auto_approve {
+            self.auto_approve = auto_approve;
+            self.sources
+                .insert("executor.auto_approve", ConfigSource::Cli);
+        }
+        if let Some(dir) = cli.data_dir {
+            self.data_dir = dir;
+            self.sources.insert("storage.data_dir", ConfigSource::Cli);
+        }
+        if let Some(enabled) = cli.cache_enabled {
+            self.cache_enabled = enabled;
+            self.sources.insert("cache.enabled", ConfigSource::Cli);
+        }
+        if let Some(record) = cli.record_prompts {
+            self.record_prompts = record;
+            self.sources
+                .insert("debug.record_prompts", ConfigSource::Cli);
+        }
+    }
+
+    /// Effective values as `(key, rendered value, source)`, in key order.
+    pub fn entries(&self) -> Vec<(&'static str, String, ConfigSource)> {
+        let max_sessions = self
+            .retention
+            .max_sessions
+            .map(|max| max.to_string())
+            .unwrap_or_else(|| "unlimited".to_string());
+        let python_script = self
+            .python_script
+            .as_ref()
+            .map(|script| format!("{:?}", script.display().to_string()))
+            .unwrap_or_else(|| "unset".to_string());
+
+        let values: [(&'static str, String); 92] = [
+            ("model.provider", format!("{:?}", self.provider)),
+            ("model.name", format!("{:?}", self.model)),
+            ("model.fallbacks", format!("{:?}", self.model_fallbacks)),
+            (
+                "model.prompt_budget_chars",
+                self.prompt_budget_chars.to_string(),
+            ),
+            (
+                "model.verbatim_history_steps",
+                self.verbatim_history_steps.to_string(),
+            ),
+            (
+                "model.shell_history_commands",
+                self.shell_history_commands.to_string(),
+            ),
+            (
+                "model.context_compression_threshold",
+                self.context_compression_threshold.to_string(),
+            ),
+            (
+                "model.command_candidates",
+                self.command_candidates.to_string(),
+            ),
+            ("model.auth", format!("{:?}", self.model_auth)),
+            ("model.vertex_project", format!("{:?}", self.vertex_project)),
+            (
+                "model.vertex_location",
+                format!("{:?}", self.vertex_location),
+            ),
+            (
+                "model.planning_timeout_secs",
+                self.planning_timeout_secs.to_string(),
+            ),
+            (
+                "model.command_timeout_secs",
+                self.command_timeout_secs.to_string(),
+            ),
+            (
+                "azure_openai.resource",
+                format!("{:?}", self.azure_resource),
+            ),
+            (
+                "azure_openai.deployment",
+                format!("{:?}", self.azure_deployment),
+            ),
+            (
+                "azure_openai.api_version",
+                format!("{:?}", self.azure_api_version),
+            ),
+            (
+                "azure_openai.planning_timeout_secs",
+                self.azure_planning_timeout_secs.to_string(),
+            ),
+            (
+                "azure_openai.command_timeout_secs",
+                self.azure_command_timeout_secs.to_string(),
+            ),
+            ("cache.enabled", self.cache_enabled.to_string()),
+            ("cache.disk", self.cache_disk.to_string()),
+            ("cache.ttl_secs", self.cache_ttl_secs.to_string()),
+            ("cache.capacity", self.cache_capacity.to_string()),
+            (
+                "cache.allow_with_privacy_mode",
+                self.cache_allow_with_privacy_mode.to_string(),
+            ),
+            ("classifier.kind", format!("{:?}", self.classifier)),
+            (
+                "classifier.confirm_below",
+                self.classifier_confirm_below.to_string(),
+            ),
+            (
+                "classifier.default_kind",
+                format!("{:?}", self.classifier_default_kind),
+            ),
+            (
+                "classifier.answer_questions",
+                self.answer_questions.to_string(),
+            ),
+            (
+                "classifier.extra_shell_commands",
+                format!("{:?}", self.extra_shell_commands),
+            ),
+            (
+                "classifier.removed_shell_commands",
+                format!("{:?}", self.removed_shell_commands),
+            ),
+            (
+                "classifier.extra_prompt_indicators",
+                format!("{:?}", self.extra_prompt_indicators),
+            ),
+            (
+                "classifier.removed_prompt_indicators",
+                format!("{:?}", self.removed_prompt_indicators),
+            ),
+            ("classifier.shell_cutoff", self.shell_cutoff.to_string()),
+            ("classifier.prompt_cutoff", self.prompt_cutoff.to_string()),
+            (
+                "classifier.remote_timeout_ms",
+                self.remote_timeout_ms.to_string(),
+            ),
+            (
+                "classifier.local_model_dir",
+                format!("{:?}", self.local_model_dir.display().to_string()),
+            ),
+            (
+                "classifier.loading_wait_secs",
+                self.loading_wait_secs.to_string(),
+            ),
+            (
+                "classifier.loading_retries",
+                self.loading_retries.to_string(),
+            ),
+            ("classifier.python_script", python_script),
+            (
+                "executor.timeout_secs",
+                self.executor_timeout_secs.to_string(),
+            ),
+            ("executor.max_output_size", self.max_output_size.to_string()),
+            ("executor.risk_threshold", self.risk_threshold.to_string()),
+            (
+                "executor.confirm_threshold",
+                self.confirm_threshold.to_string(),
+            ),
+            ("executor.block_threshold", self.block_threshold.to_string()),
+            ("executor.auto_approve", self.auto_approve.to_string()),
+            (
+                "executor.max_attempts_per_step",
+                self.max_attempts_per_step.to_string(),
+            ),
+            ("executor.parallel_steps", self.parallel_steps.to_string()),
+            (
+                "executor.timeout_from_estimates",
+                self.timeout_from_estimates.to_string(),
+            ),
+            ("executor.allow_rules", format!("{:?}", self.allowed_rules)),
+            ("executor.shell", format!("{:?}", self.shell)),
+            ("executor.sandbox", format!("{:?}", self.sandbox)),
+            (
+                "executor.sandbox_image",
+                format!("{:?}", self.sandbox_image),
+            ),
+            ("executor.sandbox_network", self.sandbox_network.to_string()),
+            (
+                "executor.min_free_disk_mb",
+                self.min_free_disk_mb.to_string(),
+            ),
+            (
+                "executor.network_probe",
+                format!("{:?}", self.network_probe),
+            ),
+            (
+                "executor.min_command_interval_ms",
+                self.rate_limits
+                    .min_command_interval
+                    .as_millis()
+                    .to_string(),
+            ),
+            (
+                "executor.max_commands_per_conversation",
+                self.rate_limits
+                    .max_commands_per_conversation
+                    .unwrap_or(0)
+                    .to_string(),
+            ),
+            (
+                "executor.breaker_failures",
+                self.rate_limits.breaker_failures.to_string(),
+            ),
+            (
+                "executor.breaker_window_secs",
+                self.rate_limits.failure_window.as_secs().to_string(),
+            ),
+            (
+                "retention.session_retention_days",
+                self.retention.session_retention_days.to_string(),
+            ),
+            (
+                "retention.conversation_retention_days",
+                self.retention.conversation_retention_days.to_string(),
+            ),
+            ("retention.max_sessions", max_sessions),
+            (
+                "storage.data_dir",
+                format!("{:?}", self.data_dir.display().to_string()),
+            ),
+            (
+                "storage.encryption",
+                format!("{:?}", self.storage_encryption),
+            ),
+            ("tools.tracked", format!("{:?}", self.tracked_tools)),
+            ("history.limit", self.history_limit.to_string()),
+            ("history.full_output", self.history_full_output.to_string()),
+            ("artifacts.enabled", self.artifacts_enabled.to_string()),
+            ("artifacts.max_depth", self.artifacts_max_depth.to_string()),
+            (
+                "artifacts.max_entries",
+                self.artifacts_max_entries.to_string(),
+            ),
+            (
+                "artifacts.collapse_threshold",
+                self.artifacts_collapse_threshold.to_string(),
+            ),
+            ("jobs.kill_on_exit", self.jobs_kill_on_exit.to_string()),
+            (
+                "followup.window_secs",
+                self.follow_up_window_secs.to_string(),
+            ),
+            ("followup.max_depth", self.follow_up_max_depth.to_string()),
+            ("audit.enabled", self.audit_enabled.to_string()),
+            ("audit.max_file_mb", self.audit_max_file_mb.to_string()),
+            ("audit.max_files", self.audit_max_files.to_string()),
+            ("metrics.enabled", self.metrics_enabled.to_string()),
+            (
+                "embeddings.provider",
+                format!("{:?}", self.embeddings_provider),
+            ),
+            ("embeddings.model", format!("{:?}", self.embeddings_model)),
+            (
+                "embeddings.max_vectors",
+                self.embeddings_max_vectors.to_string(),
+            ),
+            (
+                "embeddings.max_age_days",
+                self.embeddings_max_age_days.to_string(),
+            ),
+            ("output.sanitize", self.output_sanitize.to_string()),
+            ("output.keep_raw", self.output_keep_raw.to_string()),
+            (
+                "output.redact_secrets",
+                self.output_redact_secrets.to_string(),
+            ),
+            (
+                "output.secret_allowlist",
+                format!("{:?}", self.output_secret_allowlist),
+            ),
+            ("environment.allow", format!("{:?}", self.environment_allow)),
+            ("environment.deny", format!("{:?}", self.environment_deny)),
+            (
+                "context_files.manifests",
+                format!("{:?}", self.context_manifests),
+            ),
+            (
+                "context_files.refused",
+                format!("{:?}", self.context_refused),
+            ),
+            (
+                "context_files.max_file_bytes",
+                self.context_max_file_bytes.to_string(),
+            ),
+            (
+                "context_files.budget_bytes",
+                self.context_budget_bytes.to_string(),
+            ),
+            ("debug.record_prompts", self.record_prompts.to_string()),
+        ];
+
+        values
+            .into_iter()
+            .map(|(key, value)| {
+                let source = self
+                    .sources
+                    .get(key)
+                    .cloned()
+                    .unwrap_or(ConfigSource::Default);
+                (key, value, source)
+            })
+            .collect()
+    }
+}
+
+fn unknown_keys(table: &toml::Table) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for (section, value) in table {
+        // Project tasks, read by `tasks::TaskBook`
+        if section == "tasks" {
+            continue;
+        }
+        match value.as_table() {
+            Some(entries) => {
+                for key in entries.keys() {
+                    let dotted = format!("{}.{}", section, key);
+                    if !KNOWN_KEYS.contains(&dotted.as_str()) {
+                        unknown.push(dotted);
+                    }
+                }
+            }
+            None => unknown.push(section.clone()),
+        }
+    }
+    unknown
+}
+
+/// Remove the dotted `key` from `table`, saying whether it was there.
+fn remove_key(table: &mut toml::Table, key: &str) -> bool {
+    let Some((section, name)) = key.split_once('.') else {
+        return false;
+    };
+    table
+        .get_mut(section)
+        .and_then(|section| section.as_table_mut())
+        .is_some_and(|section| section.remove(name).is_some())
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// A fresh directory with a user config of `user` and a project config of `project`,
+    /// returned as (user config path, working directory).
+    fn configs(user: &str, project: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("parsec-config-{}", Uuid::new_v4()));
+        let working_dir = dir.join("project");
+        std::fs::create_dir_all(&working_dir).unwrap();
+        let user_config = dir.join("config.toml");
+        std::fs::write(&user_config, user).unwrap();
+        std::fs::write(ParsecConfig::project_config_path(&working_dir), project).unwrap();
+        (user_config, working_dir)
+    }
+
+    #[test]
+    fn project_config_wins_over_user_config_and_cli_over_both() {
+        let (user, working_dir) = configs(
+            "[model]\nname = \"user-model\"\n[history]\nlimit = 7\n[cache]\nenabled = true\n",
+            "[model]\nname = \"project-model\"\n[cache]\nenabled = true\n",
+        );
+        let config = ParsecConfig::load_from(
+            Some(&user),
+            &working_dir,
+            CliOverrides {
+                cache_enabled: Some(false),
+                ..CliOverrides::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.model, "project-model");
+        assert!(matches!(
+            config.sources["model.name"],
+            ConfigSource::Project(_)
+        ));
+        assert_eq!(config.history_limit, 7);
+        assert!(matches!(
+            config.sources["history.limit"],
+            ConfigSource::User(_)
+        ));
+        assert!(!config.cache_enabled);
+        assert_eq!(config.sources["cache.enabled"], ConfigSource::Cli);
+        assert_eq!(config.sources["model.provider"], ConfigSource::Default);
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn project_config_cannot_loosen_safety_settings() {
+        let (user, working_dir) = configs(
+            "[executor]\nblock_threshold = 0.8\n[audit]\nenabled = true\n",
+            "[executor]\nauto_approve = true\nshell = \"/tmp/evil\"\nblock_threshold = 1.0\ntimeout_secs = 9\n\
+             [output]\nredact_secrets = false\n[audit]\nenabled = false\n",
+        );
+        let config =
+            ParsecConfig::load_from(Some(&user), &working_dir, CliOverrides::default()).unwrap();
+
+        assert!(!config.auto_approve);
+        assert_ne!(config.shell, "/tmp/evil");
+        assert_eq!(config.block_threshold, 0.8);
+        assert!(config.output_redact_secrets);
+        assert!(config.audit_enabled);
+        // Other keys of the same sections still apply
+        assert_eq!(config.executor_timeout_secs, 9);
+        assert_eq!(config.warnings.len(), 5);
+        assert!(config
+            .warnings
+            .iter()
+            .all(|warning| warning.contains("only read from the user config")));
+
+        // The user config and the command line still set them
+        let (user, working_dir) = configs("[executor]\nauto_approve = true\n", "");
+        let config =
+            ParsecConfig::load_from(Some(&user), &working_dir, CliOverrides::default()).unwrap();
+        assert!(config.auto_approve);
+    }
+
+    #[test]
+    fn unknown_keys_are_warned_about_and_type_errors_fail() {
+        let (user, working_dir) = configs("[model]\nnmae = \"typo\"\n[colours]\non = true\n", "");
+        let config =
+            ParsecConfig::load_from(Some(&user), &working_dir, CliOverrides::default()).unwrap();
+        assert_eq!(config.warnings.len(), 2);
+        assert!(config
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("unknown config key `model.nmae`")));
+        assert!(config
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("unknown config key `colours.on`")));
+
+        let (user, working_dir) = configs("[history]\nlimit = \"many\"\n", "");
+        let error = ParsecConfig::load_from(Some(&user), &working_dir, CliOverrides::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("config.toml"), "{}", error);
+        assert!(error.contains("line 2"), "{}", error);
+    }
+}
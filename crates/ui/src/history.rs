@@ -0,0 +1,228 @@
+use crate::input_history::InputEntry;
+use chrono::{DateTime, Utc};
+use parsec_core::search::SearchHit;
+use parsec_core::{ConversationContext, DirectCommandExecution};
+use regex::Regex;
+use std::path::PathBuf;
+
+/// One line of `history` output.
+pub struct HistoryEntry {
+    /// 1-based position in `Session::command_history`, the `N` in `!N`.
+    /// Workflow commands have none and cannot be re-run this way.
+    pub number: Option<usize>,
+    pub command: String,
+    pub executed_at: DateTime<Utc>,
+    pub exit_status: Option<i32>,
+    pub working_directory: PathBuf,
+    /// Name of the conversation a workflow command ran in.
+    pub conversation: Option<String>,
+}
+
+impl HistoryEntry {
+    /// `width` columns at most, cutting the command short with `…` if needed.
+    pub fn render(&self, width: usize) -> String {
+        let number = self
+            .number
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let exit_status = self
+            .exit_status
+            .map(|status| status.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let conversation = self
+            .conversation
+            .as_ref()
+            .map(|name| format!("[{}] ", name))
+            .unwrap_or_default();
+        let line = format!(
+            "{:>5}  {}  {:>4}  {}  {}{}",
+            number,
+            self.executed_at.format("%Y-%m-%d %H:%M:%S"),
+            exit_status,
+            self.working_directory.display(),
+            conversation,
+            self.command
+        );
+
+        fit(line, width)
+    }
+}
+
+pub fn direct_entries(history: &[DirectCommandExecution]) -> Vec<HistoryEntry> {
+    history
+        .iter()
+        .enumerate()
+        .map(|(i, execution)| HistoryEntry {
+            number: Some(i + 1),
+            command: execution.command.clone(),
+            executed_at: execution.executed_at,
+            exit_status: Some(execution.exit_status),
+            working_directory: execution.working_directory.clone(),
+            conversation: None,
+        })
+        .collect()
+}
+
+/// Every command a workflow step actually ran, in execution order.
+pub fn workflow_entries(conversation: &ConversationContext) -> Vec<HistoryEntry> {
+    conversation
+        .steps
+        .iter()
+        .flat_map(|step| {
+            step.command_attempts
+                .iter()
+                .filter(|attempt| attempt.executed)
+                .map(|attempt| HistoryEntry {
+                    number: None,
+                    command: attempt.candidate.command.clone(),
+                    executed_at: attempt.timestamp,
+                    exit_status: attempt.exit_status,
+                    working_directory: step.context_used.working_directory.clone(),
+                    conversation: Some(conversation.name.clone()),
+                })
+        })
+        .collect()
+}
+
+pub enum Filter {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Filter {
+    pub fn matches(&self, command: &str) -> bool {
+        match self {
+            Filter::Substring(text) => command.contains(text.as_str()),
+            Filter::Regex(regex) => regex.is_match(command),
+        }
+    }
+}
+
+/// `history [--all|--prompts] [<substring>|/<regex>/]`
+pub struct HistoryArgs {
+    /// Also list commands run inside workflows.
+    pub all: bool,
+    /// List the workflow requests typed in this session instead of commands.
+    pub prompts: bool,
+    pub filter: Option<Filter>,
+}
+
+impl HistoryArgs {
+    pub fn parse(args: &str) -> Result<Self, anyhow::Error> {
+        let mut all = false;
+        let mut prompts = false;
+        let mut words = Vec::new();
+        for word in args.split_whitespace() {
+            match word {
+                "--all" | "-a" => all = true,
+                "--prompts" | "-p" => prompts = true,
+                _ => words.push(word),
+            }
+        }
+
+        let pattern = words.join(" ");
+        let filter = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(regex) => Some(Filter::Regex(
+                Regex::new(regex).map_err(|e| anyhow::anyhow!("Invalid pattern: {}", e))?,
+            )),
+            None if pattern.is_empty() => None,
+            None => Some(Filter::Substring(pattern)),
+        };
+
+        if all && prompts {
+            anyhow::bail!("--all lists commands; it cannot be combined with --prompts");
+        }
+        Ok(Self {
+            all,
+            prompts,
+            filter,
+        })
+    }
+}
+
+/// One line of `history --prompts` output: the request typed as `number`, on one line.
+pub fn render_input(number: usize, entry: &InputEntry, width: usize) -> String {
+    let input = entry.input.lines().collect::<Vec<_>>().join(" ↵ ");
+    fit(
+        format!(
+            "{:>5}  {}  {}",
+            number,
+            entry.entered_at.format("%Y-%m-%d %H:%M:%S"),
+            input
+        ),
+        width,
+    )
+}
+
+/// `!!` is the previous direct command and `!N` the Nth; `None` when `input` is
+/// neither, so `!<command>` keeps forcing shell handling.
+pub fn expand(
+    input: &str,
+    history: &[DirectCommandExecution],
+) -> Option<Result<String, anyhow::Error>> {
+    let reference = input.strip_prefix('!')?;
+    let execution = if reference == "!" {
+        history
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("No commands in history yet"))
+    } else if !reference.is_empty() && reference.bytes().all(|b| b.is_ascii_digit()) {
+        reference
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| history.get(i))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No command {} in history ({} recorded)",
+                    reference,
+                    history.len()
+                )
+            })
+    } else {
+        return None;
+    };
+    Some(execution.map(|execution| execution.command.clone()))
+}
+
+/// Two lines of `search` output for `hit`, listed as `number`: where and when it was
+/// recorded, then its snippet cut to `width` columns.
+pub fn render_search_hit(number: usize, hit: &SearchHit, width: usize) -> String {
+    let location = match (&hit.conversation_id, hit.step_index) {
+        (Some(conversation_id), Some(index)) => {
+            format!("conversation {} step {}", conversation_id, index + 1)
+        }
+        (Some(conversation_id), None) => format!("conversation {}", conversation_id),
+        (None, _) => format!("session {}", hit.session_id),
+    };
+    let exit_status = hit
+        .exit_status
+        .map(|status| format!("  exit {}", status))
+        .unwrap_or_default();
+    format!(
+        "{:>5}  {}  {}  {}{}\n{}",
+        number,
+        hit.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        hit.kind.as_str(),
+        location,
+        exit_status,
+        fit(format!("       {}", hit.snippet), width)
+    )
+}
+
+/// `line` cut short with `…` if it is wider than `width` columns.
+fn fit(line: String, width: usize) -> String {
+    match line.char_indices().nth(width.saturating_sub(1)) {
+        Some((end, _)) if line.chars().count() > width => format!("{}…", &line[..end]),
+        _ => line,
+    }
+}
+
+/// Columns available for one line of output; 80 when stdout is not a terminal.
+pub fn terminal_width() -> usize {
+    crossterm::terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .ok()
+        .filter(|&columns| columns > 0)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(80)
+}
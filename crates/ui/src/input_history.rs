@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use parsec_core::secrets::SecretRedactor;
+use parsec_core::{InputKind, Session};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Directory under the data dir; each session's own files are in `sessions/<id>`.
+pub const INPUT_HISTORY_DIR: &str = "input-history";
+/// Entries recalled across sessions per namespace; older ones are dropped on load.
+const GLOBAL_LIMIT: usize = 1000;
+
+/// Inputs typed at the `parsec> ` prompt, kept apart by how they were handled so that
+/// recalling after a question brings back questions rather than `ls`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+    /// Shell commands and builtins.
+    Shell,
+    /// Workflow requests.
+    Prompts,
+}
+
+impl Namespace {
+    pub fn of(kind: &InputKind) -> Self {
+        match kind {
+            InputKind::Shell => Namespace::Shell,
+            InputKind::Prompt => Namespace::Prompts,
+        }
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            Namespace::Shell => Namespace::Prompts,
+            Namespace::Prompts => Namespace::Shell,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Namespace::Shell => "shell",
+            Namespace::Prompts => "prompts",
+        }
+    }
+
+    fn file_name(self) -> String {
+        format!("{}.jsonl", self.as_str())
+    }
+}
+
+/// One line of a history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputEntry {
+    pub input: String,
+    pub entered_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    shell: Vec<InputEntry>,
+    prompts: Vec<InputEntry>,
+}
+
+impl Entries {
+    fn get(&self, namespace: Namespace) -> &Vec<InputEntry> {
+        match namespace {
+            Namespace::Shell => &self.shell,
+            Namespace::Prompts => &self.prompts,
+        }
+    }
+
+    fn get_mut(&mut self, namespace: Namespace) -> &mut Vec<InputEntry> {
+        match namespace {
+            Namespace::Shell => &mut self.shell,
+            Namespace::Prompts => &mut self.prompts,
+        }
+    }
+}
+
+/// The input history of the running session and of every session before it, in memory
+/// and, outside privacy mode, under `<data_dir>/input-history`. Lines the secret
+/// patterns match are recalled in this run but never written. The files are plain text,
+/// so a history that is not persisted, as with an encrypted store, lasts for one run.
+#[derive(Debug)]
+pub struct InputHistory {
+    dir: PathBuf,
+    /// `None` until loaded, and always when not persisted; nothing is written meanwhile.
+    session_id: Option<String>,
+    persist: bool,
+    redactor: SecretRedactor,
+    /// Every session, this one included, oldest first.
+    global: Entries,
+    /// This session only.
+    session: Entries,
+}
+
+impl InputHistory {
+    /// Histories kept under `data_dir`, skipping lines `redactor` finds a secret in.
+    pub fn new(data_dir: &Path, redactor: SecretRedactor) -> Self {
+        Self {
+            dir: data_dir.join(INPUT_HISTORY_DIR),
+            session_id: None,
+            persist: true,
+            redactor,
+            global: Entries::default(),
+            session: Entries::default(),
+        }
+    }
+
+    /// Whether histories are read from and written to disk; when not, they are kept in
+    /// memory for this run only.
+    pub fn with_persistence(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// Load what was recorded before, for recall in `session_id`.
+    pub fn load(&mut self, session_id: &str) {
+        if !self.persist {
+            return;
+        }
+        let session_dir = self.dir.join("sessions").join(session_id);
+        self.session_id = Some(session_id.to_string());
+        for namespace in [Namespace::Shell, Namespace::Prompts] {
+            let path = self.dir.join(namespace.file_name());
+            let mut global = read_entries(&path);
+            if global.len() > GLOBAL_LIMIT {
+                global.drain(..global.len() - GLOBAL_LIMIT);
+                if let Err(e) = write_entries(&path, &global) {
+                    warn!("Could not trim {}: {}", path.display(), e);
+                }
+            }
+            *self.global.get_mut(namespace) = global;
+            *self.session.get_mut(namespace) =
+                read_entries(&session_dir.join(namespace.file_name()));
+        }
+    }
+
+    /// Record `input` unless it repeats the namespace's previous entry. Privacy mode keeps
+    /// it in memory only.
+    pub fn record(&mut self, namespace: Namespace, input: &str, session: &Session) {
+        let input = input.trim();
+        if input.is_empty()
+            || self
+                .global
+                .get(namespace)
+                .last()
+                .is_some_and(|last| last.input == input)
+        {
+            return;
+        }
+        let entry = InputEntry {
+            input: input.to_string(),
+            entered_at: Utc::now(),
+        };
+        self.global.get_mut(namespace).push(entry.clone());
+        self.session.get_mut(namespace).push(entry.clone());
+
+        let Some(session_id) = &self.session_id else {
+            return;
+        };
+        if session.settings.privacy_mode || self.redactor.finds_secret(input) {
+            return;
+        }
+        let session_dir = self.dir.join("sessions").join(session_id);
+        for path in [
+            self.dir.join(namespace.file_name()),
+            session_dir.join(namespace.file_name()),
+        ] {
+            if let Err(e) = append_entry(&path, &entry) {
+                warn!("Could not save input history to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Inputs to recall in `namespace`, oldest first.
+    pub fn recall(&self, namespace: Namespace) -> Vec<&str> {
+        self.global
+            .get(namespace)
+            .iter()
+            .map(|entry| entry.input.as_str())
+            .collect()
+    }
+
+    /// What this session recorded in `namespace`, oldest first.
+    pub fn session_entries(&self, namespace: Namespace) -> &[InputEntry] {
+        self.session.get(namespace)
+    }
+}
+
+fn read_entries(path: &Path) -> Vec<InputEntry> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<InputEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    // Sessions running side by side append to the same global files
+    entries.dedup_by(|a, b| a.input == b.input);
+    entries
+}
+
+fn append_entry(path: &Path, entry: &InputEntry) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+fn write_entries(path: &Path, entries: &[InputEntry]) -> Result<(), anyhow::Error> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsec_core::{GlobalContext, SessionSettings, SCHEMA_VERSION};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn session(privacy_mode: bool) -> Session {
+        Session {
+            schema_version: SCHEMA_VERSION,
+            id: "session".to_string(),
+            created_at: Utc::now(),
+            last_active: Utc::now(),
+            conversations: Vec::new(),
+            command_history: Vec::new(),
+            global_context: GlobalContext {
+                working_directory: ".".into(),
+                environment_snapshot: HashMap::new(),
+                detected_project_type: None,
+                active_tools: Vec::new(),
+                tools_cache_key: None,
+            },
+            settings: SessionSettings {
+                privacy_mode,
+                ..SessionSettings::default()
+            },
+            last_input_kind: None,
+            classification_log: Vec::new(),
+            classification_corrections: Vec::new(),
+            context_items: Vec::new(),
+            provider_health: None,
+            step_timings: Vec::new(),
+        }
+    }
+
+    fn data_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("parsec-input-history-{}", Uuid::new_v4()))
+    }
+
+    fn loaded(data_dir: &Path) -> InputHistory {
+        let mut history = InputHistory::new(data_dir, SecretRedactor::new());
+        history.load("session");
+        history
+    }
+
+    #[test]
+    fn consecutive_repeats_are_recorded_once() {
+        let dir = data_dir();
+        let mut history = loaded(&dir);
+        for input in ["ls", "ls", "  ls  ", "pwd", "ls"] {
+            history.record(Namespace::Shell, input, &session(false));
+        }
+
+        assert_eq!(history.recall(Namespace::Shell), vec!["ls", "pwd", "ls"]);
+        assert!(history.recall(Namespace::Prompts).is_empty());
+        assert_eq!(
+            loaded(&dir).recall(Namespace::Shell),
+            vec!["ls", "pwd", "ls"]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn repeats_appended_by_sessions_side_by_side_are_dropped_on_load() {
+        let dir = data_dir();
+        let (mut first, mut second) = (loaded(&dir), loaded(&dir));
+        first.record(Namespace::Shell, "make", &session(false));
+        second.record(Namespace::Shell, "make", &session(false));
+        second.record(Namespace::Shell, "make test", &session(false));
+
+        assert_eq!(
+            loaded(&dir).recall(Namespace::Shell),
+            vec!["make", "make test"]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lines_holding_secrets_are_recalled_but_not_written() {
+        let dir = data_dir();
+        let mut history = loaded(&dir);
+        let secret = "export API_TOKEN=s3cr3t-value";
+        history.record(Namespace::Shell, "ls", &session(false));
+        history.record(Namespace::Shell, secret, &session(false));
+
+        assert_eq!(history.recall(Namespace::Shell), vec!["ls", secret]);
+        assert_eq!(loaded(&dir).recall(Namespace::Shell), vec!["ls"]);
+        let written = fs::read_to_string(dir.join(INPUT_HISTORY_DIR).join("shell.jsonl")).unwrap();
+        assert!(!written.contains("s3cr3t"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn privacy_mode_keeps_inputs_in_memory() {
+        let dir = data_dir();
+        let mut history = loaded(&dir);
+        history.record(Namespace::Prompts, "deploy the app", &session(true));
+
+        assert_eq!(history.recall(Namespace::Prompts), vec!["deploy the app"]);
+        assert!(!dir.join(INPUT_HISTORY_DIR).exists());
+    }
+
+    #[test]
+    fn histories_not_persisted_are_neither_written_nor_read() {
+        let dir = data_dir();
+        loaded(&dir).record(Namespace::Shell, "ls", &session(false));
+
+        let mut history = InputHistory::new(&dir, SecretRedactor::new()).with_persistence(false);
+        history.load("session");
+        assert!(history.recall(Namespace::Shell).is_empty());
+        history.record(Namespace::Shell, "cat notes.txt", &session(false));
+
+        assert_eq!(history.recall(Namespace::Shell), vec!["cat notes.txt"]);
+        assert_eq!(loaded(&dir).recall(Namespace::Shell), vec!["ls"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
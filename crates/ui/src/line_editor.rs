@@ -0,0 +1,434 @@
+//! The `parsec> ` prompt at a terminal. The line can be edited in place; Up and Down step
+//! through earlier inputs starting with what is typed so far, Ctrl-R searches them, and
+//! Ctrl-T switches between the shell and the prompt history.
+
+use crate::input_history::{InputHistory, Namespace};
+use crossterm::cursor::MoveToColumn;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::QueueableCommand;
+use std::io::{self, Write};
+
+/// Shown in place of line breaks of recalled multi-line inputs.
+const LINE_BREAK: char = '↵';
+
+/// Leaves raw mode however reading ends.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Ctrl-R: the newest entry containing `query`, from `found` back.
+struct Search {
+    query: String,
+    found: Option<usize>,
+}
+
+struct Editor<'a> {
+    prompt: &'a str,
+    history: &'a InputHistory,
+    namespace: Namespace,
+    /// Ctrl-T was pressed, so the namespace is shown.
+    toggled: bool,
+    line: Vec<char>,
+    cursor: usize,
+    /// Up/Down: the typed prefix, what was typed before recalling, and the entry shown.
+    browsing: Option<(String, Vec<char>, usize)>,
+    search: Option<Search>,
+}
+
+/// Read one line at `prompt`, recalling from the namespace the typed prefix implies or else
+/// `namespace`. Like `BufRead::read_line` the line ends in `\n` and the end of input
+/// (Ctrl-D on an empty line) is an empty string; Ctrl-C discards the line.
+pub fn read_line(prompt: &str, history: &InputHistory, namespace: Namespace) -> io::Result<String> {
+    let _raw = RawMode::enable()?;
+    let mut editor = Editor {
+        prompt,
+        history,
+        namespace,
+        toggled: false,
+        line: Vec::new(),
+        cursor: 0,
+        browsing: None,
+        search: None,
+    };
+    let mut out = io::stdout();
+    editor.render(&mut out)?;
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if let Some(line) = editor.key(key) {
+            editor.render(&mut out)?;
+            out.write_all(b"\r\n")?;
+            out.flush()?;
+            return Ok(line);
+        }
+        editor.render(&mut out)?;
+    }
+}
+
+impl Editor<'_> {
+    /// Apply `key`; the line read when it ends reading.
+    fn key(&mut self, key: KeyEvent) -> Option<String> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if self.search.is_some() {
+            return self.search_key(key, ctrl);
+        }
+        match key.code {
+            KeyCode::Enter => return Some(self.submit()),
+            KeyCode::Char('c') if ctrl => {
+                self.line.clear();
+                return Some("\n".to_string());
+            }
+            KeyCode::Char('d') if ctrl && self.line.is_empty() => return Some(String::new()),
+            KeyCode::Char('d') if ctrl => self.delete(),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.line.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('k') if ctrl => self.line.truncate(self.cursor),
+            KeyCode::Char('t') if ctrl => {
+                self.namespace = self.namespace.other();
+                self.toggled = true;
+                self.browsing = None;
+            }
+            KeyCode::Char('r') if ctrl => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                });
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+                self.browsing = None;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                self.browsing = None;
+            }
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.line.len(),
+            KeyCode::Up => self.recall(true),
+            KeyCode::Down => self.recall(false),
+            _ => {}
+        }
+        None
+    }
+
+    fn search_key(&mut self, key: KeyEvent, ctrl: bool) -> Option<String> {
+        let entries = self.history.recall(self.namespace);
+        let search = self.search.as_mut()?;
+        match key.code {
+            KeyCode::Char('r') if ctrl => {
+                let before = search.found.unwrap_or(entries.len());
+                search.found =
+                    newest_containing(&entries[..before], &search.query).or(search.found);
+            }
+            KeyCode::Char('c' | 'g') if ctrl => self.search = None,
+            KeyCode::Esc => self.search = None,
+            KeyCode::Char(c) if !ctrl => {
+                search.query.push(c);
+                search.found = newest_containing(&entries, &search.query);
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.found = newest_containing(&entries, &search.query);
+            }
+            code => {
+                // Anything else takes the match into the line, and Enter runs it
+                if let Some(found) = search.found {
+                    self.line = entries[found].chars().collect();
+                    self.cursor = self.line.len();
+                }
+                self.search = None;
+                self.browsing = None;
+                if code == KeyCode::Enter {
+                    return Some(self.submit());
+                }
+            }
+        }
+        None
+    }
+
+    fn submit(&mut self) -> String {
+        self.browsing = None;
+        self.cursor = self.line.len();
+        let mut line: String = self.line.iter().collect();
+        line.push('\n');
+        line
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+            self.browsing = None;
+        }
+    }
+
+    /// Show the previous (`older`) or next entry starting with what was typed before
+    /// recalling; past the newest, the typed line comes back.
+    fn recall(&mut self, older: bool) {
+        let (prefix, typed, shown) = self.browsing.take().unwrap_or_else(|| {
+            let typed: String = self.line.iter().collect();
+            if let Some(namespace) = namespace_of(&typed) {
+                self.namespace = namespace;
+            }
+            let shown = self.history.recall(self.namespace).len();
+            (typed, self.line.clone(), shown)
+        });
+        let entries = self.history.recall(self.namespace);
+        let current: String = self.line.iter().collect();
+        let matches = |i: &usize| entries[*i].starts_with(&prefix) && entries[*i] != current;
+        let next = if older {
+            (0..shown).rev().find(matches)
+        } else {
+            (shown + 1..entries.len()).find(matches)
+        };
+        match next {
+            Some(index) => {
+                self.line = entries[index].chars().collect();
+                self.browsing = Some((prefix, typed, index));
+            }
+            None if older => self.browsing = Some((prefix, typed, shown)),
+            None => self.line = typed,
+        }
+        self.cursor = self.line.len();
+    }
+
+    fn render(&self, out: &mut impl Write) -> io::Result<()> {
+        let namespace = self.namespace.as_str();
+        let (prompt, text, cursor) = match &self.search {
+            Some(search) => {
+                let found = search
+                    .found
+                    .map(|i| self.history.recall(self.namespace)[i].to_string())
+                    .unwrap_or_default();
+                (
+                    format!("(search {}) '{}': ", namespace, search.query),
+                    found.chars().collect::<Vec<_>>(),
+                    0,
+                )
+            }
+            None if self.toggled => (
+                format!("({}) {}", namespace, self.prompt),
+                self.line.clone(),
+                self.cursor,
+            ),
+            None => (self.prompt.to_string(), self.line.clone(), self.cursor),
+        };
+
+        // Scroll sideways rather than wrap, so the line can be redrawn in place
+        let width = terminal::size()
+            .map(|(columns, _)| columns as usize)
+            .unwrap_or(80);
+        let prompt_width = prompt.chars().count();
+        let room = width.saturating_sub(prompt_width + 1).max(1);
+        let start = cursor.saturating_sub(room);
+        let visible: String = text
+            .iter()
+            .skip(start)
+            .take(room)
+            .map(|&c| if c == '\n' { LINE_BREAK } else { c })
+            .collect();
+
+        out.queue(MoveToColumn(0))?;
+        out.queue(Clear(ClearType::UntilNewLine))?;
+        write!(out, "{}{}", prompt, visible)?;
+        out.queue(MoveToColumn((prompt_width + cursor - start) as u16))?;
+        out.flush()
+    }
+}
+
+/// The namespace an override prefix picks: `!` for shell, `?` or `ai:` for prompts.
+fn namespace_of(typed: &str) -> Option<Namespace> {
+    let typed = typed.trim_start();
+    if typed.starts_with('!') {
+        Some(Namespace::Shell)
+    } else if typed.starts_with('?') || typed.to_lowercase().starts_with("ai:") {
+        Some(Namespace::Prompts)
+    } else {
+        None
+    }
+}
+
+fn newest_containing(entries: &[&str], query: &str) -> Option<usize> {
+    entries.iter().rposition(|entry| entry.contains(query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_history::INPUT_HISTORY_DIR;
+    use parsec_core::secrets::SecretRedactor;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    /// A history loaded from files holding `shell` and `prompts`, oldest first.
+    fn history(shell: &[&str], prompts: &[&str]) -> (InputHistory, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("parsec-line-editor-{}", Uuid::new_v4()));
+        let history_dir = dir.join(INPUT_HISTORY_DIR);
+        std::fs::create_dir_all(&history_dir).unwrap();
+        for (file, inputs) in [("shell.jsonl", shell), ("prompts.jsonl", prompts)] {
+            let lines: String = inputs
+                .iter()
+                .map(|input| {
+                    format!(
+                        "{}\n",
+                        serde_json::json!({ "input": input, "entered_at": chrono::Utc::now() })
+                    )
+                })
+                .collect();
+            std::fs::write(history_dir.join(file), lines).unwrap();
+        }
+        let mut history = InputHistory::new(&dir, SecretRedactor::new());
+        history.load("session");
+        (history, dir)
+    }
+
+    fn editor(history: &InputHistory) -> Editor<'_> {
+        Editor {
+            prompt: "parsec> ",
+            history,
+            namespace: Namespace::Shell,
+            toggled: false,
+            line: Vec::new(),
+            cursor: 0,
+            browsing: None,
+            search: None,
+        }
+    }
+
+    fn press(editor: &mut Editor, code: KeyCode) -> Option<String> {
+        editor.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn ctrl(editor: &mut Editor, c: char) -> Option<String> {
+        editor.key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            press(editor, KeyCode::Char(c));
+        }
+    }
+
+    fn line(editor: &Editor) -> String {
+        editor.line.iter().collect()
+    }
+
+    #[test]
+    fn up_and_down_recall_entries_starting_with_the_typed_prefix() {
+        let (history, dir) = history(&["git status", "ls", "git log", "git log"], &[]);
+        let mut editor = editor(&history);
+        type_text(&mut editor, "git");
+
+        press(&mut editor, KeyCode::Up);
+        assert_eq!(line(&editor), "git log");
+        // The repeat of the line shown is skipped
+        press(&mut editor, KeyCode::Up);
+        assert_eq!(line(&editor), "git status");
+        // Past the oldest match the line stays
+        press(&mut editor, KeyCode::Up);
+        assert_eq!(line(&editor), "git status");
+        press(&mut editor, KeyCode::Down);
+        assert_eq!(line(&editor), "git log");
+        press(&mut editor, KeyCode::Down);
+        assert_eq!(line(&editor), "git");
+        assert_eq!(editor.cursor, 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_prompt_prefix_recalls_from_the_prompt_history() {
+        let (history, dir) = history(&["ls"], &["?why did the build fail"]);
+        let mut editor = editor(&history);
+        type_text(&mut editor, "?");
+
+        press(&mut editor, KeyCode::Up);
+
+        assert_eq!(editor.namespace, Namespace::Prompts);
+        assert_eq!(line(&editor), "?why did the build fail");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ctrl_t_switches_the_history_recalled() {
+        let (history, dir) = history(&["ls"], &["deploy the app"]);
+        let mut editor = editor(&history);
+
+        ctrl(&mut editor, 't');
+        press(&mut editor, KeyCode::Up);
+        assert_eq!(editor.namespace, Namespace::Prompts);
+        assert!(editor.toggled);
+        assert_eq!(line(&editor), "deploy the app");
+
+        ctrl(&mut editor, 'u');
+        ctrl(&mut editor, 't');
+        press(&mut editor, KeyCode::Up);
+        assert_eq!(editor.namespace, Namespace::Shell);
+        assert_eq!(line(&editor), "ls");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ctrl_r_searches_back_and_enter_runs_the_match() {
+        let (history, dir) = history(&["cargo build", "ls", "cargo test", "pwd"], &[]);
+        let mut editor = editor(&history);
+
+        ctrl(&mut editor, 'r');
+        type_text(&mut editor, "cargo");
+        assert_eq!(editor.search.as_ref().unwrap().found, Some(2));
+        ctrl(&mut editor, 'r');
+        assert_eq!(editor.search.as_ref().unwrap().found, Some(0));
+        // No older match keeps the one found
+        ctrl(&mut editor, 'r');
+        assert_eq!(editor.search.as_ref().unwrap().found, Some(0));
+
+        assert_eq!(
+            press(&mut editor, KeyCode::Enter),
+            Some("cargo build\n".to_string())
+        );
+        assert!(editor.search.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn leaving_a_search_keeps_or_discards_the_match() {
+        let (history, dir) = history(&["cargo build", "ls"], &[]);
+        let mut editor = editor(&history);
+
+        ctrl(&mut editor, 'r');
+        type_text(&mut editor, "bui");
+        assert_eq!(press(&mut editor, KeyCode::Right), None);
+        assert_eq!(line(&editor), "cargo build");
+
+        editor.line.clear();
+        ctrl(&mut editor, 'r');
+        type_text(&mut editor, "ls");
+        ctrl(&mut editor, 'g');
+        assert!(editor.search.is_none());
+        assert_eq!(line(&editor), "");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,467 +1,4103 @@
-use chrono::Utc;
-use clap::Parser;
-use env_logger;
-use log::{error, info, warn};
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, field, info, instrument, warn, Span};
 use uuid::Uuid;
 
-use parsec_classifier::{HeuristicClassifier, HuggingFaceClassifier};
+/// Human-facing output: stdout normally, stderr in `--output json` mode.
+macro_rules! say {
+    ($app:expr, $($arg:tt)*) => {
+        $app.say(format_args!($($arg)*))
+    };
+}
+
+mod approval;
+mod audit;
+mod config;
+mod history;
+mod input_history;
+mod line_editor;
+mod plan;
+mod render;
+mod shutdown;
+mod tasks;
+mod workflow;
+
+use parsec_classifier::disambiguation::{self, Disambiguation, Disambiguator};
+use parsec_classifier::intent;
+use parsec_classifier::{
+    EnsembleClassifier, HeuristicClassifier, HuggingFaceClassifier, MeteredClassifier,
+};
+use parsec_core::environment::EnvFilter;
+use parsec_core::logging::{self, content_hash, loggable};
+use parsec_core::metrics::{self, Histogram, InMemoryMetrics, Metrics, MetricsSnapshot};
+use parsec_core::project::ProjectDetector;
+use parsec_core::search::{SearchHitKind, SearchScope};
+use parsec_core::secrets::SecretRedactor;
 use parsec_core::*;
+use parsec_executor::jobs::{JobId, JobOutcome, JobRegistry};
+use parsec_executor::preflight::PreflightOptions;
+use parsec_executor::sandbox;
+use parsec_executor::snapshot::SnapshotOptions;
 use parsec_executor::SafeExecutor;
-use parsec_model::{GoogleAiProvider, InMemorySessionStore};
-use parsec_prompt::PromptOrchestrator;
+use parsec_model::budget::ShellHistory;
+use parsec_model::bundle::{self, PathMap, SessionBundle};
+use parsec_model::embedding::VectorStore;
+use parsec_model::openai::AzureDeployment;
+use parsec_model::prompts::{self, PromptTemplates};
+use parsec_model::{
+    AzureOpenAiClient, EmbeddingIndex, FallbackProvider, FileSessionStore, GoogleAiClient,
+    GoogleAiProvider, InMemorySessionStore, ModelClient, PromptBudget, ResponseCache,
+};
+use parsec_prompt::annotate;
+use parsec_prompt::{
+    Confirmation, ExportFormat, OrchestratorError, PromptOrchestrator, RiskTiers, StepExecution,
+    StepHandle,
+};
+
+use audit::{Approval, AuditLog, AuditRecord};
+use config::{CliOverrides, ParsecConfig};
+use input_history::{InputHistory, Namespace};
+use render::Renderer;
+use shutdown::ShutdownGuard;
+use tasks::{SavedTask, TaskBook};
+
+/// Opens and closes an input spanning several lines at the interactive prompt.
+const MULTI_LINE_QUOTE: &str = "\"\"\"";
+
+/// Lines of each stream `tail N` shows.
+const JOB_TAIL_LINES: usize = 20;
+
+/// Under the data directory; what `parsec stats` reads.
+const METRICS_FILE: &str = "metrics.json";
+
+/// Hits the `search` builtin shows at a time, and most it finds.
+const SEARCH_PAGE_SIZE: usize = 10;
+const SEARCH_MAX_HITS: usize = 200;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputMode {
+    Text,
+    Json,
+}
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    name = "parsec",
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Without a subcommand, parsec starts an interactive shell.\n\n\
+Exit status: 0 on success, 1 on errors (with doctor: a check failed), 2 on \
+invalid arguments. `parsec exec --help` lists the codes of a single run."
+)]
 struct Args {
     /// Google AI Studio API key (or set GOOGLE_AI_API_KEY env var)
-    #[arg(long)]
+    #[arg(long, global = true)]
     api_key: Option<String>,
 
     /// Use Hugging Face for classification (requires HUGGINGFACE_API_TOKEN)
-    #[arg(long)]
+    #[arg(long, conflicts_with = "classifier", global = true)]
     use_huggingface_classifier: bool,
 
+    /// Input classifier: heuristic, huggingface, ensemble, local or python
+    #[arg(long, global = true)]
+    classifier: Option<String>,
+
+    /// Script for `--classifier python` (or set PARSEC_CLASSIFIER_SCRIPT)
+    #[arg(long, global = true)]
+    classifier_script: Option<PathBuf>,
+
     /// Working directory
-    #[arg(long)]
+    #[arg(long, global = true)]
     working_dir: Option<PathBuf>,
 
     /// Interactive mode (default)
     #[arg(long)]
     interactive: bool,
 
-    /// Command to execute directly
-    #[arg(long)]
+    /// Deprecated alias for `parsec exec`
+    #[arg(long, hide = true)]
     execute: Option<String>,
+
+    /// Directory for persisted sessions (defaults to ~/.local/share/parsec)
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Deprecated alias for `parsec doctor`
+    #[arg(long, hide = true)]
+    doctor: bool,
+
+    /// Start a new session instead of resuming the one last used in this directory
+    #[arg(long, global = true)]
+    new_session: bool,
+
+    /// Output format; `json` prints one JSON record per line on stdout
+    #[arg(long, value_enum, default_value_t = OutputMode::Text, global = true)]
+    output: OutputMode,
+
+    /// Print without colors (also set by NO_COLOR, or when stdout is not a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Log filter: a level (error, warn, info, debug, trace) or RUST_LOG-style
+    /// directives; defaults to RUST_LOG, else error on stderr and info in --log-file
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Also write logs and timed spans to this file, as JSON lines
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Execute generated commands without asking for approval
+    #[arg(long, global = true)]
+    auto_approve: bool,
+
+    /// Let commands above the block threshold run once their confirmation is typed
+    #[arg(long, global = true)]
+    allow_dangerous: bool,
+
+    /// Generate commands but never execute them
+    #[arg(long, conflicts_with = "auto_approve", global = true)]
+    dry_run: bool,
+
+    /// Run workflows even when preflight checks of the working directory find errors
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Always call the model, ignoring and not storing cached responses
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Only call the model to plan and generate commands; name conversations and
+    /// summarize their history without it
+    #[arg(long, global = true)]
+    no_extra_calls: bool,
+
+    /// Plan and generate commands from the prompt alone, without the working directory,
+    /// tools, environment, history or other conversations (the default in privacy mode)
+    #[arg(long, conflicts_with = "context", global = true)]
+    no_context: bool,
+
+    /// Share the session's context with the model even in privacy mode
+    #[arg(long, global = true)]
+    context: bool,
+
+    /// Attach this file, relative to the working directory, to every prompt; may be
+    /// given more than once
+    #[arg(long = "context-file", value_name = "PATH", global = true)]
+    context_files: Vec<PathBuf>,
+
+    /// Record every model prompt and raw response; view them with `parsec debug prompts`
+    #[arg(long, global = true)]
+    debug_prompts: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Exit codes of subcommands that only fail one way, for their `--help`.
+const EXIT_CODES: &str = "Exit status: 0 on success, 1 on errors, 2 on invalid arguments.";
+
+/// Exit codes of `parsec exec`, from `execute_failure`.
+const EXEC_EXIT_CODES: &str = "Exit status:
+  0   the input ran to completion
+  1   any other error
+  2   invalid arguments
+  3   the model is unavailable; try again later
+  4   the model response could not be used
+  5   the session store is unavailable
+  6   a command could not be executed
+  7   a step used up its attempts
+  8   the conversation cannot be resumed
+  9   invalid plan or step operation, or a file or `@` reference that cannot be attached
+  10  the session is in use by another parsec
+  11  preflight checks failed; --force runs anyway
+  12  the model provider rejected its credentials or failed its health check";
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run one input, a shell command or a task for the model, then exit
+    #[command(after_help = EXEC_EXIT_CODES)]
+    Exec {
+        /// The input, as typed at the interactive prompt; `-` reads it from stdin, which
+        /// then cannot answer approval prompts, so pair it with --auto-approve or --dry-run
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        input: Vec<String>,
+    },
+
+    /// Classify and plan a task without running it, showing the risk each step looks to
+    /// carry; nothing is saved unless --save is given
+    #[command(after_help = EXEC_EXIT_CODES)]
+    Plan {
+        /// The task to plan
+        #[arg(required = true, trailing_var_arg = true)]
+        prompt: Vec<String>,
+
+        /// Keep the plan as a conversation, which `fork <id>` runs
+        #[arg(long)]
+        save: bool,
+    },
+
+    /// List, delete or archive saved sessions (lists them by default)
+    #[command(
+        after_help = "Exit status: 0 on success, 1 if the session does not exist or \
+cannot be removed, 2 on invalid arguments."
+    )]
+    Sessions {
+        #[command(subcommand)]
+        action: Option<SessionsAction>,
+    },
+
+    /// List saved conversations, pinned ones first and then most recent first; or rename,
+    /// annotate or pin one
+    #[command(args_conflicts_with_subcommands = true, after_help = EXIT_CODES)]
+    Conversations {
+        #[command(subcommand)]
+        action: Option<ConversationsAction>,
+
+        /// Only the conversations of this session
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Only conversations with this tag; repeat to require several
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Print a shell completion script; fish also completes session ids
+    #[command(after_help = EXIT_CODES)]
+    Completions { shell: Shell },
+
+    /// Export a conversation as a Markdown report or raw JSON
+    #[command(after_help = EXIT_CODES)]
+    Export {
+        /// Conversation id to export
+        conversation_id: String,
+
+        /// Output format: md or json
+        #[arg(long, default_value = "md")]
+        format: ExportFormat,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Classify an input without running it
+    #[command(args_conflicts_with_subcommands = true, after_help = EXIT_CODES)]
+    Classify {
+        #[command(subcommand)]
+        action: Option<ClassifyAction>,
+
+        #[arg(required = true)]
+        input: Option<String>,
+
+        /// List every heuristic rule that fired and its weight
+        #[arg(long)]
+        explain: bool,
+    },
+
+    /// Show or change configuration
+    #[command(
+        after_help = "Exit status: 0 on success, 1 if a config file is invalid or \
+`set` is given an unknown key or a value of the wrong type, 2 on invalid arguments."
+    )]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Inspect model prompt templates
+    #[command(after_help = EXIT_CODES)]
+    Prompts {
+        #[command(subcommand)]
+        action: PromptsAction,
+    },
+
+    /// Debugging aids
+    #[command(after_help = EXIT_CODES)]
+    Debug {
+        #[command(subcommand)]
+        action: DebugAction,
+    },
+
+    /// Read the audit log of executed commands
+    #[command(after_help = EXIT_CODES)]
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+
+    /// Summarize model calls, executions, classifications and planning time
+    #[command(after_help = EXIT_CODES)]
+    Stats {
+        /// Delete the recorded metrics instead
+        #[arg(long)]
+        reset: bool,
+    },
+
+    /// Check the session store, the model provider and the classifier, then exit
+    #[command(
+        after_help = "Exit status: 0 if every check passed, 1 if any failed, 2 on invalid arguments."
+    )]
+    Doctor,
+
+    /// Search commands, workflow steps and prompts across saved sessions, best match first
+    #[command(after_help = EXIT_CODES)]
+    Search {
+        /// Words that must all appear, in any case
+        #[arg(required = true)]
+        query: Vec<String>,
+
+        /// Only this session and its conversations
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Hits per page
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Page of hits to print, from 1
+        #[arg(long, default_value_t = 1)]
+        page: usize,
+    },
+
+    /// Save prompts as named tasks and run them again
+    #[command(after_help = EXIT_CODES)]
+    Task {
+        #[command(subcommand)]
+        action: TaskAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum TaskAction {
+    /// Save a conversation's prompt as a task
+    Save {
+        name: String,
+        /// The conversation to save; the most recent one by default
+        #[arg(long)]
+        conversation: Option<String>,
+        /// Also save its plan, to run without asking the model for one
+        #[arg(long)]
+        plan: bool,
+    },
+    /// List saved tasks and those of the project
+    List,
+    /// Run a task in a new conversation
+    #[command(after_help = EXEC_EXIT_CODES)]
+    Run {
+        name: String,
+        /// Fill the task's `{{key}}` placeholders
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_task_var)]
+        vars: Vec<(String, String)>,
+    },
+    /// Delete a saved task
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+enum AuditAction {
+    /// Print the most recent commands
+    Tail {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+    },
+    /// Print the commands matching a regular expression
+    Search { pattern: String },
+}
+
+#[derive(Subcommand)]
+enum SessionsAction {
+    /// List saved sessions, most recently active first
+    List {
+        /// Print only the session ids, one per line
+        #[arg(long)]
+        ids: bool,
+    },
+    /// Delete a session and its conversations
+    Delete { session_id: String },
+    /// Move a session and its conversations out of the way without deleting them
+    Archive { session_id: String },
+    /// Write a session and its conversations, with credentials redacted, to one file
+    Export {
+        session_id: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Load a session exported with `sessions export`, with new ids for any already taken
+    Import {
+        bundle: PathBuf,
+        /// Move recorded working directories under OLD to NEW; repeatable. Directories
+        /// that are not mapped and do not exist here are asked about
+        #[arg(long = "map-path", value_name = "OLD=NEW", value_parser = PathMap::parse_entry)]
+        map_paths: Vec<(PathBuf, PathBuf)>,
+    },
+    /// Count the saved sessions and conversations and the bytes they take
+    Stats,
+}
+
+/// Conversations are named by id, or by their number in the unfiltered `parsec
+/// conversations` listing.
+#[derive(Subcommand)]
+enum ConversationsAction {
+    /// Rename a conversation; parsec no longer renames it
+    Rename {
+        conversation: String,
+        #[arg(required = true, trailing_var_arg = true)]
+        name: Vec<String>,
+    },
+    /// Attach a note to a conversation, or list its notes
+    Note {
+        conversation: String,
+        #[arg(trailing_var_arg = true)]
+        text: Vec<String>,
+    },
+    /// Remove one note of a conversation, by number, or all of them
+    Unnote {
+        conversation: String,
+        number: Option<usize>,
+    },
+    /// Keep a conversation through retention pruning, list it first and weigh it higher
+    /// as context for later conversations
+    Pin { conversation: String },
+    /// Undo `pin`
+    Unpin { conversation: String },
+}
+
+#[derive(Subcommand)]
+enum ClassifyAction {
+    /// Summarize recorded classifier decisions and user corrections
+    Stats,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the merged configuration and where each value came from
+    Show,
+    /// Set a key (such as `executor.risk_threshold`) in the user config file
+    Set { key: String, value: String },
+}
+
+#[derive(Subcommand)]
+enum DebugAction {
+    /// Print the prompts and raw responses recorded for a conversation, in order
+    Prompts { conversation_id: String },
+}
+
+#[derive(Subcommand)]
+enum PromptsAction {
+    /// Print the effective template (`planning` or `command`) and its variables
+    Show { name: String },
+}
+
+/// A workflow step whose command runs as a background job.
+struct BackgroundStep {
+    conversation_id: ConversationId,
+    handle: StepHandle,
+    command: GeneratedCommand,
+    audit: Option<AuditRecord>,
 }
 
 struct ParsecApp {
     classifier: Box<dyn CommandClassifier>,
-    orchestrator: PromptOrchestrator,
-    session_store: Arc<InMemorySessionStore>,
+    /// `classifier.kind`, recorded with each decision.
+    classifier_name: String,
+    /// Raw text of the previous input, for the `wrong` builtin.
+    last_input: Option<String>,
+    /// What was typed at the prompt, by namespace, for recall and `history --prompts`.
+    input_history: InputHistory,
+    /// The line just typed at the prompt, recorded in `input_history` once it is known
+    /// how it was handled.
+    typed_input: Option<String>,
+    orchestrator: Arc<PromptOrchestrator>,
+    session_store: Arc<FileSessionStore>,
+    executor: SafeExecutor,
     current_session: Option<Session>,
+    project_detector: ProjectDetector,
+    retention: RetentionPolicy,
+    tracked_tools: Vec<String>,
+    env_filter: EnvFilter,
+    max_attempts_per_step: usize,
+    rate_limits: RateLimits,
+    history_limit: usize,
+    history_full_output: usize,
+    context_compression_threshold: f32,
+    /// Settles inputs the classifier is unsure about.
+    disambiguator: Disambiguator,
+    /// Answer prompts phrased as questions rather than planning them.
+    answer_questions: bool,
+    output: OutputMode,
+    /// Styles text output; plain in JSON mode.
+    render: Renderer,
+    auto_approve: bool,
+    dry_run: bool,
+    /// `--force`: run workflows whose preflight checks found errors.
+    force: bool,
+    jobs: Arc<JobRegistry>,
+    background_steps: HashMap<JobId, BackgroundStep>,
+    kill_jobs_on_exit: bool,
+    /// How soon after a conversation finished a prompt is offered as its follow-up.
+    follow_up_window: Duration,
+    /// How long the primary model provider may take to plan, if limited.
+    planning_timeout: Option<Duration>,
+    audit: Option<AuditLog>,
+    /// Applied to search results, which show commands as typed.
+    redactor: Option<SecretRedactor>,
+    /// Saves what is not saved yet if parsec is stopped mid-run.
+    guard: Arc<ShutdownGuard>,
+    /// `--new-session`: never resume the session of the working directory.
+    force_new_session: bool,
+    /// Set when this run resumed the session of its working directory.
+    resumed: Option<ResumedSession>,
+}
+
+/// When this run took over an earlier session, and when that session was last active.
+struct ResumedSession {
+    at: DateTime<Utc>,
+    previously_active: DateTime<Utc>,
 }
 
 impl ParsecApp {
-    fn new(args: &Args) -> Result<Self, anyhow::Error> {
-        // Initialize classifier
-        let classifier: Box<dyn CommandClassifier> = if args.use_huggingface_classifier {
-            let token = env::var("HUGGINGFACE_API_TOKEN")
-                .map_err(|_| anyhow::anyhow!("HUGGINGFACE_API_TOKEN environment variable required for Hugging Face classifier"))?;
-            Box::new(HuggingFaceClassifier::new(token)?)
+    fn new(args: &Args, config: &ParsecConfig) -> Result<Self, anyhow::Error> {
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let sink: Arc<dyn Metrics> = if config.metrics_enabled {
+            metrics.clone()
         } else {
-            Box::new(HeuristicClassifier::default())
+            metrics::noop()
         };
 
+        // Initialize classifier
+        let classifier: Box<dyn CommandClassifier> = Box::new(MeteredClassifier::new(
+            build_classifier(config)?,
+            config.classifier.clone(),
+            sink.clone(),
+        ));
+
         // Initialize model provider
         let api_key = args
             .api_key
             .clone()
-            .or_else(|| env::var("GOOGLE_AI_API_KEY").ok())
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "Google AI API key required. Set --api-key or GOOGLE_AI_API_KEY env var"
-                )
-            })?;
+            .or_else(|| env::var("GOOGLE_AI_API_KEY").ok());
 
-        let model_provider = Arc::new(GoogleAiProvider::new(api_key)?);
-        let session_store = Arc::new(InMemorySessionStore::new());
+        let session_store = Arc::new(open_store(config)?);
+        let model_provider =
+            build_model_provider(config, api_key.as_deref(), &sink, session_store.clone())?;
 
-        let orchestrator = PromptOrchestrator::new(model_provider, session_store.clone());
+        let orchestrator = PromptOrchestrator::new(model_provider, session_store.clone())
+            .with_executor(Self::build_executor(config, &sink)?)
+            .with_risk_threshold(config.risk_threshold)
+            .with_risk_tiers(
+                RiskTiers::new()
+                    .with_confirm_threshold(config.confirm_threshold)
+                    .with_block_threshold(config.block_threshold)
+                    .with_allow_dangerous(args.allow_dangerous),
+            )
+            .with_prompt_recording(config.record_prompts)
+            .with_extra_calls(!args.no_extra_calls)
+            .with_parallel_steps(config.parallel_steps)
+            .with_estimate_timeouts(config.timeout_from_estimates)
+            .with_secret_redaction(config.secret_redactor())
+            .with_context_files(
+                config
+                    .context_files()
+                    .with_requested(args.context_files.clone()),
+            )
+            .with_follow_up_depth(config.follow_up_max_depth)
+            .with_context_sharing(match (args.no_context, args.context) {
+                (true, _) => Some(false),
+                (_, true) => Some(true),
+                _ => None,
+            })
+            .with_metrics(sink.clone())
+            .with_prompt_budget(PromptBudget {
+                max_chars: config.prompt_budget_chars,
+                verbatim_steps: config.verbatim_history_steps,
+                ..PromptBudget::default()
+            });
+        let orchestrator = if config.artifacts_enabled {
+            orchestrator.with_artifact_tracking(SnapshotOptions {
+                max_depth: config.artifacts_max_depth,
+                max_entries: config.artifacts_max_entries,
+                collapse_threshold: config.artifacts_collapse_threshold,
+            })
+        } else {
+            orchestrator
+        };
+        let orchestrator = Arc::new(orchestrator);
+        let jobs = Arc::new(
+            JobRegistry::new(config.max_output_size)
+                .with_output_sanitizing(config.output_sanitize)
+                .with_secret_redaction(config.secret_redactor()),
+        );
+        let guard = ShutdownGuard::new(
+            session_store.clone(),
+            orchestrator.clone(),
+            jobs.clone(),
+            config.jobs_kill_on_exit,
+            config
+                .metrics_enabled
+                .then(|| (metrics, config.data_dir.join(METRICS_FILE))),
+        );
 
         Ok(Self {
             classifier,
+            classifier_name: config.classifier.clone(),
+            last_input: None,
+            input_history: InputHistory::new(
+                &config.data_dir,
+                SecretRedactor::new().with_allowlist(config.output_secret_allowlist.clone()),
+            )
+            // The history files are not encrypted, so an encrypted store keeps none
+            .with_persistence(config.storage_encryption == "none"),
+            typed_input: None,
             orchestrator,
             session_store,
+            executor: Self::build_executor(config, &sink)?,
             current_session: None,
+            project_detector: ProjectDetector::new(),
+            retention: config.retention.clone(),
+            tracked_tools: config.tracked_tools.clone(),
+            env_filter: config.env_filter(),
+            max_attempts_per_step: config.max_attempts_per_step,
+            rate_limits: config.rate_limits,
+            history_limit: config.history_limit,
+            history_full_output: config.history_full_output,
+            context_compression_threshold: config.context_compression_threshold,
+            disambiguator: Disambiguator::new(
+                config.classifier_confirm_below,
+                match config.classifier_default_kind.as_str() {
+                    "shell" => InputKind::Shell,
+                    "prompt" => InputKind::Prompt,
+                    other => anyhow::bail!(
+                        "classifier.default_kind: expected shell or prompt, got `{}`",
+                        other
+                    ),
+                },
+            )
+            // As `is_interactive`, and a single `exec` input has nobody watching to answer either
+            .with_interactive(
+                args.output == OutputMode::Text
+                    && !config.auto_approve
+                    && !args.dry_run
+                    && args.execute.is_none()
+                    && !matches!(args.command, Some(Command::Exec { .. })),
+            ),
+            output: args.output,
+            render: match args.output {
+                OutputMode::Text => Renderer::for_stdout(args.no_color),
+                OutputMode::Json => Renderer::plain(),
+            },
+            auto_approve: config.auto_approve,
+            dry_run: args.dry_run,
+            force: args.force,
+            jobs,
+            background_steps: HashMap::new(),
+            kill_jobs_on_exit: config.jobs_kill_on_exit,
+            follow_up_window: Duration::from_secs(config.follow_up_window_secs),
+            answer_questions: config.answer_questions,
+            planning_timeout: config.model_timeouts(&config.provider).0,
+            audit: config.audit_enabled.then(|| {
+                AuditLog::new(
+                    config.data_dir.join("audit"),
+                    config.audit_max_file_mb * 1024 * 1024,
+                    config.audit_max_files,
+                )
+            }),
+            redactor: config.secret_redactor(),
+            guard,
+            force_new_session: args.new_session,
+            resumed: None,
+        })
+    }
+
+    /// The executor `config` describes; fails when its sandbox cannot run here.
+    fn build_executor(
+        config: &ParsecConfig,
+        metrics: &Arc<dyn Metrics>,
+    ) -> Result<SafeExecutor, anyhow::Error> {
+        let sandbox = sandbox::named(&config.sandbox, &config.sandbox_image)
+            .map_err(|e| anyhow::anyhow!("executor.sandbox: {}", e))?;
+        let executor = SafeExecutor::new()
+            .with_metrics(metrics.clone())
+            .with_timeout(Duration::from_secs(config.executor_timeout_secs))
+            .with_max_output_size(config.max_output_size)
+            .with_job_dir(config.data_dir.join("jobs"))
+            .with_output_sanitizing(config.output_sanitize)
+            .with_secret_redaction(config.secret_redactor())
+            .with_allowed_rules(config.allowed_rules.clone())
+            .with_shell(config.shell.clone())
+            .with_sandbox(sandbox)
+            .with_sandbox_network(config.sandbox_network)
+            .with_block_threshold(config.block_threshold)
+            .with_preflight(PreflightOptions {
+                min_free_bytes: config.min_free_disk_mb * 1024 * 1024,
+                network_probe: Some(config.network_probe.clone()).filter(|probe| !probe.is_empty()),
+                ..PreflightOptions::default()
+            });
+        Ok(if config.output_keep_raw {
+            executor.with_raw_output_dir(config.data_dir.join("raw-output"))
+        } else {
+            executor
         })
     }
 
+    /// Write the `started` audit record for a command about to run in `working_directory`,
+    /// at `step` of `conversation` if it belongs to a workflow, with the text typed to
+    /// confirm it if it is high-risk.
+    fn audit_start(
+        &self,
+        session_id: &str,
+        working_directory: &std::path::Path,
+        step: Option<(&ConversationContext, usize)>,
+        command: &str,
+        approval: Approval,
+        confirmation: Option<&str>,
+    ) -> Option<AuditRecord> {
+        let audit = self.audit.as_ref()?;
+        let record = AuditRecord::started(
+            session_id,
+            step.map(|(conversation, _)| conversation.id.as_str()),
+            step.map(|(conversation, index)| conversation.steps[index].step.id.as_str()),
+            command,
+            working_directory,
+            approval,
+        )
+        .with_confirmation(confirmation);
+        audit.append(&record);
+        Some(record)
+    }
+
+    /// Write the `finished` record for a command `audit_start` logged.
+    fn audit_finish(
+        &self,
+        started: Option<AuditRecord>,
+        exit_status: Option<i32>,
+        duration: Duration,
+        error: Option<String>,
+    ) {
+        if let (Some(audit), Some(started)) = (&self.audit, started) {
+            audit.append(&started.finished(exit_status, duration, error));
+        }
+    }
+
+    fn audit_attempt(&self, started: Option<AuditRecord>, attempt: &CommandAttempt) {
+        self.audit_finish(
+            started,
+            attempt.exit_status,
+            attempt.duration,
+            attempt.error.as_ref().map(|e| e.to_string()),
+        );
+    }
+
+    /// Whether a human is expected to answer questions on stdin.
+    fn is_interactive(&self) -> bool {
+        self.output == OutputMode::Text && !self.auto_approve && !self.dry_run
+    }
+
+    fn say(&self, message: std::fmt::Arguments) {
+        match self.output {
+            OutputMode::Text => println!("{}", message),
+            OutputMode::Json => eprintln!("{}", message),
+        }
+    }
+
+    /// Print a machine-readable record; no-op outside `--output json`.
+    fn emit(&self, record: OutputRecord) {
+        if self.output != OutputMode::Json {
+            return;
+        }
+        match serde_json::to_string(&record) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!("Failed to serialize output record: {}", e),
+        }
+    }
+
+    /// Show `prompt` (on stderr in JSON mode) and read one line from stdin.
+    fn read_line(&self, prompt: &str) -> Result<String, anyhow::Error> {
+        match self.output {
+            OutputMode::Text => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+            }
+            OutputMode::Json => {
+                eprint!("{}", prompt);
+                io::stderr().flush()?;
+            }
+        }
+
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        Ok(line)
+    }
+
+    /// One input at the `parsec> ` prompt. A line ending in `\` continues on the next, and
+    /// one opening with `"""` takes everything up to the closing `"""`; line breaks are kept.
+    /// At a terminal the first line is edited with `line_editor`, recalling from the
+    /// namespace of the previous input.
+    fn read_input(&self) -> Result<String, anyhow::Error> {
+        let line = if self.output == OutputMode::Text
+            && io::stdin().is_terminal()
+            && io::stdout().is_terminal()
+        {
+            let namespace = self
+                .current_session
+                .as_ref()
+                .and_then(|session| session.last_input_kind.as_ref())
+                .map(Namespace::of)
+                .unwrap_or(Namespace::Shell);
+            line_editor::read_line("parsec> ", &self.input_history, namespace)?
+        } else {
+            self.read_line("parsec> ")?
+        };
+        if let Some(rest) = line.trim_start().strip_prefix(MULTI_LINE_QUOTE) {
+            let mut input = String::new();
+            let mut line = rest.to_string();
+            // An empty read is the end of stdin
+            while !line.is_empty() {
+                if let Some(end) = line.find(MULTI_LINE_QUOTE) {
+                    input.push_str(&line[..end]);
+                    break;
+                }
+                input.push_str(&line);
+                line = self.read_line("... ")?;
+            }
+            return Ok(input);
+        }
+
+        let mut input = line;
+        while input.trim_end().ends_with('\\') {
+            let line = self.read_line("... ")?;
+            if line.is_empty() {
+                break;
+            }
+            input.push_str(&line);
+        }
+        Ok(input)
+    }
+
+    fn report_store_warnings(&self) {
+        for warning in self.session_store.take_warnings() {
+            warn!("{}", warning);
+            say!(self, "{}", self.render.warning(warning));
+        }
+    }
+
     fn get_or_create_session(
         &mut self,
         working_dir: PathBuf,
     ) -> Result<&mut Session, anyhow::Error> {
         if self.current_session.is_none() {
-            let session_id = Uuid::new_v4().to_string();
-            let now = Utc::now();
-
-            let session = Session {
-                id: session_id,
-                created_at: now,
-                last_active: now,
-                conversations: Vec::new(),
-                command_history: Vec::new(),
-                global_context: GlobalContext {
-                    working_directory: working_dir,
-                    environment_snapshot: env::vars().collect(),
-                    detected_project_type: None, // TODO: Implement project detection
-                    active_tools: Self::detect_tools(),
-                },
-                settings: SessionSettings::default(),
+            let session = match self.resume_session(&working_dir) {
+                Some(session) => session,
+                None => self.new_session(working_dir),
             };
 
-            self.session_store.save_session(&session)?;
+            logging::set_private(session.settings.privacy_mode);
+            self.save_session(&session)?;
             self.current_session = Some(session);
         }
 
         Ok(self.current_session.as_mut().unwrap())
     }
 
-    fn get_session(&self, session_id: &str) -> Option<Session> {
-        if let Some(session) = &self.current_session {
-            if session.id == session_id {
-                return Some(session.clone());
-            }
+    /// Settings for a session of this run, from the configuration.
+    fn session_settings(&self) -> SessionSettings {
+        SessionSettings {
+            session_retention_days: self.retention.session_retention_days,
+            conversation_retention_days: self.retention.conversation_retention_days,
+            max_sessions: self.retention.max_sessions,
+            tracked_tools: self.tracked_tools.clone(),
+            max_attempts_per_step: self.max_attempts_per_step,
+            rate_limits: self.rate_limits,
+            history_limit: self.history_limit,
+            history_full_output: self.history_full_output,
+            context_compression_threshold: self.context_compression_threshold,
+            ..SessionSettings::default()
         }
-        self.session_store
-            .load_session(&session_id.to_string())
-            .ok()
     }
 
-    fn update_session(&mut self, session: Session) -> Result<(), anyhow::Error> {
-        self.session_store.save_session(&session)?;
-        if let Some(current) = &mut self.current_session {
-            if current.id == session.id {
-                *current = session;
+    fn new_session(&mut self, working_dir: PathBuf) -> Session {
+        let now = Utc::now();
+        let detected_project_type = self.project_detector.detect(&working_dir);
+        let settings = self.session_settings();
+        let previous = self.latest_session();
+        let (active_tools, tools_cache_key) = self.detect_tools(&settings, previous.as_ref());
+        let (classification_corrections, step_timings) = previous
+            .map(|session| {
+                let corrections = if settings.enable_cross_conversation_learning {
+                    session.classification_corrections
+                } else {
+                    Vec::new()
+                };
+                (corrections, session.step_timings)
+            })
+            .unwrap_or_default();
+
+        Session {
+            schema_version: SCHEMA_VERSION,
+            id: Uuid::new_v4().to_string(),
+            created_at: now,
+            last_active: now,
+            conversations: Vec::new(),
+            command_history: Vec::new(),
+            global_context: GlobalContext {
+                working_directory: working_dir,
+                environment_snapshot: self.env_filter.apply(env::vars()),
+                detected_project_type,
+                active_tools,
+                tools_cache_key: Some(tools_cache_key),
+            },
+            settings,
+            last_input_kind: None,
+            classification_log: Vec::new(),
+            classification_corrections,
+            context_items: Vec::new(),
+            provider_health: None,
+            step_timings,
+        }
+    }
+
+    /// The most recently active session of `working_dir` or the closest directory above
+    /// it that has one, unless it expired or `--new-session` was given. It moves to
+    /// `working_dir` and takes this run's environment and configuration.
+    fn resume_session(&mut self, working_dir: &Path) -> Option<Session> {
+        if self.force_new_session {
+            return None;
+        }
+        let fresh = |app: &Self, reason: String| {
+            warn!("{}", reason);
+            say!(
+                app,
+                "{}",
+                app.render
+                    .warning(format!("{}; starting a new session", reason))
+            );
+        };
+        if !working_dir.is_dir() {
+            fresh(
+                self,
+                format!("{} is not a directory", working_dir.display()),
+            );
+            return None;
+        }
+        let summaries = match self.session_store.find_sessions_by_directory(working_dir) {
+            Ok(summaries) => summaries,
+            Err(e) => {
+                fresh(self, format!("Could not look up earlier sessions: {}", e));
+                return None;
+            }
+        };
+        let cutoff =
+            Utc::now() - chrono::Duration::days(self.retention.session_retention_days as i64);
+        let summary = summaries
+            .into_iter()
+            .find(|summary| summary.last_active > cutoff)?;
+        // The session's directory may have been moved or deleted since, with the
+        // working directory a path that only looks like it is inside
+        if !summary.working_directory.is_dir() {
+            fresh(
+                self,
+                format!(
+                    "Session {} was started in {}, which no longer exists",
+                    summary.id,
+                    summary.working_directory.display()
+                ),
+            );
+            return None;
+        }
+        let mut session = match self.session_store.load_session(&summary.id) {
+            Ok(session) => session,
+            Err(e) => {
+                fresh(
+                    self,
+                    format!("Could not resume session {}: {}", summary.id, e),
+                );
+                return None;
             }
+        };
+
+        let previously_active = session.last_active;
+        session.settings = SessionSettings {
+            max_conversation_history: session.settings.max_conversation_history,
+            enable_cross_conversation_learning: session.settings.enable_cross_conversation_learning,
+            privacy_mode: session.settings.privacy_mode,
+            ..self.session_settings()
+        };
+        let (active_tools, tools_cache_key) = self.detect_tools(&session.settings, Some(&session));
+        session.last_active = Utc::now();
+        session.global_context = GlobalContext {
+            working_directory: working_dir.to_path_buf(),
+            environment_snapshot: self.env_filter.apply(env::vars()),
+            detected_project_type: self.project_detector.detect(working_dir),
+            active_tools,
+            tools_cache_key: Some(tools_cache_key),
+        };
+        say!(
+            self,
+            "Resuming session {} from {}, last active {} (--new-session starts a fresh one)",
+            session.id,
+            summary.working_directory.display(),
+            previously_active.format("%Y-%m-%d %H:%M:%S")
+        );
+        info!(session_id = %session.id, "Resumed the session of the working directory");
+        self.resumed = Some(ResumedSession {
+            at: session.last_active,
+            previously_active,
+        });
+        Some(session)
+    }
+
+    /// The session `session_id`, taken out of `current_session` rather than copied, or
+    /// loaded. Hand it back with `update_session`, or `restore_session` when unchanged.
+    fn take_session(&mut self, session_id: &str) -> Option<Session> {
+        match self
+            .current_session
+            .take_if(|session| session.id == session_id)
+        {
+            Some(session) => Some(session),
+            None => self
+                .session_store
+                .load_session(&session_id.to_string())
+                .ok(),
+        }
+    }
+
+    /// Put back a session `take_session` gave out without saving it.
+    fn restore_session(&mut self, session: Session) {
+        if self.current_session.is_none() {
+            self.current_session = Some(session);
         }
+    }
+
+    /// Save `session`; changes not saved yet are reported to the guard as they are made.
+    fn save_session(&self, session: &Session) -> Result<(), StoreError> {
+        self.session_store.save_session(session)?;
+        self.guard.session_saved(&session.id);
         Ok(())
     }
 
-    fn detect_tools() -> Vec<String> {
-        let tools = vec![
-            "git", "cargo", "npm", "python", "node", "docker", "kubectl", "make", "cmake", "gcc",
-            "clang", "rustc", "javac", "mvn",
-        ];
+    fn update_session(&mut self, session: Session) -> Result<(), anyhow::Error> {
+        self.save_session(&session)?;
+        self.restore_session(session);
+        Ok(())
+    }
 
-        tools
-            .into_iter()
-            .filter(|tool| {
-                std::process::Command::new("which")
-                    .arg(tool)
-                    .output()
-                    .map(|output| output.status.success())
-                    .unwrap_or(false)
-            })
-            .map(|s| s.to_string())
-            .collect()
+    /// The most recently active persisted session.
+    fn latest_session(&self) -> Option<Session> {
+        self.session_store
+            .list_active_sessions()
+            .ok()
+            .and_then(|sessions| sessions.into_iter().max_by_key(|s| s.last_active))
+            .and_then(|summary| self.session_store.load_session(&summary.id).ok())
+    }
+
+    /// Tools on `$PATH`, reusing the `previous` session's result when neither
+    /// `$PATH` nor the tracked tool list changed since it was detected.
+    fn detect_tools(
+        &self,
+        settings: &SessionSettings,
+        previous: Option<&Session>,
+    ) -> (Vec<String>, u64) {
+        let cache_key = tools::tool_cache_key(&settings.tracked_tools);
+
+        match previous.filter(|session| session.global_context.tools_cache_key == Some(cache_key)) {
+            Some(session) => (session.global_context.active_tools.clone(), cache_key),
+            None => (tools::detect_tools(&settings.tracked_tools), cache_key),
+        }
     }
 
     async fn run_interactive(&mut self, working_dir: PathBuf) -> Result<(), anyhow::Error> {
-        println!("Parsec Interactive Mode");
-        println!("Working directory: {}", working_dir.display());
-        println!("Type 'exit' to quit, 'help' for help\n");
+        say!(self, "Parsec Interactive Mode");
+        say!(self, "Working directory: {}", working_dir.display());
+        say!(self, "Type 'exit' to quit, 'help' for help\n");
 
         let session = self.get_or_create_session(working_dir)?;
         let session_id = session.id.clone();
+        self.input_history.load(&session_id);
+        if self.is_interactive() {
+            let mut session = self
+                .take_session(&session_id)
+                .expect("Session should exist");
+            self.offer_resume(&mut session).await?;
+            self.update_session(session)?;
+        }
 
         loop {
-            print!("parsec> ");
-            io::stdout().flush()?;
+            let session = self
+                .take_session(&session_id)
+                .expect("Session should exist");
+            self.reap_jobs(&session);
+            // Builtins never reach `run_classified`
+            if let Some(typed) = self.typed_input.take() {
+                self.input_history
+                    .record(Namespace::Shell, &typed, &session);
+            }
+            self.restore_session(session);
 
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            let input = input.trim();
+            let input = self.read_input()?;
+            // Ctrl-D, or the end of piped input
+            let input = if input.is_empty() {
+                "exit"
+            } else {
+                input.trim()
+            };
 
             if input.is_empty() {
                 continue;
             }
+            self.typed_input = Some(input.to_string());
 
             match input {
                 "exit" | "quit" => {
-                    println!("Goodbye!");
+                    let session = self
+                        .take_session(&session_id)
+                        .expect("Session should exist");
+                    self.shutdown_jobs(&session);
+                    self.restore_session(session);
+                    say!(self, "Goodbye!");
                     break;
                 }
-                "help" => {
-                    Self::print_help();
+                "jobs" => {
+                    self.print_jobs();
+                    continue;
+                }
+                "help" | "?" => {
+                    self.print_help();
                     continue;
                 }
                 "status" => {
-                    let session = self.get_session(&session_id).expect("Session should exist");
-                    self.print_status(&session)?;
+                    let session = self
+                        .take_session(&session_id)
+                        .expect("Session should exist");
+                    let printed = self.print_status(&session);
+                    self.restore_session(session);
+                    printed?;
+                    continue;
+                }
+                "conversations" => {
+                    let filter = ConversationFilter {
+                        session_id: Some(session_id.clone()),
+                        ..Default::default()
+                    };
+                    for (i, conversation) in self
+                        .session_store
+                        .list_conversations(&filter)?
+                        .iter()
+                        .enumerate()
+                    {
+                        for line in conversation_lines(Some(i + 1), conversation) {
+                            say!(self, "{}", line);
+                        }
+                    }
+                    continue;
+                }
+                "prune" => {
+                    let session = self
+                        .take_session(&session_id)
+                        .expect("Session should exist");
+                    let policy = RetentionPolicy::from_settings(&session.settings);
+                    self.restore_session(session);
+                    let report = self
+                        .session_store
+                        .prune_old_context(&policy, Some(&session_id))?;
+                    say!(
+                        self,
+                        "Pruned {} sessions and {} conversations ({} bytes reclaimed)",
+                        report.sessions_removed,
+                        report.conversations_removed,
+                        report.bytes_reclaimed
+                    );
+                    let compacted = self.session_store.compact()?;
+                    if !compacted.is_empty() {
+                        say!(
+                            self,
+                            "Compacted {} commands and {} conversation events ({} bytes reclaimed)",
+                            compacted.commands_removed + compacted.outputs_dropped,
+                            compacted.events_removed,
+                            compacted.bytes_saved
+                        );
+                    }
                     continue;
                 }
                 _ => {}
             }
 
-            let mut session = self
-                .get_session(&session_id)
-                .expect("Session should exist")
-                .clone();
-            if let Err(e) = self.process_input(input, &mut session).await {
-                error!("Error processing input: {}", e);
-                println!("Error: {}", e);
+            if let Some((builtin, id)) = self.job_reference(input) {
+                match builtin {
+                    "tail" => match self.jobs.tail(id, JOB_TAIL_LINES) {
+                        Ok((stdout, stderr)) => {
+                            if !stdout.is_empty() {
+                                say!(self, "{}", stdout);
+                            }
+                            if !stderr.is_empty() {
+                                say!(self, "stderr:\n{}", stderr);
+                            }
+                        }
+                        Err(e) => say!(self, "Tail failed: {}", e),
+                    },
+                    "wait" => {
+                        if let Err(e) = self.jobs.wait(id).await {
+                            say!(self, "Wait failed: {}", e);
+                        }
+                    }
+                    _ => {
+                        if let Err(e) = self.jobs.kill(id) {
+                            say!(self, "Kill failed: {}", e);
+                        }
+                    }
+                }
+                let session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                self.reap_jobs(&session);
+                self.restore_session(session);
+                continue;
             }
-            // Update the session in storage
-            self.update_session(session)?;
-        }
 
-        Ok(())
-    }
+            if let Some(prompt) = strip_plan_preview(input) {
+                if prompt.is_empty() {
+                    say!(self, "Nothing to plan after `plan:`");
+                    continue;
+                }
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.preview_plan(prompt, &mut session, false).await {
+                    error!("Error previewing plan: {}", e);
+                    say!(self, "Error: {}", e);
+                }
+                self.update_session(session)?;
+                self.report_store_warnings();
+                continue;
+            }
 
-    async fn process_input(
-        &mut self,
-        input: &str,
-        session: &mut Session,
-    ) -> Result<(), anyhow::Error> {
-        let classification = self.classifier.classify(input, Some(session))?;
+            if input == "wrong" {
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.correct_previous(&mut session).await {
+                    error!("Error re-running corrected input: {}", e);
+                    say!(self, "Error: {}", e);
+                }
+                self.update_session(session)?;
+                self.report_store_warnings();
+                continue;
+            }
 
-        match classification {
-            InputKind::Shell => {
-                info!("Classified as shell command: {}", input);
-                self.execute_shell_command(input, session)?;
+            if let Some(rest) = input
+                .strip_prefix("fork")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.fork_builtin(rest, &mut session).await {
+                    error!("Fork failed: {}", e);
+                    say!(self, "Fork failed: {}", e);
+                }
+                self.update_session(session)?;
+                self.report_store_warnings();
+                continue;
             }
-            InputKind::Prompt => {
-                info!("Classified as prompt: {}", input);
-                self.handle_prompt(input, session).await?;
+
+            if let Some(rest) = input
+                .strip_prefix("search")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.search_builtin(rest, &mut session).await {
+                    error!("Search failed: {}", e);
+                    say!(self, "Search failed: {}", e);
+                }
+                self.update_session(session)?;
+                self.report_store_warnings();
+                continue;
             }
-        }
 
-        // Update session
-        session.last_active = Utc::now();
-        self.session_store.save_session(session)?;
+            if let Some(rest) = input
+                .strip_prefix("history")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.history_builtin(rest, &session) {
+                    say!(self, "History failed: {}", e);
+                }
+                self.restore_session(session);
+                continue;
+            }
 
-        Ok(())
-    }
+            // Not `export`, which stays the shell's
+            if let Some(rest) = input
+                .strip_prefix("transcript")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.transcript_builtin(rest, &session) {
+                    say!(self, "Export failed: {}", e);
+                }
+                self.restore_session(session);
+                continue;
+            }
 
-    fn execute_shell_command(
-        &mut self,
-        command: &str,
-        session: &mut Session,
-    ) -> Result<(), anyhow::Error> {
-        let executor = SafeExecutor::new();
-        let result =
-            executor.execute_direct_command(command, &session.global_context.working_directory)?;
+            if let Some(rest) = input
+                .strip_prefix("undo")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.undo_builtin(rest, &session) {
+                    say!(self, "Undo failed: {}", e);
+                }
+                self.restore_session(session);
+                self.report_store_warnings();
+                continue;
+            }
 
-        println!("Exit status: {}", result.exit_status);
-        if !result.stdout.content.is_empty() {
-            println!("stdout:\n{}", result.stdout.content);
-        }
-        if !result.stderr.content.is_empty() {
-            println!("stderr:\n{}", result.stderr.content);
-        }
+            if let Some(rest) = input
+                .strip_prefix("rename")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.rename_builtin(rest, &session) {
+                    say!(self, "Rename failed: {}", e);
+                }
+                self.restore_session(session);
+                continue;
+            }
 
-        // Add to command history
-        session.command_history.push(result);
+            if let Some((builtin, rest)) =
+                ["note", "unnote", "pin", "unpin"]
+                    .into_iter()
+                    .find_map(|name| {
+                        input
+                            .strip_prefix(name)
+                            .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+                            .map(|rest| (name, rest))
+                    })
+            {
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.annotate_builtin(builtin, rest, &mut session) {
+                    say!(self, "`{}` failed: {}", builtin, e);
+                }
+                self.update_session(session)?;
+                continue;
+            }
+
+            if let Some((add, rest)) = ["tag", "untag"].into_iter().find_map(|name| {
+                input
+                    .strip_prefix(name)
+                    .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+                    .map(|rest| (name == "tag", rest))
+            }) {
+                let mut session = self
+                    .take_session(&session_id)
+                    .expect("Session should exist");
+                if let Err(e) = self.tag_builtin(rest, add, &mut session) {
+                    say!(self, "Tagging failed: {}", e);
+                }
+                self.update_session(session)?;
+                continue;
+            }
+
+            let Some(input) = self.expand_history(input, &session_id) else {
+                continue;
+            };
+            let mut session = self
+                .take_session(&session_id)
+                .expect("Session should exist");
+            if let Err(e) = self.process_input(&input, &mut session).await {
+                error!("Error processing input: {}", e);
+                say!(self, "Error: {}", e);
+                self.emit(OutputRecord::Error {
+                    message: e.to_string(),
+                });
+            }
+            // Update the session in storage
+            self.update_session(session)?;
+            self.report_store_warnings();
+        }
 
         Ok(())
     }
 
-    async fn handle_prompt(
+    /// `input` with a `!!` or `!N` reference expanded to the command it names, which is
+    /// echoed; None, after saying why, when there is no such command. The history is read
+    /// in place, so the session is left as it was either way.
+    fn expand_history(&mut self, input: &str, session_id: &str) -> Option<String> {
+        let expanded = match self
+            .current_session
+            .as_ref()
+            .filter(|session| session.id == session_id)
+        {
+            Some(session) => history::expand(input, &session.command_history),
+            None => {
+                let session = self.take_session(session_id)?;
+                let expanded = history::expand(input, &session.command_history);
+                self.restore_session(session);
+                expanded
+            }
+        };
+        match expanded {
+            Some(Ok(command)) => {
+                say!(self, "{}", command);
+                Some(command)
+            }
+            Some(Err(e)) => {
+                say!(self, "{}", e);
+                None
+            }
+            None => Some(input.to_string()),
+        }
+    }
+
+    /// `history [--all|--prompts] [<substring>|/<regex>/]`
+    fn history_builtin(&self, args: &str, session: &Session) -> Result<(), anyhow::Error> {
+        let args = history::HistoryArgs::parse(args)?;
+        if args.prompts {
+            let width = history::terminal_width();
+            let mut listed = 0;
+            for (i, entry) in self
+                .input_history
+                .session_entries(Namespace::Prompts)
+                .iter()
+                .enumerate()
+            {
+                if args.filter.as_ref().is_none_or(|f| f.matches(&entry.input)) {
+                    say!(self, "{}", history::render_input(i + 1, entry, width));
+                    listed += 1;
+                }
+            }
+            if listed == 0 {
+                say!(self, "No matching prompts");
+            }
+            return Ok(());
+        }
+
+        let mut entries = history::direct_entries(&session.command_history);
+        if args.all {
+            for conversation_id in &session.conversations {
+                match self.session_store.load_conversation(conversation_id) {
+                    Ok(conversation) => entries.extend(history::workflow_entries(&conversation)),
+                    Err(e) => warn!(
+                        "Skipping conversation {} in history: {}",
+                        conversation_id, e
+                    ),
+                }
+            }
+            entries.sort_by_key(|entry| entry.executed_at);
+        }
+        if let Some(filter) = &args.filter {
+            entries.retain(|entry| filter.matches(&entry.command));
+        }
+
+        if entries.is_empty() {
+            say!(self, "No matching commands");
+            return Ok(());
+        }
+        let width = history::terminal_width();
+        for entry in &entries {
+            say!(self, "{}", entry.render(width));
+        }
+        Ok(())
+    }
+
+    /// `search [--session] <words>`: hits from every saved session (only this one with
+    /// `--session`), `SEARCH_PAGE_SIZE` at a time, each of which can be re-run or have its
+    /// conversation exported.
+    async fn search_builtin(
         &mut self,
-        prompt: &str,
+        args: &str,
         session: &mut Session,
     ) -> Result<(), anyhow::Error> {
-        println!("Creating workflow for: {}", prompt);
+        let (this_session, query) = match args.trim().strip_prefix("--session") {
+            Some(query) => (true, query.trim()),
+            None => (false, args.trim()),
+        };
+        if query.is_empty() {
+            say!(self, "Usage: search [--session] <words>");
+            return Ok(());
+        }
+        // Unsaved commands of this session are searched too
+        self.session_store.save_session(session)?;
+        let scope = SearchScope {
+            session_id: this_session.then(|| session.id.clone()),
+            limit: SEARCH_MAX_HITS,
+            redactor: self.redactor.clone(),
+            ..Default::default()
+        };
+        let hits = self.session_store.search(query, &scope)?;
+        self.report_store_warnings();
+        if hits.is_empty() {
+            say!(self, "No matches");
+            return Ok(());
+        }
 
-        // Create conversation
-        let mut conversation = self
+        let width = history::terminal_width();
+        let mut shown = 0;
+        loop {
+            if shown < hits.len() {
+                let end = (shown + SEARCH_PAGE_SIZE).min(hits.len());
+                for (i, hit) in hits.iter().enumerate().take(end).skip(shown) {
+                    say!(self, "{}", history::render_search_hit(i + 1, hit, width));
+                }
+                shown = end;
+            }
+            if self.output != OutputMode::Text {
+                return Ok(());
+            }
+
+            let more = if shown < hits.len() {
+                "Enter for more, "
+            } else {
+                ""
+            };
+            let answer = self.read_line(&format!(
+                "{}r N to re-run, e N [path] to export, q to finish: ",
+                more
+            ))?;
+            let mut words = answer.split_whitespace();
+            let action = words.next();
+            if action.is_none() && shown < hits.len() {
+                continue;
+            }
+            let (Some(action @ ("r" | "e")), Some(number)) = (action, words.next()) else {
+                return Ok(());
+            };
+            let Some(hit) = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| hits.get(n.checked_sub(1)?))
+            else {
+                say!(self, "No hit {}", number);
+                continue;
+            };
+
+            if action == "e" {
+                let Some(conversation_id) = &hit.conversation_id else {
+                    say!(self, "Hit {} is not part of a conversation", number);
+                    continue;
+                };
+                let args = match words.next() {
+                    Some(path) => format!("{} md {}", conversation_id, path),
+                    None => conversation_id.clone(),
+                };
+                if let Err(e) = self.transcript_builtin(&args, session) {
+                    say!(self, "Export failed: {}", e);
+                }
+                continue;
+            }
+
+            if hit.redacted {
+                say!(
+                    self,
+                    "Hit {} has redacted values and cannot be re-run",
+                    number
+                );
+                continue;
+            }
+            // Re-run it the way it ran before
+            let input = match hit.kind {
+                kind if kind.is_command() => format!("!{}", hit.text),
+                SearchHitKind::ConversationPrompt => format!("?{}", hit.text),
+                _ => {
+                    say!(self, "Only commands and prompts can be re-run");
+                    continue;
+                }
+            };
+            return self.process_input(&input, session).await;
+        }
+    }
+
+    /// `transcript [<conversation-id>|last] [md|json] [<out-path>]`
+    fn transcript_builtin(&self, args: &str, session: &Session) -> Result<(), anyhow::Error> {
+        let mut parts = args.split_whitespace();
+        let conversation_id = match parts.next() {
+            None | Some("last") => session
+                .conversations
+                .last()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No conversations in this session"))?,
+            Some(id) => id.to_string(),
+        };
+        let format = match parts.next() {
+            Some(format) => format.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+            None => ExportFormat::Markdown,
+        };
+
+        let rendered = self
             .orchestrator
-            .create_conversation(&session.id, prompt.to_string())?;
+            .export_conversation(&conversation_id, format)?;
 
-        // Plan workflow
-        self.orchestrator
-            .plan_workflow(&mut conversation, session)
-            .await?;
-        println!("✓ Workflow planned with {} steps", conversation.steps.len());
+        match parts.next() {
+            Some(path) => {
+                std::fs::write(path, rendered)?;
+                say!(
+                    self,
+                    "Exported conversation {} to {}",
+                    conversation_id,
+                    path
+                );
+            }
+            None => say!(self, "{}", rendered),
+        }
+
+        Ok(())
+    }
 
-        // Display workflow
-        println!("\nWorkflow: {}", conversation.name);
-        for (i, step) in conversation.steps.iter().enumerate() {
-            println!("  {}. {}", i + 1, step.step.description);
+    /// `rename <id|number|last> <name>`
+    fn rename_builtin(&self, args: &str, session: &Session) -> Result<(), anyhow::Error> {
+        let Some((reference, name)) = args.trim().split_once(' ') else {
+            say!(self, "Usage: rename <id|number|last> <name>");
+            return Ok(());
+        };
+        let conversation_id = self.conversation_ref(session, reference)?;
+        let conversation = self
+            .orchestrator
+            .rename_conversation(&conversation_id, name)?;
+        say!(
+            self,
+            "Renamed conversation {} to \"{}\"",
+            conversation.id,
+            conversation.name
+        );
+        Ok(())
+    }
+
+    /// `note <ref> [<text>]`, `unnote <ref> [<number>]`, `pin <ref>` and `unpin <ref>`,
+    /// where `ref` is an id, a number from `conversations` or `last`. A note without text
+    /// lists the conversation's notes.
+    fn annotate_builtin(
+        &self,
+        builtin: &str,
+        args: &str,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        let args = args.trim();
+        let (reference, rest) = args.split_once(' ').unwrap_or((args, ""));
+        let rest = rest.trim();
+        if reference.is_empty() {
+            say!(
+                self,
+                "Usage: note <id|number|last> [text], unnote <id|number|last> [note number], \
+                 pin <id|number|last>, unpin <id|number|last>"
+            );
+            return Ok(());
         }
+        let conversation_id = self.conversation_ref(session, reference)?;
+        let conversation = match (builtin, rest) {
+            ("note", "") => self.session_store.load_conversation(&conversation_id)?,
+            ("note", text) => self.orchestrator.add_note(&conversation_id, text)?,
+            ("unnote", "") => self.orchestrator.remove_notes(&conversation_id, None)?,
+            ("unnote", number) => {
+                let number = number
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Expected a note number, got `{}`", number))?;
+                self.orchestrator
+                    .remove_notes(&conversation_id, Some(number))?
+            }
+            (builtin, _) => self
+                .orchestrator
+                .set_pinned(&conversation_id, builtin == "pin")?,
+        };
+        // Its context items carry the notes and the pin
+        if session.conversations.contains(&conversation.id) {
+            session.remember_conversation(&conversation);
+        }
+        for line in conversation_lines(None, &ConversationSummary::of(&conversation)) {
+            say!(self, "{}", line);
+        }
+        Ok(())
+    }
 
-        // Execute workflow interactively
-        self.execute_workflow_interactive(&mut conversation, session)
-            .await?;
+    /// The conversation `reference` names: `last` is the session's most recent one and a
+    /// number counts from 1 down the `conversations` listing; anything else is an id.
+    fn conversation_ref(
+        &self,
+        session: &Session,
+        reference: &str,
+    ) -> Result<ConversationId, anyhow::Error> {
+        if reference == "last" {
+            return session
+                .conversations
+                .last()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No conversations in this session"));
+        }
+        let filter = ConversationFilter {
+            session_id: Some(session.id.clone()),
+            ..Default::default()
+        };
+        listed_conversation(self.session_store.as_ref(), &filter, reference)
+    }
 
+    /// `tag <id|last> [<tag>...]` or, with `add` false, `untag <id|last> <tag>...`; prints
+    /// the tags the conversation ends up with.
+    fn tag_builtin(
+        &self,
+        args: &str,
+        add: bool,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        let mut words = args.split_whitespace();
+        let (Some(id), tags) = (words.next(), words.collect::<Vec<_>>()) else {
+            say!(self, "Usage: tag|untag <id|last> <tag>...");
+            return Ok(());
+        };
+        let conversation_id = match id {
+            "last" => session
+                .conversations
+                .last()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No conversations in this session"))?,
+            id => id.to_string(),
+        };
+        let mut conversation = self.session_store.load_conversation(&conversation_id)?;
+        for tag in tags {
+            conversation = if add {
+                self.orchestrator.add_tag(&conversation_id, tag)?
+            } else {
+                self.orchestrator.remove_tag(&conversation_id, tag)?
+            };
+        }
+        // Its context items carry the tags
+        if session.conversations.contains(&conversation.id) {
+            session.remember_conversation(&conversation);
+        }
+        say!(
+            self,
+            "Conversation {} is tagged: {}",
+            conversation.id,
+            if conversation.tags.is_empty() {
+                "(none)".to_string()
+            } else {
+                conversation.tags.join(", ")
+            }
+        );
         Ok(())
     }
 
-    async fn execute_workflow_interactive(
+    #[instrument(
+        name = "input",
+        skip_all,
+        fields(
+            session_id = %session.id,
+            input_hash = %content_hash(input),
+            kind = field::Empty,
+            conversation_id = field::Empty,
+        )
+    )]
+    async fn process_input(
         &mut self,
-        conversation: &mut ConversationContext,
+        input: &str,
         session: &mut Session,
     ) -> Result<(), anyhow::Error> {
-        conversation.status = ConversationStatus::InProgress;
+        let (mut classification, input, mut overridden) = match parse_override(input) {
+            Some((_, "")) => {
+                say!(self, "Nothing to run after the override prefix");
+                return Ok(());
+            }
+            Some((kind, rest)) => (
+                Classification::new(kind, 1.0, "explicit override prefix"),
+                rest,
+                true,
+            ),
+            None => (self.classify(input, session)?, input, false),
+        };
 
-        while let Some(step_index) = self.orchestrator.get_next_pending_step(conversation) {
-            let step = &conversation.steps[step_index];
-            println!("\n→ Step {}: {}", step_index + 1, step.step.description);
+        // Ask rather than guess when the classifier is unsure
+        if !overridden {
+            match self
+                .disambiguator
+                .disambiguate(input, &classification, session)
+            {
+                Disambiguation::Confident => {}
+                Disambiguation::Remembered(kind) => {
+                    classification = Classification::new(kind, 1.0, "answered for a similar input");
+                    overridden = true;
+                }
+                Disambiguation::Default(kind) => {
+                    info!(
+                        "Unsure ({:.2}) what {} is; using classifier.default_kind",
+                        classification.confidence,
+                        loggable(input)
+                    );
+                    classification = Classification::new(
+                        kind,
+                        classification.confidence,
+                        "unsure; classifier.default_kind",
+                    );
+                }
+                Disambiguation::Ask => {
+                    let kind = self.ask_kind(input)?;
+                    self.record_correction(session, input, kind.clone(), true);
+                    classification = Classification::new(kind, 1.0, "chosen by user");
+                    overridden = true;
+                }
+            }
+        }
 
-            // Generate commands for this step
-            let generated_commands = self
-                .orchestrator
-                .generate_step_commands(conversation, session, step_index)
-                .await?;
+        self.run_classified(input, classification, overridden, session)
+            .await
+    }
 
-            if generated_commands.done {
-                println!("  Step completed without commands.");
-                conversation.steps[step_index].status = StepStatus::Complete;
+    /// Offer to resume the conversations in this directory that an interrupted or crashed
+    /// run left in progress.
+    async fn offer_resume(&mut self, session: &mut Session) -> Result<(), anyhow::Error> {
+        let mut interrupted = Vec::new();
+        for summary in self.session_store.list_active_sessions()? {
+            // The session itself counts too when it was resumed
+            if summary.working_directory != session.global_context.working_directory {
                 continue;
             }
+            let Ok(previous) = self.session_store.load_session(&summary.id) else {
+                continue;
+            };
+            interrupted.extend(
+                previous
+                    .conversations
+                    .iter()
+                    .filter_map(|id| self.session_store.load_conversation(id).ok())
+                    .filter(PromptOrchestrator::was_interrupted),
+            );
+        }
 
-            if generated_commands.commands.is_empty() {
-                warn!("No commands generated for step {}", step_index + 1);
-                conversation.steps[step_index].status = StepStatus::Failed;
+        for mut conversation in interrupted {
+            let next = self
+                .orchestrator
+                .get_next_pending_step(&conversation)
+                .map(|index| format!(" before step {}", index + 1))
+                .unwrap_or_default();
+            let answer = self.read_line(&format!(
+                "Conversation \"{}\" ({}) was interrupted{}. Resume it? (y/N) ",
+                conversation.name, conversation.id, next
+            ))?;
+            if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
                 continue;
             }
+            // A crash leaves the step it was running marked Running
+            if conversation
+                .steps
+                .iter()
+                .any(|step| step.status == StepStatus::Running)
+            {
+                self.orchestrator
+                    .mark_interrupted(&mut conversation, None, "crash")?;
+            }
+            if let Err(e) = self
+                .execute_workflow_interactive(&mut conversation, session)
+                .await
+            {
+                error!("Error resuming {}: {}", conversation.id, e);
+                say!(self, "Error: {}", e);
+            }
+        }
+        Ok(())
+    }
 
-            // Show primary command
-            let primary_command = &generated_commands.commands[0];
-            println!("  Command: {}", primary_command.command);
-            println!("  Explanation: {}", primary_command.explanation);
-
-            if let Some(risk_score) = primary_command.risk_score {
-                if risk_score > 0.3 {
-                    println!("  ⚠️  Risk score: {:.2}", risk_score);
-                }
-            }
-
-            // Ask for approval
-            print!("  Execute? (y/n/a/s) [y=yes, n=no, a=abort, s=skip]: ");
-            io::stdout().flush()?;
-
-            let mut response = String::new();
-            io::stdin().read_line(&mut response)?;
-            let response = response.trim().to_lowercase();
-
-            match response.as_str() {
-                "y" | "yes" | "" => {
-                    // Execute the command
-                    match self.orchestrator.execute_step_command(
-                        conversation,
-                        session,
-                        step_index,
-                        primary_command,
-                    ) {
-                        Ok(attempt) => {
-                            if attempt.error.is_none() {
-                                println!("  ✓ Command executed successfully");
-                                if !attempt.stdout.content.is_empty() {
-                                    println!("  Output: {}", attempt.stdout.content);
-                                }
-                            } else {
-                                println!("  ✗ Command failed: {:?}", attempt.error);
-                                if !attempt.stderr.content.is_empty() {
-                                    println!("  Error: {}", attempt.stderr.content);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to execute command: {}", e);
-                            println!("  ✗ Execution error: {}", e);
-                        }
-                    }
-                }
-                "n" | "no" => {
-                    println!("  Command skipped by user");
-                    conversation.steps[step_index].status = StepStatus::Skipped;
-                }
-                "a" | "abort" => {
-                    println!("  Conversation aborted by user");
-                    self.orchestrator.abort_conversation(conversation)?;
-                    break;
-                }
-                "s" | "skip" => {
-                    println!("  Step skipped by user");
-                    conversation.steps[step_index].status = StepStatus::Skipped;
-                }
-                _ => {
-                    println!("  Invalid response, skipping command");
-                    conversation.steps[step_index].status = StepStatus::Skipped;
-                }
+    #[instrument(
+        skip_all,
+        fields(
+            classifier = %self.classifier_name,
+            kind = field::Empty,
+            confidence = field::Empty,
+        )
+    )]
+    fn classify(
+        &self,
+        input: &str,
+        session: &Session,
+    ) -> Result<Classification, ClassificationError> {
+        let classification = self.classifier.classify(input, Some(session))?;
+        let span = Span::current();
+        span.record("kind", field::debug(&classification.kind));
+        span.record("confidence", classification.confidence);
+        Ok(classification)
+    }
+
+    /// `wrong`: re-run the previous input as the other kind and remember the correction.
+    async fn correct_previous(&mut self, session: &mut Session) -> Result<(), anyhow::Error> {
+        let (Some(input), Some(previous)) =
+            (self.last_input.clone(), session.last_input_kind.clone())
+        else {
+            say!(self, "Nothing to correct yet");
+            return Ok(());
+        };
+
+        let kind = previous.opposite();
+        self.record_correction(session, &input, kind.clone(), false);
+        say!(self, "Re-running as {:?}: {}", kind, input);
+        let classification = Classification::new(kind, 1.0, "corrected by user with `wrong`");
+        self.run_classified(&input, classification, true, session)
+            .await
+    }
+
+    /// Echo an input the classifier is unsure about and ask which kind it is; the end of
+    /// stdin takes `classifier.default_kind`.
+    fn ask_kind(&self, input: &str) -> Result<InputKind, anyhow::Error> {
+        say!(self, "  {}", input);
+        loop {
+            let answer = self.read_line(disambiguation::QUESTION)?;
+            if answer.is_empty() {
+                return Ok(self.disambiguator.default_kind().clone());
             }
+            match disambiguation::parse_answer(&answer) {
+                Some(kind) => return Ok(kind),
+                None => say!(self, "Please answer s or a"),
+            }
+        }
+    }
+
+    /// Remember what kind `input` was; `disambiguated` when the user answered the
+    /// low-confidence question rather than typing `wrong`.
+    fn record_correction(
+        &self,
+        session: &mut Session,
+        input: &str,
+        corrected_to: InputKind,
+        disambiguated: bool,
+    ) {
+        session
+            .classification_corrections
+            .push(ClassificationCorrection {
+                input_hash: input_hash(input),
+                first_word: parsec_classifier::heuristic::command_word(input).unwrap_or_default(),
+                corrected_to,
+                classifier: self.classifier_name.clone(),
+                corrected_at: Utc::now(),
+                disambiguated,
+            });
+    }
 
-            // Update conversation context
-            self.orchestrator
-                .update_session_context(session, conversation)?;
+    async fn run_classified(
+        &mut self,
+        input: &str,
+        classification: Classification,
+        overridden: bool,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        session.classification_log.push(ClassificationRecord {
+            input_hash: input_hash(input),
+            kind: classification.kind.clone(),
+            confidence: classification.confidence,
+            classifier: if overridden {
+                "override".to_string()
+            } else {
+                self.classifier_name.clone()
+            },
+            decided_at: Utc::now(),
+        });
+        self.guard.session_changed(session);
+        self.last_input = Some(input.to_string());
+        if let Some(typed) = self.typed_input.take() {
+            self.input_history
+                .record(Namespace::of(&classification.kind), &typed, session);
         }
 
-        // Print final status
-        let status = self
-            .orchestrator
-            .get_conversation_status_summary(conversation);
-        println!("\nFinal status: {}", status);
+        self.emit(OutputRecord::Classification {
+            input: input.to_string(),
+            kind: classification.kind.clone(),
+            confidence: classification.confidence,
+            reasoning: classification.reasoning.clone(),
+            overridden,
+        });
+
+        session.last_input_kind = Some(classification.kind.clone());
+        Span::current().record("kind", field::debug(&classification.kind));
+        match classification.kind {
+            InputKind::Shell => {
+                info!("Classified as shell command: {}", loggable(input));
+                self.execute_shell_command(input, session, overridden)?;
+            }
+            InputKind::Prompt => {
+                info!("Classified as prompt: {}", loggable(input));
+                self.handle_prompt(&strip_continuations(input), session, overridden)
+                    .await?;
+            }
+        }
+
+        // Update session
+        session.last_active = Utc::now();
+        self.save_session(session)?;
 
         Ok(())
     }
 
-    fn print_help() {
-        println!(
-            r#"
-Parsec Help:
-  Shell commands: Execute directly (ls, git status, cargo build, etc.)
-  Natural language: Create AI-assisted workflows ("create a new Rust project")
-  
-  Special commands:
-    help     - Show this help
-    status   - Show current session status  
-    exit     - Exit the application
-"#
-        );
+    /// Whether to run a command that needs a terminal attached to this one, for `reason`.
+    /// Only asked when parsec itself runs on a terminal with text output.
+    fn ask_attach(&self, reason: &str) -> Result<bool, anyhow::Error> {
+        if self.output != OutputMode::Text || !io::stdin().is_terminal() {
+            return Ok(false);
+        }
+        println!("{} {}", self.render.warning("Needs a terminal:"), reason);
+        let line = self.read_line("Type `yes` to run it attached to this one: ")?;
+        Ok(Confirmation::Terminal {
+            reason: reason.to_string(),
+        }
+        .accepts(&line))
     }
 
-    fn print_status(&self, session: &Session) -> Result<(), anyhow::Error> {
-        println!("Session Status:");
-        println!("  ID: {}", session.id);
-        println!(
-            "  Created: {}",
-            session.created_at.format("%Y-%m-%d %H:%M:%S")
-        );
-        println!(
-            "  Last active: {}",
-            session.last_active.format("%Y-%m-%d %H:%M:%S")
-        );
-        println!(
-            "  Working directory: {}",
-            session.global_context.working_directory.display()
+    fn execute_shell_command(
+        &mut self,
+        command: &str,
+        session: &mut Session,
+        overridden: bool,
+    ) -> Result<(), anyhow::Error> {
+        let audit = self.audit_start(
+            &session.id,
+            &session.global_context.working_directory,
+            None,
+            command,
+            Approval::User,
+            None,
         );
-        println!(
-            "  Active tools: {}",
-            session.global_context.active_tools.join(", ")
+        let input = match self.executor.needs_terminal(command, None) {
+            Some(reason) if self.ask_attach(&reason)? => ExecutionInput::Terminal,
+            _ => ExecutionInput::Closed,
+        };
+        let started = Instant::now();
+        let mut result = match self.executor.execute_direct_command(
+            command,
+            &session.global_context.working_directory,
+            input.clone(),
+        ) {
+            Ok(result) => result,
+            Err(e) => {
+                self.audit_finish(audit, None, started.elapsed(), Some(e.to_string()));
+                return Err(e.into());
+            }
+        };
+        self.audit_finish(
+            audit,
+            Some(result.exit_status),
+            result.duration,
+            match result.termination {
+                Some(CommandTermination::Exited(0)) | None => None,
+                Some(termination) => Some(termination.to_string()),
+            },
         );
-        println!("  Commands executed: {}", session.command_history.len());
-        println!("  Active conversations: {}", session.conversations.len());
 
-        if let Some(project_type) = &session.global_context.detected_project_type {
-            println!("  Project type: {}", project_type);
+        self.emit(OutputRecord::Execution {
+            conversation_id: None,
+            step_index: None,
+            command: result.command.clone(),
+            executed: true,
+            exit_status: Some(result.exit_status),
+            risk_score: None,
+            stdout: result.stdout.clone(),
+            stderr: result.stderr.clone(),
+        });
+
+        let outcome = match result.termination {
+            Some(termination @ CommandTermination::Signaled { .. }) => format!(
+                "Command {} after {}",
+                termination,
+                format_duration(result.duration)
+            ),
+            _ => format!(
+                "Exit status: {} ({})",
+                result.exit_status,
+                format_duration(result.duration)
+            ),
+        };
+        if result.exit_status == 0 {
+            say!(self, "{}", self.render.success(outcome));
+        } else {
+            say!(self, "{}", self.render.failure(outcome));
+        }
+        // Attached commands showed their output as they ran
+        if !result.stdout.content.is_empty() && input != ExecutionInput::Terminal {
+            say!(
+                self,
+                "  stdout:\n{}",
+                self.render.output_block(&result.stdout.content)
+            );
+        }
+        if !result.stderr.content.is_empty() {
+            say!(
+                self,
+                "  stderr:\n{}",
+                self.render.output_block(&result.stderr.content)
+            );
         }
 
-        Ok(())
-    }
-}
+        // Add to command history
+        result.classification_overridden = overridden;
+        session.remember_command(&result);
+        session.command_history.push(result);
+        session.compact();
+        self.guard.session_changed(session);
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    // Load .env file if it exists
-    if let Err(_) = dotenvy::dotenv() {
-        // .env file not found or couldn't be loaded, continue without it
-    }
+        // The command may have created or removed project files
+        session.global_context.detected_project_type = self
+            .project_detector
+            .detect(&session.global_context.working_directory);
 
-    env_logger::init();
-    let args = Args::parse();
+        Ok(())
+    }
 
-    let working_dir = args
-        .working_dir
-        .as_ref()
-        .map(|p| p.clone())
-        .unwrap_or_else(|| env::current_dir().expect("Failed to get current directory"));
+    async fn handle_prompt(
+        &mut self,
+        prompt: &str,
+        session: &mut Session,
+        overridden: bool,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(question) = self.question(prompt)? {
+            return self.answer_question(question, session, overridden).await;
+        }
+        let prompt = strip_question(prompt).unwrap_or(prompt);
+        let (prompt, parent) = match strip_follow_up(prompt) {
+            Some("") => anyhow::bail!("Nothing to plan after `followup:`"),
+            Some(rest) => {
+                let parent = session
+                    .conversations
+                    .last()
+                    .ok_or_else(|| anyhow::anyhow!("No conversation to follow up on"))?;
+                (rest, Some(parent.clone()))
+            }
+            None => (prompt, self.offer_follow_up(session)?),
+        };
+        self.orchestrator.check_provider(session).await?;
+        say!(self, "Creating workflow for: {}", prompt);
 
-    let mut app = ParsecApp::new(&args)?;
+        // Create conversation
+        let mut conversation = match &parent {
+            Some(parent) => self
+                .orchestrator
+                .create_follow_up(parent, prompt.to_string())?,
+            None => self
+                .orchestrator
+                .create_conversation(&session.id, prompt.to_string())?,
+        };
+        conversation.classification_overridden = overridden;
+        Span::current().record("conversation_id", conversation.id.as_str());
+        self.attach_references(&mut conversation, session)?;
 
-    if let Some(command) = args.execute {
-        // Execute single command and exit
-        let mut session = app.get_or_create_session(working_dir)?.clone();
-        app.process_input(&command, &mut session).await?;
-        app.update_session(session)?;
-    } else {
-        // Interactive mode
-        app.run_interactive(working_dir).await?;
+        // Plan workflow
+        if !self.plan_or_cancel(&mut conversation, session).await? {
+            return Ok(());
+        }
+        self.run_planned(&mut conversation, session).await
     }
 
-    Ok(())
+    /// The question `prompt` asks, if it is to be answered rather than planned: after a
+    /// `??` prefix, or phrased as one with `classifier.answer_questions` on. Providers
+    /// that do not answer questions plan them.
+    fn question<'a>(&self, prompt: &'a str) -> Result<Option<&'a str>, anyhow::Error> {
+        let question = match strip_question(prompt) {
+            Some("") => anyhow::bail!("Nothing to ask after `??`"),
+            Some(question) => question,
+            None if self.answer_questions
+                && strip_follow_up(prompt).is_none()
+                && intent::intent(prompt) == PromptIntent::Question =>
+            {
+                prompt
+            }
+            None => return Ok(None),
+        };
+        if !self.orchestrator.answers_questions() {
+            warn!("The model provider does not answer questions; planning one");
+            return Ok(None);
+        }
+        Ok(Some(question))
+    }
+
+    /// Attach what the prompt of `conversation` refers to, saying what was attached.
+    fn attach_references(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+    ) -> Result<(), anyhow::Error> {
+        let skipped = self.orchestrator.attach_references(conversation, session)?;
+        // Manifests left out are in the conversation's history; they would show every prompt
+        for file in skipped {
+            info!("Context file {} not attached: {}", file.path, file.reason);
+        }
+        if !conversation.attachments.is_empty() {
+            let sources: Vec<&str> = conversation
+                .attachments
+                .iter()
+                .map(|attachment| attachment.source.as_str())
+                .collect();
+            say!(
+                self,
+                "{}",
+                self.render.dim(format!("Attached: {}", sources.join(", ")))
+            );
+        }
+        Ok(())
+    }
+
+    /// Answer `question` in a conversation of its own, without a workflow.
+    async fn answer_question(
+        &mut self,
+        question: &str,
+        session: &mut Session,
+        overridden: bool,
+    ) -> Result<(), anyhow::Error> {
+        self.orchestrator.check_provider(session).await?;
+        let mut conversation = self
+            .orchestrator
+            .create_conversation(&session.id, question.to_string())?;
+        conversation.classification_overridden = overridden;
+        Span::current().record("conversation_id", conversation.id.as_str());
+        self.attach_references(&mut conversation, session)?;
+
+        let (orchestrator, asked_in, asked) = (&self.orchestrator, &*session, &mut conversation);
+        let answered = self
+            .with_cancel_keys(|cancel| async move {
+                orchestrator
+                    .answer_question(asked, asked_in, &cancel, None)
+                    .await
+            })
+            .await;
+        let answer = match answered {
+            Err(OrchestratorError::Plan(PlanError::Cancelled)) => {
+                say!(self, "{}", self.render.warning("Question cancelled"));
+                return Ok(());
+            }
+            answered => answered?,
+        };
+        self.orchestrator
+            .update_session_context(session, &conversation)?;
+        self.guard.session_changed(session);
+
+        self.emit(OutputRecord::Answer {
+            conversation_id: conversation.id.clone(),
+            question: question.to_string(),
+            answer: answer.clone(),
+        });
+        say!(self, "\n{}\n", answer);
+        Ok(())
+    }
+
+    /// The session's last conversation, if it finished within `follow_up_window` and the
+    /// user, when asked, wants to follow up on it.
+    fn offer_follow_up(&self, session: &Session) -> Result<Option<ConversationId>, anyhow::Error> {
+        let Some(id) = session.conversations.last() else {
+            return Ok(None);
+        };
+        if self.follow_up_window.is_zero() {
+            return Ok(None);
+        }
+        let Ok(last) = self.session_store.load_conversation(id) else {
+            return Ok(None);
+        };
+        let since = (Utc::now() - last.last_activity())
+            .to_std()
+            .unwrap_or_default();
+        if last.status != ConversationStatus::Finished || since > self.follow_up_window {
+            return Ok(None);
+        }
+        if self.is_interactive() {
+            let answer = self.read_line(&format!(
+                "Follow up on \"{}\", which just finished? (Y/n) ",
+                last.name
+            ))?;
+            if matches!(answer.trim().to_lowercase().as_str(), "n" | "no") {
+                return Ok(None);
+            }
+        }
+        say!(self, "Following up on \"{}\"", last.name);
+        Ok(Some(last.id))
+    }
+
+    /// `tail N`, `wait N` or `kill N` (or `%N`) naming a known job; anything else, such
+    /// as `kill 1234` or `tail -f log`, is left to the shell.
+    fn job_reference<'a>(&self, input: &'a str) -> Option<(&'a str, JobId)> {
+        let mut words = input.split_whitespace();
+        let builtin = words
+            .next()
+            .filter(|word| ["tail", "wait", "kill"].contains(word))?;
+        let id = words.next()?;
+        let id: JobId = id.strip_prefix('%').unwrap_or(id).parse().ok()?;
+        (words.next().is_none() && self.jobs.contains(id)).then_some((builtin, id))
+    }
+
+    fn print_jobs(&self) {
+        let jobs = self.jobs.list();
+        if jobs.is_empty() {
+            say!(self, "No background jobs");
+        }
+        for job in jobs {
+            say!(
+                self,
+                "[{}] {:<8} {:>7}  {}",
+                job.id,
+                job.state.to_string(),
+                format_duration(job.duration),
+                job.command
+            );
+        }
+    }
+
+    /// Report background jobs that finished and record the ones that ran workflow steps.
+    fn reap_jobs(&mut self, session: &Session) {
+        for (job, outcome) in self.jobs.take_finished() {
+            say!(
+                self,
+                "[{}] {} after {}: {}",
+                job.id,
+                outcome.termination,
+                format_duration(job.duration),
+                job.command
+            );
+            let Some(mut step) = self.background_steps.remove(&job.id) else {
+                continue;
+            };
+            self.audit_finish(
+                step.audit.take(),
+                match outcome.termination {
+                    CommandTermination::Exited(code) => Some(code),
+                    CommandTermination::Signaled { .. } => Some(-1),
+                    CommandTermination::TimedOut => None,
+                },
+                outcome.duration,
+                (outcome.termination != CommandTermination::Exited(0))
+                    .then(|| outcome.termination.to_string()),
+            );
+            if let Err(e) = self.finish_background_step(&step, &outcome, session) {
+                error!("Failed to record job {}: {}", job.id, e);
+                say!(self, "  Could not record job {} in its step: {}", job.id, e);
+            }
+        }
+    }
+
+    fn finish_background_step(
+        &self,
+        step: &BackgroundStep,
+        outcome: &JobOutcome,
+        session: &Session,
+    ) -> Result<(), anyhow::Error> {
+        let mut conversation = self
+            .session_store
+            .load_conversation(&step.conversation_id)?;
+        let execution = self.orchestrator.finish_background_command(
+            &mut conversation,
+            session,
+            &step.handle,
+            &step.command,
+            outcome,
+        )?;
+        let step_index = step.handle.index_in(&conversation)?;
+        say!(
+            self,
+            "  Step {} of {} is now {:?}",
+            step_index + 1,
+            conversation.name,
+            conversation.steps[step_index].status
+        );
+        if let StepExecution::Exhausted(_) = execution {
+            say!(
+                self,
+                "  It used up its {} attempts; the conversation needs a skip, replan or abort",
+                session.settings.max_attempts_per_step
+            );
+        }
+        Ok(())
+    }
+
+    /// Kill the background jobs still running, or leave them be, per `jobs.kill_on_exit`.
+    fn shutdown_jobs(&mut self, session: &Session) {
+        let running = self.jobs.shutdown(self.kill_jobs_on_exit);
+        if self.kill_jobs_on_exit {
+            self.reap_jobs(session);
+            return;
+        }
+        for job in running {
+            say!(self, "[{}] left running: {}", job.id, job.command);
+        }
+    }
+
+    /// After a timeout, offer to rerun the command with a longer one; unattended runs don't.
+    fn ask_longer_timeout(&self) -> Result<Option<Duration>, anyhow::Error> {
+        if !self.is_interactive() {
+            return Ok(None);
+        }
+        loop {
+            let answer = self.read_line("  Retry with a longer timeout? (5m/15m/1h/N): ")?;
+            match answer.trim().to_lowercase().as_str() {
+                "5m" => return Ok(Some(Duration::from_secs(5 * 60))),
+                "15m" => return Ok(Some(Duration::from_secs(15 * 60))),
+                "1h" => return Ok(Some(Duration::from_secs(60 * 60))),
+                "" | "n" | "no" => return Ok(None),
+                _ => say!(self, "  Please answer 5m, 15m, 1h or n"),
+            }
+        }
+    }
+
+    fn print_help(&self) {
+        say!(
+            self,
+            r#"
+Parsec Help:
+  Shell commands: Execute directly (ls, git status, cargo build, etc.)
+  Natural language: Create AI-assisted workflows ("create a new Rust project")
+  Force a choice:   !<command> always runs in the shell,
+                    ?<request> or ai: <request> always starts a workflow
+  Follow up:        followup: <request> plans with what the last
+                    conversation did; right after one finishes, parsec
+                    offers this for any request
+  Preview a plan:   plan: <request> shows the steps with the risk each
+                    looks to carry, runs nothing and saves nothing;
+                    `parsec plan --save` keeps one
+  Several lines:    end a line with \ to continue on the next, or
+                    wrap the input in """ ... """
+  
+  Special commands:
+    help, ?  - Show this help
+    status   - Show current session status  
+    prune    - Remove sessions and conversations past retention and
+               compact the history of the rest
+    wrong    - Re-run the previous input the other way and remember it
+    fork [id|last] [new prompt]
+             - Start a new conversation from an earlier one's plan,
+               adapted to the new prompt if one is given
+    transcript [id|last] [md|json] [path]
+             - Export a conversation transcript, as `parsec export`
+               does; `export` itself is left to the shell
+    conversations
+             - List this session's conversations, pinned ones first,
+               numbered for the commands below
+    rename <id|number|last> <name>
+             - Rename a conversation; parsec no longer renames it
+    note <id|number|last> [text], unnote <id|number|last> [N]
+             - Attach a note to a conversation or list its notes;
+               remove note N, or all of them
+    pin <id|number|last>, unpin <id|number|last>
+             - Keep a conversation through pruning, list it first and
+               weigh it higher as context for later requests
+    undo [id|last]
+             - Offer the undo commands recorded with a conversation's
+               most recent step; they run only after a yes
+    tag <id|last> [tag...], untag <id|last> <tag>...
+             - Show, add or remove conversation tags; `parsec
+               conversations --tag <tag>` lists those with a tag
+    history [--all|--prompts] [text|/regex/]
+             - List earlier commands, optionally filtered; --all adds
+               commands run by workflows, --prompts lists the requests
+               typed in this session instead
+    search [--session] <words>
+             - Find commands, steps and prompts in every saved session,
+               then re-run or export what was found
+    !!, !N   - Re-run the previous command, or command N from history
+    jobs     - List background jobs; answer b at a step prompt to start one
+    tail N, wait N, kill N
+             - Show the latest output of job N, wait for it, or kill it
+    exit     - Exit the application
+"#
+        );
+    }
+
+    fn print_status(&self, session: &Session) -> Result<(), anyhow::Error> {
+        say!(self, "Session Status:");
+        say!(self, "  ID: {}", session.id);
+        say!(
+            self,
+            "  Created: {}",
+            session.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+        say!(
+            self,
+            "  Last active: {}",
+            session.last_active.format("%Y-%m-%d %H:%M:%S")
+        );
+        say!(
+            self,
+            "  Working directory: {}",
+            session.global_context.working_directory.display()
+        );
+        say!(
+            self,
+            "  Active tools: {}",
+            session.global_context.active_tools.join(", ")
+        );
+        say!(
+            self,
+            "  Commands executed: {}",
+            session.command_history.len()
+        );
+        say!(
+            self,
+            "  Active conversations: {}",
+            session.conversations.len()
+        );
+
+        if let Some(project_type) = &session.global_context.detected_project_type {
+            say!(self, "  Project type: {}", project_type);
+        }
+        if let Some(resumed) = &self.resumed {
+            say!(
+                self,
+                "  Resumed automatically: at {} (previously active {})",
+                resumed.at.format("%Y-%m-%d %H:%M:%S"),
+                resumed.previously_active.format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    // Load .env file if it exists
+    if dotenvy::dotenv().is_err() {
+        // .env file not found or couldn't be loaded, continue without it
+    }
+
+    let args = Args::parse();
+    init_logging(&args)?;
+
+    if let Some(Command::Completions { shell }) = &args.command {
+        print_completions(*shell);
+        return Ok(());
+    }
+
+    let working_dir = args
+        .working_dir
+        .clone()
+        .unwrap_or_else(|| env::current_dir().expect("Failed to get current directory"));
+
+    let config = ParsecConfig::load(
+        &working_dir,
+        CliOverrides {
+            classifier: args.classifier.clone().or_else(|| {
+                args.use_huggingface_classifier
+                    .then(|| "huggingface".to_string())
+            }),
+            python_script: args.classifier_script.clone(),
+            auto_approve: args.auto_approve.then_some(true),
+            data_dir: args.data_dir.clone(),
+            cache_enabled: args.no_cache.then_some(false),
+            record_prompts: args.debug_prompts.then_some(true),
+        },
+    )?;
+    for warning in &config.warnings {
+        warn!("{}", warning);
+        eprintln!("⚠️  {}", warning);
+    }
+
+    if let Some(Command::Config { action }) = &args.command {
+        match action {
+            ConfigAction::Show => {
+                for (key, value, source) in config.entries() {
+                    println!("{} = {}  # {}", key, value, source);
+                }
+            }
+            ConfigAction::Set { key, value } => {
+                let path = ParsecConfig::set_user_value(key, value)?;
+                println!("Set {} in {}", key, path.display());
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Sessions { action }) = &args.command {
+        let store = open_store(&config)?;
+        let redactor = SecretRedactor::new().with_allowlist(config.output_secret_allowlist.clone());
+        return run_sessions(&store, action.as_ref(), args.output, &redactor);
+    }
+
+    if let Some(Command::Conversations {
+        action,
+        session,
+        tags,
+    }) = &args.command
+    {
+        let store = open_store(&config)?;
+        if let Some(action) = action {
+            return run_conversations_action(&store, action, args.output);
+        }
+        let filter = ConversationFilter {
+            session_id: session.clone(),
+            tags: tags.clone(),
+        };
+        return run_conversations(&store, &filter, args.output);
+    }
+
+    if let Some(Command::Prompts {
+        action: PromptsAction::Show { name },
+    }) = &args.command
+    {
+        return run_prompts_show(name);
+    }
+
+    if let Some(Command::Debug {
+        action: DebugAction::Prompts { conversation_id },
+    }) = &args.command
+    {
+        return run_debug_prompts(&open_store(&config)?, conversation_id);
+    }
+
+    if let Some(Command::Audit { action }) = &args.command {
+        return run_audit(&config.data_dir.join("audit"), action);
+    }
+
+    if let Some(Command::Stats { reset }) = &args.command {
+        return run_stats(&config.data_dir.join(METRICS_FILE), *reset, args.output);
+    }
+
+    if let Some(Command::Search {
+        query,
+        session,
+        limit,
+        page,
+    }) = &args.command
+    {
+        let scope = SearchScope {
+            session_id: session.clone(),
+            limit: limit * page,
+            redactor: config.secret_redactor(),
+            ..Default::default()
+        };
+        return run_search(
+            &open_store(&config)?,
+            &query.join(" "),
+            &scope,
+            (page.saturating_sub(1)) * limit,
+            args.output,
+        );
+    }
+
+    if let Some(Command::Classify {
+        action,
+        input,
+        explain,
+    }) = &args.command
+    {
+        return match (action, input) {
+            (Some(ClassifyAction::Stats), _) => run_classify_stats(&open_store(&config)?),
+            (None, Some(input)) => run_classify(&config, input, *explain),
+            (None, None) => Err(anyhow::anyhow!("Nothing to classify")),
+        };
+    }
+
+    if args.doctor || matches!(args.command, Some(Command::Doctor)) {
+        if args.doctor {
+            eprintln!("⚠️  --doctor is deprecated; use `parsec doctor`");
+        }
+        return run_doctor(&args, &config, Renderer::for_stdout(args.no_color)).await;
+    }
+
+    if let Some(Command::Export {
+        conversation_id,
+        format,
+        out,
+    }) = &args.command
+    {
+        let store = open_store(&config)?;
+        let rendered =
+            parsec_prompt::export::export_conversation(&store, conversation_id, *format)?;
+        match out {
+            Some(path) => std::fs::write(path, rendered)?,
+            None => println!("{}", rendered),
+        }
+        return Ok(());
+    }
+
+    let task = match &args.command {
+        Some(Command::Task { action }) => {
+            match run_task_action(&config, &working_dir, action, args.output)? {
+                Some(task) => Some(task),
+                None => return Ok(()),
+            }
+        }
+        _ => None,
+    };
+
+    // A preview runs nothing, so needs no approval
+    let preview = match &args.command {
+        Some(Command::Plan { prompt, save }) => Some((prompt.join(" "), *save)),
+        _ => None,
+    };
+
+    if preview.is_none() && args.output == OutputMode::Json && !config.auto_approve && !args.dry_run
+    {
+        return Err(anyhow::anyhow!(
+            "--output json disables interactive approval; pass --auto-approve or --dry-run"
+        ));
+    }
+
+    let input = match &args.command {
+        Some(Command::Exec { input }) if input == &["-"] => {
+            let input = io::read_to_string(io::stdin())?;
+            if input.trim().is_empty() {
+                anyhow::bail!("Nothing to run: stdin was empty");
+            }
+            Some(input.trim().to_string())
+        }
+        Some(Command::Exec { input }) => Some(input.join(" ")),
+        _ => args.execute.clone().inspect(|_| {
+            eprintln!("⚠️  --execute is deprecated; use `parsec exec <input>`");
+        }),
+    };
+    let mut app = ParsecApp::new(&args, &config)?;
+    app.guard.install()?;
+
+    if input.is_some() || task.is_some() || preview.is_some() {
+        // Execute single command, task or preview and exit
+        let session_id = app.get_or_create_session(working_dir)?.id.clone();
+        let mut session = app.take_session(&session_id).expect("Session should exist");
+        let result = match (&task, &input, &preview) {
+            (_, _, Some((prompt, save))) => app.preview_plan(prompt, &mut session, *save).await,
+            (Some((name, task)), _, _) => app.run_task(name, task, &mut session).await,
+            (None, command, None) => {
+                app.process_input(command.as_deref().unwrap_or_default(), &mut session)
+                    .await
+            }
+        };
+        app.shutdown_jobs(&session);
+        app.update_session(session)?;
+        app.guard.flush_metrics();
+        if let Err(e) = result {
+            let (message, code) = execute_failure(&e);
+            app.emit(OutputRecord::Error {
+                message: message.clone(),
+            });
+            eprintln!("Error: {}", message);
+            std::process::exit(code);
+        }
+    } else {
+        // Interactive mode
+        let result = app.run_interactive(working_dir).await;
+        app.guard.flush_metrics();
+        result?;
+    }
+
+    Ok(())
+}
+
+/// `input` without the `\` that continued each line onto the next, keeping the line breaks.
+fn strip_continuations(input: &str) -> String {
+    input
+        .lines()
+        .map(|line| {
+            line.trim_end()
+                .strip_suffix('\\')
+                .map(str::trim_end)
+                .unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Comma-separated paths of the artifacts with `change`, directories marked with a
+/// trailing `/`, or `None` if there are none.
+fn artifact_list(artifacts: &[ArtifactInfo], change: ArtifactChange) -> Option<String> {
+    const SHOWN: usize = 10;
+    let paths: Vec<String> = artifacts
+        .iter()
+        .filter(|artifact| artifact.change == change)
+        .map(|artifact| {
+            let path = artifact.file_path.display();
+            if artifact.artifact_type == "directory" {
+                format!("{}/", path)
+            } else {
+                path.to_string()
+            }
+        })
+        .collect();
+    if paths.is_empty() {
+        return None;
+    }
+    let mut list = paths[..paths.len().min(SHOWN)].join(", ");
+    if paths.len() > SHOWN {
+        list.push_str(&format!(" and {} more", paths.len() - SHOWN));
+    }
+    Some(list)
+}
+
+/// User-facing message and exit code for a failed `--execute` run, so scripts can tell
+/// a transient model outage from a broken store or an unusable conversation.
+fn execute_failure(e: &anyhow::Error) -> (String, i32) {
+    let Some(e) = e.downcast_ref::<OrchestratorError>() else {
+        return (e.to_string(), 1);
+    };
+    match e {
+        OrchestratorError::Plan(PlanError::Timeout(_) | PlanError::ModelError(_))
+        | OrchestratorError::CommandGen(
+            CommandGenError::Timeout(_) | CommandGenError::ModelError(_),
+        ) => (
+            format!("{}; the model is unavailable, try again later", e),
+            3,
+        ),
+        OrchestratorError::Plan(_) | OrchestratorError::CommandGen(_) => {
+            (format!("{}; the model response could not be used", e), 4)
+        }
+        OrchestratorError::Store(StoreError::Conflict { .. }) => {
+            (format!("{}; another parsec instance is using it", e), 10)
+        }
+        OrchestratorError::Store(_) | OrchestratorError::Serialization(_) => {
+            (format!("Session store unavailable: {}", e), 5)
+        }
+        OrchestratorError::Execution(_) => (e.to_string(), 6),
+        OrchestratorError::AttemptsExhausted { .. } => (e.to_string(), 7),
+        OrchestratorError::ConversationNotResumable { .. } => (e.to_string(), 8),
+        OrchestratorError::InvalidStepIndex(_)
+        | OrchestratorError::StepNotFound(_)
+        | OrchestratorError::StepAlreadyStarted(_)
+        | OrchestratorError::StepNotFailed(_)
+        | OrchestratorError::NotForked
+        | OrchestratorError::InvalidPlanEdit(_)
+        | OrchestratorError::InvalidName(_)
+        | OrchestratorError::InvalidTag(_)
+        | OrchestratorError::InvalidNote(_)
+        | OrchestratorError::Binding(_) => (e.to_string(), 9),
+        OrchestratorError::Preflight(_) => (e.to_string(), 11),
+        OrchestratorError::Health(HealthError::Network(_) | HealthError::Quota(_)) => {
+            (e.to_string(), 3)
+        }
+        OrchestratorError::Health(_) => (e.to_string(), 12),
+    }
+}
+
+fn load_prompt_templates() -> PromptTemplates {
+    match ParsecConfig::user_prompts_dir() {
+        Some(dir) => PromptTemplates::load(&dir),
+        None => PromptTemplates::builtin(),
+    }
+}
+
+fn run_prompts_show(name: &str) -> Result<(), anyhow::Error> {
+    let spec = prompts::template_spec(name).ok_or_else(|| {
+        let names: Vec<&str> = prompts::TEMPLATES.iter().map(|spec| spec.name).collect();
+        anyhow::anyhow!(
+            "Unknown prompt template: {} (expected one of: {})",
+            name,
+            names.join(", ")
+        )
+    })?;
+    let templates = load_prompt_templates();
+
+    eprintln!("# {} template ({})", spec.name, templates.origin(spec.name));
+    eprintln!("# variables: {}", spec.variables.join(", "));
+    println!("{}", templates.source(spec.name).unwrap_or(spec.builtin));
+    Ok(())
+}
+
+/// The session store in the data directory, encrypted as `storage.encryption` says.
+fn open_store(config: &ParsecConfig) -> Result<FileSessionStore, anyhow::Error> {
+    let store = FileSessionStore::new(&config.data_dir)?;
+    match config.storage_encryption.as_str() {
+        "none" => Ok(store),
+        "passphrase" => {
+            let passphrase = match env::var("PARSEC_STORE_PASSPHRASE") {
+                Ok(passphrase) => passphrase,
+                Err(_) if io::stdin().is_terminal() => {
+                    rpassword::prompt_password("Session store passphrase: ")?
+                }
+                Err(_) => anyhow::bail!(
+                    "storage.encryption is \"passphrase\": set PARSEC_STORE_PASSPHRASE or run in a terminal"
+                ),
+            };
+            Ok(store.with_passphrase(&passphrase)?)
+        }
+        #[cfg(feature = "keyring")]
+        "keyring" => {
+            let key = parsec_model::StoreKey::from_keyring(&config.data_dir)?;
+            Ok(store.with_key(&key)?)
+        }
+        #[cfg(not(feature = "keyring"))]
+        "keyring" => Err(anyhow::anyhow!(
+            "storage.encryption is \"keyring\" but parsec was built without the `keyring` feature"
+        )),
+        other => Err(anyhow::anyhow!(
+            "Unknown storage.encryption: {} (expected none, passphrase or keyring)",
+            other
+        )),
+    }
+}
+
+/// The configured provider, wrapped in a `FallbackProvider` when fallbacks are configured.
+/// Embedding vectors, if `embeddings.provider` is set, are kept in `vectors`.
+fn build_model_provider(
+    config: &ParsecConfig,
+    api_key: Option<&str>,
+    metrics: &Arc<dyn Metrics>,
+    vectors: Arc<dyn VectorStore>,
+) -> Result<Arc<dyn ModelProvider>, anyhow::Error> {
+    let google_client = || -> Result<GoogleAiClient, anyhow::Error> {
+        match config.model_auth.as_str() {
+            "api_key" => {
+                let api_key = api_key.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Google AI API key required. Set --api-key or GOOGLE_AI_API_KEY env var, or model.auth = \"vertex_adc\" for Vertex AI"
+                    )
+                })?;
+                Ok(GoogleAiClient::new(api_key.to_string())?)
+            }
+            "vertex_adc" => Ok(GoogleAiClient::with_vertex(
+                config.vertex_project.clone(),
+                config.vertex_location.clone(),
+            )?),
+            other => Err(anyhow::anyhow!(
+                "Unknown model.auth: {} (expected api_key or vertex_adc)",
+                other
+            )),
+        }
+    };
+
+    // One cache for every provider; keys include the model name
+    let cache = config.cache_enabled.then(|| {
+        let cache = ResponseCache::new(
+            config.cache_capacity,
+            chrono::Duration::seconds(config.cache_ttl_secs as i64),
+        )
+        .allow_private(config.cache_allow_with_privacy_mode);
+        Arc::new(if config.cache_disk {
+            cache.with_disk_dir(config.data_dir.join("cache"))
+        } else {
+            cache
+        })
+    });
+
+    let templates = Arc::new(load_prompt_templates());
+
+    // One index for every provider, so fallbacks rank context the same way
+    let embeddings = match config.embeddings_provider.as_str() {
+        "none" => None,
+        "google-ai" => Some(Arc::new(
+            EmbeddingIndex::new(Arc::new(
+                google_client()?
+                    .with_embedding_model(config.embeddings_model.clone())
+                    .with_metrics(metrics.clone()),
+            ))
+            .with_store(vectors)
+            .with_eviction(
+                config.embeddings_max_vectors,
+                chrono::Duration::days(config.embeddings_max_age_days as i64),
+            ),
+        )),
+        other => {
+            return Err(anyhow::anyhow!(
+                "Unknown embeddings.provider: {} (expected none or google-ai)",
+                other
+            ))
+        }
+    };
+
+    // The model a provider runs: for `azure-openai`, the deployment
+    let model_name = |name: &str, model: Option<&str>| -> String {
+        match (name, model) {
+            (_, Some(model)) => model.to_string(),
+            ("azure-openai", None) => {
+                azure_setting(&config.azure_deployment, "AZURE_OPENAI_DEPLOYMENT", "")
+            }
+            (_, None) => config.model.clone(),
+        }
+    };
+    let provider = |name: &str, model: &str| -> Result<Arc<dyn ModelProvider>, anyhow::Error> {
+        let client: Arc<dyn ModelClient> = match name {
+            "google-ai" => {
+                let client = google_client()?
+                    .with_model(model.to_string())
+                    .with_metrics(metrics.clone());
+                Arc::new(match &cache {
+                    Some(cache) => client.with_cache(cache.clone()),
+                    None => client,
+                })
+            }
+            "azure-openai" => {
+                let api_key = env::var("AZURE_OPENAI_API_KEY").map_err(|_| {
+                    anyhow::anyhow!(
+                        "AZURE_OPENAI_API_KEY environment variable required for Azure OpenAI"
+                    )
+                })?;
+                let deployment = AzureDeployment {
+                    resource: azure_setting(&config.azure_resource, "AZURE_OPENAI_RESOURCE", ""),
+                    deployment: model.to_string(),
+                    api_version: azure_setting(
+                        &config.azure_api_version,
+                        "AZURE_OPENAI_API_VERSION",
+                        AZURE_OPENAI_API_VERSION,
+                    ),
+                };
+                let client =
+                    AzureOpenAiClient::new(api_key, deployment)?.with_metrics(metrics.clone());
+                Arc::new(match &cache {
+                    Some(cache) => client.with_cache(cache.clone()),
+                    None => client,
+                })
+            }
+            other => return Err(anyhow::anyhow!("Unknown model provider: {}", other)),
+        };
+        let (planning_timeout, command_timeout) = config.model_timeouts(name);
+        let provider = GoogleAiProvider::with_client(client)
+            .with_timeouts(planning_timeout, command_timeout)
+            .with_templates(templates.clone())
+            .with_prompt_budget(PromptBudget {
+                max_chars: config.prompt_budget_chars,
+                verbatim_steps: config.verbatim_history_steps,
+                shell_history: ShellHistory {
+                    max_commands: config.shell_history_commands,
+                    ..ShellHistory::default()
+                },
+                ..PromptBudget::default()
+            })
+            .with_command_candidates(config.command_candidates)
+            .with_secret_redaction(config.secret_redactor());
+        Ok(Arc::new(match &embeddings {
+            Some(index) => provider.with_embeddings(index.clone()),
+            None => provider,
+        }))
+    };
+
+    let primary_model = model_name(&config.provider, None);
+    let primary = provider(&config.provider, &primary_model)?;
+    if config.model_fallbacks.is_empty() {
+        return Ok(primary);
+    }
+
+    let mut fallback =
+        FallbackProvider::new(format!("{}:{}", config.provider, primary_model), primary);
+    for entry in &config.model_fallbacks {
+        let (name, model) = match entry.split_once(':') {
+            Some((name, model)) => (name, model_name(name, Some(model))),
+            None => (entry.as_str(), model_name(entry, None)),
+        };
+        fallback = fallback.with_fallback(format!("{}:{}", name, model), provider(name, &model)?);
+    }
+    Ok(Arc::new(fallback))
+}
+
+/// API version of Azure OpenAI requests when neither the config nor the environment
+/// names one.
+const AZURE_OPENAI_API_VERSION: &str = "2024-06-01";
+
+/// An `[azure_openai]` setting, else the environment variable `var`, else `default`.
+fn azure_setting(configured: &str, var: &str, default: &str) -> String {
+    Some(configured.to_string())
+        .filter(|value| !value.is_empty())
+        .or_else(|| env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| default.to_string())
+}
+
+fn huggingface_classifier(config: &ParsecConfig) -> Result<HuggingFaceClassifier, anyhow::Error> {
+    let token = env::var("HUGGINGFACE_API_TOKEN").map_err(|_| {
+        anyhow::anyhow!(
+            "HUGGINGFACE_API_TOKEN environment variable required for Hugging Face classifier"
+        )
+    })?;
+    Ok(HuggingFaceClassifier::new(token)?
+        .with_max_loading_wait(Duration::from_secs(config.loading_wait_secs))
+        .with_max_loading_retries(config.loading_retries))
+}
+
+fn build_classifier(config: &ParsecConfig) -> Result<Box<dyn CommandClassifier>, anyhow::Error> {
+    match config.classifier.as_str() {
+        "huggingface" => Ok(Box::new(huggingface_classifier(config)?)),
+        "heuristic" => Ok(Box::new(heuristic_classifier(config))),
+        "ensemble" => Ok(Box::new(
+            EnsembleClassifier::new(
+                heuristic_classifier(config),
+                Arc::new(huggingface_classifier(config)?),
+            )
+            .with_shell_cutoff(config.shell_cutoff)
+            .with_prompt_cutoff(config.prompt_cutoff)
+            .with_timeout(Duration::from_millis(config.remote_timeout_ms)),
+        )),
+        #[cfg(feature = "local")]
+        "local" => Ok(Box::new(
+            parsec_classifier::LocalClassifier::new(&config.local_model_dir)
+                .with_heuristic(heuristic_classifier(config)),
+        )),
+        #[cfg(not(feature = "local"))]
+        "local" => Err(anyhow::anyhow!(
+            "The local classifier requires building parsec with `--features local`"
+        )),
+        #[cfg(feature = "python")]
+        "python" => {
+            let python = match &config.python_script {
+                Some(script) => parsec_classifier::PythonClassifier::with_script_path(script),
+                None => parsec_classifier::PythonClassifier::new(),
+            };
+            match python {
+                Ok(python) => Ok(Box::new(python)),
+                Err(e) => {
+                    warn!("Python classifier unavailable, using heuristic: {}", e);
+                    eprintln!("⚠️  Python classifier unavailable, using heuristic: {}", e);
+                    Ok(Box::new(heuristic_classifier(config)))
+                }
+            }
+        }
+        #[cfg(not(feature = "python"))]
+        "python" => Err(anyhow::anyhow!(
+            "The python classifier requires building parsec with `--features python`"
+        )),
+        other => Err(anyhow::anyhow!("Unknown classifier: {}", other)),
+    }
+}
+
+/// The heuristic with the config's word-list changes applied and `$PATH` snapshotted.
+fn heuristic_classifier(config: &ParsecConfig) -> HeuristicClassifier {
+    HeuristicClassifier::default()
+        .with_extra_shell_commands(config.extra_shell_commands.iter().cloned())
+        .without_shell_commands(config.removed_shell_commands.iter().cloned())
+        .with_extra_prompt_indicators(config.extra_prompt_indicators.iter().cloned())
+        .without_prompt_indicators(config.removed_prompt_indicators.iter().cloned())
+        .with_path_executables(tools::path_executables())
+}
+
+fn run_classify(config: &ParsecConfig, input: &str, explain: bool) -> Result<(), anyhow::Error> {
+    let classification = build_classifier(config)?.classify(input, None)?;
+    println!(
+        "{:?} (confidence {:.2})",
+        classification.kind, classification.confidence
+    );
+    if let Some(reasoning) = &classification.reasoning {
+        println!("  {}", reasoning);
+    }
+
+    if explain {
+        if config.classifier != "heuristic" {
+            println!(
+                "\nHeuristic signals (the {} classifier decided):",
+                config.classifier
+            );
+        } else {
+            println!("\nSignals:");
+        }
+        let signals = heuristic_classifier(config).signals(input, None);
+        if signals.is_empty() {
+            println!("  none");
+        }
+        for signal in &signals {
+            println!("  {:+.2}  {}", signal.weight, signal.name);
+        }
+        let score: f32 = signals.iter().map(|s| s.weight).sum();
+        println!("  total {:+.2} (positive = shell)", score);
+    }
+
+    Ok(())
+}
+
+/// `stats`: totals from the metrics file, across every run that recorded them.
+fn run_stats(path: &std::path::Path, reset: bool, output: OutputMode) -> Result<(), anyhow::Error> {
+    if reset {
+        match std::fs::remove_file(path) {
+            Ok(()) => println!("Deleted {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => println!("No metrics recorded"),
+            Err(e) => return Err(e.into()),
+        }
+        return Ok(());
+    }
+    let snapshot = MetricsSnapshot::load(path)?;
+    if output == OutputMode::Json {
+        println!("{}", serde_json::to_string(&snapshot)?);
+        return Ok(());
+    }
+    if snapshot == MetricsSnapshot::default() {
+        println!("No metrics recorded yet");
+        return Ok(());
+    }
+    let mean_ms = |histogram: Histogram| match histogram.mean() {
+        Some(mean) => format!("{:.1} ms", mean),
+        None => "-".to_string(),
+    };
+
+    let calls = snapshot.counter_by(metrics::MODEL_CALLS, "provider");
+    println!("Model calls: {}", calls.values().sum::<u64>());
+    for (provider, count) in &calls {
+        let count_of = |name: &str, label: (&str, &str)| {
+            snapshot.counter(name, &[("provider", provider), label])
+        };
+        println!(
+            "  {}: {} ({} cached, {} failed), ~{} tokens sent, ~{} received, mean latency {}",
+            provider,
+            count,
+            count_of(metrics::MODEL_CALLS, ("outcome", "cached")),
+            count_of(metrics::MODEL_CALLS, ("outcome", "error")),
+            count_of(metrics::MODEL_TOKENS, ("direction", "prompt")),
+            count_of(metrics::MODEL_TOKENS, ("direction", "response")),
+            mean_ms(snapshot.histogram(metrics::MODEL_LATENCY_MS, &[("provider", provider)])),
+        );
+    }
+
+    let executions = snapshot.counter_by(metrics::EXECUTIONS, "exit_class");
+    println!("Executions: {}", executions.values().sum::<u64>());
+    for (exit_class, count) in &executions {
+        println!("  {}: {}", exit_class, count);
+    }
+
+    let classifications = snapshot.counter_by(metrics::CLASSIFICATIONS, "kind");
+    println!(
+        "Classifications: {} (mean latency {})",
+        classifications.values().sum::<u64>(),
+        mean_ms(snapshot.histogram(metrics::CLASSIFICATION_LATENCY_MS, &[]))
+    );
+    for (kind, count) in &classifications {
+        println!("  {}: {}", kind, count);
+    }
+
+    let planning = snapshot.histogram(metrics::PLANNING_LATENCY_MS, &[]);
+    println!(
+        "Plans: {} (mean latency {})",
+        planning.count,
+        mean_ms(planning)
+    );
+    Ok(())
+}
+
+/// `audit tail` and `audit search`: commands from the audit log, oldest first.
+fn run_audit(dir: &std::path::Path, action: &AuditAction) -> Result<(), anyhow::Error> {
+    let commands = audit::read_commands(dir)?;
+    let shown: Vec<&AuditRecord> = match action {
+        AuditAction::Tail { lines } => commands[commands.len().saturating_sub(*lines)..]
+            .iter()
+            .collect(),
+        AuditAction::Search { pattern } => {
+            let pattern = regex::Regex::new(pattern)?;
+            commands
+                .iter()
+                .filter(|record| pattern.is_match(&record.command))
+                .collect()
+        }
+    };
+    if commands.is_empty() {
+        println!(
+            "No commands in the audit log at {}; enable it with audit.enabled",
+            dir.display()
+        );
+    }
+    for record in shown {
+        println!("{}", record.render());
+    }
+    Ok(())
+}
+
+/// `debug prompts`: the `model_exchange` events of a conversation.
+fn run_debug_prompts(
+    store: &FileSessionStore,
+    conversation_id: &ConversationId,
+) -> Result<(), anyhow::Error> {
+    let conversation = store.load_conversation(conversation_id)?;
+    let exchanges: Vec<&ConversationEvent> = conversation
+        .history
+        .iter()
+        .filter(|event| event.event_type == "model_exchange")
+        .collect();
+    if exchanges.is_empty() {
+        println!(
+            "No prompts recorded for {}; enable them with --debug-prompts or debug.record_prompts",
+            conversation_id
+        );
+        return Ok(());
+    }
+
+    let text = |value: &serde_json::Value| -> Option<String> {
+        let text: TruncatedText = serde_json::from_value(value.clone()).ok()?;
+        Some(if text.truncated {
+            format!(
+                "{}\n[truncated from {} chars]",
+                text.content, text.original_length
+            )
+        } else {
+            text.content
+        })
+    };
+    for (i, event) in exchanges.iter().enumerate() {
+        let step = match event.data["step_index"].as_u64() {
+            Some(index) => format!(", step {}", index + 1),
+            None => String::new(),
+        };
+        println!(
+            "=== #{} {}{} at {} ===",
+            i + 1,
+            event.data["operation"].as_str().unwrap_or("unknown"),
+            step,
+            event.timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        println!("--- prompt ---");
+        println!("{}", text(&event.data["prompt"]).unwrap_or_default());
+        println!("--- response ---");
+        println!(
+            "{}\n",
+            text(&event.data["response"]).unwrap_or_else(|| "(call failed)".to_string())
+        );
+    }
+    Ok(())
+}
+
+/// Human-readable logs on stderr and JSON lines in `--log-file`. Spans are logged as
+/// they close with how long they took, which times each input, plan, generation and
+/// command without timers of their own.
+fn init_logging(args: &Args) -> Result<(), anyhow::Error> {
+    use tracing_subscriber::fmt::{self, format::FmtSpan};
+    use tracing_subscriber::prelude::*;
+
+    let filter = |default: &str| match &args.log_level {
+        Some(directives) => tracing_subscriber::EnvFilter::try_new(directives)
+            .map_err(|e| anyhow::anyhow!("Invalid --log-level `{}`: {}", directives, e)),
+        None => Ok(tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default))),
+    };
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(!args.no_color && io::stderr().is_terminal())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_filter(filter("error")?);
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("Cannot open log file {}: {}", path.display(), e))?;
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_span_list(true)
+                    .with_filter(filter("info")?),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .try_init()?;
+    Ok(())
+}
+
+/// `completions`: the clap-generated script for `shell`. Fish can run a command for each
+/// completion, so its script also offers the saved session ids to `sessions delete` and
+/// `sessions archive`.
+fn print_completions(shell: Shell) {
+    let mut command = Args::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name.clone(), &mut io::stdout());
+    if shell == Shell::Fish {
+        println!(
+            "complete -c {name} -n '__fish_seen_subcommand_from sessions; and \
+__fish_seen_subcommand_from delete archive' -f -a '({name} sessions list --ids 2>/dev/null)'"
+        );
+    }
+}
+
+/// `sessions`: list saved sessions, most recently active first, or delete or archive one.
+fn run_sessions(
+    store: &FileSessionStore,
+    action: Option<&SessionsAction>,
+    output: OutputMode,
+    redactor: &SecretRedactor,
+) -> Result<(), anyhow::Error> {
+    match action.unwrap_or(&SessionsAction::List { ids: false }) {
+        SessionsAction::List { ids } => {
+            let mut sessions = store.list_active_sessions()?;
+            sessions.sort_by_key(|summary| std::cmp::Reverse(summary.last_active));
+            for summary in &sessions {
+                if output == OutputMode::Json {
+                    println!("{}", serde_json::to_string(summary)?);
+                } else if *ids {
+                    println!("{}", summary.id);
+                } else {
+                    println!(
+                        "{}  {}  {} conversation(s)  {}",
+                        summary.id,
+                        summary.last_active.format("%Y-%m-%d %H:%M:%S"),
+                        summary.conversation_count,
+                        summary.working_directory.display()
+                    );
+                }
+            }
+        }
+        SessionsAction::Delete { session_id } => {
+            let conversations = store.delete_session(session_id)?;
+            println!(
+                "Deleted session {} and {} conversation(s)",
+                session_id, conversations
+            );
+        }
+        SessionsAction::Archive { session_id } => {
+            let conversations = store.archive_session(session_id)?;
+            println!(
+                "Archived session {} and {} conversation(s) under {}",
+                session_id,
+                conversations,
+                store
+                    .root()
+                    .join(parsec_model::file_store::ARCHIVE_DIR)
+                    .display()
+            );
+        }
+        SessionsAction::Export { session_id, out } => {
+            let export = bundle::export(store, session_id, redactor)?;
+            for warning in &export.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            std::fs::write(out, export.bundle.to_json()?)?;
+            println!(
+                "Exported session {} and {} conversation(s) to {}",
+                session_id,
+                export.bundle.conversations.len(),
+                out.display()
+            );
+        }
+        SessionsAction::Import { bundle, map_paths } => {
+            let text = std::fs::read_to_string(bundle)
+                .map_err(|e| anyhow::anyhow!("{}: {}", bundle.display(), e))?;
+            let bundle = SessionBundle::parse(&text)?;
+            let mut paths = PathMap::new();
+            for (old, new) in map_paths {
+                paths.insert(old.clone(), new.clone());
+            }
+            ask_path_map(&bundle, &mut paths, output)?;
+            let report = bundle::import(store, bundle, &paths)?;
+            if output == OutputMode::Json {
+                println!("{}", serde_json::to_string(&report)?);
+                return Ok(());
+            }
+            for (old, new) in &report.renamed {
+                println!("{} is already in use here; imported as {}", old, new);
+            }
+            println!(
+                "Imported session {} and {} conversation(s)",
+                report.session_id, report.conversations
+            );
+        }
+        SessionsAction::Stats => {
+            let stats = store.stats()?;
+            if output == OutputMode::Json {
+                println!("{}", serde_json::to_string(&stats)?);
+                return Ok(());
+            }
+            println!("Sessions: {}", stats.sessions);
+            println!("Conversations: {}", stats.conversations);
+            println!("Total bytes: {}", stats.total_bytes);
+            if let Some((id, bytes)) = &stats.largest_conversation {
+                println!("Largest conversation: {} ({} bytes)", id, bytes);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ask where each working directory of `bundle` goes that `paths` does not map and that
+/// does not exist here; left as they are without a terminal to ask on.
+fn ask_path_map(
+    bundle: &SessionBundle,
+    paths: &mut PathMap,
+    output: OutputMode,
+) -> Result<(), anyhow::Error> {
+    if output != OutputMode::Text || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+    for dir in bundle.working_directories() {
+        if paths.covers(&dir) || dir.exists() {
+            continue;
+        }
+        print!(
+            "{} does not exist here; move it to (empty keeps it): ",
+            dir.display()
+        );
+        io::stdout().flush()?;
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        let new = line.trim();
+        if !new.is_empty() {
+            paths.insert(dir, PathBuf::from(new));
+        }
+    }
+    Ok(())
+}
+
+/// `KEY=VALUE` of `task run --var`.
+fn parse_task_var(var: &str) -> Result<(String, String), String> {
+    match var.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{}`", var)),
+    }
+}
+
+/// `task save|list|delete`, or for `task run` the task to run with its variables filled
+/// in; it goes through the app like `exec`.
+fn run_task_action(
+    config: &ParsecConfig,
+    working_dir: &Path,
+    action: &TaskAction,
+    output: OutputMode,
+) -> Result<Option<(String, SavedTask)>, anyhow::Error> {
+    let mut book = TaskBook::load(
+        &config.data_dir,
+        &ParsecConfig::project_config_path(working_dir),
+    )?;
+    match action {
+        TaskAction::Save {
+            name,
+            conversation,
+            plan,
+        } => {
+            let store = open_store(config)?;
+            let conversation = match conversation {
+                Some(id) => store.load_conversation(id)?,
+                None => latest_conversation(&store)?
+                    .ok_or_else(|| anyhow::anyhow!("No saved conversations"))?,
+            };
+            let task = SavedTask {
+                prompt: conversation.user_prompt.clone(),
+                plan: if *plan {
+                    conversation
+                        .steps
+                        .iter()
+                        .map(|state| state.step.description.clone())
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+            };
+            let path = book.save(name, task)?;
+            println!(
+                "Saved task {} from conversation {} in {}",
+                name,
+                conversation.id,
+                path.display()
+            );
+        }
+        TaskAction::List => {
+            for (name, task, source) in book.list() {
+                if output == OutputMode::Json {
+                    println!(
+                        "{}",
+                        serde_json::json!({
+                            "name": name,
+                            "prompt": task.prompt,
+                            "plan": task.plan,
+                            "variables": task.variables(),
+                            "source": source.to_string(),
+                        })
+                    );
+                } else {
+                    let plan = if task.plan.is_empty() {
+                        String::new()
+                    } else {
+                        format!("  ({} saved steps)", task.plan.len())
+                    };
+                    println!("{}  {}{}  # {}", name, task.prompt, plan, source);
+                }
+            }
+        }
+        TaskAction::Delete { name } => {
+            book.delete(name)?;
+            println!("Deleted task {}", name);
+        }
+        TaskAction::Run { name, vars } => {
+            let (task, _) = book
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("No task named `{}`", name))?;
+            let vars: HashMap<String, String> = vars.iter().cloned().collect();
+            return Ok(Some((name.clone(), task.substitute(&vars)?)));
+        }
+    }
+    Ok(None)
+}
+
+/// The most recently created conversation across active sessions.
+fn latest_conversation(
+    store: &FileSessionStore,
+) -> Result<Option<ConversationContext>, anyhow::Error> {
+    let mut latest: Option<ConversationContext> = None;
+    for summary in store.list_active_sessions()? {
+        let session = store.load_session(&summary.id)?;
+        for conversation_id in &session.conversations {
+            let conversation = store.load_conversation(conversation_id)?;
+            if latest
+                .as_ref()
+                .is_none_or(|latest| conversation.created_at > latest.created_at)
+            {
+                latest = Some(conversation);
+            }
+        }
+    }
+    Ok(latest)
+}
+
+/// `conversations`: saved conversations matching `filter`, most recent first.
+fn run_conversations(
+    store: &FileSessionStore,
+    filter: &ConversationFilter,
+    output: OutputMode,
+) -> Result<(), anyhow::Error> {
+    let conversations = store.list_conversations(filter)?;
+    for warning in store.take_warnings() {
+        eprintln!("⚠️  Skipped a conversation: {}", warning);
+    }
+
+    // Numbers only name conversations in the unfiltered listing
+    let numbered = filter.session_id.is_none() && filter.tags.is_empty();
+    for (i, conversation) in conversations.iter().enumerate() {
+        if output == OutputMode::Json {
+            println!("{}", serde_json::to_string(conversation)?);
+        } else {
+            for line in conversation_lines(numbered.then_some(i + 1), conversation) {
+                println!("{}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `conversations rename|note|unnote|pin|unpin`, printing the conversation it changed.
+fn run_conversations_action(
+    store: &FileSessionStore,
+    action: &ConversationsAction,
+    output: OutputMode,
+) -> Result<(), anyhow::Error> {
+    let id =
+        |reference: &str| listed_conversation(store, &ConversationFilter::default(), reference);
+    let conversation = match action {
+        ConversationsAction::Rename { conversation, name } => {
+            annotate::rename(store, &id(conversation)?, &name.join(" "))?
+        }
+        ConversationsAction::Note { conversation, text } if text.is_empty() => {
+            store.load_conversation(&id(conversation)?)?
+        }
+        ConversationsAction::Note { conversation, text } => {
+            annotate::add_note(store, &id(conversation)?, &text.join(" "))?
+        }
+        ConversationsAction::Unnote {
+            conversation,
+            number,
+        } => annotate::remove_notes(store, &id(conversation)?, *number)?,
+        ConversationsAction::Pin { conversation } => {
+            annotate::set_pinned(store, &id(conversation)?, true)?
+        }
+        ConversationsAction::Unpin { conversation } => {
+            annotate::set_pinned(store, &id(conversation)?, false)?
+        }
+    };
+    let summary = ConversationSummary::of(&conversation);
+    if output == OutputMode::Json {
+        println!("{}", serde_json::to_string(&summary)?);
+    } else {
+        for line in conversation_lines(None, &summary) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// A conversation as listed: a line of its id, status and name, then its last run and
+/// its notes. `number` leads the first line.
+fn conversation_lines(number: Option<usize>, conversation: &ConversationSummary) -> Vec<String> {
+    let number = number
+        .map(|number| format!("{:>3}. ", number))
+        .unwrap_or_default();
+    let pinned = if conversation.pinned {
+        "  (pinned)"
+    } else {
+        ""
+    };
+    let task = conversation
+        .task
+        .as_ref()
+        .map(|task| format!("  [{}]", task))
+        .unwrap_or_default();
+    let tags: String = conversation
+        .tags
+        .iter()
+        .map(|tag| format!("  #{}", tag))
+        .collect();
+    let mut lines = vec![format!(
+        "{}{}  {}  {:?}  {}/{} steps  {}{}{}{}",
+        number,
+        conversation.id,
+        conversation.created_at.format("%Y-%m-%d %H:%M:%S"),
+        conversation.status,
+        conversation.steps_completed,
+        conversation.steps_total,
+        conversation.name,
+        pinned,
+        task,
+        tags
+    )];
+    if let Some(outcome) = &conversation.outcome {
+        lines.push(format!("    last run {}", outcome));
+    }
+    for (i, note) in conversation.notes.iter().enumerate() {
+        lines.push(format!("    note {}: {}", i + 1, note));
+    }
+    lines
+}
+
+/// `reference` as a conversation id: a number picks the conversation listed at that
+/// position under `filter`, counting from 1; anything else is taken as an id.
+fn listed_conversation(
+    store: &dyn SessionStore,
+    filter: &ConversationFilter,
+    reference: &str,
+) -> Result<ConversationId, anyhow::Error> {
+    let Ok(number) = reference.parse::<usize>() else {
+        return Ok(reference.to_string());
+    };
+    let listed = store.list_conversations(filter)?;
+    number
+        .checked_sub(1)
+        .and_then(|index| listed.get(index))
+        .map(|conversation| conversation.id.clone())
+        .ok_or_else(|| anyhow::anyhow!("No conversation {}; {} are listed", number, listed.len()))
+}
+
+/// `search`: the hits in `scope` after the first `skip`.
+fn run_search(
+    store: &FileSessionStore,
+    query: &str,
+    scope: &SearchScope,
+    skip: usize,
+    output: OutputMode,
+) -> Result<(), anyhow::Error> {
+    let hits = store.search(query, scope)?;
+    for warning in store.take_warnings() {
+        eprintln!("⚠️  Skipped in search: {}", warning);
+    }
+    if hits.len() <= skip && output == OutputMode::Text {
+        println!("No matches");
+        return Ok(());
+    }
+
+    let width = history::terminal_width();
+    for (i, hit) in hits.iter().enumerate().skip(skip) {
+        if output == OutputMode::Json {
+            println!("{}", serde_json::to_string(hit)?);
+        } else {
+            println!("{}", history::render_search_hit(i + 1, hit, width));
+        }
+    }
+    Ok(())
+}
+
+/// `classify stats`: decisions per classifier and corrections across persisted sessions.
+fn run_classify_stats(store: &FileSessionStore) -> Result<(), anyhow::Error> {
+    let mut decisions: BTreeMap<String, (usize, f32)> = BTreeMap::new();
+    // Corrections are carried between sessions, so the same one may appear in several
+    let mut seen = HashSet::new();
+    let mut corrections: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut answers: BTreeMap<(String, String), usize> = BTreeMap::new();
+
+    for summary in store.list_active_sessions()? {
+        let session = store.load_session(&summary.id)?;
+        for record in &session.classification_log {
+            let entry = decisions.entry(record.classifier.clone()).or_default();
+            entry.0 += 1;
+            entry.1 += record.confidence;
+        }
+        for correction in session.classification_corrections {
+            if seen.insert((correction.input_hash, correction.corrected_at)) {
+                let tally = if correction.disambiguated {
+                    &mut answers
+                } else {
+                    &mut corrections
+                };
+                *tally
+                    .entry((
+                        format!("{:?}", correction.corrected_to),
+                        correction.first_word,
+                    ))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    let total: usize = decisions.values().map(|(count, _)| count).sum();
+    println!("Decisions: {}", total);
+    for (classifier, (count, confidence)) in &decisions {
+        println!(
+            "  {}: {} (mean confidence {:.2})",
+            classifier,
+            count,
+            confidence / *count as f32
+        );
+    }
+
+    println!("Corrections: {}", corrections.values().sum::<usize>());
+    for ((kind, word), count) in &corrections {
+        println!("  `{}` -> {}: {}", word, kind, count);
+    }
+
+    println!(
+        "Answers to low-confidence questions: {}",
+        answers.values().sum::<usize>()
+    );
+    for ((kind, word), count) in &answers {
+        println!("  `{}` -> {}: {}", word, kind, count);
+    }
+
+    Ok(())
+}
+
+/// Explicit classification override: `!cmd` forces the shell, `?request` or
+/// `ai: request` forces a workflow. Returns the forced kind and the stripped input.
+fn parse_override(input: &str) -> Option<(InputKind, &str)> {
+    // `followup:` and `??` are prompts; `handle_prompt` strips them
+    if strip_follow_up(input).is_some() || strip_question(input).is_some() {
+        return Some((InputKind::Prompt, input));
+    }
+    if let Some(rest) = input.strip_prefix('!') {
+        return Some((InputKind::Shell, rest.trim_start()));
+    }
+    if let Some(rest) = input.strip_prefix('?') {
+        return Some((InputKind::Prompt, rest.trim_start()));
+    }
+    match input.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("ai:") => {
+            Some((InputKind::Prompt, input[3..].trim_start()))
+        }
+        _ => None,
+    }
+}
+
+/// The prompt after a `followup:` prefix, in any case.
+fn strip_follow_up(input: &str) -> Option<&str> {
+    const PREFIX: &str = "followup:";
+    input
+        .get(..PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+        .map(|_| input[PREFIX.len()..].trim_start())
+}
+
+/// The question after a `??` prefix, which is answered without a workflow.
+fn strip_question(input: &str) -> Option<&str> {
+    input.strip_prefix("??").map(str::trim_start)
+}
+
+/// The prompt after a `plan:` prefix, in any case.
+fn strip_plan_preview(input: &str) -> Option<&str> {
+    const PREFIX: &str = "plan:";
+    input
+        .get(..PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+        .map(|_| input[PREFIX.len()..].trim_start())
+}
+
+/// `doctor`: the session store's files, a minimal request to the model provider, and the
+/// classifier with the backends it calls, one line each.
+async fn run_doctor(
+    args: &Args,
+    config: &ParsecConfig,
+    render: Renderer,
+) -> Result<(), anyhow::Error> {
+    let mut failed = 0;
+    let mut report = |check: &str, result: Result<String, anyhow::Error>| match result {
+        Ok(detail) => println!("{}", render.success(format_args!("{}: {}", check, detail))),
+        Err(e) => {
+            failed += 1;
+            println!("{}", render.failure(format_args!("{}: {:#}", check, e)));
+        }
+    };
+
+    report("Session store", check_store(config));
+
+    let api_key = args
+        .api_key
+        .clone()
+        .or_else(|| env::var("GOOGLE_AI_API_KEY").ok());
+    // Health checks make no embedding calls, so no vectors need keeping
+    let provider = build_model_provider(
+        config,
+        api_key.as_deref(),
+        &metrics::noop(),
+        Arc::new(InMemorySessionStore::new()),
+    );
+    let health = match provider {
+        Ok(provider) => match provider.health_check().await {
+            Ok(health) => Ok(format!(
+                "{} ({}) answered in {} ms",
+                health.provider,
+                health.model,
+                health.latency.as_millis()
+            )),
+            Err(HealthError::Unsupported(name)) => Ok(format!("{} cannot be checked", name)),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e),
+    };
+    report("Model provider", health);
+
+    let classifier = build_classifier(config).and_then(|classifier| {
+        let classification = classifier.classify(DOCTOR_INPUT, None)?;
+        Ok(format!(
+            "{} classified `{}` as {:?}",
+            config.classifier, DOCTOR_INPUT, classification.kind
+        ))
+    });
+    report("Classifier", classifier);
+    // The ensemble answers with the heuristic when Hugging Face fails, so check it alone
+    if config.classifier == "ensemble" {
+        let huggingface = huggingface_classifier(config).and_then(|classifier| {
+            classifier.classify(DOCTOR_INPUT, None)?;
+            Ok("answered".to_string())
+        });
+        report("Hugging Face", huggingface);
+    }
+    if config.classifier == "python" {
+        report("Python", check_python(config));
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} check(s) failed", failed));
+    }
+    Ok(())
+}
+
+/// What `doctor` asks the classifier about.
+const DOCTOR_INPUT: &str = "ls -la";
+
+/// Every store file parses, or has a valid backup to restore.
+fn check_store(config: &ParsecConfig) -> Result<String, anyhow::Error> {
+    let store = open_store(config)?;
+    let issues = store.check_integrity()?;
+    for issue in &issues {
+        let recovery = if issue.backup_valid {
+            "backup is valid and will be restored on next load"
+        } else {
+            "no valid backup; this file cannot be recovered"
+        };
+        println!("  {}: {} ({})", issue.path.display(), issue.error, recovery);
+    }
+    if !issues.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} corrupt file(s) in {}",
+            issues.len(),
+            store.root().display()
+        ));
+    }
+    Ok(format!("no corrupt files in {}", store.root().display()))
+}
+
+/// Whether the embedded interpreter loads the classifier script.
+#[cfg(feature = "python")]
+fn check_python(config: &ParsecConfig) -> Result<String, anyhow::Error> {
+    match &config.python_script {
+        Some(script) => parsec_classifier::PythonClassifier::with_script_path(script)
+            .map(|_| format!("loaded {}", script.display())),
+        None => parsec_classifier::PythonClassifier::new()
+            .map(|_| "loaded the built-in classifier".to_string()),
+    }
+    .map_err(Into::into)
+}
+
+#[cfg(not(feature = "python"))]
+fn check_python(_config: &ParsecConfig) -> Result<String, anyhow::Error> {
+    Err(anyhow::anyhow!(
+        "The python classifier requires building parsec with `--features python`"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsec_model::{GenerationOptions, ModelClientError};
+
+    /// An app over a fresh data directory, and a session of it that was never saved.
+    fn app_with_session() -> (ParsecApp, Session) {
+        let data_dir = env::temp_dir().join(format!("parsec-app-{}", Uuid::new_v4()));
+        let config = ParsecConfig {
+            data_dir: data_dir.clone(),
+            ..ParsecConfig::default()
+        };
+        let args = Args::parse_from(["parsec", "--api-key", "test", "--output", "json"]);
+        let mut app = ParsecApp::new(&args, &config).unwrap();
+        let session = app.new_session(data_dir);
+        (app, session)
+    }
+
+    #[test]
+    fn a_failing_history_reference_keeps_the_session() {
+        let (mut app, mut session) = app_with_session();
+        session.last_input_kind = Some(InputKind::Prompt);
+        let session_id = session.id.clone();
+        app.current_session = Some(session);
+
+        assert_eq!(app.expand_history("!3", &session_id), None);
+        assert_eq!(app.expand_history("!!", &session_id), None);
+
+        let kept = app.current_session.as_ref().expect("the session is kept");
+        assert_eq!(kept.id, session_id);
+        assert_eq!(kept.last_input_kind, Some(InputKind::Prompt));
+        assert!(app.session_store.load_session(&session_id).is_err());
+        assert_eq!(
+            app.expand_history("ls -la", &session_id),
+            Some("ls -la".to_string())
+        );
+    }
+
+    /// Answers every call with the step already done.
+    struct DoneClient;
+
+    #[async_trait::async_trait]
+    impl ModelClient for DoneClient {
+        async fn generate_text(
+            &self,
+            _prompt: &str,
+            _opts: &GenerationOptions,
+        ) -> Result<String, ModelClientError> {
+            Ok(r#"{ "commands": [], "done": true }"#.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn tasks_run_with_their_variables_in_a_conversation_tagged_with_their_name() {
+        let (mut app, mut session) = app_with_session();
+        let project = session.global_context.working_directory.clone();
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::write(
+            ParsecConfig::project_config_path(&project),
+            "[tasks.release]\nprompt = \"release {{version}}\"\nplan = [\"Tag v{{version}}\"]\n",
+        )
+        .unwrap();
+        let args = Args::parse_from(["parsec", "task", "run", "release", "--var", "version=1.2"]);
+        let Some(Command::Task { action }) = &args.command else {
+            panic!("not a task command");
+        };
+        let config = ParsecConfig {
+            data_dir: project.clone(),
+            ..ParsecConfig::default()
+        };
+
+        let (name, task) = run_task_action(&config, &project, action, OutputMode::Json)
+            .unwrap()
+            .expect("a task to run");
+        assert_eq!(task.prompt, "release 1.2");
+        assert_eq!(task.plan, ["Tag v1.2"]);
+
+        // The saved plan needs no model, and its step turns out to be done already
+        app.orchestrator = Arc::new(PromptOrchestrator::new(
+            Arc::new(GoogleAiProvider::with_client(Arc::new(DoneClient))),
+            app.session_store.clone(),
+        ));
+        app.run_task(&name, &task, &mut session).await.unwrap();
+        app.session_store.save_session(&session).unwrap();
+        let filter = ConversationFilter {
+            session_id: Some(session.id.clone()),
+            ..ConversationFilter::default()
+        };
+        let found = app.session_store.find_conversations(&filter).unwrap();
+        let [conversation] = found.as_slice() else {
+            panic!("expected one conversation, found {}", found.len());
+        };
+        assert_eq!(conversation.task.as_deref(), Some("release"));
+        assert_eq!(conversation.user_prompt, "release 1.2");
+        let _ = std::fs::remove_dir_all(&project);
+    }
 }
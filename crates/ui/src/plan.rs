@@ -0,0 +1,271 @@
+//! Plans before they run: the `plan` builtin's preview, the listing of a conversation's
+//! steps and its preflight findings, and the review where steps are edited, inserted,
+//! removed, reordered or regenerated before anything runs.
+
+use std::time::Duration;
+
+use parsec_core::preflight::PreflightSeverity;
+use parsec_core::step_risk::StepRisk;
+use parsec_core::*;
+use parsec_prompt::OrchestratorError;
+
+use crate::{strip_continuations, ParsecApp};
+
+impl ParsecApp {
+    pub(crate) fn print_plan(&self, conversation: &ConversationContext) {
+        say!(self, "\nWorkflow: {}", conversation.name);
+        for (i, step) in conversation.steps.iter().enumerate() {
+            let after = step_order(&conversation.steps, i);
+            say!(
+                self,
+                "  {}. {}{}{}",
+                i + 1,
+                step.step.description,
+                after,
+                self.render.dim(step_estimate(&step.step))
+            );
+        }
+    }
+
+    /// `plan`: classify and plan `prompt` without running anything, showing the risk each
+    /// step looks to carry and the tokens the planning took. Only with `save` is the plan
+    /// kept, as a conversation that `fork` runs.
+    pub(crate) async fn preview_plan(
+        &mut self,
+        prompt: &str,
+        session: &mut Session,
+        save: bool,
+    ) -> Result<(), anyhow::Error> {
+        let prompt = strip_continuations(prompt);
+        let classification = self.classify(&prompt, session)?;
+        self.emit(OutputRecord::Classification {
+            input: prompt.clone(),
+            kind: classification.kind.clone(),
+            confidence: classification.confidence,
+            reasoning: classification.reasoning.clone(),
+            overridden: false,
+        });
+        if classification.kind == InputKind::Shell {
+            say!(
+                self,
+                "{}",
+                self.render.warning(format_args!(
+                    "This reads like a shell command ({:.0}% sure); planning it anyway",
+                    classification.confidence * 100.0
+                ))
+            );
+        }
+
+        self.orchestrator.check_provider(session).await?;
+        say!(self, "Planning: {}", prompt);
+        let tokens = self.orchestrator.tokens_used();
+        let (orchestrator, planned_for, task) = (&self.orchestrator, &*session, prompt.as_str());
+        let planned = self
+            .with_cancel_keys(|cancel| async move {
+                orchestrator
+                    .preview_plan(planned_for, task, &cancel, None)
+                    .await
+            })
+            .await;
+        let workflow = match planned {
+            Err(OrchestratorError::Plan(PlanError::Cancelled)) => {
+                say!(self, "{}", self.render.warning("Planning cancelled"));
+                return Ok(());
+            }
+            planned => planned?,
+        };
+        let tokens_used = self.orchestrator.tokens_used().saturating_sub(tokens);
+        let risks: Vec<StepRisk> = workflow
+            .steps
+            .iter()
+            .map(|step| StepRisk::of(&step.description))
+            .collect();
+        self.emit(OutputRecord::PlanPreview {
+            prompt: prompt.clone(),
+            steps: workflow.steps.clone(),
+            risks: risks.clone(),
+            tokens_used,
+        });
+
+        say!(self, "\nPlan ({} steps):", workflow.steps.len());
+        for (i, (step, risk)) in workflow.steps.iter().zip(&risks).enumerate() {
+            let label = match risk {
+                StepRisk::Destructive | StepRisk::ChangesSystem => {
+                    self.render.failure(risk).to_string()
+                }
+                StepRisk::Installs => self.render.warning(risk).to_string(),
+                StepRisk::ChangesFiles | StepRisk::ReadOnly => self.render.dim(risk).to_string(),
+            };
+            let after = step_order(&workflow.steps, i);
+            say!(
+                self,
+                "  {}. [{}] {}{}{}",
+                i + 1,
+                label,
+                step.description,
+                after,
+                self.render.dim(step_estimate(step))
+            );
+        }
+        if tokens_used > 0 {
+            say!(self, "Tokens used: ~{}", tokens_used);
+        }
+
+        if !save {
+            say!(
+                self,
+                "{}",
+                self.render
+                    .dim("Nothing was saved; `parsec plan --save` keeps the plan")
+            );
+            return Ok(());
+        }
+        let mut conversation = self.orchestrator.create_conversation(&session.id, prompt)?;
+        self.orchestrator
+            .attach_references(&mut conversation, session)?;
+        self.orchestrator
+            .adopt_plan(&mut conversation, session, workflow)
+            .await?;
+        self.emit(OutputRecord::Plan {
+            conversation_id: conversation.id.clone(),
+            name: conversation.name.clone(),
+            steps: conversation
+                .steps
+                .iter()
+                .map(|state| state.step.clone())
+                .collect(),
+        });
+        self.print_preflight(&conversation);
+        say!(
+            self,
+            "Saved as \"{}\" ({}); `fork {}` runs it",
+            conversation.name,
+            conversation.id,
+            conversation.id
+        );
+        Ok(())
+    }
+
+    /// What the preflight checks of a planned conversation found.
+    pub(crate) fn print_preflight(&self, conversation: &ConversationContext) {
+        for finding in &conversation.preflight {
+            let line = match finding.severity {
+                PreflightSeverity::Warning => self.render.warning(finding),
+                PreflightSeverity::Error => self.render.failure(finding),
+            };
+            say!(self, "  {}", line);
+        }
+    }
+
+    /// Let the user adjust the plan before anything runs. Returns false if they aborted.
+    pub(crate) async fn review_plan(
+        &mut self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+    ) -> Result<bool, anyhow::Error> {
+        loop {
+            let answer = self.read_line(
+                "\nRun this plan? [y=yes, e N text=edit, d N=delete, i N text=insert, \
+                 o N N ...=reorder, r text=regenerate, a=abort]: ",
+            )?;
+            let answer = answer.trim();
+            let (action, rest) = answer.split_once(' ').unwrap_or((answer, ""));
+            let rest = rest.trim();
+
+            let result = match action.to_lowercase().as_str() {
+                "" | "y" | "yes" => return Ok(true),
+                "a" | "abort" => {
+                    self.orchestrator.abort_conversation(conversation)?;
+                    say!(self, "Conversation aborted");
+                    return Ok(false);
+                }
+                "e" | "edit" => parse_step_text(rest).and_then(|(index, text)| {
+                    Ok(self.orchestrator.edit_step(conversation, index, text)?)
+                }),
+                "d" | "delete" => parse_step_number(rest)
+                    .and_then(|index| Ok(self.orchestrator.remove_step(conversation, index)?)),
+                "i" | "insert" => parse_step_text(rest).and_then(|(index, text)| {
+                    Ok(self
+                        .orchestrator
+                        .insert_step(conversation, session, index, text)?)
+                }),
+                "o" | "order" => rest
+                    .split_whitespace()
+                    .map(parse_step_number)
+                    .collect::<Result<Vec<_>, _>>()
+                    .and_then(|order| Ok(self.orchestrator.reorder_steps(conversation, &order)?)),
+                "r" | "regenerate" if !rest.is_empty() => {
+                    say!(self, "Regenerating plan...");
+                    Ok(self
+                        .orchestrator
+                        .replan_with_feedback(conversation, session, rest)
+                        .await?)
+                }
+                _ => Err(anyhow::anyhow!("Unrecognized answer: {}", answer)),
+            };
+
+            match result {
+                Ok(()) => self.print_plan(conversation),
+                Err(e) => say!(self, "  {}", e),
+            }
+        }
+    }
+}
+
+/// A 1-based step number typed by the user, as an index.
+fn parse_step_number(text: &str) -> Result<usize, anyhow::Error> {
+    match text.trim().parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number - 1),
+        _ => Err(anyhow::anyhow!("Expected a step number, got `{}`", text)),
+    }
+}
+
+/// `N text` as a step index and the text.
+fn parse_step_text(text: &str) -> Result<(usize, String), anyhow::Error> {
+    let (number, rest) = text.split_once(' ').unwrap_or((text, ""));
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Expected a step number followed by a description"
+        ));
+    }
+    Ok((parse_step_number(number)?, rest.to_string()))
+}
+
+/// How step `index` depends on the others, for plan listings: nothing for steps that need
+/// only those before them, so such plans read as a plain list.
+fn step_order(steps: &[impl AsRef<WorkflowStep>], index: usize) -> String {
+    if steps[index].as_ref().depends_on.is_none() {
+        return String::new();
+    }
+    match dependencies::dependencies(steps, index).as_slice() {
+        [] => " (independent)".to_string(),
+        needed => format!(
+            " (after {})",
+            needed
+                .iter()
+                .map(|index| (index + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// ` (~N min)` after a step with an estimate, noting one taken from earlier runs.
+fn step_estimate(step: &WorkflowStep) -> String {
+    match (step.estimate(), step.estimate_source) {
+        (None, _) => String::new(),
+        (Some(estimate), EstimateSource::Planner) => format!(" ({})", rough_minutes(estimate)),
+        (Some(estimate), EstimateSource::History) => {
+            format!(" ({}, from earlier runs)", rough_minutes(estimate))
+        }
+    }
+}
+
+pub(crate) fn rough_minutes(duration: Duration) -> String {
+    match duration.as_secs() {
+        0 => "0 min".to_string(),
+        1..=59 => "<1 min".to_string(),
+        secs => format!("~{} min", (secs + 30) / 60),
+    }
+}
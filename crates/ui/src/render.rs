@@ -0,0 +1,137 @@
+//! Styling for the human-readable output. With color off every method returns the same
+//! text without escape codes, so callers never need to care which mode is active.
+
+use anstream::{AutoStream, ColorChoice};
+use anstyle::{AnsiColor, Style};
+use parsec_core::ConversationStatus;
+use std::fmt::Display;
+
+const HEADER: Style = AnsiColor::Cyan.on_default().bold();
+const COMMAND: Style = Style::new().bold();
+const DIM: Style = Style::new().dimmed();
+const SUCCESS: Style = AnsiColor::Green.on_default();
+const FAILURE: Style = AnsiColor::Red.on_default();
+const WARNING: Style = AnsiColor::Yellow.on_default();
+const HIGHLIGHT: Style = AnsiColor::Red.on_default().bold().underline();
+
+/// Risk scores from here up get a badge; below it commands are routine.
+const NOTABLE_RISK: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Color unless `disabled` (`--no-color`), `NO_COLOR` is set or stdout is not a
+    /// terminal.
+    pub fn for_stdout(disabled: bool) -> Self {
+        Self::new(!disabled && AutoStream::choice(&std::io::stdout()) != ColorChoice::Never)
+    }
+
+    pub fn plain() -> Self {
+        Self::new(false)
+    }
+
+    fn paint(&self, style: Style, text: impl Display) -> String {
+        if self.color {
+            format!("{}{}{}", style.render(), text, style.render_reset())
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn step_header(&self, number: usize, description: &str) -> String {
+        self.paint(HEADER, format!("→ Step {}: {}", number, description))
+    }
+
+    pub fn command(&self, command: &str) -> String {
+        self.paint(COMMAND, command)
+    }
+
+    /// `command` with the last occurrence of `part` highlighted.
+    pub fn highlight(&self, command: &str, part: &str) -> String {
+        match command.rfind(part) {
+            Some(start) if !part.is_empty() => format!(
+                "{}{}{}",
+                self.command(&command[..start]),
+                self.paint(HIGHLIGHT, part),
+                self.command(&command[start + part.len()..])
+            ),
+            _ => self.command(command),
+        }
+    }
+
+    pub fn dim(&self, text: impl Display) -> String {
+        self.paint(DIM, text)
+    }
+
+    pub fn success(&self, text: impl Display) -> String {
+        self.paint(SUCCESS, format!("✓ {}", text))
+    }
+
+    pub fn failure(&self, text: impl Display) -> String {
+        self.paint(FAILURE, format!("✗ {}", text))
+    }
+
+    pub fn warning(&self, text: impl Display) -> String {
+        self.paint(WARNING, format!("⚠️  {}", text))
+    }
+
+    /// `Risk score: 0.45`, yellow below `threshold` and red from it up; `None` for
+    /// routine scores.
+    pub fn risk_badge(&self, risk_score: f32, threshold: f32) -> Option<String> {
+        if risk_score <= NOTABLE_RISK {
+            return None;
+        }
+        let style = if risk_score >= threshold {
+            FAILURE
+        } else {
+            WARNING
+        };
+        Some(self.paint(style, format!("⚠️  Risk score: {:.2}", risk_score)))
+    }
+
+    /// `text` indented under a dimmed gutter, one line per output line.
+    pub fn output_block(&self, text: &str) -> String {
+        text.trim_end_matches('\n')
+            .lines()
+            .map(|line| format!("    {} {}", self.dim("│"), line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `script` with its lines numbered.
+    pub fn script_block(&self, script: &str) -> String {
+        let lines: Vec<&str> = script.trim().lines().collect();
+        let width = lines.len().to_string().len();
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                format!(
+                    "    {} {}",
+                    self.dim(format_args!("{:>width$} │", i + 1)),
+                    self.command(line)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `text` in the color of how the conversation stands.
+    pub fn status(&self, status: &ConversationStatus, text: impl Display) -> String {
+        let style = match status {
+            ConversationStatus::Finished => SUCCESS,
+            ConversationStatus::Aborted | ConversationStatus::Error => FAILURE,
+            ConversationStatus::Planning
+            | ConversationStatus::Ready
+            | ConversationStatus::InProgress => WARNING,
+        };
+        self.paint(style, text)
+    }
+}
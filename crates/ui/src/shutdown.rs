@@ -0,0 +1,178 @@
+//! Saving what parsec holds in memory when it is stopped mid-run by SIGTERM, SIGHUP or a
+//! panic. Saves otherwise happen at checkpoints, so an interrupted run would lose the
+//! session's latest changes and leave its conversation looking busy.
+
+use parsec_core::metrics::InMemoryMetrics;
+use parsec_core::*;
+use parsec_executor::jobs::JobRegistry;
+use parsec_model::FileSessionStore;
+use parsec_prompt::PromptOrchestrator;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use tracing::{error, info, warn};
+
+/// Changes not saved yet, as last reported to the guard.
+#[derive(Default)]
+struct Dirty {
+    session: Option<Session>,
+    /// Conversations being run, with the step in progress.
+    conversations: HashMap<ConversationId, (ConversationContext, Option<usize>)>,
+}
+
+pub struct ShutdownGuard {
+    store: Arc<FileSessionStore>,
+    orchestrator: Arc<PromptOrchestrator>,
+    jobs: Arc<JobRegistry>,
+    kill_jobs: bool,
+    /// Metrics recorded in memory and the file they are added to.
+    metrics: Option<(Arc<InMemoryMetrics>, PathBuf)>,
+    dirty: Mutex<Dirty>,
+}
+
+impl ShutdownGuard {
+    pub fn new(
+        store: Arc<FileSessionStore>,
+        orchestrator: Arc<PromptOrchestrator>,
+        jobs: Arc<JobRegistry>,
+        kill_jobs: bool,
+        metrics: Option<(Arc<InMemoryMetrics>, PathBuf)>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            store,
+            orchestrator,
+            jobs,
+            kill_jobs,
+            metrics,
+            dirty: Mutex::new(Dirty::default()),
+        })
+    }
+
+    fn dirty(&self) -> MutexGuard<'_, Dirty> {
+        self.dirty.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn session_changed(&self, session: &Session) {
+        self.dirty().session = Some(session.clone());
+    }
+
+    pub fn session_saved(&self, session_id: &SessionId) {
+        let mut dirty = self.dirty();
+        if dirty
+            .session
+            .as_ref()
+            .is_some_and(|session| &session.id == session_id)
+        {
+            dirty.session = None;
+        }
+    }
+
+    /// `conversation` is being run and has got to `step_index`.
+    pub fn step_started(&self, conversation: &ConversationContext, step_index: usize) {
+        self.dirty().conversations.insert(
+            conversation.id.clone(),
+            (conversation.clone(), Some(step_index)),
+        );
+    }
+
+    /// `conversation` is being run, between steps or with the current one recorded.
+    pub fn conversation_changed(&self, conversation: &ConversationContext) {
+        self.dirty()
+            .conversations
+            .insert(conversation.id.clone(), (conversation.clone(), None));
+    }
+
+    /// The run of `conversation_id` returned, having saved what it did.
+    pub fn run_finished(&self, conversation_id: &ConversationId) {
+        self.dirty().conversations.remove(conversation_id);
+    }
+
+    /// Save the session and mark every conversation being run as interrupted.
+    pub fn flush(&self, reason: &str) {
+        let mut dirty = match self.dirty.try_lock() {
+            Ok(dirty) => dirty,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            // The panic struck while this thread was reporting a change
+            Err(TryLockError::WouldBlock) => {
+                error!("Not saving state on {}: it is being updated", reason);
+                return;
+            }
+        };
+        if let Some(session) = dirty.session.take() {
+            if let Err(e) = self.store.save_session(&session) {
+                error!("Failed to save session {} on {}: {}", session.id, reason, e);
+            }
+        }
+        for (_, (mut conversation, step_index)) in dirty.conversations.drain() {
+            // The orchestrator saves as steps finish, so the stored copy may be newer
+            if let Ok(stored) = self.store.load_conversation(&conversation.id) {
+                if stored.revision > conversation.revision {
+                    conversation = stored;
+                }
+            }
+            match self
+                .orchestrator
+                .mark_interrupted(&mut conversation, step_index, reason)
+            {
+                Ok(()) => info!("Marked conversation {} interrupted", conversation.id),
+                Err(e) => error!(
+                    "Failed to save conversation {} on {}: {}",
+                    conversation.id, reason, e
+                ),
+            }
+        }
+    }
+
+    /// Add the metrics recorded so far to the metrics file.
+    pub fn flush_metrics(&self) {
+        if let Some((metrics, path)) = &self.metrics {
+            if let Err(e) = metrics.flush(path) {
+                warn!("Failed to save metrics to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// `flush` and `flush_metrics`, then kill background jobs or leave them running as `jobs.kill_on_exit`
+    /// says. Foreground commands are left to the signal that stopped parsec.
+    fn shutdown(&self, reason: &str) {
+        self.flush(reason);
+        self.flush_metrics();
+        for job in self.jobs.shutdown(self.kill_jobs) {
+            if !self.kill_jobs {
+                eprintln!("[{}] left running: {}", job.id, job.command);
+            }
+        }
+    }
+
+    /// Shut down on a panic, before the default hook reports it, and on SIGTERM or
+    /// SIGHUP, exiting with the usual `128 + signal` status. Must run inside the runtime.
+    pub fn install(self: &Arc<Self>) -> Result<(), std::io::Error> {
+        let guard = Arc::clone(self);
+        let report = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Panics elsewhere end a thread or task, not parsec
+            if std::thread::current().name() == Some("main") {
+                guard.shutdown("panic");
+            }
+            report(info);
+        }));
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut terminate = signal(SignalKind::terminate())?;
+            let mut hangup = signal(SignalKind::hangup())?;
+            let guard = Arc::clone(self);
+            tokio::spawn(async move {
+                let (reason, status) = tokio::select! {
+                    _ = terminate.recv() => ("SIGTERM", 143),
+                    _ = hangup.recv() => ("SIGHUP", 129),
+                };
+                guard.shutdown(reason);
+                std::process::exit(status);
+            });
+        }
+        Ok(())
+    }
+}
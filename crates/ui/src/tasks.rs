@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the file saved tasks are kept in, under the data directory.
+pub const TASKS_FILE: &str = "tasks.json";
+
+/// A prompt kept under a name to run again, with `{{var}}` placeholders filled in by
+/// `parsec task run --var var=value`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedTask {
+    pub prompt: String,
+    /// Step descriptions to run instead of asking the model for a plan.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plan: Vec<String>,
+}
+
+impl SavedTask {
+    /// Names of the `{{var}}` placeholders in the prompt and plan, in order of appearance.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for text in std::iter::once(&self.prompt).chain(&self.plan) {
+            for name in placeholders(text) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// The task with its placeholders replaced by `vars`; every one must be given.
+    pub fn substitute(&self, vars: &HashMap<String, String>) -> Result<SavedTask, anyhow::Error> {
+        let missing: Vec<String> = self
+            .variables()
+            .into_iter()
+            .filter(|name| !vars.contains_key(name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Missing {}",
+                missing
+                    .iter()
+                    .map(|name| format!("--var {}=...", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        let fill = |text: &str| {
+            placeholders(text).fold(text.to_string(), |text, name| {
+                text.replace(&format!("{{{{{}}}}}", name), &vars[&name])
+            })
+        };
+        Ok(SavedTask {
+            prompt: fill(&self.prompt),
+            plan: self.plan.iter().map(|step| fill(step)).collect(),
+        })
+    }
+}
+
+/// Names between `{{` and `}}` in `text`, such as `version` in `{{version}}`.
+fn placeholders(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split("{{").skip(1).filter_map(|rest| {
+        let (name, _) = rest.split_once("}}")?;
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        valid.then(|| name.to_string())
+    })
+}
+
+/// Where a task was defined.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskSource {
+    User(PathBuf),
+    Project(PathBuf),
+}
+
+impl std::fmt::Display for TaskSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskSource::User(path) => write!(f, "saved in {}", path.display()),
+            TaskSource::Project(path) => write!(f, "from {}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProjectFile {
+    #[serde(default)]
+    tasks: BTreeMap<String, SavedTask>,
+}
+
+/// The user's saved tasks and those a project ships in the `[tasks]` table of its
+/// `.parsec.toml`, which win when both have a task of the same name.
+pub struct TaskBook {
+    user_path: PathBuf,
+    user: BTreeMap<String, SavedTask>,
+    project_path: PathBuf,
+    project: BTreeMap<String, SavedTask>,
+}
+
+impl TaskBook {
+    pub fn load(data_dir: &Path, project_config: &Path) -> Result<Self, anyhow::Error> {
+        let user_path = data_dir.join(TASKS_FILE);
+        let user = match fs::read_to_string(&user_path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("{}: {}", user_path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(anyhow::anyhow!("{}: {}", user_path.display(), e)),
+        };
+        let project = match fs::read_to_string(project_config) {
+            Ok(text) => {
+                toml::from_str::<ProjectFile>(&text)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", project_config.display(), e))?
+                    .tasks
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(anyhow::anyhow!("{}: {}", project_config.display(), e)),
+        };
+        Ok(Self {
+            user_path,
+            user,
+            project_path: project_config.to_path_buf(),
+            project,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<(&SavedTask, TaskSource)> {
+        self.project
+            .get(name)
+            .map(|task| (task, TaskSource::Project(self.project_path.clone())))
+            .or_else(|| {
+                self.user
+                    .get(name)
+                    .map(|task| (task, TaskSource::User(self.user_path.clone())))
+            })
+    }
+
+    /// Every task by name.
+    pub fn list(&self) -> Vec<(&str, &SavedTask, TaskSource)> {
+        let mut names: Vec<&String> = self.project.keys().chain(self.user.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let (task, source) = self.get(name)?;
+                Some((name.as_str(), task, source))
+            })
+            .collect()
+    }
+
+    /// Save `task` as `name` among the user's tasks, replacing any of that name.
+    pub fn save(&mut self, name: &str, task: SavedTask) -> Result<&Path, anyhow::Error> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(anyhow::anyhow!(
+                "Task names may only use letters, digits, `_`, `-` and `.`"
+            ));
+        }
+        self.user.insert(name.to_string(), task);
+        self.write()?;
+        Ok(&self.user_path)
+    }
+
+    /// Delete the user's task `name`; project tasks can only be removed from the project file.
+    pub fn delete(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        if self.user.remove(name).is_none() {
+            return Err(if self.project.contains_key(name) {
+                anyhow::anyhow!(
+                    "Task `{}` comes from {}; remove it there",
+                    name,
+                    self.project_path.display()
+                )
+            } else {
+                anyhow::anyhow!("No task named `{}`", name)
+            });
+        }
+        self.write()
+    }
+
+    fn write(&self) -> Result<(), anyhow::Error> {
+        if let Some(dir) = self.user_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.user_path, serde_json::to_string_pretty(&self.user)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a task book's data directory and project.
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parsec-tasks-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn task(prompt: &str, plan: &[&str]) -> SavedTask {
+        SavedTask {
+            prompt: prompt.to_string(),
+            plan: plan.iter().map(|step| step.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn saved_tasks_are_kept_in_the_data_directory_and_listed() {
+        let dir = scratch();
+        let project = dir.join(".parsec.toml");
+        let mut book = TaskBook::load(&dir, &project).unwrap();
+        let saved = book
+            .save("deps", task("update all dependencies and run tests", &[]))
+            .unwrap()
+            .to_path_buf();
+        assert_eq!(saved, dir.join(TASKS_FILE));
+        book.save("release", task("tag {{version}}", &["Tag", "Push"]))
+            .unwrap();
+        assert!(book.save("no spaces", task("x", &[])).is_err());
+
+        let reloaded = TaskBook::load(&dir, &project).unwrap();
+        let listed: Vec<(&str, &SavedTask, TaskSource)> = reloaded.list();
+        assert_eq!(
+            listed,
+            [
+                (
+                    "deps",
+                    &task("update all dependencies and run tests", &[]),
+                    TaskSource::User(saved.clone())
+                ),
+                (
+                    "release",
+                    &task("tag {{version}}", &["Tag", "Push"]),
+                    TaskSource::User(saved.clone())
+                ),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn variables_fill_the_prompt_and_plan_and_must_all_be_given() {
+        let release = task(
+            "create release {{version}} of {{crate}}",
+            &["Tag v{{version}}", "Publish {{crate}}"],
+        );
+        assert_eq!(release.variables(), ["version", "crate"]);
+
+        let vars = HashMap::from([
+            ("version".to_string(), "1.2.0".to_string()),
+            ("crate".to_string(), "parsec".to_string()),
+        ]);
+        assert_eq!(
+            release.substitute(&vars).unwrap(),
+            task(
+                "create release 1.2.0 of parsec",
+                &["Tag v1.2.0", "Publish parsec"]
+            )
+        );
+        let partial = HashMap::from([("version".to_string(), "1.2.0".to_string())]);
+        let missing = release.substitute(&partial).unwrap_err().to_string();
+        assert_eq!(missing, "Missing --var crate=...");
+    }
+
+    #[test]
+    fn projects_ship_tasks_in_parsec_toml_that_win_over_saved_ones() {
+        let dir = scratch();
+        let project = dir.join(".parsec.toml");
+        fs::write(
+            &project,
+            "[tasks.release]\nprompt = \"create a release tag and changelog\"\nplan = [\"Tag\", \"Changelog\"]\n",
+        )
+        .unwrap();
+        let mut book = TaskBook::load(&dir, &project).unwrap();
+        book.save("release", task("my own release", &[])).unwrap();
+
+        let (release, source) = book.get("release").unwrap();
+        assert_eq!(
+            release,
+            &task("create a release tag and changelog", &["Tag", "Changelog"])
+        );
+        assert_eq!(source, TaskSource::Project(project.clone()));
+        assert_eq!(book.list().len(), 1);
+        book.delete("release").unwrap();
+        let refused = book.delete("release").unwrap_err().to_string();
+        assert!(refused.contains("remove it there"), "{}", refused);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
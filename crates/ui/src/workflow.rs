@@ -0,0 +1,511 @@
+//! Running a task from the interactive shell: planning it, with Esc or Ctrl-C to cancel,
+//! reviewing the plan, running its workflow under `TerminalApproval`, and offering to roll
+//! back or undo what the steps changed.
+
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+use parsec_core::preflight;
+use parsec_core::*;
+use parsec_prompt::{OrchestratorError, RunOptions};
+
+use crate::approval::TerminalApproval;
+use crate::audit::Approval;
+use crate::tasks::SavedTask;
+use crate::{artifact_list, ParsecApp};
+
+impl ParsecApp {
+    /// Run a saved task, with its variables filled in, in a new conversation tagged
+    /// with its name; a task that keeps its plan is not planned again.
+    pub(crate) async fn run_task(
+        &mut self,
+        name: &str,
+        task: &SavedTask,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        say!(self, "Running task {}: {}", name, task.prompt);
+        if task.plan.is_empty() {
+            self.orchestrator.check_provider(session).await?;
+        }
+        let mut conversation = self
+            .orchestrator
+            .create_conversation(&session.id, task.prompt.clone())?;
+        conversation.task = Some(name.to_string());
+        self.attach_references(&mut conversation, session)?;
+        if task.plan.is_empty() {
+            if !self.plan_or_cancel(&mut conversation, session).await? {
+                return Ok(());
+            }
+        } else {
+            self.orchestrator
+                .plan_from_steps(&mut conversation, session, &task.plan)
+                .await?;
+        }
+        self.run_planned(&mut conversation, session).await
+    }
+
+    /// Plan `conversation`; at a terminal, Esc or Ctrl-C cancels the model call, and a
+    /// plan that timed out twice may be asked for again with twice the time. Returns
+    /// whether a plan was made.
+    pub(crate) async fn plan_or_cancel(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+    ) -> Result<bool, anyhow::Error> {
+        let mut timeout = None;
+        loop {
+            match self.plan_once(conversation, session, timeout).await {
+                Err(OrchestratorError::Plan(PlanError::Timeout(reason)))
+                    if self.is_interactive() =>
+                {
+                    let Some(longer) = timeout.or(self.planning_timeout).map(|t| t * 2) else {
+                        return Err(OrchestratorError::Plan(PlanError::Timeout(reason)).into());
+                    };
+                    let answer = self.read_line(&format!(
+                        "Planning timed out ({}). Try again with {}s? (y/N) ",
+                        reason,
+                        longer.as_secs()
+                    ))?;
+                    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                        return Err(OrchestratorError::Plan(PlanError::Timeout(reason)).into());
+                    }
+                    timeout = Some(longer);
+                }
+                Ok(()) => return Ok(true),
+                Err(OrchestratorError::Plan(PlanError::Cancelled)) => {
+                    say!(
+                        self,
+                        "{}",
+                        self.render.warning(format_args!(
+                            "Planning cancelled; `fork {}` plans it again",
+                            conversation.id
+                        ))
+                    );
+                    return Ok(false);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// One planning call for `conversation`, watching for Esc and Ctrl-C at a terminal.
+    async fn plan_once(
+        &self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        timeout: Option<Duration>,
+    ) -> Result<(), OrchestratorError> {
+        self.with_cancel_keys(|cancel| async move {
+            self.orchestrator
+                .plan_workflow_cancellable(conversation, session, &cancel, timeout)
+                .await
+        })
+        .await
+    }
+
+    /// Run `planning` with a token that Esc or Ctrl-C cancels at a terminal.
+    pub(crate) async fn with_cancel_keys<T, F: Future<Output = T>>(
+        &self,
+        planning: impl FnOnce(CancellationToken) -> F,
+    ) -> T {
+        let cancel = CancellationToken::new();
+        let stop = CancellationToken::new();
+        let keys = (self.is_interactive() && io::stdin().is_terminal()).then(|| {
+            say!(self, "{}", self.render.dim("Press Esc to cancel"));
+            let (cancel, stop) = (cancel.clone(), stop.clone());
+            tokio::task::spawn_blocking(move || watch_cancel_keys(&cancel, &stop))
+        });
+        let result = planning(cancel).await;
+        stop.cancel();
+        if let Some(keys) = keys {
+            // Raw mode must be off again before anything more is printed
+            let _ = keys.await;
+        }
+        result
+    }
+
+    /// Show a planned conversation, let the user review it, then execute it.
+    pub(crate) async fn run_planned(
+        &mut self,
+        conversation: &mut ConversationContext,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        say!(
+            self,
+            "{}",
+            self.render.success(format_args!(
+                "Workflow planned with {} steps",
+                conversation.steps.len()
+            ))
+        );
+        self.emit(OutputRecord::Plan {
+            conversation_id: conversation.id.clone(),
+            name: conversation.name.clone(),
+            steps: conversation
+                .steps
+                .iter()
+                .map(|state| state.step.clone())
+                .collect(),
+        });
+
+        // Display workflow
+        self.print_plan(conversation);
+        self.print_preflight(conversation);
+        let errors = preflight::errors(&conversation.preflight);
+        if !errors.is_empty() && !self.force {
+            return Err(OrchestratorError::Preflight(errors).into());
+        }
+        if self.is_interactive() && !self.review_plan(conversation, session).await? {
+            return Ok(());
+        }
+
+        // Execute workflow interactively
+        self.execute_workflow_interactive(conversation, session)
+            .await?;
+
+        Ok(())
+    }
+
+    /// `fork [<conversation-id>|last] [<new prompt>]`
+    pub(crate) async fn fork_builtin(
+        &mut self,
+        args: &str,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        let args = args.trim();
+        let (id, prompt) = match args.split_once(' ') {
+            Some((id, prompt)) => (id, Some(prompt.trim().to_string())),
+            None => (args, None),
+        };
+        let source_id = match id {
+            "" | "last" => session
+                .conversations
+                .last()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No conversations in this session"))?,
+            id => id.to_string(),
+        };
+
+        let source = self.session_store.load_conversation(&source_id)?;
+        if source.status != ConversationStatus::Finished {
+            say!(
+                self,
+                "{}",
+                self.render.warning(format_args!(
+                    "Conversation {} is {:?}; forking it anyway",
+                    source_id, source.status
+                ))
+            );
+        }
+
+        let prompt = prompt.filter(|prompt| !prompt.is_empty());
+        let adapt = prompt.is_some();
+        if adapt || source.steps.is_empty() {
+            self.orchestrator.check_provider(session).await?;
+        }
+        let mut conversation = self.orchestrator.fork_conversation(&source_id, prompt)?;
+        say!(self, "Forked {} into {}", source_id, conversation.id);
+
+        if conversation.steps.is_empty() {
+            if !self.plan_or_cancel(&mut conversation, session).await? {
+                return Ok(());
+            }
+        } else if adapt {
+            say!(self, "Adapting the plan to: {}", conversation.user_prompt);
+            self.orchestrator
+                .adapt_forked_plan(&mut conversation, session)
+                .await?;
+        }
+
+        self.run_planned(&mut conversation, session).await
+    }
+
+    pub(crate) async fn execute_workflow_interactive(
+        &mut self,
+        conversation: &mut ConversationContext,
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
+        let policy = TerminalApproval::new(self, session);
+        let outcome = self
+            .orchestrator
+            .run_workflow(
+                conversation,
+                session,
+                &policy,
+                RunOptions {
+                    force: self.force,
+                    ..RunOptions::default()
+                },
+            )
+            .await;
+        self.guard.run_finished(&conversation.id);
+        let background = policy.into_background();
+        self.background_steps.extend(background);
+        let outcome = outcome?;
+        if outcome.status == WorkflowStatus::Aborted {
+            self.offer_rollback(conversation, session).await?;
+        }
+
+        // Print final status
+        let status = self
+            .orchestrator
+            .get_conversation_status_summary(conversation);
+        say!(
+            self,
+            "\nFinal status: {}",
+            self.render.status(&conversation.status, status)
+        );
+        self.print_outcome(&outcome);
+        self.emit(OutputRecord::Summary {
+            conversation_id: conversation.id.clone(),
+            name: conversation.name.clone(),
+            status: conversation.status.clone(),
+            completed_steps: conversation
+                .steps
+                .iter()
+                .filter(|step| step.status == StepStatus::Complete)
+                .count(),
+            total_steps: conversation.steps.len(),
+            artifacts: conversation.context_summary.generated_artifacts.clone(),
+        });
+        self.emit(OutputRecord::Outcome {
+            conversation_id: conversation.id.clone(),
+            outcome,
+        });
+
+        Ok(())
+    }
+
+    /// The digest of a run: where each step stands, what the run executed, produced and
+    /// changed, and what is left for the user.
+    fn print_outcome(&self, outcome: &WorkflowOutcome) {
+        say!(
+            self,
+            "This run: {} completed, {} failed, {} skipped in {}",
+            outcome.steps_completed,
+            outcome.steps_failed,
+            outcome.steps_skipped,
+            format_duration(outcome.duration)
+        );
+        for step in &outcome.steps {
+            let line = format!("{}. {}", step.step_index + 1, step.description);
+            let line = match step.status {
+                StepStatus::Complete => self.render.success(format!("✓ {}", line)),
+                StepStatus::Failed => self.render.failure(format!("✗ {}", line)),
+                StepStatus::Skipped => self.render.dim(format!("- {} (skipped)", line)),
+                StepStatus::Running => self.render.warning(format!("… {} (running)", line)),
+                StepStatus::NeedsAttention => {
+                    self.render.warning(format!("! {} (needs attention)", line))
+                }
+                StepStatus::Pending | StepStatus::CommandSuggested => {
+                    self.render.dim(format!("· {} (pending)", line))
+                }
+            };
+            let finished = matches!(
+                step.status,
+                StepStatus::Complete | StepStatus::Failed | StepStatus::Skipped
+            );
+            if finished && !step.this_run {
+                say!(self, "  {}{}", line, self.render.dim(" (earlier run)"));
+            } else {
+                say!(self, "  {}", line);
+            }
+        }
+        say!(self, "Commands executed: {}", outcome.commands_executed);
+        for (label, change) in [
+            ("Created", ArtifactChange::Created),
+            ("Modified", ArtifactChange::Modified),
+        ] {
+            if let Some(list) = artifact_list(&outcome.artifacts, change) {
+                say!(self, "{}: {}", label, list);
+            }
+        }
+        if !outcome.environment_changes.is_empty() {
+            let changes: Vec<String> = outcome
+                .environment_changes
+                .iter()
+                .map(|change| format!("{}={}", change.variable_name, change.new_value))
+                .collect();
+            say!(self, "Environment: {}", changes.join(", "));
+        }
+        if outcome.tokens_used > 0 {
+            say!(self, "Tokens used: ~{}", outcome.tokens_used);
+        }
+        if !outcome.follow_up.is_empty() {
+            say!(self, "Follow up:");
+            for item in &outcome.follow_up {
+                say!(self, "  - {}", item);
+            }
+        }
+    }
+
+    /// After an abort, list how to undo what the workflow changed. Each command runs only
+    /// after its own explicit yes; unattended runs just print the suggestions.
+    async fn offer_rollback(
+        &mut self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+    ) -> Result<(), anyhow::Error> {
+        let suggestions = self
+            .orchestrator
+            .suggest_rollback(conversation, session)
+            .await?;
+        if suggestions.is_empty() {
+            return Ok(());
+        }
+        self.emit(OutputRecord::Rollback {
+            conversation_id: conversation.id.clone(),
+            suggestions: suggestions.clone(),
+        });
+
+        say!(self, "\nSuggested cleanup, most recent step first:");
+        for suggestion in &suggestions {
+            self.offer_undo(conversation, session, suggestion, true)?;
+        }
+        Ok(())
+    }
+
+    /// Show how to undo one step and, when interactive, run the command after an explicit
+    /// yes. `rollback` tells an abort's cleanup from `undo`.
+    fn offer_undo(
+        &mut self,
+        conversation: &mut ConversationContext,
+        session: &Session,
+        suggestion: &RollbackSuggestion,
+        rollback: bool,
+    ) -> Result<(), anyhow::Error> {
+        say!(
+            self,
+            "  Step {}: {}",
+            suggestion.step_index + 1,
+            suggestion.step_description
+        );
+        let command = match &suggestion.action {
+            RollbackAction::Command(command) => command,
+            RollbackAction::ManualCleanup { reason } => {
+                say!(self, "    Manual cleanup needed: {}", reason);
+                for path in &suggestion.artifacts {
+                    say!(self, "      {}", path.display());
+                }
+                return Ok(());
+            }
+        };
+
+        say!(self, "    Undo: {}", command.command);
+        if !command.explanation.is_empty() {
+            say!(self, "    {}", command.explanation);
+        }
+        if !self.is_interactive() {
+            return Ok(());
+        }
+        let answer = self.read_line("    Run it? (y/N): ")?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            if rollback {
+                self.orchestrator
+                    .decline_rollback(conversation, suggestion.step_index, command)?;
+            } else {
+                self.orchestrator
+                    .decline_undo(conversation, suggestion.step_index, command)?;
+            }
+            return Ok(());
+        }
+        let audit = self.audit_start(
+            &session.id,
+            &session.global_context.working_directory,
+            Some((conversation, suggestion.step_index)),
+            &command.command,
+            Approval::User,
+            None,
+        );
+        let started = Instant::now();
+        let result = if rollback {
+            self.orchestrator
+                .run_rollback(conversation, session, suggestion.step_index, command)
+        } else {
+            self.orchestrator
+                .run_undo(conversation, session, suggestion.step_index, command)
+        };
+        let attempt = match result {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                self.audit_finish(audit, None, started.elapsed(), Some(e.to_string()));
+                return Err(e.into());
+            }
+        };
+        self.audit_attempt(audit, &attempt);
+        let (done, failed) = if rollback {
+            ("Rolled back", "Rollback failed")
+        } else {
+            ("Undone", "Undo failed")
+        };
+        if attempt.error.is_none() {
+            say!(self, "    {}", self.render.success(done));
+        } else {
+            say!(
+                self,
+                "    {}",
+                self.render.failure(format_args!(
+                    "{}: {}",
+                    failed,
+                    attempt.stderr.content.trim()
+                ))
+            );
+        }
+        Ok(())
+    }
+
+    /// `undo [id|last]`: offer the undo commands recorded with the most recent step of a
+    /// conversation that ran commands. They run only after an explicit yes, whatever the
+    /// approval mode.
+    pub(crate) fn undo_builtin(
+        &mut self,
+        args: &str,
+        session: &Session,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id = match args.trim() {
+            "" | "last" => session
+                .conversations
+                .last()
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("No conversations in this session"))?,
+            id => id.to_string(),
+        };
+        let mut conversation = self.session_store.load_conversation(&conversation_id)?;
+        let Some(suggestion) = self.orchestrator.undo_last_step(&conversation, session) else {
+            say!(self, "Nothing to undo in conversation {}", conversation.id);
+            return Ok(());
+        };
+        self.emit(OutputRecord::Rollback {
+            conversation_id: conversation.id.clone(),
+            suggestions: vec![suggestion.clone()],
+        });
+        say!(self, "Last step of \"{}\":", conversation.name);
+        self.offer_undo(&mut conversation, session, &suggestion, false)
+    }
+}
+
+/// Cancel `cancel` when Esc or Ctrl-C is pressed, until `stop` is cancelled. The terminal
+/// is in raw mode meanwhile, so Ctrl-C arrives as a key rather than ending parsec.
+fn watch_cancel_keys(cancel: &CancellationToken, stop: &CancellationToken) {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    if crossterm::terminal::enable_raw_mode().is_err() {
+        return;
+    }
+    while !stop.is_cancelled() {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => break,
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press && (key.code == KeyCode::Esc || ctrl_c) {
+                cancel.cancel();
+                break;
+            }
+        }
+    }
+    let _ = crossterm::terminal::disable_raw_mode();
+}
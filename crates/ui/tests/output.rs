@@ -0,0 +1,47 @@
+//! `--output json` as a script driving parsec sees it, from the built binary.
+
+use std::path::PathBuf;
+use std::process::Command;
+use uuid::Uuid;
+
+/// A home and data directory of their own, so no user configuration or session is read.
+fn isolated() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("parsec-output-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn json_mode_prints_records_on_stdout_and_messages_on_stderr() {
+    let dir = isolated();
+    let output = Command::new(env!("CARGO_BIN_EXE_parsec"))
+        .args(["--api-key", "test", "--output", "json", "--auto-approve"])
+        .arg("--data-dir")
+        .arg(dir.join("data"))
+        .args(["exec", "echo hello"])
+        .current_dir(&dir)
+        .env("HOME", &dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("each stdout line is one record"))
+        .collect();
+    let types: Vec<&str> = records
+        .iter()
+        .map(|record| record["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["classification", "execution"]);
+    assert_eq!(records[1]["conversation_id"], serde_json::Value::Null);
+    assert_eq!(records[1]["stdout"]["content"], "hello\n");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Exit status: 0"), "{}", stderr);
+    assert!(!stdout.contains("Exit status"));
+    let _ = std::fs::remove_dir_all(&dir);
+}